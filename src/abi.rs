@@ -0,0 +1,156 @@
+// This file contains the "abi" subcommand, which describes the exports sidefuzz expects from a target
+//
+// Rust targets get these exports from the sidefuzz crate. Targets written in C or C++ can include the header this
+// command emits, and be built with clang --target=wasm32.
+
+use crate::errors::SideFuzzError;
+
+// The exports of a target taking a single input: name, C declaration, whether it is required, and what it does
+const EXPORTS: &[(&str, &str, bool, &str)] = &[
+    (
+        "fuzz",
+        "void fuzz(void)",
+        true,
+        "runs the code under test on the input, once per execution",
+    ),
+    (
+        "input_pointer",
+        "uint8_t *input_pointer(void)",
+        true,
+        "where sidefuzz writes the input, in linear memory",
+    ),
+    (
+        "input_len",
+        "size_t input_len(void)",
+        true,
+        "the length of the input in bytes, as an i32 or an i64",
+    ),
+    (
+        "input_is_str",
+        "int32_t input_is_str(void)",
+        false,
+        "non-zero if the input must be valid UTF-8, bytes are assumed if it is missing",
+    ),
+];
+
+// Where the header is written when no file is given
+pub const DEFAULT_HEADER: &str = "sidefuzz.h";
+
+const HEADER_PRELUDE: &str = r#"/*
+ * The exports sidefuzz expects from a fuzz target, generated by `sidefuzz abi --emit-c-header` (sidefuzz {version}).
+ *
+ * Either define the functions declared below, or let SIDEFUZZ_TARGET define them for a function taking the input:
+ *
+ *     #include "sidefuzz.h"
+ *
+ *     static void check(const uint8_t *input, size_t len) {
+ *         SIDEFUZZ_BLACK_BOX(compare(input, secret, len));
+ *     }
+ *
+ *     SIDEFUZZ_TARGET(32, check)
+ *
+ * Build it with: clang --target=wasm32 -O2 -nostdlib -Wl,--no-entry -o target.wasm target.c
+ */
+#ifndef SIDEFUZZ_H
+#define SIDEFUZZ_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#if defined(__wasm__)
+#define SIDEFUZZ_EXPORT(name) __attribute__((export_name(#name)))
+#else
+#define SIDEFUZZ_EXPORT(name) __attribute__((visibility("default")))
+#endif
+
+"#;
+
+const HEADER_HELPERS: &str = r#"
+/* Keeps the optimizer from removing a computation whose result is ignored */
+#define SIDEFUZZ_BLACK_BOX(value)                                                                                  \
+    do {                                                                                                           \
+        volatile __typeof__(value) sidefuzz_sink = (value);                                                        \
+        (void)sidefuzz_sink;                                                                                       \
+    } while (0)
+
+/* Defines every export for `target`, a void function taking the input and its length, with a `len` byte input */
+#define SIDEFUZZ_TARGET(len, target)                                                                               \
+    static uint8_t sidefuzz_input[(len)];                                                                          \
+    uint8_t *input_pointer(void) { return sidefuzz_input; }                                                        \
+    size_t input_len(void) { return (len); }                                                                       \
+    int32_t input_is_str(void) { return 0; }                                                                       \
+    void fuzz(void) { target(sidefuzz_input, (len)); }
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SIDEFUZZ_H */
+"#;
+
+// The C header declaring every export
+pub fn c_header() -> String {
+    let mut header = HEADER_PRELUDE.replace("{version}", env!("CARGO_PKG_VERSION"));
+    for (name, declaration, required, description) in EXPORTS.iter() {
+        let optional = if *required { "" } else { " Optional," };
+        header.push_str(&format!(
+            "/*{} {} */\nSIDEFUZZ_EXPORT({}) {};\n",
+            optional, description, name, declaration
+        ));
+    }
+    header.push_str(HEADER_HELPERS);
+    header
+}
+
+pub struct Abi {
+    header: Option<String>, // Where to write the C header, if it is wanted
+}
+
+impl Abi {
+    pub fn new(header: Option<&str>) -> Self {
+        Abi {
+            header: header.map(str::to_string),
+        }
+    }
+
+    pub fn run(&self) -> Result<(), SideFuzzError> {
+        match &self.header {
+            Some(path) => {
+                std::fs::write(path, c_header()).map_err(SideFuzzError::CouldNotWriteFile)?;
+                println!("Wrote {}", path);
+            }
+            None => {
+                println!("Exports of a fuzz target:");
+                for (name, declaration, required, description) in EXPORTS.iter() {
+                    let optional = if *required { "" } else { ", optional" };
+                    println!("  {} ({}{}): {}", name, declaration, optional, description);
+                }
+                println!("\nAnd its memory, exported as \"memory\". Write them as a C header with --emit-c-header.");
+            }
+        }
+        std::process::exit(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn c_header_test() {
+        let header = c_header();
+        assert!(header.starts_with("/*"));
+        assert!(header.contains(env!("CARGO_PKG_VERSION")));
+        assert!(header.contains("SIDEFUZZ_EXPORT(input_len) size_t input_len(void);\n"));
+        assert!(header.contains("/* Optional, non-zero"));
+        assert!(header.trim_end().ends_with("#endif /* SIDEFUZZ_H */"));
+        assert_eq!(
+            header.matches("#if").count(),
+            header.matches("#endif").count()
+        );
+    }
+}
@@ -0,0 +1,262 @@
+// The library interface, for embedding sidefuzz in other tools
+
+use crate::fuzz::Fuzz;
+use crate::report::Reporter;
+use crate::wasm::{WasmModule, WasmOptions};
+
+pub use crate::errors::{ErrorCategory, SideFuzzError};
+pub use crate::mutators::{DictionaryMutator, Mutator, Mutators};
+pub use crate::report::{Report as FuzzReport, Verdict};
+pub use crate::scores::{Observation, Score, Scores};
+
+/// Fuzz a wasm target from Rust, instead of from the command line.
+///
+/// ```rust,ignore
+/// let report = sidefuzz::SideFuzz::builder()
+///     .wasm(std::fs::read("my_target.wasm")?)
+///     .max_iterations(100_000)
+///     .run()?;
+///
+/// if report.verdict == sidefuzz::Verdict::Leak {
+///     println!("{} and {} leak", report.input_1, report.input_2);
+/// }
+/// ```
+pub struct SideFuzz;
+
+impl SideFuzz {
+    /// Start configuring a fuzzing run
+    pub fn builder() -> SideFuzzBuilder {
+        SideFuzzBuilder::default()
+    }
+
+    /// Count the instructions a wasm target executes for one input, as fuzzing does.
+    ///
+    /// This is what the tests `sidefuzz fuzz --reproducer` writes are built on, to check that two inputs take the
+    /// same time:
+    ///
+    /// ```rust,ignore
+    /// let wasm = std::fs::read("target/wasm32-unknown-unknown/release/my_target.wasm")?;
+    /// assert_eq!(
+    ///     sidefuzz::SideFuzz::count_instructions(&wasm, &[0; 32])?,
+    ///     sidefuzz::SideFuzz::count_instructions(&wasm, &[0xff; 32])?,
+    /// );
+    /// ```
+    pub fn count_instructions(wasm: &[u8], input: &[u8]) -> Result<u64, SideFuzzError> {
+        Self::count_instructions_with(wasm, input, &CountOptions::default())
+    }
+
+    /// Count instructions like [`SideFuzz::count_instructions`], for targets fuzzed with `--entry`, a key or
+    /// `--fuzz-key`.
+    ///
+    /// ```rust,ignore
+    /// let options = sidefuzz::CountOptions {
+    ///     entry: Some("fuzz_sign".to_string()),
+    ///     key_env: Some("SIGNING_KEY".to_string()),
+    ///     ..Default::default()
+    /// };
+    /// let count = sidefuzz::SideFuzz::count_instructions_with(&wasm, &[0; 32], &options)?;
+    /// ```
+    pub fn count_instructions_with(
+        wasm: &[u8],
+        input: &[u8],
+        options: &CountOptions,
+    ) -> Result<u64, SideFuzzError> {
+        let key = match (&options.key, &options.key_env) {
+            (Some(key), _) => Some(key.clone()),
+            (None, Some(var)) => {
+                let key = std::env::var(var)
+                    .map_err(|_| SideFuzzError::BadKey(format!("{} isn't set", var)))?;
+                let key = hex::decode(key.trim())
+                    .map_err(|_| SideFuzzError::BadKey(format!("{} must hold hexadecimal", var)))?;
+                Some(key)
+            }
+            (None, None) => None,
+        };
+        let options = WasmOptions {
+            entry: options.entry.clone(),
+            key,
+            fuzz_key: options.fuzz_key,
+            message: options.message.clone(),
+            ..WasmOptions::default()
+        };
+        WasmModule::with_options(wasm.to_vec(), options)?.count_instructions(input)
+    }
+}
+
+/// How [`SideFuzz::count_instructions_with`] runs a target, like the target options of the command line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CountOptions {
+    /// The export to run instead of `fuzz`, like `--entry`
+    pub entry: Option<String>,
+    /// The fixed key of targets that fetch one with `sidefuzz::fetch_key`, like `--key-hex`
+    pub key: Option<Vec<u8>>,
+    /// The environment variable the key is read from in hexadecimal instead, like `--key-env`
+    pub key_env: Option<String>,
+    /// The input is the key, under a fixed message, like `--fuzz-key`
+    pub fuzz_key: bool,
+    /// The fixed message when the input is the key, like `--message-hex`. It is all zero if None.
+    pub message: Option<Vec<u8>>,
+}
+
+/// How a fuzzing run ended, for callers that act on the result without looking into reports or error messages.
+#[derive(Debug)]
+pub enum FuzzOutcome {
+    /// A leak was found and confirmed
+    Leak(FuzzReport),
+    /// No leak was confirmed before the run's budget ran out. Crashes found along the way are in the report.
+    NoLeak(FuzzReport),
+    /// The target failed while running an input that fuzzing couldn't do without, such as the leaking pair
+    Crashed(SideFuzzError),
+    /// The target couldn't be loaded, or the run couldn't be set up
+    SetupFailure(SideFuzzError),
+}
+
+impl FuzzOutcome {
+    /// The outcome of a run that returned `result`, once the target had loaded
+    pub fn from_result(result: Result<FuzzReport, SideFuzzError>) -> Self {
+        match result {
+            Ok(report) if report.verdict == Verdict::Leak => FuzzOutcome::Leak(report),
+            Ok(report) => FuzzOutcome::NoLeak(report),
+            Err(err) if err.is_crash() => FuzzOutcome::Crashed(err),
+            Err(err) => FuzzOutcome::SetupFailure(err),
+        }
+    }
+}
+
+/// Configures and runs a fuzzing run, see [`SideFuzz`].
+#[derive(Debug, Clone, Default)]
+pub struct SideFuzzBuilder {
+    wasm: Option<Vec<u8>>,
+    max_iterations: Option<u64>,
+    workers: Option<usize>,
+    seed: Option<u64>,
+    mutators: Option<Mutators>,
+    scores: Option<Scores>,
+    verbose: bool,
+}
+
+impl SideFuzzBuilder {
+    /// The wasm module to fuzz. This is required.
+    pub fn wasm(mut self, bytes: Vec<u8>) -> Self {
+        self.wasm = Some(bytes);
+        self
+    }
+
+    /// Give up after this many generations of the optimizer, with an inconclusive result.
+    /// By default fuzzing goes on until a leak is found.
+    pub fn max_iterations(mut self, max_iterations: u64) -> Self {
+        self.max_iterations = Some(max_iterations);
+        self
+    }
+
+    /// Score candidate inputs on this many threads.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = Some(workers);
+        self
+    }
+
+    /// Seed all random choices, making the run reproducible.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Mutate inputs with these strategies, which may include custom [`Mutator`]s.
+    ///
+    /// ```rust,ignore
+    /// let mut mutators = sidefuzz::Mutators::default();
+    /// mutators.add(3, MyMutator);
+    /// let report = sidefuzz::SideFuzz::builder().wasm(wasm).mutators(mutators).run()?;
+    /// ```
+    pub fn mutators(mut self, mutators: Mutators) -> Self {
+        self.mutators = Some(mutators);
+        self
+    }
+
+    /// Score input pairs with these, which may include custom [`Score`]s, instead of by the difference in
+    /// instructions executed.
+    ///
+    /// ```rust,ignore
+    /// let mut scores = sidefuzz::Scores::default();
+    /// scores.add(0.5, MyScore);
+    /// let report = sidefuzz::SideFuzz::builder().wasm(wasm).scores(scores).run()?;
+    /// ```
+    pub fn scores(mut self, scores: Scores) -> Self {
+        self.scores = Some(scores);
+        self
+    }
+
+    /// Print progress messages to stdout, as the command line does.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Fuzz until a leak is found, or until `max_iterations` is reached.
+    pub fn run(self) -> Result<FuzzReport, SideFuzzError> {
+        self.fuzz()?.search()
+    }
+
+    /// Fuzz like [`SideFuzzBuilder::run`], telling apart how the run ended.
+    ///
+    /// ```rust,ignore
+    /// match sidefuzz::SideFuzz::builder().wasm(wasm).outcome() {
+    ///     sidefuzz::FuzzOutcome::Leak(report) => println!("{} and {} leak", report.input_1, report.input_2),
+    ///     sidefuzz::FuzzOutcome::NoLeak(_) => println!("no leak found"),
+    ///     sidefuzz::FuzzOutcome::Crashed(err) => println!("the target crashed: {}", err),
+    ///     sidefuzz::FuzzOutcome::SetupFailure(err) => println!("{}: {}", err.category(), err),
+    /// }
+    /// ```
+    pub fn outcome(self) -> FuzzOutcome {
+        match self.fuzz() {
+            Ok(mut fuzz) => FuzzOutcome::from_result(fuzz.search()),
+            Err(err) => FuzzOutcome::SetupFailure(err),
+        }
+    }
+
+    fn fuzz(self) -> Result<Fuzz, SideFuzzError> {
+        let wasm = self.wasm.ok_or(SideFuzzError::NoTarget)?;
+        let mut fuzz = Fuzz::new(WasmModule::new(wasm)?);
+
+        let mut reporter = Reporter::default();
+        reporter.set_quiet(!self.verbose);
+        fuzz.set_reporter(reporter);
+
+        if let Some(max_iterations) = self.max_iterations {
+            fuzz.set_max_generations(max_iterations);
+        }
+        if let Some(workers) = self.workers {
+            fuzz.set_workers(workers);
+        }
+        if let Some(seed) = self.seed {
+            fuzz.set_seed(seed);
+        }
+        if let Some(mutators) = self.mutators {
+            fuzz.set_mutators(mutators);
+        }
+        if let Some(scores) = self.scores {
+            fuzz.set_scores(scores);
+        }
+        Ok(fuzz)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_target_test() {
+        let result = SideFuzz::builder().max_iterations(500).run();
+        match result {
+            Err(SideFuzzError::NoTarget) => {}
+            _ => panic!("expected a NoTarget error"),
+        }
+        match SideFuzz::builder().outcome() {
+            FuzzOutcome::SetupFailure(err) => assert_eq!(err.category(), ErrorCategory::Harness),
+            _ => panic!("expected a setup failure"),
+        }
+        assert!(SideFuzzError::Timeout(100).is_crash());
+        assert!(!SideFuzzError::WasmModuleNoMemory.is_crash());
+    }
+}
@@ -0,0 +1,100 @@
+// Archives of leaks found, one directory for each, so they describe themselves
+//
+// Each leak is saved under the archive directory (findings/ by default) in a directory named after the first 16 hex
+// digits of a SHA-256 over the target module and both inputs. The same inputs leaking in another build of the target
+// get another directory, so leaks of different versions of a target are never mixed up, and finding a leak again
+// only rewrites its directory. It holds both inputs as raw bytes, in input_1 and input_2, and finding.json with the
+// hash of the module, the inputs, their instruction counts, the seed and the version of sidefuzz that found it.
+
+use crate::errors::SideFuzzError;
+use crate::report::Report;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+// Hex digits of the hash naming each leak's directory
+const PREFIX_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Archived {
+    pub sidefuzz_version: String,
+    pub module_sha256: String,
+    pub command: String, // The subcommand that found the leak
+    pub input_1: String, // Hex encoded
+    pub input_2: String,
+    pub instructions_1: f64, // Mean instructions executed, while the leak was confirmed
+    pub instructions_2: f64,
+    pub difference: f64,
+    pub t_value: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>, // Such as "my_target 0.1.0", if the target embeds its metadata
+}
+
+pub(crate) fn sha256(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+// The name of the directory of the leak between `first` and `second` in `module`. Inputs are length prefixed, so
+// moving bytes from one input to the other changes the name.
+fn name(module: &[u8], first: &[u8], second: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(module);
+    for input in [first, second].iter() {
+        hasher.update(&(input.len() as u64).to_le_bytes());
+        hasher.update(input);
+    }
+    hex::encode(hasher.finalize())[..PREFIX_LEN].to_string()
+}
+
+// Archive the leak in `report` between `first` and `second` under `dir`, returning the directory it was saved to
+pub(crate) fn save(
+    dir: &str,
+    module: &[u8],
+    report: &Report,
+    first: &[u8],
+    second: &[u8],
+) -> Result<PathBuf, SideFuzzError> {
+    let path = Path::new(dir).join(name(module, first, second));
+    std::fs::create_dir_all(&path).map_err(SideFuzzError::CouldNotWriteFile)?;
+    std::fs::write(path.join("input_1"), first).map_err(SideFuzzError::CouldNotWriteFile)?;
+    std::fs::write(path.join("input_2"), second).map_err(SideFuzzError::CouldNotWriteFile)?;
+
+    let archived = Archived {
+        sidefuzz_version: crate::VERSION.to_string(),
+        module_sha256: sha256(module),
+        command: report.command.to_string(),
+        input_1: hex::encode(first),
+        input_2: hex::encode(second),
+        instructions_1: report.instructions_1,
+        instructions_2: report.instructions_2,
+        difference: report.difference,
+        t_value: report.t_value,
+        seed: report.seed,
+        target: report.target_meta.as_ref().map(|meta| meta.label()),
+    };
+    // Ok to expect, archived leaks always serialize.
+    let json = serde_json::to_string_pretty(&archived).expect("Could not serialize the finding");
+    std::fs::write(path.join("finding.json"), json).map_err(SideFuzzError::CouldNotWriteFile)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_test() {
+        let module = b"\0asm\x01\0\0\0";
+        let named = name(module, &[1, 2], &[3]);
+        assert_eq!(named.len(), PREFIX_LEN);
+        assert_eq!(named, name(module, &[1, 2], &[3]));
+        assert_ne!(named, name(module, &[1], &[2, 3]));
+        assert_ne!(named, name(b"\0asm\x01\0\0\0\0", &[1, 2], &[3]));
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
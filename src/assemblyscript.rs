@@ -0,0 +1,136 @@
+// Inputs for AssemblyScript targets, written as the managed objects they are read as
+//
+// AssemblyScript keeps buffers and strings in linear memory behind a 20 byte header, which ends with the object's
+// class id and its size in bytes. A target exporting `input_kind`, as a constant or a function, asks for its input
+// to be written as such an object: 1 for an ArrayBuffer and 2 for a String. Its input_pointer returns the address of
+// an object it allocated itself, such as `changetype<usize>(new ArrayBuffer(32))`. Before every execution the input
+// is written into that object and its size is set to the input's, so `byteLength` and `length` are the input's.
+// Strings are UTF-16: their inputs are UTF-8 strings of input_len bytes, and the object needs room for twice as many.
+
+use crate::errors::SideFuzzError;
+use std::convert::TryInto;
+
+// The export saying what kind of input a target takes
+pub(crate) const INPUT_KIND: &str = "input_kind";
+
+// Where the class id and size are, before the address of an object
+const ID_OFFSET: usize = 8;
+const SIZE_OFFSET: usize = 4;
+
+// The class ids AssemblyScript gives its built in classes
+const ARRAY_BUFFER_ID: u32 = 1;
+const STRING_ID: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InputKind {
+    Bytes, // Raw bytes, for targets written in anything else
+    ArrayBuffer,
+    String,
+}
+
+impl Default for InputKind {
+    fn default() -> Self {
+        InputKind::Bytes
+    }
+}
+
+impl InputKind {
+    // The kind an `input_kind` export asks for
+    pub fn from_export(value: i32) -> Result<Self, SideFuzzError> {
+        match value {
+            0 => Ok(InputKind::Bytes),
+            1 => Ok(InputKind::ArrayBuffer),
+            2 => Ok(InputKind::String),
+            _ => Err(SideFuzzError::WasmModuleBadManagedInput(format!(
+                "input_kind is {}, expected 0 for bytes, 1 for an ArrayBuffer or 2 for a String",
+                value
+            ))),
+        }
+    }
+
+    fn id(self) -> Option<u32> {
+        match self {
+            InputKind::Bytes => None,
+            InputKind::ArrayBuffer => Some(ARRAY_BUFFER_ID),
+            InputKind::String => Some(STRING_ID),
+        }
+    }
+
+    // Check that the object at `ptr` in `memory` is of this kind, and can hold inputs of `len` bytes
+    pub fn check(self, memory: &[u8], ptr: usize, len: usize) -> Result<(), SideFuzzError> {
+        let expected = match self.id() {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+        let header = ptr
+            .checked_sub(ID_OFFSET)
+            .and_then(|start| memory.get(start..ptr))
+            .ok_or_else(|| {
+                SideFuzzError::WasmModuleBadManagedInput(format!(
+                    "input_pointer {} has no room for an object header before it",
+                    ptr
+                ))
+            })?;
+        // Ok to unwrap, the header is exactly 8 bytes
+        let id = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        if id != expected {
+            return Err(SideFuzzError::WasmModuleBadManagedInput(format!(
+                "the object at input_pointer has class id {}, expected {} for {:?}",
+                id, expected, self
+            )));
+        }
+        let capacity = if self == InputKind::String {
+            len * 2
+        } else {
+            len
+        };
+        if size < capacity {
+            return Err(SideFuzzError::WasmModuleBadManagedInput(format!(
+                "the object at input_pointer holds {} bytes, inputs of {} bytes need {}",
+                size, len, capacity
+            )));
+        }
+        Ok(())
+    }
+
+    // What to write where for an input, for an object at `ptr`: its contents, and then its size
+    pub fn writes(self, ptr: usize, input: &[u8]) -> Vec<(usize, Vec<u8>)> {
+        let contents = match self {
+            InputKind::Bytes => return vec![(ptr, input.to_vec())],
+            InputKind::ArrayBuffer => input.to_vec(),
+            InputKind::String => String::from_utf8_lossy(input)
+                .encode_utf16()
+                .flat_map(|unit| unit.to_le_bytes().to_vec())
+                .collect(),
+        };
+        let size = (contents.len() as u32).to_le_bytes().to_vec();
+        vec![(ptr, contents), (ptr - SIZE_OFFSET, size)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_kind_test() {
+        assert!(InputKind::from_export(3).is_err());
+        let kind = InputKind::from_export(2).unwrap();
+
+        // A string with room for 4 UTF-16 code units at address 24
+        let mut memory = vec![0; 32];
+        memory[16..20].copy_from_slice(&STRING_ID.to_le_bytes());
+        memory[20..24].copy_from_slice(&8u32.to_le_bytes());
+        assert!(kind.check(&memory, 24, 4).is_ok());
+        assert!(kind.check(&memory, 24, 5).is_err());
+        assert!(InputKind::ArrayBuffer.check(&memory, 24, 4).is_err());
+        assert!(kind.check(&memory, 4, 4).is_err());
+
+        assert_eq!(
+            kind.writes(24, "hé".as_bytes()),
+            vec![(24, vec![b'h', 0, 0xe9, 0]), (20, vec![4, 0, 0, 0])]
+        );
+        assert_eq!(InputKind::Bytes.writes(24, &[1, 2]), vec![(24, vec![1, 2])]);
+    }
+}
@@ -0,0 +1,307 @@
+// This file contains the "batch" subcommand, for fuzzing many targets in one go
+//
+// Targets are either given as a list of files, or described in a TOML file:
+//
+// max_generations = 100000 # Budget for each target, unless the target sets its own
+// jobs = 4                 # How many targets are fuzzed at once
+//
+// [[target]]
+// path = "target/wasm32-unknown-unknown/release/compare.wasm"
+// max_generations = 20000
+// seed = 42
+
+use crate::errors::SideFuzzError;
+use crate::fuzz::Fuzz;
+use crate::report::{Report, Reporter, Verdict};
+use crate::target::TargetOptions;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+
+// Generations each target is fuzzed for when no budget is configured
+pub const DEFAULT_MAX_GENERATIONS: u64 = 100_000;
+
+#[derive(Debug, Clone, Deserialize)]
+struct BatchSpec {
+    max_generations: Option<u64>,
+    jobs: Option<usize>,
+    #[serde(default, rename = "target")]
+    targets: Vec<BatchTarget>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchTarget {
+    pub path: String,
+    pub max_generations: Option<u64>,
+    pub seed: Option<u64>,
+}
+
+// The outcome of fuzzing one target
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub target: String,
+    pub verdict: Option<Verdict>, // None if the target could not be fuzzed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<Report>,
+}
+
+pub struct Batch {
+    targets: Vec<BatchTarget>,
+    options: TargetOptions,
+    max_generations: u64,
+    jobs: usize,
+    reporter: Reporter,
+}
+
+impl Batch {
+    // Fuzz each of the given files
+    pub fn new(files: Vec<String>, options: TargetOptions) -> Self {
+        let targets = files
+            .into_iter()
+            .map(|path| BatchTarget {
+                path,
+                max_generations: None,
+                seed: None,
+            })
+            .collect();
+        Batch {
+            targets,
+            options,
+            max_generations: DEFAULT_MAX_GENERATIONS,
+            jobs: default_jobs(),
+            reporter: Reporter::default(),
+        }
+    }
+
+    // Fuzz the targets listed in a TOML file
+    pub fn from_config(path: &str, options: TargetOptions) -> Result<Self, SideFuzzError> {
+        let toml = std::fs::read_to_string(path)?;
+        let spec: BatchSpec =
+            toml::from_str(&toml).map_err(|e| SideFuzzError::BadBatch(e.to_string()))?;
+        if spec.targets.is_empty() {
+            return Err(SideFuzzError::BadBatch(
+                "no [[target]] tables were found".to_string(),
+            ));
+        }
+
+        let mut batch = Self::new(Vec::new(), options);
+        batch.targets = spec.targets;
+        if let Some(max_generations) = spec.max_generations {
+            batch.max_generations = max_generations;
+        }
+        if let Some(jobs) = spec.jobs {
+            batch.set_jobs(jobs);
+        }
+        Ok(batch)
+    }
+
+    // Set the budget of targets that don't have their own
+    pub fn set_max_generations(&mut self, max_generations: u64) {
+        self.max_generations = max_generations;
+    }
+
+    // Set how many targets are fuzzed at the same time
+    pub fn set_jobs(&mut self, jobs: usize) {
+        self.jobs = jobs.max(1);
+    }
+
+    // Set how progress and the combined result are reported.
+    pub fn set_reporter(&mut self, reporter: Reporter) {
+        self.reporter = reporter;
+    }
+
+    // Exits with 1 if any target leaks, otherwise 2 if any target could not be fuzzed, and 0 if all is well.
+    pub fn run(&mut self) -> Result<(), SideFuzzError> {
+        let results = self.fuzz_all();
+
+        let mut text = Vec::new();
+        for result in results.iter() {
+            let outcome = match (&result.report, &result.error) {
+                (Some(report), _) if report.verdict == Verdict::Leak => {
+                    let risk = report
+                        .effect
+                        .and_then(|effect| effect.exploitability)
+                        .map(|exploitability| format!(", {} risk", exploitability.risk))
+                        .unwrap_or_default();
+                    let severity = report
+                        .severity
+                        .map(|severity| format!(" ({} severity{})", severity, risk))
+                        .unwrap_or_default();
+                    format!(
+                        "leak of {} instructions between {} and {}{}",
+                        report.difference, report.input_1, report.input_2, severity
+                    )
+                }
+                (Some(report), _) => format!(
+                    "no leak found in {} generations",
+                    report.generations.unwrap_or(0)
+                ),
+                (None, Some(error)) => format!("error: {}", error),
+                (None, None) => "error".to_string(),
+            };
+            text.push(format!("{}: {}", result.target, outcome));
+        }
+        self.reporter.finish_value(&results, &text.join("\n"))?;
+
+        if results.iter().any(|r| r.verdict == Some(Verdict::Leak)) {
+            std::process::exit(1);
+        }
+        if results.iter().any(|r| r.verdict.is_none()) {
+            std::process::exit(2);
+        }
+        std::process::exit(0);
+    }
+
+    // Fuzz every target, `jobs` at a time, returning results in the order the targets were given
+    fn fuzz_all(&self) -> Vec<BatchResult> {
+        let queue = Arc::new(Mutex::new(self.targets.clone().into_iter().enumerate()));
+        let (sender, receiver) = channel();
+
+        let mut handles = Vec::new();
+        for _ in 0..self.jobs.min(self.targets.len()) {
+            let queue = queue.clone();
+            let sender = sender.clone();
+            let options = self.options.clone();
+            let max_generations = self.max_generations;
+            let reporter = self.reporter.clone();
+
+            handles.push(std::thread::spawn(move || loop {
+                // Ok to unwrap, the lock is never held while anything can panic.
+                let next = queue.lock().unwrap().next();
+                let (index, target) = match next {
+                    Some(next) => next,
+                    None => return,
+                };
+                reporter.progress(format!("Fuzzing {}", target.path));
+                let result = fuzz_one(&target, &options, max_generations);
+                let verdict = match &result {
+                    Ok(report) => format!("{:?}", report.verdict),
+                    Err(err) => format!("error: {}", err),
+                };
+                reporter.progress(format!("{}: {}", target.path, verdict));
+                if sender.send((index, target.path, result)).is_err() {
+                    return;
+                }
+            }));
+        }
+        drop(sender);
+
+        let mut results: Vec<(usize, BatchResult)> = receiver
+            .into_iter()
+            .map(|(index, target, result)| {
+                let result = match result {
+                    Ok(report) => BatchResult {
+                        target,
+                        verdict: Some(report.verdict),
+                        error: None,
+                        report: Some(report),
+                    },
+                    Err(err) => BatchResult {
+                        target,
+                        verdict: None,
+                        error: Some(err.to_string()),
+                        report: None,
+                    },
+                };
+                (index, result)
+            })
+            .collect();
+        // A job that panicked never sent the result of the target it was fuzzing, and if every job panicked some
+        // targets were never fuzzed. Either way they count as errors, not as targets without a leak.
+        let panics: Vec<String> = handles
+            .into_iter()
+            .filter_map(|handle| handle.join().err())
+            .map(|panic| panic_message(&*panic))
+            .collect();
+        for (index, target) in self.targets.iter().enumerate() {
+            if results.iter().all(|(done, _)| *done != index) {
+                let error = if panics.is_empty() {
+                    "it wasn't fuzzed".to_string()
+                } else {
+                    format!("a job panicked while fuzzing it: {}", panics.join("; "))
+                };
+                let result = BatchResult {
+                    target: target.path.clone(),
+                    verdict: None,
+                    error: Some(error),
+                    report: None,
+                };
+                results.push((index, result));
+            }
+        }
+
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
+
+// What a panic said, if it said anything
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (None, Some(message)) => message.clone(),
+        (None, None) => "no message".to_string(),
+    }
+}
+
+fn fuzz_one(
+    target: &BatchTarget,
+    options: &TargetOptions,
+    max_generations: u64,
+) -> Result<Report, SideFuzzError> {
+    let mut fuzz = Fuzz::from_file_with_options(&target.path, options)?;
+    fuzz.set_max_generations(target.max_generations.unwrap_or(max_generations));
+    if let Some(seed) = target.seed {
+        fuzz.set_seed(seed);
+    }
+    let mut reporter = Reporter::default();
+    reporter.set_quiet(true);
+    fuzz.set_reporter(reporter);
+    // Reports only get a severity from the reporter they are finished with, and batch results are finished together
+    let mut report = fuzz.search()?;
+    report.severity = crate::severity::classify(&report);
+    Ok(report)
+}
+
+fn default_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_spec_test() {
+        let spec: BatchSpec = toml::from_str(
+            r#"
+            max_generations = 1000
+
+            [[target]]
+            path = "a.wasm"
+
+            [[target]]
+            path = "b.wasm"
+            seed = 7
+            "#,
+        )
+        .unwrap();
+        assert_eq!(spec.max_generations, Some(1000));
+        assert_eq!(spec.jobs, None);
+        assert_eq!(spec.targets.len(), 2);
+        assert_eq!(spec.targets[1].path, "b.wasm");
+        assert_eq!(spec.targets[1].seed, Some(7));
+    }
+
+    #[test]
+    fn panic_message_test() {
+        let panic = std::panic::catch_unwind(|| panic!("boom")).unwrap_err();
+        assert_eq!(panic_message(&*panic), "boom");
+        let panic = std::panic::catch_unwind(|| panic!("{} went wrong", 3)).unwrap_err();
+        assert_eq!(panic_message(&*panic), "3 went wrong");
+    }
+}
@@ -0,0 +1,598 @@
+// Minimal parsing and rewriting of the wasm binary format
+//
+// wasmi doesn't give us access to everything we need (non-exported globals for example),
+// so in a few places we look at, or patch, the raw module bytes before handing them to wasmi.
+
+use std::collections::HashMap;
+
+// Section ids
+pub(crate) const SECTION_CUSTOM: u8 = 0;
+pub(crate) const SECTION_TYPE: u8 = 1;
+pub(crate) const SECTION_IMPORT: u8 = 2;
+pub(crate) const SECTION_FUNCTION: u8 = 3;
+pub(crate) const SECTION_MEMORY: u8 = 5;
+pub(crate) const SECTION_GLOBAL: u8 = 6;
+pub(crate) const SECTION_EXPORT: u8 = 7;
+pub(crate) const SECTION_START: u8 = 8;
+pub(crate) const SECTION_ELEMENT: u8 = 9;
+pub(crate) const SECTION_CODE: u8 = 10;
+
+// External kinds used in imports and exports
+pub(crate) const KIND_FUNC: u8 = 0;
+pub(crate) const KIND_GLOBAL: u8 = 3;
+
+// Prefix for the exports we add for otherwise hidden mutable globals
+pub(crate) const GLOBAL_EXPORT_PREFIX: &str = "__sidefuzz_global_";
+
+// A section in a wasm binary. `header` is the offset of the id byte,
+// `start` and `end` delimit the payload (not including the id and size).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Section {
+    pub id: u8,
+    pub header: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// Read an unsigned LEB128 u32, advancing `pos`
+pub(crate) fn read_u32(bytes: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut result: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        result |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift > 35 {
+            return None;
+        }
+    }
+    if result > u32::max_value() as u64 {
+        return None;
+    }
+    Some(result as u32)
+}
+
+// Skip over a signed or unsigned LEB128 of any length, advancing `pos`
+pub(crate) fn skip_leb(bytes: &[u8], pos: &mut usize) -> Option<()> {
+    loop {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        if byte & 0x80 == 0 {
+            return Some(());
+        }
+    }
+}
+
+// Write an unsigned LEB128 u32
+pub(crate) fn write_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+// Write a signed LEB128 i64
+pub(crate) fn write_i64(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// Write a section, with its id and size
+pub(crate) fn write_section(out: &mut Vec<u8>, id: u8, payload: &[u8]) {
+    out.push(id);
+    write_u32(out, payload.len() as u32);
+    out.extend_from_slice(payload);
+}
+
+// Read a length-prefixed name, advancing `pos`
+pub(crate) fn read_name(bytes: &[u8], pos: &mut usize) -> Option<String> {
+    let len = read_u32(bytes, pos)? as usize;
+    let name = bytes.get(*pos..*pos + len)?;
+    *pos += len;
+    String::from_utf8(name.to_vec()).ok()
+}
+
+// Split a module into its sections. Returns None if the bytes are not a wasm module.
+pub(crate) fn sections(bytes: &[u8]) -> Option<Vec<Section>> {
+    if bytes.len() < 8 || &bytes[0..4] != b"\0asm" {
+        return None;
+    }
+
+    let mut sections = Vec::new();
+    let mut pos = 8;
+    while pos < bytes.len() {
+        let header = pos;
+        let id = bytes[pos];
+        pos += 1;
+        let size = read_u32(bytes, &mut pos)? as usize;
+        let start = pos;
+        let end = start.checked_add(size)?;
+        if end > bytes.len() {
+            return None;
+        }
+        sections.push(Section {
+            id,
+            header,
+            start,
+            end,
+        });
+        pos = end;
+    }
+
+    Some(sections)
+}
+
+// Skip over a constant initializer expression, advancing `pos` past the final `end` opcode
+fn skip_init_expr(bytes: &[u8], pos: &mut usize) -> Option<()> {
+    loop {
+        let opcode = *bytes.get(*pos)?;
+        *pos += 1;
+        match opcode {
+            0x0b => return Some(()),                            // end
+            0x41 | 0x42 | 0x23 | 0xd2 => skip_leb(bytes, pos)?, // i32.const, i64.const, global.get, ref.func
+            0x43 => *pos += 4,                                  // f32.const
+            0x44 => *pos += 8,                                  // f64.const
+            0xd0 => *pos += 1,                                  // ref.null
+            _ => return None,
+        }
+    }
+}
+
+// Get the mutability of every global in the module's global index space (imported globals first)
+pub(crate) fn global_mutability(bytes: &[u8]) -> Option<Vec<bool>> {
+    let mut globals = Vec::new();
+
+    for section in sections(bytes)? {
+        let mut pos = section.start;
+        if section.id == SECTION_IMPORT {
+            let count = read_u32(bytes, &mut pos)?;
+            for _ in 0..count {
+                read_name(bytes, &mut pos)?;
+                read_name(bytes, &mut pos)?;
+                let kind = *bytes.get(pos)?;
+                pos += 1;
+                match kind {
+                    // Function: type index
+                    0 => skip_leb(bytes, &mut pos)?,
+                    // Table: reftype + limits
+                    1 => {
+                        pos += 1;
+                        skip_limits(bytes, &mut pos)?;
+                    }
+                    // Memory: limits
+                    2 => skip_limits(bytes, &mut pos)?,
+                    // Global: valtype + mutability
+                    KIND_GLOBAL => {
+                        globals.push(*bytes.get(pos + 1)? == 1);
+                        pos += 2;
+                    }
+                    _ => return None,
+                }
+            }
+        } else if section.id == SECTION_GLOBAL {
+            let count = read_u32(bytes, &mut pos)?;
+            for _ in 0..count {
+                globals.push(*bytes.get(pos + 1)? == 1);
+                pos += 2;
+                skip_init_expr(bytes, &mut pos)?;
+            }
+        }
+    }
+
+    Some(globals)
+}
+
+// Read function names from the "name" custom section, if the module has one.
+// Names are keyed by function index, including imported functions.
+pub(crate) fn function_names(bytes: &[u8]) -> HashMap<u32, String> {
+    let mut names = HashMap::new();
+    let sections = match sections(bytes) {
+        Some(sections) => sections,
+        None => return names,
+    };
+
+    for section in sections.iter().filter(|s| s.id == SECTION_CUSTOM) {
+        let mut pos = section.start;
+        if read_name(bytes, &mut pos).as_deref() != Some("name") {
+            continue;
+        }
+
+        // The name section is a sequence of subsections, subsection 1 holds function names
+        while pos < section.end {
+            let id = bytes[pos];
+            pos += 1;
+            let size = match read_u32(bytes, &mut pos) {
+                Some(size) => size as usize,
+                None => return names,
+            };
+            let end = pos + size;
+            if id == 1 {
+                let mut entry = pos;
+                let count = read_u32(bytes, &mut entry).unwrap_or(0);
+                for _ in 0..count {
+                    let index = read_u32(bytes, &mut entry);
+                    let name = read_name(bytes, &mut entry);
+                    match (index, name) {
+                        (Some(index), Some(name)) => names.insert(index, name),
+                        _ => break,
+                    };
+                }
+            }
+            pos = end;
+        }
+    }
+
+    names
+}
+
+// The name and contents of every custom section, in the order they appear
+pub(crate) fn custom_sections(bytes: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let sections = match sections(bytes) {
+        Some(sections) => sections,
+        None => return Vec::new(),
+    };
+
+    let mut custom = Vec::new();
+    for section in sections.iter().filter(|s| s.id == SECTION_CUSTOM) {
+        let mut pos = section.start;
+        if let Some(name) = read_name(bytes, &mut pos).filter(|_| pos <= section.end) {
+            custom.push((name, bytes[pos..section.end].to_vec()));
+        }
+    }
+    custom
+}
+
+// A memory defined or imported by a module
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MemoryType {
+    pub memory64: bool, // Indexed with 64-bit addresses, from the memory64 proposal
+    pub imported: bool,
+    pub initial_pages: u64, // Of 64 KiB each
+    pub maximum_pages: Option<u64>,
+}
+
+// Limits flag marking a 64-bit memory
+const LIMITS_MEMORY64: u8 = 0x04;
+
+// Get every memory in the module's memory index space (imported memories first).
+// More than one means the module uses the multi-memory proposal.
+pub(crate) fn memories(bytes: &[u8]) -> Option<Vec<MemoryType>> {
+    let mut memories = Vec::new();
+
+    for section in sections(bytes)? {
+        let mut pos = section.start;
+        if section.id == SECTION_IMPORT {
+            let count = read_u32(bytes, &mut pos)?;
+            for _ in 0..count {
+                read_name(bytes, &mut pos)?;
+                read_name(bytes, &mut pos)?;
+                let kind = *bytes.get(pos)?;
+                pos += 1;
+                match kind {
+                    0 => skip_leb(bytes, &mut pos)?,
+                    1 => {
+                        pos += 1;
+                        skip_limits(bytes, &mut pos)?;
+                    }
+                    2 => {
+                        let memory64 = *bytes.get(pos)? & LIMITS_MEMORY64 != 0;
+                        let (initial_pages, maximum_pages) = read_limits(bytes, &mut pos)?;
+                        memories.push(MemoryType {
+                            memory64,
+                            imported: true,
+                            initial_pages,
+                            maximum_pages,
+                        });
+                    }
+                    KIND_GLOBAL => pos += 2,
+                    _ => return None,
+                }
+            }
+        } else if section.id == SECTION_MEMORY {
+            let count = read_u32(bytes, &mut pos)?;
+            for _ in 0..count {
+                let memory64 = *bytes.get(pos)? & LIMITS_MEMORY64 != 0;
+                let (initial_pages, maximum_pages) = read_limits(bytes, &mut pos)?;
+                memories.push(MemoryType {
+                    memory64,
+                    imported: false,
+                    initial_pages,
+                    maximum_pages,
+                });
+            }
+        }
+    }
+
+    Some(memories)
+}
+
+// Read memory limits, the initial and maximum size, advancing `pos`. The sizes of 64-bit memories are 64-bit.
+fn read_limits(bytes: &[u8], pos: &mut usize) -> Option<(u64, Option<u64>)> {
+    let flags = *bytes.get(*pos)?;
+    *pos += 1;
+    let initial = read_u64(bytes, pos)?;
+    let maximum = if flags & 0x01 == 1 {
+        Some(read_u64(bytes, pos)?)
+    } else {
+        None
+    };
+    Some((initial, maximum))
+}
+
+// Read an unsigned LEB128 u64, advancing `pos`
+fn read_u64(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut result: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        result |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+    }
+    None
+}
+
+// Skip over table or memory limits, advancing `pos`
+pub(crate) fn skip_limits(bytes: &[u8], pos: &mut usize) -> Option<()> {
+    let flags = *bytes.get(*pos)?;
+    *pos += 1;
+    skip_leb(bytes, pos)?;
+    if flags & 0x01 == 1 {
+        skip_leb(bytes, pos)?;
+    }
+    Some(())
+}
+
+// Rewrite the module so that it defines `count` more globals after its own, each of them `global`: the type,
+// mutability and initializer of a global as encoded in the global section. Returns None if the module could not
+// be parsed.
+pub(crate) fn add_globals(bytes: &[u8], global: &[u8], count: u32) -> Option<Vec<u8>> {
+    let sections = sections(bytes)?;
+    let mut payload = Vec::new();
+    let (header, end) = match sections.iter().find(|s| s.id == SECTION_GLOBAL) {
+        Some(section) => {
+            let mut pos = section.start;
+            let existing = read_u32(bytes, &mut pos)?;
+            write_u32(&mut payload, existing.checked_add(count)?);
+            payload.extend_from_slice(&bytes[pos..section.end]);
+            (section.header, section.end)
+        }
+        None => {
+            // A new global section goes before the first of the sections that follow it: exports, start, elements,
+            // data count, code and data
+            let at = sections
+                .iter()
+                .find(|s| (SECTION_EXPORT..=12).contains(&s.id))
+                .map_or(bytes.len(), |s| s.header);
+            write_u32(&mut payload, count);
+            (at, at)
+        }
+    };
+    for _ in 0..count {
+        payload.extend_from_slice(global);
+    }
+
+    let mut out = Vec::with_capacity(bytes.len() + payload.len());
+    out.extend_from_slice(&bytes[..header]);
+    write_section(&mut out, SECTION_GLOBAL, &payload);
+    out.extend_from_slice(&bytes[end..]);
+    Some(out)
+}
+
+// Rewrite the module so that every mutable global is exported.
+// Exported globals can be read and written by the host, which lets us snapshot and restore them.
+// Returns None if the module could not be parsed, or has no export section.
+pub(crate) fn export_mutable_globals(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mutable = global_mutability(bytes)?;
+    let sections = sections(bytes)?;
+    let export = sections.iter().find(|s| s.id == SECTION_EXPORT)?;
+
+    // Find which globals are already exported
+    let mut exported = vec![false; mutable.len()];
+    let mut pos = export.start;
+    let count = read_u32(bytes, &mut pos)?;
+    let entries_start = pos;
+    for _ in 0..count {
+        read_name(bytes, &mut pos)?;
+        let kind = *bytes.get(pos)?;
+        pos += 1;
+        let index = read_u32(bytes, &mut pos)? as usize;
+        if kind == KIND_GLOBAL && index < exported.len() {
+            exported[index] = true;
+        }
+    }
+
+    let missing: Vec<usize> = (0..mutable.len())
+        .filter(|&i| mutable[i] && !exported[i])
+        .collect();
+    if missing.is_empty() {
+        return Some(bytes.to_vec());
+    }
+
+    // Build the new export section payload
+    let mut payload = Vec::new();
+    write_u32(&mut payload, count + missing.len() as u32);
+    payload.extend_from_slice(&bytes[entries_start..export.end]);
+    for index in missing {
+        let name = format!("{}{}", GLOBAL_EXPORT_PREFIX, index);
+        write_u32(&mut payload, name.len() as u32);
+        payload.extend_from_slice(name.as_bytes());
+        payload.push(KIND_GLOBAL);
+        write_u32(&mut payload, index as u32);
+    }
+
+    // Splice it in place of the old one
+    let mut out = Vec::with_capacity(bytes.len() + payload.len());
+    out.extend_from_slice(&bytes[..export.header]);
+    write_section(&mut out, SECTION_EXPORT, &payload);
+    out.extend_from_slice(&bytes[export.end..]);
+
+    Some(out)
+}
+
+// A target for tests, with a one byte input at address 0 and a mutable i32 global starting at 0. `fuzz` is the body
+// of its fuzz function, and every one of `startup` is exported under its name.
+#[cfg(test)]
+pub(crate) fn test_target(fuzz: &[u8], startup: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    // () -> i32 and () -> ()
+    write_section(
+        &mut module,
+        SECTION_TYPE,
+        &[0x02, 0x60, 0x00, 0x01, 0x7f, 0x60, 0x00, 0x00],
+    );
+    let mut functions = vec![3 + startup.len() as u8, 0x00, 0x00, 0x01];
+    functions.extend(startup.iter().map(|_| 0x01));
+    write_section(&mut module, SECTION_FUNCTION, &functions);
+    write_section(&mut module, SECTION_MEMORY, &[0x01, 0x00, 0x01]);
+    write_section(
+        &mut module,
+        SECTION_GLOBAL,
+        &[0x01, 0x7f, 0x01, 0x41, 0x00, 0x0b],
+    );
+
+    let mut names = vec!["input_pointer", "input_len", "fuzz"];
+    names.extend(startup.iter().map(|(name, _)| *name));
+    let mut exports = vec![names.len() as u8 + 1];
+    write_u32(&mut exports, 6);
+    exports.extend_from_slice(b"memory\x02\x00");
+    for (index, name) in names.iter().enumerate() {
+        write_u32(&mut exports, name.len() as u32);
+        exports.extend_from_slice(name.as_bytes());
+        exports.extend_from_slice(&[KIND_FUNC, index as u8]);
+    }
+    write_section(&mut module, SECTION_EXPORT, &exports);
+
+    // input_pointer returns 0, input_len returns 1
+    let mut bodies: Vec<&[u8]> = vec![&[0x00, 0x41, 0x00, 0x0b], &[0x00, 0x41, 0x01, 0x0b], fuzz];
+    bodies.extend(startup.iter().map(|(_, body)| *body));
+    let mut code = vec![bodies.len() as u8];
+    for body in bodies {
+        write_u32(&mut code, body.len() as u32);
+        code.extend_from_slice(body);
+    }
+    write_section(&mut module, SECTION_CODE, &code);
+    module
+}
+
+// Bodies of fuzz functions that run longer on every call, for checking that `--fresh-memory` resets them. The first
+// increments global 0 and loops that many times, the second does the same with the byte at address 8.
+#[cfg(test)]
+pub(crate) const GROWING_GLOBAL: &[u8] = &[
+    0x01, 0x01, 0x7f, 0x23, 0x00, 0x41, 0x01, 0x6a, 0x24, 0x00, 0x23, 0x00, 0x21, 0x00, 0x03, 0x40,
+    0x20, 0x00, 0x41, 0x01, 0x6b, 0x22, 0x00, 0x0d, 0x00, 0x0b, 0x0b,
+];
+#[cfg(test)]
+pub(crate) const GROWING_MEMORY: &[u8] = &[
+    0x01, 0x01, 0x7f, 0x41, 0x08, 0x41, 0x08, 0x2d, 0x00, 0x00, 0x41, 0x01, 0x6a, 0x3a, 0x00, 0x00,
+    0x41, 0x08, 0x2d, 0x00, 0x00, 0x21, 0x00, 0x03, 0x40, 0x20, 0x00, 0x41, 0x01, 0x6b, 0x22, 0x00,
+    0x0d, 0x00, 0x0b, 0x0b,
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::binary::*;
+
+    // A module with a mutable global 0, and a constant global 1 exported as "c"
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic + version
+        0x06, 0x0b, 0x02, 0x7f, 0x01, 0x41, 0x00, 0x0b, 0x7f, 0x00, 0x41, 0x01,
+        0x0b, // globals
+        0x07, 0x05, 0x01, 0x01, b'c', 0x03, 0x01, // exports
+    ];
+
+    #[test]
+    fn export_mutable_globals_test() {
+        assert_eq!(global_mutability(MODULE), Some(vec![true, false]));
+
+        let rewritten = export_mutable_globals(MODULE).unwrap();
+        let sections = sections(&rewritten).unwrap();
+        assert_eq!(sections.len(), 2);
+
+        let export = sections[1];
+        assert_eq!(export.id, SECTION_EXPORT);
+
+        let mut pos = export.start;
+        assert_eq!(read_u32(&rewritten, &mut pos), Some(2));
+        assert_eq!(read_name(&rewritten, &mut pos).unwrap(), "c");
+        assert_eq!(&rewritten[pos..pos + 2], &[KIND_GLOBAL, 1]);
+        pos += 2;
+        assert_eq!(
+            read_name(&rewritten, &mut pos).unwrap(),
+            format!("{}0", GLOBAL_EXPORT_PREFIX)
+        );
+        assert_eq!(&rewritten[pos..pos + 2], &[KIND_GLOBAL, 0]);
+        assert_eq!(pos + 2, export.end);
+    }
+
+    #[test]
+    fn add_globals_test() {
+        // An i64 global starting at 0
+        let global = [0x7e, 0x01, 0x42, 0x00, 0x0b];
+        let added = add_globals(MODULE, &global, 2).unwrap();
+        assert_eq!(global_mutability(&added), Some(vec![true, false, true, true]));
+
+        // Modules without globals get a global section before their exports
+        let module = &[MODULE[..8].to_vec(), MODULE[21..].to_vec()].concat();
+        let added = add_globals(module, &global, 1).unwrap();
+        assert_eq!(global_mutability(&added), Some(vec![true]));
+        let ids: Vec<u8> = sections(&added).unwrap().iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![SECTION_GLOBAL, SECTION_EXPORT]);
+    }
+
+    #[test]
+    fn memories_test() {
+        let mut module = MODULE[..8].to_vec();
+        module.extend_from_slice(&[
+            0x02, 0x0f, 0x01, 0x03, b'e', b'n', b'v', 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02,
+            0x00, 0x01, // imports "env" "memory"
+            0x05, 0x05, 0x02, 0x00, 0x01, 0x04, 0x01, // a 32-bit and a 64-bit memory
+        ]);
+        let memory = |memory64, imported| MemoryType {
+            memory64,
+            imported,
+            initial_pages: 1,
+            maximum_pages: None,
+        };
+        assert_eq!(
+            memories(&module),
+            Some(vec![memory(false, true), memory(false, false), memory(true, false)])
+        );
+        assert_eq!(memories(MODULE), Some(vec![]));
+    }
+
+    #[test]
+    fn function_names_test() {
+        let mut module = MODULE[..8].to_vec();
+        let names = [
+            0x04, b'n', b'a', b'm', b'e', // section name
+            0x01, 0x07, 0x01, 0x02, 0x04, b'f', b'u', b'z', b'z', // function 2 is "fuzz"
+        ];
+        write_section(&mut module, SECTION_CUSTOM, &names);
+
+        let names = function_names(&module);
+        assert_eq!(names.len(), 1);
+        assert_eq!(names[&2], "fuzz");
+        assert_eq!(custom_sections(&module)[0].0, "name");
+    }
+}
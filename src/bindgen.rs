@@ -0,0 +1,205 @@
+// Compatibility with modules built by wasm-bindgen and wasm-pack
+//
+// wasm-bindgen expects its JS glue to be loaded alongside the module: it imports `__wbindgen_*` functions from
+// it, and exports a `__wbindgen_start` function that the glue calls before anything else. The imports are
+// satisfied by the usual stubs. Exports may also be renamed from the names sidefuzz looks for, with a `__wbg_`
+// prefix or as a mangled Rust symbol when `#[no_mangle]` is missing, so those are looked up behind the shim.
+
+use std::collections::HashMap;
+use wasmi::Module;
+
+// Modules the JS glue of wasm-bindgen provides imports from
+const BINDGEN_MODULES: &[&str] = &[
+    "__wbindgen_placeholder__",
+    "__wbindgen_externref_xform__",
+    "wbg",
+];
+
+// The exports sidefuzz uses
+const SIDEFUZZ_EXPORTS: &[&str] = &[
+    "memory",
+    "fuzz",
+    "input_pointer",
+    "input_len",
+    "input_is_str",
+    "input_kind",
+    "input_count",
+    "input_pointer_at",
+    "input_len_at",
+    "input_is_public_at",
+    "input_actual_len",
+    "fuzz_batch",
+    "input_batch_pointer",
+    "batch_results_pointer",
+    "batch_capacity",
+    "output_pointer",
+    "output_len",
+    "key_pointer",
+    "key_len",
+];
+
+// The exports an entry point can have its own of, named after it such as `fuzz_sign_input_len`
+const ENTRY_EXPORTS: &[&str] = &[
+    "input_pointer",
+    "input_len",
+    "input_is_str",
+    "input_kind",
+    "input_count",
+    "input_pointer_at",
+    "input_len_at",
+    "input_is_public_at",
+    "input_actual_len",
+    "output_pointer",
+    "output_len",
+];
+
+// Export the JS glue calls on startup
+pub(crate) const BINDGEN_START: &str = "__wbindgen_start";
+
+// Whether the module was built by wasm-bindgen
+pub(crate) fn is_bindgen(module: &Module) -> bool {
+    module.imports().any(|import| {
+        BINDGEN_MODULES.contains(&import.module())
+            || import.name().starts_with("__wbindgen_")
+            || import.name().starts_with("__wbg_")
+    }) || module
+        .exports()
+        .any(|export| export.name().starts_with("__wbindgen_"))
+}
+
+// The names the exports sidefuzz uses actually have in a module
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Exports {
+    names: HashMap<&'static str, String>,
+}
+
+impl Exports {
+    // Prefer exports with exactly the expected name, then any single export that is the expected name behind a shim
+    pub fn resolve<'a, I: IntoIterator<Item = &'a str>>(exports: I) -> Self {
+        let exports: Vec<&str> = exports.into_iter().collect();
+        let mut names = HashMap::new();
+        for expected in SIDEFUZZ_EXPORTS.iter() {
+            if exports.contains(expected) {
+                continue;
+            }
+            let mut candidates = exports
+                .iter()
+                .filter(|export| unshimmed(export).as_deref() == Some(*expected));
+            if let (Some(found), None) = (candidates.next(), candidates.next()) {
+                names.insert(*expected, found.to_string());
+            }
+        }
+        Exports { names }
+    }
+
+    // Fuzz the export `entry` instead of "fuzz", with the input exports named after it if there are any.
+    // Otherwise the input is found through the shared exports, once `entry` has fetched it.
+    // Returns the entry points the module does have if it doesn't export `entry`.
+    pub fn set_entry(&mut self, exports: &[&str], entry: &str) -> Result<(), Vec<String>> {
+        if !exports.contains(&entry) {
+            return Err(entry_points(exports));
+        }
+        self.names.insert("fuzz", entry.to_string());
+        for name in ENTRY_EXPORTS.iter() {
+            let own = format!("{}_{}", entry, name);
+            if exports.contains(&own.as_str()) {
+                self.names.insert(*name, own);
+            }
+        }
+        Ok(())
+    }
+
+    // The name of the export sidefuzz calls `name`
+    pub fn name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.names.get(name).map(String::as_str).unwrap_or(name)
+    }
+}
+
+// Exports that look like something to fuzz, such as "fuzz_sign"
+pub(crate) fn entry_points(exports: &[&str]) -> Vec<String> {
+    exports
+        .iter()
+        .filter(|export| **export == "fuzz" || (export.starts_with("fuzz_") && !SIDEFUZZ_EXPORTS.contains(export)))
+        .filter(|export| !ENTRY_EXPORTS.iter().any(|name| export.ends_with(&format!("_{}", name))))
+        .map(|export| export.to_string())
+        .collect()
+}
+
+// The name an export had before wasm-bindgen prefixed it or rustc mangled it
+fn unshimmed(export: &str) -> Option<String> {
+    if let Some(name) = export.strip_prefix("__wbg_") {
+        return Some(name.to_string());
+    }
+    demangle_legacy(export)
+}
+
+// The last path segment of a legacy mangled Rust symbol, such as `_ZN6target4fuzz17h0123456789abcdefE`
+fn demangle_legacy(symbol: &str) -> Option<String> {
+    let mut rest = symbol.strip_prefix("_ZN")?.strip_suffix('E')?;
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        let len: usize = rest[..digits].parse().ok()?;
+        let segment = rest.get(digits..digits + len)?;
+        segments.push(segment);
+        rest = &rest[digits + len..];
+    }
+
+    // The last segment is a hash, like h0123456789abcdef
+    let is_hash = |segment: &&str| {
+        segment.len() == 17
+            && segment.starts_with('h')
+            && segment[1..].chars().all(|c| c.is_ascii_hexdigit())
+    };
+    if segments.last().map_or(false, is_hash) {
+        segments.pop();
+    }
+    segments.last().map(|segment| segment.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_test() {
+        let exports = Exports::resolve(vec![
+            "memory",
+            "__wbg_fuzz",
+            "_ZN9my_target13input_pointer17h0123456789abcdefE",
+            "input_len",
+            "__wbg_input_len",
+            "_ZN1a12input_is_str17h0123456789abcdefE",
+            "_ZN1b12input_is_str17hfedcba9876543210E",
+            "__wbindgen_start",
+        ]);
+        assert_eq!(exports.name("memory"), "memory");
+        assert_eq!(exports.name("fuzz"), "__wbg_fuzz");
+        assert_eq!(
+            exports.name("input_pointer"),
+            "_ZN9my_target13input_pointer17h0123456789abcdefE"
+        );
+        // Exact names win
+        assert_eq!(exports.name("input_len"), "input_len");
+        // Ambiguous exports are left alone
+        assert_eq!(exports.name("input_is_str"), "input_is_str");
+
+        let names = ["memory", "fuzz_sign", "fuzz_verify", "fuzz_verify_input_len", "fuzz_batch", "input_len"];
+        let mut entry = Exports::resolve(names.iter().copied());
+        entry.set_entry(&names, "fuzz_verify").unwrap();
+        assert_eq!(entry.name("fuzz"), "fuzz_verify");
+        assert_eq!(entry.name("input_len"), "fuzz_verify_input_len");
+        assert_eq!(entry.name("input_pointer"), "input_pointer");
+        assert_eq!(
+            entry.set_entry(&names, "fuzz_derive"),
+            Err(vec!["fuzz_sign".to_string(), "fuzz_verify".to_string()])
+        );
+
+        assert_eq!(
+            demangle_legacy("_ZN4core3fmt5write17h0123456789abcdefE").as_deref(),
+            Some("write")
+        );
+        assert_eq!(demangle_legacy("_ZN4core3fmt5wri"), None);
+        assert_eq!(demangle_legacy("fuzz"), None);
+    }
+}
@@ -0,0 +1,227 @@
+// This file contains the "bitmap" subcommand, which maps which input bits change the instruction count
+//
+// Each bit of a baseline input is flipped on its own, and the count compared against the baseline's. Bits that
+// change it are the ones the target's running time depends on, so for a secret input they show where the leak is.
+// With several baselines a bit can matter for some of them only, which is shown by how often it changed the count.
+
+use crate::errors::SideFuzzError;
+use crate::target::{Target, TargetOptions};
+use crate::util::random_input;
+use rand::{rngs::StdRng, SeedableRng};
+use std::io::{BufWriter, Write};
+
+// Shades for how much flipping a bit changes the count, from not at all to the largest change seen
+const SHADES: &[char] = &['.', ':', '+', '#'];
+
+pub struct Bitmap {
+    module: Box<dyn Target>,
+    baselines: Vec<Vec<u8>>,
+    csv: Option<String>,
+}
+
+// What flipping one bit did, over every baseline
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sensitivity {
+    pub changed: usize,  // Baselines whose count changed
+    pub crashed: usize,  // Baselines where the flipped input crashed
+    pub mean_delta: f64, // The mean absolute change in the count, over the baselines that didn't crash
+}
+
+impl Bitmap {
+    pub fn from_file_with_options(
+        filename: &str,
+        options: &TargetOptions,
+        baseline: Option<Vec<u8>>,
+    ) -> Result<Self, SideFuzzError> {
+        let module = crate::target::load(filename, options)?;
+        if let Some(baseline) = &baseline {
+            if baseline.len() != module.fuzz_len() {
+                return Err(SideFuzzError::InputsWrongSize(module.fuzz_len()));
+            }
+        }
+        Ok(Bitmap {
+            module,
+            baselines: baseline.into_iter().collect(),
+            csv: None,
+        })
+    }
+
+    // Also flip the bits of `count` random baselines, generated from `seed`
+    pub fn add_random_baselines(&mut self, count: usize, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let len = self.module.fuzz_len();
+        let is_str = self.module.input_is_str();
+        for _ in 0..count {
+            self.baselines.push(random_input(&mut rng, len, is_str));
+        }
+    }
+
+    // Also write the change for every bit and baseline to a CSV file
+    pub fn set_csv(&mut self, path: &str) {
+        self.csv = Some(path.to_string());
+    }
+
+    // Exits with 1 if any bit changes the instruction count, and 0 otherwise
+    pub fn run(&mut self) -> Result<(), SideFuzzError> {
+        if self.baselines.is_empty() {
+            return Err(SideFuzzError::NoInputs);
+        }
+
+        let mut deltas = Vec::with_capacity(self.baselines.len());
+        for baseline in self.baselines.iter() {
+            let count = self.module.count_instructions(baseline)?;
+            deltas.push(flip_bits(self.module.as_mut(), baseline, count));
+        }
+        if let Some(path) = &self.csv {
+            write_csv(path, &deltas)?;
+        }
+
+        let sensitivities = sensitivities(&deltas);
+        print!(
+            "{}",
+            render(&sensitivities, &self.module.input_segments(), deltas.len())
+        );
+
+        let leaking = sensitivities.iter().filter(|s| s.changed > 0).count();
+        println!();
+        println!(
+            "{} of {} bits change the instruction count, over {} baselines",
+            leaking,
+            sensitivities.len(),
+            deltas.len()
+        );
+        if leaking > 0 {
+            std::process::exit(1);
+        } else {
+            std::process::exit(0);
+        }
+    }
+}
+
+// The change in the count from flipping each bit of `baseline` in turn, or None if the flipped input crashed.
+// Bit 0 is the least significant bit of the first byte.
+fn flip_bits(module: &mut dyn Target, baseline: &[u8], count: u64) -> Vec<Option<i64>> {
+    let mut input = baseline.to_vec();
+    let mut deltas = Vec::with_capacity(baseline.len() * 8);
+    for bit in 0..baseline.len() * 8 {
+        input[bit / 8] ^= 1 << (bit % 8);
+        let flipped = module.count_instructions(&input).ok();
+        deltas.push(flipped.map(|flipped| flipped as i64 - count as i64));
+        input[bit / 8] ^= 1 << (bit % 8);
+    }
+    deltas
+}
+
+// Combine the changes of every baseline, `deltas` holds one list of changes per baseline
+pub(crate) fn sensitivities(deltas: &[Vec<Option<i64>>]) -> Vec<Sensitivity> {
+    let bits = deltas.first().map_or(0, |deltas| deltas.len());
+    let mut sensitivities = vec![Sensitivity::default(); bits];
+    for (bit, sensitivity) in sensitivities.iter_mut().enumerate() {
+        let mut total = 0;
+        for baseline in deltas.iter() {
+            match baseline[bit] {
+                Some(0) => {}
+                Some(delta) => {
+                    sensitivity.changed += 1;
+                    total += delta.abs();
+                }
+                None => sensitivity.crashed += 1,
+            }
+        }
+        let ran = deltas.len() - sensitivity.crashed;
+        if ran > 0 {
+            sensitivity.mean_delta = total as f64 / ran as f64;
+        }
+    }
+    sensitivities
+}
+
+// A heatmap with a row for each byte, most significant bit first, starting a new block for each separate input
+pub(crate) fn render(
+    sensitivities: &[Sensitivity],
+    segments: &[usize],
+    baselines: usize,
+) -> String {
+    let max = sensitivities
+        .iter()
+        .map(|s| s.mean_delta)
+        .fold(0.0, f64::max);
+    let mut out = String::new();
+    out += &format!(
+        "Flipping each bit of {} baseline input{}. Shades from none to the largest change ({:.1} instructions): {}, x: crashed\n",
+        baselines,
+        if baselines == 1 { "" } else { "s" },
+        max,
+        SHADES.iter().collect::<String>()
+    );
+
+    let mut offset = 0;
+    for (index, len) in segments.iter().enumerate() {
+        if segments.len() > 1 {
+            out += &format!("\ninput {} ({} bytes)\n", index + 1, len);
+        }
+        out += "\n      76543210\n";
+        for byte in offset..offset + len {
+            let mut row = String::with_capacity(8);
+            for bit in (0..8).rev() {
+                row.push(shade(&sensitivities[byte * 8 + bit], max));
+            }
+            out += &format!("{:>5} {}\n", byte - offset, row);
+        }
+        offset += len;
+    }
+    out
+}
+
+fn shade(sensitivity: &Sensitivity, max: f64) -> char {
+    if sensitivity.crashed > 0 && sensitivity.changed == 0 {
+        return 'x';
+    }
+    if sensitivity.changed == 0 || max <= 0.0 {
+        return SHADES[0];
+    }
+    // Any change at all is shaded, however small
+    let level = (sensitivity.mean_delta / max * (SHADES.len() - 1) as f64).ceil() as usize;
+    SHADES[level.max(1).min(SHADES.len() - 1)]
+}
+
+fn write_csv(path: &str, deltas: &[Vec<Option<i64>>]) -> Result<(), SideFuzzError> {
+    let file = std::fs::File::create(path).map_err(SideFuzzError::CouldNotWriteFile)?;
+    let mut out = BufWriter::new(file);
+    writeln!(out, "baseline,byte,bit,delta,crash").map_err(SideFuzzError::CouldNotWriteFile)?;
+    for (baseline, deltas) in deltas.iter().enumerate() {
+        for (bit, delta) in deltas.iter().enumerate() {
+            let line = match delta {
+                Some(delta) => format!("{},{},{},{},false", baseline, bit / 8, bit % 8, delta),
+                None => format!("{},{},{},,true", baseline, bit / 8, bit % 8),
+            };
+            writeln!(out, "{}", line).map_err(SideFuzzError::CouldNotWriteFile)?;
+        }
+    }
+    out.flush().map_err(SideFuzzError::CouldNotWriteFile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitmap_test() {
+        // Two one byte baselines: bit 0 always matters, bit 1 only for the first, bit 7 crashes the second
+        let mut first = vec![Some(0); 8];
+        let mut second = vec![Some(0); 8];
+        first[0] = Some(10);
+        second[0] = Some(-30);
+        first[1] = Some(4);
+        second[7] = None;
+
+        let sensitivities = sensitivities(&[first, second]);
+        assert_eq!(sensitivities[0].changed, 2);
+        assert_eq!(sensitivities[0].mean_delta, 20.0);
+        assert_eq!(sensitivities[1].changed, 1);
+        assert_eq!(sensitivities[7].crashed, 1);
+
+        let map = render(&sensitivities, &[1], 2);
+        assert!(map.ends_with("    0 x.....:#\n"));
+    }
+}
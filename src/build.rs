@@ -0,0 +1,84 @@
+// This file contains the "build" subcommand
+
+use crate::errors::SideFuzzError;
+use crate::inspect::inspect;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+pub struct Build {
+    dir: PathBuf,
+}
+
+impl Build {
+    // Build the target crate in `dir`
+    pub fn new(dir: &str) -> Self {
+        Build {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    // Compile the crate in release mode for wasm, returning the path of the module
+    pub fn compile(&self) -> Result<PathBuf, SideFuzzError> {
+        let output = Command::new("cargo")
+            .args(&[
+                "build",
+                "--release",
+                "--target",
+                "wasm32-unknown-unknown",
+                "--message-format=json-render-diagnostics",
+            ])
+            .current_dir(&self.dir)
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| SideFuzzError::BuildFailed(format!("could not run cargo: {}", e)))?;
+        if !output.status.success() {
+            return Err(SideFuzzError::BuildFailed(
+                "cargo build failed, see the errors above. If the wasm target is missing, install it with: rustup target add wasm32-unknown-unknown".to_string(),
+            ));
+        }
+
+        // Cargo describes each artifact it built, the last wasm file is the target's
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let wasm = stdout
+            .lines()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .filter(|message| message["reason"] == "compiler-artifact")
+            .flat_map(|message| match message["filenames"].as_array() {
+                Some(filenames) => filenames
+                    .iter()
+                    .filter_map(|filename| filename.as_str().map(PathBuf::from))
+                    .collect(),
+                None => Vec::new(),
+            })
+            .filter(|filename| filename.extension().map_or(false, |ext| ext == "wasm"))
+            .last();
+        wasm.ok_or_else(|| {
+            SideFuzzError::BuildFailed(
+                "no wasm file was built, build as a cdylib, with crate-type = [\"cdylib\"] in the [lib] section of Cargo.toml".to_string(),
+            )
+        })
+    }
+
+    pub fn run(&self) -> Result<(), SideFuzzError> {
+        let wasm = self.compile()?;
+        validate_file(&wasm)?;
+        println!("Built {}\nFuzz it with: sidefuzz fuzz {}", wasm.display(), wasm.display());
+        std::process::exit(0);
+    }
+}
+
+// Check that a module has everything sidefuzz needs to fuzz it
+pub fn validate_file(path: &Path) -> Result<(), SideFuzzError> {
+    let bytes = std::fs::read(path)?;
+    let inspection = inspect(&bytes)?;
+    if inspection.is_valid() {
+        Ok(())
+    } else {
+        Err(SideFuzzError::InvalidTarget(format!(
+            "{} is not a valid fuzz target:\n  {}\nRun `sidefuzz inspect {}` for details",
+            path.display(),
+            inspection.problems.join("\n  "),
+            path.display()
+        )))
+    }
+}
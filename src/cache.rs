@@ -0,0 +1,100 @@
+// A cache simulator, run over the memory accesses recorded by a memory trace
+//
+// Instruction counts say nothing about what an attacker sharing a cache with the target sees. They observe which
+// cache lines (or, with Prime+Probe, which cache sets) the target touches, and how often it misses.
+// This models a single set-associative cache with LRU replacement, starting out empty for each execution.
+
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub line_size: u64, // Bytes per cache line
+    pub sets: u64,      // Number of sets
+    pub ways: usize,    // Lines per set
+}
+
+impl Default for CacheConfig {
+    // A typical L1 data cache: 32 KiB, 8-way, 64 byte lines
+    fn default() -> Self {
+        CacheConfig {
+            line_size: 64,
+            sets: 64,
+            ways: 8,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn line(&self, address: u64) -> u64 {
+        address / self.line_size
+    }
+
+    pub fn set(&self, line: u64) -> u64 {
+        line % self.sets
+    }
+}
+
+// What an attacker could observe of one execution
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheObservation {
+    pub accesses: Vec<u64>,   // The cache line of every access, in order
+    pub lines: BTreeSet<u64>, // Every line touched
+    pub sets: BTreeSet<u64>,  // Every set touched
+    pub hits: usize,
+    pub misses: usize,
+}
+
+// Run `addresses` through a cold cache
+pub fn simulate(addresses: &[u64], config: &CacheConfig) -> CacheObservation {
+    // Each set holds its lines from least to most recently used
+    let mut cache: Vec<Vec<u64>> = vec![Vec::with_capacity(config.ways); config.sets as usize];
+    let mut observation = CacheObservation::default();
+
+    for address in addresses {
+        let line = config.line(*address);
+        let set_index = config.set(line);
+        let set = &mut cache[set_index as usize];
+
+        match set.iter().position(|cached| *cached == line) {
+            Some(position) => {
+                set.remove(position);
+                observation.hits += 1;
+            }
+            None => {
+                if set.len() == config.ways {
+                    set.remove(0);
+                }
+                observation.misses += 1;
+            }
+        }
+        set.push(line);
+
+        observation.accesses.push(line);
+        observation.lines.insert(line);
+        observation.sets.insert(set_index);
+    }
+
+    observation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulate_test() {
+        let config = CacheConfig {
+            line_size: 64,
+            sets: 2,
+            ways: 1,
+        };
+
+        // 0 and 10 share a line, 128 maps to the same set as 0 and evicts it
+        let observation = simulate(&[0, 10, 64, 128, 0], &config);
+        assert_eq!(observation.accesses, vec![0, 0, 1, 2, 0]);
+        assert_eq!(observation.hits, 1);
+        assert_eq!(observation.misses, 4);
+        assert_eq!(observation.sets.len(), 2);
+        assert_eq!(observation.lines.len(), 3);
+    }
+}
@@ -0,0 +1,202 @@
+// This file contains the "canary" subcommand, which checks that the black box helpers work with the installed rustc
+//
+// The helpers rely on volatile accesses and compiler fences, which optimizers are allowed to see through in ways
+// they don't today. The canary is a target with one entry point per helper, built with every optimization on:
+// -O3, fat LTO and a single codegen unit. Each runs a loop as many times as the first input byte, and passes its
+// result to the helper without using it otherwise. If the helper works the loop is kept, and inputs starting with
+// 255 execute many more instructions than inputs starting with 0. If the loop is optimized away they execute the same.
+
+use crate::build::Build;
+use crate::errors::SideFuzzError;
+use crate::new::NewTarget;
+use crate::report::Reporter;
+use crate::target::TargetOptions;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const LIB_RS: &str = r#"// Generated by `sidefuzz canary`, each entry point checks that one black box helper keeps an unused result
+
+// Work whose result is only ever passed to a helper, taking about `rounds` times as long as one round
+#[inline(never)]
+fn work(rounds: u8) -> u64 {
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    for _ in 0..rounds {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state = state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+    }
+    state
+}
+
+sidefuzz::sidefuzz_targets! {
+    fuzz_black_box: 1 => |input: &[u8]| {
+        sidefuzz::black_box(work(input[0]));
+    },
+    fuzz_black_box_slice: 1 => |input: &[u8]| {
+        let result = [work(input[0]); 4];
+        sidefuzz::black_box_slice(&result);
+    },
+    fuzz_black_box_slice_mut: 1 => |input: &[u8]| {
+        let mut result = [0; 4];
+        result[0] = work(input[0]);
+        sidefuzz::black_box_slice_mut(&mut result);
+    },
+}
+"#;
+
+// The entry points of the canary, and the helper each checks
+const ENTRIES: &[(&str, &str)] = &[
+    ("fuzz_black_box", "black_box"),
+    ("fuzz_black_box_slice", "black_box_slice"),
+    ("fuzz_black_box_slice_mut", "black_box_slice_mut"),
+];
+
+// At least one instruction for each of the 255 extra rounds, when the loop is kept
+const MIN_DIFFERENCE: u64 = 255;
+
+// Whether a helper kept the loop, from the instructions executed for 0 and 255 rounds
+fn kept(zero: u64, max: u64) -> bool {
+    max.saturating_sub(zero) >= MIN_DIFFERENCE
+}
+
+pub struct Canary {
+    dir: PathBuf,
+    sidefuzz: Option<PathBuf>, // A checkout of sidefuzz to build against, instead of the matching release
+    reporter: Reporter,
+}
+
+// The instructions the entry point of one helper executed for 0 and 255 rounds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HelperResult {
+    pub helper: &'static str,
+    pub kept: bool, // Whether the work was kept, instead of optimized away
+    pub zero: u64,
+    pub max: u64,
+}
+
+// Whether the helpers worked with the installed rustc
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CanaryResult {
+    pub rustc: String,
+    pub helpers: Vec<HelperResult>,
+}
+
+impl Canary {
+    pub fn new(sidefuzz: Option<&str>) -> Self {
+        Canary {
+            dir: std::env::temp_dir().join(format!("sidefuzz-canary-{}", std::process::id())),
+            sidefuzz: sidefuzz.map(PathBuf::from),
+            reporter: Reporter::default(),
+        }
+    }
+
+    // Set how progress is reported
+    pub fn set_reporter(&mut self, reporter: Reporter) {
+        self.reporter = reporter;
+    }
+
+    fn target(&self) -> Result<NewTarget, SideFuzzError> {
+        let mut target = NewTarget::new(&self.dir.join("canary").to_string_lossy())?
+            .with_lib(LIB_RS.to_string())
+            .with_release_setting("opt-level = 3")
+            .with_release_setting("lto = true")
+            .with_release_setting("codegen-units = 1");
+        if let Some(path) = &self.sidefuzz {
+            target = target.with_sidefuzz_path(&std::fs::canonicalize(path)?);
+        }
+        Ok(target)
+    }
+
+    // Build the canary and count the instructions each entry point executes
+    fn check(&self, dir: &Path) -> Result<Vec<HelperResult>, SideFuzzError> {
+        let wasm = Build::new(&dir.to_string_lossy()).compile()?;
+        let mut helpers = Vec::new();
+        for (entry, helper) in ENTRIES.iter() {
+            let options = TargetOptions {
+                entry: Some(entry.to_string()),
+                ..TargetOptions::default()
+            };
+            let mut target = crate::target::load(&wasm.to_string_lossy(), &options)?;
+            let zero = target.count_instructions(&[0])?;
+            let max = target.count_instructions(&[255])?;
+            helpers.push(HelperResult {
+                helper: *helper,
+                kept: kept(zero, max),
+                zero,
+                max,
+            });
+        }
+        Ok(helpers)
+    }
+
+    // Check every helper against a canary built with the installed rustc
+    pub fn run(&self) -> Result<CanaryResult, SideFuzzError> {
+        let rustc = Command::new("rustc")
+            .arg("--version")
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .unwrap_or_else(|_| "an unknown rustc".to_string());
+        let target = self.target()?;
+        target.create()?;
+        self.reporter
+            .progress(format!("Checking the black box helpers with {}", rustc));
+        let helpers = self.check(&self.dir.join("canary"));
+        // The canary is only needed for the check
+        let _ = std::fs::remove_dir_all(&self.dir);
+        Ok(CanaryResult {
+            rustc,
+            helpers: helpers?,
+        })
+    }
+}
+
+impl CanaryResult {
+    // 0 if every helper works, and 1 otherwise
+    pub fn exit_code(&self) -> i32 {
+        if self.helpers.iter().all(|helper| helper.kept) {
+            0
+        } else {
+            1
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let mut lines = Vec::new();
+        for helper in self.helpers.iter() {
+            if helper.kept {
+                lines.push(format!(
+                    "  {}: ok ({} and {} instructions)",
+                    helper.helper, helper.zero, helper.max
+                ));
+            } else {
+                lines.push(format!(
+                    "  {}: FAILED, the work was optimized away ({} and {} instructions)",
+                    helper.helper, helper.zero, helper.max
+                ));
+            }
+        }
+        if self.exit_code() == 0 {
+            lines.push("Every helper works".to_string());
+        } else {
+            lines.push("Some helpers don't keep their values with this rustc, results of fuzzing targets using them can't be trusted".to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canary_test() {
+        assert!(kept(100, 100 + MIN_DIFFERENCE));
+        assert!(!kept(100, 101));
+        assert!(!kept(200, 100));
+        for (entry, helper) in ENTRIES.iter() {
+            assert!(LIB_RS.contains(&format!("{}: 1 =>", entry)));
+            assert!(LIB_RS.contains(&format!("sidefuzz::{}(", helper)));
+        }
+    }
+}
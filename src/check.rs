@@ -1,35 +1,79 @@
 // This file contains the "check" subcommand
 
-use crate::dudect::{DudeCT, DudeResult};
+use crate::dudect::{Analysis, DudeCT, DudeResult};
 use crate::errors::SideFuzzError;
+use crate::report::{Report, Reporter, Verdict};
+use crate::target::{Backend, Target, TargetOptions};
+use crate::template::Template;
+use crate::tracing::{format_function_deltas, function_deltas, FunctionDelta, TraceKind};
+use crate::tui::{remaining_samples, Stats};
 use crate::util::*;
-use crate::wasm::WasmModule;
+use crate::wasm::{WasmModule, WasmOptions};
+use std::time::{Duration, Instant};
+
+// Below this t-value (50% confidence) the target is considered constant time
+const GIVE_UP_T_VALUE: f64 = 0.674;
 
 pub struct Check {
-    module: WasmModule,
+    module: Box<dyn Target>,
+    wasm: Option<(Vec<u8>, WasmOptions)>, // Module bytes and options of wasm targets, used for localizing leaks
     input: InputPair,
+    classes: Option<(Template, Template)>, // Input classes, `input` then holds an example of each
+    reporter: Reporter,
+    max_t_value: f64,
+    max_samples: Option<usize>,
+    max_time: Option<Duration>,
+    analysis: Analysis,
+    archive: Option<(String, String)>, // Where to archive leaks, and the path of the target
+    expected_sha256: Option<(String, String)>, // The SHA-256 the target must have, and its path
 }
 
 impl Check {
     // Create a new check command with the given wasm module and two inputs
     pub fn new(module: WasmModule, first: Vec<u8>, second: Vec<u8>) -> Result<Self, SideFuzzError> {
-        if first.len() != second.len() {
-            return Err(SideFuzzError::InputsDifferentSizes);
-        }
+        let wasm = Some((module.bytes(), module.options().clone()));
+        Self::with_target(Box::new(module), wasm, first, second)
+    }
+
+    fn with_target(
+        module: Box<dyn Target>,
+        wasm: Option<(Vec<u8>, WasmOptions)>,
+        first: Vec<u8>,
+        second: Vec<u8>,
+    ) -> Result<Self, SideFuzzError> {
+        if module.variable_len() {
+            // Inputs of variable-length targets can differ in length, which may be the leak
+            if first.len().max(second.len()) > module.fuzz_len() {
+                return Err(SideFuzzError::InputTooLong(module.fuzz_len()));
+            }
+        } else {
+            if first.len() != second.len() {
+                return Err(SideFuzzError::InputsDifferentSizes);
+            }
 
-        if first.len() != module.fuzz_len() {
-            return Err(SideFuzzError::InputsWrongSize(module.fuzz_len()));
+            if first.len() != module.fuzz_len() {
+                return Err(SideFuzzError::InputsWrongSize(module.fuzz_len()));
+            }
         }
 
         let input_is_str = &module.input_is_str();
 
         Ok(Check {
             module: module,
+            wasm,
             input: InputPair {
                 first,
                 second,
                 is_str: *input_is_str,
             },
+            classes: None,
+            reporter: Reporter::default(),
+            max_t_value: 4.5,
+            max_samples: None,
+            max_time: None,
+            analysis: Analysis::default(),
+            archive: None,
+            expected_sha256: None,
         })
     }
 
@@ -42,42 +86,191 @@ impl Check {
         Self::new(module, first, second)
     }
 
+    pub fn from_file_with_options(
+        filename: &str,
+        options: &TargetOptions,
+        first: Vec<u8>,
+        second: Vec<u8>,
+    ) -> Result<Self, SideFuzzError> {
+        let module = crate::target::load(filename, options)?;
+        let wasm = match options.backend {
+            Backend::Wasm => Some((std::fs::read(filename)?, options.wasm_options())),
+            _ => None,
+        };
+        Self::with_target(module, wasm, first, second)
+    }
+
+    // Compare two inputs given as hex. Inputs with "??" for some of their bytes, or naming a ".json" input template,
+    // are classes of inputs instead, and a fresh input from each class is measured for every sample.
+    pub fn from_args_with_options(
+        filename: &str,
+        options: &TargetOptions,
+        first: &str,
+        second: &str,
+    ) -> Result<Self, SideFuzzError> {
+        let first = Template::from_arg(first)?;
+        let second = Template::from_arg(second)?;
+        // Inputs of fixed templates are always the same, so they are an example of their class either way
+        let mut rng = rand::thread_rng();
+        let mut check =
+            Self::from_file_with_options(filename, options, first.random(&mut rng), second.random(&mut rng))?;
+        if !first.is_fixed() || !second.is_fixed() {
+            check.classes = Some((first, second));
+        }
+        Ok(check)
+    }
+
+    // Set how progress and the final result are reported.
+    pub fn set_reporter(&mut self, reporter: Reporter) {
+        self.reporter = reporter;
+    }
+
+    // Report a leak once the t-value goes above `max_t_value`. The default is 4.5, very high confidence.
+    pub fn set_max_t_value(&mut self, max_t_value: f64) {
+        self.max_t_value = max_t_value;
+    }
+
+    // Stop after `max_samples` samples.
+    // If neither a leak nor constant time has been established by then, the result is inconclusive.
+    pub fn set_max_samples(&mut self, max_samples: usize) {
+        self.max_samples = Some(max_samples);
+    }
+
+    // Stop after sampling for `max_time`, the same way as after `max_samples` samples.
+    pub fn set_max_time(&mut self, max_time: Duration) {
+        self.max_time = Some(max_time);
+    }
+
+    // Set which statistical tests are run on the samples.
+    pub fn set_analysis(&mut self, analysis: Analysis) {
+        self.analysis = analysis;
+    }
+
+    // Archive a leak between the two inputs under `dir` if one is found, hashing the target at `target` to name it.
+    // Leaks between input classes aren't archived, they have no two inputs to save.
+    pub fn set_archive(&mut self, dir: String, target: String) {
+        self.archive = Some((dir, target));
+    }
+
+    // Fail before sampling unless the target at `target` has the SHA-256 `hash`, such as the `module_sha256` of an
+    // archived finding, so a finding isn't reproduced against a rebuilt target.
+    pub fn set_expected_sha256(&mut self, hash: String, target: String) {
+        self.expected_sha256 = Some((hash.to_lowercase(), target));
+    }
+
+    // Exits with 0 if the target is constant time, 1 if a leak was found and 2 if the result is inconclusive.
     pub fn run(&mut self) -> Result<(), SideFuzzError> {
+        if let Some((expected, target)) = &self.expected_sha256 {
+            let module = std::fs::read(target).map_err(SideFuzzError::CouldNotReadFile)?;
+            let actual = crate::archive::sha256(&module);
+            if &actual != expected {
+                return Err(SideFuzzError::ModuleHashMismatch(expected.clone(), actual));
+            }
+        }
+
+        // Counts that depend on what ran before them make every sample suspect
+        let order = crate::order::check(self.module.as_ref(), &self.input.first, &self.input.second)?;
+        if let Some(warning) = order {
+            self.reporter.progress(format!("Warning: {}", warning));
+            self.reporter.add_warnings(vec![warning]);
+        }
+
         // Get the instruction counts
         let input_is_str = self.module.input_is_str();
         let scored_input = ScoredInputPair::generate(
-            &mut self.module,
+            self.module.as_mut(),
             self.input.first.to_vec(),
             self.input.second.to_vec(),
             input_is_str,
         );
 
+        let same_output = self.same_output()?;
+
         // Construct DudeCT
-        // Return success on t = 4.5 (very high confidence)
+        // Return success on t = max_t_value
         // Give up on t < 0.674 (50% confidence) when over 1 million samples.
         let mut dudect = DudeCT::new(
-            4.5,     // Success t-value
-            0.674,   // Give up t-value
-            100_000, // Give up min samples
+            self.max_t_value, // Success t-value
+            GIVE_UP_T_VALUE,  // Give up t-value
+            100_000,          // Give up min samples
             &self.input.first,
             &self.input.second,
-            self.module.clone(),
+            self.module.try_clone()?,
         )?;
+        dudect.set_recording(self.reporter.wants_evidence());
+        dudect.set_analysis(self.analysis);
+        if let Some((first, second)) = &self.classes {
+            dudect.set_classes(first.clone(), second.clone())?;
+        }
+        if dudect.public_len() > 0 {
+            self.reporter.progress(format!(
+                "Randomizing the {} bytes of public input for every sample",
+                dudect.public_len()
+            ));
+        }
 
+        let deadline = self.max_time.map(|max_time| Instant::now() + max_time);
         loop {
-            let (t, result) = dudect.sample(10_000)?;
+            let (t, mut result) = dudect.sample(10_000)?;
             let p = p_value_from_t_value(t);
 
-            println!(
+            self.reporter.stats(&Stats {
+                executions: dudect.len() as u64,
+                t_value: Some(t),
+                remaining_executions: remaining_samples(t, dudect.len(), self.max_t_value),
+                ..Stats::default()
+            });
+
+            self.reporter.progress(format!(
                 "samples: {}, t-value: {}, confidence: {}%",
                 dudect.len(),
                 t,
                 (1.0 - p) * 100.0
-            );
+            ));
+            if let Some(rejected) = dudect.rejected() {
+                self.reporter.progress(rejected.to_string());
+            }
+
+            let (first_mean, second_mean) = dudect.means();
+            let mut report = Report {
+                command: "check",
+                verdict: Verdict::Leak,
+                input_1: self.describe_input(0),
+                input_2: self.describe_input(1),
+                instructions_1: first_mean,
+                instructions_2: second_mean,
+                difference: scored_input.score,
+                t_value: t,
+                confidence: (1.0 - p) * 100.0,
+                samples: dudect.len(),
+                effect: dudect.effect(),
+                rejected: dudect.rejected(),
+                same_output,
+                ..Default::default()
+            };
+
+            // Out of samples or time, the target is only constant time if the t-value is already small
+            let out_of_samples = self
+                .max_samples
+                .map_or(false, |max_samples| dudect.len() >= max_samples)
+                || deadline.map_or(false, |deadline| Instant::now() >= deadline);
+            if out_of_samples && result == DudeResult::Progress && t <= GIVE_UP_T_VALUE {
+                result = DudeResult::Err;
+            }
 
             match result {
                 DudeResult::Ok => {
-                    println!(
+                    report.functions = self.localize();
+                    let mut text = match &self.classes {
+                        Some(_) => format!(
+                            "Found timing difference between these two input classes with {}% confidence:\nclass 1: {} ({} instructions on average)\nclass 2: {} ({} instructions on average)",
+                            (1.0 - p) * 100.0,
+                            report.input_1,
+                            first_mean,
+                            report.input_2,
+                            second_mean,
+                        ),
+                        None => format!(
                 "Found timing difference of {} instructions between these two inputs with {}% confidence:\ninput 1: {} ({} instructions) \ninput 2: {} ({} instructions)",
                 scored_input.score,
                 (1.0 - p) * 100.0,
@@ -85,19 +278,115 @@ impl Check {
                 scored_input.highest,
                 hex::encode(&scored_input.pair.second),
                 scored_input.lowest,
-              );
-                    std::process::exit(0);
+              ),
+                    };
+                    if let Some(effect) = &report.effect {
+                        text += &format!("\n{}", effect);
+                    }
+                    if let Some(noise) = self.analysis.noise {
+                        text += &format!("\nCounts include simulated {}.", noise);
+                    }
+                    if let Some(rejected) = &report.rejected {
+                        text += &format!("\n{}.", rejected);
+                    }
+                    match report.same_output {
+                        Some(true) => text += "\nBoth inputs produce the same output.",
+                        Some(false) => text += "\nThe inputs produce different outputs, so the difference may come from them doing different work, such as one being rejected early.",
+                        None => {}
+                    }
+                    if !report.functions.is_empty() {
+                        text += "\n\nInstructions executed per function:\n";
+                        text += &format_function_deltas(&report.functions);
+                    }
+                    report.evidence = dudect.evidence();
+                    if let (None, Some((dir, target))) = (&self.classes, &self.archive) {
+                        let module = std::fs::read(target).map_err(SideFuzzError::CouldNotReadFile)?;
+                        let path = crate::archive::save(
+                            dir,
+                            &module,
+                            &report,
+                            &self.input.first,
+                            &self.input.second,
+                        )?;
+                        text += &format!("\nArchived the leak in {}", path.display());
+                    }
+                    self.reporter.finish(&report, &text)?;
+                    std::process::exit(1);
                 }
                 DudeResult::Err => {
-                    println!(
-                "Candidate input pair rejected: t-statistic small after many samples. Target is probably constant time."
-              );
+                    report.verdict = Verdict::NoLeak;
+                    let text = "Candidate input pair rejected: t-statistic small after many samples. Target is probably constant time.";
+                    report.evidence = dudect.evidence();
+                    self.reporter.finish(&report, text)?;
                     std::process::exit(0);
                 }
+                DudeResult::Progress if out_of_samples => {
+                    report.verdict = Verdict::Inconclusive;
+                    let mut text = format!(
+                        "Inconclusive: t-value of {} after {} samples is too large for the target to be constant time, but too small for a leak.",
+                        t,
+                        dudect.len()
+                    );
+                    if let Some(noise) = self.analysis.noise {
+                        text += &format!(
+                            "\nWith simulated {}, telling the inputs apart takes more than {} measurements.",
+                            noise,
+                            dudect.len()
+                        );
+                    }
+                    report.evidence = dudect.evidence();
+                    self.reporter.finish(&report, &text)?;
+                    std::process::exit(2);
+                }
                 DudeResult::Progress => {
                     continue;
                 }
             }
         }
     }
+
+    // Whether both inputs produce the same output, for targets that set one. Input classes have no single output.
+    fn same_output(&mut self) -> Result<Option<bool>, SideFuzzError> {
+        if self.classes.is_some() {
+            return Ok(None);
+        }
+        let first = self.module.run_with_output(&self.input.first).map(|(_, output)| output);
+        let second = self.module.run_with_output(&self.input.second).map(|(_, output)| output);
+        match (first, second) {
+            (Ok(Some(first)), Ok(Some(second))) => Ok(Some(first == second)),
+            (Err(SideFuzzError::WasmModuleBadOutput), _) | (_, Err(SideFuzzError::WasmModuleBadOutput)) => {
+                Err(SideFuzzError::WasmModuleBadOutput)
+            }
+            // Crashes are left to the measurements to report
+            _ => Ok(None),
+        }
+    }
+
+    // An input as hex, or the pattern of its class when comparing input classes
+    fn describe_input(&self, index: usize) -> String {
+        match (&self.classes, index) {
+            (Some((first, _)), 0) => first.pattern(),
+            (Some((_, second)), _) => second.pattern(),
+            (None, 0) => hex::encode(&self.input.first),
+            (None, _) => hex::encode(&self.input.second),
+        }
+    }
+
+    // Find which functions the difference in instruction counts comes from.
+    // This is best effort, an empty result just means no breakdown is available.
+    fn localize(&self) -> Vec<FunctionDelta> {
+        let (bytes, mut options) = match &self.wasm {
+            Some(wasm) => wasm.clone(),
+            None => return Vec::new(),
+        };
+        options.trace = Some(TraceKind::Functions);
+        let mut traced = match WasmModule::with_options(bytes.clone(), options) {
+            Ok(traced) => traced,
+            Err(_) => return Vec::new(),
+        };
+        match (traced.trace(&self.input.first), traced.trace(&self.input.second)) {
+            (Ok(first), Ok(second)) => function_deltas(&first, &second, &bytes),
+            _ => Vec::new(),
+        }
+    }
 }
@@ -0,0 +1,168 @@
+// Checkpoints of fuzzing runs, so that long runs can be resumed after being interrupted
+
+use crate::errors::SideFuzzError;
+use crate::util::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+    pub fuzz_len: usize,
+    pub seed: u64,     // The seed the run was started with
+    pub rng_seed: u64, // Where the optimizer's random choices continue from
+    pub generations: u64,
+    pub population: Vec<Individual>,
+    pub best: Option<Candidate>,
+}
+
+// A member of the population, inputs are hex encoded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Individual {
+    pub first: String,
+    pub second: String,
+}
+
+// The best input pair found so far
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Candidate {
+    pub first: String,
+    pub second: String,
+    pub score: f64,
+}
+
+impl Checkpoint {
+    pub fn load(path: &str) -> Result<Self, SideFuzzError> {
+        let json = std::fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(|e| SideFuzzError::BadCheckpoint(e.to_string()))
+    }
+
+    // Write the checkpoint, replacing any previous one.
+    // The file is written next to the old one and then renamed, so an interruption never leaves a partial checkpoint.
+    pub fn save(&self, path: &str) -> Result<(), SideFuzzError> {
+        // Ok to expect, a Checkpoint always serializes.
+        let json = serde_json::to_string(self).expect("Could not serialize checkpoint");
+        let temp = format!("{}.tmp", path);
+        std::fs::write(&temp, json).map_err(SideFuzzError::CouldNotWriteFile)?;
+        std::fs::rename(&temp, path).map_err(SideFuzzError::CouldNotWriteFile)
+    }
+
+    // Decode the population, checking it is usable by a target with inputs of `fuzz_len` bytes,
+    // or of up to `fuzz_len` bytes for variable-length targets, and by an optimizer with a population of `size`
+    pub fn population(
+        &self,
+        fuzz_len: usize,
+        is_str: bool,
+        variable_len: bool,
+        size: usize,
+    ) -> Result<Vec<InputPair>, SideFuzzError> {
+        if self.fuzz_len != fuzz_len {
+            return Err(SideFuzzError::BadCheckpoint(format!(
+                "it is for a target with {} byte inputs, this target has {} byte inputs",
+                self.fuzz_len, fuzz_len
+            )));
+        }
+        if self.population.is_empty() {
+            return Err(SideFuzzError::BadCheckpoint(
+                "its population is empty".to_string(),
+            ));
+        }
+        if self.population.len() != size {
+            return Err(SideFuzzError::BadCheckpoint(format!(
+                "its population has {} input pairs, the optimizer breeds {}",
+                self.population.len(),
+                size
+            )));
+        }
+
+        self.population
+            .iter()
+            .map(|individual| {
+                let pair = InputPair {
+                    first: decode(&individual.first)?,
+                    second: decode(&individual.second)?,
+                    is_str,
+                };
+                let fits = |input: &[u8]| {
+                    input.len() == fuzz_len || (variable_len && input.len() < fuzz_len)
+                };
+                if !fits(&pair.first) || !fits(&pair.second) {
+                    return Err(SideFuzzError::BadCheckpoint(
+                        "population contains inputs of the wrong length".to_string(),
+                    ));
+                }
+                Ok(pair)
+            })
+            .collect()
+    }
+
+    // The best input pair, if there was one
+    pub fn best(&self, is_str: bool) -> Result<ScoredInputPair, SideFuzzError> {
+        match &self.best {
+            Some(best) => Ok(ScoredInputPair {
+                score: best.score,
+                pair: InputPair {
+                    first: decode(&best.first)?,
+                    second: decode(&best.second)?,
+                    is_str,
+                },
+                ..ScoredInputPair::default()
+            }),
+            None => Ok(ScoredInputPair::default()),
+        }
+    }
+}
+
+impl Individual {
+    pub fn new(pair: &InputPair) -> Self {
+        Individual {
+            first: hex::encode(&pair.first),
+            second: hex::encode(&pair.second),
+        }
+    }
+}
+
+fn decode(input: &str) -> Result<Vec<u8>, SideFuzzError> {
+    hex::decode(input).map_err(|e| SideFuzzError::BadCheckpoint(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checkpoint::*;
+
+    #[test]
+    fn checkpoint_test() {
+        let pair = InputPair {
+            first: vec![1, 2],
+            second: vec![3, 4],
+            is_str: false,
+        };
+        let checkpoint = Checkpoint {
+            fuzz_len: 2,
+            seed: 1,
+            rng_seed: 2,
+            generations: 500,
+            population: vec![Individual::new(&pair)],
+            best: None,
+        };
+
+        let path = std::env::temp_dir().join("sidefuzz_checkpoint_test.json");
+        let path = path.to_str().unwrap();
+        checkpoint.save(path).unwrap();
+        let loaded = Checkpoint::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.generations, 500);
+        let population = loaded.population(2, false, false, 1).unwrap();
+        assert_eq!(population[0].first, pair.first);
+        assert_eq!(population[0].second, pair.second);
+        assert!(loaded.population(3, false, false, 1).is_err());
+
+        // Populations that are empty or of another size can't be bred from
+        assert!(loaded.population(2, false, false, 2).is_err());
+        let empty = Checkpoint {
+            population: Vec::new(),
+            ..loaded.clone()
+        };
+        assert!(empty.population(2, false, false, 0).is_err());
+        assert_eq!(loaded.best(false).unwrap().score, 0.0);
+    }
+}
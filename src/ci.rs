@@ -0,0 +1,94 @@
+// Status lines for CI logs
+//
+// Progress messages and dashboards are written for someone watching, and fill CI logs with thousands of lines. In CI
+// mode the progress messages are left out, and the statistics are summed up in a single line at most once every
+// interval, which is enough to see that a long run is still alive and how far it has got.
+
+use crate::tui::{execution_rate, format_duration, time_to_decision, Stats};
+use std::time::{Duration, Instant};
+
+// How often a status line is printed when no interval is given, in seconds
+pub const DEFAULT_INTERVAL: u64 = 60;
+
+#[derive(Debug)]
+pub(crate) struct CiStatus {
+    started: Instant,
+    interval: Duration,
+    last: Option<Instant>, // When the last status line was printed
+}
+
+impl CiStatus {
+    pub fn new(interval: Duration) -> Self {
+        CiStatus {
+            started: Instant::now(),
+            interval,
+            last: None,
+        }
+    }
+
+    // The status line for `stats`, unless the last one was printed less than an interval ago
+    pub fn update(&mut self, stats: &Stats) -> Option<String> {
+        self.update_at(stats, Instant::now())
+    }
+
+    fn update_at(&mut self, stats: &Stats, now: Instant) -> Option<String> {
+        if let Some(last) = self.last {
+            if now.duration_since(last) < self.interval {
+                return None;
+            }
+        }
+        self.last = Some(now);
+        Some(self.line(stats, now.duration_since(self.started).as_secs_f64()))
+    }
+
+    fn line(&self, stats: &Stats, elapsed: f64) -> String {
+        let rate = execution_rate(stats, elapsed);
+        let mut parts = vec![format!("{} executions ({:.0}/s)", stats.executions, rate)];
+        if let Some(generations) = stats.generations {
+            parts.push(format!("{} generations", generations));
+        }
+        if let Some(best_score) = stats.best_score {
+            parts.push(format!("best difference {}", best_score));
+        }
+        if let Some(t) = stats.t_value {
+            parts.push(format!("t-value {:.3}", t));
+        }
+        if let Some(crashes) = stats.crashes.filter(|crashes| *crashes > 0) {
+            parts.push(format!("{} crashes", crashes));
+        }
+        parts.push(format!(
+            "time to decision {}",
+            time_to_decision(stats, rate)
+        ));
+        format!("[{}] {}", format_duration(elapsed), parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ci_status_test() {
+        let mut status = CiStatus::new(Duration::from_secs(30));
+        let stats = Stats {
+            executions: 1000,
+            generations: Some(500),
+            t_value: Some(2.5),
+            ..Stats::default()
+        };
+        let start = status.started;
+        assert!(status.update_at(&stats, start).is_some());
+        assert!(status
+            .update_at(&stats, start + Duration::from_secs(29))
+            .is_none());
+
+        let line = status
+            .update_at(&stats, start + Duration::from_secs(40))
+            .unwrap();
+        assert_eq!(
+            line,
+            "[40s] 1000 executions (25/s), 500 generations, t-value 2.500, time to decision unknown"
+        );
+    }
+}
@@ -0,0 +1,267 @@
+// This file contains the "compare" subcommand, which runs the same inputs against two builds of a target
+
+use crate::errors::SideFuzzError;
+use crate::target::{Target, TargetOptions};
+use crate::util::random_input;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Serialize;
+
+pub struct Compare {
+    baseline: Box<dyn Target>,
+    candidate: Box<dyn Target>,
+    inputs: Vec<Vec<u8>>,
+    max_regression: Option<f64>,
+}
+
+// Instruction counts of one input on both builds, None if it crashed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comparison {
+    pub input: String, // In hexadecimal
+    pub baseline: Option<u64>,
+    pub candidate: Option<u64>,
+}
+
+// The comparisons of every input, and how much each build varies between them
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CompareResult {
+    pub comparisons: Vec<Comparison>,
+    pub baseline_spread: Option<u64>, // The difference between the highest and lowest count, None if every input crashed
+    pub candidate_spread: Option<u64>,
+    pub mean_change: Option<f64>, // As a percentage of the baseline
+    pub max_regression: Option<f64>,
+}
+
+impl Comparison {
+    // How much the candidate changed the instruction count, as a percentage of the baseline
+    pub fn change(&self) -> Option<f64> {
+        match (self.baseline, self.candidate) {
+            (Some(baseline), Some(candidate)) if baseline > 0 => {
+                Some((candidate as f64 - baseline as f64) / baseline as f64 * 100.0)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Compare {
+    fn with_targets(
+        baseline: Box<dyn Target>,
+        candidate: Box<dyn Target>,
+        inputs: Vec<Vec<u8>>,
+    ) -> Result<Self, SideFuzzError> {
+        if baseline.fuzz_len() != candidate.fuzz_len() {
+            return Err(SideFuzzError::TargetsDiffer(format!(
+                "the baseline takes {} bytes of input, the candidate takes {}",
+                baseline.fuzz_len(),
+                candidate.fuzz_len()
+            )));
+        }
+        if baseline.variable_len() != candidate.variable_len() {
+            return Err(SideFuzzError::TargetsDiffer(
+                "only one of them takes variable-length input".to_string(),
+            ));
+        }
+        for input in inputs.iter() {
+            if baseline.variable_len() {
+                if input.len() > baseline.fuzz_len() {
+                    return Err(SideFuzzError::InputTooLong(baseline.fuzz_len()));
+                }
+            } else if input.len() != baseline.fuzz_len() {
+                return Err(SideFuzzError::InputsWrongSize(baseline.fuzz_len()));
+            }
+        }
+
+        Ok(Compare {
+            baseline,
+            candidate,
+            inputs,
+            max_regression: None,
+        })
+    }
+
+    pub fn from_files_with_options(
+        baseline: &str,
+        candidate: &str,
+        options: &TargetOptions,
+        inputs: Vec<Vec<u8>>,
+    ) -> Result<Self, SideFuzzError> {
+        let baseline = crate::target::load(baseline, options)?;
+        let candidate = crate::target::load(candidate, options)?;
+        Self::with_targets(baseline, candidate, inputs)
+    }
+
+    // Also run `count` random inputs, generated from `seed`
+    pub fn add_random_inputs(&mut self, count: usize, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let len = self.baseline.fuzz_len();
+        let is_str = self.baseline.input_is_str();
+        for _ in 0..count {
+            self.inputs.push(random_input(&mut rng, len, is_str));
+        }
+    }
+
+    // Fail if the candidate executes more than `percent` more instructions than the baseline on average
+    pub fn set_max_regression(&mut self, percent: f64) {
+        self.max_regression = Some(percent);
+    }
+
+    // Count the instructions of every input on both builds
+    pub fn compare(&mut self) -> Vec<Comparison> {
+        let mut comparisons = Vec::with_capacity(self.inputs.len());
+        for input in self.inputs.iter() {
+            comparisons.push(Comparison {
+                input: hex::encode(input),
+                baseline: self.baseline.count_instructions(input).ok(),
+                candidate: self.candidate.count_instructions(input).ok(),
+            });
+        }
+        comparisons
+    }
+
+    // Count the instructions of every input on both builds, and compare how much they vary
+    pub fn run(&mut self) -> Result<CompareResult, SideFuzzError> {
+        if self.inputs.is_empty() {
+            return Err(SideFuzzError::NoInputs);
+        }
+        let comparisons = self.compare();
+        let baseline: Vec<Option<u64>> = comparisons.iter().map(|c| c.baseline).collect();
+        let candidate: Vec<Option<u64>> = comparisons.iter().map(|c| c.candidate).collect();
+        Ok(CompareResult {
+            baseline_spread: spread(&baseline),
+            candidate_spread: spread(&candidate),
+            mean_change: mean_change(&comparisons),
+            max_regression: self.max_regression,
+            comparisons,
+        })
+    }
+}
+
+impl CompareResult {
+    // Whether the candidate regressed by more than the maximum regression
+    pub fn regressed(&self) -> bool {
+        match (self.mean_change, self.max_regression) {
+            (Some(mean), Some(max)) => mean > max,
+            _ => false,
+        }
+    }
+
+    // 1 if the candidate regressed by more than the maximum regression, and 0 otherwise
+    pub fn exit_code(&self) -> i32 {
+        if self.regressed() {
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let count = |count: Option<u64>| count.map_or("crashed".to_string(), |c| c.to_string());
+
+        let mut lines = vec![format!(
+            "{:<24} {:>12} {:>12} {:>20}",
+            "input", "baseline", "candidate", "change"
+        )];
+        for comparison in self.comparisons.iter() {
+            let mut input = comparison.input.clone();
+            if input.len() > 24 {
+                input.truncate(21);
+                input += "...";
+            }
+            let change = match (
+                comparison.baseline,
+                comparison.candidate,
+                comparison.change(),
+            ) {
+                (Some(baseline), Some(candidate), Some(change)) => {
+                    format!("{:+} ({:+.2}%)", candidate as i64 - baseline as i64, change)
+                }
+                _ => String::new(),
+            };
+            lines.push(format!(
+                "{:<24} {:>12} {:>12} {:>20}",
+                input,
+                count(comparison.baseline),
+                count(comparison.candidate),
+                change
+            ));
+        }
+
+        // A constant-time build executes the same number of instructions for every input
+        lines.push(String::new());
+        for (name, spread) in [
+            ("baseline", self.baseline_spread),
+            ("candidate", self.candidate_spread),
+        ]
+        .iter()
+        {
+            lines.push(match spread {
+                Some(0) => format!("{}: the same number of instructions for every input", name),
+                Some(spread) => format!(
+                    "{}: instruction counts vary by {} between inputs",
+                    name, spread
+                ),
+                None => format!("{}: crashed on every input", name),
+            });
+        }
+
+        if let Some(mean) = self.mean_change {
+            lines.push(format!("mean change: {:+.2}%", mean));
+        }
+        if self.regressed() {
+            // Ok to unwrap, only a mean change above the maximum regression is a regression.
+            lines.push(format!(
+                "The candidate regressed by {:.2}%, more than the maximum of {}%",
+                self.mean_change.unwrap(),
+                self.max_regression.unwrap()
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+// The difference between the highest and lowest count, ignoring crashes
+fn spread(counts: &[Option<u64>]) -> Option<u64> {
+    let counts = counts.iter().filter_map(|count| *count);
+    let max = counts.clone().max()?;
+    let min = counts.min()?;
+    Some(max - min)
+}
+
+// The average change over the inputs that ran on both builds
+fn mean_change(comparisons: &[Comparison]) -> Option<f64> {
+    let changes: Vec<f64> = comparisons.iter().filter_map(Comparison::change).collect();
+    if changes.is_empty() {
+        None
+    } else {
+        Some(changes.iter().sum::<f64>() / changes.len() as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_test() {
+        let comparison = |baseline, candidate| Comparison {
+            input: String::new(),
+            baseline,
+            candidate,
+        };
+        let comparisons = vec![
+            comparison(Some(100), Some(110)),
+            comparison(Some(200), Some(110)),
+            comparison(None, Some(110)),
+        ];
+
+        assert_eq!(comparisons[0].change(), Some(10.0));
+        assert_eq!(comparisons[2].change(), None);
+        assert_eq!(mean_change(&comparisons), Some(-17.5));
+
+        let baseline: Vec<_> = comparisons.iter().map(|c| c.baseline).collect();
+        let candidate: Vec<_> = comparisons.iter().map(|c| c.candidate).collect();
+        assert_eq!(spread(&baseline), Some(100));
+        assert_eq!(spread(&candidate), Some(0));
+        assert_eq!(spread(&[None]), None);
+    }
+}
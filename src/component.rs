@@ -0,0 +1,473 @@
+// Targets built as wasm components, such as with cargo-component
+//
+// A component wraps one or more core modules, and only the one that defines the target's memory holds its code.
+// That module is unwrapped and fuzzed like any other, the rest (shims and the WASI adapter) are left out, as
+// their imports are stubbed anyway. Modules built with the sidefuzz crate keep its exports, and are used as they are.
+//
+// Other components take their input the way the component model passes it, with a `fuzz: func(input: list<u8>)`
+// or `fuzz: func(input: string)` export, and say how long it is with `input-len: func() -> u32`. In the core module
+// the input is lowered to a pointer and a length, in memory allocated with `cabi_realloc`. The module is given
+// the exports sidefuzz expects: an `input_pointer` allocating a buffer for the input once with `cabi_realloc`,
+// `input_len`, and a `fuzz` taking no arguments that passes the input to the original. The canonical ABI hands
+// arguments over to the callee, which frees them, so the buffer sidefuzz writes to is never passed itself: every
+// call allocates a fresh copy of it for the original to own. String inputs are UTF-8, as the canonical ABI's
+// default, and are marked as such by `input-is-str: func() -> bool`.
+
+use crate::binary::*;
+use crate::errors::SideFuzzError;
+
+// The version and layer following the magic number of components, where core modules have version 1
+const COMPONENT_VERSION: [u8; 4] = [0x0d, 0x00, 0x01, 0x00];
+
+// The component section holding a core module
+const SECTION_CORE_MODULE: u8 = 1;
+
+const KIND_MEMORY: u8 = 2;
+
+// Value types of lowered functions
+const I32: u8 = 0x7f;
+
+// Exports of components, and the ones of the sidefuzz ABI they become
+const FUZZ: &str = "fuzz";
+const REALLOC: &str = "cabi_realloc";
+const INPUT_LEN: &str = "input-len";
+const INPUT_IS_STR: &str = "input-is-str";
+
+// Where sections go in a core module, custom sections aside
+fn section_order(id: u8) -> u8 {
+    match id {
+        13 => 6, // Tags, from the exception handling proposal
+        SECTION_GLOBAL => 7,
+        SECTION_EXPORT..=SECTION_ELEMENT => id + 1,
+        12 => 11, // Data count
+        SECTION_CODE => 12,
+        11 => 13, // Data
+        _ => id,
+    }
+}
+
+pub(crate) fn is_component(bytes: &[u8]) -> bool {
+    bytes.len() >= 8 && &bytes[0..4] == b"\0asm" && bytes[4..8] == COMPONENT_VERSION
+}
+
+// The core module to fuzz of `bytes`, which are returned as they are unless they are a component
+pub(crate) fn core_module(bytes: Vec<u8>) -> Result<Vec<u8>, SideFuzzError> {
+    if !is_component(&bytes) {
+        return Ok(bytes);
+    }
+    let unsupported = |reason: &str| SideFuzzError::WasmComponentUnsupported(reason.to_string());
+    let sections = sections(&bytes).ok_or_else(|| unsupported("it could not be parsed"))?;
+    let module = sections
+        .iter()
+        .filter(|section| section.id == SECTION_CORE_MODULE)
+        .map(|section| &bytes[section.start..section.end])
+        .find(|module| {
+            exports(module).map_or(false, |exports| {
+                exports.iter().any(|(_, kind, _)| *kind == KIND_MEMORY)
+            })
+        })
+        .ok_or_else(|| unsupported("none of its core modules exports a memory"))?;
+
+    // Ok to unwrap, the module's exports were just parsed
+    let exports = exports(module).unwrap();
+    if exports.iter().any(|(name, _, _)| name == "input_pointer") {
+        return Ok(module.to_vec());
+    }
+    lower_input(module, &exports).map_err(|reason| unsupported(&reason))
+}
+
+// The name, kind and index of every export of a core module
+fn exports(module: &[u8]) -> Option<Vec<(String, u8, u32)>> {
+    let mut exports = Vec::new();
+    for section in sections(module)?.iter().filter(|s| s.id == SECTION_EXPORT) {
+        let mut pos = section.start;
+        let count = read_u32(module, &mut pos)?;
+        for _ in 0..count {
+            let name = read_name(module, &mut pos)?;
+            let kind = *module.get(pos)?;
+            pos += 1;
+            exports.push((name, kind, read_u32(module, &mut pos)?));
+        }
+    }
+    Some(exports)
+}
+
+// The type index of every function, imported functions first, and the number of imported functions
+fn function_types(module: &[u8]) -> Option<(Vec<u32>, usize)> {
+    let mut types = Vec::new();
+    let mut imported = 0;
+    for section in sections(module)? {
+        let mut pos = section.start;
+        if section.id == SECTION_IMPORT {
+            let count = read_u32(module, &mut pos)?;
+            for _ in 0..count {
+                read_name(module, &mut pos)?;
+                read_name(module, &mut pos)?;
+                let kind = *module.get(pos)?;
+                pos += 1;
+                match kind {
+                    KIND_FUNC => {
+                        types.push(read_u32(module, &mut pos)?);
+                        imported += 1;
+                    }
+                    1 => {
+                        pos += 1;
+                        skip_limits(module, &mut pos)?;
+                    }
+                    KIND_MEMORY => skip_limits(module, &mut pos)?,
+                    KIND_GLOBAL => pos += 2,
+                    _ => return None,
+                }
+            }
+        } else if section.id == SECTION_FUNCTION {
+            let count = read_u32(module, &mut pos)?;
+            for _ in 0..count {
+                types.push(read_u32(module, &mut pos)?);
+            }
+        }
+    }
+    Some((types, imported))
+}
+
+// The parameters and results of every type in the type section. Only plain function types of single byte value
+// types can be read, anything else gives None.
+fn types(module: &[u8]) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut types = Vec::new();
+    for section in sections(module)?.iter().filter(|s| s.id == SECTION_TYPE) {
+        let mut pos = section.start;
+        let count = read_u32(module, &mut pos)?;
+        for _ in 0..count {
+            if *module.get(pos)? != 0x60 {
+                return None;
+            }
+            pos += 1;
+            let mut read_types = || {
+                let len = read_u32(module, &mut pos)? as usize;
+                let types = module.get(pos..pos + len)?.to_vec();
+                pos += len;
+                Some(types)
+            };
+            let params = read_types()?;
+            types.push((params, read_types()?));
+        }
+    }
+    Some(types)
+}
+
+// The number of entries of a section, and the entries
+fn split_count(payload: &[u8]) -> Option<(u32, &[u8])> {
+    let mut pos = 0;
+    let count = read_u32(payload, &mut pos)?;
+    Some((count, &payload[pos..]))
+}
+
+// Give a module that takes its input through the canonical ABI the exports of the sidefuzz ABI
+fn lower_input(module: &[u8], exports: &[(String, u8, u32)]) -> Result<Vec<u8>, String> {
+    let exported = |name: &str| {
+        exports
+            .iter()
+            .find(|(export, kind, _)| export == name && *kind == KIND_FUNC)
+            .map(|(_, _, index)| *index)
+            .ok_or_else(|| format!("its core module has no '{}' export", name))
+    };
+    let fuzz = exported(FUZZ)?;
+    let realloc = exported(REALLOC)?;
+    let input_len = exported(INPUT_LEN)?;
+
+    let unparsed = || "its core module could not be parsed".to_string();
+    let (function_types, imported) = function_types(module).ok_or_else(unparsed)?;
+    let types = types(module).ok_or_else(unparsed)?;
+    let globals = global_mutability(module).ok_or_else(unparsed)?.len() as u32;
+    let signature = |index: u32| {
+        function_types
+            .get(index as usize)
+            .and_then(|ty| types.get(*ty as usize))
+            .map(|(params, results)| (params.as_slice(), results.as_slice()))
+    };
+    let is =
+        |index: u32, params: &[u8], results: &[u8]| signature(index) == Some((params, results));
+    if !is(fuzz, &[I32, I32], &[]) {
+        return Err("'fuzz' must take a single list<u8> or string and return nothing".to_string());
+    }
+    if !is(input_len, &[], &[I32]) {
+        return Err("'input-len' must take nothing and return a u32".to_string());
+    }
+    if !is(realloc, &[I32, I32, I32, I32], &[I32]) {
+        return Err("'cabi_realloc' isn't the canonical ABI's".to_string());
+    }
+
+    // Two function types, two functions and the global holding the input's address are added after the rest
+    let unit_type = types.len() as u32;
+    let pointer_type = unit_type + 1;
+    let input_pointer = function_types.len() as u32;
+    let fuzz_wrapper = input_pointer + 1;
+    let pointer = globals;
+    let defined = (function_types.len() - imported) as u32;
+
+    let mut pointer_body = vec![0x00]; // No locals
+    pointer_body.push(0x23); // global.get
+    write_u32(&mut pointer_body, pointer);
+    pointer_body.extend_from_slice(&[0x45, 0x04, 0x40]); // i32.eqz, if
+    pointer_body.extend_from_slice(&[0x41, 0x00, 0x41, 0x00, 0x41, 0x01]); // No old allocation, byte aligned
+    pointer_body.push(0x10); // call
+    write_u32(&mut pointer_body, input_len);
+    pointer_body.push(0x10);
+    write_u32(&mut pointer_body, realloc);
+    pointer_body.push(0x24); // global.set
+    write_u32(&mut pointer_body, pointer);
+    pointer_body.extend_from_slice(&[0x0b, 0x23]); // end, global.get
+    write_u32(&mut pointer_body, pointer);
+    pointer_body.push(0x0b);
+
+    // The original frees its argument, so it is given a copy of the input in a fresh allocation on every call
+    let mut fuzz_body = vec![0x01, 0x01, I32]; // One local, the copy's address
+    fuzz_body.extend_from_slice(&[0x41, 0x00, 0x41, 0x00, 0x41, 0x01]);
+    fuzz_body.push(0x10);
+    write_u32(&mut fuzz_body, input_len);
+    fuzz_body.push(0x10);
+    write_u32(&mut fuzz_body, realloc);
+    fuzz_body.extend_from_slice(&[0x22, 0x00, 0x23]); // local.tee, global.get
+    write_u32(&mut fuzz_body, pointer);
+    fuzz_body.push(0x10);
+    write_u32(&mut fuzz_body, input_len);
+    fuzz_body.extend_from_slice(&[0xfc, 0x0a, 0x00, 0x00, 0x20, 0x00]); // memory.copy, local.get
+    for function in [input_len, fuzz].iter() {
+        fuzz_body.push(0x10);
+        write_u32(&mut fuzz_body, *function);
+    }
+    fuzz_body.push(0x0b);
+
+    // The new exports, and which of the old ones they replace
+    let mut new_exports = vec![
+        (FUZZ.to_string(), fuzz_wrapper),
+        ("input_pointer".to_string(), input_pointer),
+        ("input_len".to_string(), input_len),
+    ];
+    if let Ok(input_is_str) = exported(INPUT_IS_STR) {
+        new_exports.push(("input_is_str".to_string(), input_is_str));
+    }
+    let replaced = |name: &str| new_exports.iter().any(|(new, _)| new == name);
+
+    let sections = sections(module).ok_or_else(unparsed)?;
+    let has_globals = sections.iter().any(|s| s.id == SECTION_GLOBAL);
+    let mut out = module[..8].to_vec();
+    let mut globals_added = false;
+    for section in sections.iter() {
+        let payload = &module[section.start..section.end];
+
+        // Modules without globals get a section for the one added, where it belongs
+        if !has_globals
+            && !globals_added
+            && section.id != SECTION_CUSTOM
+            && section_order(section.id) > section_order(SECTION_GLOBAL)
+        {
+            let mut globals = vec![0x01];
+            globals.extend_from_slice(&[I32, 0x01, 0x41, 0x00, 0x0b]);
+            write_section(&mut out, SECTION_GLOBAL, &globals);
+            globals_added = true;
+        }
+
+        let mut rewritten = Vec::new();
+        match section.id {
+            SECTION_TYPE => {
+                let (count, entries) = split_count(payload).ok_or_else(unparsed)?;
+                write_u32(&mut rewritten, count + 2);
+                rewritten.extend_from_slice(entries);
+                rewritten.extend_from_slice(&[0x60, 0x00, 0x00, 0x60, 0x00, 0x01, I32]);
+            }
+            SECTION_FUNCTION => {
+                let (count, entries) = split_count(payload).ok_or_else(unparsed)?;
+                write_u32(&mut rewritten, count + 2);
+                rewritten.extend_from_slice(entries);
+                write_u32(&mut rewritten, pointer_type);
+                write_u32(&mut rewritten, unit_type);
+            }
+            SECTION_GLOBAL => {
+                let (count, entries) = split_count(payload).ok_or_else(unparsed)?;
+                write_u32(&mut rewritten, count + 1);
+                rewritten.extend_from_slice(entries);
+                rewritten.extend_from_slice(&[I32, 0x01, 0x41, 0x00, 0x0b]); // Mutable, starting at 0
+            }
+            SECTION_EXPORT => {
+                let kept: Vec<&(String, u8, u32)> = exports
+                    .iter()
+                    .filter(|(name, _, _)| !replaced(name))
+                    .collect();
+                write_u32(&mut rewritten, (kept.len() + new_exports.len()) as u32);
+                let kept = kept
+                    .into_iter()
+                    .map(|(name, kind, index)| (name, *kind, *index));
+                let added = new_exports
+                    .iter()
+                    .map(|(name, index)| (name, KIND_FUNC, *index));
+                for (name, kind, index) in kept.chain(added) {
+                    write_u32(&mut rewritten, name.len() as u32);
+                    rewritten.extend_from_slice(name.as_bytes());
+                    rewritten.push(kind);
+                    write_u32(&mut rewritten, index);
+                }
+            }
+            SECTION_CODE => {
+                let (count, entries) = split_count(payload).ok_or_else(unparsed)?;
+                if count != defined {
+                    return Err(unparsed());
+                }
+                write_u32(&mut rewritten, count + 2);
+                rewritten.extend_from_slice(entries);
+                for body in [pointer_body.as_slice(), fuzz_body.as_slice()].iter() {
+                    write_u32(&mut rewritten, body.len() as u32);
+                    rewritten.extend_from_slice(body);
+                }
+            }
+            _ => rewritten.extend_from_slice(payload),
+        }
+        write_section(&mut out, section.id, &rewritten);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn core_module_test() {
+        // A core module with cabi_realloc (0), input-len (1) and fuzz (2), exporting its memory
+        let module_with = |input_len: &str| {
+            let mut module = b"\0asm\x01\0\0\0".to_vec();
+            write_section(
+                &mut module,
+                SECTION_TYPE,
+                &[
+                    0x03, 0x60, 0x04, I32, I32, I32, I32, 0x01, I32, 0x60, 0x00, 0x01, I32, 0x60,
+                    0x02, I32, I32, 0x00,
+                ],
+            );
+            write_section(&mut module, SECTION_FUNCTION, &[0x03, 0x00, 0x01, 0x02]);
+            write_section(&mut module, SECTION_MEMORY, &[0x01, 0x00, 0x01]);
+            let mut exports = vec![0x04];
+            for (name, kind, index) in [
+                ("memory", KIND_MEMORY, 0),
+                (REALLOC, KIND_FUNC, 0),
+                (input_len, KIND_FUNC, 1),
+                (FUZZ, KIND_FUNC, 2),
+            ]
+            .iter()
+            {
+                write_u32(&mut exports, name.len() as u32);
+                exports.extend_from_slice(name.as_bytes());
+                exports.extend_from_slice(&[*kind, *index]);
+            }
+            write_section(&mut module, SECTION_EXPORT, &exports);
+            write_section(
+                &mut module,
+                SECTION_CODE,
+                &[
+                    0x03, 0x04, 0x00, 0x41, 0x10, 0x0b, 0x04, 0x00, 0x41, 0x20, 0x0b, 0x02, 0x00,
+                    0x0b,
+                ],
+            );
+            module
+        };
+        let module = module_with(INPUT_LEN);
+
+        // Core modules pass through, components are unwrapped
+        assert_eq!(core_module(module.clone()).unwrap(), module);
+        let mut component = b"\0asm".to_vec();
+        component.extend_from_slice(&COMPONENT_VERSION);
+        write_section(&mut component, SECTION_CORE_MODULE, &module);
+        assert!(is_component(&component));
+        let lowered = core_module(component).unwrap();
+
+        assert_eq!(
+            types(&lowered).unwrap()[3..],
+            [(vec![], vec![]), (vec![], vec![I32])]
+        );
+        assert_eq!(function_types(&lowered).unwrap(), (vec![0, 1, 2, 4, 3], 0));
+        assert_eq!(global_mutability(&lowered), Some(vec![true]));
+        let exports = exports(&lowered).unwrap();
+        let export = |name: &str| {
+            exports
+                .iter()
+                .find(|(export, _, _)| export == name)
+                .map(|e| e.2)
+        };
+        assert_eq!(export(FUZZ), Some(4));
+        assert_eq!(export("input_pointer"), Some(3));
+        assert_eq!(export("input_len"), Some(1));
+        assert_eq!(export("memory"), Some(0));
+
+        // Without input-len the input's length is unknown
+        let mut component = b"\0asm".to_vec();
+        component.extend_from_slice(&COMPONENT_VERSION);
+        write_section(
+            &mut component,
+            SECTION_CORE_MODULE,
+            &module_with("input-lem"),
+        );
+        assert!(core_module(component).is_err());
+    }
+
+    #[test]
+    fn fresh_argument_test() {
+        // A core module whose fuzz frees its argument, as the canonical ABI has callees do. cabi_realloc (0) is a
+        // bump allocator marking the byte before every allocation as live, and fuzz (2) traps if its argument
+        // isn't live or doesn't start with 0x2a, then marks it as freed.
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        write_section(
+            &mut module,
+            SECTION_TYPE,
+            &[
+                0x03, 0x60, 0x04, I32, I32, I32, I32, 0x01, I32, 0x60, 0x00, 0x01, I32, 0x60, 0x02,
+                I32, I32, 0x00,
+            ],
+        );
+        write_section(&mut module, SECTION_FUNCTION, &[0x03, 0x00, 0x01, 0x02]);
+        write_section(&mut module, SECTION_MEMORY, &[0x01, 0x00, 0x01]);
+        write_section(
+            &mut module,
+            SECTION_GLOBAL,
+            &[0x01, I32, 0x01, 0x41, 0x10, 0x0b],
+        );
+        let mut exports = vec![0x04];
+        for (name, kind, index) in [
+            ("memory", KIND_MEMORY, 0),
+            (REALLOC, KIND_FUNC, 0),
+            (INPUT_LEN, KIND_FUNC, 1),
+            (FUZZ, KIND_FUNC, 2),
+        ]
+        .iter()
+        {
+            write_u32(&mut exports, name.len() as u32);
+            exports.extend_from_slice(name.as_bytes());
+            exports.extend_from_slice(&[*kind, *index]);
+        }
+        write_section(&mut module, SECTION_EXPORT, &exports);
+        let realloc: &[u8] = &[
+            0x01, 0x01, I32, 0x23, 0x00, 0x22, 0x04, 0x41, 0x01, 0x3a, 0x00, 0x00, 0x23, 0x00,
+            0x20, 0x03, 0x6a, 0x41, 0x01, 0x6a, 0x24, 0x00, 0x20, 0x04, 0x41, 0x01, 0x6a, 0x0b,
+        ];
+        let input_len: &[u8] = &[0x00, 0x41, 0x04, 0x0b];
+        let fuzz: &[u8] = &[
+            0x00, 0x20, 0x00, 0x41, 0x01, 0x6b, 0x2d, 0x00, 0x00, 0x45, 0x04, 0x40, 0x00, 0x0b,
+            0x20, 0x00, 0x2d, 0x00, 0x00, 0x41, 0x2a, 0x47, 0x04, 0x40, 0x00, 0x0b, 0x20, 0x00,
+            0x41, 0x01, 0x6b, 0x41, 0x00, 0x3a, 0x00, 0x00, 0x0b,
+        ];
+        let mut code = vec![0x03];
+        for body in [realloc, input_len, fuzz].iter() {
+            write_u32(&mut code, body.len() as u32);
+            code.extend_from_slice(body);
+        }
+        write_section(&mut module, SECTION_CODE, &code);
+
+        let mut component = b"\0asm".to_vec();
+        component.extend_from_slice(&COMPONENT_VERSION);
+        write_section(&mut component, SECTION_CORE_MODULE, &module);
+        let mut target = crate::wasm::WasmModule::new(component).unwrap();
+
+        // Every call gets an argument of its own, holding the input
+        let first = target.count_instructions(&[0x2a; 4]).unwrap();
+        assert_eq!(target.count_instructions(&[0x2a; 4]).unwrap(), first);
+        assert!(target.count_instructions(&[0x00; 4]).is_err());
+    }
+}
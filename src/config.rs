@@ -0,0 +1,148 @@
+// Settings from a sidefuzz.toml file in the working directory
+//
+// Settings are named after command line options, and are used for options that aren't given on the command line.
+// Top level settings apply to every subcommand that has the option, tables apply to a single subcommand:
+//
+// backend = "wasm"
+// format = "json"
+// max_instructions = 100000000
+//
+// [fuzz]
+// workers = 4
+// max_generations = 100000
+//
+// [check]
+// max_t_value = 5.0
+//
+// `sidefuzz batch` without any arguments fuzzes the targets listed as [[target]] tables, see `batch.rs`.
+
+use crate::errors::SideFuzzError;
+use toml::value::{Table, Value};
+
+// The file settings are read from
+pub const CONFIG_FILE: &str = "sidefuzz.toml";
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    table: Table,
+}
+
+impl Config {
+    // Read sidefuzz.toml from the working directory, if there is one
+    pub fn discover() -> Result<Option<Self>, SideFuzzError> {
+        if !std::path::Path::new(CONFIG_FILE).is_file() {
+            return Ok(None);
+        }
+        let toml = std::fs::read_to_string(CONFIG_FILE)?;
+        Self::parse(&toml).map(Some)
+    }
+
+    pub fn parse(toml: &str) -> Result<Self, SideFuzzError> {
+        let table = toml::from_str(toml).map_err(|e| SideFuzzError::BadConfig(e.to_string()))?;
+        Ok(Config { table })
+    }
+
+    // The settings for `subcommand`, as (long option, values) pairs.
+    // Flags that don't take a value have no values. Settings in the subcommand's table override top level settings.
+    pub fn args(&self, subcommand: &str) -> Result<Vec<(String, Vec<String>)>, SideFuzzError> {
+        let mut args: Vec<(String, Vec<String>)> = Vec::new();
+        let section = match self.table.get(subcommand) {
+            Some(Value::Table(section)) => Some(section),
+            _ => None,
+        };
+
+        for table in std::iter::once(&self.table).chain(section) {
+            for (key, value) in table.iter() {
+                let values = match option_values(key, value)? {
+                    Some(values) => values,
+                    None => continue,
+                };
+                let option = key.replace('_', "-");
+                args.retain(|(existing, _)| existing != &option);
+                args.push((option, values));
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+// The values of a setting, or None if it isn't an option (such as a table)
+fn option_values(key: &str, value: &Value) -> Result<Option<Vec<String>>, SideFuzzError> {
+    let values = match value {
+        Value::Boolean(true) => Vec::new(),
+        Value::Array(array) => {
+            let mut values = Vec::new();
+            for value in array {
+                match scalar(value) {
+                    Some(value) => values.push(value),
+                    // Arrays of tables, such as [[target]], aren't options
+                    None if value.is_table() => return Ok(None),
+                    None => return Err(bad_value(key)),
+                }
+            }
+            values
+        }
+        Value::Table(_) => return Ok(None),
+        Value::Boolean(false) => return Ok(None),
+        value => vec![scalar(value).ok_or_else(|| bad_value(key))?],
+    };
+    Ok(Some(values))
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        _ => None,
+    }
+}
+
+fn bad_value(key: &str) -> SideFuzzError {
+    SideFuzzError::BadConfig(format!(
+        "'{}' must be a string, number, boolean or list of them",
+        key
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_test() {
+        let config = Config::parse(
+            r#"
+            format = "json"
+            max_instructions = 1000
+            resume = false
+            stub = ["env.abort=trap", "env.now=0"]
+
+            [check]
+            format = "sarif"
+            max_t_value = 5.5
+
+            [[target]]
+            path = "a.wasm"
+            "#,
+        )
+        .unwrap();
+
+        let args = config.args("check").unwrap();
+        let get = |option: &str| {
+            args.iter()
+                .find(|(existing, _)| existing == option)
+                .map(|(_, values)| values.clone())
+        };
+        assert_eq!(get("format"), Some(vec!["sarif".to_string()]));
+        assert_eq!(get("max-instructions"), Some(vec!["1000".to_string()]));
+        assert_eq!(get("max-t-value"), Some(vec!["5.5".to_string()]));
+        assert_eq!(get("stub").map(|values| values.len()), Some(2));
+        assert_eq!(get("resume"), None);
+        assert_eq!(get("target"), None);
+
+        let args = config.args("fuzz").unwrap();
+        assert!(args.contains(&("format".to_string(), vec!["json".to_string()])));
+    }
+}
@@ -0,0 +1,124 @@
+// A directory of interesting inputs, shared between fuzzing runs
+//
+// Each file holds one input pair: the first input followed by the second.
+// Files holding a single input seed a pair where both inputs start out the same.
+
+use crate::errors::SideFuzzError;
+use crate::util::*;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+// Hex digits of the SHA-256 of an input pair in the names of its files
+pub(crate) const NAME_LEN: usize = 16;
+
+pub(crate) struct Corpus {
+    dir: PathBuf,
+}
+
+impl Corpus {
+    // Open the corpus directory, creating it if it doesn't exist
+    pub fn open(dir: &str) -> Result<Self, SideFuzzError> {
+        std::fs::create_dir_all(dir).map_err(SideFuzzError::CouldNotWriteFile)?;
+        Ok(Corpus { dir: dir.into() })
+    }
+
+    // Read every usable input pair in the corpus, in file name order.
+    // Files of the wrong length for this target are skipped.
+    // For variable-length targets, any file of up to `fuzz_len` bytes is used as both inputs.
+    pub fn load(&self, fuzz_len: usize, is_str: bool, variable_len: bool) -> Result<Vec<InputPair>, SideFuzzError> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut pairs = Vec::new();
+        for path in paths {
+            let bytes = std::fs::read(path)?;
+            let (first, second) = if bytes.len() == fuzz_len * 2 {
+                (bytes[..fuzz_len].to_vec(), bytes[fuzz_len..].to_vec())
+            } else if bytes.len() == fuzz_len || (variable_len && bytes.len() < fuzz_len) {
+                (bytes.clone(), bytes)
+            } else {
+                continue;
+            };
+
+            if is_str
+                && (std::str::from_utf8(&first).is_err() || std::str::from_utf8(&second).is_err())
+            {
+                continue;
+            }
+
+            pairs.push(InputPair {
+                first,
+                second,
+                is_str,
+            });
+        }
+
+        Ok(pairs)
+    }
+
+    // Save an input pair as `<kind>-<hash>`, so the same pair is only ever saved once
+    pub fn save(&self, kind: &str, pair: &InputPair) -> Result<(), SideFuzzError> {
+        let mut bytes = pair.first.clone();
+        bytes.extend_from_slice(&pair.second);
+
+        let path = self.dir.join(format!("{}-{}", kind, name(pair)));
+        std::fs::write(path, bytes).map_err(SideFuzzError::CouldNotWriteFile)
+    }
+}
+
+// The hash a pair is saved under, which doesn't change between builds. Inputs are length prefixed, so moving bytes
+// from one input to the other changes it.
+fn name(pair: &InputPair) -> String {
+    let mut hasher = Sha256::new();
+    for input in [&pair.first, &pair.second].iter() {
+        hasher.update(&(input.len() as u64).to_le_bytes());
+        hasher.update(input);
+    }
+    hex::encode(hasher.finalize())[..NAME_LEN].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::corpus::*;
+
+    #[test]
+    fn corpus_test() {
+        let dir = std::env::temp_dir().join("sidefuzz_corpus_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let corpus = Corpus::open(dir.to_str().unwrap()).unwrap();
+
+        let pair = InputPair {
+            first: vec![1, 2],
+            second: vec![3, 4],
+            is_str: false,
+        };
+        corpus.save("best", &pair).unwrap();
+        assert!(dir.join("best-2246528180fc63d5").exists());
+        std::fs::write(dir.join("single"), [5, 6]).unwrap();
+        std::fs::write(dir.join("wrong-length"), [7]).unwrap();
+
+        let pairs = corpus.load(2, false, false).unwrap();
+        // Shorter inputs are fine for variable-length targets
+        let variable = corpus.load(2, false, true).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(variable.len(), 3);
+        assert_eq!(variable[2].first, vec![7]);
+
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(
+            (pairs[0].first.clone(), pairs[0].second.clone()),
+            (vec![1, 2], vec![3, 4])
+        );
+        assert_eq!(
+            (pairs[1].first.clone(), pairs[1].second.clone()),
+            (vec![5, 6], vec![5, 6])
+        );
+    }
+}
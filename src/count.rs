@@ -1,24 +1,31 @@
 // This file contains the "count" subcommand
 
 use crate::errors::SideFuzzError;
+use crate::target::{Target, TargetOptions};
+use crate::crashes::crash_code;
 use crate::wasm::WasmModule;
+use std::io::{BufWriter, Read, Write};
 
 pub struct Count {
-  module: WasmModule,
-  input: Vec<u8>,
+  module: Box<dyn Target>,
+  inputs: Vec<Vec<u8>>,
+  csv: bool, // Print a CSV of every input and its count, instead of just the count
 }
 
 impl Count {
 
   // Create a new check command with the given wasm module and two inputs
   pub fn new(module: WasmModule, input: Vec<u8>) -> Result<Self, SideFuzzError> {
-    if input.len() != module.fuzz_len() {
-      return Err(SideFuzzError::InputsWrongSize(module.fuzz_len()));
-    }
+    Self::with_target(Box::new(module), input)
+  }
+
+  fn with_target(module: Box<dyn Target>, input: Vec<u8>) -> Result<Self, SideFuzzError> {
+    check_len(module.as_ref(), &input)?;
 
     Ok(Count {
       module: module,
-      input: input,
+      inputs: vec![input],
+      csv: false,
     })
   }
 
@@ -27,8 +34,40 @@ impl Count {
     Self::new(module, input)
   }
 
+  pub fn from_file_with_options(filename: &str, options: &TargetOptions, input: Vec<u8>) -> Result<Self, SideFuzzError> {
+    let module = crate::target::load(filename, options)?;
+    Self::with_target(module, input)
+  }
+
+  // Count every input in a file of hex inputs, one per line. `-` reads them from stdin.
+  pub fn from_inputs_file_with_options(filename: &str, options: &TargetOptions, inputs: &str) -> Result<Self, SideFuzzError> {
+    let module = crate::target::load(filename, options)?;
+    let text = if inputs == "-" {
+      let mut text = String::new();
+      std::io::stdin().read_to_string(&mut text)?;
+      text
+    } else {
+      std::fs::read_to_string(inputs)?
+    };
+    let inputs = parse_inputs(&text)?;
+    for (line, input) in inputs.iter() {
+      check_len(module.as_ref(), input)
+        .map_err(|err| SideFuzzError::BadInputs(format!("line {}: {}", line, err)))?;
+    }
+
+    Ok(Count {
+      module: module,
+      inputs: inputs.into_iter().map(|(_, input)| input).collect(),
+      csv: true,
+    })
+  }
+
   pub fn run(&mut self) {
-    let num_instructions = self.module.count_instructions(&self.input);
+    if self.csv {
+      self.run_csv();
+    }
+
+    let num_instructions = self.module.count_instructions(&self.inputs[0]);
     match num_instructions {
       Ok(num) => {
         println!("{}", num);
@@ -40,4 +79,68 @@ impl Count {
       }
     }
   }
-}
\ No newline at end of file
+
+  // Print `input,count,crash` for every input. Inputs that crashed have no count, and a crash code instead.
+  fn run_csv(&mut self) -> ! {
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut written = writeln!(out, "input,count,crash");
+    for input in self.inputs.iter() {
+      if written.is_err() {
+        break;
+      }
+      written = match self.module.count_instructions(input) {
+        Ok(count) => writeln!(out, "{},{},", hex::encode(input), count),
+        Err(err) => writeln!(out, "{},,{}", hex::encode(input), crash_code(&err)),
+      };
+    }
+    // Exiting skips destructors, so the buffer has to be flushed explicitly
+    match written.and_then(|_| out.flush()) {
+      Ok(_) => std::process::exit(0),
+      Err(_) => std::process::exit(1),
+    }
+  }
+}
+
+// Whether `input` is the right length for `module`
+fn check_len(module: &dyn Target, input: &[u8]) -> Result<(), SideFuzzError> {
+  if module.variable_len() {
+    if input.len() > module.fuzz_len() {
+      return Err(SideFuzzError::InputTooLong(module.fuzz_len()));
+    }
+  } else if input.len() != module.fuzz_len() {
+    return Err(SideFuzzError::InputsWrongSize(module.fuzz_len()));
+  }
+  Ok(())
+}
+
+// Decode hex inputs, one per line, along with their line numbers. Blank lines are skipped.
+fn parse_inputs(text: &str) -> Result<Vec<(usize, Vec<u8>)>, SideFuzzError> {
+  let mut inputs = Vec::new();
+  for (index, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+    let input = hex::decode(line)
+      .map_err(|err| SideFuzzError::BadInputs(format!("line {}: {}", index + 1, err)))?;
+    inputs.push((index + 1, input));
+  }
+  if inputs.is_empty() {
+    return Err(SideFuzzError::BadInputs("it doesn't contain any inputs".to_string()));
+  }
+  Ok(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_inputs_test() {
+    let inputs = parse_inputs("0102\n\n  ff \n").unwrap();
+    assert_eq!(inputs, vec![(1, vec![1, 2]), (3, vec![255])]);
+    assert!(parse_inputs("0102\nzz\n").is_err());
+    assert!(parse_inputs("\n").is_err());
+  }
+}
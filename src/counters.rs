@@ -0,0 +1,158 @@
+// Per-block execution counters, kept by the target itself
+//
+// Branch traces call into the host at the start of every basic block, which is slow and only works for hosts that
+// let us define the hooks. Instead, a module can be rewritten to count how often each of its basic blocks runs:
+// it is given a mutable i64 global for every block, after the globals it already had, and every block adds one to
+// its counter. After an execution, the host reads the counters through their exports. Globals are out of reach of
+// the module's own code, unlike memory, which allocators such as TinyGo's, AssemblyScript's and wasi-libc's treat
+// as theirs up to its current size. Blocks are numbered as in branch traces, see `tracing::block_sites`.
+
+use crate::binary::{add_globals, global_mutability, write_u32, GLOBAL_EXPORT_PREFIX};
+use crate::errors::SideFuzzError;
+use crate::instrument::*;
+use crate::tracing::block_start;
+use wasmi::{Extern, Global, Instance, Store, Value};
+
+// A mutable i64 global starting at 0, as encoded in the global section
+const COUNTER: &[u8] = &[0x7e, 0x01, 0x42, 0x00, 0x0b];
+
+// Instructions used to increment a counter
+const OP_GLOBAL_GET: u8 = 0x23;
+const OP_GLOBAL_SET: u8 = 0x24;
+const OP_I64_CONST: u8 = 0x42;
+const OP_I64_ADD: u8 = 0x7c;
+
+// Where a module instrumented with `instrument_block_counts` keeps its counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlockCounters {
+    pub first: u32, // Index of the first counter's global, after every global the module had
+    pub blocks: usize, // One counter for each of the module's `block_sites`, in the same order
+}
+
+impl BlockCounters {
+    // Where the counters of `bytes` would be. Returns None if the module could not be parsed.
+    pub fn of(bytes: &[u8]) -> Option<Self> {
+        Some(BlockCounters {
+            first: global_mutability(bytes)?.len() as u32,
+            blocks: crate::tracing::block_sites(bytes).ok()?.len(),
+        })
+    }
+
+    // The counters, which every mutable global is exported as
+    fn globals(
+        &self,
+        instance: &Instance,
+        store: &Store<()>,
+    ) -> Result<Vec<Global>, SideFuzzError> {
+        (0..self.blocks as u32)
+            .map(|block| {
+                let name = format!("{}{}", GLOBAL_EXPORT_PREFIX, self.first + block);
+                instance
+                    .get_export(store, &name)
+                    .and_then(Extern::into_global)
+                    .ok_or(SideFuzzError::InstrumentationFailed)
+            })
+            .collect()
+    }
+
+    // The counts since the counters were last reset
+    pub fn read(&self, instance: &Instance, store: &Store<()>) -> Result<Vec<u64>, SideFuzzError> {
+        let globals = self.globals(instance, store)?;
+        Ok(globals
+            .iter()
+            .map(|global| match global.get(store) {
+                Value::I64(count) => count as u64,
+                _ => 0,
+            })
+            .collect())
+    }
+
+    pub fn reset(&self, instance: &Instance, store: &mut Store<()>) -> Result<(), SideFuzzError> {
+        for global in self.globals(instance, &*store)? {
+            global
+                .set(&mut *store, Value::I64(0))
+                .map_err(|_| SideFuzzError::InstrumentationFailed)?;
+        }
+        Ok(())
+    }
+}
+
+// Add one to a counter at the start of every basic block, and add the counters to the module.
+// Returns None for modules `BlockCounters::of` can't count the blocks of.
+pub(crate) fn instrument_block_counts(bytes: &[u8]) -> Option<Vec<u8>> {
+    let counters = BlockCounters::of(bytes)?;
+    let mut block = 0;
+    let instrumented = instrument(bytes, &[], &[], |site| {
+        let mut insert = Insert::default();
+        if block_start(site).is_none() {
+            return insert;
+        }
+        let global = counters.first + block;
+        block += 1;
+
+        let code = if site.op.is_none() {
+            &mut insert.before
+        } else {
+            &mut insert.after
+        };
+        emit_increment(code, global);
+        insert
+    })?;
+    add_globals(&instrumented, COUNTER, counters.blocks as u32)
+}
+
+// counter = counter + 1, for the i64 global `global`
+fn emit_increment(out: &mut Vec<u8>, global: u32) {
+    out.push(OP_GLOBAL_GET);
+    write_u32(out, global);
+    out.push(OP_I64_CONST);
+    out.push(0x01);
+    out.push(OP_I64_ADD);
+    out.push(OP_GLOBAL_SET);
+    write_u32(out, global);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (module (memory 1 2) (func (export "f") (param i32) local.get 0 if nop end))
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic + version
+        0x01, 0x05, 0x01, 0x60, 0x01, 0x7f, 0x00, // types
+        0x03, 0x02, 0x01, 0x00, // functions
+        0x05, 0x04, 0x01, 0x01, 0x01, 0x02, // memory
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // exports
+        0x0a, 0x0a, 0x01, 0x08, 0x00, 0x20, 0x00, 0x04, 0x40, 0x01, 0x0b, 0x0b, // code
+    ];
+
+    #[test]
+    fn counters_test() {
+        // Blocks start at the entry, inside the if and after it
+        let counters = BlockCounters::of(MODULE).unwrap();
+        assert_eq!(
+            counters,
+            BlockCounters {
+                first: 0,
+                blocks: 3
+            }
+        );
+
+        // Memory is left as it was, the counters are globals of their own
+        let instrumented = instrument_block_counts(MODULE).unwrap();
+        let memory = crate::binary::memories(&instrumented).unwrap()[0];
+        assert_eq!(memory.initial_pages, 1);
+        assert_eq!(memory.maximum_pages, Some(2));
+        assert_eq!(global_mutability(&instrumented), Some(vec![true; 3]));
+
+        // The counter of the block inside the if is the second one
+        let mut increment = Vec::new();
+        emit_increment(&mut increment, 1);
+        let mut expected = vec![0x04, 0x40];
+        expected.extend_from_slice(&increment);
+        expected.push(0x01);
+        assert!(instrumented
+            .windows(expected.len())
+            .any(|window| window == expected.as_slice()));
+    }
+}
@@ -0,0 +1,76 @@
+// Coverage guidance
+//
+// The optimizer only follows the difference in instruction counts, so it can settle on a local optimum and never
+// try inputs that take other paths through the target. Coverage runs inputs through a copy of the target that is
+// instrumented to record the basic blocks it enters, and keeps the input pairs that take a transition between
+// blocks (an edge) that no earlier input took. These are fed back into the population to keep exploring.
+
+use crate::errors::SideFuzzError;
+use crate::target::Target;
+use crate::tracing::TraceKind;
+use crate::util::InputPair;
+use crate::wasm::WasmModule;
+use std::collections::{HashSet, VecDeque};
+
+// At most this many input pairs with new coverage are kept, older ones are dropped first
+const MAX_FINDS: usize = 50;
+
+// Stands in for the block before the first one, so that entering the target counts as an edge
+const ENTRY: u32 = u32::MAX;
+
+pub(crate) struct Coverage {
+    module: WasmModule, // Instrumented with `TraceKind::Branches`
+    edges: HashSet<(u32, u32)>,
+    finds: VecDeque<InputPair>,
+}
+
+impl Coverage {
+    // Only wasm targets can be instrumented
+    pub fn new(target: &dyn Target) -> Result<Self, SideFuzzError> {
+        let (bytes, mut options) = target.wasm().ok_or(SideFuzzError::CoverageUnsupported)?;
+        options.trace = Some(TraceKind::Branches);
+        Ok(Coverage {
+            module: WasmModule::with_options(bytes, options)?,
+            edges: HashSet::new(),
+            finds: VecDeque::new(),
+        })
+    }
+
+    // Run every individual, keeping those that cover new edges. Returns how many new edges were covered.
+    pub fn explore(&mut self, population: &[InputPair]) -> usize {
+        let before = self.edges.len();
+        for individual in population.iter() {
+            let mut novel = false;
+            for input in [&individual.first, &individual.second].iter() {
+                // Inputs that crash don't tell us anything here, triage takes care of them
+                let trace = match self.module.trace(input) {
+                    Ok(trace) => trace,
+                    Err(_) => continue,
+                };
+                let mut previous = ENTRY;
+                for block in trace.blocks {
+                    novel |= self.edges.insert((previous, block));
+                    previous = block;
+                }
+            }
+
+            if novel {
+                if self.finds.len() == MAX_FINDS {
+                    self.finds.pop_front();
+                }
+                self.finds.push_back(individual.clone());
+            }
+        }
+        self.edges.len() - before
+    }
+
+    // Input pairs that covered new edges, oldest first
+    pub fn finds(&self) -> Vec<InputPair> {
+        self.finds.iter().cloned().collect()
+    }
+
+    // How many distinct edges have been covered
+    pub fn edges(&self) -> usize {
+        self.edges.len()
+    }
+}
@@ -0,0 +1,207 @@
+// Crash triage
+//
+// Inputs that make the target trap are scored as unfit and otherwise forgotten by the optimizer. Triage re-runs
+// them to find out how they crashed: the trap code, and for wasm targets the function that was running.
+// Crashes with the same code and function are counted as one, and the first input found for each is saved.
+// Executions that time out are triaged the same way, as hangs.
+
+use crate::binary::function_names;
+use crate::corpus::NAME_LEN;
+use crate::errors::SideFuzzError;
+use crate::target::Target;
+use crate::tracing::TraceKind;
+use crate::wasm::WasmModule;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+// What distinguishes one crash from another
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Signature {
+    code: String,
+    function: Option<u32>,
+}
+
+struct Crash {
+    count: usize,
+    input: Vec<u8>,
+    reproducer: Option<String>,
+}
+
+// A distinct crash found while fuzzing
+#[derive(Debug, Clone, Serialize)]
+pub struct CrashSummary {
+    pub code: String,
+    pub function: Option<u32>,
+    pub function_name: Option<String>,
+    pub count: usize,               // How many different inputs crashed this way
+    pub input: String,              // The first of them
+    pub reproducer: Option<String>, // Where that input was saved
+}
+
+pub(crate) struct Triage {
+    target: Box<dyn Target>,
+    traced: Option<WasmModule>, // Wasm targets instrumented to find the faulting function
+    names: HashMap<u32, String>,
+    dir: Option<PathBuf>,
+    hangs: Option<PathBuf>, // Where inputs that timed out are saved, instead of `dir`
+    crashes: BTreeMap<Signature, Crash>,
+    seen: HashSet<String>, // Hashes of inputs already examined
+}
+
+impl Triage {
+    // Triage crashes of `target`, saving reproducers to `dir` (created if it doesn't exist) if there is one.
+    // Inputs that timed out are saved to `hangs` if there is one.
+    pub fn new(target: Box<dyn Target>, dir: Option<&str>, hangs: Option<&str>) -> Result<Self, SideFuzzError> {
+        for dir in dir.iter().chain(hangs.iter()) {
+            std::fs::create_dir_all(dir).map_err(SideFuzzError::CouldNotWriteFile)?;
+        }
+
+        let (traced, names) = match target.wasm() {
+            Some((bytes, mut options)) => {
+                options.trace = Some(TraceKind::Functions);
+                let names = function_names(&bytes);
+                // Best effort, without instrumentation crashes are still classified by trap code
+                (WasmModule::with_options(bytes, options).ok(), names)
+            }
+            None => (None, HashMap::new()),
+        };
+
+        Ok(Triage {
+            target,
+            traced,
+            names,
+            dir: dir.map(PathBuf::from),
+            hangs: hangs.map(PathBuf::from),
+            crashes: BTreeMap::new(),
+            seen: HashSet::new(),
+        })
+    }
+
+    // Run an input, and record how it crashed if it does. Returns whether this was a new kind of crash.
+    pub fn examine(&mut self, input: &[u8]) -> Result<bool, SideFuzzError> {
+        // The same input keeps the same name across builds
+        let hash = crate::archive::sha256(input)[..NAME_LEN].to_string();
+        if !self.seen.insert(hash.clone()) {
+            return Ok(false);
+        }
+
+        let crash = match &mut self.traced {
+            Some(traced) => traced.trace_crash(input),
+            None => self
+                .target
+                .count_instructions(input)
+                .err()
+                .map(|err| (err, None)),
+        };
+        let (err, function) = match crash {
+            Some(crash) => crash,
+            None => return Ok(false),
+        };
+        let signature = Signature {
+            code: crash_code(&err),
+            function,
+        };
+
+        if let Some(crash) = self.crashes.get_mut(&signature) {
+            crash.count += 1;
+            return Ok(false);
+        }
+
+        let dir = match &self.hangs {
+            Some(hangs) if signature.code == TIMEOUT => Some(hangs),
+            _ => self.dir.as_ref(),
+        };
+        let reproducer = match dir {
+            Some(dir) => {
+                let name = match function {
+                    Some(function) => format!("{}-f{}-{}", signature.code, function, hash),
+                    None => format!("{}-{}", signature.code, hash),
+                };
+                let path = dir.join(name);
+                std::fs::write(&path, input).map_err(SideFuzzError::CouldNotWriteFile)?;
+                Some(path.to_string_lossy().into_owned())
+            }
+            None => None,
+        };
+        self.crashes.insert(
+            signature,
+            Crash {
+                count: 1,
+                input: input.to_vec(),
+                reproducer,
+            },
+        );
+        Ok(true)
+    }
+
+    // Every distinct crash found so far, most common first
+    pub fn summary(&self) -> Vec<CrashSummary> {
+        let mut summary: Vec<CrashSummary> = self
+            .crashes
+            .iter()
+            .map(|(signature, crash)| CrashSummary {
+                code: signature.code.clone(),
+                function: signature.function,
+                function_name: signature
+                    .function
+                    .and_then(|function| self.names.get(&function).cloned()),
+                count: crash.count,
+                input: hex::encode(&crash.input),
+                reproducer: crash.reproducer.clone(),
+            })
+            .collect();
+        summary.sort_by(|a, b| b.count.cmp(&a.count));
+        summary
+    }
+}
+
+// The code of executions that timed out
+const TIMEOUT: &str = "Timeout";
+
+// How an execution failed, named after the wasm trap code where there is one
+pub(crate) fn crash_code(err: &SideFuzzError) -> String {
+    match err {
+        SideFuzzError::WasmError(wasmi::Error::Trap(trap)) => match trap.trap_code() {
+            Some(code) => format!("{:?}", code),
+            None => "HostError".to_string(),
+        },
+        SideFuzzError::InstructionBudgetExhausted(_) => "OutOfFuel".to_string(),
+        SideFuzzError::Timeout(_) => TIMEOUT.to_string(),
+        _ => "Error".to_string(),
+    }
+}
+
+// Format crashes as a human readable list
+pub fn format_crashes(crashes: &[CrashSummary]) -> String {
+    let mut lines = Vec::new();
+    for crash in crashes {
+        let location = match (&crash.function_name, crash.function) {
+            (Some(name), _) => format!(" in {}", name),
+            (None, Some(function)) => format!(" in function {}", function),
+            (None, None) => String::new(),
+        };
+        let example = match &crash.reproducer {
+            Some(path) => path.clone(),
+            None => crash.input.clone(),
+        };
+        lines.push(format!(
+            "{}{}: {} inputs, e.g. {}",
+            crash.code, location, crash.count, example
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crash_code_test() {
+        let err = SideFuzzError::InstructionBudgetExhausted(1000);
+        assert_eq!(crash_code(&err), "OutOfFuel");
+        assert_eq!(crash_code(&SideFuzzError::Timeout(100)), "Timeout");
+        assert_eq!(crash_code(&SideFuzzError::InputsDifferentSizes), "Error");
+    }
+}
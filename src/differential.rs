@@ -0,0 +1,313 @@
+// This file contains the "differential" subcommand, which compares two implementations of the same primitive
+//
+// A constant-time rewrite should behave exactly like the implementation it replaces, and its instruction count should
+// vary less. Both targets are run on the same inputs: any input they produce different outputs for, through
+// `sidefuzz::set_output`, is a functional difference. The optimizer searches for the pair of inputs whose counts
+// differ more in the rewrite than in the reference, which is where the rewrite is leakier than what it replaces.
+
+use crate::errors::SideFuzzError;
+use crate::optimizer::Optimizer;
+use crate::report::Reporter;
+use crate::target::{Target, TargetOptions};
+use crate::util::{InputPair, ScoredInputPair};
+use rolling_stats::Stats;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::f64::{NAN, NEG_INFINITY};
+
+pub struct Differential {
+    reference: Box<dyn Target>,
+    rewrite: Box<dyn Target>,
+    generations: u64,
+    seed: u64,
+    reporter: Reporter,
+}
+
+// What has been seen of both targets so far
+struct Comparison {
+    reference: Box<dyn Target>,
+    rewrite: Box<dyn Target>,
+    reference_counts: Stats<f64>,
+    rewrite_counts: Stats<f64>,
+    executions: usize, // Of each target, not counting crashes
+    compared_outputs: bool,
+    mismatch: Option<Mismatch>, // The first input the targets disagreed on
+}
+
+// An input the two targets produce different outputs for, all in hexadecimal
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Mismatch {
+    pub input: String,
+    pub reference: String,
+    pub rewrite: String,
+}
+
+// The instruction counts of one target
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Counts {
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    pub std_dev: f64,
+}
+
+// What the search found
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DifferentialResult {
+    pub mismatch: Option<Mismatch>, // The search stops at the first one
+    pub compared_outputs: bool,
+    pub executions: usize, // Of each target, not counting crashes
+    pub reference: Counts,
+    pub rewrite: Counts,
+    // The most divergent inputs, and how many more instructions their counts differ by in the rewrite than in the
+    // reference. The score is negative infinity if every input crashed one of the targets.
+    pub input_1: String,
+    pub input_2: String,
+    pub score: f64,
+}
+
+impl Differential {
+    pub fn from_files_with_options(
+        reference: &str,
+        rewrite: &str,
+        options: &TargetOptions,
+    ) -> Result<Self, SideFuzzError> {
+        let reference = crate::target::load(reference, options)?;
+        let rewrite = crate::target::load(rewrite, options)?;
+        if reference.input_segments() != rewrite.input_segments() {
+            return Err(SideFuzzError::IncomparableTargets(format!(
+                "the reference takes inputs of {:?} bytes, but the rewrite takes {:?} bytes",
+                reference.input_segments(),
+                rewrite.input_segments()
+            )));
+        }
+        if reference.input_is_str() != rewrite.input_is_str()
+            || reference.variable_len() != rewrite.variable_len()
+        {
+            return Err(SideFuzzError::IncomparableTargets(
+                "only one of them fetches its input as a string, or with a variable length"
+                    .to_string(),
+            ));
+        }
+        Ok(Differential {
+            reference,
+            rewrite,
+            generations: 50,
+            seed: rand::random(),
+            reporter: Reporter::default(),
+        })
+    }
+
+    // How many generations to search for
+    pub fn set_generations(&mut self, generations: u64) {
+        self.generations = generations;
+    }
+
+    // Seed the optimizer, so a search can be repeated
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // Set how progress is reported
+    pub fn set_reporter(&mut self, reporter: Reporter) {
+        self.reporter = reporter;
+    }
+
+    // Search for inputs the targets disagree on, or whose counts differ more in the rewrite than in the reference
+    pub fn run(self) -> Result<DifferentialResult, SideFuzzError> {
+        let fuzz_len = self.reference.fuzz_len();
+        let input_is_str = self.reference.input_is_str();
+        let segments = self.reference.input_segments();
+        let variable_len = self.reference.variable_len();
+        let comparison = RefCell::new(Comparison {
+            reference: self.reference,
+            rewrite: self.rewrite,
+            reference_counts: Stats::new(),
+            rewrite_counts: Stats::new(),
+            executions: 0,
+            compared_outputs: false,
+            mismatch: None,
+        });
+
+        let mut optimizer = Optimizer::with_seed(
+            fuzz_len,
+            |first: &[u8], second: &[u8]| {
+                comparison.borrow_mut().score(first, second, input_is_str)
+            },
+            input_is_str,
+            self.seed,
+        );
+        optimizer.set_segments(segments);
+        if variable_len {
+            optimizer.set_variable_len();
+        }
+
+        let mut best = ScoredInputPair {
+            score: NEG_INFINITY,
+            ..ScoredInputPair::default()
+        };
+        for generation in 0..self.generations {
+            // Sorted most fit first
+            let scored = optimizer.scored_population();
+            if scored[0].score > best.score {
+                best = scored[0].clone();
+            }
+            optimizer.step_with_scores(scored);
+            if comparison.borrow().mismatch.is_some() {
+                break;
+            }
+            if generation % 10 == 9 {
+                self.reporter.progress(format!(
+                    "generation {}: the rewrite differs by {} more instructions than the reference",
+                    generation + 1,
+                    best.score
+                ));
+            }
+        }
+        drop(optimizer);
+        let comparison = comparison.into_inner();
+
+        Ok(DifferentialResult {
+            mismatch: comparison.mismatch,
+            compared_outputs: comparison.compared_outputs,
+            executions: comparison.executions,
+            reference: Counts::new(&comparison.reference_counts),
+            rewrite: Counts::new(&comparison.rewrite_counts),
+            input_1: hex::encode(&best.pair.first),
+            input_2: hex::encode(&best.pair.second),
+            score: best.score,
+        })
+    }
+}
+
+impl Counts {
+    fn new(stats: &Stats<f64>) -> Self {
+        Counts {
+            mean: stats.mean,
+            min: stats.min,
+            max: stats.max,
+            std_dev: stats.std_dev,
+        }
+    }
+}
+
+impl DifferentialResult {
+    // 1 if the targets disagree on an input or the rewrite is leakier than the reference, and 0 otherwise
+    pub fn exit_code(&self) -> i32 {
+        if self.mismatch.is_some() || !self.score.is_finite() || self.score > 0.0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn text(&self) -> String {
+        if let Some(mismatch) = &self.mismatch {
+            return format!(
+                "The implementations disagree on input {}: the reference outputs {}, but the rewrite outputs {}.",
+                mismatch.input, mismatch.reference, mismatch.rewrite
+            );
+        }
+
+        let mut lines = Vec::new();
+        if !self.compared_outputs {
+            lines.push(
+                "Outputs weren't compared, call sidefuzz::set_output from both targets to check that they behave the same."
+                    .to_string(),
+            );
+        }
+        lines.push(format!(
+            "reference: {}",
+            describe_counts(&self.reference, self.executions)
+        ));
+        lines.push(format!(
+            "rewrite:   {}",
+            describe_counts(&self.rewrite, self.executions)
+        ));
+        if !self.score.is_finite() {
+            lines.push("Every input crashed one of the targets.".to_string());
+            return lines.join("\n");
+        }
+
+        lines.push(format!(
+            "Most divergent inputs: {} and {}, whose counts differ by {} more instructions in the rewrite than in the reference.",
+            self.input_1, self.input_2, self.score
+        ));
+        if self.score > 0.0 {
+            lines.push(
+                "The rewrite's timing depends on its input more than the reference's does for these inputs."
+                    .to_string(),
+            );
+        } else {
+            lines.push(
+                "The rewrite's timing never depended on its input more than the reference's did."
+                    .to_string(),
+            );
+        }
+        lines.join("\n")
+    }
+}
+
+impl Comparison {
+    // How much more the counts of the two inputs differ in the rewrite than in the reference
+    fn score(&mut self, first: &[u8], second: &[u8], is_str: bool) -> ScoredInputPair {
+        let counts = (self.run(first), self.run(second));
+        let pair = InputPair {
+            first: first.to_vec(),
+            second: second.to_vec(),
+            is_str,
+        };
+        match counts {
+            (Some((reference_first, rewrite_first)), Some((reference_second, rewrite_second))) => {
+                let reference = (reference_first as f64 - reference_second as f64).abs();
+                let rewrite = (rewrite_first as f64 - rewrite_second as f64).abs();
+                ScoredInputPair {
+                    score: rewrite - reference,
+                    highest: rewrite_first.max(rewrite_second) as f64,
+                    lowest: rewrite_first.min(rewrite_second) as f64,
+                    pair,
+                }
+            }
+            // Either target crashed
+            _ => ScoredInputPair {
+                score: NEG_INFINITY,
+                highest: NAN,
+                lowest: NAN,
+                pair,
+            },
+        }
+    }
+
+    // Run both targets on an input, returning their counts unless either crashed
+    fn run(&mut self, input: &[u8]) -> Option<(u64, u64)> {
+        let reference = self.reference.run_with_output(input).ok();
+        let rewrite = self.rewrite.run_with_output(input).ok();
+        let ((reference_count, reference_output), (rewrite_count, rewrite_output)) =
+            (reference?, rewrite?);
+        self.reference_counts.update(reference_count as f64);
+        self.rewrite_counts.update(rewrite_count as f64);
+        self.executions += 1;
+
+        if let (Some(reference_output), Some(rewrite_output)) = (reference_output, rewrite_output) {
+            self.compared_outputs = true;
+            if reference_output != rewrite_output && self.mismatch.is_none() {
+                self.mismatch = Some(Mismatch {
+                    input: hex::encode(input),
+                    reference: hex::encode(&reference_output),
+                    rewrite: hex::encode(&rewrite_output),
+                });
+            }
+        }
+        Some((reference_count, rewrite_count))
+    }
+}
+
+fn describe_counts(counts: &Counts, executions: usize) -> String {
+    if executions == 0 {
+        return "no executions".to_string();
+    }
+    format!(
+        "{} instructions on average, from {} to {} (standard deviation {}) over {} executions",
+        counts.mean, counts.min, counts.max, counts.std_dev, executions
+    )
+}
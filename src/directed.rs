@@ -0,0 +1,214 @@
+// Directed input generation for branches the optimizer doesn't reach
+//
+// Branches on secret values are what makes targets variable-time, but the optimizer only finds them when the
+// difference in instruction counts leads there. When it plateaus, the best input pair is traced with taint tracking,
+// which tells which tainted branches only ever went one way, and which input bytes decide each of them. Only those
+// bytes are then searched for values sending the branch the other way: every value for branches on a single byte,
+// and random values otherwise. This stands in for a constraint solver, it needs no model of the instructions and
+// works on anything the interpreter runs. Inputs taking both ways of a branch are paired and fed back into the
+// population, as they are the pairs most likely to differ in execution time.
+
+use crate::errors::SideFuzzError;
+use crate::taint::TaintedUse;
+use crate::target::Target;
+use crate::tracing::TraceKind;
+use crate::util::InputPair;
+use crate::wasm::WasmModule;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::{BTreeMap, HashSet};
+
+// At most this many branches are solved for each time
+const MAX_BRANCHES: usize = 8;
+
+// Random inputs tried for a branch that depends on more than one byte, before giving up on it
+const MAX_ATTEMPTS: usize = 256;
+
+// The characters tried for string inputs, to keep them valid UTF-8
+const PRINTABLE: std::ops::Range<u8> = 0x20..0x7f;
+
+// A tainted branch that went only one way, and the input bytes it depends on
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct OneSided {
+    id: u32,
+    outcome: u32,
+    bytes: Vec<usize>,
+}
+
+// The tainted branches of a trace that only went one way, ignoring bytes past the end of the input
+fn one_sided(uses: &BTreeMap<u32, TaintedUse>, len: usize) -> Vec<OneSided> {
+    uses.iter()
+        .filter(|(_, used)| used.outcomes.len() == 1)
+        .filter_map(|(id, used)| {
+            let bytes: Vec<usize> = used
+                .taint
+                .bytes()
+                .into_iter()
+                .filter(|byte| *byte < len)
+                .collect();
+            if bytes.is_empty() {
+                return None;
+            }
+            Some(OneSided {
+                id: *id,
+                // Ok to unwrap, there is exactly one outcome
+                outcome: *used.outcomes.iter().next().unwrap(),
+                bytes,
+            })
+        })
+        .collect()
+}
+
+// Whether a trace took `branch` a way it didn't go before
+fn flipped(uses: &BTreeMap<u32, TaintedUse>, branch: &OneSided) -> bool {
+    uses.get(&branch.id)
+        .map(|used| {
+            used.outcomes
+                .iter()
+                .any(|outcome| *outcome != branch.outcome)
+        })
+        .unwrap_or(false)
+}
+
+pub(crate) struct Directed {
+    module: WasmModule, // Instrumented with `TraceKind::Taint`
+    input_is_str: bool,
+    rng: StdRng,
+    solved: HashSet<u32>, // Branches already flipped, which the optimizer can take from here
+}
+
+impl Directed {
+    // Only wasm targets can be instrumented
+    pub fn new(target: &dyn Target, seed: u64) -> Result<Self, SideFuzzError> {
+        let (bytes, mut options) = target.wasm().ok_or(SideFuzzError::DirectedUnsupported)?;
+        options.trace = Some(TraceKind::Taint);
+        Ok(Directed {
+            module: WasmModule::with_options(bytes, options)?,
+            input_is_str: target.input_is_str(),
+            rng: StdRng::seed_from_u64(seed),
+            solved: HashSet::new(),
+        })
+    }
+
+    fn value(&mut self) -> u8 {
+        if self.input_is_str {
+            self.rng.gen_range(PRINTABLE.start, PRINTABLE.end)
+        } else {
+            self.rng.gen()
+        }
+    }
+
+    // Whether `input` runs without crashing and sends `branch` the other way
+    fn flips(&mut self, input: &[u8], branch: &OneSided) -> bool {
+        // Inputs that crash don't tell us anything here, triage takes care of them
+        match self.module.trace(input) {
+            Ok(trace) => flipped(&trace.taint.uses, branch),
+            Err(_) => false,
+        }
+    }
+
+    // Search for an input like `input` that sends `branch` the other way
+    fn flip(&mut self, input: &[u8], branch: &OneSided) -> Option<Vec<u8>> {
+        let mut candidate = input.to_vec();
+        if let [byte] = branch.bytes[..] {
+            let values: Vec<u8> = if self.input_is_str {
+                PRINTABLE.collect()
+            } else {
+                (0..=255).collect()
+            };
+            for value in values.into_iter().filter(|value| *value != input[byte]) {
+                candidate[byte] = value;
+                if self.flips(&candidate, branch) {
+                    return Some(candidate);
+                }
+            }
+            return None;
+        }
+        for attempt in 0..MAX_ATTEMPTS {
+            candidate.copy_from_slice(input);
+            // Alternate between changing one of the bytes, and changing all of them
+            if attempt % 2 == 0 {
+                let byte = branch.bytes[self.rng.gen_range(0, branch.bytes.len())];
+                candidate[byte] = self.value();
+            } else {
+                for byte in branch.bytes.iter() {
+                    candidate[*byte] = self.value();
+                }
+            }
+            if candidate != input && self.flips(&candidate, branch) {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+
+    // Input pairs taking secret-dependent branches of `individual` both ways, pairing its first input with inputs
+    // that flip a branch it took only one way
+    pub fn solve(&mut self, individual: &InputPair) -> Vec<InputPair> {
+        let input = &individual.first;
+        let uses = match self.module.trace(input) {
+            Ok(trace) => trace.taint.uses,
+            Err(_) => return Vec::new(),
+        };
+        let branches: Vec<OneSided> = one_sided(&uses, input.len())
+            .into_iter()
+            .filter(|branch| !self.solved.contains(&branch.id))
+            .take(MAX_BRANCHES)
+            .collect();
+        let mut solutions = Vec::new();
+        for branch in branches.iter() {
+            if let Some(second) = self.flip(input, branch) {
+                self.solved.insert(branch.id);
+                solutions.push(InputPair {
+                    first: input.clone(),
+                    second,
+                    is_str: individual.is_str,
+                });
+            }
+        }
+        solutions
+    }
+
+    // How many branches have been flipped so far
+    pub fn solved(&self) -> usize {
+        self.solved.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taint::{Taint, Use};
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn one_sided_test() {
+        let mut taint = Taint::byte(1);
+        taint.add(&Taint::byte(9));
+        let used = |outcomes: &[u32], taint: &Taint| TaintedUse {
+            used_as: Use::Branch,
+            count: 1,
+            taint: taint.clone(),
+            outcomes: outcomes.iter().cloned().collect::<BTreeSet<u32>>(),
+        };
+        let mut uses = BTreeMap::new();
+        uses.insert(2, used(&[1], &taint));
+        uses.insert(5, used(&[0, 1], &taint));
+        uses.insert(7, used(&[0], &Taint::byte(12)));
+
+        // Branch 5 already went both ways, and branch 7 depends on a byte past the end of the input
+        let branches = one_sided(&uses, 10);
+        assert_eq!(
+            branches,
+            vec![OneSided {
+                id: 2,
+                outcome: 1,
+                bytes: vec![1, 9]
+            }]
+        );
+        assert!(!flipped(&uses, &branches[0]));
+        uses.insert(2, used(&[0], &taint));
+        assert!(flipped(&uses, &branches[0]));
+        uses.remove(&2);
+        assert!(!flipped(&uses, &branches[0]));
+    }
+}
@@ -0,0 +1,424 @@
+// This file contains the "worker" subcommand, and the coordinator side of "serve", which fuzz one target on several
+// machines
+//
+// The coordinator is an ordinary fuzzing run that also accepts workers over TCP: it owns the population, the
+// statistics and the results, and workers only count instructions. Each message is a 4-byte big-endian length
+// followed by that many bytes of JSON. A worker says hello with the sidefuzz version it runs, a fingerprint of
+// its target and the coordinator's token if it has one, and is refused unless all of them match the coordinator's,
+// since counts from another target would be meaningless. Anyone with the target can compute its fingerprint, so
+// coordinators only accept workers on the loopback interface unless told otherwise, and the token is what keeps
+// other hosts from feeding them counts. The token is checked first, and hellos are kept short, so until a worker
+// gives it, it learns nothing about the coordinator and can't make it allocate much. It is then sent chunks of the
+// population as hexadecimal inputs, and answers each with their instruction counts. Chunks a worker disconnects in the middle of are scored by another worker instead.
+
+use crate::errors::SideFuzzError;
+use crate::scores::Scorer;
+use crate::target::{Target, TargetOptions};
+use crate::util::scored_from_counts;
+use crate::workers::{next_job, Outcome, Queue};
+use serde::{Deserialize, Serialize};
+use std::io::{Error as IOError, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Messages longer than this are refused, rather than allocated
+const MAX_MESSAGE: usize = 64 << 20;
+
+// Hellos are read before the worker is known to have the token, so they are kept short, and only this many
+// connections may be waiting to say hello at once
+const MAX_HELLO: usize = 4 << 10;
+const MAX_UNAUTHENTICATED: usize = 16;
+
+// How long a worker may take to say hello, and to score a chunk, before it is considered gone
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+const WORK_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    // Worker to coordinator, once connected
+    Hello {
+        version: String,
+        fingerprint: String,
+        token: Option<String>,
+    },
+    // Coordinator to worker, instead of work
+    Reject {
+        reason: String,
+    },
+    // Coordinator to worker: the first and second input of each pair in turn
+    Work {
+        id: usize,
+        inputs: Vec<String>,
+    },
+    // Worker to coordinator: the count of each input of the work, None if it crashed
+    Counts {
+        id: usize,
+        counts: Vec<Option<u64>>,
+    },
+    // Coordinator to worker, once the run is over
+    Done,
+}
+
+pub struct Worker {
+    module: Box<dyn Target>,
+    token: Option<String>,
+}
+
+impl Worker {
+    pub fn from_file_with_options(
+        filename: &str,
+        options: &TargetOptions,
+    ) -> Result<Self, SideFuzzError> {
+        let module = crate::target::load(filename, options)?;
+        Ok(Worker {
+            module,
+            token: None,
+        })
+    }
+
+    // Give the coordinator this token, for coordinators started with one
+    pub fn set_token(&mut self, token: String) {
+        self.token = Some(token);
+    }
+
+    // Score inputs for the coordinator at `addr`, such as "fuzz-1:7878", until its run is over
+    pub fn run(&mut self, addr: &str) -> Result<(), SideFuzzError> {
+        let mut stream = TcpStream::connect(addr).map_err(SideFuzzError::CoordinatorConnection)?;
+        let _ = stream.set_nodelay(true);
+        let hello = Message::Hello {
+            version: crate::VERSION.to_string(),
+            fingerprint: fingerprint(self.module.as_ref()),
+            token: self.token.clone(),
+        };
+        write_message(&mut stream, &hello).map_err(SideFuzzError::CoordinatorConnection)?;
+        println!("Connected to {}, scoring inputs", addr);
+
+        let mut scored = 0;
+        loop {
+            let message = match read_message(&mut stream, MAX_MESSAGE) {
+                Ok(message) => message,
+                // The coordinator may exit before telling its workers it is done
+                Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(SideFuzzError::CoordinatorConnection(err)),
+            };
+            match message {
+                Message::Work { id, inputs } => {
+                    let inputs = inputs
+                        .iter()
+                        .map(hex::decode)
+                        .collect::<Result<Vec<Vec<u8>>, _>>()
+                        .map_err(|_| {
+                            SideFuzzError::BadWorkerMessage("inputs aren't hexadecimal".to_string())
+                        })?;
+                    let refs: Vec<&[u8]> = inputs.iter().map(Vec::as_slice).collect();
+                    let counts = self
+                        .module
+                        .count_batch(&refs)
+                        .into_iter()
+                        .map(|count| count.ok())
+                        .collect();
+                    write_message(&mut stream, &Message::Counts { id, counts })
+                        .map_err(SideFuzzError::CoordinatorConnection)?;
+                    scored += inputs.len();
+                }
+                Message::Reject { reason } => return Err(SideFuzzError::WorkerRejected(reason)),
+                Message::Done => break,
+                _ => {
+                    return Err(SideFuzzError::BadWorkerMessage(
+                        "expected work from the coordinator".to_string(),
+                    ))
+                }
+            }
+        }
+
+        println!(
+            "The coordinator's run is over, this worker scored {} inputs",
+            scored
+        );
+        Ok(())
+    }
+}
+
+// Accept workers on `addr` that give `token`, giving them chunks from `queue` and sending their scores to `results`.
+// `remote` is kept to the number of workers connected.
+pub(crate) fn serve(
+    addr: &str,
+    token: Option<String>,
+    target: &dyn Target,
+    scorer: Scorer,
+    queue: Queue,
+    results: Sender<Outcome>,
+    remote: Arc<AtomicUsize>,
+) -> Result<(), SideFuzzError> {
+    let listener = TcpListener::bind(addr).map_err(SideFuzzError::CouldNotServeWorkers)?;
+    let fingerprint = fingerprint(target);
+    let unauthenticated = Arc::new(AtomicUsize::new(0));
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            // A worker that fails to connect can try again, as can one turned away while too many are saying hello
+            if let Ok(stream) = stream {
+                if unauthenticated.fetch_add(1, Ordering::SeqCst) >= MAX_UNAUTHENTICATED {
+                    unauthenticated.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+                let unauthenticated = unauthenticated.clone();
+                let queue = queue.clone();
+                let results = results.clone();
+                let remote = remote.clone();
+                let fingerprint = fingerprint.clone();
+                let token = token.clone();
+                // Scores that need traces run them here, the worker only counts instructions
+                let mut scorer = match scorer.try_clone() {
+                    Ok(scorer) => scorer,
+                    Err(_) => {
+                        unauthenticated.fetch_sub(1, Ordering::SeqCst);
+                        continue;
+                    }
+                };
+                std::thread::spawn(move || {
+                    let _ = coordinate(
+                        stream,
+                        &fingerprint,
+                        token.as_deref(),
+                        &unauthenticated,
+                        &queue,
+                        &results,
+                        &remote,
+                        &mut scorer,
+                    );
+                });
+            }
+        }
+    });
+    Ok(())
+}
+
+// Talk to one worker until it disconnects or the run is over. It counts as `unauthenticated` until it said hello.
+#[allow(clippy::too_many_arguments)]
+fn coordinate(
+    mut stream: TcpStream,
+    fingerprint: &str,
+    token: Option<&str>,
+    unauthenticated: &AtomicUsize,
+    queue: &Queue,
+    results: &Sender<Outcome>,
+    remote: &AtomicUsize,
+    scorer: &mut Scorer,
+) -> std::io::Result<()> {
+    let hello = stream
+        .set_read_timeout(Some(HELLO_TIMEOUT))
+        .and_then(|_| read_message(&mut stream, MAX_HELLO));
+    unauthenticated.fetch_sub(1, Ordering::SeqCst);
+    if let Err(reason) = check_hello(&hello?, fingerprint, token) {
+        return write_message(&mut stream, &Message::Reject { reason });
+    }
+
+    stream.set_read_timeout(Some(WORK_TIMEOUT))?;
+    let _ = stream.set_nodelay(true);
+    remote.fetch_add(1, Ordering::SeqCst);
+    let scored = score_remotely(&mut stream, queue, results, scorer);
+    remote.fetch_sub(1, Ordering::SeqCst);
+    scored
+}
+
+// Why a worker saying `hello` is refused, if it is. The token is checked first, so workers without it learn
+// nothing about the coordinator's version or target.
+fn check_hello(hello: &Message, fingerprint: &str, token: Option<&str>) -> Result<(), String> {
+    match hello {
+        Message::Hello { token: theirs, .. } if !same_token(theirs.as_deref(), token) => {
+            Err("this worker didn't give the coordinator's token".to_string())
+        }
+        Message::Hello { version, .. } if version != crate::VERSION => Err(format!(
+            "the coordinator runs sidefuzz {}, but this worker runs sidefuzz {}",
+            crate::VERSION,
+            version
+        )),
+        Message::Hello {
+            fingerprint: theirs,
+            ..
+        } if theirs != fingerprint => {
+            Err("this worker loaded a different target than the one being fuzzed".to_string())
+        }
+        Message::Hello { .. } => Ok(()),
+        _ => Err("expected a hello from the worker".to_string()),
+    }
+}
+
+fn score_remotely(
+    stream: &mut TcpStream,
+    queue: &Queue,
+    results: &Sender<Outcome>,
+    scorer: &mut Scorer,
+) -> std::io::Result<()> {
+    while let Some((index, chunk)) = next_job(queue) {
+        let inputs = chunk
+            .iter()
+            .flat_map(|pair| vec![hex::encode(&pair.first), hex::encode(&pair.second)])
+            .collect();
+        let reply = write_message(stream, &Message::Work { id: index, inputs })
+            .and_then(|_| read_message(stream, MAX_MESSAGE));
+        match reply {
+            Ok(Message::Counts { id, counts })
+                if id == index && counts.len() == chunk.len() * 2 =>
+            {
+                let scored = scored_from_counts(chunk, &counts, scorer);
+                if results.send((index, Ok(scored))).is_err() {
+                    return Ok(());
+                }
+            }
+            reply => {
+                // Another worker scores the chunk instead
+                let _ = results.send((index, Err(chunk)));
+                return match reply {
+                    Err(err) => Err(err),
+                    Ok(_) => Err(bad_message("expected the counts of the inputs sent")),
+                };
+            }
+        }
+    }
+    write_message(stream, &Message::Done)
+}
+
+// Whether a worker gave the coordinator's token. The comparison takes as long wherever the tokens first differ.
+fn same_token(theirs: Option<&str>, ours: Option<&str>) -> bool {
+    match (theirs, ours) {
+        (_, None) => true,
+        (Some(theirs), Some(ours)) if theirs.len() == ours.len() => {
+            theirs
+                .bytes()
+                .zip(ours.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+        }
+        _ => false,
+    }
+}
+
+// Whether workers on other hosts can connect to `addr`, as opposed to only ones on the loopback interface
+pub(crate) fn is_exposed(addr: &str) -> bool {
+    match addr.to_socket_addrs() {
+        Ok(mut addrs) => addrs.any(|addr| !addr.ip().is_loopback()),
+        Err(_) => true,
+    }
+}
+
+// Identifies the target, so workers fuzzing something else can be refused. This is FNV-1a over the module, the entry
+// point fuzzed and the shape of its input, which unlike `DefaultHasher` is the same on every machine.
+fn fingerprint(target: &dyn Target) -> String {
+    let (module, entry) = match target.wasm() {
+        Some((bytes, options)) => (bytes, options.entry),
+        None => (Vec::new(), None),
+    };
+    let shape = format!(
+        "{:?} {} {} {:?}",
+        target.input_segments(),
+        target.input_is_str(),
+        target.variable_len(),
+        entry
+    );
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in shape.as_bytes().iter().chain(module.iter()) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+fn write_message<W: Write>(stream: &mut W, message: &Message) -> std::io::Result<()> {
+    let json = serde_json::to_vec(message)?;
+    stream.write_all(&(json.len() as u32).to_be_bytes())?;
+    stream.write_all(&json)?;
+    stream.flush()
+}
+
+// Read a message of at most `max` bytes
+fn read_message<R: Read>(stream: &mut R, max: usize) -> std::io::Result<Message> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max {
+        return Err(bad_message("message too long"));
+    }
+    let mut json = vec![0; len];
+    stream.read_exact(&mut json)?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+fn bad_message(reason: &str) -> IOError {
+    IOError::new(ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_test() {
+        let work = Message::Work {
+            id: 3,
+            inputs: vec!["00ff".to_string(), "ff00".to_string()],
+        };
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &work).unwrap();
+        write_message(&mut buffer, &Message::Done).unwrap();
+        let len = serde_json::to_vec(&work).unwrap().len() as u32;
+        assert_eq!(&buffer[..4], &len.to_be_bytes());
+        assert!(std::str::from_utf8(&buffer[4..])
+            .unwrap()
+            .starts_with("{\"type\":\"work\""));
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_message(&mut reader, MAX_MESSAGE).unwrap(), work);
+        assert_eq!(read_message(&mut reader, MAX_MESSAGE).unwrap(), Message::Done);
+        assert_eq!(
+            read_message(&mut reader, MAX_MESSAGE).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+
+        // Messages over the limit aren't read
+        let mut reader = buffer.as_slice();
+        assert_eq!(
+            read_message(&mut reader, 8).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn check_hello_test() {
+        let hello = |version: &str, fingerprint: &str, token: Option<&str>| Message::Hello {
+            version: version.to_string(),
+            fingerprint: fingerprint.to_string(),
+            token: token.map(str::to_string),
+        };
+        let token = Some("s3cret");
+        assert_eq!(check_hello(&hello(crate::VERSION, "ab", token), "ab", token), Ok(()));
+
+        // Without the token, nothing else is revealed
+        let reason = check_hello(&hello("0.0.1", "cd", None), "ab", token).unwrap_err();
+        assert_eq!(reason, "this worker didn't give the coordinator's token");
+
+        let reason = check_hello(&hello("0.0.1", "ab", token), "ab", token).unwrap_err();
+        assert!(reason.contains(crate::VERSION));
+        assert!(check_hello(&hello(crate::VERSION, "cd", token), "ab", token).is_err());
+        assert!(check_hello(&Message::Done, "ab", token).is_err());
+    }
+
+    #[test]
+    fn token_test() {
+        assert!(same_token(None, None));
+        assert!(same_token(Some("anything"), None));
+        assert!(same_token(Some("s3cret"), Some("s3cret")));
+        assert!(!same_token(Some("s3cres"), Some("s3cret")));
+        assert!(!same_token(Some("s3cret!"), Some("s3cret")));
+        assert!(!same_token(None, Some("s3cret")));
+
+        assert!(!is_exposed("127.0.0.1:7878"));
+        assert!(!is_exposed("[::1]:7878"));
+        assert!(is_exposed("0.0.0.0:7878"));
+        assert!(is_exposed("192.168.1.20:7878"));
+    }
+}
@@ -1,8 +1,82 @@
 // Contains an implementation of dudect
 
 use crate::errors::SideFuzzError;
-use crate::wasm::WasmModule;
+use crate::exploitability::DEFAULT_CONFIDENCE;
+use crate::noise::{Noise, NoiseModel};
+use crate::nonparametric::Histogram;
+use crate::outliers::Filter;
+use crate::report::{Effect, Evidence, Rejected};
+use crate::target::Target;
+use crate::template::Template;
+use crate::util::{public_ranges, random_input, share_public};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use rolling_stats::Stats;
+use std::borrow::Cow;
+use std::ops::Range;
+use std::str::FromStr;
+
+// At most this many samples of each class are kept when recording
+const MAX_RECORDED_SAMPLES: usize = 100_000;
+
+// Fixed vs random gives up once more than half of the random inputs were rejected, after at least this many
+const MIN_RANDOM_INPUTS: u64 = 100;
+
+// Second-order statistics are only collected once the mean of a class has settled, after this many samples
+const SECOND_ORDER_WARMUP: usize = 10_000;
+
+// Error rates of the sequential test: reporting a leak in a constant time target about as rarely as a t-value of 4.5,
+// and missing a leak at least as large as the minimum effect size one time in a hundred
+const SEQUENTIAL_ALPHA: f64 = 0.00001;
+const SEQUENTIAL_BETA: f64 = 0.01;
+
+// The statistical test used to compare the two classes of samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Test {
+    T,           // Welch's t-test, which assumes roughly normal distributions
+    MannWhitney, // Mann-Whitney U test, for whether one class tends to be larger than the other
+    Ks,          // Kolmogorov-Smirnov test, for any difference in the shape of the distributions
+}
+
+impl Default for Test {
+    fn default() -> Self {
+        Test::T
+    }
+}
+
+impl FromStr for Test {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "t" => Ok(Test::T),
+            "mann-whitney" => Ok(Test::MannWhitney),
+            "ks" => Ok(Test::Ks),
+            _ => Err(format!("unknown test '{}'", s)),
+        }
+    }
+}
+
+// How the two classes of samples are compared
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Analysis {
+    // The nonparametric tests report a z-score, which is compared against the same thresholds as the t-value
+    pub test: Test,
+    // Also compare the squared distance of each sample from its class's mean.
+    // This finds leaks in masked implementations, where the mean doesn't depend on the secret but the variance does.
+    pub second_order: bool,
+    // Decide with Wald's sequential probability ratio test instead of fixed thresholds, stopping as soon as the
+    // evidence for either verdict is strong. The value is the smallest leak worth finding, in standard deviations.
+    pub sequential: Option<f64>,
+    // Add simulated measurement noise to every count before it is analyzed, to see how many measurements an
+    // attacker timing the target for real would need
+    pub noise: Option<NoiseModel>,
+    // The confidence, in percent, at which to estimate how many measurements an attacker needs. 99 if not set.
+    pub attacker_confidence: Option<f64>,
+    // Discard this many samples of each class before analyzing any, while the target warms up
+    pub warmup: usize,
+    // Reject samples further than this many median absolute deviations from the median of their class
+    pub outliers: Option<f64>,
+}
 
 #[derive(Eq, PartialEq, Debug)]
 pub enum DudeResult {
@@ -17,11 +91,29 @@ pub struct DudeCT<'a> {
     fail_min_samples: usize,
     first: &'a [u8],
     second: &'a [u8],
-    module: WasmModule,
+    module: Box<dyn Target>,
+    random: Option<StdRng>, // When set, the second class is fresh random inputs instead of `second`
+    random_inputs: u64,     // Random inputs run, and how many of them the target rejected
+    random_rejected: u64,
+    public: Vec<Range<usize>>, // Randomized for every sample, and the same in both classes
+    public_rng: StdRng,
+    classes: Option<(Template, Template)>, // When set, each sample is fresh inputs matching these
     first_stats: Stats<f64>,
     second_stats: Stats<f64>,
     first_stats_count: usize,
     second_stats_count: usize,
+    t_history: Vec<(usize, f64)>,
+    recorded: Option<(Vec<f64>, Vec<f64>)>,
+    analysis: Analysis,
+    first_centered: Stats<f64>, // Squared distances from the mean, for the second-order test
+    second_centered: Stats<f64>,
+    first_centered_count: usize,
+    second_centered_count: usize,
+    histogram: Histogram, // Only kept for the nonparametric tests
+    noise: Option<Noise>,
+    noise_seed: u64,
+    first_filter: Filter,
+    second_filter: Filter,
 }
 
 impl<'a> DudeCT<'a> {
@@ -31,11 +123,12 @@ impl<'a> DudeCT<'a> {
         fail_min_samples: usize,
         first: &'a [u8],
         second: &'a [u8],
-        module: WasmModule,
+        module: Box<dyn Target>,
     ) -> Result<Self, SideFuzzError> {
         if module.fuzz_len() != first.len() || module.fuzz_len() != second.len() {
             return Err(SideFuzzError::InputsDifferentSizes);
         }
+        let public = public_ranges(&module.input_segments(), &module.public_segments());
 
         Ok(DudeCT {
             t_threshold,
@@ -44,10 +137,135 @@ impl<'a> DudeCT<'a> {
             first,
             second,
             module,
+            random: None,
+            random_inputs: 0,
+            random_rejected: 0,
+            public,
+            public_rng: StdRng::seed_from_u64(rand::random()),
+            classes: None,
             first_stats: Stats::new(),
             second_stats: Stats::new(),
             first_stats_count: 0,
             second_stats_count: 0,
+            t_history: Vec::new(),
+            recorded: None,
+            analysis: Analysis::default(),
+            first_centered: Stats::new(),
+            second_centered: Stats::new(),
+            first_centered_count: 0,
+            second_centered_count: 0,
+            histogram: Histogram::new(),
+            noise: None,
+            noise_seed: rand::random(),
+            first_filter: Filter::default(),
+            second_filter: Filter::default(),
+        })
+    }
+
+    // Compare a fixed input against random inputs (the classic dudect "fixed vs random" test),
+    // instead of against a second fixed input. Random inputs, and any simulated noise, are generated from `seed`.
+    pub fn fixed_vs_random(
+        t_threshold: f64,
+        t_fail: f64,
+        fail_min_samples: usize,
+        fixed: &'a [u8],
+        module: Box<dyn Target>,
+        seed: u64,
+    ) -> Result<Self, SideFuzzError> {
+        let mut dudect = Self::new(t_threshold, t_fail, fail_min_samples, fixed, fixed, module)?;
+        dudect.random = Some(StdRng::seed_from_u64(seed));
+        dudect.set_public_seed(seed.wrapping_add(1));
+        dudect.noise_seed = seed.wrapping_add(2);
+        Ok(dudect)
+    }
+
+    // Compare two classes of inputs instead of two inputs, drawing a random input from each template for every
+    // sample. The templates must describe inputs of the target's length.
+    pub fn set_classes(&mut self, first: Template, second: Template) -> Result<(), SideFuzzError> {
+        if first.len() != self.module.fuzz_len() || second.len() != self.module.fuzz_len() {
+            return Err(SideFuzzError::InputsWrongSize(self.module.fuzz_len()));
+        }
+        self.classes = Some((first, second));
+        Ok(())
+    }
+
+    // Set the seed the public inputs and input classes are randomized from
+    pub fn set_public_seed(&mut self, seed: u64) {
+        self.public_rng = StdRng::seed_from_u64(seed);
+    }
+
+    // How many bytes of input are public
+    pub fn public_len(&self) -> usize {
+        self.public.iter().map(|range| range.len()).sum()
+    }
+
+    pub fn set_analysis(&mut self, analysis: Analysis) {
+        let noise_seed = self.noise_seed;
+        self.noise = analysis.noise.map(|model| Noise::new(model, noise_seed));
+        self.first_filter = Filter::new(analysis.warmup, analysis.outliers);
+        self.second_filter = Filter::new(analysis.warmup, analysis.outliers);
+        self.analysis = analysis;
+    }
+
+    // Keep individual samples, so that they can be included in `evidence`
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recorded = if recording {
+            Some((Vec::new(), Vec::new()))
+        } else {
+            None
+        };
+    }
+
+    // The measurements taken so far
+    pub fn evidence(&self) -> Evidence {
+        let (samples_1, samples_2) = self.recorded.clone().unwrap_or_default();
+        Evidence {
+            t_history: self.t_history.clone(),
+            samples_1,
+            samples_2,
+            input_segments: self.module.input_segments(),
+        }
+    }
+
+    // Mean instruction counts for the first and second inputs
+    pub fn means(&self) -> (f64, f64) {
+        (self.first_stats.mean, self.second_stats.mean)
+    }
+
+    // The size of the difference between the means, with a 95% confidence interval
+    pub fn effect(&self) -> Option<Effect> {
+        if self.first_stats_count < 2 || self.second_stats_count < 2 {
+            return None;
+        }
+        let first = &self.first_stats;
+        let second = &self.second_stats;
+        let first_count = self.first_stats_count as f64;
+        let second_count = self.second_stats_count as f64;
+        let first_variance = first.std_dev * first.std_dev;
+        let second_variance = second.std_dev * second.std_dev;
+
+        let difference = first.mean - second.mean;
+        let standard_error = (first_variance / first_count + second_variance / second_count).sqrt();
+        let largest = first.mean.abs().max(second.mean.abs());
+        let pooled = (((first_count - 1.0) * first_variance + (second_count - 1.0) * second_variance)
+            / (first_count + second_count - 2.0))
+            .sqrt();
+
+        Some(Effect {
+            difference,
+            lower: difference - 1.96 * standard_error,
+            upper: difference + 1.96 * standard_error,
+            relative: if largest > 0.0 {
+                difference.abs() / largest * 100.0
+            } else {
+                0.0
+            },
+            cohens_d: if pooled > 0.0 { Some(difference / pooled) } else { None },
+            exploitability: crate::exploitability::estimate(
+                difference,
+                pooled,
+                self.analysis.attacker_confidence.unwrap_or(DEFAULT_CONFIDENCE),
+            ),
         })
     }
 
@@ -55,17 +273,31 @@ impl<'a> DudeCT<'a> {
         self.first_stats_count + self.second_stats_count
     }
 
+    // Samples taken but not analyzed, of both classes. None unless warm-up or outlier rejection was asked for.
+    pub fn rejected(&self) -> Option<Rejected> {
+        if self.analysis.warmup == 0 && self.analysis.outliers.is_none() {
+            return None;
+        }
+        let (first, second) = (self.first_filter.rejected(), self.second_filter.rejected());
+        Some(Rejected {
+            warmup: first.warmup + second.warmup,
+            outliers: first.outliers + second.outliers,
+        })
+    }
+
     pub fn sample(&mut self, num_samples: u64) -> Result<(f64, DudeResult), SideFuzzError> {
-        for _ in 0..num_samples {
-            let first_instructions = self.module.count_instructions(self.first)?;
-            let second_instructions = self.module.count_instructions(self.second)?;
-            self.first_stats.update(first_instructions as f64);
-            self.first_stats_count += 1;
-            self.second_stats.update(second_instructions as f64);
-            self.second_stats_count += 1;
+        if self.random.is_none() && self.module.batch_capacity() > 1 {
+            self.sample_batched(num_samples)?;
+        } else {
+            self.sample_each(num_samples)?;
         }
 
         let t = self.calculate_t();
+        self.t_history.push((self.len(), t));
+
+        if let Some(min_effect) = self.analysis.sequential {
+            return Ok((t, self.sequential_result(t, min_effect)));
+        }
 
         // Return results when t value is above threshold
         if t >= self.t_threshold {
@@ -80,28 +312,262 @@ impl<'a> DudeCT<'a> {
         }
     }
 
-    fn calculate_t(&self) -> f64 {
-        let first_mean = self.first_stats.mean;
-        let second_mean = self.second_stats.mean;
+    // Compare the likelihood of a leak of `min_effect` standard deviations against the likelihood of no leak at all
+    fn sequential_result(&self, t: f64, min_effect: f64) -> DudeResult {
+        // No variance and the same means, the inputs are indistinguishable
+        let z = if t.is_nan() { 0.0 } else { t };
+        let first_count = self.first_stats_count as f64;
+        let second_count = self.second_stats_count as f64;
+        // The distance (on the z scale) between no leak and a leak of `min_effect`
+        let effect = min_effect * (first_count * second_count / (first_count + second_count)).sqrt();
+        let log_likelihood_ratio = effect * z - effect * effect / 2.0;
+
+        if log_likelihood_ratio >= ((1.0 - SEQUENTIAL_BETA) / SEQUENTIAL_ALPHA).ln() {
+            DudeResult::Ok
+        } else if log_likelihood_ratio <= (SEQUENTIAL_BETA / (1.0 - SEQUENTIAL_ALPHA)).ln() {
+            DudeResult::Err
+        } else {
+            DudeResult::Progress
+        }
+    }
+
+    // The inputs of the next sample, drawn from the input classes if there are any, and with the same fresh random
+    // public inputs in both. Otherwise, the inputs are always used as they are.
+    fn next_inputs(&mut self) -> (Cow<'a, [u8]>, Cow<'a, [u8]>) {
+        let (mut first, mut second) = match &self.classes {
+            Some((first, second)) => (
+                first.random(&mut self.public_rng),
+                second.random(&mut self.public_rng),
+            ),
+            None if self.public.is_empty() => {
+                return (Cow::Borrowed(self.first), Cow::Borrowed(self.second))
+            }
+            None => (self.first.to_vec(), self.second.to_vec()),
+        };
+        if self.public.is_empty() {
+            return (Cow::Owned(first), Cow::Owned(second));
+        }
+        let is_str = self.module.input_is_str();
+        for range in self.public.iter() {
+            let bytes = random_input(&mut self.public_rng, range.len(), is_str);
+            first[range.clone()].copy_from_slice(&bytes);
+        }
+        share_public(&self.public, &first, &mut second);
+        (Cow::Owned(first), Cow::Owned(second))
+    }
+
+    // Sample one input at a time
+    fn sample_each(&mut self, num_samples: u64) -> Result<(), SideFuzzError> {
+        for _ in 0..num_samples {
+            let len = self.first.len();
+            let is_str = self.module.input_is_str();
+            let (first, second) = self.next_inputs();
+            let public = &self.public;
+            let random = self.random.as_mut().map(|rng| {
+                let mut input = random_input(rng, len, is_str);
+                share_public(public, &first, &mut input);
+                (input, rng.gen::<bool>())
+            });
+
+            match random {
+                None => {
+                    let first_instructions = self.module.count_instructions(&first)?;
+                    let second_instructions = self.module.count_instructions(&second)?;
+                    self.record_first(first_instructions as f64);
+                    self.record_second(second_instructions as f64);
+                }
+                // Measure the classes in a random order, so that neither always runs in the other's wake
+                Some((input, fixed_first)) => {
+                    if fixed_first {
+                        self.sample_fixed(&first)?;
+                    }
+                    // Random inputs the target can't handle are skipped, as long as most of them aren't
+                    self.random_inputs += 1;
+                    match self.module.count_instructions(&input) {
+                        Ok(random_instructions) => self.record_second(random_instructions as f64),
+                        Err(_) => self.random_rejected += 1,
+                    }
+                    self.check_rejected()?;
+                    if !fixed_first {
+                        self.sample_fixed(&first)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Fail if the target rejected so many random inputs that the random class is empty or unrepresentative
+    fn check_rejected(&self) -> Result<(), SideFuzzError> {
+        let most = self.random_inputs >= MIN_RANDOM_INPUTS && self.random_rejected * 2 > self.random_inputs;
+        let none = self.random_inputs as usize > self.fail_min_samples && self.random_rejected == self.random_inputs;
+        if most || none {
+            return Err(SideFuzzError::RandomInputsRejected(
+                self.random_rejected,
+                self.random_inputs,
+            ));
+        }
+        Ok(())
+    }
+
+    // Sample pairs of inputs a batch at a time, for targets that can run several inputs at once
+    fn sample_batched(&mut self, num_samples: u64) -> Result<(), SideFuzzError> {
+        let per_batch = (self.module.batch_capacity() / 2).max(1) as u64;
+        let mut sampled = 0;
+        while sampled < num_samples {
+            let count = per_batch.min(num_samples - sampled);
+            let pairs: Vec<(Cow<'a, [u8]>, Cow<'a, [u8]>)> =
+                (0..count).map(|_| self.next_inputs()).collect();
+            let inputs: Vec<&[u8]> = pairs
+                .iter()
+                .flat_map(|(first, second)| vec![first.as_ref(), second.as_ref()])
+                .collect();
+
+            let mut counts = self.module.count_batch(&inputs).into_iter();
+            while let (Some(first), Some(second)) = (counts.next(), counts.next()) {
+                let (first, second) = (first?, second?);
+                self.record_first(first as f64);
+                self.record_second(second as f64);
+            }
+            sampled += count;
+        }
+        Ok(())
+    }
+
+    fn sample_fixed(&mut self, input: &[u8]) -> Result<(), SideFuzzError> {
+        let instructions = self.module.count_instructions(input)?;
+        self.record_first(instructions as f64);
+        Ok(())
+    }
+
+    fn record_first(&mut self, sample: f64) {
+        let sample = self.add_noise(sample);
+        for sample in self.first_filter.push(sample) {
+            self.accept_first(sample);
+        }
+    }
 
-        let first_std_dev = self.first_stats.std_dev;
-        let second_std_dev = self.second_stats.std_dev;
+    fn accept_first(&mut self, sample: f64) {
+        self.first_stats.update(sample);
+        self.first_stats_count += 1;
+        if self.analysis.second_order && self.first_stats_count > SECOND_ORDER_WARMUP {
+            let centered = sample - self.first_stats.mean;
+            self.first_centered.update(centered * centered);
+            self.first_centered_count += 1;
+        }
+        if self.analysis.test != Test::T {
+            self.histogram.record_first(sample);
+        }
+        if let Some((first, _)) = &mut self.recorded {
+            if first.len() < MAX_RECORDED_SAMPLES {
+                first.push(sample);
+            }
+        }
+    }
 
-        let first_variance = first_std_dev * first_std_dev;
-        let second_variance = second_std_dev * second_std_dev;
+    fn record_second(&mut self, sample: f64) {
+        let sample = self.add_noise(sample);
+        for sample in self.second_filter.push(sample) {
+            self.accept_second(sample);
+        }
+    }
 
-        let first_sample_size = self.first_stats_count as f64;
-        let second_sample_size = self.second_stats_count as f64;
+    fn accept_second(&mut self, sample: f64) {
+        self.second_stats.update(sample);
+        self.second_stats_count += 1;
+        if self.analysis.second_order && self.second_stats_count > SECOND_ORDER_WARMUP {
+            let centered = sample - self.second_stats.mean;
+            self.second_centered.update(centered * centered);
+            self.second_centered_count += 1;
+        }
+        if self.analysis.test != Test::T {
+            self.histogram.record_second(sample);
+        }
+        if let Some((_, second)) = &mut self.recorded {
+            if second.len() < MAX_RECORDED_SAMPLES {
+                second.push(sample);
+            }
+        }
+    }
+
+    fn add_noise(&mut self, sample: f64) -> f64 {
+        match &mut self.noise {
+            Some(noise) => noise.add(sample),
+            None => sample,
+        }
+    }
 
-        let t = (first_mean - second_mean)
-            / ((first_variance / first_sample_size) + (second_variance / second_sample_size))
-                .sqrt();
+    // The largest t-value of the tests being run
+    fn calculate_t(&self) -> f64 {
+        let t = match self.analysis.test {
+            Test::T => welch_t(
+                &self.first_stats,
+                self.first_stats_count,
+                &self.second_stats,
+                self.second_stats_count,
+            ),
+            Test::MannWhitney => self.histogram.mann_whitney_z(),
+            Test::Ks => self.histogram.ks_z(),
+        };
+        if !self.analysis.second_order
+            || self.first_centered_count < 2
+            || self.second_centered_count < 2
+        {
+            return t;
+        }
 
-        t.abs()
+        let second_order_t = welch_t(
+            &self.first_centered,
+            self.first_centered_count,
+            &self.second_centered,
+            self.second_centered_count,
+        );
+        // A NaN (no variance at all) must not hide the first-order result
+        if second_order_t > t {
+            second_order_t
+        } else {
+            t
+        }
     }
 }
 
+// Welch's t-statistic for two samples
+pub(crate) fn welch_t(first: &Stats<f64>, first_count: usize, second: &Stats<f64>, second_count: usize) -> f64 {
+    let first_variance = first.std_dev * first.std_dev;
+    let second_variance = second.std_dev * second.std_dev;
+
+    let first_sample_size = first_count as f64;
+    let second_sample_size = second_count as f64;
+
+    let t = (first.mean - second.mean)
+        / ((first_variance / first_sample_size) + (second_variance / second_sample_size)).sqrt();
+
+    t.abs()
+}
+
 #[cfg(test)]
 mod tests {
-    // TODO: Create a test that uses a minimal hard-coded WAT.
+    use super::*;
+    use crate::binary::test_target;
+    use crate::wasm::WasmModule;
+
+    #[test]
+    fn rejected_random_inputs_test() {
+        // Traps unless the input is 0: i32.const 0, i32.load8_u, if, unreachable, end
+        let fuzz = [0x00, 0x41, 0x00, 0x2d, 0x00, 0x00, 0x04, 0x40, 0x00, 0x0b, 0x0b];
+        let module = WasmModule::new(test_target(&fuzz, &[])).unwrap();
+        let mut dudect = DudeCT::fixed_vs_random(4.5, 0.67, 10_000, &[0], Box::new(module), 1).unwrap();
+        match dudect.sample(1_000) {
+            Err(SideFuzzError::RandomInputsRejected(rejected, inputs)) => {
+                assert_eq!(inputs, MIN_RANDOM_INPUTS);
+                assert!(rejected * 2 > inputs);
+            }
+            _ => panic!("a target rejecting random inputs can't be measured fixed vs random"),
+        }
+
+        // Targets that always trap fail on the fixed input too
+        let module = WasmModule::new(test_target(&[0x00, 0x00, 0x0b], &[])).unwrap();
+        let mut dudect = DudeCT::fixed_vs_random(4.5, 0.67, 10_000, &[0], Box::new(module), 1).unwrap();
+        assert!(dudect.sample(1_000).is_err());
+    }
 }
@@ -2,6 +2,7 @@
 
 use failure::*;
 use std::convert::From;
+use std::fmt;
 use std::io::Error as IOError;
 use wasmi::Error as WasmError;
 
@@ -12,48 +13,372 @@ pub enum SideFuzzError {
 
     #[fail(
         display = "The input is of the wrong length for this fuzzing target. The target wants an input of {} bytes.",
-        0
+        _0
     )]
     InputsWrongSize(usize),
 
-    #[fail(display = "Could not read file: {}", 0)]
+    #[fail(
+        display = "The input is too long for this fuzzing target. The target wants an input of at most {} bytes.",
+        _0
+    )]
+    InputTooLong(usize),
+
+    #[fail(display = "The two inputs execute the same number of instructions, there is nothing to minimize.")]
+    NoDifferenceToMinimize,
+
+    #[fail(display = "Could not read file: {}", _0)]
     CouldNotReadFile(IOError),
 
-    #[fail(display = "wasm error: {}", 0)]
+    #[fail(display = "Could not write file: {}", _0)]
+    CouldNotWriteFile(IOError),
+
+    #[fail(display = "Could not serve metrics: {}", _0)]
+    CouldNotServeMetrics(IOError),
+
+    #[fail(display = "Could not accept workers: {}", _0)]
+    CouldNotServeWorkers(IOError),
+
+    #[fail(display = "Lost the connection to the coordinator: {}", _0)]
+    CoordinatorConnection(IOError),
+
+    #[fail(display = "input template could not be used: {}", _0)]
+    BadTemplate(String),
+
+    #[fail(display = "import stub could not be used: {}", _0)]
+    BadStub(String),
+
+    #[fail(display = "sidefuzz.toml could not be used: {}", _0)]
+    BadConfig(String),
+
+    #[fail(display = "mutators could not be used: {}", _0)]
+    BadMutator(String),
+
+    #[fail(display = "scores could not be used: {}", _0)]
+    BadScore(String),
+
+    #[fail(display = "dictionary could not be used: {}", _0)]
+    BadDictionary(String),
+
+    #[fail(display = "batch configuration could not be used: {}", _0)]
+    BadBatch(String),
+
+    #[fail(display = "'{}' is not a valid crate name, use letters, numbers, - and _, starting with a letter", _0)]
+    BadTargetName(String),
+
+    #[fail(display = "could not generate a harness: {}", _0)]
+    HarnessError(String),
+
+    #[fail(display = "target could not be built: {}", _0)]
+    BuildFailed(String),
+
+    #[fail(display = "{}", _0)]
+    InvalidTarget(String),
+
+    #[fail(display = "no inputs were given, pass some or generate them with --random")]
+    NoInputs,
+
+    #[fail(display = "input file could not be used: {}", _0)]
+    BadInputs(String),
+
+    #[fail(display = "the two targets can't be compared: {}", _0)]
+    TargetsDiffer(String),
+
+    #[fail(display = "checkpoint file could not be used: {}", _0)]
+    BadCheckpoint(String),
+
+    #[fail(display = "findings file could not be used: {}", _0)]
+    BadFindings(String),
+
+    #[fail(display = "session log could not be used: {}", _0)]
+    BadSession(String),
+
+    #[fail(display = "wasm error: {}", _0)]
     WasmError(WasmError),
 
-    #[fail(display = "wasm error: {}", 0)]
+    #[fail(display = "wasm error: {}", _0)]
     WasmInstantiationError(wasmi::errors::InstantiationError),
 
-    #[fail(display = "wasm module expected to have 'memory' export")]
+    #[fail(display = "wasm linker error: {}", _0)]
+    WasmLinkerError(wasmi::errors::LinkerError),
+
+    #[fail(
+        display = "execution ran out of its budget of {} instructions, the target may be stuck in an infinite loop",
+        _0
+    )]
+    InstructionBudgetExhausted(u64),
+
+    #[fail(
+        display = "execution ran for longer than the timeout of {} ms, the target may be stuck in an infinite loop",
+        _0
+    )]
+    Timeout(u64),
+
+    #[fail(display = "wasm module could not be instrumented, it may use unsupported wasm features")]
+    InstrumentationFailed,
+
+    #[fail(display = "native library error: {}", _0)]
+    NativeLibraryError(String),
+
+    #[fail(display = "the native backend is only supported on Linux, Windows and macOS")]
+    NativeUnsupported,
+
+    #[fail(display = "sidefuzz was built without the jit engine, reinstall it with `cargo install --path . --features jit`")]
+    JitUnsupported,
+
+    #[fail(display = "jit error: {}", _0)]
+    JitError(String),
+
+    #[fail(display = "coverage guidance is only supported for wasm targets")]
+    CoverageUnsupported,
+
+    #[fail(display = "directed input generation is only supported for wasm targets")]
+    DirectedUnsupported,
+
+    #[fail(display = "scores that need traces are only supported for wasm targets")]
+    ScoreUnsupported,
+
+    #[fail(display = "could not measure the native target: {}", _0)]
+    PerfCounterError(IOError),
+
+    #[fail(display = "this target can't be fuzzed with more than one worker")]
+    WorkersUnsupported,
+
+    #[fail(display = "the coordinator refused this worker: {}", _0)]
+    WorkerRejected(String),
+
+    #[fail(display = "unexpected message between coordinator and worker: {}", _0)]
+    BadWorkerMessage(String),
+
+    #[fail(display = "no target to fuzz was given")]
+    NoTarget,
+
+    #[fail(
+        display = "the target's SHA-256 is {}, not {}. It was rebuilt since the finding was produced",
+        _1, _0
+    )]
+    ModuleHashMismatch(String, String), // Expected, actual
+
+    #[fail(display = "wasm module expected to have 'memory' export, run `sidefuzz inspect` for details")]
     WasmModuleNoMemory,
 
+    #[fail(display = "wasm module's AssemblyScript input can't be used: {}", _0)]
+    WasmModuleBadManagedInput(String),
+
+    #[fail(display = "wasm component can't be fuzzed: {}", _0)]
+    WasmComponentUnsupported(String),
+
+    #[fail(display = "the TinyGo runtime didn't start: {}", _0)]
+    TinyGoStartFailed(String),
+
+    #[fail(display = "wasm module's '{}' export, which runs before anything else, failed: {}", _0, _1)]
+    StartupFailed(String, String),
+
+    #[fail(
+        display = "the target rejected {} of the {} random inputs it was given, fixed vs random can only compare inputs it accepts",
+        _0, _1
+    )]
+    RandomInputsRejected(u64, u64),
+
     #[fail(display = "wasm module exported non-memory to 'memory' export")]
     WasmModuleBadMemory,
 
-    #[fail(display = "wasm module expected to have 'input_pointer' that returns an i32")]
+    #[fail(display = "wasm features could not be used: {}", _0)]
+    BadFeature(String),
+
+    #[fail(display = "wasm module uses {}, which isn't supported yet, run `sidefuzz inspect` for details", _0)]
+    UnsupportedWasmFeature(String),
+
+    #[fail(
+        display = "the target was built with sidefuzz {}, which is newer than this sidefuzz ({}). Upgrade sidefuzz to fuzz it",
+        _0, _1
+    )]
+    IncompatibleTarget(String, String),
+
+    #[fail(display = "the targets can't be compared: {}", _0)]
+    IncomparableTargets(String),
+
+    #[fail(display = "persistent mode can't be used: {}", _0)]
+    PersistentModeUnsupported(String),
+
+    #[fail(display = "wasm module expected to have 'input_pointer' that returns an i32, run `sidefuzz inspect` for details")]
     WasmModuleNoInputPointer,
 
-    #[fail(display = "wasm module expected to have 'input_len' that returns an i32")]
+    #[fail(display = "wasm module expected to have 'input_len' that returns an i32 or i64, run `sidefuzz inspect` for details")]
     WasmModuleNoInputLen,
 
-    #[fail(display = "wasm module expected to have 'fuzz' function export")]
+    #[fail(display = "wasm module expected to have 'fuzz' function export, run `sidefuzz inspect` for details")]
     WasmModuleNoFuzz,
 
+    #[fail(display = "a key was given, but the wasm module doesn't fetch one with `sidefuzz::fetch_key`, so it has no 'key_pointer' and 'key_len' exports")]
+    WasmModuleNoKey,
+
+    #[fail(display = "the key is {} bytes, but the target fetches a {} byte key", _0, _1)]
+    KeyWrongSize(usize, i32),
+
+    #[fail(display = "the key could not be used: {}", _0)]
+    BadKey(String),
+
+    #[fail(display = "keys can only be injected into wasm targets")]
+    KeyUnsupported,
+
+    #[fail(display = "the key can't be fuzzed: {}", _0)]
+    KeyFuzzingUnsupported(String),
+
+    #[fail(display = "the message is {} bytes, but the target fetches {} bytes of input", _0, _1)]
+    MessageWrongSize(usize, usize),
+
+    #[fail(display = "wasm module has no '{}' export to fuzz, its entry points are: {}", _0, _1)]
+    NoSuchEntry(String, String),
+
     #[fail(display = "wasm module input_pointer returned bad type, i32 expected.")]
     WasmModuleBadInputPointer,
 
-    #[fail(display = "wasm module 'input_len' returned bad type, i32 expected.")]
+    #[fail(display = "wasm module 'input_len' or 'input_is_str' returned a bad type or value, a non-negative i32 or i64 expected.")]
     WasmModuleBadInpuLen,
 
-    #[fail(display = "error writing input memory to wasm: {}", 0)]
+    #[fail(
+        display = "wasm module exporting 'input_count' must also export 'input_pointer_at' and 'input_len_at' returning i32, describing inputs that add up to 'input_len'"
+    )]
+    WasmModuleBadInputSegments,
+
+    #[fail(display = "error writing input memory to wasm: {}", _0)]
     MemorySetError(WasmError),
 
     #[fail(
-        display = "requested fuzzing input length of {} is too long. 1024 bytes is the maximum.",
-        0
+        display = "wasm module exporting 'output_len' must also export 'output_pointer' returning i32, describing an output in its memory"
     )]
-    FuzzLenTooLong(u32),
+    WasmModuleBadOutput,
+
+    #[fail(display = "the target doesn't set an output, call sidefuzz::set_output from fuzz (outputs are only read with the wasm engine)")]
+    NoOutput,
+
+    #[fail(
+        display = "requested fuzzing input length of {} is too long. {} bytes is the maximum, raise it with --max-input-len.",
+        _0, _1
+    )]
+    FuzzLenTooLong(u32, usize),
+
+    #[fail(
+        display = "wasm module input does not fit in its exported memory of {} bytes",
+        _0
+    )]
+    WasmModuleInputOutOfBounds(usize),
+}
+
+// What went wrong, broadly, for callers that handle errors without matching on every variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    Target,      // The target is malformed, or failed while running
+    Harness,     // The arguments, files, configuration or machinery around the target
+    Measurement, // The target ran, but couldn't be measured
+    Analysis,    // The measurements can't be analyzed as asked
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorCategory::Target => "target error",
+            ErrorCategory::Harness => "harness error",
+            ErrorCategory::Measurement => "measurement error",
+            ErrorCategory::Analysis => "analysis error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl SideFuzzError {
+    // Every variant is listed, so new ones have to be given a category
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            SideFuzzError::InvalidTarget(_)
+            | SideFuzzError::WasmError(_)
+            | SideFuzzError::WasmInstantiationError(_)
+            | SideFuzzError::WasmLinkerError(_)
+            | SideFuzzError::InstructionBudgetExhausted(_)
+            | SideFuzzError::Timeout(_)
+            | SideFuzzError::InstrumentationFailed
+            | SideFuzzError::NativeLibraryError(_)
+            | SideFuzzError::JitError(_)
+            | SideFuzzError::WasmModuleNoMemory
+            | SideFuzzError::WasmModuleBadManagedInput(_)
+            | SideFuzzError::WasmComponentUnsupported(_)
+            | SideFuzzError::TinyGoStartFailed(_)
+            | SideFuzzError::StartupFailed(_, _)
+            | SideFuzzError::RandomInputsRejected(_, _)
+            | SideFuzzError::WasmModuleBadMemory
+            | SideFuzzError::UnsupportedWasmFeature(_)
+            | SideFuzzError::IncompatibleTarget(_, _)
+            | SideFuzzError::PersistentModeUnsupported(_)
+            | SideFuzzError::WasmModuleNoInputPointer
+            | SideFuzzError::WasmModuleNoKey
+            | SideFuzzError::WasmModuleNoInputLen
+            | SideFuzzError::WasmModuleNoFuzz
+            | SideFuzzError::NoSuchEntry(_, _)
+            | SideFuzzError::WasmModuleBadInputPointer
+            | SideFuzzError::WasmModuleBadInpuLen
+            | SideFuzzError::WasmModuleBadInputSegments
+            | SideFuzzError::MemorySetError(_)
+            | SideFuzzError::WasmModuleBadOutput
+            | SideFuzzError::NoOutput
+            | SideFuzzError::WasmModuleInputOutOfBounds(_) => ErrorCategory::Target,
+            SideFuzzError::InputsDifferentSizes
+            | SideFuzzError::InputsWrongSize(_)
+            | SideFuzzError::InputTooLong(_)
+            | SideFuzzError::ModuleHashMismatch(_, _)
+            | SideFuzzError::KeyWrongSize(_, _)
+            | SideFuzzError::BadKey(_)
+            | SideFuzzError::MessageWrongSize(_, _)
+            | SideFuzzError::CouldNotReadFile(_)
+            | SideFuzzError::CouldNotWriteFile(_)
+            | SideFuzzError::CouldNotServeMetrics(_)
+            | SideFuzzError::CouldNotServeWorkers(_)
+            | SideFuzzError::CoordinatorConnection(_)
+            | SideFuzzError::BadTemplate(_)
+            | SideFuzzError::BadStub(_)
+            | SideFuzzError::BadConfig(_)
+            | SideFuzzError::BadMutator(_)
+            | SideFuzzError::BadScore(_)
+            | SideFuzzError::BadDictionary(_)
+            | SideFuzzError::BadBatch(_)
+            | SideFuzzError::BadTargetName(_)
+            | SideFuzzError::HarnessError(_)
+            | SideFuzzError::BuildFailed(_)
+            | SideFuzzError::NoInputs
+            | SideFuzzError::BadInputs(_)
+            | SideFuzzError::BadCheckpoint(_)
+            | SideFuzzError::BadFindings(_)
+            | SideFuzzError::BadSession(_)
+            | SideFuzzError::JitUnsupported
+            | SideFuzzError::CoverageUnsupported
+            | SideFuzzError::DirectedUnsupported
+            | SideFuzzError::ScoreUnsupported
+            | SideFuzzError::KeyUnsupported
+            | SideFuzzError::KeyFuzzingUnsupported(_)
+            | SideFuzzError::WorkersUnsupported
+            | SideFuzzError::WorkerRejected(_)
+            | SideFuzzError::BadWorkerMessage(_)
+            | SideFuzzError::NoTarget
+            | SideFuzzError::BadFeature(_)
+            | SideFuzzError::FuzzLenTooLong(_, _) => ErrorCategory::Harness,
+            SideFuzzError::PerfCounterError(_)
+            | SideFuzzError::NativeUnsupported => ErrorCategory::Measurement,
+            SideFuzzError::NoDifferenceToMinimize
+            | SideFuzzError::TargetsDiffer(_)
+            | SideFuzzError::IncomparableTargets(_) => ErrorCategory::Analysis,
+        }
+    }
+
+    // Whether the target failed while running an input: it trapped, ran out of its budget or timed out.
+    // The jit reports traps and compilation errors alike, so its errors count as crashes once the target has loaded.
+    pub fn is_crash(&self) -> bool {
+        match self {
+            SideFuzzError::WasmError(_)
+            | SideFuzzError::InstructionBudgetExhausted(_)
+            | SideFuzzError::Timeout(_)
+            | SideFuzzError::JitError(_) => true,
+            _ => false,
+        }
+    }
 }
 
 impl From<IOError> for SideFuzzError {
@@ -62,6 +387,14 @@ impl From<IOError> for SideFuzzError {
     }
 }
 
+// wasmtime's errors carry their causes as context, which is kept in the message
+#[cfg(feature = "jit")]
+impl From<wasmtime::Error> for SideFuzzError {
+    fn from(error: wasmtime::Error) -> Self {
+        SideFuzzError::JitError(format!("{:#}", error))
+    }
+}
+
 impl From<WasmError> for SideFuzzError {
     fn from(error: WasmError) -> Self {
         SideFuzzError::WasmError(error)
@@ -74,3 +407,9 @@ impl From<wasmi::errors::InstantiationError> for SideFuzzError {
         SideFuzzError::WasmInstantiationError(error)
     }
 }
+
+impl From<wasmi::errors::LinkerError> for SideFuzzError {
+    fn from(error: wasmi::errors::LinkerError) -> Self {
+        SideFuzzError::WasmLinkerError(error)
+    }
+}
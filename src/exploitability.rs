@@ -0,0 +1,67 @@
+// Estimates of how exploitable a leak is, so findings can be prioritized
+//
+// The estimate is how many measurements an attacker needs to tell the two inputs apart at a given confidence, from
+// the size of the difference relative to the spread of the counts. Counts are treated as normally distributed,
+// which is what Welch's t-test assumes too. Counts that never vary give no estimate, as a single count of each input
+// shows their difference but says nothing about how it looks in timings. Simulated noise (`--noise`) gives estimates
+// for attackers who can only time the target.
+
+use crate::nonparametric::z_from_p_value;
+use crate::report::{Exploitability, Risk};
+
+// The confidence attackers are assumed to want when none is given, in percent
+pub(crate) const DEFAULT_CONFIDENCE: f64 = 99.0;
+
+// Leaks that take at most this many measurements are of each risk
+const CRITICAL_MEASUREMENTS: u64 = 1_000;
+const HIGH_MEASUREMENTS: u64 = 100_000;
+const MEDIUM_MEASUREMENTS: u64 = 10_000_000;
+
+// Estimate how exploitable a difference of `difference` is, given the pooled standard deviation of the counts
+pub(crate) fn estimate(difference: f64, pooled: f64, confidence: f64) -> Option<Exploitability> {
+    if difference == 0.0 || !difference.is_finite() || pooled <= 0.0 {
+        return None;
+    }
+    // A t-value of z takes n measurements of each input, where z = d * sqrt(n / 2)
+    let z = z_from_p_value(1.0 - confidence / 100.0);
+    let cohens_d = difference.abs() / pooled;
+    let per_input = (2.0 * (z / cohens_d).powi(2)).ceil().max(1.0) as u64;
+    let measurements = per_input.saturating_mul(2);
+    Some(Exploitability {
+        confidence,
+        measurements,
+        risk: risk(measurements),
+    })
+}
+
+fn risk(measurements: u64) -> Risk {
+    if measurements <= CRITICAL_MEASUREMENTS {
+        Risk::Critical
+    } else if measurements <= HIGH_MEASUREMENTS {
+        Risk::High
+    } else if measurements <= MEDIUM_MEASUREMENTS {
+        Risk::Medium
+    } else {
+        Risk::Low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exploitability_test() {
+        // Half a standard deviation at 95% confidence: 2 * (1.96 / 0.5)^2 = 30.7 measurements of each input
+        let half = estimate(5.0, 10.0, 95.0).unwrap();
+        assert_eq!(half.measurements, 62);
+        assert_eq!(half.risk, Risk::Critical);
+
+        let tiny = estimate(-0.1, 10.0, 99.0).unwrap();
+        assert_eq!(tiny.risk, Risk::Medium);
+        assert!(tiny.measurements > estimate(0.1, 10.0, 95.0).unwrap().measurements);
+
+        assert_eq!(estimate(3.0, 0.0, 99.0), None);
+        assert_eq!(estimate(0.0, 10.0, 99.0), None);
+    }
+}
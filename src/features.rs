@@ -0,0 +1,150 @@
+// Wasm proposals the interpreter accepts
+//
+// Newer toolchains emit instructions from finished proposals by default, so every proposal wasmi supports is
+// enabled unless turned off. Turning one off makes modules that use it fail to load, which is useful for checking
+// that a target still runs on hosts without it. The enabled set is included in reports, as it decides which
+// modules load at all.
+
+use crate::errors::SideFuzzError;
+use std::collections::BTreeSet;
+use wasmi::Config;
+
+// Every proposal that can be turned on or off
+const FEATURES: &[&str] = &[
+    "bulk-memory",
+    "multi-value",
+    "mutable-global",
+    "reference-types",
+    "saturating-float-to-int",
+    "sign-extension",
+];
+
+// Proposals wasmi doesn't implement, which can't be turned on
+const UNSUPPORTED: &[&str] = &[
+    "simd",
+    "relaxed-simd",
+    "threads",
+    "tail-call",
+    "exceptions",
+    "memory64",
+    "multi-memory",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WasmFeatures {
+    enabled: BTreeSet<&'static str>,
+}
+
+impl Default for WasmFeatures {
+    fn default() -> Self {
+        WasmFeatures {
+            enabled: FEATURES.iter().copied().collect(),
+        }
+    }
+}
+
+impl WasmFeatures {
+    // Turn proposals on or off from a comma separated list such as "-bulk-memory,+sign-extension".
+    // Names without a sign are turned on.
+    pub fn parse_arg(&mut self, arg: &str) -> Result<(), SideFuzzError> {
+        for item in arg
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            let (enable, name) = match item.chars().next() {
+                Some('-') => (false, &item[1..]),
+                Some('+') => (true, &item[1..]),
+                _ => (true, item),
+            };
+            match FEATURES.iter().copied().find(|feature| *feature == name) {
+                Some(feature) if enable => {
+                    self.enabled.insert(feature);
+                }
+                Some(feature) => {
+                    self.enabled.remove(feature);
+                }
+                // Unsupported proposals are always off already
+                None if UNSUPPORTED.contains(&name) && !enable => {}
+                None if UNSUPPORTED.contains(&name) => {
+                    return Err(SideFuzzError::BadFeature(format!(
+                        "{} isn't supported by the wasm interpreter",
+                        name
+                    )))
+                }
+                None => {
+                    return Err(SideFuzzError::BadFeature(format!(
+                        "unknown proposal '{}', expected one of {}",
+                        name,
+                        FEATURES.join(", ")
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.enabled.contains(name)
+    }
+
+    // The enabled proposals, in alphabetical order
+    pub fn enabled(&self) -> Vec<String> {
+        self.enabled.iter().map(|name| name.to_string()).collect()
+    }
+
+    // An engine configuration with exactly these proposals enabled
+    pub(crate) fn config(&self) -> Config {
+        let mut config = Config::default();
+        config
+            .wasm_bulk_memory(self.is_enabled("bulk-memory"))
+            .wasm_multi_value(self.is_enabled("multi-value"))
+            .wasm_mutable_global(self.is_enabled("mutable-global"))
+            .wasm_reference_types(self.is_enabled("reference-types"))
+            .wasm_saturating_float_to_int(self.is_enabled("saturating-float-to-int"))
+            .wasm_sign_extension(self.is_enabled("sign-extension"));
+        config
+    }
+}
+
+// The proposals a module uses: those it fails to load without. None if it doesn't load even with all of them.
+pub(crate) fn used(bytes: &[u8]) -> Option<Vec<&'static str>> {
+    let loads = |features: &WasmFeatures| {
+        wasmi::Module::new(&wasmi::Engine::new(&features.config()), bytes).is_ok()
+    };
+    if !loads(&WasmFeatures::default()) {
+        return None;
+    }
+    Some(
+        FEATURES
+            .iter()
+            .copied()
+            .filter(|feature| {
+                let mut features = WasmFeatures::default();
+                features.enabled.remove(feature);
+                !loads(&features)
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_test() {
+        let mut features = WasmFeatures::default();
+        assert!(features.is_enabled("bulk-memory"));
+
+        features.parse_arg("-bulk-memory, -simd").unwrap();
+        assert!(!features.is_enabled("bulk-memory"));
+        assert_eq!(features.enabled().len(), FEATURES.len() - 1);
+
+        features.parse_arg("bulk-memory").unwrap();
+        assert_eq!(features, WasmFeatures::default());
+
+        assert!(features.parse_arg("+simd").is_err());
+        assert!(features.parse_arg("gc").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+// Findings files, which keep the leaks fuzzing runs found so later builds of a target can be checked for them
+//
+// A findings file is JSON, with every leak found so far and whether it has been fixed. Fuzzing adds the leaks it
+// finds, and the regress subcommand marks them fixed once a build no longer leaks on them, so a leak that comes
+// back after being fixed can be told apart from one that never went away.
+
+use crate::errors::SideFuzzError;
+use crate::report::Report;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Findings {
+    pub findings: Vec<Finding>,
+}
+
+// A leak between two inputs, which are hex encoded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Finding {
+    pub input_1: String,
+    pub input_2: String,
+    pub difference: f64, // In instructions, when it was found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>, // Of the fuzzing run that found it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>, // Such as "my_target 0.1.0", if the target embeds its metadata
+    #[serde(default)]
+    pub fixed: bool,
+}
+
+impl Findings {
+    // The findings in `path`, or none if there is no such file yet
+    pub fn load(path: &str) -> Result<Self, SideFuzzError> {
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Findings::default())
+            }
+            Err(err) => return Err(SideFuzzError::CouldNotReadFile(err)),
+        };
+        serde_json::from_str(&json).map_err(|e| SideFuzzError::BadFindings(e.to_string()))
+    }
+
+    // Write the findings, replacing the file. As with checkpoints, the file is written next to the old one first.
+    pub fn save(&self, path: &str) -> Result<(), SideFuzzError> {
+        // Ok to expect, findings always serialize.
+        let json = serde_json::to_string_pretty(self).expect("Could not serialize findings");
+        let temp = format!("{}.tmp", path);
+        std::fs::write(&temp, json).map_err(SideFuzzError::CouldNotWriteFile)?;
+        std::fs::rename(&temp, path).map_err(SideFuzzError::CouldNotWriteFile)
+    }
+
+    // Add the leak in `report`, returning whether it is new. A leak found again after being fixed is reopened.
+    pub fn add(&mut self, report: &Report) -> bool {
+        let existing = self
+            .findings
+            .iter_mut()
+            .find(|finding| finding.input_1 == report.input_1 && finding.input_2 == report.input_2);
+        if let Some(finding) = existing {
+            finding.fixed = false;
+            return false;
+        }
+        self.findings.push(Finding {
+            input_1: report.input_1.clone(),
+            input_2: report.input_2.clone(),
+            difference: report.difference,
+            seed: report.seed,
+            target: report.target_meta.as_ref().map(|meta| meta.label()),
+            fixed: false,
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn findings_test() {
+        let mut findings: Findings = serde_json::from_str(
+            r#"{"findings": [{"input_1": "00", "input_2": "ff", "difference": 4.0}]}"#,
+        )
+        .unwrap();
+        assert!(!findings.findings[0].fixed);
+        findings.findings[0].fixed = true;
+
+        let json = serde_json::to_string(&findings).unwrap();
+        assert!(!json.contains("seed"));
+        let reloaded: Findings = serde_json::from_str(&json).unwrap();
+        assert_eq!(reloaded, findings);
+    }
+}
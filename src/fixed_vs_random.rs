@@ -0,0 +1,149 @@
+// This file contains the "dudect" subcommand
+
+use crate::dudect::{Analysis, DudeCT, DudeResult};
+use crate::errors::SideFuzzError;
+use crate::report::{Report, Reporter, Verdict};
+use crate::target::{Target, TargetOptions};
+use crate::util::*;
+use crate::wasm::WasmModule;
+
+pub struct FixedVsRandom {
+    module: Box<dyn Target>,
+    fixed: Vec<u8>,
+    seed: u64,
+    reporter: Reporter,
+    analysis: Analysis,
+}
+
+impl FixedVsRandom {
+    // Create a new dudect command with the given wasm module and fixed input
+    pub fn new(module: WasmModule, fixed: Vec<u8>) -> Result<Self, SideFuzzError> {
+        Self::with_target(Box::new(module), fixed)
+    }
+
+    fn with_target(module: Box<dyn Target>, fixed: Vec<u8>) -> Result<Self, SideFuzzError> {
+        if fixed.len() != module.fuzz_len() {
+            return Err(SideFuzzError::InputsWrongSize(module.fuzz_len()));
+        }
+
+        Ok(FixedVsRandom {
+            module,
+            fixed,
+            seed: rand::random(),
+            reporter: Reporter::default(),
+            analysis: Analysis::default(),
+        })
+    }
+
+    pub fn from_file(filename: &str, fixed: Vec<u8>) -> Result<Self, SideFuzzError> {
+        let module = WasmModule::from_file(filename)?;
+        Self::new(module, fixed)
+    }
+
+    pub fn from_file_with_options(
+        filename: &str,
+        options: &TargetOptions,
+        fixed: Vec<u8>,
+    ) -> Result<Self, SideFuzzError> {
+        let module = crate::target::load(filename, options)?;
+        Self::with_target(module, fixed)
+    }
+
+    // Set the seed the random inputs are generated from.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // Set how progress and the final result are reported.
+    pub fn set_reporter(&mut self, reporter: Reporter) {
+        self.reporter = reporter;
+    }
+
+    // Set which statistical tests are run on the samples.
+    pub fn set_analysis(&mut self, analysis: Analysis) {
+        self.analysis = analysis;
+    }
+
+    pub fn run(&mut self) -> Result<(), SideFuzzError> {
+        self.reporter.progress(format!("Using seed {}", self.seed));
+
+        // Same thresholds as check
+        let mut dudect = DudeCT::fixed_vs_random(
+            4.5,     // Success t-value
+            0.674,   // Give up t-value
+            100_000, // Give up min samples
+            &self.fixed,
+            self.module.try_clone()?,
+            self.seed,
+        )?;
+        dudect.set_recording(self.reporter.wants_evidence());
+        dudect.set_analysis(self.analysis);
+
+        loop {
+            let (t, result) = dudect.sample(10_000)?;
+            let p = p_value_from_t_value(t);
+
+            self.reporter.progress(format!(
+                "samples: {}, t-value: {}, confidence: {}%",
+                dudect.len(),
+                t,
+                (1.0 - p) * 100.0
+            ));
+            if let Some(rejected) = dudect.rejected() {
+                self.reporter.progress(rejected.to_string());
+            }
+
+            let (fixed_mean, random_mean) = dudect.means();
+            let mut report = Report {
+                command: "dudect",
+                verdict: Verdict::Leak,
+                input_1: hex::encode(&self.fixed),
+                input_2: "random".to_string(),
+                instructions_1: fixed_mean,
+                instructions_2: random_mean,
+                difference: (fixed_mean - random_mean).abs(),
+                t_value: t,
+                confidence: (1.0 - p) * 100.0,
+                samples: dudect.len(),
+                seed: Some(self.seed),
+                effect: dudect.effect(),
+                rejected: dudect.rejected(),
+                ..Default::default()
+            };
+
+            match result {
+                DudeResult::Ok => {
+                    let mut text = format!(
+                        "Found timing difference between the fixed input and random inputs with {}% confidence:\nfixed input: {} ({} instructions on average)\nrandom inputs: {} instructions on average",
+                        (1.0 - p) * 100.0,
+                        hex::encode(&self.fixed),
+                        fixed_mean,
+                        random_mean,
+                    );
+                    if let Some(effect) = &report.effect {
+                        text += &format!("\n{}", effect);
+                    }
+                    if let Some(noise) = self.analysis.noise {
+                        text += &format!("\nCounts include simulated {}.", noise);
+                    }
+                    if let Some(rejected) = &report.rejected {
+                        text += &format!("\n{}.", rejected);
+                    }
+                    report.evidence = dudect.evidence();
+                    self.reporter.finish(&report, &text)?;
+                    std::process::exit(0);
+                }
+                DudeResult::Err => {
+                    report.verdict = Verdict::NoLeak;
+                    let text = "No difference between the fixed input and random inputs: t-statistic small after many samples. Target is probably constant time.";
+                    report.evidence = dudect.evidence();
+                    self.reporter.finish(&report, text)?;
+                    std::process::exit(0);
+                }
+                DudeResult::Progress => {
+                    continue;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+// Instruction counting with wasmi's fuel metering
+//
+// A store only ever counts up the fuel it has consumed, and panics if more fuel is added than a u64 can hold.
+// Rather than doing that arithmetic wherever instructions are counted, `FuelMeter` gives each execution a budget
+// and counts from where it started, and reports when the store has consumed so much that it must be replaced.
+
+use wasmi::{Caller, Store};
+
+// Anything consumed fuel can be read from
+pub(crate) trait Fuel {
+    // The fuel consumed since the store was created
+    fn consumed(&self) -> u64;
+}
+
+// Anything that can also be given fuel
+pub(crate) trait Refuel: Fuel {
+    fn remaining(&mut self) -> u64;
+    fn add(&mut self, delta: u64);
+    fn consume(&mut self, delta: u64);
+}
+
+// Fuel metering is always enabled for sidefuzz's engines, so the Options and Results below can't be errors.
+
+impl<T> Fuel for Store<T> {
+    fn consumed(&self) -> u64 {
+        self.fuel_consumed().unwrap_or(0)
+    }
+}
+
+impl<T> Fuel for Caller<'_, T> {
+    fn consumed(&self) -> u64 {
+        self.fuel_consumed().unwrap_or(0)
+    }
+}
+
+impl<T> Refuel for Store<T> {
+    fn remaining(&mut self) -> u64 {
+        self.consume_fuel(0).unwrap_or(0)
+    }
+
+    fn add(&mut self, delta: u64) {
+        // Ok to expect, metering is enabled and `FuelMeter` checks for overflow first
+        self.add_fuel(delta).expect("Could not add fuel");
+    }
+
+    fn consume(&mut self, delta: u64) {
+        // Ok to expect, only fuel that is remaining is ever consumed
+        self.consume_fuel(delta).expect("Could not consume fuel");
+    }
+}
+
+// The store has consumed so much fuel that it can't be given another budget, and must be replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FuelOverflow;
+
+// Counts the instructions of one execution at a time
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct FuelMeter {
+    start: u64, // Fuel the store had consumed when the execution started
+}
+
+impl FuelMeter {
+    // Leave exactly `budget` fuel for the next execution, and start counting from zero
+    pub fn reset<F: Refuel>(&mut self, fuel: &mut F, budget: u64) -> Result<(), FuelOverflow> {
+        let consumed = fuel.consumed();
+        if consumed.checked_add(budget).is_none() {
+            return Err(FuelOverflow);
+        }
+        let remaining = fuel.remaining();
+        if remaining < budget {
+            fuel.add(budget - remaining);
+        } else {
+            // Taking fuel away counts it as consumed, so counting starts afterwards
+            fuel.consume(remaining - budget);
+        }
+        self.start = fuel.consumed();
+        Ok(())
+    }
+
+    // Instructions executed since the last reset
+    pub fn consumed<F: Fuel>(&self, fuel: &F) -> u64 {
+        fuel.consumed().saturating_sub(self.start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fuel that behaves like a store's: adding more than a u64 can hold panics
+    struct TestFuel {
+        consumed: u64,
+        remaining: u64,
+    }
+
+    impl Fuel for TestFuel {
+        fn consumed(&self) -> u64 {
+            self.consumed
+        }
+    }
+
+    impl Refuel for TestFuel {
+        fn remaining(&mut self) -> u64 {
+            self.remaining
+        }
+
+        fn add(&mut self, delta: u64) {
+            let total = self.consumed.checked_add(self.remaining);
+            assert!(total.and_then(|total| total.checked_add(delta)).is_some());
+            self.remaining += delta;
+        }
+
+        fn consume(&mut self, delta: u64) {
+            self.remaining -= delta;
+            self.consumed += delta;
+        }
+    }
+
+    #[test]
+    fn fuel_meter_test() {
+        let mut fuel = TestFuel {
+            consumed: 0,
+            remaining: 0,
+        };
+        let mut meter = FuelMeter::default();
+
+        meter.reset(&mut fuel, 1000).unwrap();
+        assert_eq!(fuel.remaining, 1000);
+        fuel.consume(300);
+        assert_eq!(meter.consumed(&fuel), 300);
+
+        // Leftover fuel is taken away rather than carried over, without counting towards the next execution
+        meter.reset(&mut fuel, 100).unwrap();
+        assert_eq!(fuel.remaining, 100);
+        assert_eq!(meter.consumed(&fuel), 0);
+
+        // A store close to overflowing can't take another budget
+        fuel.consumed = u64::MAX - 50;
+        fuel.remaining = 0;
+        assert_eq!(meter.reset(&mut fuel, 100), Err(FuelOverflow));
+        meter.reset(&mut fuel, 50).unwrap();
+        fuel.consume(50);
+        assert_eq!(meter.consumed(&fuel), 50);
+    }
+}
@@ -1,18 +1,106 @@
 // This file contains the "fuzz" subcommand
 
+use crate::api::CountOptions;
+use crate::checkpoint::{Candidate, Checkpoint, Individual};
+use crate::corpus::Corpus;
+use crate::coverage::Coverage;
+use crate::crashes::{format_crashes, Triage};
+use crate::directed::Directed;
 use crate::dudect::{DudeCT, DudeResult};
 use crate::errors::SideFuzzError;
-use crate::optimizer::Optimizer;
+use crate::findings::{Finding, Findings};
+use crate::interrupt;
+use crate::lineage::format_lineage;
+use crate::mutators::Mutators;
+use crate::optimizer::{Optimizer, Strategy};
+use crate::regress::{replay, shorten, Outcome};
+use crate::score_log::{GenerationScores, ScoreLog};
+use crate::scores::{Scorer, Scores};
+use crate::report::{Report, Reporter, Verdict};
+use crate::strings::Charset;
+use crate::template::Template;
+use crate::tui::{remaining_samples, Stats};
+use crate::target::{Target, TargetOptions};
 
 use crate::util::*;
 use crate::wasm::WasmModule;
+use crate::workers::WorkerPool;
+use std::f64::NEG_INFINITY;
+use std::time::{Duration, Instant};
+
+// With coverage guidance, the population is run for coverage once every this many generations
+const COVERAGE_INTERVAL: u64 = 10;
+
+// With directed input generation, branches are solved for once the best score hasn't improved in this many checks
+const PLATEAU_CHECKS: u32 = 3;
+
 pub struct Fuzz {
-    module: WasmModule,
+    module: Box<dyn Target>,
+    workers: usize,
+    seed: u64,
+    reporter: Reporter,
+    checkpoint: Option<String>,
+    resume: bool,
+    corpus: Option<String>,
+    template: Option<String>,
+    crashes: Option<String>,
+    hangs: Option<String>,
+    max_generations: Option<u64>,
+    max_time: Option<Duration>,
+    mutators: Mutators,
+    scores: Scores,
+    charset: Charset,
+    coverage: bool,
+    directed: bool,
+    strategy: Strategy,
+    surrogate: bool,
+    score_log: Option<String>,
+    islands: Option<(usize, u64)>,
+    restart_diversity: Option<f64>,
+    listen: Option<String>,
+    token: Option<String>, // Workers must give this to be accepted
+    reproducer: Option<(String, String, CountOptions)>, // Where to save the test, and the target it loads and how
+    findings: Option<String>,
+    archive: Option<(String, String)>, // Where to archive leaks, and the path of the target they were found in
+    watch: Option<(String, TargetOptions)>, // The target to reload when it is rebuilt, and how to load it
 }
 
 impl Fuzz {
     pub fn new(module: WasmModule) -> Self {
-        Fuzz { module }
+        Self::with_target(Box::new(module))
+    }
+
+    fn with_target(module: Box<dyn Target>) -> Self {
+        Fuzz {
+            module,
+            workers: 1,
+            seed: rand::random(),
+            reporter: Reporter::default(),
+            checkpoint: None,
+            resume: false,
+            corpus: None,
+            template: None,
+            crashes: None,
+            hangs: None,
+            max_generations: None,
+            max_time: None,
+            mutators: Mutators::default(),
+            scores: Scores::default(),
+            charset: Charset::default(),
+            coverage: false,
+            directed: false,
+            strategy: Strategy::default(),
+            surrogate: false,
+            score_log: None,
+            islands: None,
+            restart_diversity: None,
+            listen: None,
+            token: None,
+            reproducer: None,
+            findings: None,
+            archive: None,
+            watch: None,
+        }
     }
 
     pub fn from_file(filename: &str) -> Result<Self, SideFuzzError> {
@@ -20,60 +108,691 @@ impl Fuzz {
         Ok(Self::new(module))
     }
 
+    pub fn from_file_with_options(
+        filename: &str,
+        options: &TargetOptions,
+    ) -> Result<Self, SideFuzzError> {
+        let module = crate::target::load(filename, options)?;
+        Ok(Self::with_target(module))
+    }
+
+    // Set the number of worker threads used to score candidate inputs.
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = workers.max(1);
+    }
+
+    // Set the seed for all random choices made while fuzzing, making runs reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // Set how progress and the final result are reported.
+    pub fn set_reporter(&mut self, reporter: Reporter) {
+        self.reporter = reporter;
+    }
+
+    // Periodically save progress to `path`. With `resume`, continue the run previously saved there.
+    pub fn set_checkpoint(&mut self, path: String, resume: bool) {
+        self.checkpoint = Some(path);
+        self.resume = resume;
+    }
+
+    // Seed the population from the files in `dir`, and save interesting inputs found back to it.
+    pub fn set_corpus(&mut self, dir: String) {
+        self.corpus = Some(dir);
+    }
+
+    // Constrain inputs to the structure described by the template file at `path`.
+    pub fn set_template(&mut self, path: String) {
+        self.template = Some(path);
+    }
+
+    // Save an input for each distinct crash found to `dir`.
+    pub fn set_crashes(&mut self, dir: String) {
+        self.crashes = Some(dir);
+    }
+
+    // Save inputs that time out to `dir`, separately from crashes.
+    pub fn set_hangs(&mut self, dir: String) {
+        self.hangs = Some(dir);
+    }
+
+    // Set the mutation strategies the optimizer picks from.
+    pub fn set_mutators(&mut self, mutators: Mutators) {
+        self.mutators = mutators;
+    }
+
+    // Set what input pairs are scored by, the difference in instructions executed by default.
+    pub fn set_scores(&mut self, scores: Scores) {
+        self.scores = scores;
+    }
+
+    // Set the characters string inputs may contain. Only used for targets with string inputs.
+    pub fn set_charset(&mut self, charset: Charset) {
+        self.charset = charset;
+    }
+
+    // Keep input pairs that reach new parts of the target, and feed them back into the population.
+    // Only supported for wasm targets.
+    pub fn set_coverage(&mut self, coverage: bool) {
+        self.coverage = coverage;
+    }
+
+    // When the optimizer stops improving, search for inputs taking secret-dependent branches it took only one way,
+    // and feed them back into the population. Only supported for wasm targets.
+    pub fn set_directed(&mut self, directed: bool) {
+        self.directed = directed;
+    }
+
+    // Set how the optimizer searches for input pairs.
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+    }
+
+    // Only run the children a surrogate model of the scores predicts to be fittest, to run slow targets less often.
+    // Only used by the genetic optimizer.
+    pub fn set_surrogate(&mut self, surrogate: bool) {
+        self.surrogate = surrogate;
+    }
+
+    // Write the best and average score of every generation to a CSV file at `path`.
+    pub fn set_score_log(&mut self, path: String) {
+        self.score_log = Some(path);
+    }
+
+    // Evolve `islands` separate subpopulations, exchanging their best individuals every `migration_interval`
+    // generations.
+    pub fn set_islands(&mut self, islands: usize, migration_interval: u64) {
+        self.islands = Some((islands, migration_interval));
+    }
+
+    // Partially restart the population once the mean fraction of bits that differ between individuals falls below
+    // `diversity`. 0 never restarts.
+    pub fn set_restart_diversity(&mut self, diversity: f64) {
+        self.restart_diversity = Some(diversity);
+    }
+
+    // Give up after roughly `max_generations` generations, with an inconclusive result.
+    // Generations are run in batches of 500, so up to 499 more may run.
+    pub fn set_max_generations(&mut self, max_generations: u64) {
+        self.max_generations = Some(max_generations);
+    }
+
+    // Give up once fuzzing has run for `max_time`, with an inconclusive result reporting the best pair so far.
+    // A leak being confirmed when time runs out is reported that way too.
+    pub fn set_max_time(&mut self, max_time: Duration) {
+        self.max_time = Some(max_time);
+    }
+
+    // Also score inputs on workers that connect to `addr` from other machines, with `sidefuzz worker`.
+    pub fn set_listen(&mut self, addr: String) {
+        self.listen = Some(addr);
+    }
+
+    // Only accept workers that give this token, with `Worker::set_token`
+    pub fn set_token(&mut self, token: String) {
+        self.token = Some(token);
+    }
+
+    // Save a Rust test reproducing the leak to `path` if one is found. The test loads the target from `wasm` and
+    // runs it with `options`.
+    pub fn set_reproducer(&mut self, path: String, wasm: String, options: CountOptions) {
+        self.reproducer = Some((path, wasm, options));
+    }
+
+    // Record a leak in the findings file at `path` if one is found, for the regress subcommand.
+    pub fn set_findings(&mut self, path: String) {
+        self.findings = Some(path);
+    }
+
+    // Archive a leak under `dir` if one is found, hashing the target at `target` to name it.
+    pub fn set_archive(&mut self, dir: String, target: String) {
+        self.archive = Some((dir, target));
+    }
+
+    // Keep fuzzing the target at `target` as it is rebuilt, loading it again with `options` every time it changes.
+    pub fn set_watch(&mut self, target: String, options: TargetOptions) {
+        self.watch = Some((target, options));
+    }
+
     pub fn run(&mut self) -> Result<(), SideFuzzError> {
-        // Grab a copy of module bytes, we will pass this into DudeCT later
-        let module_bytes = self.module.bytes();
+        interrupt::install();
+        if let Some((target, options)) = self.watch.take() {
+            return self.watch(&target, &options);
+        }
+        let report = self.search()?;
+        self.check_order(&report)?;
+        let text = self.describe(&report)?;
+        self.reporter.finish(&report, &text)?;
+        std::process::exit(0);
+    }
+
+    // Warn about a leak whose inputs' counts depend on the order they run in, as it may not be real
+    fn check_order(&mut self, report: &Report) -> Result<(), SideFuzzError> {
+        if report.verdict != Verdict::Leak {
+            return Ok(());
+        }
+        // Ok to unwrap, the inputs were hex encoded by search.
+        let first = hex::decode(&report.input_1).unwrap();
+        let second = hex::decode(&report.input_2).unwrap();
+        if let Some(warning) = crate::order::check(self.module.as_ref(), &first, &second)? {
+            self.reporter.add_warnings(vec![warning]);
+        }
+        Ok(())
+    }
+
+    // The text of `report`, saving its leak everywhere it was asked to be saved
+    fn describe(&self, report: &Report) -> Result<String, SideFuzzError> {
+        let mut text = match (report.verdict, &report.checkpoint) {
+            (Verdict::Leak, _) => format!(
+                "Found timing difference of {} instructions between these two inputs with {}% confidence:\ninput 1: {}\ninput 2: {}",
+                report.difference, report.confidence, report.input_1, report.input_2
+            ),
+            (_, Some(checkpoint)) => format!(
+                "Interrupted after {} generations, the best timing difference so far is {} instructions between these two inputs:\ninput 1: {}\ninput 2: {}\nSaved a checkpoint to {}, continue with --checkpoint-file {} --resume",
+                report.generations.unwrap_or(0), report.difference, report.input_1, report.input_2, checkpoint, checkpoint
+            ),
+            _ if report.difference > 0.0 => format!(
+                "Gave up after {} generations without confirming a timing difference, the best so far is {} instructions between these two inputs:\ninput 1: {}\ninput 2: {}",
+                report.generations.unwrap_or(0), report.difference, report.input_1, report.input_2
+            ),
+            _ => format!(
+                "Gave up after {} generations without finding a timing difference.",
+                report.generations.unwrap_or(0)
+            ),
+        };
+        if let Some(effect) = &report.effect {
+            text += &format!("\n{}", effect);
+        }
+        if report.verdict == Verdict::Leak && !report.lineage.is_empty() {
+            text += "\n\nHow the inputs were bred:\n";
+            text += &format_lineage(&report.lineage);
+        }
+        if let (Verdict::Leak, Some((path, wasm, options))) = (report.verdict, &self.reproducer) {
+            // Ok to unwrap, the inputs were hex encoded by search.
+            let first = hex::decode(&report.input_1).unwrap();
+            let second = hex::decode(&report.input_2).unwrap();
+            crate::reproducer::save(path, wasm, options, &first, &second)?;
+            text += &format!("\nSaved a test that fails until the leak is fixed to {}", path);
+        }
+        if let (Verdict::Leak, Some(path)) = (report.verdict, &self.findings) {
+            let mut findings = Findings::load(path)?;
+            if findings.add(&report) {
+                text += &format!("\nRecorded the leak in {}", path);
+            } else {
+                text += &format!("\nThe leak was already recorded in {}", path);
+            }
+            findings.save(path)?;
+        }
+        if let (Verdict::Leak, Some((dir, target))) = (report.verdict, &self.archive) {
+            let module = std::fs::read(target).map_err(SideFuzzError::CouldNotReadFile)?;
+            // Ok to unwrap, the inputs were hex encoded by search.
+            let first = hex::decode(&report.input_1).unwrap();
+            let second = hex::decode(&report.input_2).unwrap();
+            let path = crate::archive::save(dir, &module, &report, &first, &second)?;
+            text += &format!("\nArchived the leak in {}", path.display());
+        }
+        if !report.crashes.is_empty() {
+            text += "\n\nCrashes found while fuzzing:\n";
+            text += &format_crashes(&report.crashes);
+        }
+        Ok(text)
+    }
+
+    // Fuzz the target at `target` again every time it is rebuilt, after replaying the leaks found so far against the
+    // new build. Fuzzing only starts again once none of them leak anymore, and continues from the population of the
+    // previous build when the search was cut short by the rebuild.
+    fn watch(&mut self, target: &str, options: &TargetOptions) -> Result<(), SideFuzzError> {
+        crate::watch::start(target);
+        let mut leaks = Findings::default(); // Found since watching started
+        loop {
+            if self.replay_leaks(&leaks.findings)? == 0 {
+                let report = self.search()?;
+                if report.verdict == Verdict::Leak {
+                    leaks.add(&report);
+                }
+                self.resume = false;
+                if crate::watch::changed() {
+                    if let Some(path) = report.checkpoint {
+                        self.checkpoint = Some(path);
+                        self.resume = true;
+                    }
+                } else {
+                    self.check_order(&report)?;
+                    let text = self.describe(&report)?;
+                    self.reporter.finish(&report, &text)?;
+                    if interrupt::requested() {
+                        std::process::exit(0);
+                    }
+                }
+            }
+
+            self.reporter
+                .progress(format!("Waiting for {} to be rebuilt", target));
+            loop {
+                if !crate::watch::wait() {
+                    std::process::exit(0);
+                }
+                match crate::target::load(target, options) {
+                    Ok(module) => {
+                        self.module = module;
+                        break;
+                    }
+                    Err(err) => self
+                        .reporter
+                        .progress(format!("Could not load the rebuilt target: {}", err)),
+                }
+            }
+            if let Ok(module) = std::fs::read(target) {
+                self.reporter.set_module(&module);
+            }
+            self.reporter.progress(format!("Reloaded {}", target));
+
+            // The population can't be carried over to inputs of another length
+            if let (true, Some(path)) = (self.resume, &self.checkpoint) {
+                let fuzz_len = self.module.fuzz_len();
+                self.resume = Checkpoint::load(path)
+                    .map_or(false, |checkpoint| checkpoint.fuzz_len == fuzz_len);
+            }
+        }
+    }
+
+    // Run the leaks in the findings file and `found` against the target, returning how many still leak
+    fn replay_leaks(&mut self, found: &[Finding]) -> Result<usize, SideFuzzError> {
+        let mut known = match &self.findings {
+            Some(path) => Findings::load(path)?.findings,
+            None => Vec::new(),
+        };
+        for finding in found.iter() {
+            if !known
+                .iter()
+                .any(|known| known.input_1 == finding.input_1 && known.input_2 == finding.input_2)
+            {
+                known.push(finding.clone());
+            }
+        }
+        if known.is_empty() {
+            return Ok(0);
+        }
+
+        let mut leaking = 0;
+        for finding in known.iter() {
+            let decoded = (hex::decode(&finding.input_1), hex::decode(&finding.input_2));
+            let (first, second) = match decoded {
+                (Ok(first), Ok(second)) => (first, second),
+                _ => {
+                    return Err(SideFuzzError::BadFindings(format!(
+                        "{} and {} aren't hexadecimal",
+                        finding.input_1, finding.input_2
+                    )))
+                }
+            };
+            let status = match replay(self.module.as_mut(), &first, &second) {
+                Outcome::Leaks(first, second) => {
+                    leaking += 1;
+                    format!("still leaks ({} and {} instructions)", first, second)
+                }
+                Outcome::Fixed => "fixed".to_string(),
+                Outcome::Crashes => "crashes".to_string(),
+                Outcome::WrongSize => "skipped, the inputs don't fit this target".to_string(),
+            };
+            self.reporter.progress(format!(
+                "leak {} and {}: {}",
+                shorten(&finding.input_1),
+                shorten(&finding.input_2),
+                status
+            ));
+        }
+        if leaking > 0 {
+            self.reporter.progress(format!(
+                "{} of {} known leaks still leak, fuzzing again once none do",
+                leaking,
+                known.len()
+            ));
+        } else {
+            self.reporter.progress(format!(
+                "None of the {} known leaks leak anymore, fuzzing",
+                known.len()
+            ));
+        }
+        Ok(leaking)
+    }
+
+    // Fuzz until a leak is found, until the maximum number of generations has run, or until interrupted.
+    pub fn search(&mut self) -> Result<Report, SideFuzzError> {
+        let reporter = self.reporter.clone();
+
+        let resumed = match &self.checkpoint {
+            Some(path) if self.resume => Some(Checkpoint::load(path)?),
+            _ => None,
+        };
+        if let Some(checkpoint) = &resumed {
+            self.seed = checkpoint.seed;
+        }
+
+        // Print the seed so this run can be reproduced with --seed
+        reporter.progress(format!("Using seed {}", self.seed));
+        self.module.set_seed(self.seed);
+
+        // Keep a fresh copy of the target, we will clone this for DudeCT later
+        let pristine = self.module.try_clone()?;
 
         // Print approximately fuzzing duration
-        // duration = run-time * aprox-num-loops * num-generations-per-loop * population-size
-        let duration = self.module.measure_time()? * 40.0 * 500.0 * 1000.0;
-        println!("Fuzzing will take approximately {:.*}", 0, duration);
+        // duration = run-time * aprox-num-loops * num-generations-per-loop * population-size / num-workers
+        let duration =
+            self.module.measure_time()? * 40.0 * 500.0 * 1000.0 / self.workers as f64;
+        reporter.progress(format!("Fuzzing will take approximately {:.*}", 0, duration));
+
+        let mut scorer = Scorer::new(pristine.as_ref(), self.scores.clone())?;
+
+        // With more than one worker, scoring is done by the pool instead of by the optimizer itself.
+        // Workers score whole chunks of the population at once, so targets that run batches use a pool of one.
+        // Remote workers join the pool, alongside the local ones.
+        let pool = if let Some(addr) = &self.listen {
+            let mut pool = WorkerPool::new(self.module.as_ref(), self.workers, &scorer)?;
+            pool.listen(addr, self.token.clone(), self.module.as_ref())?;
+            reporter.progress(format!(
+                "Fuzzing with {} local workers, accepting more on {}",
+                self.workers, addr
+            ));
+            if self.token.is_none() && crate::distributed::is_exposed(addr) {
+                reporter.progress(format!(
+                    "Warning: workers are accepted on {} without a token, any host that can reach it can send counts",
+                    addr
+                ));
+            }
+            Some(pool)
+        } else if self.workers > 1 {
+            reporter.progress(format!("Starting {} fuzzing workers", self.workers));
+            Some(WorkerPool::new(self.module.as_ref(), self.workers, &scorer)?)
+        } else if self.module.batch_capacity() > 1 {
+            reporter.progress(format!(
+                "Running up to {} inputs per call in persistent mode",
+                self.module.batch_capacity()
+            ));
+            Some(WorkerPool::new(self.module.as_ref(), 1, &scorer)?)
+        } else {
+            None
+        };
 
         let input_is_str = self.module.input_is_str();
-        let mut optimizer = Optimizer::new(
-            self.module.fuzz_len(),
+        let segments = self.module.input_segments();
+        let public = public_ranges(&segments, &self.module.public_segments());
+        let fuzz_len = self.module.fuzz_len();
+        let variable_len = self.module.variable_len();
+        let seed = self.seed;
+        let checkpoint_path = self.checkpoint.clone();
+        let max_generations = self.max_generations;
+        let mutators = self.mutators.clone();
+        let charset = self.charset;
+        let strategy = self.strategy;
+        let surrogate = self.surrogate;
+        let islands = self.islands;
+        let restart_diversity = self.restart_diversity;
+        let template = match &self.template {
+            Some(path) => Some(Template::load(path)?),
+            None => None,
+        };
+        if let Some(template) = &template {
+            if variable_len {
+                return Err(SideFuzzError::BadTemplate(
+                    "the target has variable-length input".to_string(),
+                ));
+            }
+            if template.len() != fuzz_len {
+                return Err(SideFuzzError::BadTemplate(format!(
+                    "it describes {} bytes of input, the target has {} bytes of input",
+                    template.len(),
+                    fuzz_len
+                )));
+            }
+        }
+        let corpus = match &self.corpus {
+            Some(dir) => Some(Corpus::open(dir)?),
+            None => None,
+        };
+        let mut triage = Triage::new(
+            pristine.try_clone()?,
+            self.crashes.as_deref(),
+            self.hangs.as_deref(),
+        )?;
+        let mut coverage = if self.coverage {
+            Some(Coverage::new(pristine.as_ref())?)
+        } else {
+            None
+        };
+        let mut directed = if self.directed {
+            Some(Directed::new(pristine.as_ref(), seed)?)
+        } else {
+            None
+        };
+        let mut optimizer = Optimizer::with_seed(
+            fuzz_len,
             |first: &[u8], second: &[u8]| {
-                ScoredInputPair::generate(
-                    &mut self.module,
+                scorer.generate(
+                    self.module.as_mut(),
                     first.to_vec(),
                     second.to_vec(),
                     input_is_str,
                 )
             },
             input_is_str,
+            seed,
         );
+        optimizer.set_strategy(strategy);
+        if surrogate && strategy == Strategy::Genetic {
+            optimizer.set_surrogate();
+        }
+        optimizer.set_segments(segments);
+        optimizer.set_mutators(mutators);
+        optimizer.set_charset(charset);
+        if let Some((islands, migration_interval)) = islands {
+            optimizer.set_islands(islands, migration_interval);
+        }
+        if let Some(diversity) = restart_diversity {
+            optimizer.set_restart_diversity(diversity);
+        }
+        if variable_len {
+            reporter.progress(format!("Fuzzing inputs of up to {} bytes", fuzz_len));
+            optimizer.set_variable_len();
+        }
+        if let Some(template) = template {
+            optimizer.set_template(template);
+        }
+        if !public.is_empty() {
+            let public_len: usize = public.iter().map(|range| range.len()).sum();
+            reporter.progress(format!(
+                "{} of {} bytes of input are public, only the rest is treated as secret",
+                public_len, fuzz_len
+            ));
+            optimizer.set_public(public);
+        }
 
-        println!("Evolving candidate input pairs");
         let mut best = ScoredInputPair::default(); // defaults to score of zero.
+        let mut lineage = Vec::new(); // Of the best, while it is still in the population
         let mut moving_window = vec![0.0; 10]; // Moving window of size 10
+        let mut generations: u64 = 0;
+        let mut stalled = 0; // Checks since the best score last improved
+
+        if let Some(checkpoint) = resumed {
+            let size = optimizer.population().len();
+            optimizer.set_population(checkpoint.population(fuzz_len, input_is_str, variable_len, size)?);
+            optimizer.set_seed(checkpoint.rng_seed);
+            best = checkpoint.best(input_is_str)?;
+            generations = checkpoint.generations;
+            reporter.progress(format!(
+                "Resuming from checkpoint after {} generations",
+                generations
+            ));
+        } else if let Some(corpus) = &corpus {
+            let seeds = corpus.load(fuzz_len, input_is_str, variable_len)?;
+            reporter.progress(format!("Seeding population with {} corpus inputs", seeds.len()));
+            optimizer.seed_population(seeds);
+        }
+
+        reporter.progress("Evolving candidate input pairs");
+        let mut stats = Stats::default();
+        let mut restarts = 0;
+        let mut score_log = match &self.score_log {
+            Some(path) => Some(ScoreLog::create(path)?),
+            None => None,
+        };
+        let mut executions: u64 = 0;
+        let mut interrupted = false;
+        let deadline = self.max_time.map(|max_time| Instant::now() + max_time);
+        let out_of_time = move || deadline.map_or(false, |deadline| Instant::now() >= deadline);
         loop {
-            // Check results once every 500 genearations
-            for _ in 0..500 {
-                optimizer.step();
+            // Check results once every 500 genearations, or as soon as the run is interrupted
+            let mut ran = 0;
+            for generation in 0..500 {
+                let scored = score_population(&mut optimizer, &pool);
+                executions += scored.len() as u64 * 2;
+                if let Some(score_log) = &mut score_log {
+                    let scores = GenerationScores::new(&scored);
+                    score_log.record(generations + generation + 1, executions, &scores);
+                }
+                optimizer.step_with_scores(scored);
+
+                if let Some(coverage) = &mut coverage {
+                    if generation % COVERAGE_INTERVAL == 0 {
+                        coverage.explore(optimizer.population());
+                        optimizer.inject(coverage.finds());
+                    }
+                }
+
+                ran += 1;
+                if stopping() {
+                    interrupted = true;
+                    break;
+                }
+                if out_of_time() {
+                    break;
+                }
+            }
+            generations += ran;
+            if let Some(score_log) = &mut score_log {
+                score_log.flush();
             }
-            let population = optimizer.scored_population();
+            crate::session::flush_all();
+            if let Some(coverage) = &coverage {
+                reporter.progress(format!("{} edges covered", coverage.edges()));
+            }
+            if optimizer.restarts() > restarts {
+                restarts = optimizer.restarts();
+                reporter.progress(format!(
+                    "Diversity collapsed, {} partial restarts so far",
+                    restarts
+                ));
+            }
+            let population = score_population(&mut optimizer, &pool);
             let pop_best = population[0].clone(); // Best of this population is ordered first.
 
+            // Each generation runs both inputs of every individual
+            let executions_per_generation = optimizer.population().len() as u64 * 2;
+            stats.executions += executions_per_generation * (ran + 1);
+            stats.generations = Some(generations);
+            stats.best_score = Some(pop_best.score.max(best.score));
+            stats.diversity = Some(optimizer.diversity());
+            stats.bit_diversity = Some(optimizer.hamming_diversity());
+            stats.t_value = None;
+            stats.remaining_executions = max_generations
+                .map(|max| max.saturating_sub(generations) * executions_per_generation);
+            stats.crashes = Some(triage.summary().iter().map(|crash| crash.count as u64).sum());
+            reporter.stats(&stats);
+
             if pop_best.score != 0.0 {
-                println!(
+                reporter.progress(format!(
                     "{} {} {}",
                     pop_best.score,
                     hex::encode(&population[0].pair.first),
                     hex::encode(&population[0].pair.second)
-                );
+                ));
             } else {
-                println!("Looks constant-time so far...");
+                reporter.progress("Looks constant-time so far...");
             }
 
             // Adjust moving window
             moving_window.remove(0);
             moving_window.push(pop_best.score);
 
+            if let Some(corpus) = &corpus {
+                if pop_best.score > best.score {
+                    corpus.save("best", &pop_best.pair)?;
+                }
+                // Population is sorted, so any input pair that trapped is last
+                if let Some(crash) = population.last().filter(|s| s.score == NEG_INFINITY) {
+                    corpus.save("crash", &crash.pair)?;
+                }
+            }
+
+            // Triage the input pairs that trapped, to tell apart the different ways the target crashes
+            for crash in population.iter().rev().take_while(|s| s.score == NEG_INFINITY) {
+                for input in [&crash.pair.first, &crash.pair.second].iter() {
+                    if triage.examine(input)? {
+                        reporter.progress(format!(
+                            "Found a new kind of crash, {} so far",
+                            triage.summary().len()
+                        ));
+                    }
+                }
+            }
+
             if pop_best.score > best.score {
+                lineage = optimizer.lineage(&pop_best.pair);
                 best = pop_best;
+                stalled = 0;
+            } else {
+                stalled += 1;
+            }
+
+            // The optimizer is stuck, look for inputs sending the branches of the best individual the other way
+            if let Some(directed) = &mut directed {
+                if stalled >= PLATEAU_CHECKS && !interrupted {
+                    stalled = 0;
+                    let finds = directed.solve(&population[0].pair);
+                    if !finds.is_empty() {
+                        reporter.progress(format!(
+                            "Flipped {} secret-dependent branches, {} so far",
+                            finds.len(),
+                            directed.solved()
+                        ));
+                        optimizer.inject(finds);
+                    }
+                }
             }
 
-            if best.score != 0.0 {
+            // The generator is reseeded whether or not a checkpoint is saved, so runs with the same seed make the
+            // same choices either way
+            let rng_seed = optimizer.reseed();
+
+            // Interrupted runs always leave a checkpoint, so they can be resumed
+            let saved_to = match &checkpoint_path {
+                Some(path) => Some(path.clone()),
+                None if interrupted => Some(interrupt::DEFAULT_CHECKPOINT.to_string()),
+                None => None,
+            };
+            if let Some(path) = &saved_to {
+                let checkpoint = Checkpoint {
+                    fuzz_len,
+                    seed,
+                    rng_seed,
+                    generations,
+                    population: optimizer.population().iter().map(Individual::new).collect(),
+                    best: if best.score > 0.0 {
+                        Some(Candidate {
+                            first: hex::encode(&best.pair.first),
+                            second: hex::encode(&best.pair.second),
+                            score: best.score,
+                        })
+                    } else {
+                        None
+                    },
+                };
+                checkpoint.save(path)?;
+                log::debug!("Saved a checkpoint after {} generations to {}", generations, path);
+            }
+
+            if best.score != 0.0 && !interrupted {
                 // Check the moving window is entirely the same as the best, this means we're maxed out.
                 let mut local_optimum = true;
                 for score in moving_window.iter() {
@@ -84,11 +803,11 @@ impl Fuzz {
                 }
 
                 if local_optimum {
-                    println!(
+                    reporter.progress(format!(
                         "Checking {} {}",
                         hex::encode(&best.pair.first),
                         hex::encode(&best.pair.second)
-                    );
+                    ));
 
                     // Construct DudeCT
                     // Return success on t = 4.5 (very high confidence)
@@ -99,34 +818,60 @@ impl Fuzz {
                         100_000, // Give up min samples
                         &best.pair.first,
                         &best.pair.second,
-                        WasmModule::new(module_bytes.clone())?,
+                        pristine.try_clone()?,
                     )?;
+                    dudect.set_public_seed(seed);
+                    dudect.set_recording(reporter.wants_evidence());
+                    let executions = stats.executions;
 
                     loop {
+                        // Stop sampling, the next generation notices the interruption and saves a checkpoint
+                        if stopping() || out_of_time() {
+                            break;
+                        }
                         let (t, result) = dudect.sample(10_000)?;
                         let p = p_value_from_t_value(t);
 
-                        println!(
+                        stats.executions = executions + dudect.len() as u64;
+                        stats.t_value = Some(t);
+                        stats.remaining_executions = remaining_samples(t, dudect.len(), 4.5);
+                        reporter.stats(&stats);
+
+                        reporter.progress(format!(
                             "samples: {}, t-value: {}, confidence: {}%",
                             dudect.len(),
                             t,
                             (1.0 - p) * 100.0
-                        );
+                        ));
 
                         match result {
                             DudeResult::Ok => {
-                                println!(
-                "Found timing difference of {} instructions between these two inputs with {}% confidence:\ninput 1: {}\ninput 2: {}",
-                best.score,
-                (1.0 - p) * 100.0,
-                hex::encode(&best.pair.first),
-                hex::encode(&best.pair.second)
-              );
-                                std::process::exit(0);
+                                let (first_mean, second_mean) = dudect.means();
+                                return Ok(Report {
+                                    command: "fuzz",
+                                    verdict: Verdict::Leak,
+                                    input_1: hex::encode(&best.pair.first),
+                                    input_2: hex::encode(&best.pair.second),
+                                    instructions_1: first_mean,
+                                    instructions_2: second_mean,
+                                    difference: best.score,
+                                    t_value: t,
+                                    confidence: (1.0 - p) * 100.0,
+                                    samples: dudect.len(),
+                                    generations: Some(generations),
+                                    seed: Some(seed),
+                                    effect: dudect.effect(),
+                                    rejected: dudect.rejected(),
+                                    crashes: triage.summary(),
+                                    lineage,
+                                    evidence: dudect.evidence(),
+                                    ..Default::default()
+                                });
                             }
                             DudeResult::Err => {
                                 best = ScoredInputPair::default();
-                                println!(
+                                lineage = Vec::new();
+                                reporter.progress(
                 "Candidate input pair rejected: t-statistic small after many samples. Continuing to evolve candidate inputs."
               );
                                 break;
@@ -138,6 +883,46 @@ impl Fuzz {
                     }
                 }
             }
+
+            if interrupted
+                || out_of_time()
+                || max_generations.map_or(false, |max| generations >= max)
+            {
+                return Ok(Report {
+                    command: "fuzz",
+                    verdict: Verdict::Inconclusive,
+                    input_1: hex::encode(&best.pair.first),
+                    input_2: hex::encode(&best.pair.second),
+                    instructions_1: best.highest,
+                    instructions_2: best.lowest,
+                    difference: best.score,
+                    generations: Some(generations),
+                    seed: Some(seed),
+                    crashes: triage.summary(),
+                    lineage,
+                    checkpoint: if interrupted { saved_to } else { None },
+                    ..Default::default()
+                });
+            }
         }
     }
 }
+
+// Whether the search should stop early, because it was interrupted or the watched target was rebuilt
+fn stopping() -> bool {
+    interrupt::requested() || crate::watch::changed()
+}
+
+// Score the optimizer's population, using the worker pool if there is one
+fn score_population<T>(
+    optimizer: &mut Optimizer<T>,
+    pool: &Option<WorkerPool>,
+) -> Vec<ScoredInputPair>
+where
+    T: FnMut(&[u8], &[u8]) -> ScoredInputPair,
+{
+    match pool {
+        Some(pool) => pool.score(optimizer.population()),
+        None => optimizer.scored_population(),
+    }
+}
@@ -0,0 +1,427 @@
+// This file contains the "gen-harness" subcommand, which writes a target crate fuzzing a function of another crate
+//
+// The function is found by parsing the crate's sources, following its modules from the crate root along the given
+// path. Its arguments are cut from the fuzzing input one after the other, in the order they are declared, so only
+// arguments that can be decoded from bytes are supported: u8 slices, arrays and Vecs, integers and bools. The
+// signature doesn't say how long slices and Vecs are, they are all given the same length.
+
+use crate::errors::SideFuzzError;
+use crate::new::NewTarget;
+use quote::ToTokens;
+use std::path::{Path, PathBuf};
+use syn::{
+    Expr, FnArg, GenericArgument, GenericParam, Item, ItemFn, Lit, Pat, PathArguments, Type,
+    Visibility,
+};
+
+// How many bytes slice and Vec arguments get when no length is given
+pub const DEFAULT_SLICE_LEN: usize = 32;
+
+// The integers arguments can be, and their size in bytes. Targets are built for wasm32, where usize and isize are 4 bytes.
+const INTEGERS: &[(&str, usize)] = &[
+    ("u8", 1),
+    ("i8", 1),
+    ("u16", 2),
+    ("i16", 2),
+    ("u32", 4),
+    ("i32", 4),
+    ("u64", 8),
+    ("i64", 8),
+    ("u128", 16),
+    ("i128", 16),
+    ("usize", 4),
+    ("isize", 4),
+];
+
+// How an argument is passed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Passed {
+    Value,
+    Ref,
+    Mut,
+}
+
+// An argument that can be decoded from the fuzzing input
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Argument {
+    Slice(Passed),
+    Vec(Passed),
+    Array(usize, Passed),
+    Integer(&'static str, usize, Passed), // Little endian
+    Bool(Passed),                         // The lowest bit of a byte
+}
+
+impl Argument {
+    fn of(ty: &Type) -> Option<Self> {
+        let (passed, ty) = match ty {
+            Type::Reference(reference) if reference.mutability.is_some() => {
+                (Passed::Mut, &*reference.elem)
+            }
+            Type::Reference(reference) => (Passed::Ref, &*reference.elem),
+            ty => (Passed::Value, ty),
+        };
+        match ty {
+            Type::Slice(slice) if is_u8(&slice.elem) && passed != Passed::Value => {
+                Some(Argument::Slice(passed))
+            }
+            Type::Array(array) if is_u8(&array.elem) => match &array.len {
+                Expr::Lit(lit) => match &lit.lit {
+                    Lit::Int(len) => len
+                        .base10_parse()
+                        .ok()
+                        .map(|len| Argument::Array(len, passed)),
+                    _ => None,
+                },
+                _ => None,
+            },
+            Type::Path(path) => {
+                let segment = path.path.segments.last()?;
+                let ident = segment.ident.to_string();
+                if ident == "Vec" {
+                    let is_bytes = match &segment.arguments {
+                        PathArguments::AngleBracketed(args) => match args.args.first() {
+                            Some(GenericArgument::Type(ty)) => args.args.len() == 1 && is_u8(ty),
+                            _ => false,
+                        },
+                        _ => false,
+                    };
+                    return Some(Argument::Vec(passed)).filter(|_| is_bytes);
+                }
+                if ident == "bool" {
+                    return Some(Argument::Bool(passed));
+                }
+                INTEGERS
+                    .iter()
+                    .find(|(name, _)| *name == ident)
+                    .map(|(name, size)| Argument::Integer(*name, *size, passed))
+            }
+            _ => None,
+        }
+    }
+
+    fn len(self, slice_len: usize) -> usize {
+        match self {
+            Argument::Slice(_) | Argument::Vec(_) => slice_len,
+            Argument::Array(len, _) => len,
+            Argument::Integer(_, size, _) => size,
+            Argument::Bool(_) => 1,
+        }
+    }
+
+    // The statement decoding the argument from `input[start..end]` into `name`, and the expression passing it
+    fn decode(self, name: &str, start: usize, end: usize) -> (String, String) {
+        let bytes = format!("input[{}..{}]", start, end);
+        let (passed, value) = match self {
+            Argument::Slice(Passed::Ref) => {
+                return (format!("let {} = &{};", name, bytes), name.to_string())
+            }
+            Argument::Slice(passed) | Argument::Vec(passed) => {
+                (passed, format!("{}.to_vec()", bytes))
+            }
+            Argument::Array(len, passed) => (
+                passed,
+                format!("<[u8; {}]>::try_from(&{}).unwrap()", len, bytes),
+            ),
+            // Integers as wide as the host's usize are read at their wasm32 size
+            Argument::Integer(ty @ "usize", _, passed)
+            | Argument::Integer(ty @ "isize", _, passed) => {
+                let read = if ty == "usize" { "u32" } else { "i32" };
+                (
+                    passed,
+                    format!(
+                        "{}::from_le_bytes({}.try_into().unwrap()) as {}",
+                        read, bytes, ty
+                    ),
+                )
+            }
+            Argument::Integer(ty, _, passed) => (
+                passed,
+                format!("{}::from_le_bytes({}.try_into().unwrap())", ty, bytes),
+            ),
+            Argument::Bool(passed) => (passed, format!("input[{}] & 1 == 1", start)),
+        };
+        match passed {
+            Passed::Value => (format!("let {} = {};", name, value), name.to_string()),
+            Passed::Ref => (format!("let {} = {};", name, value), format!("&{}", name)),
+            Passed::Mut => (
+                format!("let mut {} = {};", name, value),
+                format!("&mut {}", name),
+            ),
+        }
+    }
+}
+
+fn is_u8(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path.path.is_ident("u8"),
+        _ => false,
+    }
+}
+
+fn harness_error(message: String) -> SideFuzzError {
+    SideFuzzError::HarnessError(message)
+}
+
+// Parse a source file of the crate
+fn parse(path: &Path) -> Result<Vec<Item>, SideFuzzError> {
+    let source = std::fs::read_to_string(path)?;
+    let file = syn::parse_file(&source)
+        .map_err(|err| harness_error(format!("{} could not be parsed: {}", path.display(), err)))?;
+    Ok(file.items)
+}
+
+// Where the sources of a crate start, and the name its code is used by
+fn crate_root(dir: &Path) -> Result<(String, String, PathBuf), SideFuzzError> {
+    let manifest = std::fs::read_to_string(dir.join("Cargo.toml"))?;
+    let manifest: toml::Value = toml::from_str(&manifest)
+        .map_err(|err| harness_error(format!("Cargo.toml could not be parsed: {}", err)))?;
+    let package = manifest
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(toml::Value::as_str)
+        .ok_or_else(|| harness_error("Cargo.toml has no package name".to_string()))?;
+    let lib = manifest.get("lib");
+    let name = lib
+        .and_then(|lib| lib.get("name"))
+        .and_then(toml::Value::as_str)
+        .unwrap_or(package)
+        .replace('-', "_");
+    let root = lib
+        .and_then(|lib| lib.get("path"))
+        .and_then(toml::Value::as_str)
+        .unwrap_or("src/lib.rs");
+    Ok((package.to_string(), name, dir.join(root)))
+}
+
+// Find the public function at `path`, following modules from the crate root at `root`
+fn find_function(root: &Path, path: &[&str]) -> Result<ItemFn, SideFuzzError> {
+    let (name, modules) = path
+        .split_last()
+        .ok_or_else(|| harness_error("no function was given".to_string()))?;
+    let mut items = parse(root)?;
+    let mut dir = root
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .to_path_buf();
+    for module in modules {
+        let found = items.into_iter().find_map(|item| match item {
+            Item::Mod(item) if item.ident == module => Some(item),
+            _ => None,
+        });
+        let found =
+            found.ok_or_else(|| harness_error(format!("module {} wasn't found", module)))?;
+        if !matches!(found.vis, Visibility::Public(_)) {
+            return Err(harness_error(format!("module {} isn't public", module)));
+        }
+        items = match found.content {
+            Some((_, inline)) => inline,
+            None => {
+                let file = dir.join(format!("{}.rs", module));
+                let file = if file.exists() {
+                    file
+                } else {
+                    dir.join(module).join("mod.rs")
+                };
+                parse(&file)?
+            }
+        };
+        dir = dir.join(module);
+    }
+
+    let function = items.into_iter().find_map(|item| match item {
+        Item::Fn(function) if function.sig.ident == name => Some(function),
+        _ => None,
+    });
+    let function = function.ok_or_else(|| {
+        harness_error(format!("function {} wasn't found, functions that are re-exported or in impl blocks aren't supported", path.join("::")))
+    })?;
+    if !matches!(function.vis, Visibility::Public(_)) {
+        return Err(harness_error(format!("function {} isn't public", name)));
+    }
+    let generic = function
+        .sig
+        .generics
+        .params
+        .iter()
+        .any(|param| match param {
+            GenericParam::Lifetime(_) => false,
+            _ => true,
+        });
+    if generic || function.sig.asyncness.is_some() {
+        return Err(harness_error(format!(
+            "function {} is generic or async",
+            name
+        )));
+    }
+    Ok(function)
+}
+
+// The source of a harness calling `function`, at `path` in the crate named `krate`
+fn harness_source(
+    krate: &str,
+    path: &[&str],
+    function: &ItemFn,
+    slice_len: usize,
+) -> Result<String, SideFuzzError> {
+    let mut statements = Vec::new();
+    let mut calls = Vec::new();
+    let mut described = Vec::new();
+    let mut unsupported = Vec::new();
+    let mut offset = 0;
+    for (index, arg) in function.sig.inputs.iter().enumerate() {
+        let arg = match arg {
+            FnArg::Typed(arg) => arg,
+            FnArg::Receiver(_) => {
+                return Err(harness_error("methods aren't supported".to_string()))
+            }
+        };
+        let name = match &*arg.pat {
+            Pat::Ident(ident) if ident.ident != "input" => ident.ident.to_string(),
+            _ => format!("arg{}", index),
+        };
+        let argument = match Argument::of(&arg.ty) {
+            Some(argument) => argument,
+            None => {
+                unsupported.push(format!("{}: {}", name, arg.ty.to_token_stream()));
+                continue;
+            }
+        };
+        let len = argument.len(slice_len);
+        let (statement, call) = argument.decode(&name, offset, offset + len);
+        statements.push(statement);
+        calls.push(call);
+        let unit = if len == 1 { "byte" } else { "bytes" };
+        described.push(format!("{} ({} {})", name, len, unit));
+        offset += len;
+    }
+    if !unsupported.is_empty() {
+        return Err(harness_error(format!(
+            "these arguments can't be decoded from bytes: {}",
+            unsupported.join(", ")
+        )));
+    }
+    if offset == 0 {
+        return Err(harness_error(format!(
+            "function {} has no arguments to fuzz",
+            function.sig.ident
+        )));
+    }
+
+    let mut source = format!(
+        "// Generated by `sidefuzz gen-harness` to fuzz {}::{}.\n// The fuzzing input is split between the arguments in order: {}.\n\n",
+        krate,
+        path.join("::"),
+        described.join(", ")
+    );
+    for (method, import) in [("try_from", "TryFrom"), ("try_into", "TryInto")].iter() {
+        if statements
+            .iter()
+            .any(|statement| statement.contains(method))
+        {
+            source.push_str(&format!("use std::convert::{};\n", import));
+        }
+    }
+    if source.contains("use std") {
+        source.push('\n');
+    }
+    source.push_str("fn harness(input: &[u8]) {\n");
+    for statement in statements {
+        source.push_str(&format!("    {}\n", statement));
+    }
+    source.push_str(&format!(
+        "    sidefuzz::black_box({}::{}({}));\n}}\n\nsidefuzz::sidefuzz_target!({}, harness);\n",
+        krate,
+        path.join("::"),
+        calls.join(", "),
+        offset
+    ));
+    Ok(source)
+}
+
+pub struct GenHarness {
+    crate_dir: PathBuf,
+    function: String, // Path of the function in its crate, such as "module::sign"
+    output: Option<String>,
+    slice_len: usize,
+}
+
+impl GenHarness {
+    pub fn new(crate_dir: &str, function: &str, output: Option<&str>, slice_len: usize) -> Self {
+        GenHarness {
+            crate_dir: PathBuf::from(crate_dir),
+            function: function.to_string(),
+            output: output.map(str::to_string),
+            slice_len,
+        }
+    }
+
+    // The harness project, and the source of its src/lib.rs
+    fn target(&self) -> Result<NewTarget, SideFuzzError> {
+        let (package, krate, root) = crate_root(&self.crate_dir)?;
+        let mut path: Vec<&str> = self.function.split("::").collect();
+        if path.len() > 1 && (path[0] == krate || path[0] == "crate") {
+            path.remove(0);
+        }
+        let function = find_function(&root, &path)?;
+        let lib = harness_source(&krate, &path, &function, self.slice_len)?;
+
+        let output = match &self.output {
+            Some(output) => output.clone(),
+            None => format!("fuzz_{}", function.sig.ident),
+        };
+        let dir = std::fs::canonicalize(&self.crate_dir)?;
+        let dependency = format!("{} = {{ path = {:?} }}", package, dir.to_string_lossy());
+        Ok(NewTarget::new(&output)?
+            .with_lib(lib)
+            .with_dependency(dependency))
+    }
+
+    pub fn run(&self) -> Result<(), SideFuzzError> {
+        let target = self.target()?;
+        target.create()?;
+        println!(
+            "Created fuzz target {} for {}\nBuild it with ./build.sh in {}",
+            target.name(),
+            self.function,
+            self.output.as_deref().unwrap_or_else(|| target.name())
+        );
+        std::process::exit(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn harness_source_test() {
+        let file: syn::File = syn::parse_str(
+            "pub fn sign<'a>(message: &'a [u8], key: &[u8; 32], nonce: u64, flag: bool, out: &mut Vec<u8>) {}
+             pub fn verify(key: &str) {}",
+        )
+        .unwrap();
+        let functions: Vec<ItemFn> = file
+            .items
+            .into_iter()
+            .filter_map(|item| match item {
+                Item::Fn(function) => Some(function),
+                _ => None,
+            })
+            .collect();
+
+        let source = harness_source("sig", &["ed", "sign"], &functions[0], 16).unwrap();
+        assert!(source.contains(
+            "message (16 bytes), key (32 bytes), nonce (8 bytes), flag (1 byte), out (16 bytes)"
+        ));
+        assert!(source.contains("    let message = &input[0..16];\n"));
+        assert!(source.contains("    let key = <[u8; 32]>::try_from(&input[16..48]).unwrap();\n"));
+        assert!(source.contains("    let flag = input[56] & 1 == 1;\n"));
+        assert!(source.contains("    let mut out = input[57..73].to_vec();\n"));
+        assert!(source
+            .contains("sidefuzz::black_box(sig::ed::sign(message, &key, nonce, flag, &mut out));"));
+        assert!(source.ends_with("sidefuzz::sidefuzz_target!(73, harness);\n"));
+
+        let err = harness_source("sig", &["verify"], &functions[1], 16).unwrap_err();
+        assert!(err.to_string().contains("key: & str"));
+    }
+}
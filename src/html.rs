@@ -0,0 +1,280 @@
+// HTML reports, for sharing results with people who don't want to read terminal output
+//
+// Everything is inline (charts are SVG), so the report is a single self-contained file.
+
+use crate::report::{Region, Report, Verdict};
+use std::fmt::Write;
+
+const CHART_WIDTH: f64 = 640.0;
+const CHART_HEIGHT: f64 = 240.0;
+const HISTOGRAM_BINS: usize = 40;
+
+// The t-value above which a difference is reported as a leak
+const T_THRESHOLD: f64 = 4.5;
+
+// Render a report for `target` (the file that was fuzzed or checked)
+pub(crate) fn render(report: &Report, target: &str) -> String {
+    let verdict = match report.verdict {
+        Verdict::Leak => "Timing leak found",
+        Verdict::NoLeak => "No timing leak found",
+        Verdict::Inconclusive => "Inconclusive",
+    };
+
+    let mut html = String::new();
+    html += "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n";
+    let _ = writeln!(html, "<title>sidefuzz: {}</title>", escape(target));
+    html += "<style>body{font-family:sans-serif;max-width:720px;margin:2em auto;} td{padding:2px 12px 2px 0;} code{word-break:break-all;} .leak{color:#b00;} .ok{color:#070;}</style>\n";
+    html += "</head>\n<body>\n";
+    let _ = writeln!(
+        html,
+        "<h1 class=\"{}\">{}</h1>",
+        if report.verdict == Verdict::Leak {
+            "leak"
+        } else {
+            "ok"
+        },
+        verdict
+    );
+
+    html += "<h2>Target</h2>\n<table>\n";
+    row(&mut html, "File", &escape(target));
+    if let Some(region) = report.region {
+        let fuzzed = match region {
+            Region::Message => "The message, under a fixed key",
+            Region::Key => "The key, under a fixed message",
+        };
+        row(&mut html, "Fuzzed", fuzzed);
+    }
+    if let Some(hash) = &report.module_sha256 {
+        row(&mut html, "Module SHA-256", &format!("<code>{}</code>", hash));
+    }
+    row(&mut html, "Command", report.command);
+    row(
+        &mut html,
+        "Input length",
+        &format!("{} bytes", report.input_1.len() / 2),
+    );
+    if report.evidence.input_segments.len() > 1 {
+        let segments: Vec<String> = report
+            .evidence
+            .input_segments
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        row(
+            &mut html,
+            "Inputs",
+            &format!("{} bytes", segments.join(" + ")),
+        );
+    }
+    if let Some(seed) = report.seed {
+        row(&mut html, "Seed", &seed.to_string());
+    }
+    if let Some(generations) = report.generations {
+        row(&mut html, "Generations", &generations.to_string());
+    }
+    html += "</table>\n";
+
+    html += "<h2>Result</h2>\n<table>\n";
+    row(
+        &mut html,
+        "Input 1",
+        &format!("<code>{}</code>", escape(&report.input_1)),
+    );
+    row(
+        &mut html,
+        "Input 2",
+        &format!("<code>{}</code>", escape(&report.input_2)),
+    );
+    row(
+        &mut html,
+        "Mean count, input 1",
+        &report.instructions_1.to_string(),
+    );
+    row(
+        &mut html,
+        "Mean count, input 2",
+        &report.instructions_2.to_string(),
+    );
+    row(&mut html, "Difference", &report.difference.to_string());
+    if let Some(severity) = report.severity {
+        row(&mut html, "Severity", &severity.to_string());
+    }
+    if let Some(effect) = &report.effect {
+        row(
+            &mut html,
+            "95% confidence interval",
+            &format!("{:.2} to {:.2}", effect.lower, effect.upper),
+        );
+        row(&mut html, "Relative difference", &format!("{:.4}%", effect.relative));
+        if let Some(cohens_d) = effect.cohens_d {
+            row(&mut html, "Effect size (Cohen's d)", &format!("{:.3}", cohens_d));
+        }
+        if let Some(exploitability) = effect.exploitability {
+            row(
+                &mut html,
+                "Measurements needed",
+                &format!(
+                    "{} for {}% confidence ({} risk)",
+                    exploitability.measurements, exploitability.confidence, exploitability.risk
+                ),
+            );
+        }
+    }
+    row(&mut html, "t-value", &report.t_value.to_string());
+    row(&mut html, "Confidence", &format!("{}%", report.confidence));
+    row(&mut html, "Samples", &report.samples.to_string());
+    html += "</table>\n";
+
+    if !report.functions.is_empty() {
+        html += "<h2>Functions</h2>\n<table>\n<tr><th>Function</th><th>Input 1</th><th>Input 2</th><th>Delta</th></tr>\n";
+        for function in report.functions.iter() {
+            let name = match &function.name {
+                Some(name) => format!("{} ({})", escape(name), function.func),
+                None => format!("function {}", function.func),
+            };
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                name,
+                function.instructions_1,
+                function.instructions_2,
+                function.delta()
+            );
+        }
+        html += "</table>\n";
+    }
+
+    if report.evidence.t_history.len() > 1 {
+        html += "<h2>t-value over time</h2>\n";
+        html += &t_chart(&report.evidence.t_history);
+    }
+
+    if !report.evidence.samples_1.is_empty() && !report.evidence.samples_2.is_empty() {
+        html += "<h2>Distribution of counts</h2>\n";
+        html += "<p><span style=\"color:#c33\">&#9632;</span> input 1 &nbsp; <span style=\"color:#36c\">&#9632;</span> input 2</p>\n";
+        html += &histogram(&report.evidence.samples_1, &report.evidence.samples_2);
+    }
+
+    html += "</body>\n</html>\n";
+    html
+}
+
+fn row(html: &mut String, name: &str, value: &str) {
+    let _ = writeln!(html, "<tr><td>{}</td><td>{}</td></tr>", name, value);
+}
+
+// A line chart of t-value against the number of samples, with the leak threshold marked
+fn t_chart(history: &[(usize, f64)]) -> String {
+    let max_samples = history.iter().map(|(n, _)| *n).max().unwrap_or(1).max(1) as f64;
+    let max_t = history
+        .iter()
+        .map(|(_, t)| *t)
+        .filter(|t| t.is_finite())
+        .fold(T_THRESHOLD, f64::max)
+        * 1.1;
+
+    let x = |n: usize| n as f64 / max_samples * CHART_WIDTH;
+    let y = |t: f64| CHART_HEIGHT - t.min(max_t) / max_t * CHART_HEIGHT;
+
+    let points: Vec<String> = history
+        .iter()
+        .map(|(n, t)| format!("{:.1},{:.1}", x(*n), y(*t)))
+        .collect();
+
+    let mut svg = svg_start();
+    let _ = writeln!(
+        svg,
+        "<line x1=\"0\" y1=\"{0:.1}\" x2=\"{1}\" y2=\"{0:.1}\" stroke=\"#b00\" stroke-dasharray=\"4\"/>",
+        y(T_THRESHOLD),
+        CHART_WIDTH
+    );
+    let _ = writeln!(
+        svg,
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"#333\" stroke-width=\"2\"/>",
+        points.join(" ")
+    );
+    let _ = writeln!(svg, "<text x=\"4\" y=\"14\">t = {:.2}</text>", max_t);
+    let _ = writeln!(
+        svg,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{} samples</text>",
+        CHART_WIDTH - 4.0,
+        CHART_HEIGHT - 4.0,
+        max_samples
+    );
+    svg += "</svg>\n";
+    svg
+}
+
+// Overlaid histograms of the counts measured for each input
+fn histogram(first: &[f64], second: &[f64]) -> String {
+    let min = first
+        .iter()
+        .chain(second.iter())
+        .cloned()
+        .fold(f64::INFINITY, f64::min);
+    let max = first
+        .iter()
+        .chain(second.iter())
+        .cloned()
+        .fold(f64::NEG_INFINITY, f64::max);
+    let width = ((max - min) / HISTOGRAM_BINS as f64).max(f64::MIN_POSITIVE);
+
+    let bin = |samples: &[f64]| {
+        let mut bins = vec![0usize; HISTOGRAM_BINS];
+        for sample in samples {
+            let index = ((sample - min) / width) as usize;
+            bins[index.min(HISTOGRAM_BINS - 1)] += 1;
+        }
+        bins
+    };
+    let first_bins = bin(first);
+    let second_bins = bin(second);
+    let tallest = first_bins
+        .iter()
+        .chain(second_bins.iter())
+        .cloned()
+        .max()
+        .unwrap_or(1)
+        .max(1) as f64;
+
+    let bar_width = CHART_WIDTH / HISTOGRAM_BINS as f64;
+    let mut svg = svg_start();
+    for (bins, color) in [(&first_bins, "#c33"), (&second_bins, "#36c")].iter() {
+        for (index, count) in bins.iter().enumerate() {
+            let height = *count as f64 / tallest * (CHART_HEIGHT - 20.0);
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\" fill-opacity=\"0.5\"/>",
+                index as f64 * bar_width,
+                CHART_HEIGHT - height,
+                bar_width,
+                height,
+                color
+            );
+        }
+    }
+    let _ = writeln!(svg, "<text x=\"4\" y=\"14\">{}</text>", min);
+    let _ = writeln!(
+        svg,
+        "<text x=\"{}\" y=\"14\" text-anchor=\"end\">{}</text>",
+        CHART_WIDTH - 4.0,
+        max
+    );
+    svg += "</svg>\n";
+    svg
+}
+
+fn svg_start() -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" style=\"border:1px solid #ccc\">\n",
+        CHART_WIDTH, CHART_HEIGHT
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,214 @@
+// This file contains the "info" subcommand, which prints what sidefuzz knows about a target
+//
+// Everything that decides how a target is fuzzed is shown in one place: its input, its memory, the wasm proposals
+// it needs and what it exports and imports, along with the metadata toolchains embed in custom sections. It is a
+// quick sanity check before starting a long run.
+
+use crate::errors::SideFuzzError;
+use crate::meta::TargetMeta;
+use crate::target::{Target, TargetOptions};
+
+// Custom section contents longer than this aren't printed
+const MAX_SHOWN: usize = 200;
+
+pub struct Info {
+    filename: String,
+    bytes: Vec<u8>,
+    module: Result<Box<dyn Target>, SideFuzzError>, // Targets that fail to start still have their other details shown
+}
+
+impl Info {
+    pub fn from_file_with_options(
+        filename: &str,
+        options: &TargetOptions,
+    ) -> Result<Self, SideFuzzError> {
+        Ok(Info {
+            filename: filename.to_string(),
+            bytes: std::fs::read(filename)?,
+            module: crate::target::load(filename, options),
+        })
+    }
+
+    // Exits with 0 if the target could be started, and 1 otherwise
+    pub fn run(&self) -> Result<(), SideFuzzError> {
+        println!("{} ({} bytes)", self.filename, self.bytes.len());
+        println!();
+        match &self.module {
+            Ok(module) => println!("input      {}", describe_input(module.as_ref())),
+            Err(err) => println!(
+                "input      unknown, the target could not be started: {}",
+                err
+            ),
+        }
+
+        if let Some(meta) = TargetMeta::find(&self.bytes) {
+            println!(
+                "target     {}, built with sidefuzz {}",
+                meta.label(),
+                meta.sidefuzz
+            );
+        }
+        if crate::binary::sections(&self.bytes).is_some() {
+            self.print_wasm()?;
+        }
+
+        if self.module.is_ok() {
+            std::process::exit(0);
+        }
+        std::process::exit(1);
+    }
+
+    fn print_wasm(&self) -> Result<(), SideFuzzError> {
+        let memories = crate::binary::memories(&self.bytes).unwrap_or_default();
+        if memories.is_empty() {
+            println!("memory     none");
+        }
+        for memory in memories.iter() {
+            println!(
+                "memory     {}{}",
+                describe_memory(memory.initial_pages, memory.maximum_pages),
+                if memory.imported { ", imported" } else { "" }
+            );
+        }
+        match crate::features::used(&self.bytes) {
+            Some(features) if features.is_empty() => println!("features   none beyond wasm 1.0"),
+            Some(features) => println!("features   {}", features.join(", ")),
+            None => {
+                println!("features   unknown, the module doesn't load with every proposal enabled")
+            }
+        }
+
+        let inspection = crate::inspect::inspect(&self.bytes)?;
+        if !inspection.exports.is_empty() {
+            println!("\nExports:");
+            for (name, ty) in inspection.exports.iter() {
+                println!("  {} ({})", name, ty);
+            }
+        }
+        if !inspection.imports.is_empty() {
+            println!("\nImported functions, which will be stubbed:");
+            for import in inspection.imports.iter() {
+                println!("  {}", import);
+            }
+        }
+
+        let custom = crate::binary::custom_sections(&self.bytes);
+        if !custom.is_empty() {
+            println!("\nCustom sections:");
+            for (name, contents) in custom.iter() {
+                match describe_custom(name, contents) {
+                    Some(description) => {
+                        println!("  {} ({} bytes): {}", name, contents.len(), description)
+                    }
+                    None => println!("  {} ({} bytes)", name, contents.len()),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn describe_input(module: &dyn Target) -> String {
+    let kind = if module.input_is_str() {
+        "str"
+    } else {
+        "bytes"
+    };
+    let len = if module.variable_len() {
+        format!("up to {} bytes", module.fuzz_len())
+    } else {
+        format!("{} bytes", module.fuzz_len())
+    };
+    let segments = module.input_segments();
+    if segments.len() < 2 {
+        return format!("{} of {}", len, kind);
+    }
+
+    let public = module.public_segments();
+    let parts: Vec<String> = segments
+        .iter()
+        .enumerate()
+        .map(|(index, segment)| {
+            if public.get(index).copied().unwrap_or(false) {
+                format!("{} public", segment)
+            } else {
+                segment.to_string()
+            }
+        })
+        .collect();
+    format!(
+        "{} of {}, in {} inputs of {} bytes",
+        len,
+        kind,
+        segments.len(),
+        parts.join(", ")
+    )
+}
+
+// Pages are 64 KiB
+fn describe_memory(initial: u64, maximum: Option<u64>) -> String {
+    let size = |pages: u64| format!("{} pages ({} KiB)", pages, pages * 64);
+    match maximum {
+        Some(maximum) => format!("{}, up to {}", size(initial), size(maximum)),
+        None => format!("{}, no maximum", size(initial)),
+    }
+}
+
+// What a custom section says, if it can be shown in a line or two
+fn describe_custom(name: &str, contents: &[u8]) -> Option<String> {
+    if name == "producers" {
+        return producers(contents);
+    }
+    let text = std::str::from_utf8(contents).ok()?;
+    if text.len() > MAX_SHOWN || text.chars().any(|c| c.is_control() && c != '\n') {
+        return None;
+    }
+    Some(text.trim().replace('\n', " "))
+}
+
+// The "producers" section lists the languages and tools that made the module, such as "language: Rust"
+fn producers(contents: &[u8]) -> Option<String> {
+    use crate::binary::{read_name, read_u32};
+
+    let mut pos = 0;
+    let mut fields = Vec::new();
+    for _ in 0..read_u32(contents, &mut pos)? {
+        let field = read_name(contents, &mut pos)?;
+        let mut values = Vec::new();
+        for _ in 0..read_u32(contents, &mut pos)? {
+            let name = read_name(contents, &mut pos)?;
+            let version = read_name(contents, &mut pos)?;
+            values.push(if version.is_empty() {
+                name
+            } else {
+                format!("{} {}", name, version)
+            });
+        }
+        fields.push(format!("{}: {}", field, values.join(", ")));
+    }
+    Some(fields.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_test() {
+        assert_eq!(
+            describe_memory(17, Some(32)),
+            "17 pages (1088 KiB), up to 32 pages (2048 KiB)"
+        );
+
+        let mut producers = vec![0x01, 0x08];
+        producers.extend_from_slice(b"language");
+        producers.extend_from_slice(&[0x01, 0x04]);
+        producers.extend_from_slice(b"Rust");
+        producers.push(0x00);
+        assert_eq!(
+            describe_custom("producers", &producers),
+            Some("language: Rust".to_string())
+        );
+        assert_eq!(describe_custom("target_features", &[0x01, 0x2b]), None);
+    }
+}
@@ -0,0 +1,265 @@
+// This file contains the "inspect" subcommand, which explains why a module can't be fuzzed
+
+use crate::bindgen::Exports;
+use crate::errors::SideFuzzError;
+use wasmi::core::ValueType;
+use wasmi::{Engine, ExternType, Module};
+
+// What a required export must be
+enum Expected {
+    Memory,
+    Func(&'static [ValueType], &'static [ValueType]), // Parameters and results
+    Integer, // A function without parameters returning an i32 or an i64, such as a size_t from C
+}
+
+// Exports every target needs, and how to provide them
+const REQUIRED_EXPORTS: &[(&str, Expected, &str)] = &[
+    (
+        "memory",
+        Expected::Memory,
+        "build as a cdylib, with crate-type = [\"cdylib\"] in the [lib] section of Cargo.toml",
+    ),
+    (
+        "fuzz",
+        Expected::Func(&[], &[]),
+        "define it with sidefuzz::sidefuzz_target!, or as #[no_mangle] pub extern \"C\" fn fuzz()",
+    ),
+    (
+        "input_pointer",
+        Expected::Func(&[], &[ValueType::I32]),
+        "depend on the sidefuzz crate and call sidefuzz::fetch_input from fuzz",
+    ),
+    (
+        "input_len",
+        Expected::Integer,
+        "depend on the sidefuzz crate and call sidefuzz::fetch_input from fuzz",
+    ),
+];
+
+// Exports a target may leave out, but which must have the right type if it doesn't
+const OPTIONAL_EXPORTS: &[(&str, Expected, &str)] = &[(
+    "input_is_str",
+    Expected::Integer,
+    "return non-zero if the input is a string, or leave it out for bytes",
+)];
+
+// What a module exports and imports, and what stops it from being fuzzed
+#[derive(Debug, Clone, Default)]
+pub struct Inspection {
+    pub exports: Vec<(String, String)>, // Name and type of every export
+    pub imports: Vec<String>,           // Imported functions, as module.name
+    pub problems: Vec<String>, // Missing or mistyped required exports, and how to provide them
+    pub hints: Vec<String>,    // Likely causes of the problems, and other things worth knowing
+}
+
+impl Inspection {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+// Inspect a module's exports and imports
+pub fn inspect(bytes: &[u8]) -> Result<Inspection, SideFuzzError> {
+    // Components are inspected through the core module that is fuzzed
+    if crate::component::is_component(bytes) {
+        return match crate::component::core_module(bytes.to_vec()) {
+            Ok(module) => inspect(&module),
+            Err(err) => {
+                let mut inspection = Inspection::default();
+                inspection.problems.push(err.to_string());
+                inspection.hints.push("Components should export `fuzz: func(input: list<u8>)` and `input-len: func() -> u32`, or be built with the sidefuzz crate".to_string());
+                Ok(inspection)
+            }
+        };
+    }
+    // wasmi can't parse modules using these proposals, so there is nothing more to inspect
+    if let Some(feature) = crate::wasm::unsupported_memories(bytes) {
+        let mut inspection = Inspection::default();
+        inspection
+            .problems
+            .push(format!("the module uses {}, which isn't supported yet", feature));
+        inspection.hints.push("Rust targets built for wasm32-unknown-unknown have a single 32-bit memory. With other toolchains, turn off the memory64 and multi-memory features".to_string());
+        return Ok(inspection);
+    }
+
+    let engine = Engine::default();
+    let module = Module::new(&engine, bytes)?;
+    let names: Vec<&str> = module.exports().map(|export| export.name()).collect();
+    let resolved = Exports::resolve(names.iter().copied());
+
+    let mut inspection = Inspection::default();
+    for export in module.exports() {
+        inspection
+            .exports
+            .push((export.name().to_string(), describe(export.ty())));
+    }
+    for import in module.imports() {
+        if let ExternType::Func(_) = import.ty() {
+            inspection
+                .imports
+                .push(format!("{}.{}", import.module(), import.name()));
+        }
+    }
+
+    let mut renamed = false;
+    let optional = OPTIONAL_EXPORTS.iter().map(|export| (export, false));
+    for ((name, expected, fix), required) in REQUIRED_EXPORTS
+        .iter()
+        .map(|export| (export, true))
+        .chain(optional)
+    {
+        let actual = resolved.name(name);
+        renamed |= actual != *name;
+        let ty = module
+            .exports()
+            .find(|export| export.name() == actual)
+            .map(|export| export.ty().clone());
+        let matches = match (expected, &ty) {
+            (Expected::Memory, Some(ExternType::Memory(_))) => true,
+            (Expected::Func(params, results), Some(ExternType::Func(ty))) => {
+                ty.params() == *params && ty.results() == *results
+            }
+            (Expected::Integer, Some(ExternType::Func(ty))) => {
+                ty.params().is_empty()
+                    && (ty.results() == [ValueType::I32] || ty.results() == [ValueType::I64])
+            }
+            _ => false,
+        };
+        if ty.is_none() && required {
+            inspection
+                .problems
+                .push(format!("missing export '{}': {}", name, fix));
+        } else if ty.is_some() && !matches {
+            inspection
+                .problems
+                .push(format!("export '{}' has the wrong type: {}", name, fix));
+        }
+    }
+
+    let hints = &mut inspection.hints;
+    let functions = inspection
+        .exports
+        .iter()
+        .filter(|(_, ty)| ty.starts_with("func"))
+        .count();
+    if names.contains(&"_start") || names.contains(&"main") {
+        hints.push("the module exports _start or main, so it looks like it was built from a binary crate. Targets must be library crates with crate-type = [\"cdylib\"]".to_string());
+    } else if functions == 0 {
+        hints.push("the module exports no functions. Check that the crate-type is cdylib, and that fuzz is marked #[no_mangle] and pub".to_string());
+    }
+    if names.iter().any(|name| name.starts_with("_ZN")) {
+        hints.push(
+            "some exports have mangled Rust names, which usually means #[no_mangle] is missing"
+                .to_string(),
+        );
+    }
+    if renamed {
+        hints.push(
+            "some required exports were found under other names, which sidefuzz will use"
+                .to_string(),
+        );
+    }
+    let has_fuzz = inspection
+        .problems
+        .iter()
+        .all(|problem| !problem.contains("'fuzz'"));
+    if has_fuzz && !inspection.problems.is_empty() {
+        hints.push("fuzz is exported but the sidefuzz exports aren't. They are only kept if fuzz calls sidefuzz::fetch_input (or fetch_str_input or fetch_inputs), and tools like wasm-opt or wasm-snip can remove them if told to remove unused exports".to_string());
+    }
+    if crate::binary::function_names(bytes).is_empty() {
+        hints.push("the module has no name section, so leaks can't be localized to named functions. It may have been stripped, by wasm-opt or strip = true in the release profile".to_string());
+    }
+    if crate::bindgen::is_bindgen(&module) {
+        hints.push(
+            "the module was built with wasm-bindgen, its imports will be stubbed".to_string(),
+        );
+    }
+    let imports = module.imports().map(|import| import.name());
+    if crate::tinygo::is_tinygo(imports, names.iter().copied(), bytes) {
+        hints.push("the module was built with TinyGo, its runtime will be started through _start first. Go functions are exported with //export comments above them, such as //export input_pointer, and main should be empty".to_string());
+    }
+
+    Ok(inspection)
+}
+
+fn describe(ty: &ExternType) -> String {
+    match ty {
+        ExternType::Func(ty) => format!("func {:?} -> {:?}", ty.params(), ty.results()),
+        ExternType::Memory(_) => "memory".to_string(),
+        ExternType::Global(_) => "global".to_string(),
+        ExternType::Table(_) => "table".to_string(),
+    }
+}
+
+// Describe everything that stops the module in `filename` from being fuzzed
+pub struct Inspect {
+    filename: String,
+    bytes: Vec<u8>,
+}
+
+impl Inspect {
+    pub fn from_file(filename: &str) -> Result<Self, SideFuzzError> {
+        Ok(Inspect {
+            filename: filename.to_string(),
+            bytes: std::fs::read(filename)?,
+        })
+    }
+
+    // Exits with 0 if the module can be fuzzed, and 1 otherwise
+    pub fn run(&self) -> Result<(), SideFuzzError> {
+        let inspection = inspect(&self.bytes)?;
+
+        println!("Exports of {}:", self.filename);
+        for (name, ty) in inspection.exports.iter() {
+            println!("  {} ({})", name, ty);
+        }
+        if !inspection.imports.is_empty() {
+            println!("\nImported functions, which will be stubbed:");
+            for import in inspection.imports.iter() {
+                println!("  {}", import);
+            }
+        }
+        if !inspection.problems.is_empty() {
+            println!("\nProblems:");
+            for problem in inspection.problems.iter() {
+                println!("  {}", problem);
+            }
+        }
+        if !inspection.hints.is_empty() {
+            println!("\nNotes:");
+            for hint in inspection.hints.iter() {
+                println!("  {}", hint);
+            }
+        }
+
+        if inspection.is_valid() {
+            println!("\n{} can be fuzzed", self.filename);
+            std::process::exit(0);
+        }
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inspect_test() {
+        // A module that only exports its memory
+        let mut bytes = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        bytes.extend_from_slice(&[0x05, 0x03, 0x01, 0x00, 0x01]); // memory section, one memory of one page
+        bytes.extend_from_slice(&[0x07, 0x0a, 0x01, 0x06]); // export section, one export
+        bytes.extend_from_slice(b"memory");
+        bytes.extend_from_slice(&[0x02, 0x00]); // memory 0
+
+        let inspection = inspect(&bytes).unwrap();
+        assert_eq!(
+            inspection.exports,
+            vec![("memory".to_string(), "memory".to_string())]
+        );
+        assert_eq!(inspection.problems.len(), 3);
+        assert!(inspection.problems[0].starts_with("missing export 'fuzz'"));
+        assert!(inspection.hints[0].contains("exports no functions"));
+    }
+}
@@ -0,0 +1,622 @@
+// Instrumentation of wasm modules
+//
+// Some analyses need to observe more than wasmi can tell us (memory addresses, branches, function calls etc),
+// so we rewrite the module to call back into the host. Hooks are added as function imports from the
+// "sidefuzz" module, and a callback decides what code to insert around each instruction of each function.
+//
+// Instrumented modules execute more instructions than the original, so their instruction counts must
+// never be compared with those of an uninstrumented module.
+
+use crate::binary::*;
+
+pub(crate) const HOOK_MODULE: &str = "sidefuzz";
+
+// Value types
+pub(crate) const I32: u8 = 0x7f;
+pub(crate) const I64: u8 = 0x7e;
+pub(crate) const F32: u8 = 0x7d;
+pub(crate) const F64: u8 = 0x7c;
+
+// Opcodes we care about
+pub(crate) const OP_BLOCK: u8 = 0x02;
+pub(crate) const OP_LOOP: u8 = 0x03;
+pub(crate) const OP_IF: u8 = 0x04;
+pub(crate) const OP_ELSE: u8 = 0x05;
+pub(crate) const OP_END: u8 = 0x0b;
+pub(crate) const OP_BR: u8 = 0x0c;
+pub(crate) const OP_BR_IF: u8 = 0x0d;
+pub(crate) const OP_BR_TABLE: u8 = 0x0e;
+pub(crate) const OP_RETURN: u8 = 0x0f;
+pub(crate) const OP_CALL: u8 = 0x10;
+pub(crate) const OP_CALL_INDIRECT: u8 = 0x11;
+pub(crate) const OP_RETURN_CALL: u8 = 0x12;
+pub(crate) const OP_RETURN_CALL_INDIRECT: u8 = 0x13;
+pub(crate) const OP_REF_FUNC: u8 = 0xd2;
+
+// A host function that instrumentation code can call
+pub(crate) struct Hook {
+    pub name: &'static str,
+    pub params: &'static [u8],
+    pub results: &'static [u8],
+}
+
+// A decoded instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Op {
+    pub opcode: u8,
+    pub sub: u32,           // Sub-opcode for prefixed (0xfc) instructions
+    pub memarg_offset: u32, // Static offset of memory accesses
+}
+
+impl Op {
+    pub fn is_load(&self) -> bool {
+        (0x28..=0x35).contains(&self.opcode)
+    }
+
+    pub fn is_store(&self) -> bool {
+        (0x36..=0x3e).contains(&self.opcode)
+    }
+
+    // The type of the value being stored, for store instructions
+    pub fn store_type(&self) -> Option<u8> {
+        match self.opcode {
+            0x36 | 0x3a | 0x3b => Some(I32),
+            0x37 | 0x3c | 0x3d | 0x3e => Some(I64),
+            0x38 => Some(F32),
+            0x39 => Some(F64),
+            _ => None,
+        }
+    }
+
+    // Does this instruction start a new block (and a matching `end`)
+    pub fn is_block_start(&self) -> bool {
+        self.opcode == OP_BLOCK || self.opcode == OP_LOOP || self.opcode == OP_IF
+    }
+}
+
+// A place where code can be inserted
+pub(crate) struct Site<'a> {
+    pub func: u32,          // Index of the function in the original module
+    pub op: Option<Op>,     // The instruction, or None at the function entry
+    pub function_end: bool, // Is this the `end` that closes the function body
+    pub hooks: &'a [u32],   // Function index of each hook, in the order they were given
+    pub locals: u32,        // Index of the first extra local
+}
+
+// Code to insert before and after an instruction.
+// Code inserted after the `end` that closes a function body is ignored.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Insert {
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+// Instrument a module.
+// `extra_locals` are added to every function, and `insert` is called for the entry and every instruction of every function.
+// Returns None if the module could not be parsed, or uses instructions that we don't understand.
+pub(crate) fn instrument<F>(
+    bytes: &[u8],
+    hooks: &[Hook],
+    extra_locals: &[u8],
+    mut insert: F,
+) -> Option<Vec<u8>>
+where
+    F: FnMut(&Site) -> Insert,
+{
+    let sections = sections(bytes)?;
+
+    // Gather types, imported functions and the types of defined functions
+    let mut types: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    let mut imported_funcs: u32 = 0;
+    let mut func_types: Vec<u32> = Vec::new();
+    for section in sections.iter() {
+        let mut pos = section.start;
+        match section.id {
+            SECTION_TYPE => {
+                let count = read_u32(bytes, &mut pos)?;
+                for _ in 0..count {
+                    if *bytes.get(pos)? != 0x60 {
+                        return None;
+                    }
+                    pos += 1;
+                    let params = read_vec_bytes(bytes, &mut pos)?;
+                    let results = read_vec_bytes(bytes, &mut pos)?;
+                    types.push((params, results));
+                }
+            }
+            SECTION_IMPORT => {
+                let count = read_u32(bytes, &mut pos)?;
+                for _ in 0..count {
+                    read_name(bytes, &mut pos)?;
+                    read_name(bytes, &mut pos)?;
+                    let kind = *bytes.get(pos)?;
+                    pos += 1;
+                    match kind {
+                        KIND_FUNC => {
+                            skip_leb(bytes, &mut pos)?;
+                            imported_funcs += 1;
+                        }
+                        1 => {
+                            pos += 1;
+                            skip_limits(bytes, &mut pos)?;
+                        }
+                        2 => skip_limits(bytes, &mut pos)?,
+                        KIND_GLOBAL => pos += 2,
+                        _ => return None,
+                    }
+                }
+            }
+            SECTION_FUNCTION => {
+                let count = read_u32(bytes, &mut pos)?;
+                for _ in 0..count {
+                    func_types.push(read_u32(bytes, &mut pos)?);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Find or add a type for each hook
+    let original_types = types.len();
+    let mut hook_types = Vec::with_capacity(hooks.len());
+    for hook in hooks {
+        let ty = (hook.params.to_vec(), hook.results.to_vec());
+        let index = match types.iter().position(|t| t == &ty) {
+            Some(index) => index,
+            None => {
+                types.push(ty);
+                types.len() - 1
+            }
+        };
+        hook_types.push(index as u32);
+    }
+
+    // Hooks are imported after existing imports, shifting all defined functions
+    let shift = hooks.len() as u32;
+    let remap = |index: u32| {
+        if index >= imported_funcs {
+            index + shift
+        } else {
+            index
+        }
+    };
+    let hook_indices: Vec<u32> = (0..shift).map(|i| imported_funcs + i).collect();
+
+    let mut out = bytes[0..8].to_vec();
+    let mut wrote_types = false;
+    let mut wrote_imports = false;
+    let write_types = |out: &mut Vec<u8>| {
+        let mut payload = Vec::new();
+        write_u32(&mut payload, types.len() as u32);
+        for (params, results) in types.iter() {
+            payload.push(0x60);
+            write_vec_bytes(&mut payload, params);
+            write_vec_bytes(&mut payload, results);
+        }
+        write_section(out, SECTION_TYPE, &payload);
+    };
+    let write_hook_imports = |payload: &mut Vec<u8>| {
+        for (hook, ty) in hooks.iter().zip(hook_types.iter()) {
+            write_name(payload, HOOK_MODULE);
+            write_name(payload, hook.name);
+            payload.push(KIND_FUNC);
+            write_u32(payload, *ty);
+        }
+    };
+
+    for section in sections.iter() {
+        // Add type and import sections if the module doesn't have them
+        if section.id != SECTION_CUSTOM {
+            if !wrote_types && section.id > SECTION_TYPE {
+                if types.len() > original_types {
+                    write_types(&mut out);
+                }
+                wrote_types = true;
+            }
+            if !wrote_imports && section.id > SECTION_IMPORT {
+                if !hooks.is_empty() {
+                    let mut payload = Vec::new();
+                    write_u32(&mut payload, shift);
+                    write_hook_imports(&mut payload);
+                    write_section(&mut out, SECTION_IMPORT, &payload);
+                }
+                wrote_imports = true;
+            }
+        }
+
+        let mut pos = section.start;
+        let mut payload = Vec::new();
+        match section.id {
+            SECTION_TYPE => {
+                write_types(&mut out);
+                wrote_types = true;
+                continue;
+            }
+            SECTION_IMPORT => {
+                let count = read_u32(bytes, &mut pos)?;
+                write_u32(&mut payload, count + shift);
+                payload.extend_from_slice(&bytes[pos..section.end]);
+                write_hook_imports(&mut payload);
+                wrote_imports = true;
+            }
+            SECTION_GLOBAL => {
+                let count = read_u32(bytes, &mut pos)?;
+                write_u32(&mut payload, count);
+                for _ in 0..count {
+                    payload.extend_from_slice(bytes.get(pos..pos + 2)?);
+                    pos += 2;
+                    rewrite_const_expr(bytes, &mut pos, &mut payload, &remap)?;
+                }
+            }
+            SECTION_EXPORT => {
+                let count = read_u32(bytes, &mut pos)?;
+                write_u32(&mut payload, count);
+                for _ in 0..count {
+                    let name_start = pos;
+                    read_name(bytes, &mut pos)?;
+                    payload.extend_from_slice(&bytes[name_start..pos]);
+                    let kind = *bytes.get(pos)?;
+                    pos += 1;
+                    let index = read_u32(bytes, &mut pos)?;
+                    payload.push(kind);
+                    write_u32(
+                        &mut payload,
+                        if kind == KIND_FUNC {
+                            remap(index)
+                        } else {
+                            index
+                        },
+                    );
+                }
+            }
+            SECTION_START => {
+                let index = read_u32(bytes, &mut pos)?;
+                write_u32(&mut payload, remap(index));
+            }
+            SECTION_ELEMENT => {
+                let count = read_u32(bytes, &mut pos)?;
+                write_u32(&mut payload, count);
+                for _ in 0..count {
+                    rewrite_element(bytes, &mut pos, &mut payload, &remap)?;
+                }
+            }
+            SECTION_CODE => {
+                let count = read_u32(bytes, &mut pos)?;
+                write_u32(&mut payload, count);
+                for i in 0..count {
+                    let size = read_u32(bytes, &mut pos)? as usize;
+                    let end = pos.checked_add(size)?;
+                    let params = types.get(*func_types.get(i as usize)? as usize)?.0.len() as u32;
+                    let body = rewrite_body(
+                        bytes.get(pos..end)?,
+                        imported_funcs + i,
+                        params,
+                        &hook_indices,
+                        extra_locals,
+                        &remap,
+                        &mut insert,
+                    )?;
+                    write_u32(&mut payload, body.len() as u32);
+                    payload.extend_from_slice(&body);
+                    pos = end;
+                }
+            }
+            _ => {
+                out.extend_from_slice(&bytes[section.header..section.end]);
+                continue;
+            }
+        }
+        write_section(&mut out, section.id, &payload);
+    }
+
+    Some(out)
+}
+
+// Rewrite a single function body
+fn rewrite_body<F, R>(
+    body: &[u8],
+    func: u32,
+    params: u32,
+    hooks: &[u32],
+    extra_locals: &[u8],
+    remap: &R,
+    insert: &mut F,
+) -> Option<Vec<u8>>
+where
+    F: FnMut(&Site) -> Insert,
+    R: Fn(u32) -> u32,
+{
+    let mut out = Vec::with_capacity(body.len() * 2);
+
+    // Locals, with our extra locals added after the existing ones.
+    let mut pos = 0;
+    let groups = read_u32(body, &mut pos)?;
+    let groups_start = pos;
+    let mut locals = params;
+    for _ in 0..groups {
+        locals = locals.checked_add(read_u32(body, &mut pos)?)?;
+        pos += 1;
+    }
+    write_u32(&mut out, groups + extra_locals.len() as u32);
+    out.extend_from_slice(body.get(groups_start..pos)?);
+    for ty in extra_locals {
+        write_u32(&mut out, 1);
+        out.push(*ty);
+    }
+
+    let mut site = Site {
+        func,
+        op: None,
+        function_end: false,
+        hooks,
+        locals,
+    };
+    out.extend_from_slice(&insert(&site).before);
+
+    // Instructions
+    let mut depth = 0;
+    while pos < body.len() {
+        let start = pos;
+        let (op, func_index) = decode(body, &mut pos)?;
+        site.op = Some(op);
+        site.function_end = op.opcode == OP_END && depth == 0;
+
+        let code = insert(&site);
+        out.extend_from_slice(&code.before);
+        match func_index {
+            Some(index) => {
+                out.push(op.opcode);
+                write_u32(&mut out, remap(index));
+            }
+            None => out.extend_from_slice(&body[start..pos]),
+        }
+
+        if site.function_end {
+            return if pos == body.len() { Some(out) } else { None };
+        }
+        out.extend_from_slice(&code.after);
+
+        if op.is_block_start() {
+            depth += 1;
+        } else if op.opcode == OP_END {
+            depth -= 1;
+        }
+    }
+
+    // The body didn't end with `end`
+    None
+}
+
+// Decode the instruction at `pos`, advancing past it.
+// Also returns the function index for instructions that refer to functions, since they need remapping.
+pub(crate) fn decode(bytes: &[u8], pos: &mut usize) -> Option<(Op, Option<u32>)> {
+    let opcode = *bytes.get(*pos)?;
+    *pos += 1;
+    let mut op = Op {
+        opcode,
+        sub: 0,
+        memarg_offset: 0,
+    };
+    let mut func_index = None;
+
+    match opcode {
+        // No immediates
+        0x00 | 0x01 | OP_ELSE | OP_END | OP_RETURN | 0x1a | 0x1b | 0x45..=0xc4 | 0xd1 => {}
+        // Block type
+        OP_BLOCK | OP_LOOP | OP_IF => {
+            let ty = *bytes.get(*pos)?;
+            if ty == 0x40 || (0x6f..=0x7f).contains(&ty) {
+                *pos += 1;
+            } else {
+                skip_leb(bytes, pos)?;
+            }
+        }
+        // Single index (or 0x00 memory index for memory.size/grow)
+        OP_BR | OP_BR_IF | 0x20..=0x26 | 0x3f | 0x40 => skip_leb(bytes, pos)?,
+        OP_BR_TABLE => {
+            let count = read_u32(bytes, pos)?;
+            for _ in 0..=count {
+                skip_leb(bytes, pos)?;
+            }
+        }
+        OP_CALL | OP_RETURN_CALL | OP_REF_FUNC => func_index = Some(read_u32(bytes, pos)?),
+        OP_CALL_INDIRECT | OP_RETURN_CALL_INDIRECT => {
+            skip_leb(bytes, pos)?;
+            skip_leb(bytes, pos)?;
+        }
+        // Typed select
+        0x1c => {
+            let count = read_u32(bytes, pos)? as usize;
+            *pos += count;
+        }
+        // Loads and stores: alignment and offset
+        0x28..=0x3e => {
+            skip_leb(bytes, pos)?;
+            op.memarg_offset = read_u32(bytes, pos)?;
+        }
+        0x41 | 0x42 => skip_leb(bytes, pos)?,
+        0x43 => *pos += 4,
+        0x44 => *pos += 8,
+        0xd0 => *pos += 1,
+        // Saturating truncation, bulk memory and table instructions
+        0xfc => {
+            op.sub = read_u32(bytes, pos)?;
+            match op.sub {
+                0..=7 => {}
+                8 => {
+                    skip_leb(bytes, pos)?;
+                    *pos += 1;
+                }
+                9 | 13 | 15 | 16 | 17 => skip_leb(bytes, pos)?,
+                10 => *pos += 2,
+                11 => *pos += 1,
+                12 | 14 => {
+                    skip_leb(bytes, pos)?;
+                    skip_leb(bytes, pos)?;
+                }
+                _ => return None,
+            }
+        }
+        _ => return None,
+    }
+
+    if *pos > bytes.len() {
+        return None;
+    }
+    Some((op, func_index))
+}
+
+// Copy a constant expression, remapping function indices
+fn rewrite_const_expr<R>(bytes: &[u8], pos: &mut usize, out: &mut Vec<u8>, remap: &R) -> Option<()>
+where
+    R: Fn(u32) -> u32,
+{
+    loop {
+        let start = *pos;
+        let (op, func_index) = decode(bytes, pos)?;
+        match func_index {
+            Some(index) => {
+                out.push(op.opcode);
+                write_u32(out, remap(index));
+            }
+            None => out.extend_from_slice(&bytes[start..*pos]),
+        }
+        if op.opcode == OP_END {
+            return Some(());
+        }
+    }
+}
+
+// Copy an element segment, remapping function indices
+fn rewrite_element<R>(bytes: &[u8], pos: &mut usize, out: &mut Vec<u8>, remap: &R) -> Option<()>
+where
+    R: Fn(u32) -> u32,
+{
+    let flags = read_u32(bytes, pos)?;
+    write_u32(out, flags);
+
+    // Table index
+    if flags == 2 || flags == 6 {
+        write_u32(out, read_u32(bytes, pos)?);
+    }
+    // Offset expression for active segments
+    if flags & 0x01 == 0 {
+        rewrite_const_expr(bytes, pos, out, remap)?;
+    }
+    // Element kind or reference type
+    if flags & 0x03 != 0 {
+        out.push(*bytes.get(*pos)?);
+        *pos += 1;
+    }
+
+    let count = read_u32(bytes, pos)?;
+    write_u32(out, count);
+    for _ in 0..count {
+        if flags & 0x04 == 0 {
+            write_u32(out, remap(read_u32(bytes, pos)?));
+        } else {
+            rewrite_const_expr(bytes, pos, out, remap)?;
+        }
+    }
+
+    Some(())
+}
+
+fn read_vec_bytes(bytes: &[u8], pos: &mut usize) -> Option<Vec<u8>> {
+    let count = read_u32(bytes, pos)? as usize;
+    let vec = bytes.get(*pos..*pos + count)?.to_vec();
+    *pos += count;
+    Some(vec)
+}
+
+fn write_vec_bytes(out: &mut Vec<u8>, vec: &[u8]) {
+    write_u32(out, vec.len() as u32);
+    out.extend_from_slice(vec);
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_vec_bytes(out, name.as_bytes());
+}
+
+// Helpers for emitting instrumentation code
+
+pub(crate) fn emit_call(out: &mut Vec<u8>, func: u32) {
+    out.push(OP_CALL);
+    write_u32(out, func);
+}
+
+pub(crate) fn emit_local_get(out: &mut Vec<u8>, local: u32) {
+    out.push(0x20);
+    write_u32(out, local);
+}
+
+pub(crate) fn emit_local_set(out: &mut Vec<u8>, local: u32) {
+    out.push(0x21);
+    write_u32(out, local);
+}
+
+pub(crate) fn emit_local_tee(out: &mut Vec<u8>, local: u32) {
+    out.push(0x22);
+    write_u32(out, local);
+}
+
+pub(crate) fn emit_i32_const(out: &mut Vec<u8>, value: i32) {
+    out.push(0x41);
+    write_i64(out, value as i64);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::binary::*;
+    use crate::instrument::*;
+
+    // (module (memory 1) (func (export "f") (result i32) i32.const 0 i32.load))
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic + version
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // types
+        0x03, 0x02, 0x01, 0x00, // functions
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // exports
+        0x0a, 0x09, 0x01, 0x07, 0x00, 0x41, 0x00, 0x28, 0x02, 0x00, 0x0b, // code
+    ];
+
+    const HOOKS: &[Hook] = &[Hook {
+        name: "hook",
+        params: &[],
+        results: &[],
+    }];
+
+    #[test]
+    fn instrument_test() {
+        // Call the hook before every load
+        let instrumented = instrument(MODULE, HOOKS, &[I32], |site| {
+            let mut insert = Insert::default();
+            if let Some(op) = site.op {
+                if op.is_load() {
+                    emit_call(&mut insert.before, site.hooks[0]);
+                }
+            }
+            insert
+        })
+        .unwrap();
+
+        let sections = sections(&instrumented).unwrap();
+        let ids: Vec<u8> = sections.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 5, 7, 10]);
+
+        // The new type is added after the existing one
+        let types = &instrumented[sections[0].start..sections[0].end];
+        assert_eq!(types, &[0x02, 0x60, 0x00, 0x01, 0x7f, 0x60, 0x00, 0x00]);
+
+        // The exported function is shifted past the imported hook
+        let exports = &instrumented[sections[4].start..sections[4].end];
+        assert_eq!(exports, &[0x01, 0x01, b'f', 0x00, 0x01]);
+
+        // An extra i32 local, and a call to the hook before the load
+        let code = &instrumented[sections[5].start..sections[5].end];
+        assert_eq!(
+            code,
+            &[0x01, 0x0b, 0x01, 0x01, 0x7f, 0x41, 0x00, 0x10, 0x00, 0x28, 0x02, 0x00, 0x0b]
+        );
+    }
+}
@@ -0,0 +1,51 @@
+// Stopping fuzzing runs early with Ctrl-C, without losing what they found
+//
+// SIGINT and SIGTERM only ask the run to stop, which it checks for after every generation. It then saves a
+// checkpoint and reports the best input pair so far, as if it had run out of generations. A second signal stops the
+// process straight away, for runs stuck inside a long execution. Signals are only handled on Linux, elsewhere they
+// still end the process as usual.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Where interrupted runs without a --checkpoint-file save their checkpoint
+pub(crate) const DEFAULT_CHECKPOINT: &str = "sidefuzz.checkpoint";
+
+// Handle SIGINT and SIGTERM by asking the run to stop
+#[cfg(target_os = "linux")]
+pub(crate) fn install() {
+    extern "C" fn handle(_signal: libc::c_int) {
+        // Only async-signal-safe calls are allowed here
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            unsafe { libc::_exit(130) };
+        }
+    }
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn install() {}
+
+// Whether the run has been asked to stop
+pub(crate) fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn interrupt_test() {
+        install();
+        assert!(!requested());
+        unsafe { libc::raise(libc::SIGTERM) };
+        assert!(requested());
+    }
+}
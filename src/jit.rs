@@ -0,0 +1,804 @@
+// Running wasm targets with wasmtime's Cranelift JIT instead of the wasmi interpreter
+//
+// Interpreting big targets (RSA, pairings) is slow. Compiled code runs them many times faster, but wasmtime's own
+// fuel is counted at compile time per basic block and depends on the wasmtime version. Instead, the module is
+// instrumented to count its own instructions: at the end of each straight-line run of code it calls the "count"
+// hook with how many of the original instructions the run holds. Counts are deterministic, but they are not the
+// same as the interpreter's, so measurements from different engines must never be compared.
+//
+// The instruction budget is enforced by the hook, and the timeout with wasmtime's epoch interruption, which
+// checks a deadline at loop headers and function entries.
+
+use crate::assemblyscript::{InputKind, INPUT_KIND};
+use crate::bindgen::{Exports, BINDGEN_START};
+use crate::errors::SideFuzzError;
+use crate::instrument::*;
+use crate::stubs::{random_unit, Entropy, StubBehavior, Stubs};
+use crate::target::Target;
+use crate::wasm::{WasmOptions, DEFAULT_MAX_INPUT_LEN, DEFAULT_MAX_INSTRUCTIONS};
+use float_duration::{FloatDuration, TimePoint};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wasmtime::*;
+
+// How often the epoch advances, the resolution of the timeout
+const EPOCH_TICK: Duration = Duration::from_millis(1);
+
+// An epoch deadline that is never reached, deadlines are added to the current epoch so this can't be u64::MAX
+const NO_DEADLINE: u64 = u64::MAX / 2;
+
+const COUNT_HOOKS: &[Hook] = &[Hook {
+    name: "count",
+    params: &[I32],
+    results: &[],
+}];
+
+// Instructions after which execution may continue somewhere else, so the count of the run so far is flushed
+const RUN_ENDS: &[u8] = &[
+    0x00, // unreachable
+    OP_LOOP,
+    OP_IF,
+    OP_ELSE,
+    OP_END,
+    OP_BR,
+    OP_BR_IF,
+    OP_BR_TABLE,
+    OP_RETURN,
+    OP_RETURN_CALL,
+    OP_RETURN_CALL_INDIRECT,
+];
+
+// What the count hook keeps track of during an execution
+#[derive(Default)]
+struct Counter {
+    count: u64,
+    budget: u64,
+}
+
+// Returned by the count hook once an execution has used up its budget
+#[derive(Debug)]
+struct BudgetExhausted;
+
+impl std::fmt::Display for BudgetExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "instruction budget exhausted")
+    }
+}
+
+impl std::error::Error for BudgetExhausted {}
+
+pub struct JitModule {
+    module: Vec<u8>,
+    options: WasmOptions,
+    store: Store<Counter>,
+    instance: Instance,
+    memory: Memory,
+    fuzz: TypedFunc<(), ()>,
+    fuzz_segments: Vec<(usize, usize)>, // (pointer, length) of each input, in order
+    public_segments: Vec<bool>,
+    fuzz_len: usize,
+    input_is_str: bool,
+    input_kind: InputKind, // How the input is written, for AssemblyScript targets
+    actual_len: Option<Global>, // Only set for variable-length targets
+    snapshot: Option<(Vec<u8>, Vec<(Global, Val)>)>, // Linear memory and mutable globals
+    rng: StdRng,
+    ticking: Option<Arc<AtomicBool>>, // Cleared to stop the epoch thread
+    entropy: Entropy,                 // Stands in for imported randomness
+}
+
+impl JitModule {
+    pub fn with_options(module: Vec<u8>, options: WasmOptions) -> Result<Self, SideFuzzError> {
+        let module = crate::component::core_module(module)?;
+        if let Some(feature) = crate::wasm::unsupported_memories(&module) {
+            return Err(SideFuzzError::UnsupportedWasmFeature(feature));
+        }
+        crate::meta::check_compatible(&module)?;
+        if options.persistent {
+            return Err(SideFuzzError::PersistentModeUnsupported(
+                "the jit engine runs one input per call".to_string(),
+            ));
+        }
+
+        let features = &options.features;
+        let mut config = Config::new();
+        config
+            .wasm_bulk_memory(features.is_enabled("bulk-memory"))
+            .wasm_multi_value(features.is_enabled("multi-value"))
+            .wasm_reference_types(features.is_enabled("reference-types"))
+            .epoch_interruption(options.timeout.is_some());
+        let engine = Engine::new(&config)?;
+
+        let counted = instrument_counters(&module).ok_or(SideFuzzError::InstrumentationFailed)?;
+        // Export all mutable globals so they can be included in snapshots
+        let prepared = crate::binary::export_mutable_globals(&counted).unwrap_or(counted);
+        let parsed = Module::new(&engine, &prepared)?;
+        let mut store = Store::new(&engine, Counter::default());
+
+        let entropy = Entropy::new(options.stubs.seed());
+        let linker = link(&engine, &parsed, &options.stubs, &entropy)?;
+
+        // Start up code runs without a budget or deadline
+        store.data_mut().budget = u64::MAX;
+        store.set_epoch_deadline(NO_DEADLINE);
+        let instance = linker.instantiate(&mut store, &parsed)?;
+        for start in ["_initialize", BINDGEN_START].iter() {
+            if let Some(start) = instance.get_func(&mut store, start) {
+                start.call(&mut store, &[], &mut [])?;
+            }
+        }
+
+        // Go's runtime is started by `_start`. proc_exit is an ordinary stub here, returning rather than ending the call.
+        let tinygo = options.tinygo
+            || crate::tinygo::is_tinygo(
+                parsed.imports().map(|import| import.name()),
+                parsed.exports().map(|export| export.name()),
+                &module,
+            );
+        if let Some(start) = instance
+            .get_func(&mut store, crate::tinygo::START)
+            .filter(|_| tinygo)
+        {
+            *store.data_mut() = Counter {
+                count: 0,
+                budget: crate::tinygo::STARTUP_INSTRUCTIONS,
+            };
+            start
+                .call(&mut store, &[], &mut [])
+                .map_err(|err| SideFuzzError::TinyGoStartFailed(err.to_string()))?;
+            store.data_mut().budget = u64::MAX;
+        }
+
+        let names: Vec<String> = parsed
+            .exports()
+            .map(|export| export.name().to_string())
+            .collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let mut exports = Exports::resolve(names.iter().copied());
+        if let Some(entry) = &options.entry {
+            exports
+                .set_entry(&names, entry)
+                .map_err(|entries| SideFuzzError::NoSuchEntry(entry.clone(), entries.join(", ")))?;
+        }
+        let memory = instance
+            .get_export(&mut store, exports.name("memory"))
+            .ok_or(SideFuzzError::WasmModuleNoMemory)?
+            .into_memory()
+            .ok_or(SideFuzzError::WasmModuleBadMemory)?;
+        let fuzz = instance
+            .get_typed_func::<(), ()>(&mut store, exports.name("fuzz"))
+            .map_err(|_| SideFuzzError::WasmModuleNoFuzz)?;
+
+        // The epoch advances on its own thread, until the module is dropped
+        let ticking = options.timeout.map(|_| {
+            let ticking = Arc::new(AtomicBool::new(true));
+            let still_ticking = ticking.clone();
+            let engine = engine.clone();
+            std::thread::spawn(move || {
+                while still_ticking.load(Ordering::Relaxed) {
+                    std::thread::sleep(EPOCH_TICK);
+                    engine.increment_epoch();
+                }
+            });
+            ticking
+        });
+
+        let mut jit_module = JitModule {
+            module,
+            options,
+            store,
+            instance,
+            memory,
+            fuzz,
+            fuzz_segments: Vec::new(),
+            public_segments: Vec::new(),
+            fuzz_len: 0,
+            input_is_str: false,
+            input_kind: InputKind::Bytes,
+            actual_len: None,
+            snapshot: None,
+            rng: StdRng::seed_from_u64(0),
+            ticking,
+            entropy,
+        };
+        jit_module.set_input_pointer(&exports)?;
+        jit_module.set_key(&exports)?;
+
+        // Prime lazy statics, until an execution completes successfully (limited to 100 attempts)
+        let mut attempts = 0;
+        loop {
+            let input: Vec<u8> = (0..jit_module.fuzz_len)
+                .map(|_| jit_module.rng.gen())
+                .collect();
+            match jit_module.count_instructions(&input) {
+                Ok(_) => break,
+                Err(err) if attempts >= 100 => return Err(err),
+                Err(_) => attempts += 1,
+            }
+        }
+
+        jit_module.take_snapshot();
+        Ok(jit_module)
+    }
+
+    pub fn from_file_with_options(
+        filename: &str,
+        options: WasmOptions,
+    ) -> Result<Self, SideFuzzError> {
+        Self::with_options(std::fs::read(filename)?, options)
+    }
+
+    pub fn count_instructions(&mut self, input: &[u8]) -> Result<u64, SideFuzzError> {
+        let budget = self
+            .options
+            .max_instructions
+            .unwrap_or(DEFAULT_MAX_INSTRUCTIONS);
+
+        // Executions made while instantiating come before the snapshot, and can't be restored to it
+        if self.options.fresh_memory && self.snapshot.is_some() {
+            self.restore();
+        }
+
+        if self.input_kind != InputKind::Bytes {
+            // AssemblyScript objects hold a single input, and their header says how long it is
+            let input = &input[..input.len().min(self.fuzz_len)];
+            let ptr = self.fuzz_segments.first().map_or(0, |(ptr, _)| *ptr);
+            for (address, bytes) in self.input_kind.writes(ptr, input) {
+                self.write(address, &bytes)?;
+            }
+            if let Some(actual_len) = self.actual_len {
+                actual_len.set(&mut self.store, Val::I32(input.len() as i32))?;
+            }
+        } else if let Some(actual_len) = self.actual_len {
+            // Variable-length targets have a single input, and only read as much of it as they are told to
+            let input = &input[..input.len().min(self.fuzz_len)];
+            let ptr = self.fuzz_segments.first().map_or(0, |(ptr, _)| *ptr);
+            self.write(ptr, input)?;
+            actual_len.set(&mut self.store, Val::I32(input.len() as i32))?;
+        } else {
+            let mut offset = 0;
+            for (ptr, len) in self.fuzz_segments.clone() {
+                let segment = match input.get(offset..offset + len) {
+                    Some(segment) => segment,
+                    None => break,
+                };
+                self.write(ptr, segment)?;
+                offset += len;
+            }
+        }
+
+        *self.store.data_mut() = Counter { count: 0, budget };
+        self.entropy.reset();
+        if let Some(timeout) = self.options.timeout {
+            let ticks = timeout.as_nanos() / EPOCH_TICK.as_nanos() + 1;
+            self.store.set_epoch_deadline(ticks as u64);
+        }
+        let start_time = Instant::now();
+
+        if let Err(err) = self.fuzz.call(&mut self.store, ()) {
+            // Execution was abandoned part way through, or memory was corrupted, so start over from the snapshot
+            let trap = err.downcast_ref::<Trap>().copied();
+            if err.is::<BudgetExhausted>() {
+                self.restore();
+                return Err(SideFuzzError::InstructionBudgetExhausted(budget));
+            }
+            if let Some(trap) = trap {
+                self.restore();
+                if let (Trap::Interrupt, Some(timeout)) = (trap, self.options.timeout) {
+                    return Err(SideFuzzError::Timeout(timeout.as_millis() as u64));
+                }
+            }
+            return Err(err.into());
+        }
+
+        // Executions that are slow without running many instructions, in host functions for example
+        if let Some(timeout) = self.options.timeout {
+            if start_time.elapsed() > timeout {
+                return Err(SideFuzzError::Timeout(timeout.as_millis() as u64));
+            }
+        }
+        Ok(self.store.data().count)
+    }
+
+    fn write(&mut self, ptr: usize, bytes: &[u8]) -> Result<(), SideFuzzError> {
+        self.memory.write(&mut self.store, ptr, bytes).map_err(|_| {
+            SideFuzzError::WasmModuleInputOutOfBounds(self.memory.data_size(&self.store))
+        })
+    }
+
+    // Find the inputs, the same way as `WasmModule::set_input_pointer`
+    fn set_input_pointer(&mut self, exports: &Exports) -> Result<(), SideFuzzError> {
+        // Run once to prime the INPUT static and set its length
+        let _ = crate::black_box(self.count_instructions(&[]));
+
+        let input_pointer = self
+            .call_i32(exports, "input_pointer", None)
+            .map_err(|_| SideFuzzError::WasmModuleNoInputPointer)?;
+        let input_len = self
+            .call_i32(exports, "input_len", None)
+            .map_err(|_| SideFuzzError::WasmModuleNoInputLen)?;
+        // Targets that don't export input_is_str take bytes
+        let input_is_str = if self
+            .instance
+            .get_func(&mut self.store, exports.name("input_is_str"))
+            .is_some()
+        {
+            self.call_i32(exports, "input_is_str", None)
+                .map_err(|_| SideFuzzError::WasmModuleBadInpuLen)?
+        } else {
+            0
+        };
+        let max_input_len = self.options.max_input_len.unwrap_or(DEFAULT_MAX_INPUT_LEN);
+        if input_len < 0 {
+            return Err(SideFuzzError::WasmModuleBadInpuLen);
+        }
+        if input_len as usize > max_input_len {
+            return Err(SideFuzzError::FuzzLenTooLong(
+                input_len as u32,
+                max_input_len,
+            ));
+        }
+
+        let has_segments = self
+            .instance
+            .get_export(&mut self.store, exports.name("input_count"))
+            .is_some();
+        let mut segments = vec![(input_pointer as u32 as usize, input_len as usize)];
+        let mut public_segments = vec![false];
+        if has_segments {
+            let count = self.call_i32(exports, "input_count", None)?;
+            if count < 0 || count as usize > crate::MAX_INPUTS {
+                return Err(SideFuzzError::WasmModuleBadInputSegments);
+            }
+            segments.clear();
+            public_segments.clear();
+            for index in 0..count {
+                let ptr = self.call_i32(exports, "input_pointer_at", Some(index))?;
+                let len = self.call_i32(exports, "input_len_at", Some(index))?;
+                if len < 0 {
+                    return Err(SideFuzzError::WasmModuleBadInputSegments);
+                }
+                segments.push((ptr as u32 as usize, len as usize));
+                let public = self.call_i32(exports, "input_is_public_at", Some(index));
+                public_segments.push(public.map_or(false, |public| public > 0));
+            }
+        }
+        if segments.iter().map(|(_, len)| len).sum::<usize>() != input_len as usize {
+            return Err(SideFuzzError::WasmModuleBadInputSegments);
+        }
+        let memory_size = self.memory.data_size(&self.store);
+        if segments.iter().any(|(ptr, len)| ptr + len > memory_size) {
+            return Err(SideFuzzError::WasmModuleInputOutOfBounds(memory_size));
+        }
+
+        // AssemblyScript targets may ask for their input as a managed object, which must be their only input
+        let input_kind = self.input_kind(exports)?;
+        if input_kind != InputKind::Bytes {
+            if has_segments {
+                return Err(SideFuzzError::WasmModuleBadManagedInput(
+                    "it must be the only input".to_string(),
+                ));
+            }
+            input_kind.check(
+                self.memory.data(&self.store),
+                input_pointer as u32 as usize,
+                input_len as usize,
+            )?;
+        }
+
+        self.fuzz_segments = segments;
+        self.public_segments = public_segments;
+        self.fuzz_len = input_len as usize;
+        self.input_is_str = input_is_str > 0 || input_kind == InputKind::String;
+        self.input_kind = input_kind;
+        // Only targets exporting the length as a mutable global can be variable-length here
+        self.actual_len = self
+            .instance
+            .get_global(&mut self.store, exports.name("input_actual_len"))
+            .filter(|global| global.ty(&self.store).mutability() == Mutability::Var);
+        Ok(())
+    }
+
+    // Set up the key, the same way as `WasmModule::set_key`
+    fn set_key(&mut self, exports: &Exports) -> Result<(), SideFuzzError> {
+        if self.options.key.is_none() && !self.options.fuzz_key {
+            return Ok(());
+        }
+        let mut key_len = self
+            .call_i32(exports, "key_len", None)
+            .map_err(|_| SideFuzzError::WasmModuleNoKey)?;
+        if key_len == 0 {
+            let input = vec![0; self.fuzz_len];
+            let _ = crate::black_box(self.count_instructions(&input));
+            key_len = self
+                .call_i32(exports, "key_len", None)
+                .map_err(|_| SideFuzzError::WasmModuleNoKey)?;
+        }
+        if key_len <= 0 {
+            return Err(SideFuzzError::WasmModuleNoKey);
+        }
+        let key_pointer = self
+            .call_i32(exports, "key_pointer", None)
+            .map_err(|_| SideFuzzError::WasmModuleNoKey)?;
+        let (key_pointer, key_len) = (key_pointer as u32 as usize, key_len as usize);
+        let memory_size = self.memory.data_size(&self.store);
+        if key_pointer + key_len > memory_size {
+            return Err(SideFuzzError::WasmModuleInputOutOfBounds(memory_size));
+        }
+        if self.options.fuzz_key {
+            return self.fuzz_key(key_pointer, key_len);
+        }
+
+        // Ok to unwrap, there is a key unless it is fuzzed
+        let key = self.options.key.clone().unwrap();
+        if key.len() != key_len {
+            return Err(SideFuzzError::KeyWrongSize(key.len(), key_len as i32));
+        }
+        self.write(key_pointer, &key)
+    }
+
+    // Fuzz the key in place of the input, the same way as `WasmModule::fuzz_key`
+    fn fuzz_key(&mut self, key_pointer: usize, key_len: usize) -> Result<(), SideFuzzError> {
+        if self.input_kind != InputKind::Bytes {
+            return Err(SideFuzzError::KeyFuzzingUnsupported(
+                "the message must be bytes".to_string(),
+            ));
+        }
+        let message = self
+            .options
+            .message
+            .clone()
+            .unwrap_or_else(|| vec![0; self.fuzz_len]);
+        let fits = message.len() == self.fuzz_len
+            || (self.actual_len.is_some() && message.len() < self.fuzz_len);
+        if !fits {
+            return Err(SideFuzzError::MessageWrongSize(message.len(), self.fuzz_len));
+        }
+        let _ = crate::black_box(self.count_instructions(&message));
+
+        self.fuzz_segments = vec![(key_pointer, key_len)];
+        self.public_segments = vec![false];
+        self.fuzz_len = key_len;
+        self.input_is_str = false;
+        self.actual_len = None;
+        Ok(())
+    }
+
+    // What the optional "input_kind" export, a constant or a function, asks for the input to be written as
+    fn input_kind(&mut self, exports: &Exports) -> Result<InputKind, SideFuzzError> {
+        let export = self.instance.get_export(&mut self.store, exports.name(INPUT_KIND));
+        let value = match export {
+            Some(Extern::Global(global)) => global.get(&mut self.store).i32().ok_or_else(|| {
+                SideFuzzError::WasmModuleBadManagedInput("input_kind must be an i32".to_string())
+            })?,
+            Some(_) => self.call_i32(exports, INPUT_KIND, None)?,
+            None => 0,
+        };
+        InputKind::from_export(value)
+    }
+
+    // Call an exported function returning a single i32 (or an i64 that fits in one), with an optional index argument
+    fn call_i32(
+        &mut self,
+        exports: &Exports,
+        name: &str,
+        index: Option<i32>,
+    ) -> Result<i32, SideFuzzError> {
+        let func = self
+            .instance
+            .get_func(&mut self.store, exports.name(name))
+            .ok_or(SideFuzzError::WasmModuleBadInputSegments)?;
+        let args: Vec<Val> = index.map(Val::I32).into_iter().collect();
+        let mut result = [Val::I32(0)];
+        self.store.data_mut().budget = u64::MAX;
+        self.store.set_epoch_deadline(NO_DEADLINE);
+        func.call(&mut self.store, &args, &mut result)?;
+        match result[0] {
+            Val::I32(inner) => Ok(inner),
+            Val::I64(inner) => {
+                i32::try_from(inner).map_err(|_| SideFuzzError::WasmModuleBadInputSegments)
+            }
+            _ => Err(SideFuzzError::WasmModuleBadInputSegments),
+        }
+    }
+
+    fn take_snapshot(&mut self) {
+        let mut globals = Vec::new();
+        let exports: Vec<Extern> = self
+            .instance
+            .exports(&mut self.store)
+            .map(|export| export.into_extern())
+            .collect();
+        for export in exports {
+            if let Some(global) = export.into_global() {
+                if global.ty(&self.store).mutability() == Mutability::Var {
+                    globals.push((global, global.get(&mut self.store)));
+                }
+            }
+        }
+        self.snapshot = Some((self.memory.data(&self.store).to_vec(), globals));
+    }
+
+    // Restore the instance to the snapshot, falling back to a full reboot if memory has grown
+    fn restore(&mut self) {
+        let (memory, globals) = match &self.snapshot {
+            Some(snapshot) => snapshot,
+            None => return self.reboot(),
+        };
+        let data = self.memory.data_mut(&mut self.store);
+        if data.len() != memory.len() {
+            return self.reboot();
+        }
+        data.copy_from_slice(memory);
+        for (global, value) in globals.iter() {
+            // Ok to expect, we only snapshot mutable globals and the value came from the same global.
+            global
+                .set(&mut self.store, value.clone())
+                .expect("Could not restore wasm global.");
+        }
+    }
+
+    fn reboot(&mut self) {
+        // This should be ok to expect here since the module has already been instantiated previously.
+        let new = Self::with_options(self.module.clone(), self.options.clone())
+            .expect("Could not reboot wasm module instance.");
+        *self = new;
+    }
+}
+
+impl Drop for JitModule {
+    fn drop(&mut self) {
+        if let Some(ticking) = &self.ticking {
+            ticking.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Target for JitModule {
+    fn count_instructions(&mut self, input: &[u8]) -> Result<u64, SideFuzzError> {
+        JitModule::count_instructions(self, input)
+    }
+
+    fn measure_time(&mut self) -> Result<FloatDuration, SideFuzzError> {
+        let input: Vec<u8> = (0..self.fuzz_len).map(|_| self.rng.gen()).collect();
+        let start_time = Instant::now();
+        self.count_instructions(&input)?;
+        let end_time = Instant::now();
+        Ok(end_time.float_duration_since(start_time).unwrap())
+    }
+
+    fn fuzz_len(&self) -> usize {
+        self.fuzz_len
+    }
+
+    fn input_segments(&self) -> Vec<usize> {
+        self.fuzz_segments.iter().map(|(_, len)| *len).collect()
+    }
+
+    fn input_is_str(&self) -> bool {
+        self.input_is_str
+    }
+
+    fn public_segments(&self) -> Vec<bool> {
+        self.public_segments.clone()
+    }
+
+    fn variable_len(&self) -> bool {
+        self.actual_len.is_some()
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Target>, SideFuzzError> {
+        let module = JitModule::with_options(self.module.clone(), self.options.clone())?;
+        Ok(Box::new(module))
+    }
+
+    // Instrumented re-runs use the interpreter, and are only ever compared with each other
+    fn wasm(&self) -> Option<(Vec<u8>, WasmOptions)> {
+        Some((self.module.clone(), self.options.clone()))
+    }
+}
+
+// The host functions of the sidefuzz module, and stubs for everything else `module` imports
+fn link(
+    engine: &Engine,
+    module: &Module,
+    stubs: &Stubs,
+    entropy: &Entropy,
+) -> Result<Linker<Counter>, SideFuzzError> {
+    let mut linker = Linker::new(engine);
+    // Modules may import the same function more than once
+    linker.allow_shadowing(true);
+    linker.func_wrap(
+        HOOK_MODULE,
+        "count",
+        |mut caller: Caller<'_, Counter>, instructions: i32| -> Result<()> {
+            let counter = caller.data_mut();
+            counter.count += instructions as u32 as u64;
+            if counter.count > counter.budget {
+                return Err(Error::new(BudgetExhausted));
+            }
+            Ok(())
+        },
+    )?;
+    // Secrets are only recorded while tracing memory, which the jit doesn't do
+    linker.func_wrap(
+        HOOK_MODULE,
+        crate::tracing::DECLARE_SECRET,
+        |_pointer: i32, _len: i32| {},
+    )?;
+    // Imported by every target built with `sidefuzz_target!`, for its `fuzz_batch`. The jit never runs batches, but
+    // the count is the same one the hook keeps.
+    linker.func_wrap(
+        HOOK_MODULE,
+        crate::persistent::INSTRUCTIONS,
+        |caller: Caller<'_, Counter>| -> i64 { caller.data().count as i64 },
+    )?;
+    add_stubs(&mut linker, module, stubs, entropy)?;
+    Ok(linker)
+}
+
+// Stub every function import, the jit engine provides no host functions of its own other than WASI's randomness
+fn add_stubs(
+    linker: &mut Linker<Counter>,
+    module: &Module,
+    stubs: &Stubs,
+    entropy: &Entropy,
+) -> Result<(), SideFuzzError> {
+    for import in module.imports() {
+        if import.module() == HOOK_MODULE {
+            continue;
+        }
+        let ty = match import.ty() {
+            ExternType::Func(ty) => ty,
+            _ => continue,
+        };
+        let behavior = stubs.behavior(import.module(), import.name());
+        let import_name = format!("{}.{}", import.module(), import.name());
+        let result_types: Vec<ValType> = ty.results().collect();
+        let entropy = entropy.clone();
+        linker.func_new(
+            import.module(),
+            import.name(),
+            ty,
+            move |_caller, _params, results| {
+                if behavior == StubBehavior::Trap {
+                    return Err(Error::msg(format!(
+                        "stubbed import {} was called",
+                        import_name
+                    )));
+                }
+                for (result, ty) in results.iter_mut().zip(result_types.iter()) {
+                    // Random floats are between 0 and 1, like the interpreter's
+                    let (constant, float) = match behavior {
+                        StubBehavior::Constant(constant) => (constant, constant as f64),
+                        StubBehavior::Random => {
+                            let bits = entropy.next_u64();
+                            (bits as i64, random_unit(bits))
+                        }
+                        _ => (0, 0.0),
+                    };
+                    *result = match ty {
+                        ValType::I32 => Val::I32(constant as i32),
+                        ValType::I64 => Val::I64(constant),
+                        ValType::F32 => Val::F32((float as f32).to_bits()),
+                        ValType::F64 => Val::F64(float.to_bits()),
+                        _ => Val::null(),
+                    };
+                }
+                Ok(())
+            },
+        )?;
+    }
+
+    // Defined last, replacing its stub
+    let entropy = entropy.clone();
+    linker.func_wrap(
+        "wasi_snapshot_preview1",
+        "random_get",
+        move |mut caller: Caller<'_, Counter>, buf_ptr: i32, buf_len: i32| -> i32 {
+            let mut buf = vec![0; buf_len as u32 as usize];
+            entropy.fill(&mut buf);
+            let memory = caller.get_export("memory").and_then(Extern::into_memory);
+            match memory.map(|memory| memory.write(&mut caller, buf_ptr as u32 as usize, &buf)) {
+                Some(Ok(())) => 0,
+                _ => 21, // ERRNO_FAULT
+            }
+        },
+    )?;
+    Ok(())
+}
+
+// Add a call to the count hook at the end of every run of instructions, with the number of instructions in it.
+// Every instruction is counted as one, including the one ending the run.
+pub(crate) fn instrument_counters(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut pending = 0;
+    instrument(bytes, COUNT_HOOKS, &[], |site| {
+        let mut insert = Insert::default();
+        let op = match site.op {
+            Some(op) => op,
+            None => {
+                pending = 0;
+                return insert;
+            }
+        };
+        pending += 1;
+        if RUN_ENDS.contains(&op.opcode) {
+            emit_i32_const(&mut insert.before, pending);
+            emit_call(&mut insert.before, site.hooks[0]);
+            pending = 0;
+        }
+        insert
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::*;
+
+    #[test]
+    fn instrument_counters_test() {
+        // (module (memory 1) (func (export "f") (result i32) i32.const 0 i32.load))
+        let module: &[u8] = &[
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic + version
+            0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // types
+            0x03, 0x02, 0x01, 0x00, // functions
+            0x05, 0x03, 0x01, 0x00, 0x01, // memory
+            0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // exports
+            0x0a, 0x09, 0x01, 0x07, 0x00, 0x41, 0x00, 0x28, 0x02, 0x00, 0x0b, // code
+        ];
+        let instrumented = instrument_counters(module).unwrap();
+        let sections = sections(&instrumented).unwrap();
+        let code = sections.iter().find(|s| s.id == SECTION_CODE).unwrap();
+
+        // The three instructions are counted once, just before the function ends
+        assert_eq!(
+            &instrumented[code.start..code.end],
+            &[0x01, 0x0b, 0x00, 0x41, 0x00, 0x28, 0x02, 0x00, 0x41, 0x03, 0x10, 0x00, 0x0b]
+        );
+    }
+
+    #[test]
+    fn link_test() {
+        // (module (import "sidefuzz" "instructions" (func (result i64)))), as imported by `sidefuzz_target!`
+        let module: &[u8] = &[
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic + version
+            0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7e, // types
+            0x02, 0x19, 0x01, 0x08, b's', b'i', b'd', b'e', b'f', b'u', b'z', b'z', 0x0c, b'i', b'n', b's', b't',
+            b'r', b'u', b'c', b't', b'i', b'o', b'n', b's', 0x00, 0x00, // imports
+        ];
+        let engine = Engine::default();
+        let module = Module::new(&engine, module).unwrap();
+        let stubs = Stubs::default();
+        let linker = link(&engine, &module, &stubs, &Entropy::new(stubs.seed())).unwrap();
+        let mut store = Store::new(&engine, Counter::default());
+        assert!(linker.instantiate(&mut store, &module).is_ok());
+    }
+
+    #[test]
+    fn fresh_memory_test() {
+        // Like the interpreter's, memory and globals are restored before every execution
+        for fuzz in [GROWING_GLOBAL, GROWING_MEMORY].iter() {
+            let counts = |fresh_memory: bool| {
+                let options = WasmOptions {
+                    fresh_memory,
+                    ..WasmOptions::default()
+                };
+                let mut module = JitModule::with_options(test_target(fuzz, &[]), options).unwrap();
+                (0..3)
+                    .map(|_| module.count_instructions(&[0]).unwrap())
+                    .collect::<Vec<u64>>()
+            };
+            let fresh = counts(true);
+            assert!(fresh.iter().all(|count| *count == fresh[0]));
+            let kept = counts(false);
+            assert!(kept[0] < kept[1] && kept[1] < kept[2]);
+        }
+    }
+}
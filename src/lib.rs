@@ -27,14 +27,90 @@
 //! sidefuzz fuzz ./target/wasm32-unknown-unknown/release/my_target.wasm # Fuzzing!
 //! ```
 
+// The library interface
+#[cfg(not(any(target_arch = "wasm32")))]
+mod api;
+#[cfg(not(any(target_arch = "wasm32")))]
+pub use api::{
+    CountOptions, DictionaryMutator, ErrorCategory, FuzzOutcome, FuzzReport, Mutator, Mutators, Observation, Score,
+    Scores, SideFuzz, SideFuzzBuilder, SideFuzzError, Verdict,
+};
+
 // An implementation of dudect
 #[cfg(not(any(target_arch = "wasm32")))]
-pub(crate) mod dudect;
+#[doc(hidden)]
+pub mod dudect;
+
+// Simulated measurement noise, added to counts before they are analyzed
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod noise;
+
+// Nonparametric statistical tests
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod nonparametric;
+
+// Warm-up discard and outlier rejection for samples
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod outliers;
+
+// Mutation strategies for the optimizer
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod mutators;
+
+// Fitness functions for the optimizer
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod scores;
+
+// Generating and repairing string inputs
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod strings;
+
+// Coverage guidance for the optimizer
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod coverage;
+
+// Directed input generation for branches on secret values
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod directed;
 
 // A genetic optimizer
 #[cfg(not(any(target_arch = "wasm32")))]
 pub(crate) mod optimizer;
 
+// Where the optimizer's individuals came from
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod lineage;
+
+// A surrogate model of the fitness function
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod surrogate;
+
+// A Prometheus endpoint with the statistics of a run
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod metrics;
+
+// Rust tests reproducing leaks found while fuzzing
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod reproducer;
+
+// How many measurements it takes to exploit a leak
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod exploitability;
+
+// How severe leaks are, for triage
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod severity;
+
+// Whether counts depend on the order inputs run in
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod order;
+
+// CSV logs of the scores of every generation
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod score_log;
+
 // The fuzz command
 #[cfg(not(any(target_arch = "wasm32")))]
 #[doc(hidden)]
@@ -45,15 +121,274 @@ pub mod fuzz;
 #[doc(hidden)]
 pub mod check;
 
+// The dudect (fixed vs random) command
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod fixed_vs_random;
+
+// The minimize command
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod minimize;
+
+// sidefuzz.toml configuration files
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod config;
+
+// The batch command
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod batch;
+
+// The new command, which creates a target project
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod new;
+
+// The gen-harness command, which creates a target project fuzzing a function of another crate
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod harness;
+
+// The build command, which compiles and validates a target
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod build;
+
+// The inspect command, which explains why a module can't be fuzzed
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod inspect;
+
+// The abi command, which describes the exports a target needs and writes them as a C header
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod abi;
+
+// The canary command, which checks that the black box helpers work with the installed rustc
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod canary;
+
+// The compare command, which runs the same inputs against two builds of a target
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod compare;
+
+// The bitmap command, which maps which input bits change the instruction count
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod bitmap;
+
+// The selftest command, which checks that instruction counts are deterministic
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod selftest;
+
+// The oracle command, which checks whether timing depends on the target's output
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod oracle;
+
+// The differential command, which compares two implementations of the same primitive
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod differential;
+
+// The serve and worker commands, which fuzz one target on several machines
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod distributed;
+
+// The regress command, which checks a new build of a target against stored findings
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod regress;
+
+// The info command, which prints what sidefuzz knows about a target
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod info;
+
+// Metadata embedded in targets by `sidefuzz_target!`
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod meta;
+
+// The replay command, which reanalyzes or re-executes a session log
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod replay;
+
 // The count command
 #[cfg(not(any(target_arch = "wasm32")))]
 #[doc(hidden)]
 pub mod count;
 
+// Structured input templates
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod template;
+
+// Fuzzing checkpoints
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod checkpoint;
+
+// Stopping fuzzing runs with Ctrl-C
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod interrupt;
+
+// Files of the leaks found by fuzzing runs
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod findings;
+
+// Self-describing archives of the leaks found, one directory each
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod archive;
+
+// Watching targets for rebuilds
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod watch;
+
+// Crash triage
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod crashes;
+
+// Fuzzing corpus directories
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod corpus;
+
+// Fuzzing targets and backends
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod target;
+
+// Diagnostic logging
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod logging;
+
+// Session logs of every execution
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod session;
+
+// Wall-clock timing measurement
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod timing;
+
+// Native shared library targets
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+pub(crate) mod native;
+
+// Measuring native executions on each OS
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+pub(crate) mod perf;
+
+// Parallel fuzzing workers
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod workers;
+
+// Machine-readable reports
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod report;
+
+// HTML reports
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod html;
+
+// Live statistics dashboard
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod tui;
+
+// Status lines for CI logs
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod ci;
+
+// SARIF reports
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod sarif;
+
+// The trace command
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod trace;
+
+// Module instrumentation
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod instrument;
+
+// Basic-block counters kept in the target's own memory
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod counters;
+
+// Dynamic taint tracking of the secret inputs, through instrumentation
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod taint;
+
+// Cache simulation over memory traces
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod cache;
+
+// Execution tracing
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod tracing;
+
 // Wasm Module wrapper
 #[cfg(not(any(target_arch = "wasm32")))]
 pub(crate) mod wasm;
 
+// Running wasm targets with a JIT compiler
+#[cfg(all(feature = "jit", not(any(target_arch = "wasm32"))))]
+pub(crate) mod jit;
+
+// Running inputs in batches, for targets with a persistent mode
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod persistent;
+
+// Instruction counting with fuel
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod fuel;
+
+// Wasm binary parsing and rewriting
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod binary;
+
+// Compatibility with wasm-bindgen modules
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod bindgen;
+
+// Compatibility with TinyGo modules
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod tinygo;
+
+// Compatibility with wasm components
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod component;
+
+// Inputs written as AssemblyScript objects
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod assemblyscript;
+
+// Wasm proposals the interpreter accepts
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod features;
+
+// Stubs for other host imports
+#[cfg(not(any(target_arch = "wasm32")))]
+#[doc(hidden)]
+pub mod stubs;
+
+// WASI preview1 shim
+#[cfg(not(any(target_arch = "wasm32")))]
+pub(crate) mod wasi;
+
 // Errors
 #[cfg(not(any(target_arch = "wasm32")))]
 pub(crate) mod errors;
@@ -72,26 +407,290 @@ pub(crate) mod util;
 /// a workaround implementation, that may have a too big performance overhead,
 /// depending on operation, or it may fail to properly avoid having code
 /// optimized out. It is good enough that it is used.
+///
+/// The value is read with a volatile read between two compiler fences, so it must be computed before the call, and
+/// memory written before it can't be moved after it. `sidefuzz canary` checks that this holds for the current rustc.
 #[inline(never)]
 pub fn black_box<D>(dummy: D) -> D {
-    unsafe {
-        let ret = std::ptr::read_volatile(&dummy);
-        std::mem::forget(dummy);
-        ret
+    fence();
+    let ret = unsafe { std::ptr::read_volatile(&dummy) };
+    std::mem::forget(dummy);
+    fence();
+    ret
+}
+
+/// Like `black_box`, for every element of a slice, such as a buffer a fuzzed function wrote its result into.
+/// Each element is read with a volatile read, so the whole slice must have been written.
+///
+/// Example:
+/// ```ignore
+/// let mut signature = [0; 64];
+/// sign(input, &mut signature);
+/// sidefuzz::black_box_slice(&signature);
+/// ```
+#[inline(never)]
+pub fn black_box_slice<T: Copy>(slice: &[T]) -> &[T] {
+    fence();
+    for item in slice.iter() {
+        unsafe {
+            std::ptr::read_volatile(item);
+        }
     }
+    fence();
+    slice
 }
 
-// Assign a 1024 byte vector to hold inputs
-lazy_static::lazy_static! {
-  static ref INPUT: Vec<u8> = vec![0; 1024];
+/// Like `black_box_slice`, but also writes every element back with a volatile write, so the optimizer can't assume
+/// anything about the slice's contents afterwards. Use it on constants that must not be folded into the code
+/// reading them, such as a fixed key.
+#[inline(never)]
+pub fn black_box_slice_mut<T: Copy>(slice: &mut [T]) -> &mut [T] {
+    fence();
+    for item in slice.iter_mut() {
+        unsafe {
+            let value = std::ptr::read_volatile(item);
+            std::ptr::write_volatile(item, value);
+        }
+    }
+    fence();
+    slice
+}
+
+/// A compiler fence, which keeps the optimizer from moving memory accesses across it. It adds no instructions.
+/// `sidefuzz_target!` puts one on each side of the fuzzed code, so none of it is moved out of the measured call.
+#[inline(always)]
+pub fn fence() {
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// The version of sidefuzz, embedded in targets by `sidefuzz_target!`
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// The version of the interface between sidefuzz and targets, raised whenever targets built for it can't be fuzzed
+// correctly by older versions of sidefuzz
+#[doc(hidden)]
+pub const META_ABI: &str = "1";
+
+// The length of the "sidefuzz.meta" section `sidefuzz_target!` embeds, made of `parts` one after the other
+#[doc(hidden)]
+pub const fn __meta_len(parts: &[&str]) -> usize {
+    let mut len = 0;
+    let mut i = 0;
+    while i < parts.len() {
+        len += parts[i].len();
+        i += 1;
+    }
+    len
 }
 
-// The actual input length (generally less than 1024)
+// The contents of the "sidefuzz.meta" section, `N` must be `__meta_len(parts)`
+#[doc(hidden)]
+pub const fn __meta_bytes<const N: usize>(parts: &[&str]) -> [u8; N] {
+    let mut out = [0; N];
+    let mut pos = 0;
+    let mut i = 0;
+    while i < parts.len() {
+        let bytes = parts[i].as_bytes();
+        let mut j = 0;
+        while j < bytes.len() {
+            out[pos] = bytes[j];
+            pos += 1;
+            j += 1;
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Define the `fuzz` function of a target, passing it `len` bytes of fuzzing input as a `&[u8]`.
+/// The result of `body` is passed through `black_box`, so it isn't optimized away.
+///
+/// The target crate's name and version, the sidefuzz version and `len` are embedded in a custom section of the
+/// module, which sidefuzz uses to label reports and to refuse targets built for a newer version of it.
+///
+/// Example:
+/// ```ignore
+/// sidefuzz::sidefuzz_target!(32, |input: &[u8]| my_hopefully_constant_fn(input));
+/// ```
+#[macro_export]
+macro_rules! sidefuzz_target {
+    ($len:expr, $body:expr) => {
+        #[no_mangle]
+        pub extern "C" fn fuzz() {
+            let input = $crate::fetch_input($len);
+            $crate::fence();
+            $crate::black_box(($body)(input));
+            $crate::fence();
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub extern "C" fn fuzz_batch(n: i32) {
+            $crate::run_batch(n, fuzz);
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        const _: () = {
+            const PARTS: &[&str] = &[
+                concat!("name=", env!("CARGO_PKG_NAME"), "\nversion=", env!("CARGO_PKG_VERSION"), "\nsidefuzz="),
+                $crate::VERSION,
+                "\nabi=",
+                $crate::META_ABI,
+                concat!("\ninput=bytes\ninput_len=", stringify!($len), "\n"),
+            ];
+            #[used]
+            #[link_section = "sidefuzz.meta"]
+            static META: [u8; $crate::__meta_len(PARTS)] = $crate::__meta_bytes(PARTS);
+        };
+    };
+}
+
+/// Define several named entry points in one target, each passed its own length of fuzzing input as a `&[u8]`.
+/// One build of a crate can then cover all of its functions, picking which is fuzzed with `--entry`.
+/// Names should start with `fuzz_` so sidefuzz lists them, and the first entry point is also exported as `fuzz`,
+/// so it is the one fuzzed when no `--entry` is given.
+///
+/// Each entry point gets its own `<name>_input_pointer` and `<name>_input_len` exports. The metadata embedded by
+/// `sidefuzz_target!` is embedded too, listing the entry points instead of a single input length.
+/// `--persistent` isn't supported for targets defined this way.
+///
+/// Example:
+/// ```ignore
+/// sidefuzz::sidefuzz_targets! {
+///     fuzz_sign: 32 => |key: &[u8]| my_hopefully_constant_sign(key),
+///     fuzz_verify: 64 => |signature: &[u8]| my_hopefully_constant_verify(signature),
+/// }
+/// ```
+#[macro_export]
+macro_rules! sidefuzz_targets {
+    ($first:ident: $first_len:expr => $first_body:expr $(, $name:ident: $len:expr => $body:expr)* $(,)?) => {
+        $crate::__sidefuzz_entry!($first, $first_len, $first_body);
+        $($crate::__sidefuzz_entry!($name, $len, $body);)*
+
+        #[no_mangle]
+        pub extern "C" fn fuzz() {
+            $first();
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        const _: () = {
+            const PARTS: &[&str] = &[
+                concat!("name=", env!("CARGO_PKG_NAME"), "\nversion=", env!("CARGO_PKG_VERSION"), "\nsidefuzz="),
+                $crate::VERSION,
+                "\nabi=",
+                $crate::META_ABI,
+                concat!(
+                    "\ninput=bytes\nentries=",
+                    stringify!($first), ":", stringify!($first_len),
+                    $(" ", stringify!($name), ":", stringify!($len),)*
+                    "\n"
+                ),
+            ];
+            #[used]
+            #[link_section = "sidefuzz.meta"]
+            static META: [u8; $crate::__meta_len(PARTS)] = $crate::__meta_bytes(PARTS);
+        };
+    };
+}
+
+// One entry point of `sidefuzz_targets!`, with its own input exports
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __sidefuzz_entry {
+    ($name:ident, $len:expr, $body:expr) => {
+        #[no_mangle]
+        pub extern "C" fn $name() {
+            let input = $crate::fetch_input($len);
+            $crate::fence();
+            $crate::black_box(($body)(input));
+            $crate::fence();
+        }
+
+        // Every entry point shares INPUT, which is allocated by whichever is called first.
+        // Only one entry point is fuzzed per instance, so that is always the one being fuzzed.
+        const _: () = {
+            #[export_name = concat!(stringify!($name), "_input_pointer")]
+            pub extern "C" fn input_pointer() -> i32 {
+                $crate::input_pointer()
+            }
+
+            #[export_name = concat!(stringify!($name), "_input_len")]
+            pub extern "C" fn input_len() -> i32 {
+                $len
+            }
+        };
+    };
+}
+
+// Holds inputs. It is allocated when the target first asks for input, and is never freed or moved.
+// It is written by the host and by `run_batch`, so it is kept as a pointer, and only borrowed by the `fetch_` functions.
+static mut INPUT: *mut u8 = std::ptr::NonNull::dangling().as_ptr();
+
+// The length of INPUT
 static mut INPUT_LEN: i32 = 0;
 
 // Is the input a string?
 static mut INPUT_IS_STR: bool = false;
 
+// The length of the current input for variable-length targets, written by the host before each execution.
+// It stays -1 for fixed-length targets, which is how the host tells them apart.
+#[doc(hidden)]
+#[export_name = "input_actual_len"]
+pub static mut INPUT_ACTUAL_LEN: i32 = -1;
+
+// The maximum number of separate inputs that can be requested with `fetch_inputs`, which hosts refuse more of
+pub(crate) const MAX_INPUTS: usize = 16;
+
+// The lengths of each input requested with `fetch_inputs`, stored one after the other in INPUT
+static mut INPUT_SEGMENTS: [i32; MAX_INPUTS] = [0; MAX_INPUTS];
+static mut INPUT_SEGMENT_COUNT: usize = 0;
+
+// Which inputs are public, set with `fetch_secret_and_public_inputs`. Every other input is secret.
+static mut INPUT_PUBLIC: [bool; MAX_INPUTS] = [false; MAX_INPUTS];
+
+// Holds the key fetched with `fetch_key`, KEY_LEN bytes long. It is allocated when the target first fetches it, then
+// written once by the host, and never freed or moved.
+static mut KEY: *mut u8 = std::ptr::NonNull::dangling().as_ptr();
+static mut KEY_LEN: i32 = 0;
+
+// The most inputs a single call of `fuzz_batch` can run
+const MAX_BATCH: usize = 64;
+
+// Inputs for `fuzz_batch`, one after the other. It is allocated when the host first asks for it, and is null until then.
+static mut INPUT_BATCH: *mut u8 = std::ptr::null_mut();
+
+// The instructions each run of a batch executed
+static mut BATCH_RESULTS: [i64; MAX_BATCH] = [0; MAX_BATCH];
+
+// What the current execution passed to `set_output`. OUTPUT_LEN is -1 until it is set, and is reset when the next
+// execution fetches its input.
+static mut OUTPUT: Vec<u8> = Vec::new();
+static mut OUTPUT_LEN: i32 = -1;
+
+// Provided by the host, which counts the instructions executed and records secret regions while tracing memory
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "sidefuzz")]
+extern "C" {
+    fn instructions() -> i64;
+    #[link_name = "declare_secret"]
+    fn declare_secret_region(pointer: i32, len: i32);
+}
+
+// Allocate `len` zeroed bytes, which are never freed or moved
+fn leak_buffer(len: usize) -> *mut u8 {
+    Box::leak(vec![0; len].into_boxed_slice()).as_mut_ptr()
+}
+
+// Allocate INPUT, the first time the target asks for input
+unsafe fn allocate_input(len: i32) {
+    INPUT = leak_buffer(len.max(0) as usize);
+}
+
+// INPUT, borrowed for the target to read
+unsafe fn input() -> &'static [u8] {
+    std::slice::from_raw_parts(INPUT, INPUT_LEN.max(0) as usize)
+}
+
 /// Get an input of the desired length.
 /// This function should be called with a constant unchanging len argument.
 /// Calling it with different lengths will result in invalid fuzzing.
@@ -109,15 +708,19 @@ static mut INPUT_IS_STR: bool = false;
 // 2. After it is called once, we call input_pointer and input_len from the host to get a stable pointer to INPUT.
 // 3. Fuzzing starts, we write data to INPUT from the host, then call the exported `fuzz` function.
 pub fn fetch_input(len: i32) -> &'static [u8] {
+    clear_output();
     // This use of unsafe since wasm is single-threaded and nothing else is accessing INPUT_LEN.
     unsafe {
         if INPUT_LEN == 0 {
             INPUT_LEN = len;
+            allocate_input(len);
+            // Native targets can't unwind across the host boundary, they just run once with an all zero input.
+            #[cfg(target_arch = "wasm32")]
             panic!("Input length successfully set. Panicking to unwind and stop execution.");
         }
     }
 
-    &INPUT[0..len as usize]
+    unsafe { &input()[0..len as usize] }
 }
 
 /// Get an input of the desired length, as a string.
@@ -134,16 +737,222 @@ pub fn fetch_input(len: i32) -> &'static [u8] {
 //
 // See `fetch_input` for some caveats on how this weird function is used
 pub fn fetch_str_input(len: i32) -> &'static str {
+    clear_output();
     // This use of unsafe since wasm is single-threaded and nothing else is accessing INPUT_LEN.
     unsafe {
         if INPUT_LEN == 0 {
             INPUT_LEN = len;
             INPUT_IS_STR = true;
+            allocate_input(len);
+            // Native targets can't unwind across the host boundary, they just run once with an all zero input.
+            #[cfg(target_arch = "wasm32")]
+            panic!("Input length successfully set. Panicking to unwind and stop execution.");
+        }
+    }
+
+    unsafe { std::str::from_utf8_unchecked(&input()[0..len as usize]) }
+}
+
+/// Get an input of varying length, up to `max_len` bytes.
+/// The fuzzer evolves the length of the input along with its contents,
+/// so this is useful for functions processing messages that can be any length.
+/// This function should be called with a constant unchanging max_len argument.
+///
+/// If used, `fetch_variable_input` should be used exclusively, and no other `fetch_*` function should be used.
+/// Inputs only vary in length with the wasm backend, native targets always get `max_len` bytes.
+///
+/// Example:
+/// ```ignore
+/// let message = sidefuzz::fetch_variable_input(256); // get up to 256 bytes of input
+/// sidefuzz::black_box(my_contant_time_fn(message));
+/// ```
+//
+// See `fetch_input` for some caveats on how this weird function is used
+pub fn fetch_variable_input(max_len: i32) -> &'static [u8] {
+    clear_output();
+    // This use of unsafe since wasm is single-threaded and nothing else is accessing INPUT_LEN.
+    unsafe {
+        if INPUT_LEN == 0 {
+            INPUT_LEN = max_len;
+            INPUT_ACTUAL_LEN = max_len;
+            allocate_input(max_len);
+            // Native targets can't unwind across the host boundary, they just run once with an all zero input.
+            #[cfg(target_arch = "wasm32")]
+            panic!("Input length successfully set. Panicking to unwind and stop execution.");
+        }
+
+        let len = INPUT_ACTUAL_LEN.clamp(0, max_len);
+        &input()[0..len as usize]
+    }
+}
+
+/// Get several independent inputs of the desired lengths.
+/// This is useful for functions taking more than one argument, such as a key and a message,
+/// since the fuzzer will then mutate each input separately.
+/// This function should be called with a constant unchanging lens argument.
+///
+/// If used, `fetch_inputs` should be used exclusively, and neither `fetch_input` nor `fetch_str_input` should be used.
+/// At most 16 inputs may be requested, and their total length is still limited by `--max-input-len`.
+///
+/// Example:
+/// ```ignore
+/// let inputs = sidefuzz::fetch_inputs(&[32, 64]); // a 32 byte key and a 64 byte message
+/// sidefuzz::black_box(my_contant_time_fn(inputs[0], inputs[1]));
+/// ```
+//
+// See `fetch_input` for some caveats on how this weird function is used
+pub fn fetch_inputs(lens: &[i32]) -> Vec<&'static [u8]> {
+    clear_output();
+    // This use of unsafe since wasm is single-threaded and nothing else is accessing INPUT_LEN.
+    unsafe {
+        if INPUT_LEN == 0 {
+            if lens.len() > MAX_INPUTS {
+                panic!("Too many inputs requested, at most 16 are supported.");
+            }
+            for (i, len) in lens.iter().enumerate() {
+                INPUT_SEGMENTS[i] = *len;
+            }
+            INPUT_SEGMENT_COUNT = lens.len();
+            INPUT_LEN = lens.iter().sum();
+            allocate_input(INPUT_LEN);
+            // Native targets can't unwind across the host boundary, they just run once with an all zero input.
+            #[cfg(target_arch = "wasm32")]
             panic!("Input length successfully set. Panicking to unwind and stop execution.");
         }
     }
 
-    unsafe { std::str::from_utf8_unchecked(&INPUT[0..len as usize]) }
+    let mut offset = 0;
+    let mut inputs = Vec::with_capacity(lens.len());
+    for len in lens {
+        inputs.push(unsafe { &input()[offset..offset + *len as usize] });
+        offset += *len as usize;
+    }
+    inputs
+}
+
+/// Get a secret input and a public input of the desired lengths.
+/// The public input is something an attacker knows or chooses, such as the message being signed with a secret key.
+/// It is randomized whenever timings are compared, so only a dependence on the secret input counts as a leak.
+///
+/// If used, `fetch_secret_and_public_inputs` should be used exclusively, and no other `fetch_` function should be used.
+///
+/// Example:
+/// ```ignore
+/// let (key, message) = sidefuzz::fetch_secret_and_public_inputs(32, 64);
+/// sidefuzz::black_box(my_contant_time_fn(key, message));
+/// ```
+//
+// See `fetch_input` for some caveats on how this weird function is used
+pub fn fetch_secret_and_public_inputs(secret_len: i32, public_len: i32) -> (&'static [u8], &'static [u8]) {
+    // This use of unsafe since wasm is single-threaded and nothing else is accessing INPUT_PUBLIC.
+    unsafe {
+        INPUT_PUBLIC[1] = true;
+    }
+    let inputs = fetch_inputs(&[secret_len, public_len]);
+    (inputs[0], inputs[1])
+}
+
+/// Get a fixed secret of the desired length, such as a key, which isn't fuzzed.
+/// It is written once, before fuzzing starts, from `--key-hex` or `--key-env`, and is all zero without either.
+/// The input from the other `fetch_` functions is then only the message, so a leak that is found depends on the
+/// message under that key, rather than on the key.
+/// This function should be called with a constant unchanging len argument. It can be called before or after
+/// fetching the input.
+///
+/// Example:
+/// ```ignore
+/// let key = sidefuzz::fetch_key(32);
+/// let message = sidefuzz::fetch_input(64);
+/// sidefuzz::black_box(my_hopefully_constant_encrypt(key, message));
+/// ```
+pub fn fetch_key(len: i32) -> &'static [u8] {
+    // This use of unsafe since wasm is single-threaded and nothing else is accessing KEY.
+    unsafe {
+        if KEY_LEN == 0 {
+            KEY_LEN = len.max(0);
+            KEY = leak_buffer(KEY_LEN as usize);
+        }
+        std::slice::from_raw_parts(KEY, KEY_LEN as usize)
+    }
+}
+
+/// Pass the result of the current execution to sidefuzz, such as a status code or the decrypted message.
+/// Targets that set an output can be checked for timing that depends on it with `sidefuzz oracle`, and
+/// `sidefuzz check` tells whether the two inputs it compares produce the same output.
+///
+/// It should be called on every execution, after fetching the input, since fetching the input clears the output.
+///
+/// Example:
+/// ```ignore
+/// let input = sidefuzz::fetch_input(32);
+/// let valid = my_hopefully_constant_verify(input);
+/// sidefuzz::set_output(&[valid as u8]);
+/// ```
+pub fn set_output(output: &[u8]) {
+    // This use of unsafe since wasm is single-threaded and nothing else is accessing OUTPUT.
+    unsafe {
+        OUTPUT.clear();
+        OUTPUT.extend_from_slice(output);
+        OUTPUT_LEN = output.len() as i32;
+    }
+}
+
+/// Tell sidefuzz that the memory of `secret` holds secrets, such as a key or the secret part of the input.
+/// `sidefuzz trace` then only reports memory access patterns that differ after the target read from a secret:
+/// accesses before that depend on public data, and don't leak anything even when they differ between inputs.
+///
+/// It should be called on every execution, before the secret is used. Outside of `sidefuzz trace` it does nothing.
+///
+/// Example:
+/// ```ignore
+/// let (secret, message) = sidefuzz::fetch_secret_and_public_inputs(32, 64);
+/// let key = expand_key(secret);
+/// sidefuzz::declare_secret(&key);
+/// sidefuzz::black_box(my_hopefully_constant_encrypt(&key, message));
+/// ```
+pub fn declare_secret<T>(secret: &[T]) {
+    #[cfg(target_arch = "wasm32")]
+    // This use of unsafe since the host only reads the arguments.
+    unsafe {
+        declare_secret_region(secret.as_ptr() as i32, std::mem::size_of_val(secret) as i32);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = secret;
+}
+
+// Forget the output of the previous execution
+fn clear_output() {
+    // This use of unsafe since wasm is single-threaded and nothing else is accessing OUTPUT_LEN.
+    unsafe {
+        OUTPUT_LEN = -1;
+    }
+}
+
+/// Run `fuzz` once for each of the first `n` inputs of a batch, for targets with a persistent mode.
+/// `sidefuzz_target!` exports `fuzz_batch` for you, targets defining `fuzz` themselves can export it like this:
+///
+/// Example:
+/// ```ignore
+/// #[no_mangle]
+/// pub extern "C" fn fuzz_batch(n: i32) {
+///     sidefuzz::run_batch(n, fuzz);
+/// }
+/// ```
+//
+// Each input is copied into INPUT before its run, so `fuzz` reads it through its usual `fetch_` call.
+// State left behind by one run is seen by the next, just like between separate executions.
+#[cfg(target_arch = "wasm32")]
+pub fn run_batch(n: i32, fuzz: extern "C" fn()) {
+    // This use of unsafe since wasm is single-threaded and nothing else is accessing INPUT or INPUT_BATCH.
+    unsafe {
+        let len = INPUT_LEN as usize;
+        for i in 0..(n.max(0) as usize).min(MAX_BATCH) {
+            std::ptr::copy_nonoverlapping(INPUT_BATCH.add(i * len), INPUT, len);
+            let start = instructions();
+            fuzz();
+            BATCH_RESULTS[i] = instructions() - start;
+        }
+    }
 }
 
 /// Get a pointer to the input array
@@ -153,7 +962,17 @@ pub fn fetch_str_input(len: i32) -> &'static str {
 #[doc(hidden)]
 #[no_mangle]
 pub extern "C" fn input_pointer() -> i32 {
-    INPUT.as_ptr() as i32
+    unsafe { INPUT as i32 }
+}
+
+/// Get the address of the input array, for native targets where addresses don't fit in an i32
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn input_address() -> usize {
+    unsafe { INPUT as usize }
 }
 
 /// Get the length of the input array
@@ -179,3 +998,135 @@ pub extern "C" fn input_is_str() -> i32 {
         return 0;
     }
 }
+
+/// Get the number of separate inputs
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn input_count() -> i32 {
+    unsafe {
+        if INPUT_SEGMENT_COUNT == 0 {
+            1
+        } else {
+            INPUT_SEGMENT_COUNT as i32
+        }
+    }
+}
+
+/// Get a pointer to one of the separate inputs
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn input_pointer_at(index: i32) -> i32 {
+    let offset: i32 = unsafe { INPUT_SEGMENTS[0..index as usize].iter().sum() };
+    unsafe { INPUT as i32 + offset }
+}
+
+/// Get the length of one of the separate inputs
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn input_len_at(index: i32) -> i32 {
+    unsafe {
+        if INPUT_SEGMENT_COUNT == 0 {
+            INPUT_LEN
+        } else {
+            INPUT_SEGMENTS[index as usize]
+        }
+    }
+}
+
+/// Check if one of the separate inputs is public
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn input_is_public_at(index: i32) -> i32 {
+    if unsafe { INPUT_PUBLIC[index as usize] } {
+        1
+    } else {
+        0
+    }
+}
+
+/// Get a pointer to the batch of inputs for `fuzz_batch`, allocating it the first time
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn input_batch_pointer() -> i32 {
+    unsafe {
+        if INPUT_BATCH.is_null() {
+            INPUT_BATCH = leak_buffer(INPUT_LEN.max(0) as usize * MAX_BATCH);
+        }
+        INPUT_BATCH as i32
+    }
+}
+
+/// Get a pointer to the instruction counts of the last batch
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn batch_results_pointer() -> i32 {
+    unsafe { BATCH_RESULTS.as_ptr() as i32 }
+}
+
+/// Get the most inputs a batch can hold
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn batch_capacity() -> i32 {
+    MAX_BATCH as i32
+}
+
+/// Get a pointer to the key fetched with `fetch_key`
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn key_pointer() -> i32 {
+    unsafe { KEY as i32 }
+}
+
+/// Get the length of the key fetched with `fetch_key`, or 0 if the target hasn't fetched it yet
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn key_len() -> i32 {
+    unsafe { KEY_LEN }
+}
+
+/// Get a pointer to the output set with `set_output`
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn output_pointer() -> i32 {
+    unsafe { OUTPUT.as_ptr() as i32 }
+}
+
+/// Get the length of the output set with `set_output`, or -1 if the last execution didn't set one
+/// This needs to be public so we can call it across host/wasm boundary,
+/// but it should be considered a "private" function to sidefuzz.
+/// It's API is not stable and may be subject to change
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn output_len() -> i32 {
+    unsafe { OUTPUT_LEN }
+}
@@ -0,0 +1,218 @@
+// Lineages of the optimizer's individuals, so a leak can be traced back through the generations that bred it
+//
+// Every individual the optimizer creates is recorded with its parents and the operators that made it, such as
+// crossover and the mutators that were applied. Individuals are known by a hash of their inputs. Only the lineages of
+// the current population are kept, and only their last `MAX_STEPS` to `2 * MAX_STEPS` generations of ancestry, so
+// the memory used doesn't grow with the length of a run. Clones keep the lineage they had, and the initial, seeded and
+// injected individuals have none.
+
+use crate::report::LineageStep;
+use crate::util::InputPair;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+// At least this many generations of ancestry are kept for each individual
+const MAX_STEPS: usize = 16;
+
+#[derive(Debug, Clone)]
+struct Step {
+    generation: u64,
+    individual: u64,
+    parents: Vec<u64>,
+    operators: Vec<String>,
+}
+
+// A step of ancestry, following the first parent back. Nodes are shared between the individuals that descend from them.
+#[derive(Debug)]
+struct Node {
+    step: Step,
+    parent: Option<Arc<Node>>,
+    depth: usize,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Lineages {
+    current: HashMap<u64, Arc<Node>>, // Of the current population
+    born: HashMap<u64, Arc<Node>>, // Of the individuals created since the population was last settled
+}
+
+impl Lineages {
+    // Record that `child` was made from `parents` in `generation` by `operators`.
+    // The child's inputs must be final, as they are what it is known by.
+    pub fn record(
+        &mut self,
+        generation: u64,
+        child: &InputPair,
+        parents: &[&InputPair],
+        operators: Vec<String>,
+    ) {
+        let parents: Vec<u64> = parents.iter().map(|parent| hash(parent)).collect();
+        // The child is mostly made of its first parent, so that is whose ancestry it continues
+        let parent = parents
+            .first()
+            .and_then(|parent| self.current.get(parent))
+            .cloned();
+        let individual = hash(child);
+        let step = Step {
+            generation,
+            individual,
+            parents,
+            operators,
+        };
+        self.born.insert(individual, Arc::new(link(step, parent)));
+    }
+
+    // Keep the lineages of `population`, the new current population, and forget the rest
+    pub fn settle(&mut self, population: &[InputPair]) {
+        let mut current = HashMap::with_capacity(population.len());
+        for individual in population.iter() {
+            let individual = hash(individual);
+            if let Some(node) = self
+                .born
+                .get(&individual)
+                .or_else(|| self.current.get(&individual))
+            {
+                current.insert(individual, node.clone());
+            }
+        }
+        self.current = current;
+        self.born.clear();
+    }
+
+    // The ancestry of `individual` in the current population, oldest generation first
+    pub fn of(&self, individual: &InputPair) -> Vec<LineageStep> {
+        let mut steps = Vec::new();
+        let mut node = self.current.get(&hash(individual));
+        while let Some(current) = node {
+            let step = &current.step;
+            steps.push(LineageStep {
+                generation: step.generation,
+                individual: format!("{:016x}", step.individual),
+                parents: step
+                    .parents
+                    .iter()
+                    .map(|parent| format!("{:016x}", parent))
+                    .collect(),
+                operators: step.operators.clone(),
+            });
+            node = current.parent.as_ref();
+        }
+        steps.reverse();
+        steps
+    }
+}
+
+// Add `step` to the ancestry of `parent`, cutting it back to `MAX_STEPS` once it gets twice that long
+fn link(step: Step, parent: Option<Arc<Node>>) -> Node {
+    let parent = match parent {
+        Some(parent) if parent.depth >= 2 * MAX_STEPS => {
+            Some(Arc::new(truncate(&parent, MAX_STEPS - 1)))
+        }
+        parent => parent,
+    };
+    Node {
+        step,
+        depth: parent.as_ref().map_or(1, |parent| parent.depth + 1),
+        parent,
+    }
+}
+
+// A copy of the most recent `keep` steps of `node`'s ancestry
+fn truncate(node: &Node, keep: usize) -> Node {
+    let mut steps = Vec::with_capacity(keep);
+    let mut current = Some(node);
+    while let Some(node) = current.filter(|_| steps.len() < keep) {
+        steps.push(node.step.clone());
+        current = node.parent.as_deref();
+    }
+    let mut copy: Option<Arc<Node>> = None;
+    for step in steps.into_iter().rev() {
+        copy = Some(Arc::new(link(step, copy)));
+    }
+    // Ok to unwrap, `keep` is above zero and the node itself is always copied
+    Arc::try_unwrap(copy.unwrap()).unwrap()
+}
+
+fn hash(individual: &InputPair) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    individual.first.hash(&mut hasher);
+    individual.second.hash(&mut hasher);
+    hasher.finish()
+}
+
+// One line per step of a lineage, oldest first
+pub fn format_lineage(lineage: &[LineageStep]) -> String {
+    let mut lines = Vec::new();
+    for step in lineage {
+        let parents = if step.parents.is_empty() {
+            String::new()
+        } else {
+            format!(" from {}", step.parents.join(" and "))
+        };
+        lines.push(format!(
+            "generation {}: {}{} by {}",
+            step.generation,
+            step.individual,
+            parents,
+            step.operators.join(", ")
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn individual(byte: u8) -> InputPair {
+        InputPair {
+            first: vec![byte],
+            second: vec![0],
+            is_str: false,
+        }
+    }
+
+    #[test]
+    fn lineage_test() {
+        let mut lineages = Lineages::default();
+        let (one, two, child) = (individual(1), individual(2), individual(3));
+        lineages.record(1, &child, &[&one, &two], vec!["crossover".to_string()]);
+        lineages.settle(&[child.clone(), one.clone()]);
+        assert!(lineages.of(&one).is_empty());
+
+        let grandchild = individual(4);
+        lineages.record(
+            2,
+            &grandchild,
+            &[&child, &child],
+            vec!["crossover".to_string(), "bitflip".to_string()],
+        );
+        lineages.settle(&[grandchild.clone(), child.clone()]);
+        let lineage = lineages.of(&grandchild);
+        assert_eq!(lineage.len(), 2);
+        assert_eq!(
+            lineage[0].parents,
+            vec![
+                format!("{:016x}", hash(&one)),
+                format!("{:016x}", hash(&two))
+            ]
+        );
+        assert_eq!(lineage[1].individual, format!("{:016x}", hash(&grandchild)));
+        assert_eq!(lineage[1].operators, vec!["crossover", "bitflip"]);
+        assert!(format_lineage(&lineage).ends_with("by crossover, bitflip"));
+
+        // Long ancestries are cut back, keeping the most recent generations
+        let mut parent = grandchild;
+        for generation in 3..100 {
+            let child = individual(generation as u8);
+            lineages.record(generation, &child, &[&parent], vec!["byte".to_string()]);
+            lineages.settle(&[child.clone()]);
+            parent = child;
+        }
+        let lineage = lineages.of(&parent);
+        assert!(lineage.len() >= MAX_STEPS && lineage.len() <= 2 * MAX_STEPS);
+        assert_eq!(lineage.last().unwrap().generation, 99);
+    }
+}
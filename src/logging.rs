@@ -0,0 +1,160 @@
+// Diagnostic logs, for debugging sidefuzz and the targets it runs
+//
+// Progress and results are written by `Reporter`, logs are for what happens underneath, such as where a target keeps
+// its input. Warnings are logged by default and each -v logs one more level. Filters in the style of RUST_LOG pick
+// levels for single modules, such as "sidefuzz::wasm=debug". Every execution can also be logged, to the
+// "sidefuzz::executions" target at the trace level, which is only turned on by naming it in a filter since it slows
+// fuzzing down so much.
+
+use crate::errors::SideFuzzError;
+use crate::target::Target;
+use crate::wasm::WasmOptions;
+use float_duration::FloatDuration;
+use log::LevelFilter;
+
+// The log target of every execution
+pub(crate) const EXECUTIONS: &str = "sidefuzz::executions";
+
+// Start logging, to stderr or to `file`. Filters from RUST_LOG are applied before `filters`, so the command line wins.
+pub fn init(
+    verbosity: u64,
+    filters: Option<&str>,
+    file: Option<&str>,
+) -> Result<(), SideFuzzError> {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(level(verbosity));
+    builder.filter_module(EXECUTIONS, LevelFilter::Off);
+    if let Ok(env) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&env);
+    }
+    if let Some(filters) = filters {
+        builder.parse_filters(filters);
+    }
+    if let Some(path) = file {
+        let file = std::fs::File::create(path).map_err(SideFuzzError::CouldNotWriteFile)?;
+        builder.target(env_logger::Target::Pipe(Box::new(file)));
+        builder.write_style(env_logger::WriteStyle::Never);
+    }
+    // A logger set by a program using sidefuzz as a library is kept
+    let _ = builder.try_init();
+    Ok(())
+}
+
+fn level(verbosity: u64) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+// Whether executions are logged, which is only checked once per target as it is in the way of every execution
+pub(crate) fn executions_enabled() -> bool {
+    log::log_enabled!(target: EXECUTIONS, log::Level::Trace)
+}
+
+fn log_execution(input: &[u8], result: Result<&u64, &SideFuzzError>) {
+    match result {
+        Ok(count) => {
+            log::trace!(target: EXECUTIONS, "{}: {} instructions", hex::encode(input), count)
+        }
+        Err(err) => log::trace!(target: EXECUTIONS, "{}: {}", hex::encode(input), err),
+    }
+}
+
+// A target whose executions are all logged
+pub(crate) struct LoggingTarget {
+    inner: Box<dyn Target>,
+}
+
+impl LoggingTarget {
+    pub fn new(inner: Box<dyn Target>) -> Self {
+        LoggingTarget { inner }
+    }
+}
+
+impl Target for LoggingTarget {
+    fn count_instructions(&mut self, input: &[u8]) -> Result<u64, SideFuzzError> {
+        let result = self.inner.count_instructions(input);
+        log_execution(input, result.as_ref());
+        result
+    }
+
+    fn run_with_output(&mut self, input: &[u8]) -> Result<(u64, Option<Vec<u8>>), SideFuzzError> {
+        let result = self.inner.run_with_output(input);
+        log_execution(input, result.as_ref().map(|(count, _)| count));
+        result
+    }
+
+    fn count_batch(&mut self, inputs: &[&[u8]]) -> Vec<Result<u64, SideFuzzError>> {
+        let results = self.inner.count_batch(inputs);
+        for (input, result) in inputs.iter().zip(results.iter()) {
+            log_execution(input, result.as_ref());
+        }
+        results
+    }
+
+    fn batch_capacity(&self) -> usize {
+        self.inner.batch_capacity()
+    }
+
+    fn measure_time(&mut self) -> Result<FloatDuration, SideFuzzError> {
+        self.inner.measure_time()
+    }
+
+    fn fuzz_len(&self) -> usize {
+        self.inner.fuzz_len()
+    }
+
+    fn input_segments(&self) -> Vec<usize> {
+        self.inner.input_segments()
+    }
+
+    fn input_is_str(&self) -> bool {
+        self.inner.input_is_str()
+    }
+
+    fn public_segments(&self) -> Vec<bool> {
+        self.inner.public_segments()
+    }
+
+    fn variable_len(&self) -> bool {
+        self.inner.variable_len()
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.inner.set_seed(seed)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Target>, SideFuzzError> {
+        Ok(Box::new(LoggingTarget {
+            inner: self.inner.try_clone()?,
+        }))
+    }
+
+    fn parallel(&self) -> bool {
+        self.inner.parallel()
+    }
+
+    fn wasm(&self) -> Option<(Vec<u8>, WasmOptions)> {
+        self.inner.wasm()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logging_test() {
+        assert_eq!(level(0), LevelFilter::Warn);
+        assert_eq!(level(2), LevelFilter::Debug);
+        assert_eq!(level(7), LevelFilter::Trace);
+
+        // Executions aren't logged unless a filter names them
+        init(3, None, None).unwrap();
+        assert!(log::log_enabled!(log::Level::Trace));
+        assert!(!executions_enabled());
+    }
+}
@@ -1,10 +1,733 @@
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgMatches, ErrorKind, SubCommand};
 use failure::Error;
 
-
+use sidefuzz::abi::Abi;
+use sidefuzz::batch::Batch;
+use sidefuzz::bitmap::Bitmap;
+use sidefuzz::build::Build;
+use sidefuzz::canary::Canary;
+use sidefuzz::cache::CacheConfig;
 use sidefuzz::check::Check;
+use sidefuzz::compare::Compare;
+use sidefuzz::config::{Config, CONFIG_FILE};
 use sidefuzz::count::Count;
+use sidefuzz::differential::Differential;
+use sidefuzz::distributed::Worker;
+use sidefuzz::dudect::Analysis;
+use sidefuzz::features::WasmFeatures;
+use sidefuzz::fixed_vs_random::FixedVsRandom;
 use sidefuzz::fuzz::Fuzz;
+use sidefuzz::harness::GenHarness;
+use sidefuzz::info::Info;
+use sidefuzz::inspect::Inspect;
+use sidefuzz::meta::TargetMeta;
+use sidefuzz::minimize::Minimize;
+use sidefuzz::new::NewTarget;
+use sidefuzz::oracle::Oracle;
+use sidefuzz::regress::Regress;
+use sidefuzz::replay::Replay;
+use sidefuzz::selftest::SelfTest;
+use sidefuzz::report::{Format, Region, Reporter};
+use sidefuzz::stubs::Stubs;
+use sidefuzz::target::TargetOptions;
+use sidefuzz::trace::Trace;
+use sidefuzz::{CountOptions, DictionaryMutator, Mutators, Scores};
+use sidefuzz::tracing::TraceKind;
+use sidefuzz::tui::parse_duration;
+
+// Arguments controlling how results are reported
+fn report_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["text", "json", "sarif"])
+            .default_value("text")
+            .help("output format for the result"),
+        Arg::with_name("output")
+            .long("output")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("write the result to a file instead of stdout"),
+        Arg::with_name("report")
+            .long("report")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("also write an HTML report with charts of the measurements"),
+        Arg::with_name("tui")
+            .long("tui")
+            .help("show live statistics instead of printing progress line by line"),
+        Arg::with_name("ci")
+            .long("ci")
+            .conflicts_with("tui")
+            .help("print a single status line at most every --ci-interval seconds instead of progress, for CI logs"),
+        Arg::with_name("ci-interval")
+            .long("ci-interval")
+            .takes_value(true)
+            .value_name("SECONDS")
+            .help("how often --ci prints a status line [default: 60]"),
+        Arg::with_name("metrics-addr")
+            .long("metrics-addr")
+            .takes_value(true)
+            .value_name("ADDR")
+            .help("serve live statistics as Prometheus metrics at this address, such as 0.0.0.0:9100"),
+    ]
+}
+
+// Arguments for subcommands whose result isn't a single report
+fn result_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["text", "json"])
+            .default_value("text")
+            .help("output format for the results"),
+        Arg::with_name("output")
+            .long("output")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("write the results to a file instead of stdout"),
+    ]
+}
+
+fn result_reporter(sub_match: &ArgMatches) -> Reporter {
+    // Ok to unwrap, clap has already validated the format.
+    let format: Format = sub_match.value_of("format").unwrap().parse().unwrap();
+    let output = sub_match.value_of("output").map(|s| s.to_string());
+    Reporter::new(format, output)
+}
+
+fn reporter(sub_match: &ArgMatches) -> Reporter {
+    // Ok to unwrap, clap has already validated the format.
+    let format: Format = sub_match.value_of("format").unwrap().parse().unwrap();
+    let output = sub_match.value_of("output").map(|s| s.to_string());
+    let mut reporter = Reporter::new(format, output);
+    // Ok to unwrap, every subcommand with report arguments has a target file.
+    reporter.set_target(sub_match.value_of("wasm-file").unwrap().to_string());
+    if let Some(report) = sub_match.value_of("report") {
+        reporter.set_html(report.to_string());
+    }
+    reporter.set_tui(sub_match.is_present("tui"));
+    if sub_match.is_present("ci") {
+        let interval = match sub_match.value_of("ci-interval").map(str::parse::<u64>) {
+            None => sidefuzz::ci::DEFAULT_INTERVAL,
+            Some(Ok(interval)) if interval > 0 => interval,
+            _ => {
+                println!("Error: --ci-interval must be a positive number of seconds");
+                std::process::exit(1);
+            }
+        };
+        reporter.set_ci(std::time::Duration::from_secs(interval));
+    }
+    if let Some(addr) = sub_match.value_of("metrics-addr") {
+        if let Err(err) = reporter.set_metrics_addr(addr) {
+            println!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+    // Targets that can't be read fail to load with a better error later on
+    if let Ok(module) = std::fs::read(sub_match.value_of("wasm-file").unwrap()) {
+        reporter.set_module(&module);
+    }
+    if sub_match.is_present("fuzz-key") {
+        reporter.set_region(Region::Key);
+    } else if sub_match.is_present("key-hex") || sub_match.is_present("key-env") {
+        reporter.set_region(Region::Message);
+    }
+    if sub_match.value_of("backend") == Some("wasm") {
+        reporter.set_wasm_features(wasm_features(sub_match).enabled());
+        // Modules that can't be inspected fail to load with a better error later on
+        let bytes = std::fs::read(sub_match.value_of("wasm-file").unwrap()).unwrap_or_default();
+        let mut warnings = match sidefuzz::inspect::inspect(&bytes) {
+            Ok(inspection) => sidefuzz::stubs::nondeterminism_warnings(&inspection.imports),
+            Err(_) => Vec::new(),
+        };
+        if let Some(meta) = TargetMeta::find(&bytes) {
+            warnings.extend(meta.warnings());
+            reporter.set_target_meta(meta);
+        }
+        reporter.set_warnings(warnings);
+    }
+    if sub_match.value_of("measure") == Some("time") {
+        let cpu = sub_match.value_of("pin-cpu").and_then(|cpu| cpu.parse().ok());
+        reporter.add_warnings(sidefuzz::timing::machine_warnings(cpu));
+    }
+    reporter
+}
+
+// Arguments controlling how the target is run and measured
+fn target_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("backend")
+            .long("backend")
+            .takes_value(true)
+            .possible_values(&["wasm", "native"])
+            .default_value("wasm")
+            .help("run a wasm module, or a native shared library measured with performance counters (Linux, Windows and macOS)"),
+        Arg::with_name("engine")
+            .long("engine")
+            .takes_value(true)
+            .possible_values(&["interpreter", "jit"])
+            .default_value("interpreter")
+            .help("run wasm targets in the wasmi interpreter, or compile them with wasmtime (needs the jit feature). Counts from different engines can't be compared"),
+        Arg::with_name("measure")
+            .long("measure")
+            .takes_value(true)
+            .possible_values(&["instructions", "time"])
+            .default_value("instructions")
+            .help("count instructions executed, or time executions in nanoseconds"),
+        Arg::with_name("pin-cpu")
+            .long("pin-cpu")
+            .takes_value(true)
+            .value_name("N")
+            .help("take timings on cpu N with --measure time, instead of the cpu sidefuzz starts on (Linux only)"),
+        Arg::with_name("max-instructions")
+            .long("max-instructions")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1000000000")
+            .help("stop executions that run more than this many instructions, counting them as crashes (wasm only)"),
+        Arg::with_name("max-input-len")
+            .long("max-input-len")
+            .takes_value(true)
+            .value_name("BYTES")
+            .default_value("1024")
+            .help("refuse targets that ask for more than this many bytes of input"),
+        Arg::with_name("wasm-features")
+            .long("wasm-features")
+            .takes_value(true)
+            .value_name("LIST")
+            .help("turn wasm proposals on or off, such as -bulk-memory,-sign-extension (all supported ones are on by default)"),
+        Arg::with_name("timeout")
+            .long("timeout")
+            .takes_value(true)
+            .value_name("MS")
+            .help("stop executions that run for longer than this many milliseconds, counting them as hangs (native executions are only reported once they finish)"),
+        Arg::with_name("persistent")
+            .long("persistent")
+            .help("run many inputs per call into the module, through its fuzz_batch export (wasm only)"),
+        Arg::with_name("fresh-memory")
+            .long("fresh-memory")
+            .conflicts_with("persistent")
+            .help("restore memory to its state after instantiation before every execution, so executions can't affect each other, at some cost in speed (wasm only)"),
+        Arg::with_name("entry")
+            .long("entry")
+            .takes_value(true)
+            .value_name("EXPORT")
+            .help("fuzz this export instead of fuzz, for modules with several entry points such as fuzz_sign and fuzz_verify (wasm only)"),
+        Arg::with_name("tinygo")
+            .long("tinygo")
+            .help("start the Go runtime before fuzzing, for TinyGo modules that aren't recognized as such (wasm only)"),
+        Arg::with_name("key-hex")
+            .long("key-hex")
+            .takes_value(true)
+            .value_name("HEX")
+            .conflicts_with("key-env")
+            .help("fixed key for targets that fetch one with sidefuzz::fetch_key, in hexadecimal (wasm only)"),
+        Arg::with_name("key-env")
+            .long("key-env")
+            .takes_value(true)
+            .value_name("VAR")
+            .help("like --key-hex, reading the key from this environment variable, so it stays out of the command line"),
+        Arg::with_name("fuzz-key")
+            .long("fuzz-key")
+            .conflicts_with_all(&["key-hex", "key-env"])
+            .help("fuzz the key fetched with sidefuzz::fetch_key instead of the input, which is fixed, to find timing that depends on the key (wasm only)"),
+        Arg::with_name("message-hex")
+            .long("message-hex")
+            .takes_value(true)
+            .value_name("HEX")
+            .requires("fuzz-key")
+            .help("the fixed input with --fuzz-key, in hexadecimal (all zero by default)"),
+    ]
+    .into_iter()
+    .chain(stub_args())
+    .collect()
+}
+
+// Recording every execution, for subcommands that run a single target
+fn record_arg() -> Arg<'static, 'static> {
+    Arg::with_name("record")
+        .long("record")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("log every input executed and its measurement to a session file, for the replay subcommand")
+}
+
+// Archiving leaks, for subcommands that find them between two inputs
+fn archive_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("archive")
+            .long("archive")
+            .takes_value(true)
+            .value_name("DIR")
+            .default_value("findings")
+            .help("directory to archive each leak found to, in a subdirectory named after a hash of the target and inputs"),
+        Arg::with_name("no-archive")
+            .long("no-archive")
+            .help("don't archive leaks found"),
+    ]
+}
+
+fn token_arg(help: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name("token-env")
+        .long("token-env")
+        .takes_value(true)
+        .value_name("VAR")
+        .help(help)
+}
+
+// The shared token from --token-env
+fn token(sub_match: &ArgMatches) -> Option<String> {
+    let var = sub_match.value_of("token-env")?;
+    match std::env::var(var) {
+        Ok(token) if !token.is_empty() => Some(token),
+        _ => {
+            println!("Error: --token-env names {}, which isn't set", var);
+            std::process::exit(1);
+        }
+    }
+}
+
+// The fixed key from --key-hex or --key-env
+fn key(sub_match: &ArgMatches) -> Option<Vec<u8>> {
+    let (key, flag) = match (sub_match.value_of("key-hex"), sub_match.value_of("key-env")) {
+        (Some(key), _) => (key.to_string(), "--key-hex"),
+        (None, Some(var)) => match std::env::var(var) {
+            Ok(key) => (key, "--key-env"),
+            Err(_) => {
+                println!("Error: --key-env names {}, which isn't set", var);
+                std::process::exit(1);
+            }
+        },
+        (None, None) => return None,
+    };
+    match hex::decode(key.trim()) {
+        Ok(key) => Some(key),
+        Err(_) => {
+            println!("Error: the key from {} must be hexadecimal", flag);
+            std::process::exit(1);
+        }
+    }
+}
+
+// How the reproducer test runs the target, the same way it is fuzzed. A key from --key-env is read from the same
+// variable when the test runs, instead of being written into it.
+fn count_options(sub_match: &ArgMatches) -> CountOptions {
+    let options = target_options(sub_match);
+    let key_env = sub_match
+        .value_of("key-env")
+        .filter(|_| !sub_match.is_present("key-hex"))
+        .map(|var| var.to_string());
+    CountOptions {
+        entry: options.entry,
+        key: if key_env.is_some() { None } else { options.key },
+        key_env,
+        fuzz_key: options.fuzz_key,
+        message: options.message,
+    }
+}
+
+// The archive directory, None with --no-archive
+fn archive(sub_match: &ArgMatches) -> Option<String> {
+    if sub_match.is_present("no-archive") {
+        return None;
+    }
+    // Ok to unwrap, there is a default archive directory.
+    Some(sub_match.value_of("archive").unwrap().to_string())
+}
+
+// Arguments of the fuzz subcommand, which serve shares
+fn fuzz_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("wasm-file")
+            .help("wasm file fuzzing target")
+            .required(true)
+            .index(1),
+        Arg::with_name("workers")
+            .long("workers")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1")
+            .help("number of worker threads used to evaluate candidate inputs"),
+        Arg::with_name("seed")
+            .long("seed")
+            .takes_value(true)
+            .value_name("SEED")
+            .help("seed for the random number generator, to reproduce a previous run"),
+        Arg::with_name("checkpoint-file")
+            .long("checkpoint-file")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("periodically save fuzzing progress to this file"),
+        Arg::with_name("resume")
+            .long("resume")
+            .requires("checkpoint-file")
+            .help("continue the fuzzing run saved in --checkpoint-file"),
+        Arg::with_name("template")
+            .long("template")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("JSON template describing the structure of the input"),
+        Arg::with_name("corpus")
+            .long("corpus")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("directory of inputs to start from, interesting inputs found are saved to it"),
+        Arg::with_name("crashes")
+            .long("crashes")
+            .takes_value(true)
+            .value_name("DIR")
+            .default_value("crashes")
+            .help("directory to save an input reproducing each distinct crash to"),
+        Arg::with_name("reproducer")
+            .long("reproducer")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("if a leak is found, save a Rust #[test] reproducing it to this file, for the target crate's test suite"),
+        Arg::with_name("findings")
+            .long("findings")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("if a leak is found, record it in this JSON findings file, for the regress subcommand"),
+        Arg::with_name("hangs")
+            .long("hangs")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("directory to save inputs that time out to, instead of the crashes directory"),
+        Arg::with_name("max-generations")
+            .long("max-generations")
+            .takes_value(true)
+            .value_name("N")
+            .help("give up after this many generations without finding a leak"),
+        Arg::with_name("max-time")
+            .long("max-time")
+            .takes_value(true)
+            .value_name("DURATION")
+            .help("give up after fuzzing for this long without finding a leak, such as 30m or 1h30m"),
+        Arg::with_name("mutators")
+            .long("mutators")
+            .takes_value(true)
+            .value_name("NAME=WEIGHT,...")
+            .help("mutation strategies to pick from: byte (the default), bitflip, swap, arith, duplicate, dictionary and char (for string inputs), with optional weights"),
+        Arg::with_name("score")
+            .long("score")
+            .takes_value(true)
+            .value_name("NAME=WEIGHT,...")
+            .help("what input pairs are scored by: instructions (the difference in instructions executed, the default), memory or branches (the edit distance between the addresses accessed or blocks entered, wasm only), with optional weights"),
+        Arg::with_name("optimizer")
+            .long("optimizer")
+            .takes_value(true)
+            .possible_values(&["genetic", "hillclimb", "annealing"])
+            .default_value("genetic")
+            .help("how to search for input pairs: a genetic algorithm, hill climbing, or simulated annealing"),
+        Arg::with_name("score-log")
+            .long("score-log")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("write the best and average score of every generation to a CSV file"),
+        Arg::with_name("surrogate")
+            .long("surrogate")
+            .help("only run the children a model fitted to earlier scores predicts to be fittest, for slow targets (genetic optimizer only)"),
+        Arg::with_name("islands")
+            .long("islands")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1")
+            .help("split the population into this many islands that evolve separately, to explore several optima at once"),
+        Arg::with_name("migration-interval")
+            .long("migration-interval")
+            .takes_value(true)
+            .value_name("GENERATIONS")
+            .default_value("50")
+            .help("copy the best individuals of each island to the next one this often, 0 to never migrate"),
+        Arg::with_name("restart-diversity")
+            .long("restart-diversity")
+            .takes_value(true)
+            .value_name("FRACTION")
+            .default_value("0.02")
+            .help("replace half of an island with random individuals once the mean fraction of bits that differ between its individuals falls below this, 0 to never restart"),
+        Arg::with_name("coverage")
+            .long("coverage")
+            .help("also evolve input pairs that reach new parts of the target, to escape local optima (wasm only)"),
+        Arg::with_name("directed")
+            .long("directed")
+            .help("when stuck, search for inputs flipping the secret-dependent branches of the best input (wasm only)"),
+        Arg::with_name("dict")
+            .long("dict")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("AFL dictionary of tokens for the dictionary mutator to write into inputs"),
+        Arg::with_name("charset")
+            .long("charset")
+            .takes_value(true)
+            .possible_values(&["utf8", "ascii"])
+            .default_value("utf8")
+            .help("characters string inputs may contain: any valid UTF-8, or printable ASCII only"),
+        record_arg(),
+    ]
+    .into_iter()
+    .chain(archive_args())
+    .collect()
+}
+
+fn target_options(sub_match: &ArgMatches) -> TargetOptions {
+    // Ok to unwrap, clap has already validated the values.
+    TargetOptions {
+        backend: sub_match.value_of("backend").unwrap().parse().unwrap(),
+        engine: sub_match.value_of("engine").unwrap().parse().unwrap(),
+        measure: sub_match.value_of("measure").unwrap().parse().unwrap(),
+        stubs: stubs(sub_match),
+        max_instructions: match sub_match.value_of("max-instructions").unwrap().parse() {
+            Ok(max_instructions) => Some(max_instructions),
+            Err(_) => {
+                println!("Error: --max-instructions must be an unsigned 64-bit integer");
+                std::process::exit(1);
+            }
+        },
+        max_input_len: match sub_match.value_of("max-input-len").unwrap().parse() {
+            Ok(max_input_len) => Some(max_input_len),
+            Err(_) => {
+                println!("Error: --max-input-len must be a number of bytes");
+                std::process::exit(1);
+            }
+        },
+        timeout: match sub_match.value_of("timeout").map(str::parse) {
+            Some(Ok(timeout)) => Some(std::time::Duration::from_millis(timeout)),
+            Some(Err(_)) => {
+                println!("Error: --timeout must be a number of milliseconds");
+                std::process::exit(1);
+            }
+            None => None,
+        },
+        features: wasm_features(sub_match),
+        persistent: sub_match.is_present("persistent"),
+        entry: sub_match.value_of("entry").map(|s| s.to_string()),
+        tinygo: sub_match.is_present("tinygo"),
+        key: key(sub_match),
+        fuzz_key: sub_match.is_present("fuzz-key"),
+        message: match sub_match.value_of("message-hex").map(hex::decode) {
+            Some(Ok(message)) => Some(message),
+            Some(Err(_)) => {
+                println!("Error: --message-hex must be hexadecimal");
+                std::process::exit(1);
+            }
+            None => None,
+        },
+        fresh_memory: sub_match.is_present("fresh-memory"),
+        record: sub_match.value_of("record").map(|s| s.to_string()),
+        pin_cpu: match sub_match.value_of("pin-cpu").map(str::parse) {
+            Some(Ok(cpu)) => Some(cpu),
+            Some(Err(_)) => {
+                println!("Error: --pin-cpu must be the number of a cpu");
+                std::process::exit(1);
+            }
+            None => None,
+        },
+    }
+}
+
+fn wasm_features(sub_match: &ArgMatches) -> WasmFeatures {
+    let mut features = WasmFeatures::default();
+    if let Some(list) = sub_match.value_of("wasm-features") {
+        if let Err(err) = features.parse_arg(list) {
+            println!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+    features
+}
+
+// Arguments controlling which statistical tests are run on the samples
+fn analysis_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("test")
+            .long("test")
+            .takes_value(true)
+            .possible_values(&["t", "mann-whitney", "ks"])
+            .default_value("t")
+            .help("compare samples with Welch's t-test, or the nonparametric Mann-Whitney U or Kolmogorov-Smirnov tests"),
+        Arg::with_name("second-order")
+            .long("second-order")
+            .help("also test the variance of the samples, to find leaks in masked implementations"),
+        Arg::with_name("sequential")
+            .long("sequential")
+            .help("stop as soon as the evidence for or against a leak is strong, using a sequential probability ratio test"),
+        Arg::with_name("min-effect")
+            .long("min-effect")
+            .takes_value(true)
+            .value_name("STD_DEVS")
+            .default_value("0.05")
+            .help("the smallest leak the sequential test looks for, in standard deviations"),
+        Arg::with_name("noise")
+            .long("noise")
+            .takes_value(true)
+            .value_name("MODEL:INSTRUCTIONS")
+            .help("add simulated measurement noise to every count, gaussian or cache (with misses and interrupts), to estimate how many measurements an attacker would need"),
+        Arg::with_name("attacker-confidence")
+            .long("attacker-confidence")
+            .takes_value(true)
+            .value_name("PERCENT")
+            .default_value("99")
+            .help("the confidence an attacker wants, for estimating how many measurements they need to exploit a leak"),
+        Arg::with_name("warmup")
+            .long("warmup")
+            .takes_value(true)
+            .value_name("SAMPLES")
+            .default_value("0")
+            .help("discard this many samples of each input before analyzing any, while the target warms up"),
+        Arg::with_name("reject-outliers")
+            .long("reject-outliers")
+            .takes_value(true)
+            .value_name("MADS")
+            .help("reject samples further than this many median absolute deviations from the median of their input"),
+    ]
+}
+
+fn analysis(sub_match: &ArgMatches) -> Analysis {
+    // Ok to unwrap, clap has already validated the value.
+    Analysis {
+        test: sub_match.value_of("test").unwrap().parse().unwrap(),
+        second_order: sub_match.is_present("second-order"),
+        sequential: if sub_match.is_present("sequential") {
+            match sub_match.value_of("min-effect").unwrap().parse() {
+                Ok(min_effect) if min_effect > 0.0 => Some(min_effect),
+                _ => {
+                    println!("Error: --min-effect must be a positive number");
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            None
+        },
+        attacker_confidence: match sub_match.value_of("attacker-confidence").unwrap().parse() {
+            Ok(confidence) if confidence > 0.0 && confidence < 100.0 => Some(confidence),
+            _ => {
+                println!("Error: --attacker-confidence must be a percentage between 0 and 100");
+                std::process::exit(1);
+            }
+        },
+        noise: sub_match.value_of("noise").map(|noise| match noise.parse() {
+            Ok(noise) => noise,
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }),
+        warmup: match sub_match.value_of("warmup").unwrap().parse() {
+            Ok(warmup) => warmup,
+            Err(_) => {
+                println!("Error: --warmup must be a number of samples");
+                std::process::exit(1);
+            }
+        },
+        outliers: sub_match.value_of("reject-outliers").map(|outliers| match outliers.parse() {
+            Ok(outliers) if outliers > 0.0 => outliers,
+            _ => {
+                println!("Error: --reject-outliers must be a positive number");
+                std::process::exit(1);
+            }
+        }),
+    }
+}
+
+// Arguments controlling how imported host functions are stubbed
+fn stub_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("stub")
+            .long("stub")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("IMPORT=BEHAVIOR")
+            .help("stub an imported function (named module.name) with zero, trap, random, or a constant to return"),
+        Arg::with_name("stubs")
+            .long("stubs")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("JSON file mapping imported functions to stub behaviors"),
+        Arg::with_name("entropy-seed")
+            .long("entropy-seed")
+            .takes_value(true)
+            .value_name("SEED")
+            .default_value("0")
+            .help("seed of the generator answering imported randomness, which starts over before every execution"),
+    ]
+}
+
+fn stubs(sub_match: &ArgMatches) -> Stubs {
+    let mut stubs = Stubs::default();
+    let loaded = match sub_match.value_of("stubs") {
+        Some(path) => stubs.load(path),
+        None => Ok(()),
+    };
+    // Stubs given as arguments take precedence over the file
+    let parsed = sub_match
+        .values_of("stub")
+        .into_iter()
+        .flatten()
+        .try_for_each(|arg| stubs.parse_arg(arg));
+    if let Err(err) = loaded.and(parsed) {
+        println!("Error: {}", err);
+        std::process::exit(1);
+    }
+    // Ok to unwrap, it has a default value
+    match sub_match.value_of("entropy-seed").unwrap().parse() {
+        Ok(seed) => stubs.set_seed(seed),
+        Err(_) => {
+            println!("Error: --entropy-seed must be an unsigned 64-bit integer");
+            std::process::exit(1);
+        }
+    }
+    stubs
+}
+
+// Add the settings from `config` to the command line arguments, unless they are already given.
+// Settings for options the subcommand doesn't have are skipped.
+fn apply_config(app: &App<'static, 'static>, mut args: Vec<String>, config: &Config) -> Vec<String> {
+    // Top-level options take values too, so only clap knows which argument is the subcommand. Arguments it can't
+    // parse are left for it to report.
+    let subcommand = match app.clone().get_matches_from_safe(&args) {
+        Ok(matches) => match matches.subcommand_name() {
+            Some(subcommand) => subcommand.to_string(),
+            None => return args,
+        },
+        Err(_) => return args,
+    };
+    let settings = match config.args(&subcommand) {
+        Ok(settings) => settings,
+        Err(err) => {
+            println!("Error: {}", err);
+            std::process::exit(2);
+        }
+    };
+
+    for (option, values) in settings {
+        let flag = format!("--{}", option);
+        let given = args
+            .iter()
+            .any(|arg| arg == &flag || arg.starts_with(&format!("{}=", flag)));
+        if given {
+            continue;
+        }
+
+        let mut candidate = args.clone();
+        if values.is_empty() {
+            candidate.push(flag);
+        }
+        for value in values {
+            candidate.push(flag.clone());
+            candidate.push(value);
+        }
+        match app.clone().get_matches_from_safe(&candidate) {
+            Err(ref err) if err.kind == ErrorKind::UnknownArgument => continue,
+            _ => args = candidate,
+        }
+    }
+    args
+}
+
 fn main() -> Result<(), Error> {
     color_backtrace::install();
 
@@ -16,17 +739,69 @@ fn main() -> Result<(), Error> {
             Arg::with_name("v")
                 .short("v")
                 .multiple(true)
-                .help("Sets the level of verbosity"),
+                .help("Sets the level of verbosity, logging info with -v, debug with -vv and trace with -vvv"),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .long("log-file")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("write logs to FILE instead of stderr"),
+        )
+        .arg(
+            Arg::with_name("log-filter")
+                .long("log-filter")
+                .takes_value(true)
+                .value_name("FILTERS")
+                .help("log levels for single modules like RUST_LOG, such as sidefuzz::wasm=debug; sidefuzz::executions=trace logs every execution"),
         )
         .subcommand(
             SubCommand::with_name("fuzz")
                 .about("fuzzes wasm file, generating variable-time input pairs")
+                .args(&fuzz_args())
+                .arg(
+                    Arg::with_name("watch")
+                        .long("watch")
+                        .help("keep fuzzing as the target is rebuilt, replaying the leaks found so far against each new build first"),
+                )
+                .args(&target_args())
+                .args(&report_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("fuzzes wasm file like fuzz, also scoring inputs on workers that connect from other machines")
+                .args(&fuzz_args())
+                .arg(
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .value_name("ADDR")
+                        .default_value("127.0.0.1:7878")
+                        .help("address to accept workers on, only this machine's by default. Use 0.0.0.0:7878 to accept workers from other machines, along with --token-env"),
+                )
+                .arg(token_arg("only accept workers that give the token in this environment variable, so other hosts that can reach --listen can't send counts"))
+                .args(&target_args())
+                .args(&report_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("worker")
+                .about("scores inputs for a serve coordinator, until its run finishes")
                 .arg(
                     Arg::with_name("wasm-file")
-                        .help("wasm file fuzzing target")
+                        .help("wasm file the coordinator is fuzzing")
                         .required(true)
                         .index(1),
-                ),
+                )
+                .arg(
+                    Arg::with_name("connect")
+                        .long("connect")
+                        .takes_value(true)
+                        .value_name("HOST:PORT")
+                        .required(true)
+                        .help("address of the coordinator"),
+                )
+                .arg(token_arg("give the coordinator the token in this environment variable, for coordinators started with --token-env"))
+                .args(&target_args()),
         )
         .subcommand(
             SubCommand::with_name("check")
@@ -37,6 +812,87 @@ fn main() -> Result<(), Error> {
                         .required(true)
                         .index(1),
                 )
+                .arg(
+                    Arg::with_name("input-1")
+                        .help("first input in hexedecimal format, with ?? for bytes randomized every sample, or a .json input template")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("input-2")
+                        .help("second input in hexedecimal format, with ?? for bytes randomized every sample, or a .json input template")
+                        .required(true)
+                        .index(3),
+                )
+                .arg(
+                    Arg::with_name("max-t-value")
+                        .long("max-t-value")
+                        .takes_value(true)
+                        .default_value("4.5")
+                        .help("report a leak once the t-value goes above this"),
+                )
+                .arg(
+                    Arg::with_name("max-iterations")
+                        .long("max-iterations")
+                        .takes_value(true)
+                        .help("give up after this many samples, with an inconclusive result unless the target is constant time"),
+                )
+                .arg(
+                    Arg::with_name("max-time")
+                        .long("max-time")
+                        .takes_value(true)
+                        .value_name("DURATION")
+                        .help("give up after sampling for this long, such as 30m or 1h30m, like --max-iterations"),
+                )
+                .arg(
+                    Arg::with_name("expect-sha256")
+                        .long("expect-sha256")
+                        .takes_value(true)
+                        .value_name("HASH")
+                        .help("fail unless the target has this SHA-256, such as the module_sha256 of a finding, to catch rebuilt targets"),
+                )
+                .args(&analysis_args())
+                .arg(record_arg())
+                .args(&archive_args())
+                .args(&target_args())
+                .args(&report_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("dudect")
+                .about("Compare a fixed input against random inputs, without searching for a specific input pair")
+                .arg(
+                    Arg::with_name("wasm-file")
+                        .help("wasm file fuzzing target")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("fixed-input")
+                        .help("fixed input in hexedecimal format")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .value_name("SEED")
+                        .help("seed for generating random inputs, to reproduce a previous run"),
+                )
+                .args(&analysis_args())
+                .arg(record_arg())
+                .args(&target_args())
+                .args(&report_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("minimize")
+                .about("Simplify a leaking input pair while keeping its timing difference")
+                .arg(
+                    Arg::with_name("wasm-file")
+                        .help("wasm file fuzzing target")
+                        .required(true)
+                        .index(1),
+                )
                 .arg(
                     Arg::with_name("input-1")
                         .help("first input in hexedecimal format")
@@ -48,11 +904,138 @@ fn main() -> Result<(), Error> {
                         .help("second input in hexedecimal format")
                         .required(true)
                         .index(3),
+                )
+                .args(&target_args())
+                .args(&report_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("batch")
+                .about("Fuzz several targets for a limited number of generations each, reporting on all of them")
+                .arg(
+                    Arg::with_name("targets")
+                        .help("wasm files to fuzz, or a TOML file listing them (defaults to the targets in sidefuzz.toml)")
+                        .multiple(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("jobs")
+                        .long("jobs")
+                        .takes_value(true)
+                        .value_name("N")
+                        .help("number of targets fuzzed at the same time, defaults to the number of CPUs"),
+                )
+                .arg(
+                    Arg::with_name("max-generations")
+                        .long("max-generations")
+                        .takes_value(true)
+                        .value_name("N")
+                        .help("generations each target is fuzzed for, unless the TOML file sets a budget for it"),
+                )
+                .args(&result_args())
+                .args(&target_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("new")
+                .about("Create a cargo project for a new fuzz target")
+                .arg(
+                    Arg::with_name("path")
+                        .help("directory to create, its name is used as the crate name")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("gen-harness")
+                .about("Create a cargo project for a fuzz target calling a public function of another crate")
+                .arg(
+                    Arg::with_name("crate")
+                        .long("crate")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .default_value(".")
+                        .help("directory of the crate the function is in"),
+                )
+                .arg(
+                    Arg::with_name("function")
+                        .long("function")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .required(true)
+                        .help("path of the function in its crate, such as module::sign"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("DIR")
+                        .help("directory to create, defaults to fuzz_ followed by the function's name"),
+                )
+                .arg(
+                    Arg::with_name("slice-len")
+                        .long("slice-len")
+                        .takes_value(true)
+                        .value_name("BYTES")
+                        .help("bytes of input for each slice or Vec argument, whose length the signature doesn't give (defaults to 32)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Build a target crate for wasm in release mode, and check that it can be fuzzed")
+                .arg(
+                    Arg::with_name("dir")
+                        .help("directory of the target crate")
+                        .default_value(".")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .about("List a module's exports, and explain anything that stops it from being fuzzed")
+                .arg(
+                    Arg::with_name("wasm-file")
+                        .help("wasm file to inspect")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("abi")
+                .about("Describe the exports a fuzz target needs, for targets written in other languages")
+                .arg(
+                    Arg::with_name("emit-c-header")
+                        .long("emit-c-header")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .min_values(0)
+                        .help("write a C header declaring the exports, to sidefuzz.h unless a file is given"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("canary")
+                .about("Check that black_box and the other helpers keep values from being optimized away with the installed rustc")
+                .arg(
+                    Arg::with_name("sidefuzz")
+                        .long("sidefuzz")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .help("build against the sidefuzz crate at this path, instead of the release matching this version"),
+                )
+                .args(&result_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Print a target's input, memory, wasm features, exports and embedded metadata")
+                .arg(
+                    Arg::with_name("wasm-file")
+                        .help("wasm file fuzzing target")
+                        .required(true)
+                        .index(1),
+                )
+                .args(&target_args()),
+        )
         .subcommand(
             SubCommand::with_name("count")
-                .about("Count the number of instructions executed for a single input.")
+                .about("Count the number of instructions executed for a single input, or a CSV of counts for a file of inputs.")
                 .arg(
                     Arg::with_name("wasm-file")
                         .help("wasm file fuzzing target")
@@ -62,71 +1045,821 @@ fn main() -> Result<(), Error> {
                 .arg(
                     Arg::with_name("input")
                         .help("Input in hexedecimal format")
+                        .required_unless("inputs")
+                        .conflicts_with("inputs")
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("inputs")
+                        .long("inputs")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("file of inputs in hexadecimal format, one per line, or - for stdin. Prints input,count,crash as CSV"),
+                )
+                .args(&target_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("compare")
+                .about("Count instructions for the same inputs on two builds of a target, to check that a fix changed its behaviour")
+                .arg(
+                    Arg::with_name("baseline")
+                        .help("wasm file of the original build")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("candidate")
+                        .help("wasm file of the new build")
                         .required(true)
                         .index(2),
                 )
-        );
-
-    let matches = app.clone().get_matches();
-
-    // Fuzz command
-    if let Some(sub_match) = matches.subcommand_matches("fuzz") {
-        let filename = sub_match.value_of("wasm-file").unwrap();
-        let mut fuzz = match Fuzz::from_file(filename) {
-            Ok(fuzz) => fuzz,
-            Err(err) => {
-                println!("Error: {}", err);
-                std::process::exit(1);
-            }
-        };
-
-        let result = fuzz.run();
-        match result {
-            Ok(_) => std::process::exit(0),
-            Err(err) => {
-                println!("Error: {}", err);
-                std::process::exit(0);
-            }
-        }
-    }
-
-    // Check command
-    if let Some(sub_match) = matches.subcommand_matches("check") {
-        let filename = sub_match.value_of("wasm-file").unwrap();
-
-        let first = sub_match.value_of("input-1").unwrap();
-        let first = hex::decode(first)?;
-
-        let second = sub_match.value_of("input-2").unwrap();
-        let second = hex::decode(second)?;
-
-        let mut check = match Check::from_file(filename, first, second) {
-            Ok(check) => check,
-            Err(err) => {
-                println!("Error: {}", err);
-                std::process::exit(1);
-            }
-        };
-
-        let result = check.run();
-        match result {
-            Ok(_) => std::process::exit(0),
-            Err(err) => {
-                println!("Error: {}", err);
-                std::process::exit(0);
-            }
-        }
-    }
-
-    // Count command
-    if let Some(sub_match) = matches.subcommand_matches("count") {
-        let filename = sub_match.value_of("wasm-file").unwrap();
-
-        let input = sub_match.value_of("input").unwrap();
-        let input = hex::decode(input)?;
-
-        let mut count = match Count::from_file(filename, input) {
-            Ok(count) => count,
+                .arg(
+                    Arg::with_name("input")
+                        .help("inputs in hexedecimal format")
+                        .multiple(true)
+                        .index(3),
+                )
+                .arg(
+                    Arg::with_name("random")
+                        .long("random")
+                        .takes_value(true)
+                        .value_name("N")
+                        .default_value("0")
+                        .help("also compare this many random inputs"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("seed for generating random inputs"),
+                )
+                .arg(
+                    Arg::with_name("max-regression")
+                        .long("max-regression")
+                        .takes_value(true)
+                        .value_name("PERCENT")
+                        .help("exit with 1 if the candidate executes this much more than the baseline on average"),
+                )
+                .args(&result_args())
+                .args(&target_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("bitmap")
+                .about("Flip each input bit on its own and map which bits change the instruction count")
+                .arg(
+                    Arg::with_name("wasm-file")
+                        .help("wasm file fuzzing target")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .help("baseline input in hexedecimal format, a random one is used if not given")
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("random")
+                        .long("random")
+                        .takes_value(true)
+                        .value_name("N")
+                        .help("also flip the bits of this many random baselines"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("seed for generating random baselines"),
+                )
+                .arg(
+                    Arg::with_name("csv")
+                        .long("csv")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("write the change for every bit and baseline to a CSV file"),
+                )
+                .args(&target_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("selftest")
+                .about("Check that the target executes the same number of instructions every time it runs an input, before fuzzing it")
+                .arg(
+                    Arg::with_name("wasm-file")
+                        .help("wasm file fuzzing target")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .help("input in hexedecimal format to test, as well as the random ones")
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("random")
+                        .long("random")
+                        .takes_value(true)
+                        .value_name("N")
+                        .default_value("8")
+                        .help("also test this many random inputs"),
+                )
+                .arg(
+                    Arg::with_name("repeats")
+                        .long("repeats")
+                        .takes_value(true)
+                        .value_name("N")
+                        .default_value("10")
+                        .help("how many times each input is run on the same instance"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("seed for generating random inputs"),
+                )
+                .args(&target_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("oracle")
+                .about("Check whether the target's timing depends on the output it passes to sidefuzz::set_output, such as an error code")
+                .arg(
+                    Arg::with_name("wasm-file")
+                        .help("wasm file fuzzing target")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .help("inputs in hexedecimal format to run, as well as the random ones")
+                        .multiple(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("random")
+                        .long("random")
+                        .takes_value(true)
+                        .value_name("N")
+                        .default_value("1000")
+                        .help("also run this many random inputs"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("seed for generating random inputs"),
+                )
+                .arg(
+                    Arg::with_name("max-t-value")
+                        .long("max-t-value")
+                        .takes_value(true)
+                        .default_value("4.5")
+                        .help("report an oracle once the t-value between two outputs goes above this"),
+                )
+                .args(&result_args())
+                .args(&target_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("differential")
+                .about("Compare a rewrite of a primitive with the reference implementation, searching for inputs they disagree on or where the rewrite's timing varies more")
+                .arg(
+                    Arg::with_name("reference")
+                        .help("wasm file of the reference implementation")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("rewrite")
+                        .help("wasm file of the rewrite")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("generations")
+                        .long("generations")
+                        .takes_value(true)
+                        .value_name("N")
+                        .default_value("50")
+                        .help("how many generations to search for"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("seed for the optimizer"),
+                )
+                .args(&result_args())
+                .args(&target_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("regress")
+                .about("Replay the leaks in a findings file against a new build of the target, failing if a fixed one is back")
+                .arg(
+                    Arg::with_name("wasm-file")
+                        .help("wasm file fuzzing target")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("findings-file")
+                        .help("findings file written by fuzz --findings")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("update")
+                        .long("update")
+                        .help("mark findings that no longer leak as fixed in the findings file"),
+                )
+                .args(&result_args())
+                .args(&target_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Reanalyze a session log written with --record, or re-execute its inputs against a target")
+                .arg(
+                    Arg::with_name("session")
+                        .help("session log")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("wasm-file")
+                        .long("wasm")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("re-execute every logged input against this target, and report any that measure differently"),
+                )
+                .args(&result_args())
+                .args(&target_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("trace")
+                .about("Trace the execution of two inputs and show where their behaviour differs")
+                .arg(
+                    Arg::with_name("wasm-file")
+                        .help("wasm file fuzzing target")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("input-1")
+                        .help("first input in hexedecimal format")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("input-2")
+                        .help("second input in hexedecimal format")
+                        .required(true)
+                        .index(3),
+                )
+                .arg(
+                    Arg::with_name("observe")
+                        .long("observe")
+                        .takes_value(true)
+                        .possible_values(&["memory", "branches", "functions", "cache", "calls", "counts", "taint"])
+                        .default_value("memory")
+                        .help("what to record during execution"),
+                )
+                .arg(
+                    Arg::with_name("line-size")
+                        .long("line-size")
+                        .takes_value(true)
+                        .default_value("64")
+                        .help("bytes per cache line, when observing the cache"),
+                )
+                .arg(
+                    Arg::with_name("sets")
+                        .long("sets")
+                        .takes_value(true)
+                        .default_value("64")
+                        .help("number of cache sets, when observing the cache"),
+                )
+                .arg(
+                    Arg::with_name("ways")
+                        .long("ways")
+                        .takes_value(true)
+                        .default_value("8")
+                        .help("cache lines per set, when observing the cache"),
+                )
+                .args(&stub_args()),
+        );
+
+    // Settings from sidefuzz.toml are used for any options not given on the command line
+    let args: Vec<String> = std::env::args().collect();
+    let args = match Config::discover() {
+        Ok(Some(config)) => apply_config(&app, args, &config),
+        Ok(None) => args,
+        Err(err) => {
+            println!("Error: {}", err);
+            std::process::exit(2);
+        }
+    };
+    let matches = app.clone().get_matches_from(args);
+
+    if let Err(err) = sidefuzz::logging::init(
+        matches.occurrences_of("v"),
+        matches.value_of("log-filter"),
+        matches.value_of("log-file"),
+    ) {
+        println!("Error: {}", err);
+        std::process::exit(1);
+    }
+
+    // Fuzz command, and serve, which also accepts workers
+    let fuzz_match = matches
+        .subcommand_matches("fuzz")
+        .or_else(|| matches.subcommand_matches("serve"));
+    if let Some(sub_match) = fuzz_match {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+        let mut fuzz = match Fuzz::from_file_with_options(filename, &target_options(sub_match)) {
+            Ok(fuzz) => fuzz,
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        let workers = sub_match.value_of("workers").unwrap();
+        let workers: usize = match workers.parse() {
+            Ok(workers) if workers > 0 => workers,
+            _ => {
+                println!("Error: --workers must be a positive integer");
+                std::process::exit(1);
+            }
+        };
+        fuzz.set_workers(workers);
+
+        if let Some(seed) = sub_match.value_of("seed") {
+            match seed.parse() {
+                Ok(seed) => fuzz.set_seed(seed),
+                Err(_) => {
+                    println!("Error: --seed must be an unsigned 64-bit integer");
+                    std::process::exit(1);
+                }
+            }
+        }
+        fuzz.set_reporter(reporter(sub_match));
+
+        if let Some(checkpoint) = sub_match.value_of("checkpoint-file") {
+            fuzz.set_checkpoint(checkpoint.to_string(), sub_match.is_present("resume"));
+        }
+
+        if let Some(template) = sub_match.value_of("template") {
+            fuzz.set_template(template.to_string());
+        }
+
+        if let Some(corpus) = sub_match.value_of("corpus") {
+            fuzz.set_corpus(corpus.to_string());
+        }
+
+        if let Some(max_generations) = sub_match.value_of("max-generations") {
+            match max_generations.parse() {
+                Ok(max_generations) => fuzz.set_max_generations(max_generations),
+                Err(_) => {
+                    println!("Error: --max-generations must be an unsigned 64-bit integer");
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        if let Some(max_time) = sub_match.value_of("max-time") {
+            match parse_duration(max_time) {
+                Ok(max_time) => fuzz.set_max_time(max_time),
+                Err(e) => {
+                    println!("Error: --max-time {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        let mut mutators = match sub_match.value_of("mutators").map(Mutators::parse) {
+            Some(Ok(mutators)) => mutators,
+            Some(Err(err)) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+            None => Mutators::default(),
+        };
+        if let Some(dict) = sub_match.value_of("dict") {
+            match DictionaryMutator::load(dict) {
+                Ok(dictionary) => mutators.set_dictionary(dictionary),
+                Err(err) => {
+                    println!("Error: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        fuzz.set_mutators(mutators);
+        if let Some(scores) = sub_match.value_of("score") {
+            match Scores::parse(scores) {
+                Ok(scores) => fuzz.set_scores(scores),
+                Err(err) => {
+                    println!("Error: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        // Ok to unwrap, clap has already validated the value.
+        fuzz.set_charset(sub_match.value_of("charset").unwrap().parse().unwrap());
+        fuzz.set_coverage(sub_match.is_present("coverage"));
+        fuzz.set_directed(sub_match.is_present("directed"));
+        fuzz.set_strategy(sub_match.value_of("optimizer").unwrap().parse().unwrap());
+        fuzz.set_surrogate(sub_match.is_present("surrogate"));
+        if let Some(score_log) = sub_match.value_of("score-log") {
+            fuzz.set_score_log(score_log.to_string());
+        }
+
+        let islands = match sub_match.value_of("islands").unwrap().parse() {
+            Ok(islands) if islands > 0 => islands,
+            _ => {
+                println!("Error: --islands must be a positive integer");
+                std::process::exit(1);
+            }
+        };
+        let migration_interval = match sub_match.value_of("migration-interval").unwrap().parse() {
+            Ok(migration_interval) => migration_interval,
+            Err(_) => {
+                println!("Error: --migration-interval must be an unsigned 64-bit integer");
+                std::process::exit(1);
+            }
+        };
+        fuzz.set_islands(islands, migration_interval);
+        match sub_match.value_of("restart-diversity").unwrap().parse() {
+            Ok(diversity) if diversity >= 0.0 => fuzz.set_restart_diversity(diversity),
+            _ => {
+                println!("Error: --restart-diversity must be a non-negative number");
+                std::process::exit(1);
+            }
+        }
+
+        // Ok to unwrap, there is a default crashes directory.
+        fuzz.set_crashes(sub_match.value_of("crashes").unwrap().to_string());
+        if let Some(hangs) = sub_match.value_of("hangs") {
+            fuzz.set_hangs(hangs.to_string());
+        }
+        if let Some(reproducer) = sub_match.value_of("reproducer") {
+            fuzz.set_reproducer(reproducer.to_string(), filename.to_string(), count_options(sub_match));
+        }
+        if let Some(findings) = sub_match.value_of("findings") {
+            fuzz.set_findings(findings.to_string());
+        }
+        if let Some(dir) = archive(sub_match) {
+            fuzz.set_archive(dir, filename.to_string());
+        }
+        if sub_match.is_present("watch") {
+            fuzz.set_watch(filename.to_string(), target_options(sub_match));
+        }
+        if let Some(listen) = sub_match.value_of("listen") {
+            fuzz.set_listen(listen.to_string());
+        }
+        if let Some(token) = token(sub_match) {
+            fuzz.set_token(token);
+        }
+
+        let result = fuzz.run();
+        match result {
+            Ok(_) => std::process::exit(0),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(0);
+            }
+        }
+    }
+
+    // Worker command
+    if let Some(sub_match) = matches.subcommand_matches("worker") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+        let mut worker = match Worker::from_file_with_options(filename, &target_options(sub_match)) {
+            Ok(worker) => worker,
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        if let Some(token) = token(sub_match) {
+            worker.set_token(token);
+        }
+
+        let result = worker.run(sub_match.value_of("connect").unwrap());
+        match result {
+            Ok(_) => std::process::exit(0),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Regress command
+    if let Some(sub_match) = matches.subcommand_matches("regress") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+        let findings = sub_match.value_of("findings-file").unwrap();
+
+        // Errors exit with 2, since 1 means a fixed leak is back.
+        let mut regress =
+            match Regress::from_files_with_options(filename, findings, &target_options(sub_match)) {
+                Ok(regress) => regress,
+                Err(err) => {
+                    println!("Error: {}", err);
+                    std::process::exit(2);
+                }
+            };
+        regress.set_update(sub_match.is_present("update"));
+
+        let reporter = result_reporter(sub_match);
+        let result = regress.run().and_then(|result| {
+            reporter.finish_value(&result, &result.text())?;
+            Ok(result.exit_code())
+        });
+        match result {
+            Ok(code) => std::process::exit(code),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // Check command
+    if let Some(sub_match) = matches.subcommand_matches("check") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+
+        let first = sub_match.value_of("input-1").unwrap();
+        let second = sub_match.value_of("input-2").unwrap();
+
+        // Errors exit with 2 like inconclusive results, since 1 means a leak was found.
+        let mut check = match Check::from_args_with_options(filename, &target_options(sub_match), first, second) {
+            Ok(check) => check,
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(2);
+            }
+        };
+
+        check.set_reporter(reporter(sub_match));
+        check.set_analysis(analysis(sub_match));
+        if let Some(dir) = archive(sub_match) {
+            check.set_archive(dir, filename.to_string());
+        }
+        if let Some(hash) = sub_match.value_of("expect-sha256") {
+            check.set_expected_sha256(hash.to_string(), filename.to_string());
+        }
+
+        match sub_match.value_of("max-t-value").unwrap().parse() {
+            Ok(max_t_value) => check.set_max_t_value(max_t_value),
+            Err(_) => {
+                println!("Error: --max-t-value must be a number");
+                std::process::exit(2);
+            }
+        }
+        if let Some(max_iterations) = sub_match.value_of("max-iterations") {
+            match max_iterations.parse() {
+                Ok(max_iterations) => check.set_max_samples(max_iterations),
+                Err(_) => {
+                    println!("Error: --max-iterations must be a number");
+                    std::process::exit(2);
+                }
+            }
+        }
+        if let Some(max_time) = sub_match.value_of("max-time") {
+            match parse_duration(max_time) {
+                Ok(max_time) => check.set_max_time(max_time),
+                Err(e) => {
+                    println!("Error: --max-time {}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+
+        let result = check.run();
+        match result {
+            Ok(_) => std::process::exit(0),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // Dudect command
+    if let Some(sub_match) = matches.subcommand_matches("dudect") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+
+        let fixed = sub_match.value_of("fixed-input").unwrap();
+        let fixed = hex::decode(fixed)?;
+
+        let mut dudect =
+            match FixedVsRandom::from_file_with_options(filename, &target_options(sub_match), fixed) {
+                Ok(dudect) => dudect,
+                Err(err) => {
+                    println!("Error: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+        if let Some(seed) = sub_match.value_of("seed") {
+            match seed.parse() {
+                Ok(seed) => dudect.set_seed(seed),
+                Err(_) => {
+                    println!("Error: --seed must be an unsigned 64-bit integer");
+                    std::process::exit(1);
+                }
+            }
+        }
+        dudect.set_reporter(reporter(sub_match));
+        dudect.set_analysis(analysis(sub_match));
+
+        let result = dudect.run();
+        match result {
+            Ok(_) => std::process::exit(0),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(0);
+            }
+        }
+    }
+
+    // Minimize command
+    if let Some(sub_match) = matches.subcommand_matches("minimize") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+
+        let first = sub_match.value_of("input-1").unwrap();
+        let first = hex::decode(first)?;
+
+        let second = sub_match.value_of("input-2").unwrap();
+        let second = hex::decode(second)?;
+
+        let mut minimize =
+            match Minimize::from_file_with_options(filename, &target_options(sub_match), first, second) {
+                Ok(minimize) => minimize,
+                Err(err) => {
+                    println!("Error: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+        minimize.set_reporter(reporter(sub_match));
+
+        let result = minimize.run();
+        match result {
+            Ok(_) => std::process::exit(0),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Batch command
+    if let Some(sub_match) = matches.subcommand_matches("batch") {
+        let targets: Vec<String> = match sub_match.values_of("targets") {
+            Some(targets) => targets.map(|s| s.to_string()).collect(),
+            None => vec![CONFIG_FILE.to_string()],
+        };
+
+        let mut batch = if targets.len() == 1 && targets[0].ends_with(".toml") {
+            match Batch::from_config(&targets[0], target_options(sub_match)) {
+                Ok(batch) => batch,
+                Err(err) => {
+                    println!("Error: {}", err);
+                    std::process::exit(2);
+                }
+            }
+        } else {
+            Batch::new(targets, target_options(sub_match))
+        };
+
+        if let Some(jobs) = sub_match.value_of("jobs") {
+            match jobs.parse() {
+                Ok(jobs) => batch.set_jobs(jobs),
+                Err(_) => {
+                    println!("Error: --jobs must be a positive integer");
+                    std::process::exit(2);
+                }
+            }
+        }
+        if let Some(max_generations) = sub_match.value_of("max-generations") {
+            match max_generations.parse() {
+                Ok(max_generations) => batch.set_max_generations(max_generations),
+                Err(_) => {
+                    println!("Error: --max-generations must be an unsigned 64-bit integer");
+                    std::process::exit(2);
+                }
+            }
+        }
+
+        batch.set_reporter(result_reporter(sub_match));
+
+        match batch.run() {
+            Ok(_) => std::process::exit(0),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // New command
+    if let Some(sub_match) = matches.subcommand_matches("new") {
+        let path = sub_match.value_of("path").unwrap();
+        let result = NewTarget::new(path).and_then(|target| target.run());
+        if let Err(err) = result {
+            println!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // Gen-harness command
+    if let Some(sub_match) = matches.subcommand_matches("gen-harness") {
+        let slice_len = match sub_match.value_of("slice-len").map(str::parse) {
+            Some(Ok(len)) => len,
+            Some(Err(_)) => {
+                println!("Error: --slice-len must be a number of bytes");
+                std::process::exit(1);
+            }
+            None => sidefuzz::harness::DEFAULT_SLICE_LEN,
+        };
+        let harness = GenHarness::new(
+            sub_match.value_of("crate").unwrap(),
+            sub_match.value_of("function").unwrap(),
+            sub_match.value_of("output"),
+            slice_len,
+        );
+        if let Err(err) = harness.run() {
+            println!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // Build command
+    if let Some(sub_match) = matches.subcommand_matches("build") {
+        let dir = sub_match.value_of("dir").unwrap();
+        if let Err(err) = Build::new(dir).run() {
+            println!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // Inspect command
+    if let Some(sub_match) = matches.subcommand_matches("inspect") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+        let result = Inspect::from_file(filename).and_then(|inspect| inspect.run());
+        if let Err(err) = result {
+            println!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // Abi command
+    if let Some(sub_match) = matches.subcommand_matches("abi") {
+        let header = if sub_match.is_present("emit-c-header") {
+            Some(
+                sub_match
+                    .value_of("emit-c-header")
+                    .unwrap_or(sidefuzz::abi::DEFAULT_HEADER),
+            )
+        } else {
+            None
+        };
+        if let Err(err) = Abi::new(header).run() {
+            println!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // Canary command
+    if let Some(sub_match) = matches.subcommand_matches("canary") {
+        let reporter = result_reporter(sub_match);
+        let mut canary = Canary::new(sub_match.value_of("sidefuzz"));
+        canary.set_reporter(reporter.clone());
+        let result = canary.run().and_then(|result| {
+            reporter.finish_value(&result, &result.text())?;
+            Ok(result.exit_code())
+        });
+        match result {
+            Ok(code) => std::process::exit(code),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Count command
+    if let Some(sub_match) = matches.subcommand_matches("count") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+
+        let count = match sub_match.value_of("inputs") {
+            Some(inputs) => Count::from_inputs_file_with_options(filename, &target_options(sub_match), inputs),
+            None => {
+                // Ok to unwrap, clap requires an input unless there is an inputs file.
+                let input = sub_match.value_of("input").unwrap();
+                let input = hex::decode(input)?;
+                Count::from_file_with_options(filename, &target_options(sub_match), input)
+            }
+        };
+        let mut count = match count {
+            Ok(count) => count,
             Err(err) => {
                 println!("Error: {}", err);
                 std::process::exit(1);
@@ -137,6 +1870,365 @@ fn main() -> Result<(), Error> {
         std::process::exit(0);
     }
 
+    // Bitmap command
+    if let Some(sub_match) = matches.subcommand_matches("bitmap") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+        let baseline = match sub_match.value_of("input") {
+            Some(input) => Some(hex::decode(input)?),
+            None => None,
+        };
+
+        let mut bitmap = match Bitmap::from_file_with_options(filename, &target_options(sub_match), baseline.clone()) {
+            Ok(bitmap) => bitmap,
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        let seed = match sub_match.value_of("seed").map(str::parse) {
+            Some(Ok(seed)) => seed,
+            Some(Err(_)) => {
+                println!("Error: --seed must be an unsigned 64-bit integer");
+                std::process::exit(1);
+            }
+            None => rand::random(),
+        };
+        // Without a baseline, one random baseline is used
+        let random = match sub_match.value_of("random").map(str::parse) {
+            Some(Ok(random)) => random,
+            Some(Err(_)) => {
+                println!("Error: --random must be a number of baselines");
+                std::process::exit(1);
+            }
+            None if baseline.is_none() => 1,
+            None => 0,
+        };
+        bitmap.add_random_baselines(random, seed);
+        if let Some(csv) = sub_match.value_of("csv") {
+            bitmap.set_csv(csv);
+        }
+
+        if let Err(err) = bitmap.run() {
+            println!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // Selftest command
+    if let Some(sub_match) = matches.subcommand_matches("selftest") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+        let input = match sub_match.value_of("input") {
+            Some(input) => Some(hex::decode(input)?),
+            None => None,
+        };
+
+        let mut selftest = match SelfTest::from_file_with_options(filename, &target_options(sub_match), input) {
+            Ok(selftest) => selftest,
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        let seed = match sub_match.value_of("seed").map(str::parse) {
+            Some(Ok(seed)) => seed,
+            Some(Err(_)) => {
+                println!("Error: --seed must be an unsigned 64-bit integer");
+                std::process::exit(1);
+            }
+            None => rand::random(),
+        };
+        // Ok to unwrap, both have default values
+        let random = match sub_match.value_of("random").unwrap().parse() {
+            Ok(random) => random,
+            Err(_) => {
+                println!("Error: --random must be a number of inputs");
+                std::process::exit(1);
+            }
+        };
+        let repeats = match sub_match.value_of("repeats").unwrap().parse() {
+            Ok(repeats) => repeats,
+            Err(_) => {
+                println!("Error: --repeats must be a number of runs");
+                std::process::exit(1);
+            }
+        };
+        selftest.add_random_inputs(random, seed);
+        selftest.set_repeats(repeats);
+
+        if let Err(err) = selftest.run() {
+            println!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // Oracle command
+    if let Some(sub_match) = matches.subcommand_matches("oracle") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+        let mut inputs = Vec::new();
+        for input in sub_match.values_of("input").into_iter().flatten() {
+            inputs.push(hex::decode(input)?);
+        }
+
+        let mut oracle = match Oracle::from_file_with_options(filename, &target_options(sub_match), inputs) {
+            Ok(oracle) => oracle,
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        let seed = match sub_match.value_of("seed").map(str::parse) {
+            Some(Ok(seed)) => seed,
+            Some(Err(_)) => {
+                println!("Error: --seed must be an unsigned 64-bit integer");
+                std::process::exit(1);
+            }
+            None => rand::random(),
+        };
+        // Ok to unwrap, both have default values
+        let random = match sub_match.value_of("random").unwrap().parse() {
+            Ok(random) => random,
+            Err(_) => {
+                println!("Error: --random must be a number of inputs");
+                std::process::exit(1);
+            }
+        };
+        match sub_match.value_of("max-t-value").unwrap().parse() {
+            Ok(max_t_value) => oracle.set_max_t_value(max_t_value),
+            Err(_) => {
+                println!("Error: --max-t-value must be a number");
+                std::process::exit(1);
+            }
+        }
+        oracle.add_random_inputs(random, seed);
+
+        let reporter = result_reporter(sub_match);
+        let result = oracle.run().and_then(|result| {
+            reporter.finish_value(&result, &result.text())?;
+            Ok(result.exit_code())
+        });
+        match result {
+            Ok(code) => std::process::exit(code),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Differential command
+    if let Some(sub_match) = matches.subcommand_matches("differential") {
+        let reference = sub_match.value_of("reference").unwrap();
+        let rewrite = sub_match.value_of("rewrite").unwrap();
+
+        let mut differential =
+            match Differential::from_files_with_options(reference, rewrite, &target_options(sub_match)) {
+                Ok(differential) => differential,
+                Err(err) => {
+                    println!("Error: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+        // Ok to unwrap, it has a default value
+        match sub_match.value_of("generations").unwrap().parse() {
+            Ok(generations) => differential.set_generations(generations),
+            Err(_) => {
+                println!("Error: --generations must be a number");
+                std::process::exit(1);
+            }
+        }
+        match sub_match.value_of("seed").map(str::parse) {
+            Some(Ok(seed)) => differential.set_seed(seed),
+            Some(Err(_)) => {
+                println!("Error: --seed must be an unsigned 64-bit integer");
+                std::process::exit(1);
+            }
+            None => {}
+        }
+
+        let reporter = result_reporter(sub_match);
+        differential.set_reporter(reporter.clone());
+        let result = differential.run().and_then(|result| {
+            reporter.finish_value(&result, &result.text())?;
+            Ok(result.exit_code())
+        });
+        match result {
+            Ok(code) => std::process::exit(code),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Info command
+    if let Some(sub_match) = matches.subcommand_matches("info") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+        let result = Info::from_file_with_options(filename, &target_options(sub_match)).and_then(|info| info.run());
+        if let Err(err) = result {
+            println!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // Compare command
+    if let Some(sub_match) = matches.subcommand_matches("compare") {
+        let baseline = sub_match.value_of("baseline").unwrap();
+        let candidate = sub_match.value_of("candidate").unwrap();
+
+        let mut inputs = Vec::new();
+        for input in sub_match.values_of("input").into_iter().flatten() {
+            inputs.push(hex::decode(input)?);
+        }
+
+        let mut compare = match Compare::from_files_with_options(baseline, candidate, &target_options(sub_match), inputs) {
+            Ok(compare) => compare,
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        let seed = match sub_match.value_of("seed").map(str::parse) {
+            Some(Ok(seed)) => seed,
+            Some(Err(_)) => {
+                println!("Error: --seed must be an unsigned 64-bit integer");
+                std::process::exit(1);
+            }
+            None => rand::random(),
+        };
+        match sub_match.value_of("random").unwrap().parse() {
+            Ok(count) => compare.add_random_inputs(count, seed),
+            Err(_) => {
+                println!("Error: --random must be a number of inputs");
+                std::process::exit(1);
+            }
+        }
+        if let Some(max_regression) = sub_match.value_of("max-regression") {
+            match max_regression.parse() {
+                Ok(max_regression) => compare.set_max_regression(max_regression),
+                Err(_) => {
+                    println!("Error: --max-regression must be a percentage");
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        let reporter = result_reporter(sub_match);
+        let result = compare.run().and_then(|result| {
+            reporter.finish_value(&result, &result.text())?;
+            Ok(result.exit_code())
+        });
+        match result {
+            Ok(code) => std::process::exit(code),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Replay command
+    if let Some(sub_match) = matches.subcommand_matches("replay") {
+        let session = sub_match.value_of("session").unwrap();
+        let result = Replay::from_file(session).and_then(|mut replay| {
+            if let Some(filename) = sub_match.value_of("wasm-file") {
+                replay.set_target(filename, &target_options(sub_match))?;
+            }
+            replay.run()
+        });
+        let reporter = result_reporter(sub_match);
+        let result = result.and_then(|result| {
+            reporter.finish_value(&result, &result.text())?;
+            Ok(result.exit_code())
+        });
+        match result {
+            Ok(code) => std::process::exit(code),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Trace command
+    if let Some(sub_match) = matches.subcommand_matches("trace") {
+        let filename = sub_match.value_of("wasm-file").unwrap();
+
+        let first = sub_match.value_of("input-1").unwrap();
+        let first = hex::decode(first)?;
+
+        let second = sub_match.value_of("input-2").unwrap();
+        let second = hex::decode(second)?;
+
+        // Ok to unwrap, clap has already validated the trace kind.
+        let kind: TraceKind = sub_match.value_of("observe").unwrap().parse().unwrap();
+
+        let mut trace = match Trace::from_file_with_stubs(filename, &stubs(sub_match), first, second, kind) {
+            Ok(trace) => trace,
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        // Ok to unwrap, the cache arguments have defaults.
+        let cache: (Result<u64, _>, Result<u64, _>, Result<usize, _>) = (
+            sub_match.value_of("line-size").unwrap().parse(),
+            sub_match.value_of("sets").unwrap().parse(),
+            sub_match.value_of("ways").unwrap().parse(),
+        );
+        match cache {
+            (Ok(line_size), Ok(sets), Ok(ways)) if line_size > 0 && sets > 0 && ways > 0 => {
+                trace.set_cache_config(CacheConfig { line_size, sets, ways })
+            }
+            _ => {
+                println!("Error: --line-size, --sets and --ways must be positive integers");
+                std::process::exit(1);
+            }
+        }
+
+        match trace.run() {
+            Ok(_) => std::process::exit(0),
+            Err(err) => {
+                println!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
     app.print_long_help()?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_config_test() {
+        let app = App::new("sidefuzz")
+            .arg(Arg::with_name("log-file").long("log-file").takes_value(true))
+            .subcommand(
+                SubCommand::with_name("fuzz")
+                    .arg(Arg::with_name("target").required(true))
+                    .arg(Arg::with_name("format").long("format").takes_value(true)),
+            );
+        let config = Config::parse("[fuzz]\nformat = \"json\"\n").unwrap();
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+
+        // The value of a top-level option isn't mistaken for the subcommand
+        let given = args(&["sidefuzz", "--log-file", "out.log", "fuzz", "t.wasm"]);
+        assert_eq!(
+            apply_config(&app, given.clone(), &config),
+            args(&["sidefuzz", "--log-file", "out.log", "fuzz", "t.wasm", "--format", "json"])
+        );
+
+        // Options given on the command line win
+        let given = args(&["sidefuzz", "fuzz", "t.wasm", "--format", "text"]);
+        assert_eq!(apply_config(&app, given.clone(), &config), given);
+    }
+}
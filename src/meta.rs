@@ -0,0 +1,138 @@
+// Metadata that `sidefuzz_target!` embeds in targets, in a custom section named "sidefuzz.meta"
+//
+// The section holds lines of key=value pairs: the target crate's name and version, the version of sidefuzz it was
+// built with, the version of the interface between sidefuzz and targets, and the input `sidefuzz_target!` fetches.
+// Reports are labelled with it, and targets built for a newer interface than this sidefuzz speaks are refused
+// instead of being fuzzed with the wrong expectations. Unknown keys are ignored, so later versions can add more.
+
+use crate::errors::SideFuzzError;
+use serde::Serialize;
+
+pub(crate) const META_SECTION: &str = "sidefuzz.meta";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TargetMeta {
+    pub name: String,
+    pub version: String,
+    pub sidefuzz: String, // The sidefuzz version the target was built with
+    pub abi: u32,         // The version of the interface between sidefuzz and targets
+    pub input: String,    // The kind of input fetched, such as "bytes"
+    pub input_len: Option<String>, // As written in `sidefuzz_target!`, which may not be a number
+}
+
+impl TargetMeta {
+    // The metadata embedded in a module, if it has any
+    pub fn find(bytes: &[u8]) -> Option<Self> {
+        if crate::component::is_component(bytes) {
+            return Self::find(&crate::component::core_module(bytes.to_vec()).ok()?);
+        }
+        crate::binary::custom_sections(bytes)
+            .into_iter()
+            .find(|(name, _)| name == META_SECTION)
+            .and_then(|(_, contents)| Self::parse(&contents))
+    }
+
+    fn parse(contents: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(contents).ok()?;
+        let mut meta = TargetMeta::default();
+        for line in text.lines() {
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim().to_string()),
+                _ => continue,
+            };
+            match key {
+                "name" => meta.name = value,
+                "version" => meta.version = value,
+                "sidefuzz" => meta.sidefuzz = value,
+                "abi" => meta.abi = value.parse().ok()?,
+                "input" => meta.input = value,
+                "input_len" => meta.input_len = Some(value),
+                _ => {}
+            }
+        }
+        Some(meta)
+    }
+
+    // Fail for targets built for an interface this sidefuzz doesn't speak
+    pub fn check_compatible(&self) -> Result<(), SideFuzzError> {
+        if self.abi > abi() {
+            return Err(SideFuzzError::IncompatibleTarget(
+                self.sidefuzz.clone(),
+                crate::VERSION.to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Things that don't stop the target from being fuzzed, but are worth knowing
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if minor_version(&self.sidefuzz) != minor_version(crate::VERSION) {
+            warnings.push(format!(
+                "the target was built with sidefuzz {}, but is being fuzzed with sidefuzz {}",
+                self.sidefuzz,
+                crate::VERSION
+            ));
+        }
+        warnings
+    }
+
+    // A short name for reports, such as "my_target 0.1.0"
+    pub fn label(&self) -> String {
+        format!("{} {}", self.name, self.version)
+    }
+}
+
+// Check the metadata of a module that embeds it
+pub(crate) fn check_compatible(bytes: &[u8]) -> Result<(), SideFuzzError> {
+    match TargetMeta::find(bytes) {
+        Some(meta) => meta.check_compatible(),
+        None => Ok(()),
+    }
+}
+
+fn abi() -> u32 {
+    // Ok to unwrap, it is a constant
+    crate::META_ABI.parse().unwrap()
+}
+
+// Versions before 1.0 break compatibility with each minor version, later ones with each major version
+fn minor_version(version: &str) -> &str {
+    let mut parts = version.splitn(3, '.');
+    let major = parts.next().unwrap_or_default();
+    let minor = parts.next().unwrap_or_default();
+    if major == "0" {
+        &version[..(major.len() + 1 + minor.len()).min(version.len())]
+    } else {
+        major
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meta_test() {
+        let contents = format!(
+            "name=my_target\nversion=0.2.0\nsidefuzz={}\nabi=1\ninput=bytes\ninput_len=32\nlater=ignored\n",
+            crate::VERSION
+        );
+        let meta = TargetMeta::parse(contents.as_bytes()).unwrap();
+        assert_eq!(meta.label(), "my_target 0.2.0");
+        assert_eq!(meta.input_len, Some("32".to_string()));
+        assert!(meta.check_compatible().is_ok());
+        assert!(meta.warnings().is_empty());
+
+        let newer = TargetMeta {
+            abi: abi() + 1,
+            sidefuzz: "9.0.0".to_string(),
+            ..meta
+        };
+        assert!(newer.check_compatible().is_err());
+        assert_eq!(newer.warnings().len(), 1);
+        assert_eq!(minor_version("0.1.2"), "0.1");
+        assert_eq!(minor_version("1.4.0"), "1");
+    }
+}
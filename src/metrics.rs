@@ -0,0 +1,200 @@
+// A Prometheus metrics endpoint, so long campaigns can be watched by existing monitoring
+//
+// Any request to the address gets the latest statistics of the run in the Prometheus text format. Requests are
+// served from a thread of their own, and the statistics are only copied when they change, so scraping doesn't slow
+// fuzzing down. There is nothing to configure on the endpoint, every path returns the metrics.
+
+use crate::errors::SideFuzzError;
+use crate::tui::Stats;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How long a scraper may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    started: Instant,
+    stats: Stats,
+    best_t_value: Option<f64>, // Over the whole run, as `stats` only has the current one
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            started: Instant::now(),
+            stats: Stats::default(),
+            best_t_value: None,
+        }
+    }
+
+    pub fn update(&mut self, stats: &Stats) {
+        if let Some(t) = stats.t_value {
+            self.best_t_value = Some(self.best_t_value.map_or(t, |best| best.max(t)));
+        }
+        self.stats = stats.clone();
+    }
+
+    // The metrics in the Prometheus text format. Metrics that don't apply to the run are left out.
+    pub fn render(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.stats.executions as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        let mut out = String::new();
+        metric(
+            &mut out,
+            "sidefuzz_executions_total",
+            "counter",
+            "Executions of the target so far.",
+            self.stats.executions as f64,
+        );
+        metric(
+            &mut out,
+            "sidefuzz_executions_per_second",
+            "gauge",
+            "Executions per second since the run started.",
+            rate,
+        );
+        metric(
+            &mut out,
+            "sidefuzz_uptime_seconds",
+            "gauge",
+            "Seconds since the run started.",
+            elapsed,
+        );
+        if let Some(generations) = self.stats.generations {
+            metric(
+                &mut out,
+                "sidefuzz_generations_total",
+                "counter",
+                "Generations the optimizer has evolved.",
+                generations as f64,
+            );
+        }
+        if let Some(best_score) = self.stats.best_score {
+            metric(
+                &mut out,
+                "sidefuzz_best_difference",
+                "gauge",
+                "Largest instruction count difference found.",
+                best_score,
+            );
+        }
+        if let Some(t) = self.best_t_value {
+            metric(
+                &mut out,
+                "sidefuzz_best_t_value",
+                "gauge",
+                "Largest t-value seen while checking input pairs.",
+                t,
+            );
+        }
+        if let Some(crashes) = self.stats.crashes {
+            metric(
+                &mut out,
+                "sidefuzz_crashes_total",
+                "counter",
+                "Distinct inputs that crashed the target.",
+                crashes as f64,
+            );
+        }
+        if let Some(memory) = resident_memory() {
+            metric(
+                &mut out,
+                "process_resident_memory_bytes",
+                "gauge",
+                "Resident memory size in bytes.",
+                memory as f64,
+            );
+        }
+        out
+    }
+}
+
+// Serve the metrics at `addr`, such as "0.0.0.0:9100", until the process exits
+pub(crate) fn serve(addr: &str) -> Result<Arc<Mutex<Metrics>>, SideFuzzError> {
+    let listener = TcpListener::bind(addr).map_err(SideFuzzError::CouldNotServeMetrics)?;
+    let metrics = Arc::new(Mutex::new(Metrics::new()));
+    let served = metrics.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            // A scraper that goes away mid-request just misses a scrape
+            if let Ok(stream) = stream {
+                let _ = respond(stream, &served);
+            }
+        }
+    });
+    Ok(metrics)
+}
+
+fn respond(mut stream: TcpStream, metrics: &Mutex<Metrics>) -> std::io::Result<()> {
+    // The request itself doesn't matter, but it is read so the client doesn't see the connection reset
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request)?;
+
+    // Ok to unwrap, the lock is never held while anything can panic.
+    let body = metrics.lock().unwrap().render();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    out.push_str(&format!(
+        "# HELP {} {}\n# TYPE {} {}\n{} {}\n",
+        name, help, name, kind, name, value
+    ));
+}
+
+// The memory the process is using, on Linux
+#[cfg(target_os = "linux")]
+fn resident_memory() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if page_size <= 0 {
+        return None;
+    }
+    Some(pages * page_size as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_memory() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_test() {
+        let mut metrics = Metrics::new();
+        for t in [3.0, 7.5, 2.0].iter() {
+            metrics.update(&Stats {
+                executions: 2000,
+                t_value: Some(*t),
+                crashes: Some(3),
+                ..Stats::default()
+            });
+        }
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "# TYPE sidefuzz_executions_total counter\nsidefuzz_executions_total 2000\n"
+        ));
+        assert!(rendered.contains("\nsidefuzz_best_t_value 7.5\n"));
+        assert!(rendered.contains("\nsidefuzz_crashes_total 3\n"));
+        assert!(!rendered.contains("sidefuzz_generations_total"));
+    }
+}
@@ -0,0 +1,279 @@
+// This file contains the "minimize" subcommand
+
+use crate::dudect::{DudeCT, DudeResult};
+use crate::errors::SideFuzzError;
+use crate::report::{Report, Reporter, Verdict};
+use crate::target::{Target, TargetOptions};
+use crate::util::*;
+use crate::wasm::WasmModule;
+
+// A simplification is kept if the pair still differs by at least this fraction of the original difference
+const MIN_RETAINED: f64 = 0.5;
+
+// Give up after this many passes over the inputs
+const MAX_PASSES: usize = 16;
+
+pub struct Minimize {
+    module: Box<dyn Target>,
+    input: InputPair,
+    reporter: Reporter,
+}
+
+impl Minimize {
+    // Create a new minimize command with the given wasm module and leaking input pair
+    pub fn new(module: WasmModule, first: Vec<u8>, second: Vec<u8>) -> Result<Self, SideFuzzError> {
+        Self::with_target(Box::new(module), first, second)
+    }
+
+    fn with_target(
+        module: Box<dyn Target>,
+        first: Vec<u8>,
+        second: Vec<u8>,
+    ) -> Result<Self, SideFuzzError> {
+        if first.len() != second.len() {
+            return Err(SideFuzzError::InputsDifferentSizes);
+        }
+
+        if first.len() != module.fuzz_len() {
+            return Err(SideFuzzError::InputsWrongSize(module.fuzz_len()));
+        }
+
+        let is_str = module.input_is_str();
+        Ok(Minimize {
+            module,
+            input: InputPair {
+                first,
+                second,
+                is_str,
+            },
+            reporter: Reporter::default(),
+        })
+    }
+
+    pub fn from_file(
+        filename: &str,
+        first: Vec<u8>,
+        second: Vec<u8>,
+    ) -> Result<Self, SideFuzzError> {
+        let module = WasmModule::from_file(filename)?;
+        Self::new(module, first, second)
+    }
+
+    pub fn from_file_with_options(
+        filename: &str,
+        options: &TargetOptions,
+        first: Vec<u8>,
+        second: Vec<u8>,
+    ) -> Result<Self, SideFuzzError> {
+        let module = crate::target::load(filename, options)?;
+        Self::with_target(module, first, second)
+    }
+
+    // Set how progress and the final result are reported.
+    pub fn set_reporter(&mut self, reporter: Reporter) {
+        self.reporter = reporter;
+    }
+
+    pub fn run(&mut self) -> Result<(), SideFuzzError> {
+        let original = self.score(&self.input.clone());
+        if original.score <= 0.0 {
+            return Err(SideFuzzError::NoDifferenceToMinimize);
+        }
+        self.reporter.progress(format!(
+            "Minimizing input pair with a difference of {} instructions",
+            original.score
+        ));
+
+        let threshold = original.score * MIN_RETAINED;
+        let mut best = original;
+        for pass in 0..MAX_PASSES {
+            let mut changed = false;
+            for simplification in simplifications(best.pair.first.len()) {
+                let candidate = match simplification.apply(&best.pair) {
+                    Some(candidate) => candidate,
+                    None => continue,
+                };
+                let scored = self.score(&candidate);
+                if scored.score >= threshold {
+                    best = scored;
+                    changed = true;
+                }
+            }
+
+            self.reporter.progress(format!(
+                "pass {}: {} bytes differ, {} non-zero bytes, difference of {} instructions",
+                pass + 1,
+                differing_bytes(&best.pair).len(),
+                nonzero_bytes(&best.pair),
+                best.score
+            ));
+            if !changed {
+                break;
+            }
+        }
+
+        self.confirm(best)
+    }
+
+    fn score(&mut self, pair: &InputPair) -> ScoredInputPair {
+        ScoredInputPair::generate(
+            self.module.as_mut(),
+            pair.first.clone(),
+            pair.second.clone(),
+            pair.is_str,
+        )
+    }
+
+    // Check the minimized pair with DudeCT, and report it
+    fn confirm(&mut self, best: ScoredInputPair) -> Result<(), SideFuzzError> {
+        // Same thresholds as check
+        let mut dudect = DudeCT::new(
+            4.5,     // Success t-value
+            0.674,   // Give up t-value
+            100_000, // Give up min samples
+            &best.pair.first,
+            &best.pair.second,
+            self.module.try_clone()?,
+        )?;
+        dudect.set_recording(self.reporter.wants_evidence());
+
+        loop {
+            let (t, result) = dudect.sample(10_000)?;
+            let p = p_value_from_t_value(t);
+
+            let (first_mean, second_mean) = dudect.means();
+            let mut report = Report {
+                command: "minimize",
+                verdict: Verdict::Leak,
+                input_1: hex::encode(&best.pair.first),
+                input_2: hex::encode(&best.pair.second),
+                instructions_1: first_mean,
+                instructions_2: second_mean,
+                difference: best.score,
+                t_value: t,
+                confidence: (1.0 - p) * 100.0,
+                samples: dudect.len(),
+                effect: dudect.effect(),
+                rejected: dudect.rejected(),
+                ..Default::default()
+            };
+
+            match result {
+                DudeResult::Ok => {
+                    let differing: Vec<String> = differing_bytes(&best.pair)
+                        .iter()
+                        .map(|i| i.to_string())
+                        .collect();
+                    let mut text = format!(
+                        "Minimized input pair, with a timing difference of {} instructions at {}% confidence:\ninput 1: {}\ninput 2: {}\nbytes that differ: {}",
+                        best.score,
+                        (1.0 - p) * 100.0,
+                        hex::encode(&best.pair.first),
+                        hex::encode(&best.pair.second),
+                        differing.join(", ")
+                    );
+                    if let Some(effect) = &report.effect {
+                        text += &format!("\n{}", effect);
+                    }
+                    report.evidence = dudect.evidence();
+                    self.reporter.finish(&report, &text)?;
+                    std::process::exit(0);
+                }
+                DudeResult::Err => {
+                    report.verdict = Verdict::NoLeak;
+                    let text = "Minimized input pair rejected: t-statistic small after many samples. The original pair may not leak either.";
+                    report.evidence = dudect.evidence();
+                    self.reporter.finish(&report, text)?;
+                    std::process::exit(0);
+                }
+                DudeResult::Progress => {
+                    continue;
+                }
+            }
+        }
+    }
+}
+
+// A change to a single byte of an input pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Simplification {
+    ZeroFirst(usize),
+    ZeroSecond(usize),
+    Equalize(usize), // Make the second input's byte the same as the first's
+}
+
+impl Simplification {
+    // The simplified pair, or None if it wouldn't change anything or isn't a valid input.
+    // String inputs use '0' instead of zero, and must stay valid utf8.
+    fn apply(self, pair: &InputPair) -> Option<InputPair> {
+        let zero = if pair.is_str { b'0' } else { 0 };
+        let mut candidate = pair.clone();
+        match self {
+            Simplification::ZeroFirst(i) => candidate.first[i] = zero,
+            Simplification::ZeroSecond(i) => candidate.second[i] = zero,
+            Simplification::Equalize(i) => candidate.second[i] = candidate.first[i],
+        }
+
+        if candidate.first == pair.first && candidate.second == pair.second {
+            return None;
+        }
+        if pair.is_str
+            && (std::str::from_utf8(&candidate.first).is_err()
+                || std::str::from_utf8(&candidate.second).is_err())
+        {
+            return None;
+        }
+        Some(candidate)
+    }
+}
+
+// Every simplification of pairs of `len` byte inputs, in the order they are tried: zeroing bytes, then making bytes equal
+fn simplifications(len: usize) -> Vec<Simplification> {
+    let mut simplifications = Vec::with_capacity(len * 3);
+    for i in 0..len {
+        simplifications.push(Simplification::ZeroFirst(i));
+        simplifications.push(Simplification::ZeroSecond(i));
+    }
+    for i in 0..len {
+        simplifications.push(Simplification::Equalize(i));
+    }
+    simplifications
+}
+
+// Positions at which the two inputs differ
+fn differing_bytes(pair: &InputPair) -> Vec<usize> {
+    (0..pair.first.len())
+        .filter(|&i| pair.first[i] != pair.second[i])
+        .collect()
+}
+
+fn nonzero_bytes(pair: &InputPair) -> usize {
+    pair.first
+        .iter()
+        .chain(pair.second.iter())
+        .filter(|b| **b != 0)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::minimize::*;
+
+    #[test]
+    fn simplifications_test() {
+        let pair = InputPair {
+            first: vec![0, 1],
+            second: vec![0, 2],
+            is_str: false,
+        };
+        let applied: Vec<InputPair> = simplifications(2)
+            .into_iter()
+            .filter_map(|s| s.apply(&pair))
+            .collect();
+        assert_eq!(applied.len(), 3);
+        assert_eq!(applied[0].first, vec![0, 0]);
+        assert_eq!(applied[1].second, vec![0, 0]);
+        assert_eq!(applied[2].second, vec![0, 1]);
+        assert_eq!(differing_bytes(&pair), vec![1]);
+    }
+}
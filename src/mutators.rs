@@ -0,0 +1,453 @@
+// Mutation strategies used by the optimizer
+
+use crate::errors::SideFuzzError;
+use rand::{seq::SliceRandom, Rng, RngCore};
+use std::fmt;
+use std::sync::Arc;
+
+// Ratio of "large mutations" (random u8 replacement) vs "small mutations" u8 increment / decrement.
+const LARGE_MUTATION_RATIO: f64 = 0.25;
+
+// Largest amount the arithmetic mutator adds or subtracts
+const ARITH_MAX: u8 = 35;
+
+// Values that often sit on boundaries in the code being fuzzed, used by the dictionary mutator
+const INTERESTING_VALUES: &[&[u8]] = &[
+    &[0x00],
+    &[0x01],
+    &[0x7f],
+    &[0x80],
+    &[0xff],
+    &[0x00, 0x00],
+    &[0xff, 0xff],
+    &[0x7f, 0xff],
+    &[0x80, 0x00],
+    &[0x00, 0x00, 0x00, 0x00],
+    &[0xff, 0xff, 0xff, 0xff],
+    &[0x7f, 0xff, 0xff, 0xff],
+    &[0x80, 0x00, 0x00, 0x00],
+];
+
+/// A way of changing an input.
+///
+/// Inputs have a fixed length, so mutators change bytes in place rather than inserting or removing them.
+/// `input` is never empty.
+pub trait Mutator: Send + Sync {
+    /// A short name for the mutator, as used on the command line
+    fn name(&self) -> &str;
+
+    fn mutate(&self, rng: &mut dyn RngCore, input: &mut [u8]);
+}
+
+/// Replace a random byte, or increment or decrement it. This is the default.
+pub struct ByteMutator;
+
+impl Mutator for ByteMutator {
+    fn name(&self) -> &str {
+        "byte"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, input: &mut [u8]) {
+        // OK to unwrap here, input should never be empty
+        let mutating_gene = input.choose_mut(rng).unwrap();
+
+        if rng.gen_bool(LARGE_MUTATION_RATIO) {
+            // Large mutation, assign another random u8
+            *mutating_gene = rng.gen();
+        } else {
+            // Small mutation, increment or decrement
+            if rng.gen() {
+                *mutating_gene = mutating_gene.wrapping_add(1);
+            } else {
+                *mutating_gene = mutating_gene.wrapping_sub(1);
+            }
+        }
+    }
+}
+
+/// Flip a single random bit
+pub struct BitFlipMutator;
+
+impl Mutator for BitFlipMutator {
+    fn name(&self) -> &str {
+        "bitflip"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, input: &mut [u8]) {
+        let bit = rng.gen_range(0, input.len() * 8);
+        input[bit / 8] ^= 1 << (bit % 8);
+    }
+}
+
+/// Swap two random bytes
+pub struct SwapMutator;
+
+impl Mutator for SwapMutator {
+    fn name(&self) -> &str {
+        "swap"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, input: &mut [u8]) {
+        let first = rng.gen_range(0, input.len());
+        let second = rng.gen_range(0, input.len());
+        input.swap(first, second);
+    }
+}
+
+/// Add or subtract a small amount from a random byte
+pub struct ArithMutator;
+
+impl Mutator for ArithMutator {
+    fn name(&self) -> &str {
+        "arith"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, input: &mut [u8]) {
+        let amount = rng.gen_range(1, ARITH_MAX + 1);
+        // OK to unwrap here, input should never be empty
+        let byte = input.choose_mut(rng).unwrap();
+        if rng.gen() {
+            *byte = byte.wrapping_add(amount);
+        } else {
+            *byte = byte.wrapping_sub(amount);
+        }
+    }
+}
+
+/// Copy a random block of the input over another part of it
+pub struct DuplicateMutator;
+
+impl Mutator for DuplicateMutator {
+    fn name(&self) -> &str {
+        "duplicate"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, input: &mut [u8]) {
+        let len = rng.gen_range(1, input.len() + 1);
+        let from = rng.gen_range(0, input.len() - len + 1);
+        let to = rng.gen_range(0, input.len() - len + 1);
+        input.copy_within(from..from + len, to);
+    }
+}
+
+/// Replace a random character with another that is just as long when encoded, so UTF-8 strings stay valid.
+/// Bytes that aren't part of a valid character are replaced with printable ASCII.
+pub struct CharMutator;
+
+impl Mutator for CharMutator {
+    fn name(&self) -> &str {
+        "char"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, input: &mut [u8]) {
+        // Back up to the start of the character the random byte is part of
+        let mut at = rng.gen_range(0, input.len());
+        while at > 0 && input[at] & 0xc0 == 0x80 {
+            at -= 1;
+        }
+        let width = match input[at] {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            _ => 4,
+        };
+
+        let valid = input
+            .get(at..at + width)
+            .map_or(false, |bytes| std::str::from_utf8(bytes).is_ok());
+        if valid {
+            let mut buf = [0; 4];
+            let replacement = crate::strings::random_char(rng, width).encode_utf8(&mut buf);
+            input[at..at + width].copy_from_slice(replacement.as_bytes());
+        } else {
+            input[at] = rng.gen_range(0x20, 0x7f);
+        }
+    }
+}
+
+/// Overwrite part of the input with a token, such as a magic number or a boundary value
+pub struct DictionaryMutator {
+    tokens: Vec<Vec<u8>>,
+}
+
+impl DictionaryMutator {
+    /// A dictionary of values on common integer boundaries (0, 0x7f, 0x80, 0xff, ...)
+    pub fn new() -> Self {
+        Self::with_tokens(
+            INTERESTING_VALUES
+                .iter()
+                .map(|value| value.to_vec())
+                .collect(),
+        )
+    }
+
+    pub fn with_tokens(tokens: Vec<Vec<u8>>) -> Self {
+        DictionaryMutator {
+            tokens: tokens
+                .into_iter()
+                .filter(|token| !token.is_empty())
+                .collect(),
+        }
+    }
+
+    /// The boundary values of [`DictionaryMutator::new`], and the tokens of an AFL dictionary file
+    pub fn load(path: &str) -> Result<Self, SideFuzzError> {
+        let dictionary = std::fs::read_to_string(path)?;
+        let mut tokens: Vec<Vec<u8>> = INTERESTING_VALUES
+            .iter()
+            .map(|value| value.to_vec())
+            .collect();
+        tokens.extend(parse_dictionary(&dictionary)?);
+        Ok(Self::with_tokens(tokens))
+    }
+}
+
+impl Default for DictionaryMutator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mutator for DictionaryMutator {
+    fn name(&self) -> &str {
+        "dictionary"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, input: &mut [u8]) {
+        let fitting: Vec<&Vec<u8>> = self
+            .tokens
+            .iter()
+            .filter(|token| token.len() <= input.len())
+            .collect();
+        let token = match fitting.choose(rng) {
+            Some(token) => token,
+            None => return,
+        };
+        let at = rng.gen_range(0, input.len() - token.len() + 1);
+        input[at..at + token.len()].copy_from_slice(token);
+    }
+}
+
+// Parse the tokens of an AFL dictionary, with one token per line such as `header="\x89PNG"` or just `"\x89PNG"`.
+// Blank lines and lines starting with # are skipped.
+fn parse_dictionary(dictionary: &str) -> Result<Vec<Vec<u8>>, SideFuzzError> {
+    let mut tokens = Vec::new();
+    for (number, line) in dictionary.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad =
+            |reason: &str| SideFuzzError::BadDictionary(format!("line {}: {}", number + 1, reason));
+
+        // The name (and @level) before the value is optional, and unused
+        let start = line
+            .find('"')
+            .ok_or_else(|| bad("expected a quoted value"))?;
+        let value = &line[start + 1..];
+        if value.is_empty() || !value.ends_with('"') {
+            return Err(bad("expected a closing quote"));
+        }
+        let value = &value[..value.len() - 1];
+
+        let mut token = Vec::with_capacity(value.len());
+        let mut bytes = value.bytes();
+        while let Some(byte) = bytes.next() {
+            if byte != b'\\' {
+                token.push(byte);
+                continue;
+            }
+            match bytes.next() {
+                Some(b'\\') => token.push(b'\\'),
+                Some(b'"') => token.push(b'"'),
+                Some(b'x') => {
+                    let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                    let hex = std::str::from_utf8(&hex).map_err(|_| bad("invalid \\x escape"))?;
+                    let byte = u8::from_str_radix(hex, 16)
+                        .ok()
+                        .filter(|_| hex.len() == 2)
+                        .ok_or_else(|| bad("invalid \\x escape"))?;
+                    token.push(byte);
+                }
+                _ => return Err(bad("unknown escape, expected \\\\, \\\" or \\xNN")),
+            }
+        }
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// A built-in mutator, by name
+fn builtin(name: &str) -> Result<Arc<dyn Mutator>, SideFuzzError> {
+    match name {
+        "byte" => Ok(Arc::new(ByteMutator)),
+        "bitflip" => Ok(Arc::new(BitFlipMutator)),
+        "swap" => Ok(Arc::new(SwapMutator)),
+        "arith" => Ok(Arc::new(ArithMutator)),
+        "duplicate" => Ok(Arc::new(DuplicateMutator)),
+        "char" => Ok(Arc::new(CharMutator)),
+        "dictionary" => Ok(Arc::new(DictionaryMutator::new())),
+        _ => Err(SideFuzzError::BadMutator(format!(
+            "unknown mutator '{}'",
+            name
+        ))),
+    }
+}
+
+/// The mutators the optimizer picks from, each with a weight.
+///
+/// By default only [`ByteMutator`] is used.
+#[derive(Clone)]
+pub struct Mutators {
+    mutators: Vec<(u32, Arc<dyn Mutator>)>,
+}
+
+impl Mutators {
+    /// No mutators, add some with [`Mutators::add`]
+    pub fn empty() -> Self {
+        Mutators {
+            mutators: Vec::new(),
+        }
+    }
+
+    /// Use `mutator` with the given relative weight
+    pub fn add<M: Mutator + 'static>(&mut self, weight: u32, mutator: M) {
+        self.mutators.push((weight, Arc::new(mutator)));
+    }
+
+    /// Parse a list of built-in mutators with optional weights, such as "bitflip=2,swap,dictionary=3"
+    pub fn parse(arg: &str) -> Result<Self, SideFuzzError> {
+        let mut mutators = Self::empty();
+        for part in arg
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let mut parts = part.splitn(2, '=');
+            // Ok to unwrap, split always returns at least one part
+            let name = parts.next().unwrap();
+            let weight = match parts.next() {
+                Some(weight) => weight.parse().map_err(|_| {
+                    SideFuzzError::BadMutator(format!(
+                        "weight of '{}' must be a whole number",
+                        name
+                    ))
+                })?,
+                None => 1,
+            };
+            mutators.mutators.push((weight, builtin(name)?));
+        }
+        if mutators.total_weight() == 0 {
+            return Err(SideFuzzError::BadMutator(
+                "at least one mutator needs a weight above zero".to_string(),
+            ));
+        }
+        Ok(mutators)
+    }
+
+    /// Also write the tokens of `dictionary` into inputs.
+    /// Any dictionary mutator already in use is replaced, otherwise it is added with a weight of 1.
+    pub fn set_dictionary(&mut self, dictionary: DictionaryMutator) {
+        let dictionary: Arc<dyn Mutator> = Arc::new(dictionary);
+        for (_, mutator) in self.mutators.iter_mut() {
+            if mutator.name() == "dictionary" {
+                *mutator = dictionary;
+                return;
+            }
+        }
+        self.mutators.push((1, dictionary));
+    }
+
+    fn total_weight(&self) -> u64 {
+        self.mutators.iter().map(|(weight, _)| *weight as u64).sum()
+    }
+
+    /// Pick a mutator, according to the weights
+    pub fn choose<R: Rng>(&self, rng: &mut R) -> &dyn Mutator {
+        // Don't use up any randomness when there is no choice, so runs with the default mutator stay reproducible
+        if self.mutators.len() == 1 {
+            return self.mutators[0].1.as_ref();
+        }
+        let total = self.total_weight();
+        if total == 0 {
+            return &ByteMutator;
+        }
+
+        let mut pick = rng.gen_range(0, total);
+        for (weight, mutator) in self.mutators.iter() {
+            if pick < *weight as u64 {
+                return mutator.as_ref();
+            }
+            pick -= *weight as u64;
+        }
+        unreachable!("pick is below the total weight")
+    }
+}
+
+impl Default for Mutators {
+    fn default() -> Self {
+        let mut mutators = Self::empty();
+        mutators.add(1, ByteMutator);
+        mutators
+    }
+}
+
+impl fmt::Debug for Mutators {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.mutators
+                    .iter()
+                    .map(|(weight, mutator)| format!("{}={}", mutator.name(), weight)),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn mutators_test() {
+        let mutators = Mutators::parse("bitflip=2, swap,duplicate=0,dictionary").unwrap();
+        assert_eq!(
+            format!("{:?}", mutators),
+            "[\"bitflip=2\", \"swap=1\", \"duplicate=0\", \"dictionary=1\"]"
+        );
+        assert!(Mutators::parse("shuffle").is_err());
+        assert!(Mutators::parse("swap=often").is_err());
+        assert!(Mutators::parse("swap=0").is_err());
+
+        let tokens = parse_dictionary(
+            "# A comment\n\nheader=\"\\x89PNG\"\nkw@1=\"a\\\\b\\\"\"\n\"plain\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            tokens,
+            vec![b"\x89PNG".to_vec(), b"a\\b\"".to_vec(), b"plain".to_vec()]
+        );
+        assert!(parse_dictionary("header=\"\\x8\"").is_err());
+        assert!(parse_dictionary("header=\"unterminated").is_err());
+        assert!(parse_dictionary("no quotes").is_err());
+
+        // Every mutator keeps the input's length, even a single byte
+        let mut rng = StdRng::seed_from_u64(0);
+        let all = Mutators::parse("byte,bitflip,swap,arith,duplicate,dictionary,char").unwrap();
+        for len in 1..8 {
+            let mut input = vec![0; len];
+            for _ in 0..100 {
+                all.choose(&mut rng).mutate(&mut rng, &mut input);
+                assert_eq!(input.len(), len);
+            }
+        }
+
+        // The char mutator keeps strings valid
+        let mut input = "aé€😀b".as_bytes().to_vec();
+        for _ in 0..100 {
+            CharMutator.mutate(&mut rng, &mut input);
+            assert!(std::str::from_utf8(&input).is_ok());
+        }
+    }
+}
@@ -0,0 +1,178 @@
+// Native shared library targets
+//
+// Not everything can be compiled to wasm. A target built as a cdylib exposes the same exports as a wasm target,
+// and is measured while `fuzz` runs by the counter of the OS, see `perf`. On Linux that counts the user-space
+// instructions retired.
+//
+// Native targets run in-process. A target that crashes takes the fuzzer down with it.
+
+use crate::errors::SideFuzzError;
+use crate::perf::ExecutionCounter;
+use crate::target::Target;
+use float_duration::{FloatDuration, TimePoint};
+use libloading::Library;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub struct NativeModule {
+    library: Arc<Library>,
+    fuzz: unsafe extern "C" fn(),
+    fuzz_segments: Vec<(usize, usize)>, // (address, length) of each input, in order
+    public_segments: Vec<bool>,
+    fuzz_len: usize,
+    input_is_str: bool,
+    counter: Box<dyn ExecutionCounter>,
+    rng: StdRng,
+    timeout: Option<Duration>,
+}
+
+impl NativeModule {
+    pub fn from_file(filename: &str) -> Result<Self, SideFuzzError> {
+        // This is unsafe as loading a library runs its initializers, we trust the target.
+        let library = unsafe { Library::new(filename) }
+            .map_err(|e| SideFuzzError::NativeLibraryError(e.to_string()))?;
+        Self::with_library(Arc::new(library))
+    }
+
+    // Report executions that run for longer than `timeout`.
+    // Native code can't be interrupted, so they are only reported once they finish.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    // What executions are measured in on this OS, such as "instructions"
+    pub fn unit(&self) -> &'static str {
+        self.counter.unit()
+    }
+
+    fn with_library(library: Arc<Library>) -> Result<Self, SideFuzzError> {
+        unsafe {
+            let fuzz: unsafe extern "C" fn() = symbol(&library, "fuzz")?;
+            let input_address: unsafe extern "C" fn() -> usize = symbol(&library, "input_address")?;
+            let input_len: unsafe extern "C" fn() -> i32 = symbol(&library, "input_len")?;
+            let input_count: unsafe extern "C" fn() -> i32 = symbol(&library, "input_count")?;
+            let input_len_at: unsafe extern "C" fn(i32) -> i32 = symbol(&library, "input_len_at")?;
+
+            // Natively fetch_input doesn't stop execution when it sets the input length,
+            // the first call just runs the target with an all zero input.
+            fuzz();
+
+            let fuzz_len = input_len();
+
+            // Inputs are stored one after the other
+            let mut address = input_address();
+            let mut fuzz_segments = Vec::new();
+            for index in 0..input_count() {
+                let len = input_len_at(index).max(0) as usize;
+                fuzz_segments.push((address, len));
+                address += len;
+            }
+            if fuzz_segments.iter().map(|(_, len)| len).sum::<usize>() != fuzz_len as usize {
+                return Err(SideFuzzError::WasmModuleBadInputSegments);
+            }
+
+            // Libraries that don't say otherwise take bytes
+            let input_is_str: Option<unsafe extern "C" fn() -> i32> =
+                symbol(&library, "input_is_str").ok();
+
+            // Libraries built against older versions of the sidefuzz crate have no public inputs
+            let input_is_public_at: Option<unsafe extern "C" fn(i32) -> i32> =
+                symbol(&library, "input_is_public_at").ok();
+            let public_segments = (0..fuzz_segments.len())
+                .map(|index| input_is_public_at.map_or(false, |public| public(index as i32) > 0))
+                .collect();
+
+            Ok(NativeModule {
+                library,
+                fuzz,
+                fuzz_segments,
+                public_segments,
+                fuzz_len: fuzz_len as usize,
+                input_is_str: input_is_str.map_or(false, |is_str| is_str() > 0),
+                counter: crate::perf::counter()?,
+                rng: StdRng::seed_from_u64(0),
+                timeout: None,
+            })
+        }
+    }
+}
+
+// Look up an exported function
+unsafe fn symbol<T: Copy>(library: &Library, name: &str) -> Result<T, SideFuzzError> {
+    match library.get::<T>(name.as_bytes()) {
+        Ok(symbol) => Ok(*symbol),
+        Err(_) => Err(SideFuzzError::NativeLibraryError(format!(
+            "expected to have '{}' function export",
+            name
+        ))),
+    }
+}
+
+impl Target for NativeModule {
+    fn count_instructions(&mut self, input: &[u8]) -> Result<u64, SideFuzzError> {
+        let mut offset = 0;
+        for (address, len) in self.fuzz_segments.iter() {
+            let segment = match input.get(offset..offset + len) {
+                Some(segment) => segment,
+                None => break,
+            };
+            // This is safe as long as the target follows the ABI, the segments lie within its input buffer.
+            unsafe {
+                std::ptr::copy_nonoverlapping(segment.as_ptr(), *address as *mut u8, *len);
+            }
+            offset += len;
+        }
+
+        let start_time = Instant::now();
+        self.counter.start()?;
+        unsafe { (self.fuzz)() };
+        let count = self.counter.stop()?;
+        if let Some(timeout) = self.timeout {
+            if start_time.elapsed() > timeout {
+                return Err(SideFuzzError::Timeout(timeout.as_millis() as u64));
+            }
+        }
+        Ok(count)
+    }
+
+    fn measure_time(&mut self) -> Result<FloatDuration, SideFuzzError> {
+        let input: Vec<u8> = (0..self.fuzz_len).map(|_| self.rng.gen::<u8>()).collect();
+        let start_time = Instant::now();
+        self.count_instructions(&input)?;
+        let end_time = Instant::now();
+
+        Ok(end_time.float_duration_since(start_time).unwrap())
+    }
+
+    fn fuzz_len(&self) -> usize {
+        self.fuzz_len
+    }
+
+    fn input_segments(&self) -> Vec<usize> {
+        self.fuzz_segments.iter().map(|(_, len)| *len).collect()
+    }
+
+    fn input_is_str(&self) -> bool {
+        self.input_is_str
+    }
+
+    fn public_segments(&self) -> Vec<bool> {
+        self.public_segments.clone()
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // Clones share the library, and so its global state
+    fn try_clone(&self) -> Result<Box<dyn Target>, SideFuzzError> {
+        let mut module = Self::with_library(self.library.clone())?;
+        module.set_timeout(self.timeout);
+        Ok(Box::new(module))
+    }
+
+    fn parallel(&self) -> bool {
+        false
+    }
+}
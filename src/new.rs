@@ -0,0 +1,203 @@
+// This file contains the "new" subcommand
+
+use crate::errors::SideFuzzError;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const LIB_RS: &str = r#"// The fuzz target. Replace `compare` with the function you want to check for timing leaks.
+
+// A variable-time comparison, returning as soon as the inputs differ. `sidefuzz fuzz` finds the leak.
+fn compare(first: &[u8], second: &[u8]) -> bool {
+    for (a, b) in first.iter().zip(second.iter()) {
+        if a != b {
+            return false;
+        }
+    }
+    true
+}
+
+// 32 bytes of fuzzing input, compared against a fixed secret
+sidefuzz::sidefuzz_target!(32, |input: &[u8]| compare(input, &[0x42; 32]));
+"#;
+
+const CARGO_CONFIG: &str = r#"# Fuzz targets are built for wasm by default
+[build]
+target = "wasm32-unknown-unknown"
+"#;
+
+const BUILD_SH: &str = r#"#!/bin/sh
+# Build the fuzz target. Always build in release mode, debug builds are too slow to fuzz and their timing differs.
+set -e
+cargo build --release --target wasm32-unknown-unknown
+echo "Built target/wasm32-unknown-unknown/release/{lib}.wasm"
+echo "Fuzz it with: sidefuzz fuzz target/wasm32-unknown-unknown/release/{lib}.wasm"
+"#;
+
+const GITIGNORE: &str = "/target\nCargo.lock\n";
+
+pub struct NewTarget {
+    path: PathBuf,
+    name: String,
+    lib: String,               // Contents of src/lib.rs
+    dependencies: Vec<String>, // Extra lines for the [dependencies] section
+    sidefuzz: String,          // How sidefuzz is depended on
+    release: Vec<String>,      // Extra lines for the [profile.release] section
+}
+
+impl NewTarget {
+    // Create a new target project at `path`, named after its last component
+    pub fn new(path: &str) -> Result<Self, SideFuzzError> {
+        let path = PathBuf::from(path);
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string();
+
+        let valid = name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(SideFuzzError::BadTargetName(name));
+        }
+
+        Ok(NewTarget {
+            path,
+            name,
+            lib: LIB_RS.to_string(),
+            dependencies: Vec::new(),
+            sidefuzz: format!("\"{}\"", env!("CARGO_PKG_VERSION")),
+            release: Vec::new(),
+        })
+    }
+
+    // Use `lib` as src/lib.rs instead of the example target
+    pub(crate) fn with_lib(mut self, lib: String) -> Self {
+        self.lib = lib;
+        self
+    }
+
+    // Also depend on a crate, given as a line of the [dependencies] section
+    pub(crate) fn with_dependency(mut self, dependency: String) -> Self {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    // Depend on the sidefuzz crate at `path` instead of the release matching this version
+    pub(crate) fn with_sidefuzz_path(mut self, path: &Path) -> Self {
+        self.sidefuzz = format!("{{ path = {:?} }}", path.to_string_lossy());
+        self
+    }
+
+    // Also set something in the [profile.release] section, given as a line of it
+    pub(crate) fn with_release_setting(mut self, setting: &str) -> Self {
+        self.release.push(setting.to_string());
+        self
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The files making up the project, relative to its directory
+    fn files(&self) -> Vec<(&'static str, String)> {
+        let lib = self.name.replace('-', "_");
+        vec![
+            ("Cargo.toml", self.manifest()),
+            ("src/lib.rs", self.lib.clone()),
+            (".cargo/config.toml", CARGO_CONFIG.to_string()),
+            ("build.sh", BUILD_SH.replace("{lib}", &lib)),
+            (".gitignore", GITIGNORE.to_string()),
+        ]
+    }
+
+    fn manifest(&self) -> String {
+        format!(
+            r#"[package]
+name = "{}"
+version = "0.1.0"
+edition = "2018"
+
+[lib]
+crate-type = ["cdylib"]
+
+[dependencies]
+sidefuzz = {}
+{}
+[profile.release]
+# Keep the name section, so that leaks can be localized to functions
+strip = false
+{}"#,
+            self.name,
+            self.sidefuzz,
+            self.dependencies
+                .iter()
+                .map(|dependency| format!("{}\n", dependency))
+                .collect::<String>(),
+            self.release
+                .iter()
+                .map(|setting| format!("{}\n", setting))
+                .collect::<String>()
+        )
+    }
+
+    // Write the project. Nothing is overwritten, the directory must not exist yet.
+    pub fn create(&self) -> Result<(), SideFuzzError> {
+        fs::create_dir_all(self.path.parent().unwrap_or_else(|| Path::new(".")))
+            .map_err(SideFuzzError::CouldNotWriteFile)?;
+        fs::create_dir(&self.path).map_err(SideFuzzError::CouldNotWriteFile)?;
+
+        for (file, contents) in self.files() {
+            let path = self.path.join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(SideFuzzError::CouldNotWriteFile)?;
+            }
+            fs::write(&path, contents).map_err(SideFuzzError::CouldNotWriteFile)?;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let build = self.path.join("build.sh");
+            fs::set_permissions(build, fs::Permissions::from_mode(0o755))
+                .map_err(SideFuzzError::CouldNotWriteFile)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn run(&self) -> Result<(), SideFuzzError> {
+        self.create()?;
+        println!(
+            "Created fuzz target {} in {}\nBuild it with ./build.sh, or: cd {} && cargo build --release",
+            self.name,
+            self.path.display(),
+            self.path.display()
+        );
+        std::process::exit(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_target_test() {
+        assert!(NewTarget::new("targets/my-target").is_ok());
+        assert!(NewTarget::new("1target").is_err());
+        assert!(NewTarget::new("my target").is_err());
+
+        let dir = std::env::temp_dir().join(format!("sidefuzz-new-{}", std::process::id()));
+        let path = dir.join("my-target");
+        let target = NewTarget::new(path.to_str().unwrap()).unwrap();
+        target.create().unwrap();
+        let manifest = fs::read_to_string(path.join("Cargo.toml")).unwrap();
+        assert!(manifest.contains("name = \"my-target\""));
+        assert!(fs::read_to_string(path.join("build.sh")).unwrap().contains("my_target.wasm"));
+
+        // Existing projects are left alone
+        assert!(target.create().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
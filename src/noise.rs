@@ -0,0 +1,139 @@
+// Simulated measurement noise, for judging how exploitable a leak is outside of sidefuzz
+//
+// Instruction counts are exact, so even a one instruction difference is found in a handful of samples. An attacker
+// timing the target for real sees every execution blurred by noise. Adding noise of a similar size to each count
+// before it is analyzed shows how many measurements it would take them to see the difference.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::f64::consts::PI;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+// How often the cache model's measurements include a cache miss, and how large one is on average
+const MISS_RATE: f64 = 0.05;
+const MISS_SCALE: f64 = 20.0;
+
+// How often the cache model's measurements are interrupted, and by how much
+const INTERRUPT_RATE: f64 = 0.001;
+const INTERRUPT_SCALE: f64 = 500.0;
+
+// The kind of noise, with its scale in instructions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseModel {
+    // Normally distributed jitter with this standard deviation
+    Gaussian(f64),
+    // Jitter as above, plus occasional cache misses and rare interrupts that only ever add time, in multiples of it
+    Cache(f64),
+}
+
+impl FromStr for NoiseModel {
+    type Err = String;
+
+    // Such as "gaussian:100" or "cache:20"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap_or_default();
+        let scale = match parts.next().map(str::parse::<f64>) {
+            Some(Ok(scale)) if scale > 0.0 && scale.is_finite() => scale,
+            _ => {
+                return Err(format!(
+                    "noise '{}' needs a positive scale in instructions, such as {}:100",
+                    s, kind
+                ))
+            }
+        };
+        match kind {
+            "gaussian" => Ok(NoiseModel::Gaussian(scale)),
+            "cache" => Ok(NoiseModel::Cache(scale)),
+            _ => Err(format!("unknown noise model '{}'", kind)),
+        }
+    }
+}
+
+impl Display for NoiseModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NoiseModel::Gaussian(scale) => write!(f, "gaussian noise of {} instructions", scale),
+            NoiseModel::Cache(scale) => write!(
+                f,
+                "cache noise of {} instructions, with misses and interrupts",
+                scale
+            ),
+        }
+    }
+}
+
+pub(crate) struct Noise {
+    model: NoiseModel,
+    rng: StdRng,
+}
+
+impl Noise {
+    // Noise drawn from `seed`, so runs with the same seed are measured with the same noise
+    pub fn new(model: NoiseModel, seed: u64) -> Self {
+        Noise {
+            model,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // The count as it might have been measured. Like real measurements, it is a whole number and never negative.
+    pub fn add(&mut self, count: f64) -> f64 {
+        let noisy = match self.model {
+            NoiseModel::Gaussian(scale) => count + scale * self.standard_normal(),
+            NoiseModel::Cache(scale) => {
+                let mut noisy = count + scale * self.standard_normal();
+                if self.rng.gen_bool(MISS_RATE) {
+                    noisy += scale * MISS_SCALE * self.standard_exponential();
+                }
+                if self.rng.gen_bool(INTERRUPT_RATE) {
+                    noisy += scale * INTERRUPT_SCALE * self.standard_exponential();
+                }
+                noisy
+            }
+        };
+        noisy.round().max(0.0)
+    }
+
+    // Box-Muller, rather than depending on rand_distr for its normal distribution
+    fn standard_normal(&mut self) -> f64 {
+        let u: f64 = 1.0 - self.rng.gen::<f64>(); // In (0, 1], so the logarithm is finite
+        let v: f64 = self.rng.gen();
+        (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+
+    fn standard_exponential(&mut self) -> f64 {
+        let u: f64 = 1.0 - self.rng.gen::<f64>();
+        -u.ln()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_test() {
+        assert_eq!("gaussian:100".parse(), Ok(NoiseModel::Gaussian(100.0)));
+        assert_eq!("cache:2.5".parse(), Ok(NoiseModel::Cache(2.5)));
+        assert!("gaussian".parse::<NoiseModel>().is_err());
+        assert!("pink:1".parse::<NoiseModel>().is_err());
+
+        let mut noise = Noise::new(NoiseModel::Gaussian(10.0), 1);
+        let samples: Vec<f64> = (0..10_000).map(|_| noise.add(1000.0)).collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance =
+            samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / samples.len() as f64;
+        assert!((mean - 1000.0).abs() < 1.0);
+        assert!((variance.sqrt() - 10.0).abs() < 1.0);
+
+        let mut cache = Noise::new(NoiseModel::Cache(10.0), 1);
+        let slower = (0..10_000).filter(|_| cache.add(1000.0) > 1050.0).count();
+        assert!(slower > 100 && slower < 1000);
+
+        // The same seed gives the same noise
+        let mut again = Noise::new(NoiseModel::Gaussian(10.0), 1);
+        let repeated: Vec<f64> = (0..10_000).map(|_| again.add(1000.0)).collect();
+        assert_eq!(repeated, samples);
+    }
+}
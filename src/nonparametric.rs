@@ -0,0 +1,201 @@
+// Nonparametric tests for comparing two classes of samples.
+//
+// Instruction counts are often multi-modal, with a handful of distinct values repeated many times,
+// so samples are kept as a histogram instead of individually. Both tests report a z-score, so that
+// they can be compared against the same thresholds as Welch's t-test.
+
+use std::collections::BTreeMap;
+
+// How many times each value was seen in each class
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Histogram {
+    // Keyed by the bits of the sample, which sort like the samples themselves since they are never negative
+    counts: BTreeMap<u64, (u64, u64)>,
+    first_count: u64,
+    second_count: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram::default()
+    }
+
+    pub fn record_first(&mut self, sample: f64) {
+        self.counts.entry(sample.to_bits()).or_default().0 += 1;
+        self.first_count += 1;
+    }
+
+    pub fn record_second(&mut self, sample: f64) {
+        self.counts.entry(sample.to_bits()).or_default().1 += 1;
+        self.second_count += 1;
+    }
+
+    // The Mann-Whitney U test, with the normal approximation corrected for ties
+    pub fn mann_whitney_z(&self) -> f64 {
+        let n1 = self.first_count as f64;
+        let n2 = self.second_count as f64;
+        let n = n1 + n2;
+        if self.first_count == 0 || self.second_count == 0 {
+            return 0.0;
+        }
+
+        let mut rank = 0.0;
+        let mut first_rank_sum = 0.0;
+        let mut ties = 0.0;
+        for (first, second) in self.counts.values() {
+            let tied = (first + second) as f64;
+            // Tied samples all get the average of the ranks they span
+            let average_rank = rank + (tied + 1.0) / 2.0;
+            first_rank_sum += *first as f64 * average_rank;
+            ties += tied * tied * tied - tied;
+            rank += tied;
+        }
+
+        let u = first_rank_sum - n1 * (n1 + 1.0) / 2.0;
+        let mean = n1 * n2 / 2.0;
+        let variance = n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+        if variance <= 0.0 {
+            // Every sample is the same
+            return 0.0;
+        }
+
+        ((u - mean) / variance.sqrt()).abs()
+    }
+
+    // The two-sample Kolmogorov-Smirnov test
+    pub fn ks_z(&self) -> f64 {
+        if self.first_count == 0 || self.second_count == 0 {
+            return 0.0;
+        }
+        let n1 = self.first_count as f64;
+        let n2 = self.second_count as f64;
+
+        // The largest distance between the two empirical distributions
+        let mut first_seen = 0;
+        let mut second_seen = 0;
+        let mut distance: f64 = 0.0;
+        for (first, second) in self.counts.values() {
+            first_seen += first;
+            second_seen += second;
+            let difference = (first_seen as f64 / n1 - second_seen as f64 / n2).abs();
+            distance = distance.max(difference);
+        }
+
+        let effective = (n1 * n2 / (n1 + n2)).sqrt();
+        let lambda = (effective + 0.12 + 0.11 / effective) * distance;
+        z_from_p_value(kolmogorov_p_value(lambda))
+    }
+}
+
+// The probability of a Kolmogorov distributed variable being at least `lambda`
+fn kolmogorov_p_value(lambda: f64) -> f64 {
+    if lambda < 0.2 {
+        return 1.0;
+    }
+
+    let mut p = 0.0;
+    let mut sign = 1.0;
+    for k in 1..=100 {
+        let k = k as f64;
+        let term = sign * 2.0 * (-2.0 * k * k * lambda * lambda).exp();
+        p += term;
+        if term.abs() < 1e-12 {
+            break;
+        }
+        sign = -sign;
+    }
+
+    p.clamp(0.0, 1.0)
+}
+
+// The z-score whose two-tailed p-value is `p`, using Acklam's approximation of the inverse normal distribution
+pub(crate) fn z_from_p_value(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e+01,
+        2.209460984245205e+02,
+        -2.759285104469687e+02,
+        1.383577518672690e+02,
+        -3.066479806614716e+01,
+        2.506628277459239e+00,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e+01,
+        1.615858368580409e+02,
+        -1.556989798598866e+02,
+        6.680131188771972e+01,
+        -1.328068155288572e+01,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-03,
+        -3.223964580411365e-01,
+        -2.400758277161838e+00,
+        -2.549732539343734e+00,
+        4.374664141464968e+00,
+        2.938163982698783e+00,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-03,
+        3.224671290700398e-01,
+        2.445134137142996e+00,
+        3.754408661907416e+00,
+    ];
+
+    // The lower tail probability, the z-score is the distance of its quantile from 0
+    let q = p / 2.0;
+    if q <= 0.0 {
+        return f64::INFINITY;
+    }
+    if q >= 0.5 {
+        return 0.0;
+    }
+
+    let z = if q < 0.02425 {
+        let r = (-2.0 * q.ln()).sqrt();
+        (((((C[0] * r + C[1]) * r + C[2]) * r + C[3]) * r + C[4]) * r + C[5])
+            / ((((D[0] * r + D[1]) * r + D[2]) * r + D[3]) * r + 1.0)
+    } else {
+        let r = q - 0.5;
+        let s = r * r;
+        (((((A[0] * s + A[1]) * s + A[2]) * s + A[3]) * s + A[4]) * s + A[5]) * r
+            / (((((B[0] * s + B[1]) * s + B[2]) * s + B[3]) * s + B[4]) * s + 1.0)
+    };
+
+    z.abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_test() {
+        // Identical distributions
+        let mut same = Histogram::new();
+        for i in 0..1000 {
+            same.record_first((i % 3) as f64);
+            same.record_second((i % 3) as f64);
+        }
+        assert!(same.mann_whitney_z() < 0.01);
+        assert!(same.ks_z() < 0.01);
+
+        // The same mean, but different shapes
+        let mut bimodal = Histogram::new();
+        for i in 0..1000 {
+            bimodal.record_first(100.0);
+            bimodal.record_second(if i % 2 == 0 { 90.0 } else { 110.0 });
+        }
+        assert!(bimodal.mann_whitney_z() < 0.01);
+        assert!(bimodal.ks_z() > 4.5);
+
+        // A shifted distribution
+        let mut shifted = Histogram::new();
+        for i in 0..1000 {
+            shifted.record_first((i % 10) as f64);
+            shifted.record_second((i % 10 + 2) as f64);
+        }
+        assert!(shifted.mann_whitney_z() > 4.5);
+        assert!(shifted.ks_z() > 4.5);
+
+        assert!((z_from_p_value(0.05) - 1.96).abs() < 0.01);
+    }
+}
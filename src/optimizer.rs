@@ -1,5 +1,16 @@
+use crate::lineage::Lineages;
+use crate::mutators::Mutators;
+use crate::report::LineageStep;
+use crate::strings::{random_str, repair, Charset};
+use crate::surrogate::Surrogate;
+use crate::template::Template;
 use crate::util::*;
-use rand::{seq::SliceRandom, Rng};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::f64::NEG_INFINITY;
+use std::ops::Range;
+use std::str::FromStr;
 
 // Population size
 const POPULATION_SIZE: usize = 1000;
@@ -7,15 +18,73 @@ const POPULATION_SIZE: usize = 1000;
 // Mutation rate
 const MUTATION_RATE: f64 = 0.25;
 
-// Ratio of "large mutations" (random u8 replacement) vs "small mutations" u8 increment / decrement.
-const LARGE_MUTATION_RATIO: f64 = 0.25;
-
 // Directly clone this ratio of top performers
 const CLONE_RATIO: f64 = 0.05;
 
 // Breed from this top percentage of the population
 const BREEDING_POOL: f64 = 0.10;
 
+// With variable-length inputs, how often a child's length is mutated
+const RESIZE_RATE: f64 = 0.10;
+
+// With variable-length inputs, lengths are mostly changed by at most this many bytes at a time
+const MAX_RESIZE_STEP: usize = 8;
+
+// With islands, this ratio of each island's best individuals are copied to the next island when they migrate
+const MIGRATION_RATIO: f64 = 0.02;
+
+// Islands whose diversity has collapsed have this ratio of their individuals, other than the clones, replaced
+const RESTART_RATIO: f64 = 0.5;
+
+// How often diversity is checked, and how long an island is left alone after a restart, in generations
+const DIVERSITY_INTERVAL: u64 = 10;
+const RESTART_COOLDOWN: u64 = 50;
+
+// Restart islands whose diversity falls below this by default
+const DEFAULT_RESTART_DIVERSITY: f64 = 0.02;
+
+// With a surrogate model, the population size, and how many children are bred for each place in it
+const SURROGATE_POPULATION: usize = 200;
+const SURROGATE_CANDIDATES: usize = 5;
+
+// With a surrogate model, this ratio of each generation is picked at random rather than by the model
+const EXPLORE_RATIO: f64 = 0.2;
+
+// With hill climbing and annealing, the number of points searched from at once
+const CLIMBERS: usize = 50;
+
+// Annealing accepts a neighbour that is worse by this fraction of the current score with probability 1/e at first,
+// and the temperature is multiplied by `COOLING` every generation
+const INITIAL_TEMPERATURE: f64 = 0.1;
+const COOLING: f64 = 0.999;
+
+// How the optimizer searches for fit individuals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    Genetic,   // Breed a population of individuals
+    HillClimb, // Only ever move to a neighbour that is at least as fit
+    Annealing, // Also move to less fit neighbours, less and less often
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::Genetic
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "genetic" => Ok(Strategy::Genetic),
+            "hillclimb" => Ok(Strategy::HillClimb),
+            "annealing" => Ok(Strategy::Annealing),
+            _ => Err(format!("unknown optimizer '{}'", s)),
+        }
+    }
+}
+
 pub struct Optimizer<T>
 where
     T: FnMut(&[u8], &[u8]) -> ScoredInputPair,
@@ -23,6 +92,23 @@ where
     population: Vec<InputPair>,
     fitness: T,
     input_is_str: bool,
+    segments: Vec<usize>,
+    public: Vec<Range<usize>>,
+    template: Option<Template>,
+    mutators: Mutators,
+    max_len: usize,
+    variable_len: bool,
+    charset: Charset,
+    rng: StdRng,
+    strategy: Strategy,
+    islands: usize,
+    migration_interval: u64,
+    restart_diversity: f64,
+    generation: u64,
+    last_restart: Vec<Option<u64>>, // The generation each island was last restarted in
+    restarts: u64,
+    surrogate: Option<Surrogate>,
+    lineages: Lineages,
 }
 
 impl<T> Optimizer<T>
@@ -30,11 +116,139 @@ where
     T: FnMut(&[u8], &[u8]) -> ScoredInputPair,
 {
     pub fn new(len: usize, fitness_function: T, input_is_str: bool) -> Self {
+        Self::with_seed(len, fitness_function, input_is_str, rand::random())
+    }
+
+    // Create an optimizer whose random choices are entirely determined by `seed`
+    pub fn with_seed(len: usize, fitness_function: T, input_is_str: bool, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         Optimizer {
-            population: inital_population(len, input_is_str),
+            population: inital_population(&mut rng, len, input_is_str),
             fitness: fitness_function,
             input_is_str,
+            segments: vec![len],
+            public: Vec::new(),
+            template: None,
+            mutators: Mutators::default(),
+            max_len: len,
+            variable_len: false,
+            charset: Charset::default(),
+            rng,
+            strategy: Strategy::Genetic,
+            islands: 1,
+            migration_interval: 0,
+            restart_diversity: DEFAULT_RESTART_DIVERSITY,
+            generation: 0,
+            last_restart: Vec::new(),
+            restarts: 0,
+            surrogate: None,
+            lineages: Lineages::default(),
+        }
+    }
+
+    // Set the lengths of the separate inputs making up each individual, so that each is mutated independently.
+    // The lengths must add up to the length the optimizer was created with.
+    pub fn set_segments(&mut self, segments: Vec<usize>) {
+        self.segments = segments;
+    }
+
+    // Keep the byte ranges in `public` the same in both inputs of every individual, so that fitness only comes from
+    // differences in the secret parts. The population is changed to match.
+    pub fn set_public(&mut self, public: Vec<Range<usize>>) {
+        for individual in self.population.iter_mut() {
+            share_public(&public, &individual.first, &mut individual.second);
+        }
+        self.public = public;
+    }
+
+    // Constrain individuals to the structure described by `template`, which must have the optimizer's length.
+    // The population is regenerated to match it, and from then on only the template's variable fields are mutated.
+    pub fn set_template(&mut self, template: Template) {
+        let rng = &mut self.rng;
+        for individual in self.population.iter_mut() {
+            individual.first = template.random(rng);
+            individual.second = template.random(rng);
+        }
+        self.template = Some(template);
+    }
+
+    // Let individuals be any length up to the length the optimizer was created with, and evolve their lengths too.
+    // The population is regenerated with random lengths. Segments and templates can't be used with variable lengths.
+    pub fn set_variable_len(&mut self) {
+        let rng = &mut self.rng;
+        for individual in self.population.iter_mut() {
+            let first = rng.gen_range(0, self.max_len + 1);
+            let second = rng.gen_range(0, self.max_len + 1);
+            individual.first = random_individual_input(rng, first, self.input_is_str, self.charset);
+            individual.second = random_individual_input(rng, second, self.input_is_str, self.charset);
         }
+        self.variable_len = true;
+    }
+
+    // Set the characters string inputs may contain. The population is repaired to only contain them.
+    pub fn set_charset(&mut self, charset: Charset) {
+        self.charset = charset;
+        if self.input_is_str {
+            for individual in self.population.iter_mut() {
+                repair(&mut self.rng, &mut individual.first, charset);
+                repair(&mut self.rng, &mut individual.second, charset);
+            }
+        }
+    }
+
+    // Set the mutation strategies to pick from
+    pub fn set_mutators(&mut self, mutators: Mutators) {
+        self.mutators = mutators;
+    }
+
+    // Search with `strategy`. The local searches run with a smaller population of `CLIMBERS` climbers, so the
+    // population is cut down to match.
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        if strategy != Strategy::Genetic {
+            self.population.truncate(CLIMBERS * 2);
+        }
+        self.strategy = strategy;
+    }
+
+    // Breed several children for every place in the population, and only keep the ones a surrogate model of the
+    // fitness function predicts to be fittest. The population is cut down to `SURROGATE_POPULATION`, so each
+    // generation runs the target far fewer times. Only used by the genetic algorithm.
+    pub fn set_surrogate(&mut self) {
+        self.population.truncate(SURROGATE_POPULATION);
+        self.surrogate = Some(Surrogate::new(self.max_len));
+    }
+
+    // Split the population into `islands` subpopulations that only breed among themselves, so that they can
+    // converge on different optima. Every `migration_interval` generations, the best individuals of each island are
+    // copied to the next one. A migration interval of 0 keeps the islands entirely separate.
+    pub fn set_islands(&mut self, islands: usize, migration_interval: u64) {
+        self.islands = islands.max(1);
+        self.migration_interval = migration_interval;
+    }
+
+    // Partially restart islands whose Hamming diversity falls below `diversity`, or never if it is 0
+    pub fn set_restart_diversity(&mut self, diversity: f64) {
+        self.restart_diversity = diversity;
+    }
+
+    // The number of partial restarts so far
+    pub fn restarts(&self) -> u64 {
+        self.restarts
+    }
+
+    // The mean fraction of bits that differ between two individuals of the population, see `hamming_diversity`
+    pub fn hamming_diversity(&self) -> f64 {
+        hamming_diversity(&self.population, self.max_len)
+    }
+
+    // The fraction of the population that is unique, from 1 / population size up to 1
+    pub fn diversity(&self) -> f64 {
+        let unique: HashSet<(&[u8], &[u8])> = self
+            .population
+            .iter()
+            .map(|individual| (individual.first.as_slice(), individual.second.as_slice()))
+            .collect();
+        unique.len() as f64 / self.population.len() as f64
     }
 
     pub fn scored_population(&mut self) -> Vec<ScoredInputPair> {
@@ -47,171 +261,525 @@ where
         }
 
         // Sort most fit to least fit
-        // Unwrap OK since score cannot be NAN.
-        scored.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        sort_by_score(&mut scored);
 
         scored
     }
 
+    // Replace the population, for example with one restored from a checkpoint
+    pub fn set_population(&mut self, population: Vec<InputPair>) {
+        self.population = population;
+    }
+
+    // Replace the start of the population with known inputs, for example from a corpus
+    pub fn seed_population(&mut self, seeds: Vec<InputPair>) {
+        for (individual, seed) in self.population.iter_mut().zip(seeds) {
+            *individual = seed;
+        }
+    }
+
+    // Replace the end of the population with `individuals`, the fittest individuals are at the start after a step
+    pub fn inject(&mut self, individuals: Vec<InputPair>) {
+        let start = self.population.len().saturating_sub(individuals.len());
+        for (individual, injected) in self.population[start..].iter_mut().zip(individuals) {
+            *individual = injected;
+        }
+    }
+
+    // Continue making random choices from `seed`
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // Reseed from a seed drawn from the current random number generator, returning the new seed.
+    // Saving the returned seed lets a run be continued exactly where it left off.
+    pub fn reseed(&mut self) -> u64 {
+        let seed = self.rng.gen();
+        self.set_seed(seed);
+        seed
+    }
+
+    // The current (unscored) population
+    pub fn population(&self) -> &[InputPair] {
+        &self.population
+    }
+
+    // How `individual` of the current population was bred, oldest generation first
+    pub fn lineage(&self, individual: &InputPair) -> Vec<LineageStep> {
+        self.lineages.of(individual)
+    }
+
     pub fn step(&mut self) {
         // Get fitness of all individuals
         let scored = self.scored_population();
+        self.step_with_scores(scored);
+    }
+
+    // Create the next generation from an already scored population.
+    // This allows scoring to happen elsewhere (for example in parallel), `scored` must be sorted most fit first.
+    pub fn step_with_scores(&mut self, scored: Vec<ScoredInputPair>) {
+        self.generation += 1;
+        if self.strategy != Strategy::Genetic {
+            self.climb(scored);
+            return;
+        }
+        if let Some(surrogate) = &mut self.surrogate {
+            for score in scored.iter() {
+                surrogate.train(&score.pair.first, &score.pair.second, score.score);
+            }
+        }
+
+        let ranges = island_ranges(self.population.len(), self.islands);
+        self.last_restart.resize(ranges.len(), None);
+
+        // Each island breeds from its own individuals, still sorted most fit first
+        let islands = if ranges.len() == 1 {
+            vec![scored.iter().collect()]
+        } else {
+            let mut islands: Vec<Vec<&ScoredInputPair>> = vec![Vec::new(); ranges.len()];
+            for (score, index) in scored.iter().zip(self.positions(&scored)) {
+                let island = ranges.iter().position(|range| range.contains(&index)).unwrap_or(ranges.len() - 1);
+                islands[island].push(score);
+            }
+            islands
+        };
+
+        let mut next_gen: Vec<InputPair> = Vec::with_capacity(self.population.len());
+        let mut starts = Vec::with_capacity(ranges.len());
+        for (island, (range, scored)) in ranges.iter().zip(islands.iter()).enumerate() {
+            let mut individuals = self.breed(scored, range.len());
+            let num_clone = (range.len() as f64 * CLONE_RATIO) as usize;
+            self.restart(island, &mut individuals, num_clone);
+            starts.push(next_gen.len());
+            next_gen.extend(individuals);
+        }
+        starts.push(next_gen.len());
+
+        // Copy the best of each island, its clones at the start, over the end of the next island
+        if starts.len() > 2 && self.migration_interval > 0 && self.generation % self.migration_interval == 0 {
+            let islands = starts.len() - 1;
+            let mut migrants = Vec::with_capacity(islands);
+            for island in 0..islands {
+                let len = starts[island + 1] - starts[island];
+                let count = ((len as f64 * MIGRATION_RATIO) as usize).max(1).min(len / 2);
+                migrants.push(next_gen[starts[island]..starts[island] + count].to_vec());
+            }
+            for (island, migrants) in migrants.into_iter().enumerate() {
+                let end = starts[(island + 1) % islands + 1];
+                let start = end - migrants.len();
+                for (individual, migrant) in next_gen[start..end].iter_mut().zip(migrants) {
+                    *individual = migrant;
+                }
+            }
+        }
+
+        // Cloned individuals may have been injected from elsewhere, so every individual is aligned
+        for individual in next_gen.iter_mut() {
+            share_public(&self.public, &individual.first, &mut individual.second);
+        }
+
+        self.population = next_gen;
+        self.lineages.settle(&self.population);
+    }
+
+    // The index in the population of each of `scored`, matched up by their inputs. Anything that can't be matched
+    // is assumed to be at its position in `scored`.
+    fn positions(&self, scored: &[ScoredInputPair]) -> Vec<usize> {
+        let mut positions: HashMap<(&[u8], &[u8]), Vec<usize>> = HashMap::new();
+        for (index, individual) in self.population.iter().enumerate().rev() {
+            positions
+                .entry((individual.first.as_slice(), individual.second.as_slice()))
+                .or_default()
+                .push(index);
+        }
+        scored
+            .iter()
+            .enumerate()
+            .map(|(rank, score)| {
+                positions
+                    .get_mut(&(score.pair.first.as_slice(), score.pair.second.as_slice()))
+                    .and_then(|indexes| indexes.pop())
+                    .unwrap_or(rank)
+            })
+            .collect()
+    }
+
+    // One step of hill climbing or annealing. The first half of the population are the climbers' current points,
+    // and the second half a neighbour of each, which replaces its point if it is accepted.
+    fn climb(&mut self, scored: Vec<ScoredInputPair>) {
+        let climbers = self.population.len() / 2;
+        let mut scores = vec![NEG_INFINITY; self.population.len()];
+        for (score, index) in scored.iter().zip(self.positions(&scored)) {
+            if let Some(slot) = scores.get_mut(index) {
+                *slot = score.score;
+            }
+        }
+
+        let temperature = INITIAL_TEMPERATURE * COOLING.powf(self.generation as f64);
+        let mut points = Vec::with_capacity(climbers);
+        for climber in 0..climbers {
+            let (current, neighbour) = (scores[climber], scores[climbers + climber]);
+            let accept = match self.strategy {
+                Strategy::Annealing if neighbour < current => {
+                    // Worse neighbours are accepted less often the worse they are, and as the temperature cools
+                    let p = ((neighbour - current) / (temperature * current.abs().max(1.0))).exp();
+                    self.rng.gen::<f64>() < p
+                }
+                // Equally fit neighbours are accepted, so climbers can cross plateaus
+                _ => neighbour >= current,
+            };
+            points.push(if accept {
+                self.population[climbers + climber].clone()
+            } else {
+                self.population[climber].clone()
+            });
+        }
+
+        let mut next_gen = points.clone();
+        for point in points.iter() {
+            let mut neighbour = point.clone();
+            let operators = self.vary(&mut neighbour, 1.0);
+            share_public(&self.public, &neighbour.first, &mut neighbour.second);
+            self.lineages.record(self.generation, &neighbour, &[point], operators);
+            next_gen.push(neighbour);
+        }
+        for individual in next_gen.iter_mut() {
+            share_public(&self.public, &individual.first, &mut individual.second);
+        }
+        self.population = next_gen;
+        self.lineages.settle(&self.population);
+    }
+
+    // Create `size` individuals from `scored`, sorted most fit first
+    fn breed(&mut self, scored: &[&ScoredInputPair], size: usize) -> Vec<InputPair> {
+        if scored.is_empty() {
+            return Vec::new();
+        }
 
         // Calculate number to clone and number to breed
-        let num_clone: usize = (POPULATION_SIZE as f64 * CLONE_RATIO) as usize;
-        let breed_pool: usize = (POPULATION_SIZE as f64 * BREEDING_POOL) as usize;
-        let breed_fill: usize = POPULATION_SIZE - num_clone;
+        let num_clone: usize = (size as f64 * CLONE_RATIO) as usize;
+        let breed_pool: usize = ((size as f64 * BREEDING_POOL) as usize).max(1).min(scored.len());
+        let breed_fill: usize = size - num_clone.min(scored.len());
 
         // Create the next generation
-        let mut next_gen: Vec<InputPair> = Vec::with_capacity(self.population.len());
+        let mut next_gen: Vec<InputPair> = Vec::with_capacity(size);
 
         // Clone the top contenders
         for score in scored.iter().take(num_clone) {
             next_gen.push(score.pair.clone());
         }
 
-        // Breed and mutate the rest
-        for _ in 0..breed_fill {
+        // Breed and mutate the rest, with a surrogate model breeding extra children to pick from
+        let candidates = if self.surrogate.is_some() {
+            SURROGATE_CANDIDATES
+        } else {
+            1
+        };
+        let mut children = Vec::with_capacity(breed_fill * candidates);
+        for _ in 0..breed_fill * candidates {
             // Select two individuals
-            let parent_one = &scored[rand::thread_rng().gen_range(0, breed_pool)].pair;
-            let parent_two = &scored[rand::thread_rng().gen_range(0, breed_pool)].pair;
+            let rng = &mut self.rng;
+            let parent_one = &scored[rng.gen_range(0, breed_pool)].pair;
+            let parent_two = &scored[rng.gen_range(0, breed_pool)].pair;
 
             let mut child;
             if self.input_is_str {
                 child = InputPair {
-                    first: breed_str_slice(&parent_one.first, &parent_two.first),
-                    second: breed_str_slice(&parent_one.second, &parent_two.second),
+                    first: breed_str_slice(rng, &parent_one.first, &parent_two.first, self.charset),
+                    second: breed_str_slice(rng, &parent_one.second, &parent_two.second, self.charset),
                     is_str: self.input_is_str,
                 };
             } else {
                 child = InputPair {
-                    first: breed_slice(&parent_one.first, &parent_two.first),
-                    second: breed_slice(&parent_one.second, &parent_two.second),
+                    first: breed_slice(rng, &parent_one.first, &parent_two.first),
+                    second: breed_slice(rng, &parent_one.second, &parent_two.second),
                     is_str: self.input_is_str,
                 };
             }
 
-            // Mutate
-            if rand::thread_rng().gen_bool(MUTATION_RATE) {
-                if rand::thread_rng().gen() {
-                    if self.input_is_str {
-                        mutate_str_slice(&mut child.first);
-                    } else {
-                        mutate_slice(&mut child.first);
-                    }
-                } else {
-                    if self.input_is_str {
-                        mutate_str_slice(&mut child.second);
-                    } else {
-                        mutate_slice(&mut child.second);
-                    }
+            let mut operators = vec!["crossover".to_string()];
+            operators.extend(self.vary(&mut child, MUTATION_RATE));
+            share_public(&self.public, &child.first, &mut child.second);
+            self.lineages.record(self.generation, &child, &[parent_one, parent_two], operators);
+            children.push(child);
+        }
+        next_gen.extend(self.screen(children, breed_fill));
+
+        next_gen
+    }
+
+    // Keep `count` of `children`: the ones the surrogate model predicts to be fittest, and a few others so that the
+    // model keeps learning about the rest of the inputs
+    fn screen(&mut self, mut children: Vec<InputPair>, count: usize) -> Vec<InputPair> {
+        let surrogate = match &self.surrogate {
+            Some(surrogate) if surrogate.samples() > 0 => surrogate,
+            _ => {
+                children.truncate(count);
+                return children;
+            }
+        };
+
+        let mut predicted: Vec<(f64, InputPair)> = children
+            .into_iter()
+            .map(|child| (surrogate.predict(&child.first, &child.second), child))
+            .collect();
+        predicted.sort_by(|one, two| two.0.partial_cmp(&one.0).unwrap_or(Ordering::Equal));
+        let best = count - (count as f64 * EXPLORE_RATIO) as usize;
+        let mut rest = predicted.split_off(best.min(predicted.len()));
+        rest.shuffle(&mut self.rng);
+        predicted
+            .into_iter()
+            .chain(rest)
+            .take(count)
+            .map(|(_, child)| child)
+            .collect()
+    }
+
+    // Mutate `child` with probability `mutation_rate`, and keep it to the lengths and template in use.
+    // Returns the operators that changed it, for its lineage.
+    fn vary(&mut self, child: &mut InputPair, mutation_rate: f64) -> Vec<String> {
+        let mut operators = Vec::new();
+        // Mutate one of the inputs of either the first or second
+        let rng = &mut self.rng;
+        if rng.gen_bool(mutation_rate) {
+            let segment = match &self.template {
+                Some(template) => template.random_field(rng),
+                None => random_segment(rng, &self.segments),
+            };
+            let mutator = self.mutators.choose(rng);
+            let input = if rng.gen() {
+                &mut child.first
+            } else {
+                &mut child.second
+            };
+            // Variable-length inputs may be shorter than the segment
+            let segment = segment.start.min(input.len())..segment.end.min(input.len());
+            if !segment.is_empty() {
+                mutator.mutate(rng, &mut input[segment.clone()]);
+                operators.push(mutator.name().to_string());
+                if self.input_is_str {
+                    repair(rng, &mut input[segment], self.charset);
                 }
             }
+        }
 
-            next_gen.push(child);
+        if self.variable_len && rng.gen_bool(RESIZE_RATE) {
+            let input = if rng.gen() {
+                &mut child.first
+            } else {
+                &mut child.second
+            };
+            resize(rng, input, self.max_len, self.input_is_str, self.charset);
+            operators.push("resize".to_string());
         }
 
-        self.population = next_gen;
+        if let Some(template) = &self.template {
+            template.conform(rng, &mut child.first);
+            template.conform(rng, &mut child.second);
+        }
+        operators
     }
-}
 
-fn breed_slice(first: &[u8], second: &[u8]) -> Vec<u8> {
-    let mut child: Vec<u8> = Vec::with_capacity(first.len());
-    for n in 0..first.len() {
-        if rand::thread_rng().gen() {
-            child.push(first[n]);
-        } else {
-            child.push(second[n]);
+    // Replace part of an island whose diversity has collapsed with new random individuals, keeping its clones
+    fn restart(&mut self, island: usize, individuals: &mut [InputPair], num_clone: usize) {
+        if self.restart_diversity <= 0.0 || self.generation % DIVERSITY_INTERVAL != 0 {
+            return;
         }
+        if let Some(last) = self.last_restart[island] {
+            if self.generation - last < RESTART_COOLDOWN {
+                return;
+            }
+        }
+        if hamming_diversity(individuals, self.max_len) >= self.restart_diversity {
+            return;
+        }
+
+        let replace = (individuals.len().saturating_sub(num_clone) as f64 * RESTART_RATIO) as usize;
+        let start = individuals.len() - replace;
+        for individual in individuals[start..].iter_mut() {
+            *individual = self.random_individual();
+            share_public(&self.public, &individual.first, &mut individual.second);
+            self.lineages.record(self.generation, individual, &[], vec!["restart".to_string()]);
+        }
+        self.last_restart[island] = Some(self.generation);
+        self.restarts += 1;
     }
 
-    child
+    // A new random individual, following the template, lengths and charset in use
+    fn random_individual(&mut self) -> InputPair {
+        let rng = &mut self.rng;
+        let (first, second) = match &self.template {
+            Some(template) => (template.random(rng), template.random(rng)),
+            None => {
+                let (first, second) = if self.variable_len {
+                    (rng.gen_range(0, self.max_len + 1), rng.gen_range(0, self.max_len + 1))
+                } else {
+                    (self.max_len, self.max_len)
+                };
+                (
+                    random_individual_input(rng, first, self.input_is_str, self.charset),
+                    random_individual_input(rng, second, self.input_is_str, self.charset),
+                )
+            }
+        };
+        InputPair {
+            first,
+            second,
+            is_str: self.input_is_str,
+        }
+    }
+}
+
+// Split a population of `len` individuals into `islands` contiguous ranges, the last taking any remainder
+fn island_ranges(len: usize, islands: usize) -> Vec<Range<usize>> {
+    let islands = islands.max(1).min(len.max(1));
+    let size = len / islands;
+    (0..islands)
+        .map(|island| {
+            let end = if island == islands - 1 { len } else { (island + 1) * size };
+            island * size..end
+        })
+        .collect()
 }
 
-fn breed_str_slice(first: &[u8], second: &[u8]) -> Vec<u8> {
-    let mut child: Vec<u8> = breed_slice(first, second);
+// The mean fraction of bits that differ between two of `individuals`, from 0 when they are all the same up to
+// around 0.5 for random inputs. Both inputs of an individual count, and inputs shorter than `max_len` count as
+// padded with zeroes.
+fn hamming_diversity(individuals: &[InputPair], max_len: usize) -> f64 {
+    let n = individuals.len();
+    let bits = max_len * 2 * 8;
+    if n < 2 || bits == 0 {
+        return 0.0;
+    }
 
-    // Mutate until it's valid
-    loop {
-        match std::str::from_utf8(&child) {
-            Ok(_) => return child,
-            Err(_) => {}
+    let mut ones = vec![0usize; bits];
+    for individual in individuals {
+        for (side, input) in [&individual.first, &individual.second].iter().enumerate() {
+            for (index, byte) in input.iter().take(max_len).enumerate() {
+                for bit in 0..8 {
+                    ones[(side * max_len + index) * 8 + bit] += (byte >> bit) as usize & 1;
+                }
+            }
         }
-        mutate_slice(&mut child);
     }
-}
 
-fn mutate_slice(slice: &mut [u8]) {
-    // OK to unwrap here, slice should never be empty
-    let mutating_gene = slice.choose_mut(&mut rand::thread_rng()).unwrap();
+    // A bit differs between every pair of individuals where one has it set and the other doesn't
+    let differing: f64 = ones.iter().map(|ones| (ones * (n - ones)) as f64).sum();
+    let pairs = (n * (n - 1) / 2) as f64;
+    differing / pairs / bits as f64
+}
 
-    if rand::thread_rng().gen_bool(LARGE_MUTATION_RATIO) {
-        // Large mutation, assign another random u8
-        *mutating_gene = rand::thread_rng().gen();
+fn breed_slice<R: Rng>(rng: &mut R, first: &[u8], second: &[u8]) -> Vec<u8> {
+    // Children of parents of different lengths take the length of one of them.
+    // Past the end of the shorter parent, bytes come from the longer one.
+    let len = if first.len() == second.len() || rng.gen() {
+        first.len()
     } else {
-        // Small mutation, increment or decrement
-        if rand::thread_rng().gen() {
-            *mutating_gene = mutating_gene.wrapping_add(1);
-        } else {
-            *mutating_gene = mutating_gene.wrapping_sub(1);
+        second.len()
+    };
+    let mut child: Vec<u8> = Vec::with_capacity(len);
+    for n in 0..len {
+        match (first.get(n), second.get(n)) {
+            (Some(one), Some(two)) => child.push(if rng.gen() { *one } else { *two }),
+            (Some(byte), None) | (None, Some(byte)) => child.push(*byte),
+            (None, None) => unreachable!(),
         }
     }
+
+    child
 }
 
-fn mutate_str_slice(slice: &mut [u8]) {
-    loop {
-        mutate_slice(slice);
+fn breed_str_slice(rng: &mut StdRng, first: &[u8], second: &[u8], charset: Charset) -> Vec<u8> {
+    let mut child: Vec<u8> = breed_slice(rng, first, second);
+
+    // Crossing over can split characters, replace whatever was broken
+    repair(rng, &mut child, charset);
+    child
+}
+
+// Pick the byte range of one of the (non-empty) input segments, each equally likely regardless of length
+fn random_segment<R: Rng>(rng: &mut R, segments: &[usize]) -> Range<usize> {
+    let mut ranges = Vec::with_capacity(segments.len());
+    let mut offset = 0;
+    for len in segments {
+        if *len > 0 {
+            ranges.push(offset..offset + len);
+        }
+        offset += len;
+    }
+
+    match ranges.choose(rng) {
+        Some(range) => range.clone(),
+        None => 0..offset,
+    }
+}
+
+// Grow or shrink a variable-length input, mostly by a few bytes and occasionally to a random length
+fn resize(rng: &mut StdRng, input: &mut Vec<u8>, max_len: usize, is_str: bool, charset: Charset) {
+    let len = if rng.gen_bool(0.1) {
+        rng.gen_range(0, max_len + 1)
+    } else {
+        let step = rng.gen_range(1, MAX_RESIZE_STEP + 1);
+        if rng.gen() {
+            (input.len() + step).min(max_len)
+        } else {
+            input.len().saturating_sub(step)
+        }
+    };
 
-        match std::str::from_utf8(slice) {
-            Ok(_) => return,
-            Err(_) => continue,
+    if len > input.len() {
+        let extra = random_individual_input(rng, len - input.len(), is_str, charset);
+        input.extend_from_slice(&extra);
+    } else {
+        input.truncate(len);
+        // Truncating may have split a multi-byte character
+        if is_str {
+            repair(rng, input, charset);
         }
     }
 }
 
-fn inital_population(len: usize, is_str: bool) -> Vec<InputPair> {
+fn inital_population<R: Rng>(rng: &mut R, len: usize, is_str: bool) -> Vec<InputPair> {
     let mut population = Vec::with_capacity(POPULATION_SIZE);
     for _ in 0..POPULATION_SIZE {
         if is_str {
-            population.push(random_str_individual(len));
+            population.push(random_str_individual(rng, len));
         } else {
-            population.push(random_individual(len));
+            population.push(random_individual(rng, len));
         }
     }
     population
 }
 
-fn random_individual(len: usize) -> InputPair {
+fn random_individual<R: Rng>(rng: &mut R, len: usize) -> InputPair {
     InputPair {
-        first: (0..len).map(|_| rand::random::<u8>()).collect(),
-        second: (0..len).map(|_| rand::random::<u8>()).collect(),
+        first: random_input(rng, len, false),
+        second: random_input(rng, len, false),
         is_str: false,
     }
 }
 
-fn random_str_individual(len: usize) -> InputPair {
-    use rand::distributions::Alphanumeric;
-
-    // This will create ascii strings, all under 127 in value, we can translate it right to bytes
-    let first: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(len)
-        .collect();
-    let second: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(len)
-        .collect();
-
+fn random_str_individual<R: Rng>(rng: &mut R, len: usize) -> InputPair {
+    // This will create valid UTF-8 strings, mostly ASCII, of exactly `len` bytes
     InputPair {
-        first: first.into_bytes(),
-        second: second.into_bytes(),
+        first: random_str(rng, len, Charset::default()),
+        second: random_str(rng, len, Charset::default()),
         is_str: true,
     }
 }
 
+// A random input for one side of an individual
+fn random_individual_input<R: Rng>(rng: &mut R, len: usize, is_str: bool, charset: Charset) -> Vec<u8> {
+    if is_str {
+        random_str(rng, len, charset)
+    } else {
+        random_input(rng, len, false)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::optimizer::Optimizer;
+    use crate::optimizer::{hamming_diversity, Optimizer, Strategy};
     use crate::util::*;
 
     #[test]
@@ -258,4 +826,164 @@ mod tests {
         assert_eq!(population[0].pair.first, target);
         assert_eq!(population[0].pair.second, target);
     }
+
+    #[test]
+    fn optimizer_seed_test() {
+        let fitness = |first: &[u8], second: &[u8]| ScoredInputPair {
+            score: first.iter().chain(second.iter()).map(|b| *b as f64).sum(),
+            highest: 0.0,
+            lowest: 0.0,
+            pair: InputPair {
+                first: first.to_vec(),
+                second: second.to_vec(),
+                is_str: false,
+            },
+        };
+
+        let mut one = Optimizer::with_seed(16, fitness, false, 42);
+        let mut two = Optimizer::with_seed(16, fitness, false, 42);
+        for _ in 0..10 {
+            one.step();
+            two.step();
+        }
+
+        let one: Vec<_> = one.population().iter().map(|p| p.first.clone()).collect();
+        let two: Vec<_> = two.population().iter().map(|p| p.first.clone()).collect();
+        assert_eq!(one, two);
+    }
+
+    #[test]
+    fn optimizer_variable_len_test() {
+        // Longer first inputs are fitter
+        let fitness = |first: &[u8], second: &[u8]| ScoredInputPair {
+            score: first.len() as f64,
+            highest: 0.0,
+            lowest: 0.0,
+            pair: InputPair {
+                first: first.to_vec(),
+                second: second.to_vec(),
+                is_str: false,
+            },
+        };
+
+        let mut optimizer = Optimizer::with_seed(64, fitness, false, 42);
+        optimizer.set_variable_len();
+        for _ in 0..50 {
+            optimizer.step();
+        }
+
+        let population = optimizer.scored_population();
+        assert_eq!(population[0].pair.first.len(), 64);
+        assert!(optimizer.population().iter().all(|p| p.second.len() <= 64));
+        assert!(optimizer.population().iter().any(|p| p.second.len() < 64));
+    }
+
+    #[test]
+    fn optimizer_public_test() {
+        // Inputs that differ more are fitter
+        let fitness = |first: &[u8], second: &[u8]| ScoredInputPair {
+            score: first.iter().zip(second.iter()).filter(|(a, b)| a != b).count() as f64,
+            highest: 0.0,
+            lowest: 0.0,
+            pair: InputPair {
+                first: first.to_vec(),
+                second: second.to_vec(),
+                is_str: false,
+            },
+        };
+
+        let mut optimizer = Optimizer::with_seed(8, fitness, false, 42);
+        optimizer.set_segments(vec![4, 4]);
+        optimizer.set_public(public_ranges(&[4, 4], &[false, true]));
+        for _ in 0..10 {
+            optimizer.step();
+        }
+
+        // Only the secret first half is ever different
+        assert!(optimizer.population().iter().all(|p| p.first[4..] == p.second[4..]));
+        let population = optimizer.scored_population();
+        assert_eq!(population[0].score, 4.0);
+    }
+
+    #[test]
+    fn optimizer_islands_test() {
+        let pair = |first: &[u8], second: &[u8]| InputPair {
+            first: first.to_vec(),
+            second: second.to_vec(),
+            is_str: false,
+        };
+        assert_eq!(hamming_diversity(&[pair(&[0], &[0]), pair(&[0], &[0])], 1), 0.0);
+        assert_eq!(hamming_diversity(&[pair(&[0], &[1]), pair(&[0xff], &[1])], 1), 0.5);
+        // Missing bytes count as zeroes
+        assert_eq!(hamming_diversity(&[pair(&[], &[]), pair(&[0xff], &[0xff])], 1), 1.0);
+
+        let fitness = |first: &[u8], second: &[u8]| ScoredInputPair {
+            score: first.iter().chain(second.iter()).map(|b| *b as f64).sum(),
+            highest: 0.0,
+            lowest: 0.0,
+            pair: pair(first, second),
+        };
+        let mut optimizer = Optimizer::with_seed(16, fitness, false, 42);
+        optimizer.set_islands(4, 5);
+        // Every island is below a diversity of 1, and is restarted once in the first cooldown
+        optimizer.set_restart_diversity(1.0);
+        for _ in 0..20 {
+            optimizer.step();
+        }
+        assert_eq!(optimizer.population().len(), 1000);
+        assert_eq!(optimizer.restarts(), 4);
+    }
+
+    #[test]
+    fn optimizer_hillclimb_test() {
+        let fitness = |first: &[u8], second: &[u8]| ScoredInputPair {
+            score: first.iter().chain(second.iter()).map(|b| *b as f64).sum(),
+            highest: 0.0,
+            lowest: 0.0,
+            pair: InputPair {
+                first: first.to_vec(),
+                second: second.to_vec(),
+                is_str: false,
+            },
+        };
+
+        let mut optimizer = Optimizer::with_seed(8, fitness, false, 42);
+        optimizer.set_strategy(Strategy::HillClimb);
+        assert_eq!(optimizer.population().len(), 100);
+
+        // The climbers never move to a less fit point, so the best score never drops
+        let initial = optimizer.scored_population()[0].score;
+        let mut best = initial;
+        for _ in 0..200 {
+            optimizer.step();
+            let score = optimizer.scored_population()[0].score;
+            assert!(score >= best);
+            best = score;
+        }
+        assert!(best > initial);
+        assert_eq!("annealing".parse::<Strategy>(), Ok(Strategy::Annealing));
+    }
+
+    #[test]
+    fn optimizer_surrogate_test() {
+        let fitness = |first: &[u8], second: &[u8]| ScoredInputPair {
+            score: first.iter().chain(second.iter()).map(|b| *b as f64).sum(),
+            highest: 0.0,
+            lowest: 0.0,
+            pair: InputPair {
+                first: first.to_vec(),
+                second: second.to_vec(),
+                is_str: false,
+            },
+        };
+
+        let mut optimizer = Optimizer::with_seed(8, fitness, false, 42);
+        optimizer.set_surrogate();
+        let initial = optimizer.scored_population()[0].score;
+        for _ in 0..20 {
+            optimizer.step();
+        }
+        assert_eq!(optimizer.population().len(), 200);
+        assert!(optimizer.scored_population()[0].score > initial);
+    }
 }
@@ -0,0 +1,302 @@
+// This file contains the "oracle" subcommand, which checks whether a target's timing depends on its output
+//
+// Targets that pass their result to `sidefuzz::set_output`, such as a status code, can leak it through timing even
+// when no secret bit does: a decryption that takes longer to reject bad padding than bad MACs is an error oracle,
+// and comparing two inputs never reveals it unless they happen to fail differently. Inputs are grouped by the
+// output they produce, and the instruction counts of every group are compared with each other.
+
+use crate::dudect::welch_t;
+use crate::errors::SideFuzzError;
+use crate::target::{Target, TargetOptions};
+use crate::util::random_input;
+use rand::{rngs::StdRng, SeedableRng};
+use rolling_stats::Stats;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Outputs longer than this are shortened when printed
+const MAX_SHOWN: usize = 32;
+
+pub struct Oracle {
+    module: Box<dyn Target>,
+    inputs: Vec<Vec<u8>>,
+    max_t_value: f64,
+}
+
+// The inputs that produced one output
+#[derive(Debug, Clone, Default, PartialEq)]
+struct OutputClass {
+    output: Vec<u8>,
+    counts: Vec<u64>,
+}
+
+// The inputs that produced one output, as reported
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutputSummary {
+    pub output: String, // In hexadecimal
+    pub inputs: usize,
+    pub mean: f64,
+    pub min: u64,
+    pub max: u64,
+}
+
+// Two outputs whose instruction counts differ
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutputOracle {
+    pub output_1: String,
+    pub mean_1: f64,
+    pub output_2: String,
+    pub mean_2: f64,
+    pub t_value: f64,
+}
+
+// What running the inputs showed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OracleResult {
+    pub outputs: Vec<OutputSummary>, // The most common output first
+    pub crashes: usize,
+    pub compared: usize, // How many outputs were produced by at least two inputs, and compared
+    pub oracles: Vec<OutputOracle>,
+}
+
+impl Oracle {
+    pub fn from_file_with_options(
+        filename: &str,
+        options: &TargetOptions,
+        inputs: Vec<Vec<u8>>,
+    ) -> Result<Self, SideFuzzError> {
+        let module = crate::target::load(filename, options)?;
+        if inputs.iter().any(|input| input.len() != module.fuzz_len()) {
+            return Err(SideFuzzError::InputsWrongSize(module.fuzz_len()));
+        }
+        Ok(Oracle {
+            module,
+            inputs,
+            max_t_value: 4.5,
+        })
+    }
+
+    // Also run `count` random inputs, generated from `seed`
+    pub fn add_random_inputs(&mut self, count: usize, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let len = self.module.fuzz_len();
+        let is_str = self.module.input_is_str();
+        for _ in 0..count {
+            self.inputs.push(random_input(&mut rng, len, is_str));
+        }
+    }
+
+    // Report an oracle once the t-value between two outputs goes above `max_t_value`. The default is 4.5.
+    pub fn set_max_t_value(&mut self, max_t_value: f64) {
+        self.max_t_value = max_t_value;
+    }
+
+    // Run every input and compare the instruction counts of the outputs they produce
+    pub fn run(&mut self) -> Result<OracleResult, SideFuzzError> {
+        if self.inputs.is_empty() {
+            return Err(SideFuzzError::NoInputs);
+        }
+
+        let mut outputs = BTreeMap::new();
+        let mut crashes = 0;
+        for input in self.inputs.iter() {
+            match self.module.run_with_output(input) {
+                Ok((count, Some(output))) => {
+                    outputs.entry(output).or_insert_with(Vec::new).push(count)
+                }
+                Ok((_, None)) => {}
+                Err(SideFuzzError::WasmModuleBadOutput) => {
+                    return Err(SideFuzzError::WasmModuleBadOutput)
+                }
+                Err(_) => crashes += 1,
+            }
+        }
+        if outputs.is_empty() && crashes < self.inputs.len() {
+            return Err(SideFuzzError::NoOutput);
+        }
+        let classes = classes(outputs);
+
+        let compared: Vec<&OutputClass> = classes
+            .iter()
+            .filter(|class| class.counts.len() >= 2)
+            .collect();
+        let mut oracles = Vec::new();
+        if compared.len() >= 2 {
+            for (i, first) in compared.iter().enumerate() {
+                for second in compared[i + 1..].iter() {
+                    let t = t_value(first, second);
+                    if t > self.max_t_value {
+                        oracles.push(OutputOracle {
+                            output_1: hex::encode(&first.output),
+                            mean_1: mean(&first.counts),
+                            output_2: hex::encode(&second.output),
+                            mean_2: mean(&second.counts),
+                            t_value: t,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(OracleResult {
+            outputs: classes.iter().map(OutputSummary::new).collect(),
+            crashes,
+            compared: compared.len(),
+            oracles,
+        })
+    }
+}
+
+impl OutputSummary {
+    fn new(class: &OutputClass) -> Self {
+        OutputSummary {
+            output: hex::encode(&class.output),
+            inputs: class.counts.len(),
+            mean: mean(&class.counts),
+            min: class.counts.iter().min().copied().unwrap_or(0),
+            max: class.counts.iter().max().copied().unwrap_or(0),
+        }
+    }
+}
+
+impl OracleResult {
+    // 1 if timing depends on the output, 2 if fewer than two outputs were compared, and 0 otherwise
+    pub fn exit_code(&self) -> i32 {
+        if self.compared < 2 {
+            2
+        } else if !self.oracles.is_empty() {
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let mut lines = Vec::new();
+        for output in self.outputs.iter() {
+            lines.push(format!(
+                "output {}: {} inputs, {} instructions on average ({} to {})",
+                describe_output(&output.output),
+                output.inputs,
+                output.mean,
+                output.min,
+                output.max
+            ));
+        }
+        if self.crashes > 0 {
+            lines.push(format!("{} inputs crashed the target", self.crashes));
+        }
+        lines.push(String::new());
+
+        if self.compared < 2 {
+            lines.push(
+                "Fewer than two outputs were produced by at least two inputs each, so there is nothing to compare. \
+                 Pass inputs that make the target produce other outputs, such as ones that fail differently."
+                    .to_string(),
+            );
+        } else if self.oracles.is_empty() {
+            lines.push(format!(
+                "The instruction counts of the {} outputs seen don't differ, timing doesn't reveal the output.",
+                self.compared
+            ));
+        } else {
+            for oracle in self.oracles.iter() {
+                lines.push(format!(
+                    "Output {} takes {} instructions on average, but output {} takes {} (t-value: {})",
+                    describe_output(&oracle.output_1),
+                    oracle.mean_1,
+                    describe_output(&oracle.output_2),
+                    oracle.mean_2,
+                    oracle.t_value
+                ));
+            }
+            lines.push(
+                "Timing depends on the output, so anyone who can measure it learns the output without seeing it. \
+                 For outputs such as error codes this is an error oracle."
+                    .to_string(),
+            );
+        }
+        lines.join("\n")
+    }
+}
+
+// The classes of inputs by output, the most common output first
+fn classes(outputs: BTreeMap<Vec<u8>, Vec<u64>>) -> Vec<OutputClass> {
+    let mut classes: Vec<OutputClass> = outputs
+        .into_iter()
+        .map(|(output, counts)| OutputClass { output, counts })
+        .collect();
+    classes.sort_by_key(|class| std::cmp::Reverse(class.counts.len()));
+    classes
+}
+
+fn t_value(first: &OutputClass, second: &OutputClass) -> f64 {
+    let t = welch_t(
+        &stats(&first.counts),
+        first.counts.len(),
+        &stats(&second.counts),
+        second.counts.len(),
+    );
+    // Identical counts with no variance at all are no evidence of a difference
+    if t.is_nan() {
+        0.0
+    } else {
+        t
+    }
+}
+
+fn stats(counts: &[u64]) -> Stats<f64> {
+    let mut stats = Stats::new();
+    for count in counts.iter() {
+        stats.update(*count as f64);
+    }
+    stats
+}
+
+fn mean(counts: &[u64]) -> f64 {
+    counts.iter().sum::<u64>() as f64 / counts.len() as f64
+}
+
+// Outputs are in hexadecimal
+fn describe_output(output: &str) -> String {
+    if output.is_empty() {
+        return "(empty)".to_string();
+    }
+    if output.len() > MAX_SHOWN * 2 {
+        return format!(
+            "{}... ({} bytes)",
+            &output[..MAX_SHOWN * 2],
+            output.len() / 2
+        );
+    }
+    output.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oracle_test() {
+        let mut outputs = BTreeMap::new();
+        outputs.insert(vec![0], vec![100, 100, 101]);
+        outputs.insert(vec![1], vec![150, 151]);
+        outputs.insert(vec![2], vec![100, 101, 100, 101]);
+        let classes = classes(outputs);
+        assert_eq!(classes[0].output, vec![2]);
+        assert_eq!(classes[2].output, vec![1]);
+
+        assert!(t_value(&classes[0], &classes[2]) > 4.5);
+        assert!(t_value(&classes[0], &classes[1]) < 4.5);
+        let same = OutputClass {
+            output: vec![3],
+            counts: vec![100, 100],
+        };
+        assert_eq!(t_value(&same, &same), 0.0);
+
+        assert_eq!(
+            describe_output(&"ab".repeat(40)),
+            format!("{}... (40 bytes)", "ab".repeat(32))
+        );
+    }
+}
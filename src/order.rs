@@ -0,0 +1,133 @@
+// Checking that counts don't depend on the order inputs are executed in
+//
+// The statistics treat every sample as independent of the ones before it. A target that keeps state between
+// executions, such as a cache one input fills and the next one hits, breaks that: its counts depend on which inputs
+// ran before, so a difference can show up or disappear depending on how the two inputs are scheduled. Before
+// measuring, both inputs are run interleaved (A/B/A/B) on one fresh instance, and in blocks (A/A/B/B) on another.
+// Instruction counts of wasm targets are exact, so an input that doesn't execute the same number of instructions
+// every time depends on the order. Targets measured by time are too noisy to check this way, and targets started
+// from the same memory before every execution with `--fresh-memory` can't depend on the order through it.
+
+use crate::errors::SideFuzzError;
+use crate::target::Target;
+
+// Runs of each input in each order
+const RUNS: usize = 4;
+
+// Which input runs when: interleaved, then in blocks
+fn orders() -> (Vec<usize>, Vec<usize>) {
+    let interleaved = (0..RUNS * 2).map(|run| run % 2).collect();
+    let blocked = (0..RUNS * 2).map(|run| run / RUNS).collect();
+    (interleaved, blocked)
+}
+
+// Whether counts of `target` can be checked
+fn applies(target: &dyn Target) -> bool {
+    target
+        .wasm()
+        .map_or(false, |(_, options)| !options.fresh_memory)
+}
+
+// The outcomes of each input when run on a fresh instance in `order`, None where it crashed
+fn run(
+    target: &dyn Target,
+    inputs: [&[u8]; 2],
+    order: &[usize],
+) -> Result<[Vec<Option<u64>>; 2], SideFuzzError> {
+    let mut instance = target.try_clone()?;
+    let mut outcomes = [Vec::new(), Vec::new()];
+    for &input in order.iter() {
+        outcomes[input].push(instance.count_instructions(inputs[input]).ok());
+    }
+    Ok(outcomes)
+}
+
+fn describe(outcomes: &[Option<u64>]) -> String {
+    let mut distinct = outcomes.to_vec();
+    distinct.sort();
+    distinct.dedup();
+    distinct
+        .iter()
+        .map(|outcome| match outcome {
+            Some(count) => format!("{} instructions", count),
+            None => "a crash".to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(" or ")
+}
+
+// A warning if any input's outcomes weren't all the same
+fn dependence(
+    interleaved: &[Vec<Option<u64>>; 2],
+    blocked: &[Vec<Option<u64>>; 2],
+) -> Option<String> {
+    let dependent: Vec<String> = (0..2)
+        .filter(|&input| {
+            let mut outcomes = interleaved[input].iter().chain(blocked[input].iter());
+            let first = outcomes.next();
+            outcomes.any(|outcome| Some(outcome) != first)
+        })
+        .map(|input| {
+            format!(
+                "input {} executed {} interleaved with the other input (A/B/A/B), and {} in blocks (A/A/B/B)",
+                input + 1,
+                describe(&interleaved[input]),
+                describe(&blocked[input])
+            )
+        })
+        .collect();
+    if dependent.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "counts depend on the order inputs run in: {}. Earlier executions leave state behind that changes later \
+         ones, which invalidates the statistics. Run with --fresh-memory to start every execution from the same state",
+        dependent.join("; ")
+    ))
+}
+
+// Run `first` and `second` in both orders on fresh instances of `target`, returning a warning if their counts
+// depend on the order
+pub(crate) fn check(
+    target: &dyn Target,
+    first: &[u8],
+    second: &[u8],
+) -> Result<Option<String>, SideFuzzError> {
+    if !applies(target) {
+        return Ok(None);
+    }
+    let (interleaved, blocked) = orders();
+    let interleaved = run(target, [first, second], &interleaved)?;
+    let blocked = run(target, [first, second], &blocked)?;
+    Ok(dependence(&interleaved, &blocked))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dependence_test() {
+        let (interleaved, blocked) = orders();
+        assert_eq!(interleaved, vec![0, 1, 0, 1, 0, 1, 0, 1]);
+        assert_eq!(blocked, vec![0, 0, 0, 0, 1, 1, 1, 1]);
+
+        let same = [vec![Some(120); RUNS], vec![Some(95); RUNS]];
+        assert_eq!(dependence(&same, &same), None);
+
+        // The second input is cheaper right after itself, as if it hit a cache it filled
+        let blocked = [
+            vec![Some(120); RUNS],
+            vec![Some(95), Some(80), Some(80), Some(80)],
+        ];
+        let warning = dependence(&same, &blocked).unwrap();
+        assert!(warning.contains("input 2 executed 95 instructions interleaved with the other input (A/B/A/B), and 80 instructions or 95 instructions in blocks"));
+        assert!(!warning.contains("input 1"));
+        assert!(warning.contains("--fresh-memory"));
+
+        let crashed = [vec![Some(120), None, Some(120), Some(120)], same[1].clone()];
+        assert!(dependence(&crashed, &same)
+            .unwrap()
+            .contains("a crash or 120 instructions"));
+    }
+}
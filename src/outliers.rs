@@ -0,0 +1,153 @@
+// Warm-up discard and outlier rejection for the samples of one class
+//
+// The first executions after a target is instantiated can take longer than the rest, while lazily initialized
+// statics are primed and the allocator settles, so a number of samples can be discarded before any are analyzed.
+// Outliers are rejected by their distance from the median, in units of the median absolute deviation (MAD), which
+// unlike the standard deviation isn't thrown off by the outliers themselves. The median and MAD are calibrated from
+// the first samples after the warm-up, which are held back until then, and fixed from there on. Rare slow paths on
+// the secret look like outliers too, so rejection is meant for noisy measurements of native targets, not for exact
+// instruction counts.
+
+use crate::report::Rejected;
+
+// Samples calibrating the median and MAD of each class
+const CALIBRATION_SAMPLES: usize = 1000;
+
+// Scales the MAD to the standard deviation of a normal distribution, so thresholds read like standard deviations
+const MAD_TO_STD_DEV: f64 = 1.4826;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Filter {
+    warmup: usize,          // Samples still to discard
+    threshold: Option<f64>, // Samples further than this many scaled MADs from the median are rejected
+    calibration: Vec<f64>,
+    bounds: Option<(f64, f64)>, // The lowest and highest samples accepted, once calibrated
+    rejected: Rejected,
+}
+
+impl Filter {
+    pub fn new(warmup: usize, threshold: Option<f64>) -> Self {
+        Filter {
+            warmup,
+            threshold,
+            ..Filter::default()
+        }
+    }
+
+    // The samples to analyze after `sample`: none, `sample` itself, or every calibration sample accepted once the
+    // median and MAD are known
+    pub fn push(&mut self, sample: f64) -> Vec<f64> {
+        if self.warmup > 0 {
+            self.warmup -= 1;
+            self.rejected.warmup += 1;
+            return Vec::new();
+        }
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return vec![sample],
+        };
+        let (lowest, highest) = match self.bounds {
+            Some(bounds) => bounds,
+            None => {
+                self.calibration.push(sample);
+                if self.calibration.len() < CALIBRATION_SAMPLES {
+                    return Vec::new();
+                }
+                let bounds = bounds(&self.calibration, threshold);
+                self.bounds = Some(bounds);
+                let samples = std::mem::replace(&mut self.calibration, Vec::new());
+                return samples
+                    .into_iter()
+                    .filter(|sample| self.accept(*sample, bounds))
+                    .collect();
+            }
+        };
+        if self.accept(sample, (lowest, highest)) {
+            vec![sample]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn accept(&mut self, sample: f64, (lowest, highest): (f64, f64)) -> bool {
+        let accepted = sample >= lowest && sample <= highest;
+        if !accepted {
+            self.rejected.outliers += 1;
+        }
+        accepted
+    }
+
+    pub fn rejected(&self) -> Rejected {
+        self.rejected
+    }
+}
+
+fn median(sorted: &[f64]) -> f64 {
+    let middle = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+fn sort(samples: &mut [f64]) {
+    // Ok to unwrap, counts and noise are never NaN
+    samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+}
+
+// The range of samples within `threshold` scaled MADs of the median. Samples without any spread can't be scaled,
+// so then every sample is accepted.
+fn bounds(samples: &[f64], threshold: f64) -> (f64, f64) {
+    let mut sorted = samples.to_vec();
+    sort(&mut sorted);
+    let center = median(&sorted);
+    let mut deviations: Vec<f64> = sorted
+        .iter()
+        .map(|sample| (sample - center).abs())
+        .collect();
+    sort(&mut deviations);
+    let mad = median(&deviations) * MAD_TO_STD_DEV;
+    if mad == 0.0 {
+        return (std::f64::NEG_INFINITY, std::f64::INFINITY);
+    }
+    (center - threshold * mad, center + threshold * mad)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_test() {
+        let mut filter = Filter::new(2, Some(5.0));
+        assert!(filter.push(1e6).is_empty());
+        assert!(filter.push(1e6).is_empty());
+
+        // Calibration samples are held back, then released without the outliers
+        let mut accepted = Vec::new();
+        for i in 0..CALIBRATION_SAMPLES {
+            let sample = if i % 100 == 0 {
+                1e6
+            } else {
+                100.0 + (i % 3) as f64
+            };
+            accepted.extend(filter.push(sample));
+            assert_eq!(accepted.is_empty(), i + 1 < CALIBRATION_SAMPLES);
+        }
+        assert_eq!(accepted.len(), CALIBRATION_SAMPLES - 10);
+        assert_eq!(filter.push(101.0), vec![101.0]);
+        assert!(filter.push(200.0).is_empty());
+        assert_eq!(
+            filter.rejected(),
+            Rejected {
+                warmup: 2,
+                outliers: 11
+            }
+        );
+
+        // Without spread nothing is rejected, and without a threshold nothing is held back
+        assert_eq!(bounds(&[5.0, 5.0, 5.0, 9.0], 3.0).1, std::f64::INFINITY);
+        assert_eq!(Filter::new(0, None).push(7.0), vec![7.0]);
+    }
+}
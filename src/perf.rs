@@ -0,0 +1,220 @@
+// Measuring native executions, with whatever each OS offers
+//
+// Every OS counts something different, so each has its own `ExecutionCounter` and reports name the unit it counts:
+// - Linux counts the user-space instructions retired, with perf_event_open. They are exact, like the wasm backend's.
+// - Windows counts the CPU cycles the thread ran for, with QueryThreadCycleTime, which includes time in the kernel.
+// - macOS measures the CPU time of the thread, which the kernel keeps in its mach thread times, in nanoseconds.
+// Cycles and CPU time vary from one execution to the next, so runs on Windows and macOS need more samples to decide,
+// but leaks found on one OS show up as differences on the others too.
+
+use crate::errors::SideFuzzError;
+use std::io::Error as IOError;
+
+// Measures the executions of the thread that starts it
+pub(crate) trait ExecutionCounter: Send {
+    // What is counted, such as "instructions"
+    fn unit(&self) -> &'static str;
+
+    fn start(&mut self) -> Result<(), SideFuzzError>;
+
+    // How much was counted since `start`
+    fn stop(&mut self) -> Result<u64, SideFuzzError>;
+}
+
+// The counter of the OS sidefuzz is running on
+#[cfg(target_os = "linux")]
+pub(crate) fn counter() -> Result<Box<dyn ExecutionCounter>, SideFuzzError> {
+    Ok(Box::new(InstructionCounter::new()?))
+}
+
+#[cfg(windows)]
+pub(crate) fn counter() -> Result<Box<dyn ExecutionCounter>, SideFuzzError> {
+    Ok(Box::new(CycleCounter::default()))
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn counter() -> Result<Box<dyn ExecutionCounter>, SideFuzzError> {
+    Ok(Box::new(ThreadTimer::default()))
+}
+
+// perf_event_open(2) definitions. Only the fields up to config1 are needed (PERF_ATTR_SIZE_VER0).
+#[cfg(target_os = "linux")]
+mod perf_event {
+    pub const PERF_TYPE_HARDWARE: u32 = 0;
+    pub const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+    pub const PERF_FLAG_DISABLED: u64 = 1 << 0;
+    pub const PERF_FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+    pub const PERF_FLAG_EXCLUDE_HV: u64 = 1 << 6;
+    pub const PERF_EVENT_IOC_ENABLE: u64 = 0x2400;
+    pub const PERF_EVENT_IOC_DISABLE: u64 = 0x2401;
+    pub const PERF_EVENT_IOC_RESET: u64 = 0x2403;
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct PerfEventAttr {
+        pub kind: u32,
+        pub size: u32,
+        pub config: u64,
+        pub sample_period: u64,
+        pub sample_type: u64,
+        pub read_format: u64,
+        pub flags: u64,
+        pub wakeup_events: u32,
+        pub bp_type: u32,
+        pub config1: u64,
+    }
+}
+
+// Counts user-space instructions retired by the thread that created it
+#[cfg(target_os = "linux")]
+struct InstructionCounter {
+    fd: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+impl InstructionCounter {
+    fn new() -> Result<Self, SideFuzzError> {
+        use perf_event::*;
+        let attr = PerfEventAttr {
+            kind: PERF_TYPE_HARDWARE,
+            size: std::mem::size_of::<PerfEventAttr>() as u32,
+            config: PERF_COUNT_HW_INSTRUCTIONS,
+            flags: PERF_FLAG_DISABLED | PERF_FLAG_EXCLUDE_KERNEL | PERF_FLAG_EXCLUDE_HV,
+            ..PerfEventAttr::default()
+        };
+
+        // pid 0 and cpu -1 measure the calling thread on any cpu
+        let (pid, cpu, group, flags): (libc::pid_t, libc::c_int, libc::c_int, libc::c_ulong) =
+            (0, -1, -1, 0);
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                &attr as *const PerfEventAttr,
+                pid,
+                cpu,
+                group,
+                flags,
+            )
+        };
+        if fd < 0 {
+            return Err(SideFuzzError::PerfCounterError(IOError::last_os_error()));
+        }
+
+        Ok(InstructionCounter {
+            fd: fd as libc::c_int,
+        })
+    }
+
+    fn ioctl(&self, request: u64) -> Result<(), SideFuzzError> {
+        if unsafe { libc::ioctl(self.fd, request as _, 0) } < 0 {
+            return Err(SideFuzzError::PerfCounterError(IOError::last_os_error()));
+        }
+        Ok(())
+    }
+
+    fn read(&self) -> Result<u64, SideFuzzError> {
+        let mut count: u64 = 0;
+        let size = std::mem::size_of::<u64>();
+        let read =
+            unsafe { libc::read(self.fd, &mut count as *mut u64 as *mut libc::c_void, size) };
+        if read != size as isize {
+            return Err(SideFuzzError::PerfCounterError(IOError::last_os_error()));
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ExecutionCounter for InstructionCounter {
+    fn unit(&self) -> &'static str {
+        "instructions"
+    }
+
+    fn start(&mut self) -> Result<(), SideFuzzError> {
+        self.ioctl(perf_event::PERF_EVENT_IOC_RESET)?;
+        self.ioctl(perf_event::PERF_EVENT_IOC_ENABLE)
+    }
+
+    fn stop(&mut self) -> Result<u64, SideFuzzError> {
+        self.ioctl(perf_event::PERF_EVENT_IOC_DISABLE)?;
+        self.read()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for InstructionCounter {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+// Counts the CPU cycles of the current thread
+#[cfg(windows)]
+#[derive(Default)]
+struct CycleCounter {
+    started: u64,
+}
+
+#[cfg(windows)]
+fn thread_cycles() -> Result<u64, SideFuzzError> {
+    use windows_sys::Win32::System::Threading::{GetCurrentThread, QueryThreadCycleTime};
+    let mut cycles = 0;
+    // The pseudo handle of the current thread never needs to be closed
+    if unsafe { QueryThreadCycleTime(GetCurrentThread(), &mut cycles) } == 0 {
+        return Err(SideFuzzError::PerfCounterError(IOError::last_os_error()));
+    }
+    Ok(cycles)
+}
+
+#[cfg(windows)]
+impl ExecutionCounter for CycleCounter {
+    fn unit(&self) -> &'static str {
+        "cycles"
+    }
+
+    fn start(&mut self) -> Result<(), SideFuzzError> {
+        self.started = thread_cycles()?;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<u64, SideFuzzError> {
+        Ok(thread_cycles()?.saturating_sub(self.started))
+    }
+}
+
+// Measures the CPU time of the current thread
+#[cfg(target_os = "macos")]
+#[derive(Default)]
+struct ThreadTimer {
+    started: u64,
+}
+
+#[cfg(target_os = "macos")]
+fn thread_time() -> Result<u64, SideFuzzError> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } != 0 {
+        return Err(SideFuzzError::PerfCounterError(IOError::last_os_error()));
+    }
+    Ok(time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64)
+}
+
+#[cfg(target_os = "macos")]
+impl ExecutionCounter for ThreadTimer {
+    fn unit(&self) -> &'static str {
+        "nanoseconds"
+    }
+
+    fn start(&mut self) -> Result<(), SideFuzzError> {
+        self.started = thread_time()?;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<u64, SideFuzzError> {
+        Ok(thread_time()?.saturating_sub(self.started))
+    }
+}
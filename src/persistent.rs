@@ -0,0 +1,74 @@
+// Persistent mode, running a batch of inputs in a single call into the module
+//
+// For tiny targets most of the time goes into calling the module: looking up the export, writing the input and
+// resetting the fuel. Targets that export "fuzz_batch" (which `sidefuzz_target!` does) can instead be given many
+// inputs at once. They are written one after the other to the buffer at "input_batch_pointer", and "fuzz_batch(n)"
+// runs the first n of them, asking the host how many instructions have run before and after each one. The count
+// of each run is left in the i64 array at "batch_results_pointer".
+
+use crate::errors::SideFuzzError;
+use crate::fuel::Fuel;
+use crate::instrument::HOOK_MODULE;
+use wasmi::{Caller, Func, Linker, Store};
+
+// The exports a module needs for persistent mode
+pub(crate) const BATCH_EXPORTS: &[&str] = &[
+    "fuzz_batch",
+    "input_batch_pointer",
+    "batch_results_pointer",
+    "batch_capacity",
+];
+
+// The host function modules call to ask how many instructions have run
+pub(crate) const INSTRUCTIONS: &str = "instructions";
+
+// Where a module's batch lives in its linear memory
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BatchBuffers {
+    pub inputs: usize,   // Address of the input buffer
+    pub results: usize,  // Address of the results array
+    pub capacity: usize, // The most inputs one call can run
+    // Instructions each count includes on top of what the same input counts outside of a batch, from asking the
+    // host for the count and calling the target
+    pub overhead: u64,
+}
+
+// Let modules ask how many instructions have run
+pub(crate) fn add_to_linker(
+    linker: &mut Linker<()>,
+    store: &mut Store<()>,
+) -> Result<(), SideFuzzError> {
+    let instructions = Func::wrap(&mut *store, |caller: Caller<'_, ()>| -> i64 {
+        caller.consumed() as i64
+    });
+    linker.define(HOOK_MODULE, INSTRUCTIONS, instructions)?;
+    Ok(())
+}
+
+// Read the counts of `n` runs from the results array, without the batch's overhead
+pub(crate) fn decode_results(bytes: &[u8], n: usize, overhead: u64) -> Vec<u64> {
+    bytes
+        .chunks_exact(8)
+        .take(n)
+        .map(|count| {
+            let mut le = [0; 8];
+            le.copy_from_slice(count);
+            (i64::from_le_bytes(le).max(0) as u64).saturating_sub(overhead)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_results_test() {
+        let mut bytes = Vec::new();
+        for count in [120i64, 105, -1, 999].iter() {
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+        assert_eq!(decode_results(&bytes, 3, 5), vec![115, 100, 0]);
+        assert_eq!(decode_results(&bytes, 10, 0).len(), 4);
+    }
+}
@@ -0,0 +1,277 @@
+// This file contains the "regress" subcommand, which checks a new build of a target against the leaks found before
+//
+// Every finding in a findings file is run again, and leaks unless both of its inputs now execute the same number of
+// instructions. Leaks that were fixed and leak again are regressions. With `--update`, findings that no longer leak
+// are marked fixed in the file, so the next run notices if they come back.
+
+use crate::errors::SideFuzzError;
+use crate::findings::{Finding, Findings};
+use crate::target::{Target, TargetOptions};
+use serde::Serialize;
+
+// Inputs longer than this are shortened when printed
+const MAX_SHOWN: usize = 16;
+
+pub struct Regress {
+    module: Box<dyn Target>,
+    path: String,
+    findings: Findings,
+    update: bool,
+}
+
+// What running a finding again showed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Outcome {
+    Fixed,
+    Leaks(u64, u64),
+    Crashes,
+    WrongSize,
+}
+
+// What running a finding again showed, compared to whether it was fixed before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Regressed,  // It was fixed, and leaks again
+    StillLeaks, // It was never fixed
+    Fixed,
+    NowFixed, // It leaked before, and no longer does
+    Crashes,
+    WrongSize, // Its inputs don't fit the target, so it was skipped
+}
+
+impl Status {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Status::Regressed => "REGRESSED",
+            Status::StillLeaks => "still leaks",
+            Status::Fixed => "fixed",
+            Status::NowFixed => "now fixed",
+            Status::Crashes => "crashes",
+            Status::WrongSize => "skipped, the inputs don't fit this target",
+        }
+    }
+}
+
+// One finding, run again
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FindingResult {
+    pub input_1: String,
+    pub input_2: String,
+    pub status: Status,
+    pub counts: Option<(u64, u64)>, // The instructions both inputs executed, if it leaks
+}
+
+// What running every finding again showed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RegressResult {
+    pub path: String,
+    pub findings: Vec<FindingResult>,
+    pub regressed: usize,
+    pub still_leak: usize,
+    pub newly_fixed: usize,
+    pub updated: bool, // Whether the newly fixed findings were marked fixed in the findings file
+}
+
+impl Regress {
+    pub fn from_files_with_options(
+        filename: &str,
+        findings: &str,
+        options: &TargetOptions,
+    ) -> Result<Self, SideFuzzError> {
+        let module = crate::target::load(filename, options)?;
+        Ok(Regress {
+            module,
+            path: findings.to_string(),
+            findings: Findings::load(findings)?,
+            update: false,
+        })
+    }
+
+    // Mark findings that no longer leak as fixed in the findings file
+    pub fn set_update(&mut self, update: bool) {
+        self.update = update;
+    }
+
+    // Run every finding again, marking the ones that no longer leak as fixed when updating
+    pub fn run(&mut self) -> Result<RegressResult, SideFuzzError> {
+        if self.findings.findings.is_empty() {
+            return Err(SideFuzzError::BadFindings(format!(
+                "{} holds no findings",
+                self.path
+            )));
+        }
+
+        let mut results = Vec::with_capacity(self.findings.findings.len());
+        for index in 0..self.findings.findings.len() {
+            let finding = self.findings.findings[index].clone();
+            let outcome = self.replay(&finding)?;
+            let finding = &mut self.findings.findings[index];
+            let status = match (outcome, finding.fixed) {
+                (Outcome::Leaks(..), true) => Status::Regressed,
+                (Outcome::Leaks(..), false) => Status::StillLeaks,
+                (Outcome::Fixed, true) => Status::Fixed,
+                (Outcome::Fixed, false) => {
+                    if self.update {
+                        finding.fixed = true;
+                    }
+                    Status::NowFixed
+                }
+                (Outcome::Crashes, _) => Status::Crashes,
+                (Outcome::WrongSize, _) => Status::WrongSize,
+            };
+            let counts = match outcome {
+                Outcome::Leaks(first, second) => Some((first, second)),
+                _ => None,
+            };
+            results.push(FindingResult {
+                input_1: finding.input_1.clone(),
+                input_2: finding.input_2.clone(),
+                status,
+                counts,
+            });
+        }
+
+        let count = |status| results.iter().filter(|r| r.status == status).count();
+        let newly_fixed = count(Status::NowFixed);
+        let updated = self.update && newly_fixed > 0;
+        if updated {
+            self.findings.save(&self.path)?;
+        }
+        Ok(RegressResult {
+            path: self.path.clone(),
+            regressed: count(Status::Regressed),
+            still_leak: count(Status::StillLeaks),
+            newly_fixed,
+            updated,
+            findings: results,
+        })
+    }
+    fn replay(&mut self, finding: &Finding) -> Result<Outcome, SideFuzzError> {
+        let decode = |input: &str| {
+            hex::decode(input)
+                .map_err(|_| SideFuzzError::BadFindings(format!("{} isn't hexadecimal", input)))
+        };
+        let first = decode(&finding.input_1)?;
+        let second = decode(&finding.input_2)?;
+        Ok(replay(self.module.as_mut(), &first, &second))
+    }
+}
+
+impl RegressResult {
+    // 1 if a fixed leak leaks again, and 0 otherwise
+    pub fn exit_code(&self) -> i32 {
+        if self.regressed > 0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let mut lines = Vec::new();
+        for (index, finding) in self.findings.iter().enumerate() {
+            let counts = match finding.counts {
+                Some((first, second)) => format!(" ({} and {} instructions)", first, second),
+                None => String::new(),
+            };
+            lines.push(format!(
+                "finding {}: {} and {}: {}{}",
+                index + 1,
+                shorten(&finding.input_1),
+                shorten(&finding.input_2),
+                finding.status.describe(),
+                counts
+            ));
+        }
+
+        if self.updated {
+            lines.push(String::new());
+            lines.push(format!(
+                "Marked {} findings as fixed in {}",
+                self.newly_fixed, self.path
+            ));
+        }
+        lines.push(String::new());
+        lines.push(format!(
+            "{} findings, {} regressed, {} still leak, {} newly fixed",
+            self.findings.len(),
+            self.regressed,
+            self.still_leak,
+            self.newly_fixed
+        ));
+        if self.regressed > 0 {
+            lines.push("Leaks that were fixed are back.".to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+// Run a leak between `first` and `second` against `module` again
+pub(crate) fn replay(module: &mut dyn Target, first: &[u8], second: &[u8]) -> Outcome {
+    let fuzz_len = module.fuzz_len();
+    let variable_len = module.variable_len();
+    let fits = |input: &[u8]| input.len() == fuzz_len || (variable_len && input.len() < fuzz_len);
+    if !fits(first) || !fits(second) {
+        return Outcome::WrongSize;
+    }
+
+    match (
+        module.count_instructions(first),
+        module.count_instructions(second),
+    ) {
+        (Ok(first), Ok(second)) => outcome(first, second),
+        _ => Outcome::Crashes,
+    }
+}
+
+fn outcome(first: u64, second: u64) -> Outcome {
+    if first == second {
+        Outcome::Fixed
+    } else {
+        Outcome::Leaks(first, second)
+    }
+}
+
+pub(crate) fn shorten(input: &str) -> String {
+    if input.len() > MAX_SHOWN * 2 {
+        format!("{}...", &input[..MAX_SHOWN * 2])
+    } else {
+        input.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regress_test() {
+        assert_eq!(outcome(100, 100), Outcome::Fixed);
+        assert_eq!(outcome(100, 140), Outcome::Leaks(100, 140));
+        assert_eq!(shorten("00ff"), "00ff");
+        assert_eq!(shorten(&"ab".repeat(20)), format!("{}...", "ab".repeat(16)));
+
+        let mut result = RegressResult {
+            path: "findings.json".to_string(),
+            findings: vec![FindingResult {
+                input_1: "00".to_string(),
+                input_2: "ff".to_string(),
+                status: Status::StillLeaks,
+                counts: Some((100, 140)),
+            }],
+            regressed: 0,
+            still_leak: 1,
+            newly_fixed: 0,
+            updated: false,
+        };
+        assert_eq!(result.exit_code(), 0);
+        assert!(result
+            .text()
+            .starts_with("finding 1: 00 and ff: still leaks (100 and 140 instructions)"));
+        result.findings[0].status = Status::Regressed;
+        result.regressed = 1;
+        assert_eq!(result.exit_code(), 1);
+        assert!(result.text().ends_with("Leaks that were fixed are back."));
+    }
+}
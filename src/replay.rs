@@ -0,0 +1,349 @@
+// This file contains the "replay" subcommand, which reanalyzes or re-executes a session log
+
+use crate::dudect::welch_t;
+use crate::errors::SideFuzzError;
+use crate::session::{Execution, Session};
+use crate::target::{Measure, Target, TargetOptions};
+use crate::util::p_value_from_t_value;
+use rolling_stats::Stats;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// How many inputs that didn't reproduce are listed
+const MAX_MISMATCHES_SHOWN: usize = 10;
+
+pub struct Replay {
+    session: Session,
+    target: Option<Box<dyn Target>>,
+}
+
+// Every execution of one input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Samples {
+    pub input: Vec<u8>,
+    pub counts: Vec<u64>, // Executions that crashed aren't included
+    pub crashes: usize,
+}
+
+// What a session measured, worked out from the log alone
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub executions: usize,
+    pub crashes: usize,
+    pub inputs: Vec<Samples>, // In the order they were first executed
+    pub least: Option<(Vec<u8>, u64)>,
+    pub most: Option<(Vec<u8>, u64)>,
+    pub pair: Option<(usize, usize, f64)>, // The two most sampled inputs and the t-value between them
+}
+
+// The execution of an input that measured the least or the most
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Extreme {
+    pub input: String, // In hexadecimal
+    pub measured: u64,
+}
+
+// How often an input was executed, and what it measured on average
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Sampled {
+    pub input: String,
+    pub executions: usize,
+    pub mean: f64,
+}
+
+// The two most sampled inputs, compared
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SampledPair {
+    pub first: Sampled,
+    pub second: Sampled,
+    pub t_value: f64,
+    pub confidence: f64,
+}
+
+// An input that measured differently when re-executed, None if it crashed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Mismatch {
+    pub input: String,
+    pub logged: Option<u64>,
+    pub replayed: Option<u64>,
+}
+
+// What replaying a session showed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReplayResult {
+    pub unit: &'static str,
+    pub executions: usize,
+    pub inputs: usize, // Distinct inputs
+    pub crashes: usize,
+    pub least: Option<Extreme>,
+    pub most: Option<Extreme>,
+    pub most_sampled: Option<SampledPair>,
+    pub replayed: bool, // Whether the inputs were re-executed against a target
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Replay {
+    pub fn from_file(path: &str) -> Result<Self, SideFuzzError> {
+        Ok(Replay {
+            session: Session::load(path)?,
+            target: None,
+        })
+    }
+
+    // Re-execute the logged inputs against a target, instead of only reanalyzing the log
+    pub fn set_target(
+        &mut self,
+        filename: &str,
+        options: &TargetOptions,
+    ) -> Result<(), SideFuzzError> {
+        if self.session.measure == Measure::Time {
+            return Err(SideFuzzError::BadSession(
+                "it holds wall-clock timings, which can't be reproduced exactly. Replay it without a target to reanalyze it".to_string(),
+            ));
+        }
+        let target = crate::target::load(filename, options)?;
+        for execution in self.session.executions.iter() {
+            if target.variable_len() {
+                if execution.input.len() > target.fuzz_len() {
+                    return Err(SideFuzzError::InputTooLong(target.fuzz_len()));
+                }
+            } else if execution.input.len() != target.fuzz_len() {
+                return Err(SideFuzzError::InputsWrongSize(target.fuzz_len()));
+            }
+        }
+        self.target = Some(target);
+        Ok(())
+    }
+
+    // Reanalyze the log, and re-execute its inputs if there is a target
+    pub fn run(&mut self) -> Result<ReplayResult, SideFuzzError> {
+        let unit = match self.session.measure {
+            Measure::Instructions => "instructions",
+            Measure::Time => "ns",
+        };
+        let summary = summarize(&self.session.executions);
+        if summary.executions == 0 {
+            return Err(SideFuzzError::BadSession(
+                "it doesn't hold any executions".to_string(),
+            ));
+        }
+
+        let extreme = |(input, measured): &(Vec<u8>, u64)| Extreme {
+            input: hex::encode(input),
+            measured: *measured,
+        };
+        let sampled = |samples: &Samples| Sampled {
+            input: hex::encode(&samples.input),
+            executions: samples.counts.len(),
+            mean: mean(&samples.counts),
+        };
+        let most_sampled = summary.pair.map(|(first, second, t)| SampledPair {
+            first: sampled(&summary.inputs[first]),
+            second: sampled(&summary.inputs[second]),
+            t_value: t,
+            confidence: 1.0 - p_value_from_t_value(t),
+        });
+
+        // Instruction counts are exact, so every execution of an input logged the same count
+        let mut mismatches = Vec::new();
+        if let Some(target) = self.target.as_mut() {
+            for samples in summary.inputs.iter() {
+                let logged = samples.counts.first().copied();
+                let replayed = target.count_instructions(&samples.input).ok();
+                if logged != replayed {
+                    mismatches.push(Mismatch {
+                        input: hex::encode(&samples.input),
+                        logged,
+                        replayed,
+                    });
+                }
+            }
+        }
+
+        Ok(ReplayResult {
+            unit,
+            executions: summary.executions,
+            inputs: summary.inputs.len(),
+            crashes: summary.crashes,
+            least: summary.least.as_ref().map(extreme),
+            most: summary.most.as_ref().map(extreme),
+            most_sampled,
+            replayed: self.target.is_some(),
+            mismatches,
+        })
+    }
+}
+
+impl ReplayResult {
+    // 1 if re-executing the inputs gave different results than the log, and 0 otherwise
+    pub fn exit_code(&self) -> i32 {
+        if self.mismatches.is_empty() {
+            0
+        } else {
+            1
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let mut lines = vec![format!(
+            "{} executions of {} distinct inputs, {} crashed",
+            self.executions, self.inputs, self.crashes
+        )];
+        if let (Some(least), Some(most)) = (&self.least, &self.most) {
+            lines.push(format!(
+                "least: {} {} for {}",
+                least.measured, self.unit, least.input
+            ));
+            lines.push(format!(
+                "most: {} {} for {}",
+                most.measured, self.unit, most.input
+            ));
+        }
+        if let Some(pair) = &self.most_sampled {
+            lines.push(String::new());
+            lines.push("most sampled inputs:".to_string());
+            for sampled in [&pair.first, &pair.second].iter() {
+                lines.push(format!(
+                    "  {} executions, mean {:.2} {}: {}",
+                    sampled.executions, sampled.mean, self.unit, sampled.input
+                ));
+            }
+            lines.push(format!(
+                "t = {:.2}, confidence {:.4}",
+                pair.t_value, pair.confidence
+            ));
+        }
+        if !self.replayed {
+            return lines.join("\n");
+        }
+
+        let count = |count: Option<u64>| count.map_or("crashed".to_string(), |c| c.to_string());
+        lines.push(String::new());
+        lines.push(format!(
+            "{} of {} inputs reproduced",
+            self.inputs - self.mismatches.len(),
+            self.inputs
+        ));
+        for mismatch in self.mismatches.iter().take(MAX_MISMATCHES_SHOWN) {
+            lines.push(format!(
+                "  logged {}, replayed {}: {}",
+                count(mismatch.logged),
+                count(mismatch.replayed),
+                mismatch.input
+            ));
+        }
+        if self.mismatches.len() > MAX_MISMATCHES_SHOWN {
+            lines.push(format!(
+                "  and {} more",
+                self.mismatches.len() - MAX_MISMATCHES_SHOWN
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+// Group the executions by input and compare the inputs
+pub(crate) fn summarize(executions: &[Execution]) -> Summary {
+    let mut inputs: Vec<Samples> = Vec::new();
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    let mut crashes = 0;
+    let mut least: Option<(Vec<u8>, u64)> = None;
+    let mut most: Option<(Vec<u8>, u64)> = None;
+
+    for execution in executions.iter() {
+        let i = *index.entry(execution.input.as_slice()).or_insert_with(|| {
+            inputs.push(Samples {
+                input: execution.input.clone(),
+                counts: Vec::new(),
+                crashes: 0,
+            });
+            inputs.len() - 1
+        });
+        match execution.count {
+            Some(count) => {
+                inputs[i].counts.push(count);
+                if least.as_ref().map_or(true, |(_, least)| count < *least) {
+                    least = Some((execution.input.clone(), count));
+                }
+                if most.as_ref().map_or(true, |(_, most)| count > *most) {
+                    most = Some((execution.input.clone(), count));
+                }
+            }
+            None => {
+                inputs[i].crashes += 1;
+                crashes += 1;
+            }
+        }
+    }
+
+    // The pair dudect spent the most time sampling, when the log shows repeated samples of at least two inputs
+    let mut by_samples: Vec<usize> = (0..inputs.len())
+        .filter(|i| inputs[*i].counts.len() >= 2)
+        .collect();
+    by_samples.sort_by_key(|i| std::cmp::Reverse(inputs[*i].counts.len()));
+    let pair = match by_samples.as_slice() {
+        [first, second, ..] => {
+            let t = welch_t(
+                &stats(&inputs[*first].counts),
+                inputs[*first].counts.len(),
+                &stats(&inputs[*second].counts),
+                inputs[*second].counts.len(),
+            );
+            // Identical samples with no variance at all are no evidence of a difference
+            let t = if t.is_nan() { 0.0 } else { t };
+            Some((*first, *second, t))
+        }
+        _ => None,
+    };
+
+    Summary {
+        executions: executions.len(),
+        crashes,
+        inputs,
+        least,
+        most,
+        pair,
+    }
+}
+
+fn stats(counts: &[u64]) -> Stats<f64> {
+    let mut stats = Stats::new();
+    for count in counts.iter() {
+        stats.update(*count as f64);
+    }
+    stats
+}
+
+fn mean(counts: &[u64]) -> f64 {
+    counts.iter().sum::<u64>() as f64 / counts.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarize_test() {
+        let execution = |input: u8, count| Execution {
+            input: vec![input],
+            count,
+        };
+        let mut executions = vec![execution(3, Some(5)), execution(4, None)];
+        for i in 0..50 {
+            executions.push(execution(1, Some(100 + i % 3)));
+            executions.push(execution(2, Some(200 + i % 3)));
+        }
+
+        let summary = summarize(&executions);
+        assert_eq!(summary.executions, 102);
+        assert_eq!(summary.crashes, 1);
+        assert_eq!(summary.inputs.len(), 4);
+        assert_eq!(summary.least, Some((vec![3], 5)));
+        assert_eq!(summary.most, Some((vec![2], 202)));
+
+        let (first, second, t) = summary.pair.unwrap();
+        assert_eq!(summary.inputs[first].input, vec![1]);
+        assert_eq!(summary.inputs[second].input, vec![2]);
+        assert!(t > 100.0);
+    }
+}
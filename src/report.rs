@@ -0,0 +1,479 @@
+// This file contains machine-readable reports of fuzzing and checking results
+
+use crate::ci::CiStatus;
+use crate::crashes::CrashSummary;
+use crate::errors::SideFuzzError;
+use crate::meta::TargetMeta;
+use crate::metrics::Metrics;
+use crate::tracing::FunctionDelta;
+use crate::tui::{Dashboard, Stats};
+use serde::Serialize;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Output format for results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Sarif, // For code scanning dashboards
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            _ => Err(format!("unknown output format '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Leak,   // A statistically significant difference was found
+    NoLeak,       // The t-statistic stayed small after many samples
+    Inconclusive, // Sampling was stopped before either of the above
+}
+
+impl Default for Verdict {
+    fn default() -> Self {
+        Verdict::Inconclusive
+    }
+}
+
+// The result of a fuzz or check run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub command: &'static str,
+    pub verdict: Verdict,
+    pub input_1: String,
+    pub input_2: String,
+    pub instructions_1: f64,
+    pub instructions_2: f64,
+    pub difference: f64,
+    pub t_value: f64,
+    pub confidence: f64,
+    pub samples: usize,
+    pub generations: Option<u64>,
+    pub seed: Option<u64>,
+    // Functions whose instruction counts differ between the inputs, largest difference first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub functions: Vec<FunctionDelta>,
+    // How large the difference is, when it was measured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effect: Option<Effect>,
+    // Samples left out of the analysis, when warm-up or outlier rejection was asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rejected: Option<Rejected>,
+    // Distinct crashes found while fuzzing, most common first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub crashes: Vec<CrashSummary>,
+    // Whether both inputs produced the same output, for targets that pass one to `sidefuzz::set_output`.
+    // Inputs with different outputs may just be doing different work.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub same_output: Option<bool>,
+    // The wasm proposals the target was loaded with, as they decide which modules load at all
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub wasm_features: Vec<String>,
+    // Things about the target that make its measurements less trustworthy, such as imported randomness
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    // What the target says about itself, if it was built with `sidefuzz_target!`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_meta: Option<TargetMeta>,
+    // SHA-256 of the target module, so results can be matched to the build they came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub module_sha256: Option<String>,
+    // Whether the inputs are messages or keys, for targets that fetch a key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    // How much a leak matters, from the size and consistency of the difference. Only set for leaks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    // How the optimizer bred the inputs, oldest generation first. Only set by fuzzing.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lineage: Vec<LineageStep>,
+    // Where an interrupted fuzzing run saved its progress, so it can be resumed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkpoint: Option<String>,
+    // Only used for HTML reports
+    #[serde(skip)]
+    pub evidence: Evidence,
+}
+
+// The estimated size of a difference, for judging whether it is practically exploitable
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Effect {
+    pub difference: f64, // Mean of the first input minus mean of the second, per execution
+    pub lower: f64,      // 95% confidence interval of the difference
+    pub upper: f64,
+    pub relative: f64, // The difference as a percentage of the larger mean
+    // Cohen's d, the difference in standard deviations. Not set when neither input's count varies.
+    pub cohens_d: Option<f64>,
+    // How many measurements it takes to see the difference. Not set when neither input's count varies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exploitability: Option<Exploitability>,
+}
+
+// Samples taken but left out of the analysis, of both classes together
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Rejected {
+    pub warmup: usize,   // Discarded while the target warmed up
+    pub outliers: usize, // Too far from the median
+}
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} samples were discarded as warm-up and {} rejected as outliers",
+            self.warmup, self.outliers
+        )
+    }
+}
+
+// Which part of the input was fuzzed, for targets that fetch a key separately with `sidefuzz::fetch_key`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Region {
+    Message, // Under a fixed key, leaks depend on the message
+    Key,     // With the message fixed, leaks depend on the key
+}
+
+impl Region {
+    // What the two inputs of a report are, and what is fixed
+    fn describe(&self, verdict: Verdict) -> &'static str {
+        match (self, verdict) {
+            (Region::Message, Verdict::Leak) => "The difference depends on the message: the inputs are messages, under a fixed key.",
+            (Region::Key, Verdict::Leak) => "The difference depends on the key: the inputs are keys, under a fixed message.",
+            (Region::Message, _) => "The inputs are messages, under a fixed key.",
+            (Region::Key, _) => "The inputs are keys, under a fixed message.",
+        }
+    }
+}
+
+// How many measurements an attacker needs to tell two inputs apart, and how much of a risk that makes the leak
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Exploitability {
+    pub confidence: f64,   // The confidence the attacker wants, in percent
+    pub measurements: u64, // Of both inputs together
+    pub risk: Risk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Risk {
+    Critical, // At most a thousand measurements
+    High,     // At most a hundred thousand
+    Medium,   // At most ten million
+    Low,
+}
+
+impl Display for Risk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Risk::Critical => "critical",
+            Risk::High => "high",
+            Risk::Medium => "medium",
+            Risk::Low => "low",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// How urgently a leak should be looked at, most severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Critical, // A large difference that shows in most executions
+    High,
+    Medium,
+    Low, // A small difference that only shows in some executions
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Critical => "critical",
+            Severity::High => "high",
+            Severity::Medium => "medium",
+            Severity::Low => "low",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Estimated difference: {:.2} per execution (95% confidence interval {:.2} to {:.2}), {:.4}% of the larger mean",
+            self.difference, self.lower, self.upper, self.relative
+        )?;
+        if let Some(cohens_d) = self.cohens_d {
+            write!(f, ", effect size (Cohen's d) {:.3}", cohens_d)?;
+        }
+        if let Some(exploitability) = self.exploitability {
+            write!(
+                f,
+                "\nAn attacker needs about {} measurements to tell the inputs apart with {}% confidence: {} risk",
+                exploitability.measurements, exploitability.confidence, exploitability.risk
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// A generation of the ancestry of an input pair. Individuals are known by a hash of their inputs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LineageStep {
+    pub generation: u64,
+    pub individual: String,
+    pub parents: Vec<String>,   // Empty for random individuals, the first is the one followed back
+    pub operators: Vec<String>, // Such as "crossover" and the mutators applied, in order
+}
+
+// The measurements behind a result, used to draw charts
+#[derive(Debug, Clone, Default)]
+pub struct Evidence {
+    pub t_history: Vec<(usize, f64)>, // (samples, t-value) after each round of sampling
+    pub samples_1: Vec<f64>,
+    pub samples_2: Vec<f64>,
+    pub input_segments: Vec<usize>,
+}
+
+// Writes progress and results in the requested format
+#[derive(Debug, Clone)]
+pub struct Reporter {
+    format: Format,
+    output: Option<String>,
+    target: String,       // The file that was fuzzed or checked
+    html: Option<String>, // Where to write an HTML report
+    quiet: bool,
+    wasm_features: Vec<String>, // Added to every report
+    warnings: Vec<String>,      // Added to every report
+    target_meta: Option<TargetMeta>, // Added to every report
+    module_sha256: Option<String>,   // Added to every report
+    region: Option<Region>,          // Added to every report
+    tui: Option<Arc<Mutex<Dashboard>>>, // Shared between clones, so that they all draw the same dashboard
+    metrics: Option<Arc<Mutex<Metrics>>>, // Served to Prometheus, also shared between clones
+    ci: Option<Arc<Mutex<CiStatus>>>,     // Shared between clones too, so they print one line per interval between them
+}
+
+impl Reporter {
+    pub fn new(format: Format, output: Option<String>) -> Self {
+        Reporter {
+            format,
+            output,
+            target: "target.wasm".to_string(),
+            html: None,
+            quiet: false,
+            wasm_features: Vec::new(),
+            warnings: Vec::new(),
+            target_meta: None,
+            module_sha256: None,
+            region: None,
+            tui: None,
+            metrics: None,
+            ci: None,
+        }
+    }
+
+    // Show a live dashboard of statistics instead of printing progress messages line by line
+    pub fn set_tui(&mut self, tui: bool) {
+        self.tui = if tui {
+            Some(Arc::new(Mutex::new(Dashboard::new())))
+        } else {
+            None
+        };
+    }
+
+    // Print a status line at most once every `interval` instead of progress messages, to keep CI logs short
+    pub fn set_ci(&mut self, interval: Duration) {
+        self.ci = Some(Arc::new(Mutex::new(CiStatus::new(interval))));
+    }
+
+    // Serve the statistics of the run as Prometheus metrics at `addr`, such as "0.0.0.0:9100"
+    pub fn set_metrics_addr(&mut self, addr: &str) -> Result<(), SideFuzzError> {
+        self.metrics = Some(crate::metrics::serve(addr)?);
+        Ok(())
+    }
+
+    // Update the statistics shown on the dashboard and served as metrics, if there are any
+    pub fn stats(&self, stats: &Stats) {
+        if let Some(tui) = &self.tui {
+            // Ok to unwrap, the lock is never held while anything can panic.
+            tui.lock().unwrap().update(stats);
+        }
+        if let Some(metrics) = &self.metrics {
+            // Ok to unwrap, the lock is never held while anything can panic.
+            metrics.lock().unwrap().update(stats);
+        }
+        if let Some(ci) = &self.ci {
+            // Ok to unwrap, the lock is never held while anything can panic.
+            let line = ci.lock().unwrap().update(stats);
+            if let Some(line) = line {
+                self.print(line);
+            }
+        }
+    }
+
+    // Don't print progress messages
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    // Name the file that was fuzzed or checked in reports
+    pub fn set_target(&mut self, target: String) {
+        self.target = target;
+    }
+
+    // Record the wasm proposals the target was loaded with in reports
+    pub fn set_wasm_features(&mut self, features: Vec<String>) {
+        self.wasm_features = features;
+    }
+
+    // Warn about the target in reports, and in the text output
+    pub fn set_warnings(&mut self, warnings: Vec<String>) {
+        self.warnings = warnings;
+    }
+
+    pub fn add_warnings(&mut self, warnings: Vec<String>) {
+        self.warnings.extend(warnings);
+    }
+
+    // Label reports with the metadata embedded in the target
+    pub fn set_target_meta(&mut self, meta: TargetMeta) {
+        self.target_meta = Some(meta);
+    }
+
+    // Say in reports whether the inputs are messages or keys
+    pub fn set_region(&mut self, region: Region) {
+        self.region = Some(region);
+    }
+
+    // Record the SHA-256 of the target module, the raw bytes of the file, in reports
+    pub fn set_module(&mut self, module: &[u8]) {
+        self.module_sha256 = Some(crate::archive::sha256(module));
+    }
+
+    // Also write an HTML report of the result to `path`
+    pub fn set_html(&mut self, path: String) {
+        self.html = Some(path);
+    }
+
+    // Whether the result needs `Evidence`
+    pub fn wants_evidence(&self) -> bool {
+        self.html.is_some()
+    }
+
+    // Print a progress message, unless status lines are printed for CI instead.
+    // When the report is machine-readable these go to stderr, so that stdout only contains the report.
+    pub fn progress<D: Display>(&self, message: D) {
+        if self.quiet || self.ci.is_some() {
+            return;
+        }
+        if let Some(tui) = &self.tui {
+            tui.lock().unwrap().log(message.to_string());
+            return;
+        }
+        self.print(message);
+    }
+
+    // Print a line of progress, which goes to stderr as well when the report is machine-readable
+    fn print<D: Display>(&self, message: D) {
+        match self.format {
+            Format::Text => println!("{}", message),
+            _ => eprintln!("{}", message),
+        }
+    }
+
+    // Write the final report, `text` is the human readable version
+    pub fn finish(&self, report: &Report, text: &str) -> Result<(), SideFuzzError> {
+        let mut report = report.clone();
+        report.wasm_features = self.wasm_features.clone();
+        report.warnings = self.warnings.clone();
+        report.target_meta = self.target_meta.clone();
+        report.module_sha256 = self.module_sha256.clone();
+        report.region = self.region;
+        report.severity = crate::severity::classify(&report);
+        let report = &report;
+
+        if let Some(path) = &self.html {
+            let html = crate::html::render(report, &self.target);
+            std::fs::write(path, html).map_err(SideFuzzError::CouldNotWriteFile)?;
+        }
+
+        let rendered = match self.format {
+            Format::Text => {
+                let mut text = text.to_string();
+                if let Some(severity) = crate::severity::describe(report) {
+                    text += &format!("\n{}", severity);
+                }
+                if let Some(region) = report.region {
+                    text += &format!("\n{}", region.describe(report.verdict));
+                }
+                if let Some(meta) = &report.target_meta {
+                    text += &format!(
+                        "\nTarget: {}, built with sidefuzz {}",
+                        meta.label(),
+                        meta.sidefuzz
+                    );
+                }
+                if let Some(hash) = &report.module_sha256 {
+                    text += &format!("\nModule SHA-256: {}", hash);
+                }
+                for warning in report.warnings.iter() {
+                    text += &format!("\nWarning: {}", warning);
+                }
+                text
+            }
+            // Ok to expect, a Report always serializes.
+            Format::Json => {
+                serde_json::to_string_pretty(report).expect("Could not serialize report")
+            }
+            Format::Sarif => {
+                let sarif = crate::sarif::render(report, &self.target);
+                serde_json::to_string_pretty(&sarif).expect("Could not serialize report")
+            }
+        };
+
+        self.write(rendered)
+    }
+
+    // Write a result that isn't a single report, such as the results of a batch.
+    // Anything other than text is written as JSON.
+    pub fn finish_value<S: Serialize>(&self, value: &S, text: &str) -> Result<(), SideFuzzError> {
+        let rendered = match self.format {
+            Format::Text => text.to_string(),
+            // Ok to expect, results always serialize.
+            _ => serde_json::to_string_pretty(value).expect("Could not serialize report"),
+        };
+        self.write(rendered)
+    }
+
+    fn write(&self, rendered: String) -> Result<(), SideFuzzError> {
+        match &self.output {
+            Some(path) => {
+                std::fs::write(path, rendered + "\n").map_err(SideFuzzError::CouldNotWriteFile)
+            }
+            None => {
+                println!("{}", rendered);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Default for Reporter {
+    fn default() -> Self {
+        Reporter::new(Format::Text, None)
+    }
+}
@@ -0,0 +1,174 @@
+// Reproducers for leaks, as Rust tests that can be added to the target crate
+//
+// The test loads the built target and asserts that both inputs execute the same number of instructions through
+// `SideFuzz::count_instructions`, so it fails until the leak is fixed and fails again if it comes back. Targets fuzzed
+// with `--entry`, a key or `--fuzz-key` are counted the same way through `SideFuzz::count_instructions_with`. Targets
+// are built for wasm, so the test only runs on the host, from the crate root where `cargo test` runs.
+
+use crate::api::CountOptions;
+use crate::errors::SideFuzzError;
+
+// Bytes per line of the input literals
+const BYTES_PER_LINE: usize = 12;
+
+// Write a test reproducing the leak between `first` and `second` to `path`
+pub(crate) fn save(
+    path: &str,
+    wasm: &str,
+    options: &CountOptions,
+    first: &[u8],
+    second: &[u8],
+) -> Result<(), SideFuzzError> {
+    std::fs::write(path, rust_test(wasm, options, first, second))
+        .map_err(SideFuzzError::CouldNotWriteFile)
+}
+
+// A #[test] asserting that the target at `wasm`, run with `options`, takes as long for `first` as for `second`
+pub(crate) fn rust_test(wasm: &str, options: &CountOptions, first: &[u8], second: &[u8]) -> String {
+    let (options, count) = if *options == CountOptions::default() {
+        (String::new(), "count_instructions(&wasm, {})")
+    } else {
+        (
+            options_literal(options),
+            "count_instructions_with(&wasm, {}, &options)",
+        )
+    };
+    format!(
+        "// Found by sidefuzz {version}. Build the target with `sidefuzz build` before running this test.
+#[cfg(not(target_arch = \"wasm32\"))]
+#[test]
+fn no_timing_leak_{name}() {{
+    let wasm = std::fs::read({wasm:?}).expect(\"the target should be built\");
+{options}    let first: &[u8] = &[{first}];
+    let second: &[u8] = &[{second}];
+    assert_eq!(
+        sidefuzz::SideFuzz::{count_first}.unwrap(),
+        sidefuzz::SideFuzz::{count_second}.unwrap(),
+        \"the inputs execute a different number of instructions\"
+    );
+}}
+",
+        version = crate::VERSION,
+        name = hex::encode(&first[..first.len().min(4)]),
+        wasm = wasm,
+        options = options,
+        first = byte_literal(first, 4),
+        second = byte_literal(second, 4),
+        count_first = count.replace("{}", "first"),
+        count_second = count.replace("{}", "second"),
+    )
+}
+
+// A statement binding `options` to the options the target was fuzzed with. A key read from an environment variable
+// is read from it again when the test runs, so it doesn't end up in the test suite.
+fn options_literal(options: &CountOptions) -> String {
+    let mut fields = Vec::new();
+    if let Some(entry) = &options.entry {
+        fields.push(format!("entry: Some({:?}.to_string()),", entry));
+    }
+    if let Some(key_env) = &options.key_env {
+        fields.push(format!("key_env: Some({:?}.to_string()),", key_env));
+    } else if let Some(key) = &options.key {
+        fields.push(format!("key: Some(vec![{}]),", byte_literal(key, 8)));
+    }
+    if options.fuzz_key {
+        fields.push("fuzz_key: true,".to_string());
+    }
+    if let Some(message) = &options.message {
+        fields.push(format!(
+            "message: Some(vec![{}]),",
+            byte_literal(message, 8)
+        ));
+    }
+    format!(
+        "    let options = sidefuzz::CountOptions {{\n        {}\n        ..Default::default()\n    }};\n",
+        fields.join("\n        ")
+    )
+}
+
+// The contents of a byte slice literal on a line indented by `indent`, wrapped so long inputs stay readable
+fn byte_literal(bytes: &[u8], indent: usize) -> String {
+    let lines: Vec<String> = bytes
+        .chunks(BYTES_PER_LINE)
+        .map(|line| {
+            line.iter()
+                .map(|byte| format!("0x{:02x}", byte))
+                .collect::<Vec<String>>()
+                .join(", ")
+        })
+        .collect();
+    if lines.len() < 2 {
+        return lines.concat();
+    }
+    let inner = " ".repeat(indent + 4);
+    format!(
+        "\n{}{},\n{}",
+        inner,
+        lines.join(&format!(",\n{}", inner)),
+        " ".repeat(indent)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reproducer_test() {
+        let test = rust_test(
+            "my_target.wasm",
+            &CountOptions::default(),
+            &[0xde, 0xad, 0xbe, 0xef, 0x00],
+            &[0; 13],
+        );
+        assert!(test.contains("fn no_timing_leak_deadbeef() {"));
+        assert!(test.contains("std::fs::read(\"my_target.wasm\")"));
+        assert!(test.contains("let first: &[u8] = &[0xde, 0xad, 0xbe, 0xef, 0x00];"));
+        assert!(test.contains(&format!(
+            "&[\n        {}\n        0x00,\n    ];",
+            "0x00, ".repeat(12).trim_end()
+        )));
+        assert!(test.contains("sidefuzz::SideFuzz::count_instructions(&wasm, first).unwrap(),"));
+        assert!(!test.contains("options"));
+        assert_eq!(byte_literal(&[], 4), "");
+    }
+
+    #[test]
+    fn reproducer_options_test() {
+        let options = CountOptions {
+            entry: Some("fuzz_sign".to_string()),
+            key: Some(vec![0x42; 13]),
+            fuzz_key: true,
+            message: Some(vec![0x01, 0x02]),
+            ..CountOptions::default()
+        };
+        let test = rust_test("my_target.wasm", &options, &[0x01], &[0x02]);
+        assert!(test.contains(&format!(
+            "    let options = sidefuzz::CountOptions {{
+        entry: Some(\"fuzz_sign\".to_string()),
+        key: Some(vec![
+            {}
+            0x42,
+        ]),
+        fuzz_key: true,
+        message: Some(vec![0x01, 0x02]),
+        ..Default::default()
+    }};
+    let first: &[u8] = &[0x01];",
+            "0x42, ".repeat(12).trim_end()
+        )));
+        assert!(test.contains(
+            "sidefuzz::SideFuzz::count_instructions_with(&wasm, second, &options).unwrap(),"
+        ));
+
+        // Keys from the environment are read from it again instead of being written into the test
+        let options = CountOptions {
+            key: Some(vec![0x42; 32]),
+            key_env: Some("SIGNING_KEY".to_string()),
+            ..CountOptions::default()
+        };
+        let test = rust_test("my_target.wasm", &options, &[0x01], &[0x02]);
+        assert!(test.contains("key_env: Some(\"SIGNING_KEY\".to_string()),"));
+        assert!(!test.contains("0x42"));
+    }
+}
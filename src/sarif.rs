@@ -0,0 +1,169 @@
+// SARIF reports, for code scanning dashboards such as GitHub code scanning
+//
+// See https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html
+
+use crate::report::{Report, Verdict};
+use serde_json::{json, Value};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const RULE_ID: &str = "timing-leak";
+
+// Render a report for `target` (the file that was fuzzed or checked)
+pub(crate) fn render(report: &Report, target: &str) -> Value {
+    let results = match report.verdict {
+        Verdict::Leak => vec![leak(report, target)],
+        Verdict::NoLeak => Vec::new(),
+        Verdict::Inconclusive => vec![inconclusive(report, target)],
+    };
+
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "sidefuzz",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/phayes/sidefuzz",
+                    "rules": [{
+                        "id": RULE_ID,
+                        "name": "TimingLeak",
+                        "shortDescription": {
+                            "text": "Execution time depends on the input"
+                        },
+                        "fullDescription": {
+                            "text": "Two inputs run a statistically significant different number of instructions, which may leak secrets through a timing side-channel."
+                        },
+                        "defaultConfiguration": {
+                            "level": "error"
+                        }
+                    }]
+                }
+            },
+            "results": results
+        }]
+    })
+}
+
+fn leak(report: &Report, target: &str) -> Value {
+    let mut location = json!({
+        "physicalLocation": {
+            "artifactLocation": {
+                "uri": target
+            }
+        }
+    });
+
+    // Point at the function the difference comes from, when the leak was localized
+    let mut message = format!(
+        "Found timing difference of {} instructions between inputs {} and {} with {}% confidence",
+        report.difference, report.input_1, report.input_2, report.confidence
+    );
+    if let Some(function) = report.functions.iter().find(|f| f.delta() != 0) {
+        let name = match &function.name {
+            Some(name) => name.clone(),
+            None => format!("function {}", function.func),
+        };
+        message += &format!(
+            ", {} instructions of it in {}",
+            function.delta().abs(),
+            name
+        );
+        location["logicalLocations"] = json!([{
+            "name": name,
+            "index": function.func,
+            "kind": "function"
+        }]);
+    }
+
+    json!({
+        "ruleId": RULE_ID,
+        "level": "error",
+        "message": {
+            "text": message
+        },
+        "locations": [location],
+        "properties": {
+            "input_1": report.input_1,
+            "input_2": report.input_2,
+            "instructions_1": report.instructions_1,
+            "instructions_2": report.instructions_2,
+            "t_value": report.t_value,
+            "confidence": report.confidence,
+            "samples": report.samples,
+            "exploitability": report.effect.and_then(|effect| effect.exploitability),
+            "same_output": report.same_output,
+            "wasm_features": report.wasm_features,
+            "warnings": report.warnings,
+            "target_meta": report.target_meta,
+            "module_sha256": report.module_sha256,
+            "region": report.region,
+            "severity": report.severity
+        }
+    })
+}
+
+// A warning that the target could not be shown to be constant time
+fn inconclusive(report: &Report, target: &str) -> Value {
+    let message = format!(
+        "Inconclusive after {} samples: t-value {} between inputs {} and {}",
+        report.samples, report.t_value, report.input_1, report.input_2
+    );
+    json!({
+        "ruleId": RULE_ID,
+        "level": "warning",
+        "message": {
+            "text": message
+        },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": {
+                    "uri": target
+                }
+            }
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracing::FunctionDelta;
+
+    #[test]
+    fn sarif_test() {
+        let mut report = Report {
+            command: "check",
+            verdict: Verdict::Leak,
+            input_1: "00".to_string(),
+            input_2: "ff".to_string(),
+            instructions_1: 10.0,
+            instructions_2: 30.0,
+            difference: 20.0,
+            t_value: 10.0,
+            confidence: 100.0,
+            samples: 20_000,
+            functions: vec![FunctionDelta {
+                func: 3,
+                name: Some("compare".to_string()),
+                instructions_1: 5,
+                instructions_2: 25,
+            }],
+            ..Default::default()
+        };
+
+        let sarif = render(&report, "target.wasm");
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], RULE_ID);
+        let location = &result["locations"][0];
+        assert_eq!(
+            location["physicalLocation"]["artifactLocation"]["uri"],
+            "target.wasm"
+        );
+        assert_eq!(location["logicalLocations"][0]["name"], "compare");
+
+        report.verdict = Verdict::NoLeak;
+        let sarif = render(&report, "target.wasm");
+        assert_eq!(sarif["runs"][0]["results"], json!([]));
+    }
+}
@@ -0,0 +1,120 @@
+// Score logs, a CSV file with the scores of every generation of a fuzzing run
+//
+// The log has a row for each generation, with the best and average score of the population that generation was
+// bred from. Plotting it shows how quickly the optimizer converges, for tuning the optimizer's settings.
+
+use crate::errors::SideFuzzError;
+use crate::util::ScoredInputPair;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+pub(crate) struct ScoreLog {
+    out: Option<BufWriter<File>>, // None once writing has failed
+}
+
+// The scores of one generation
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GenerationScores {
+    pub best: f64,
+    pub average: f64, // Of the individuals that didn't crash, or 0 if they all did
+    pub crashes: usize,
+}
+
+impl GenerationScores {
+    pub fn new(scored: &[ScoredInputPair]) -> Self {
+        let finite: Vec<f64> = scored
+            .iter()
+            .map(|scored| scored.score)
+            .filter(|score| score.is_finite())
+            .collect();
+        let best = finite.iter().cloned().fold(0.0, f64::max);
+        let average = if finite.is_empty() {
+            0.0
+        } else {
+            finite.iter().sum::<f64>() / finite.len() as f64
+        };
+        GenerationScores {
+            best,
+            average,
+            crashes: scored.len() - finite.len(),
+        }
+    }
+}
+
+impl ScoreLog {
+    pub fn create(path: &str) -> Result<Self, SideFuzzError> {
+        let file = File::create(path).map_err(SideFuzzError::CouldNotWriteFile)?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "generation,executions,best,average,crashes")
+            .map_err(SideFuzzError::CouldNotWriteFile)?;
+        Ok(ScoreLog { out: Some(out) })
+    }
+
+    // Failing to log doesn't stop the run, the log just ends there
+    pub fn record(&mut self, generation: u64, executions: u64, scores: &GenerationScores) {
+        let line = format!(
+            "{},{},{},{},{}",
+            generation, executions, scores.best, scores.average, scores.crashes
+        );
+        let result = match self.out.as_mut() {
+            Some(out) => writeln!(out, "{}", line),
+            None => return,
+        };
+        self.check(result);
+    }
+
+    // Write out what has been logged so far, so an interrupted run still leaves a usable log
+    pub fn flush(&mut self) {
+        let result = match self.out.as_mut() {
+            Some(out) => out.flush(),
+            None => return,
+        };
+        self.check(result);
+    }
+
+    fn check(&mut self, result: std::io::Result<()>) {
+        if let Err(err) = result {
+            log::warn!("stopped writing the score log: {}", err);
+            self.out = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::InputPair;
+
+    #[test]
+    fn score_log_test() {
+        let scored: Vec<ScoredInputPair> = [30.0, 10.0, 2.0, std::f64::NEG_INFINITY]
+            .iter()
+            .map(|score| ScoredInputPair {
+                score: *score,
+                highest: 0.0,
+                lowest: 0.0,
+                pair: InputPair::default(),
+            })
+            .collect();
+        let scores = GenerationScores::new(&scored);
+        assert_eq!(
+            scores,
+            GenerationScores {
+                best: 30.0,
+                average: 14.0,
+                crashes: 1
+            }
+        );
+
+        let path = std::env::temp_dir().join(format!("sidefuzz-scores-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut log = ScoreLog::create(path).unwrap();
+        log.record(1, 8, &scores);
+        log.flush();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "generation,executions,best,average,crashes\n1,8,30,14,1\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
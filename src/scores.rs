@@ -0,0 +1,367 @@
+// Fitness functions used by the optimizer to score input pairs
+
+use crate::errors::SideFuzzError;
+use crate::target::Target;
+use crate::tracing::{ExecutionTrace, TraceKind};
+use crate::util::{InputPair, ScoredInputPair};
+use crate::wasm::{WasmModule, WasmOptions};
+use std::fmt;
+use std::sync::Arc;
+
+// Entries of traces compared by the edit distance, after their common prefix and suffix.
+// Comparing is quadratic in this, entries past it are counted as inserted instead.
+const MAX_EDIT_LEN: usize = 2048;
+
+/// What was observed of one execution of the target.
+pub struct Observation {
+    /// Instructions executed, as the target counts them
+    pub instructions: u64,
+    /// The trace asked for by [`Score::trace`], empty when no trace is needed
+    pub trace: ExecutionTrace,
+}
+
+/// A way of scoring an input pair, from what was observed of executing each of its inputs.
+///
+/// The optimizer breeds the pairs with the highest scores, so scores should be higher the more the two executions
+/// differ in ways an attacker could measure.
+pub trait Score: Send + Sync {
+    /// A short name for the score, as used on the command line
+    fn name(&self) -> &str;
+
+    /// What the score needs traced, in addition to instruction counts. Only wasm targets can be traced.
+    fn trace(&self) -> Option<TraceKind> {
+        None
+    }
+
+    fn score(&self, first: &Observation, second: &Observation) -> f64;
+}
+
+/// The difference in instructions executed. This is the default.
+pub struct InstructionScore;
+
+impl Score for InstructionScore {
+    fn name(&self) -> &str {
+        "instructions"
+    }
+
+    fn score(&self, first: &Observation, second: &Observation) -> f64 {
+        (first.instructions as f64 - second.instructions as f64).abs()
+    }
+}
+
+/// The edit distance between the sequences of linear-memory addresses accessed, for leaks through caches
+pub struct MemoryScore;
+
+impl Score for MemoryScore {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn trace(&self) -> Option<TraceKind> {
+        Some(TraceKind::Memory)
+    }
+
+    fn score(&self, first: &Observation, second: &Observation) -> f64 {
+        edit_distance(&first.trace.memory, &second.trace.memory) as f64
+    }
+}
+
+/// The edit distance between the sequences of basic blocks entered, for leaks through branch predictors
+pub struct BranchScore;
+
+impl Score for BranchScore {
+    fn name(&self) -> &str {
+        "branches"
+    }
+
+    fn trace(&self) -> Option<TraceKind> {
+        Some(TraceKind::Branches)
+    }
+
+    fn score(&self, first: &Observation, second: &Observation) -> f64 {
+        edit_distance(&first.trace.blocks, &second.trace.blocks) as f64
+    }
+}
+
+// The number of insertions, deletions and substitutions turning `a` into `b`.
+// Traces of two inputs usually differ in one place, so their common prefix and suffix are skipped. Past the first
+// `MAX_EDIT_LEN` entries of what remains, the longer one's entries are counted as inserted, which never gives less
+// than the real distance.
+pub(crate) fn edit_distance<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    let prefix = a.iter().zip(b.iter()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+
+    let (a_len, b_len) = (a.len().min(MAX_EDIT_LEN), b.len().min(MAX_EDIT_LEN));
+    let skipped = (a.len() - a_len).max(b.len() - b_len);
+    let (a, b) = (&a[..a_len], &b[..b_len]);
+
+    // One row of the table at a time, distances from a prefix of `a` to every prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substituted = diagonal + (a != b) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()] + skipped
+}
+
+fn builtin(name: &str) -> Result<Arc<dyn Score>, SideFuzzError> {
+    match name {
+        "instructions" => Ok(Arc::new(InstructionScore)),
+        "memory" => Ok(Arc::new(MemoryScore)),
+        "branches" => Ok(Arc::new(BranchScore)),
+        _ => Err(SideFuzzError::BadScore(format!("unknown score '{}'", name))),
+    }
+}
+
+/// The scores input pairs are scored with, each with a weight. A pair's score is the weighted sum of its scores.
+///
+/// By default only [`InstructionScore`] is used.
+#[derive(Clone)]
+pub struct Scores {
+    scores: Vec<(f64, Arc<dyn Score>)>,
+}
+
+impl Scores {
+    /// No scores, add some with [`Scores::add`]
+    pub fn empty() -> Self {
+        Scores { scores: Vec::new() }
+    }
+
+    /// Use `score` with the given weight
+    pub fn add<S: Score + 'static>(&mut self, weight: f64, score: S) {
+        self.scores.push((weight, Arc::new(score)));
+    }
+
+    /// Parse a list of built-in scores with optional weights, such as "instructions,memory=0.5"
+    pub fn parse(arg: &str) -> Result<Self, SideFuzzError> {
+        let mut scores = Self::empty();
+        for part in arg
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let mut parts = part.splitn(2, '=');
+            // Ok to unwrap, split always returns at least one part
+            let name = parts.next().unwrap();
+            let weight = match parts.next() {
+                Some(weight) => weight.parse().map_err(|_| {
+                    SideFuzzError::BadScore(format!("weight of '{}' must be a number", name))
+                })?,
+                None => 1.0,
+            };
+            scores.scores.push((weight, builtin(name)?));
+        }
+        if scores.scores.is_empty() {
+            return Err(SideFuzzError::BadScore(
+                "at least one score is needed".to_string(),
+            ));
+        }
+        scores.trace()?;
+        Ok(scores)
+    }
+
+    // The trace the scores need. A module can only be instrumented for one kind of trace at a time.
+    fn trace(&self) -> Result<Option<TraceKind>, SideFuzzError> {
+        let mut trace = None;
+        for (_, score) in self.scores.iter() {
+            match (trace, score.trace()) {
+                (Some(kind), Some(other)) if kind != other => {
+                    return Err(SideFuzzError::BadScore(format!(
+                        "'{}' needs a different trace than the scores before it",
+                        score.name()
+                    )))
+                }
+                (None, other) => trace = other,
+                _ => {}
+            }
+        }
+        Ok(trace)
+    }
+
+    /// The weighted sum of every score of two executions
+    pub fn score(&self, first: &Observation, second: &Observation) -> f64 {
+        self.scores
+            .iter()
+            .map(|(weight, score)| weight * score.score(first, second))
+            .sum()
+    }
+}
+
+impl Default for Scores {
+    fn default() -> Self {
+        let mut scores = Self::empty();
+        scores.add(1.0, InstructionScore);
+        scores
+    }
+}
+
+impl fmt::Debug for Scores {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.scores
+                    .iter()
+                    .map(|(weight, score)| format!("{}={}", score.name(), weight)),
+            )
+            .finish()
+    }
+}
+
+// Scores input pairs from the instruction counts of their inputs, tracing them first if the scores need it
+pub(crate) struct Scorer {
+    scores: Scores,
+    wasm: Option<(Vec<u8>, WasmOptions)>, // The target, instrumented for the trace the scores need
+    traced: Option<WasmModule>,
+}
+
+impl Scorer {
+    // Only wasm targets can be traced
+    pub fn new(target: &dyn Target, scores: Scores) -> Result<Self, SideFuzzError> {
+        let wasm = match scores.trace()? {
+            Some(kind) => {
+                let (bytes, mut options) = target.wasm().ok_or(SideFuzzError::ScoreUnsupported)?;
+                options.trace = Some(kind);
+                Some((bytes, options))
+            }
+            None => None,
+        };
+        Self::with_wasm(scores, wasm)
+    }
+
+    fn with_wasm(
+        scores: Scores,
+        wasm: Option<(Vec<u8>, WasmOptions)>,
+    ) -> Result<Self, SideFuzzError> {
+        let traced = match &wasm {
+            Some((bytes, options)) => {
+                Some(WasmModule::with_options(bytes.clone(), options.clone())?)
+            }
+            None => None,
+        };
+        Ok(Scorer {
+            scores,
+            wasm,
+            traced,
+        })
+    }
+
+    // Another scorer with its own instance of the traced module, for another thread
+    pub fn try_clone(&self) -> Result<Self, SideFuzzError> {
+        Self::with_wasm(self.scores.clone(), self.wasm.clone())
+    }
+
+    fn observe(&mut self, input: &[u8], instructions: u64) -> Observation {
+        // The uninstrumented target ran the input fine, so this only fails if instrumenting pushed it over a limit.
+        // The pair is scored on an empty trace then, rather than counted as a crash triage wouldn't reproduce.
+        let trace = match &mut self.traced {
+            Some(module) => module.trace(input).unwrap_or_default(),
+            None => ExecutionTrace::default(),
+        };
+        Observation {
+            instructions,
+            trace,
+        }
+    }
+
+    // Score a pair from the instruction counts of its inputs, None if that input crashed
+    pub fn scored(
+        &mut self,
+        pair: InputPair,
+        first_instructions: Option<u64>,
+        second_instructions: Option<u64>,
+    ) -> ScoredInputPair {
+        let mut scored = ScoredInputPair::from_counts(
+            pair.first,
+            pair.second,
+            pair.is_str,
+            first_instructions,
+            second_instructions,
+        );
+        if let (Some(first_instructions), Some(second_instructions)) =
+            (first_instructions, second_instructions)
+        {
+            let first = self.observe(&scored.pair.first, first_instructions);
+            let second = self.observe(&scored.pair.second, second_instructions);
+            scored.score = self.scores.score(&first, &second);
+        }
+        scored
+    }
+
+    // Run both inputs of a pair and score it. The second input isn't run if the first one crashed.
+    pub fn generate(
+        &mut self,
+        module: &mut dyn Target,
+        first: Vec<u8>,
+        second: Vec<u8>,
+        is_str: bool,
+    ) -> ScoredInputPair {
+        let first_instructions = module.count_instructions(&first).ok();
+        let second_instructions = match first_instructions {
+            Some(_) => module.count_instructions(&second).ok(),
+            None => None,
+        };
+        let pair = InputPair {
+            first,
+            second,
+            is_str,
+        };
+        self.scored(pair, first_instructions, second_instructions)
+    }
+}
+
+impl Default for Scorer {
+    fn default() -> Self {
+        Scorer {
+            scores: Scores::default(),
+            wasm: None,
+            traced: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_test() {
+        let scores = Scores::parse("instructions, memory=0.5").unwrap();
+        assert_eq!(
+            format!("{:?}", scores),
+            "[\"instructions=1\", \"memory=0.5\"]"
+        );
+        assert!(Scores::parse("time").is_err());
+        assert!(Scores::parse("memory=often").is_err());
+        assert!(Scores::parse("memory,branches").is_err());
+        assert!(Scores::parse("").is_err());
+
+        assert_eq!(edit_distance(b"kitten", b"sitting"), 3);
+        assert_eq!(edit_distance(b"same", b"same"), 0);
+        assert_eq!(edit_distance(b"", b"abc"), 3);
+
+        let observe = |instructions, memory: &[u64]| {
+            let mut trace = ExecutionTrace::default();
+            trace.memory = memory.to_vec();
+            Observation {
+                instructions,
+                trace,
+            }
+        };
+        // 10 instructions apart, and two addresses substituted
+        let first = observe(100, &[8, 16, 24, 32]);
+        let second = observe(110, &[8, 40, 48, 32]);
+        assert_eq!(scores.score(&first, &second), 11.0);
+    }
+}
@@ -0,0 +1,189 @@
+// This file contains the "selftest" subcommand, which checks that a target's instruction counts are deterministic
+//
+// Statistics over millions of executions only mean something if the same input always executes the same number
+// of instructions. Each input is run several times on one instance, interleaved with the other inputs, and once
+// more on a freshly started instance. Counts that change mean the target depends on more than its input: state
+// left behind by earlier executions, such as memory read before it is written, caches or lazily set up statics.
+// Imports that return something different on every call in a real host, randomness and clocks, are listed too.
+// sidefuzz stubs them deterministically, so they don't change counts here, but the real target behaves differently.
+
+use crate::errors::SideFuzzError;
+use crate::target::{Target, TargetOptions};
+use crate::util::random_input;
+use rand::{rngs::StdRng, SeedableRng};
+
+pub struct SelfTest {
+    module: Box<dyn Target>,
+    inputs: Vec<Vec<u8>>,
+    repeats: usize,
+}
+
+impl SelfTest {
+    pub fn from_file_with_options(
+        filename: &str,
+        options: &TargetOptions,
+        input: Option<Vec<u8>>,
+    ) -> Result<Self, SideFuzzError> {
+        let module = crate::target::load(filename, options)?;
+        if let Some(input) = &input {
+            if input.len() != module.fuzz_len() {
+                return Err(SideFuzzError::InputsWrongSize(module.fuzz_len()));
+            }
+        }
+        Ok(SelfTest {
+            module,
+            inputs: input.into_iter().collect(),
+            repeats: 10,
+        })
+    }
+
+    // Also test `count` random inputs, generated from `seed`
+    pub fn add_random_inputs(&mut self, count: usize, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let len = self.module.fuzz_len();
+        let is_str = self.module.input_is_str();
+        for _ in 0..count {
+            self.inputs.push(random_input(&mut rng, len, is_str));
+        }
+    }
+
+    // How many times each input is run on the same instance
+    pub fn set_repeats(&mut self, repeats: usize) {
+        self.repeats = repeats.max(2);
+    }
+
+    // Exits with 1 if any input's count changed, and 0 otherwise
+    pub fn run(&mut self) -> Result<(), SideFuzzError> {
+        if self.inputs.is_empty() {
+            return Err(SideFuzzError::NoInputs);
+        }
+
+        // Rounds of every input in turn, so each run follows runs of other inputs
+        let mut repeated = vec![Vec::with_capacity(self.repeats); self.inputs.len()];
+        for _ in 0..self.repeats {
+            for (input, outcomes) in self.inputs.iter().zip(repeated.iter_mut()) {
+                outcomes.push(self.module.count_instructions(input).ok());
+            }
+        }
+        let mut fresh = Vec::with_capacity(self.inputs.len());
+        for input in self.inputs.iter() {
+            fresh.push(self.module.try_clone()?.count_instructions(input).ok());
+        }
+
+        let mut nondeterministic = 0;
+        for (index, input) in self.inputs.iter().enumerate() {
+            let outcomes = distinct(&repeated[index]);
+            let problem = if outcomes.len() > 1 {
+                Some(format!(
+                    "varies between runs: {}",
+                    outcomes
+                        .iter()
+                        .map(|outcome| describe(*outcome))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ))
+            } else if outcomes[0] != fresh[index] {
+                Some(format!(
+                    "{} on a freshly started instance, but {} once other inputs have run",
+                    describe(fresh[index]),
+                    describe(outcomes[0])
+                ))
+            } else {
+                None
+            };
+            match problem {
+                Some(problem) => {
+                    nondeterministic += 1;
+                    println!("input {} ({}): {}", index + 1, hex::encode(input), problem);
+                }
+                None => println!(
+                    "input {} ({}): {} every time",
+                    index + 1,
+                    hex::encode(input),
+                    describe(outcomes[0])
+                ),
+            }
+        }
+
+        let imports = match self.module.wasm() {
+            Some((bytes, _)) => nondeterministic_imports(&crate::inspect::inspect(&bytes)?.imports),
+            None => Vec::new(),
+        };
+        if !imports.is_empty() {
+            println!();
+            println!(
+                "The target imports {}, which a real host may answer differently on every call. \
+                 sidefuzz stubs them deterministically, so the target may behave differently outside of sidefuzz.",
+                imports.join(", ")
+            );
+        }
+
+        println!();
+        if nondeterministic > 0 {
+            println!(
+                "{} of {} inputs didn't always execute the same number of instructions. The target depends on state \
+                 left behind by earlier executions, such as memory read before it is written, and fuzzing it would \
+                 produce meaningless statistics.",
+                nondeterministic,
+                self.inputs.len()
+            );
+            std::process::exit(1);
+        } else {
+            println!(
+                "Every input executed the same number of instructions over {} runs and a restart.",
+                self.repeats
+            );
+            std::process::exit(0);
+        }
+    }
+}
+
+// The different outcomes of running one input, None where it crashed
+fn distinct(outcomes: &[Option<u64>]) -> Vec<Option<u64>> {
+    let mut distinct = outcomes.to_vec();
+    distinct.sort();
+    distinct.dedup();
+    distinct
+}
+
+fn describe(outcome: Option<u64>) -> String {
+    match outcome {
+        Some(count) => format!("{} instructions", count),
+        None => "a crash".to_string(),
+    }
+}
+
+// Imports, named module.name, that look like sources of randomness or time
+pub(crate) fn nondeterministic_imports(imports: &[String]) -> Vec<String> {
+    imports
+        .iter()
+        .filter(|import| {
+            crate::stubs::classify(import.rsplit('.').next().unwrap_or_default()).is_some()
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selftest_test() {
+        assert_eq!(
+            distinct(&[Some(10), None, Some(10), Some(12)]),
+            vec![None, Some(10), Some(12)]
+        );
+
+        let imports: Vec<String> = vec![
+            "wasi_snapshot_preview1.random_get".to_string(),
+            "wasi_snapshot_preview1.fd_write".to_string(),
+            "__wbindgen_placeholder__.__wbg_getRandomValues_3aa56aa6".to_string(),
+            "env.performance_now".to_string(),
+            "env.abort".to_string(),
+        ];
+        let flagged = nondeterministic_imports(&imports);
+        assert_eq!(flagged.len(), 3);
+        assert!(!flagged.contains(&"env.abort".to_string()));
+    }
+}
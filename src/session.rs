@@ -0,0 +1,415 @@
+// Session logs, which record every input a target was run with and what was measured
+//
+// Fuzzing runs execute millions of inputs, and most of them are repeats: dudect samples the same pair over and
+// over. The log keeps the last few distinct inputs, so a repeat is written as a one byte reference to one of
+// them. Records are buffered, and written out when the process exits, when a fuzzing run is interrupted and after
+// every batch of generations, so an interrupted run still leaves a usable log.
+//
+// The format is the magic bytes "SFZS", a version byte and a byte for what was measured, followed by records:
+//  - a tag byte, 0 for a new input followed by its length (as a LEB128 varint) and bytes, or the position
+//    (starting from 1) of one of the recent inputs
+//  - a varint of the measurement plus one, or 0 if the execution crashed
+
+use crate::errors::SideFuzzError;
+use crate::target::{Measure, Target};
+use crate::wasm::WasmOptions;
+use float_duration::FloatDuration;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex, Weak};
+
+const MAGIC: &[u8] = b"SFZS";
+const VERSION: u8 = 1;
+
+// How many distinct inputs repeats can refer to
+const RECENT: usize = 16;
+
+// Every session being recorded, so they can all be written out before the process exits
+static OPEN: Mutex<Vec<Weak<Mutex<SessionWriter>>>> = Mutex::new(Vec::new());
+
+// One execution of the target
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Execution {
+    pub input: Vec<u8>,
+    pub count: Option<u64>, // None if the execution crashed
+}
+
+// A whole session log
+#[derive(Debug, Clone)]
+pub(crate) struct Session {
+    pub measure: Measure,
+    pub executions: Vec<Execution>,
+}
+
+impl Session {
+    pub fn load(path: &str) -> Result<Self, SideFuzzError> {
+        let bytes = std::fs::read(path)?;
+        Self::decode(&bytes)
+    }
+
+    // A log that ends partway through a record was interrupted while it was written, the rest of it is kept
+    fn decode(bytes: &[u8]) -> Result<Self, SideFuzzError> {
+        if bytes.len() < MAGIC.len() + 2 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(SideFuzzError::BadSession(
+                "it is not a session log".to_string(),
+            ));
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(SideFuzzError::BadSession(format!(
+                "it is version {} of the format, only version {} is supported",
+                bytes[MAGIC.len()],
+                VERSION
+            )));
+        }
+        let measure = match bytes[MAGIC.len() + 1] {
+            0 => Measure::Instructions,
+            1 => Measure::Time,
+            other => {
+                return Err(SideFuzzError::BadSession(format!(
+                    "unknown measurement {}",
+                    other
+                )))
+            }
+        };
+
+        let mut reader = Reader {
+            bytes,
+            position: MAGIC.len() + 2,
+        };
+        let mut recent: Vec<Vec<u8>> = Vec::with_capacity(RECENT);
+        let mut next = 0;
+        let mut executions = Vec::new();
+        while let Some(execution) = reader.record(&mut recent, &mut next)? {
+            executions.push(execution);
+        }
+
+        Ok(Session {
+            measure,
+            executions,
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    // The next record, or None at the end of the log
+    fn record(
+        &mut self,
+        recent: &mut Vec<Vec<u8>>,
+        next: &mut usize,
+    ) -> Result<Option<Execution>, SideFuzzError> {
+        let tag = match self.byte() {
+            Some(tag) => tag as usize,
+            None => return Ok(None),
+        };
+        let input = if tag == 0 {
+            let len = match self.varint() {
+                Some(len) => len as usize,
+                None => return Ok(None),
+            };
+            if self.bytes.len() - self.position < len {
+                return Ok(None);
+            }
+            let input = self.bytes[self.position..self.position + len].to_vec();
+            self.position += len;
+            remember(recent, next, &input);
+            input
+        } else {
+            match recent.get(tag - 1) {
+                Some(input) => input.clone(),
+                None => {
+                    return Err(SideFuzzError::BadSession(format!(
+                        "a record refers to recent input {}, which doesn't exist",
+                        tag
+                    )))
+                }
+            }
+        };
+        let count = match self.varint() {
+            Some(0) => None,
+            Some(count) => Some(count - 1),
+            None => return Ok(None),
+        };
+        Ok(Some(Execution { input, count }))
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+// Store `input` as a recent input, replacing the oldest one once there are `RECENT` of them
+fn remember(recent: &mut Vec<Vec<u8>>, next: &mut usize, input: &[u8]) {
+    if recent.len() < RECENT {
+        recent.push(input.to_vec());
+    } else {
+        recent[*next] = input.to_vec();
+    }
+    *next = (*next + 1) % RECENT;
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// Writes a session log
+pub(crate) struct SessionWriter {
+    file: Option<BufWriter<File>>, // None once writing has failed
+    recent: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl SessionWriter {
+    pub fn create(path: &str, measure: Measure) -> Result<Self, SideFuzzError> {
+        let file = File::create(path).map_err(SideFuzzError::CouldNotWriteFile)?;
+        let mut file = BufWriter::new(file);
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.push(match measure {
+            Measure::Instructions => 0,
+            Measure::Time => 1,
+        });
+        file.write_all(&header)
+            .map_err(SideFuzzError::CouldNotWriteFile)?;
+        Ok(SessionWriter {
+            file: Some(file),
+            recent: Vec::with_capacity(RECENT),
+            next: 0,
+        })
+    }
+
+    // Failing to record doesn't stop the run, the log just ends there
+    pub fn record(&mut self, input: &[u8], count: Option<u64>) {
+        let mut record = Vec::with_capacity(input.len() + 8);
+        match self
+            .recent
+            .iter()
+            .position(|recent| recent.as_slice() == input)
+        {
+            Some(index) => record.push(index as u8 + 1),
+            None => {
+                record.push(0);
+                write_varint(&mut record, input.len() as u64);
+                record.extend_from_slice(input);
+                remember(&mut self.recent, &mut self.next, input);
+            }
+        }
+        write_varint(
+            &mut record,
+            count.map_or(0, |count| count.saturating_add(1)),
+        );
+
+        let result = match self.file.as_mut() {
+            Some(file) => file.write_all(&record),
+            None => return,
+        };
+        self.check(result);
+    }
+
+    // Write out what has been recorded so far
+    pub fn flush(&mut self) {
+        let result = match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => return,
+        };
+        self.check(result);
+    }
+
+    fn check(&mut self, result: std::io::Result<()>) {
+        if let Err(err) = result {
+            log::warn!("stopped recording the session: {}", err);
+            self.file = None;
+        }
+    }
+}
+
+// Write out every session being recorded. `std::process::exit` doesn't drop anything, so this also runs when the
+// process exits on platforms where that can be hooked.
+pub(crate) fn flush_all() {
+    // Ok to unwrap, recording never panics while holding the locks
+    let mut open = OPEN.lock().unwrap();
+    open.retain(|log| log.strong_count() > 0);
+    for log in open.iter().filter_map(Weak::upgrade) {
+        log.lock().unwrap().flush();
+    }
+}
+
+#[cfg(unix)]
+fn flush_at_exit() {
+    use std::sync::Once;
+    static REGISTERED: Once = Once::new();
+    extern "C" fn flush() {
+        flush_all();
+    }
+    REGISTERED.call_once(|| unsafe {
+        libc::atexit(flush);
+    });
+}
+
+#[cfg(not(unix))]
+fn flush_at_exit() {}
+
+// A target whose executions are all written to a session log. Clones write to the same log.
+pub(crate) struct RecordingTarget {
+    inner: Box<dyn Target>,
+    log: Arc<Mutex<SessionWriter>>,
+}
+
+impl RecordingTarget {
+    pub fn new(
+        inner: Box<dyn Target>,
+        path: &str,
+        measure: Measure,
+    ) -> Result<Self, SideFuzzError> {
+        let log = Arc::new(Mutex::new(SessionWriter::create(path, measure)?));
+        // Ok to unwrap, recording never panics while holding the lock
+        OPEN.lock().unwrap().push(Arc::downgrade(&log));
+        flush_at_exit();
+        Ok(RecordingTarget { inner, log })
+    }
+}
+
+impl Target for RecordingTarget {
+    fn count_instructions(&mut self, input: &[u8]) -> Result<u64, SideFuzzError> {
+        let result = self.inner.count_instructions(input);
+        // Ok to unwrap, recording never panics while holding the lock
+        self.log
+            .lock()
+            .unwrap()
+            .record(input, result.as_ref().ok().copied());
+        result
+    }
+
+    fn run_with_output(&mut self, input: &[u8]) -> Result<(u64, Option<Vec<u8>>), SideFuzzError> {
+        let result = self.inner.run_with_output(input);
+        // Ok to unwrap, recording never panics while holding the lock
+        self.log
+            .lock()
+            .unwrap()
+            .record(input, result.as_ref().ok().map(|(count, _)| *count));
+        result
+    }
+
+    fn count_batch(&mut self, inputs: &[&[u8]]) -> Vec<Result<u64, SideFuzzError>> {
+        let results = self.inner.count_batch(inputs);
+        // Ok to unwrap, recording never panics while holding the lock
+        let mut log = self.log.lock().unwrap();
+        for (input, result) in inputs.iter().zip(results.iter()) {
+            log.record(input, result.as_ref().ok().copied());
+        }
+        results
+    }
+
+    fn batch_capacity(&self) -> usize {
+        self.inner.batch_capacity()
+    }
+
+    // Estimating execution time runs throwaway inputs, which aren't part of the session
+    fn measure_time(&mut self) -> Result<FloatDuration, SideFuzzError> {
+        self.inner.measure_time()
+    }
+
+    fn fuzz_len(&self) -> usize {
+        self.inner.fuzz_len()
+    }
+
+    fn input_segments(&self) -> Vec<usize> {
+        self.inner.input_segments()
+    }
+
+    fn input_is_str(&self) -> bool {
+        self.inner.input_is_str()
+    }
+
+    fn public_segments(&self) -> Vec<bool> {
+        self.inner.public_segments()
+    }
+
+    fn variable_len(&self) -> bool {
+        self.inner.variable_len()
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.inner.set_seed(seed)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Target>, SideFuzzError> {
+        Ok(Box::new(RecordingTarget {
+            inner: self.inner.try_clone()?,
+            log: self.log.clone(),
+        }))
+    }
+
+    fn parallel(&self) -> bool {
+        self.inner.parallel()
+    }
+
+    fn wasm(&self) -> Option<(Vec<u8>, WasmOptions)> {
+        self.inner.wasm()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_test() {
+        let path = std::env::temp_dir().join(format!("sidefuzz-session-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut executions = Vec::new();
+        for i in 0..100u64 {
+            let input = vec![(i % 20) as u8; (i % 3) as usize * 200];
+            let count = if i % 7 == 0 { None } else { Some(i * 1000) };
+            executions.push(Execution { input, count });
+        }
+
+        let mut writer = SessionWriter::create(path, Measure::Time).unwrap();
+        for execution in executions.iter() {
+            writer.record(&execution.input, execution.count);
+        }
+        // Records are buffered until flushed
+        writer.flush();
+
+        let session = Session::load(path).unwrap();
+        assert_eq!(session.measure, Measure::Time);
+        assert_eq!(session.executions, executions);
+
+        // A log cut off partway through a record keeps every complete record
+        let mut bytes = std::fs::read(path).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        let session = Session::decode(&bytes).unwrap();
+        assert_eq!(session.executions, executions[..99].to_vec());
+
+        drop(writer);
+        std::fs::remove_file(path).unwrap();
+        assert!(Session::decode(b"not a log").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+// Severity of leaks, so triage can start with the ones most likely to be exploitable
+//
+// A leak is judged by two things: how many instructions the difference is (its magnitude), and how consistently the
+// inputs are told apart by it (its consistency). A difference that is the same in every execution is as consistent
+// as it gets, while counts that vary, such as when input classes only sometimes take a slow path, are judged by
+// Cohen's d, the difference in standard deviations. A large difference that shows every time is critical, a small
+// one that only shows now and then is low.
+
+use crate::report::{Report, Severity, Verdict};
+
+// Differences of at least this many instructions per execution are large, and of at least the second moderate
+const LARGE_DIFFERENCE: f64 = 100.0;
+const MODERATE_DIFFERENCE: f64 = 10.0;
+
+// Differences of at least this many standard deviations show in most executions, what Cohen calls a large effect
+const CONSISTENT_EFFECT: f64 = 0.8;
+
+// The severity of a difference of `difference` instructions per execution, with `cohens_d` None if the counts
+// never vary
+fn severity(difference: f64, cohens_d: Option<f64>) -> Severity {
+    let difference = difference.abs();
+    let magnitude = if difference >= LARGE_DIFFERENCE {
+        0
+    } else if difference >= MODERATE_DIFFERENCE {
+        1
+    } else {
+        2
+    };
+    let inconsistent = match cohens_d {
+        Some(cohens_d) => (cohens_d.abs() < CONSISTENT_EFFECT) as usize,
+        None => 0,
+    };
+    match magnitude + inconsistent {
+        0 => Severity::Critical,
+        1 => Severity::High,
+        2 => Severity::Medium,
+        _ => Severity::Low,
+    }
+}
+
+// The difference per execution of a report, and its Cohen's d. Reports without an estimated effect only have the
+// difference between single executions, which never vary in wasm targets.
+fn measured(report: &Report) -> (f64, Option<f64>) {
+    match &report.effect {
+        Some(effect) => (effect.difference, effect.cohens_d),
+        None => (report.difference, None),
+    }
+}
+
+// The severity of the leak in `report`, None if it isn't a leak
+pub(crate) fn classify(report: &Report) -> Option<Severity> {
+    if report.verdict != Verdict::Leak {
+        return None;
+    }
+    let (difference, cohens_d) = measured(report);
+    Some(severity(difference, cohens_d))
+}
+
+// The severity of the leak in `report` and why, for text output
+pub(crate) fn describe(report: &Report) -> Option<String> {
+    let severity = classify(report)?;
+    let (difference, cohens_d) = measured(report);
+    let consistency = match cohens_d {
+        None => "the same in every execution".to_string(),
+        Some(cohens_d) if cohens_d.abs() >= CONSISTENT_EFFECT => format!(
+            "showing in most executions (Cohen's d {:.2})",
+            cohens_d.abs()
+        ),
+        Some(cohens_d) => format!(
+            "only showing in some executions (Cohen's d {:.2})",
+            cohens_d.abs()
+        ),
+    };
+    Some(format!(
+        "Severity: {}, a difference of {:.2} instructions per execution, {}",
+        severity,
+        difference.abs(),
+        consistency
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn severity_test() {
+        assert_eq!(severity(250.0, None), Severity::Critical);
+        assert_eq!(severity(-250.0, Some(3.0)), Severity::Critical);
+        assert_eq!(severity(250.0, Some(0.1)), Severity::High);
+        assert_eq!(severity(40.0, None), Severity::High);
+        assert_eq!(severity(40.0, Some(-0.5)), Severity::Medium);
+        assert_eq!(severity(2.0, None), Severity::Medium);
+        assert_eq!(severity(0.3, Some(0.05)), Severity::Low);
+        assert!(Severity::Critical < Severity::Low);
+    }
+}
@@ -0,0 +1,117 @@
+// Generating and repairing string inputs
+//
+// Targets that fetch their input with `fetch_str_input` treat it as UTF-8 without checking, so every input the
+// optimizer produces for them must be valid. Rather than retrying random mutations until the result happens to
+// be valid, invalid bytes are replaced with printable ASCII, which keeps the rest of the input as it was.
+
+use rand::{Rng, RngCore};
+use std::str::FromStr;
+
+// The characters string inputs are made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    Utf8,  // Any valid UTF-8
+    Ascii, // Printable ASCII only, for targets that reject anything else
+}
+
+impl Default for Charset {
+    fn default() -> Self {
+        Charset::Utf8
+    }
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf8" => Ok(Charset::Utf8),
+            "ascii" => Ok(Charset::Ascii),
+            _ => Err(format!("unknown charset '{}'", s)),
+        }
+    }
+}
+
+// Generate a random string of exactly `len` bytes. Most characters are ASCII, so that ASCII-only code paths are reached.
+pub(crate) fn random_str<R: Rng + ?Sized>(rng: &mut R, len: usize, charset: Charset) -> Vec<u8> {
+    let mut input = Vec::with_capacity(len);
+    while input.len() < len {
+        let remaining = len - input.len();
+        let width = match charset {
+            Charset::Ascii => 1,
+            Charset::Utf8 if rng.gen_bool(0.75) => 1,
+            Charset::Utf8 => rng.gen_range(1, remaining.min(4) + 1),
+        };
+        let mut buf = [0; 4];
+        input.extend_from_slice(random_char(rng, width).encode_utf8(&mut buf).as_bytes());
+    }
+    input
+}
+
+// A random character that is `width` bytes long when encoded as UTF-8. Single byte characters are printable.
+pub(crate) fn random_char<R: Rng + ?Sized>(rng: &mut R, width: usize) -> char {
+    let code = match width {
+        1 => rng.gen_range(0x20, 0x7f),
+        2 => rng.gen_range(0x80, 0x800),
+        3 => {
+            // Skip the surrogates, which aren't characters
+            let code = rng.gen_range(0x800, 0x10000 - 0x800);
+            if code >= 0xd800 {
+                code + 0x800
+            } else {
+                code
+            }
+        }
+        _ => rng.gen_range(0x10000, 0x110000),
+    };
+    // Ok to unwrap, every range above only holds valid characters
+    std::char::from_u32(code).unwrap()
+}
+
+// Replace everything that isn't in the charset with random printable ASCII, leaving the rest of the input alone
+pub(crate) fn repair(rng: &mut dyn RngCore, input: &mut [u8], charset: Charset) {
+    match charset {
+        Charset::Utf8 => {
+            while let Err(err) = std::str::from_utf8(input) {
+                input[err.valid_up_to()] = rng.gen_range(0x20, 0x7f);
+            }
+        }
+        Charset::Ascii => {
+            for byte in input
+                .iter_mut()
+                .filter(|byte| !(0x20..0x7f).contains(&**byte))
+            {
+                *byte = rng.gen_range(0x20, 0x7f);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn strings_test() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for len in 0..64 {
+            let input = random_str(&mut rng, len, Charset::Utf8);
+            assert_eq!(input.len(), len);
+            assert!(std::str::from_utf8(&input).is_ok());
+
+            let input = random_str(&mut rng, len, Charset::Ascii);
+            assert!(input.iter().all(|byte| (0x20..0x7f).contains(byte)));
+        }
+
+        // Valid characters on either side of the broken one are kept
+        let mut input = "aé€😀b".as_bytes().to_vec();
+        input[3] = 0xff;
+        repair(&mut rng, &mut input, Charset::Utf8);
+        let repaired = std::str::from_utf8(&input).unwrap();
+        assert!(repaired.starts_with("aé") && repaired.ends_with("😀b"));
+
+        repair(&mut rng, &mut input, Charset::Ascii);
+        assert!(input.iter().all(|byte| (0x20..0x7f).contains(byte)));
+    }
+}
@@ -0,0 +1,285 @@
+// Stubs for host functions that targets import, but that sidefuzz doesn't provide
+//
+// Targets built with wasm-bindgen, or linked against a custom "env" module, import functions that only make
+// sense in their real host. Rather than failing to instantiate, each of them is satisfied with a stub.
+// Stubs are deterministic, so they don't add noise to instruction counts. By default they return zeros,
+// individual imports can be configured to trap or return a constant instead.
+//
+// Imports that look like sources of randomness return values from a seeded generator instead, which starts over
+// before every execution. Targets that check their randomness isn't all zeros still run, and every execution of an
+// input sees the same values, so measurements stay reproducible. Clocks are frozen, by returning zero.
+
+use crate::errors::SideFuzzError;
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use wasmi::core::{Trap, ValueType};
+use wasmi::*;
+
+// What a stubbed import does when it's called
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StubBehavior {
+    Zero,          // Return zero for every result
+    Trap,          // Trap, for imports that must never be called
+    Constant(i64), // Return this value for every result
+    Random,        // Return values from the seeded generator
+}
+
+impl FromStr for StubBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(StubBehavior::Zero),
+            "trap" => Ok(StubBehavior::Trap),
+            "random" => Ok(StubBehavior::Random),
+            _ => s.parse().map(StubBehavior::Constant).map_err(|_| {
+                format!(
+                    "unknown stub behavior '{}', expected zero, trap, random or a number",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+// What an import that isn't deterministic in a real host provides
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nondeterminism {
+    Randomness,
+    Time,
+}
+
+// Parts of import names that usually mean the import isn't deterministic
+const RANDOM_IMPORTS: &[&str] = &["random", "entropy"];
+const TIME_IMPORTS: &[&str] = &["clock", "time", "now", "date", "performance"];
+
+// Whether an import looks like a source of randomness or time, from its name
+pub(crate) fn classify(name: &str) -> Option<Nondeterminism> {
+    let name = name.to_lowercase();
+    if RANDOM_IMPORTS.iter().any(|part| name.contains(part)) {
+        Some(Nondeterminism::Randomness)
+    } else if TIME_IMPORTS.iter().any(|part| name.contains(part)) {
+        Some(Nondeterminism::Time)
+    } else {
+        None
+    }
+}
+
+// A warning for each function a module imports that a real host would answer differently on every call
+pub fn nondeterminism_warnings(imports: &[String]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for import in imports {
+        match classify(import.rsplit('.').next().unwrap_or_default()) {
+            Some(Nondeterminism::Randomness) => warnings.push(format!(
+                "the target imports randomness from {}, which is replaced by a seeded generator that starts over before every execution",
+                import
+            )),
+            Some(Nondeterminism::Time) => warnings.push(format!(
+                "the target imports the time from {}, which is frozen at zero",
+                import
+            )),
+            None => {}
+        }
+    }
+    warnings
+}
+
+// Deterministic randomness for the imports that ask for it, the same sequence in every execution
+#[derive(Clone)]
+pub(crate) struct Entropy {
+    seed: u64,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl Entropy {
+    pub fn new(seed: u64) -> Self {
+        Entropy {
+            seed,
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+
+    // Start the sequence over, before each execution
+    pub fn reset(&self) {
+        // Ok to unwrap, the lock is never held while anything can panic.
+        *self.rng.lock().unwrap() = StdRng::seed_from_u64(self.seed);
+    }
+
+    pub fn fill(&self, bytes: &mut [u8]) {
+        self.rng.lock().unwrap().fill_bytes(bytes);
+    }
+
+    pub fn next_u64(&self) -> u64 {
+        self.rng.lock().unwrap().next_u64()
+    }
+}
+
+// How each import is stubbed, imports are named "module.name"
+#[derive(Debug, Clone, Default)]
+pub struct Stubs {
+    behaviors: HashMap<String, StubBehavior>,
+    seed: u64, // Seed of the generator standing in for imported randomness
+}
+
+impl Stubs {
+    pub fn set(&mut self, import: String, behavior: StubBehavior) {
+        self.behaviors.insert(import, behavior);
+    }
+
+    // Configure a stub from a "module.name=behavior" argument
+    pub fn parse_arg(&mut self, arg: &str) -> Result<(), SideFuzzError> {
+        let mut parts = arg.splitn(2, '=');
+        let import = parts.next().unwrap_or_default();
+        let behavior = parts.next().ok_or_else(|| {
+            SideFuzzError::BadStub(format!("expected module.name=behavior, got '{}'", arg))
+        })?;
+        let behavior = behavior.parse().map_err(SideFuzzError::BadStub)?;
+        self.set(import.to_string(), behavior);
+        Ok(())
+    }
+
+    // Configure stubs from a JSON file mapping imports to behaviors, such as {"env.now": "zero"}
+    pub fn load(&mut self, path: &str) -> Result<(), SideFuzzError> {
+        let json = std::fs::read_to_string(path)?;
+        let behaviors: HashMap<String, String> =
+            serde_json::from_str(&json).map_err(|e| SideFuzzError::BadStub(e.to_string()))?;
+        for (import, behavior) in behaviors {
+            let behavior = behavior.parse().map_err(SideFuzzError::BadStub)?;
+            self.set(import, behavior);
+        }
+        Ok(())
+    }
+
+    // Seed the generator that stands in for imported randomness
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub(crate) fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Imports that look like randomness are random unless configured otherwise, everything else is zero
+    pub(crate) fn behavior(&self, module: &str, name: &str) -> StubBehavior {
+        let import = format!("{}.{}", module, name);
+        let default = match classify(name) {
+            Some(Nondeterminism::Randomness) => StubBehavior::Random,
+            _ => StubBehavior::Zero,
+        };
+        self.behaviors.get(&import).copied().unwrap_or(default)
+    }
+}
+
+// Stub every function `module` imports that isn't already defined in the linker
+pub(crate) fn add_to_linker(
+    linker: &mut Linker<()>,
+    store: &mut Store<()>,
+    module: &Module,
+    stubs: &Stubs,
+    entropy: &Entropy,
+) -> Result<(), SideFuzzError> {
+    for import in module.imports() {
+        let ty = match import.ty() {
+            ExternType::Func(ty) => ty.clone(),
+            _ => continue,
+        };
+        let behavior = stubs.behavior(import.module(), import.name());
+        let import_name = format!("{}.{}", import.module(), import.name());
+        let result_types = ty.results().to_vec();
+        let entropy = entropy.clone();
+        let stub = Func::new(
+            &mut *store,
+            ty,
+            move |_caller: Caller<'_, ()>, _params: &[Value], results: &mut [Value]| {
+                if behavior == StubBehavior::Trap {
+                    return Err(Trap::new(format!(
+                        "stubbed import {} was called",
+                        import_name
+                    )));
+                }
+                for (result, ty) in results.iter_mut().zip(result_types.iter()) {
+                    *result = stub_value(behavior, *ty, &entropy);
+                }
+                Ok(())
+            },
+        );
+
+        // WASI functions and tracing hooks are already defined, redefining them is expected to fail
+        let _ = linker.define(import.module(), import.name(), stub);
+    }
+    Ok(())
+}
+
+fn stub_value(behavior: StubBehavior, ty: ValueType, entropy: &Entropy) -> Value {
+    let constant = match behavior {
+        StubBehavior::Constant(constant) => constant,
+        StubBehavior::Random => return random_value(ty, entropy),
+        _ => return Value::default(ty),
+    };
+    match ty {
+        ValueType::I32 => Value::I32(constant as i32),
+        ValueType::I64 => Value::I64(constant),
+        ValueType::F32 => Value::F32((constant as f32).into()),
+        ValueType::F64 => Value::F64((constant as f64).into()),
+        _ => Value::default(ty),
+    }
+}
+
+// A float between 0 and 1 from random bits
+pub(crate) fn random_unit(bits: u64) -> f64 {
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Random integers use every bit, random floats are between 0 and 1 like JavaScript's Math.random
+fn random_value(ty: ValueType, entropy: &Entropy) -> Value {
+    let bits = entropy.next_u64();
+    let unit = random_unit(bits);
+    match ty {
+        ValueType::I32 => Value::I32(bits as i32),
+        ValueType::I64 => Value::I64(bits as i64),
+        ValueType::F32 => Value::F32((unit as f32).into()),
+        ValueType::F64 => Value::F64(unit.into()),
+        _ => Value::default(ty),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_arg_test() {
+        let mut stubs = Stubs::default();
+        stubs.parse_arg("env.abort=trap").unwrap();
+        stubs.parse_arg("env.now=-1").unwrap();
+        assert_eq!(stubs.behavior("env", "abort"), StubBehavior::Trap);
+        assert_eq!(stubs.behavior("env", "now"), StubBehavior::Constant(-1));
+        assert_eq!(stubs.behavior("env", "other"), StubBehavior::Zero);
+
+        assert!(stubs.parse_arg("env.now").is_err());
+        assert!(stubs.parse_arg("env.now=sometimes").is_err());
+
+        // Randomness is random by default, but can still be configured
+        assert_eq!(stubs.behavior("env", "getRandom"), StubBehavior::Random);
+        stubs.parse_arg("env.getRandom=7").unwrap();
+        assert_eq!(stubs.behavior("env", "getRandom"), StubBehavior::Constant(7));
+    }
+
+    #[test]
+    fn entropy_test() {
+        let entropy = Entropy::new(5);
+        let first = entropy.next_u64();
+        assert_ne!(entropy.next_u64(), first);
+        entropy.reset();
+        assert_eq!(entropy.next_u64(), first);
+
+        let imports = vec![
+            "wasi_snapshot_preview1.random_get".to_string(),
+            "env.performance_now".to_string(),
+            "env.abort".to_string(),
+        ];
+        assert_eq!(nondeterminism_warnings(&imports).len(), 2);
+    }
+}
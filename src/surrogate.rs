@@ -0,0 +1,112 @@
+// A surrogate model of the fitness function, used to pick which children are worth running
+//
+// Slow targets spend nearly all of the fuzzing time executing the target. The model is a linear function of the
+// bits of both inputs of a pair, fitted online to the score of every pair that is run, so it learns which bits
+// tend to make the difference large. It is far from exact, but it only has to rank candidates: the optimizer breeds
+// several children per place in the population and only runs the ones the model predicts to be fittest.
+
+// Step size of the normalized least mean squares update
+const LEARNING_RATE: f64 = 0.05;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Surrogate {
+    max_len: usize,
+    weights: Vec<f64>, // One for each bit of both inputs
+    bias: f64,
+    // Running mean and variance of the scores trained on, so the model works on scores of any scale
+    samples: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Surrogate {
+    pub fn new(max_len: usize) -> Self {
+        Surrogate {
+            max_len,
+            weights: vec![0.0; max_len * 2 * 8],
+            bias: 0.0,
+            samples: 0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    // The number of scores the model has been fitted to
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    // Fit the model a little closer to the score of a pair. Crashes say nothing about the difference, so they are
+    // left out.
+    pub fn train(&mut self, first: &[u8], second: &[u8], score: f64) {
+        if !score.is_finite() {
+            return;
+        }
+
+        self.samples += 1;
+        let delta = score - self.mean;
+        self.mean += delta / self.samples as f64;
+        self.m2 += delta * (score - self.mean);
+        let deviation = (self.m2 / self.samples as f64).sqrt();
+        if deviation == 0.0 {
+            return;
+        }
+
+        let target = (score - self.mean) / deviation;
+        let error = target - self.predict(first, second);
+        // Every feature is set to 1 or -1, except for missing bytes of variable-length inputs
+        let features = 1 + 8 * (first.len().min(self.max_len) + second.len().min(self.max_len));
+        let step = LEARNING_RATE * error / features as f64;
+        self.bias += step;
+        let max_len = self.max_len;
+        for_each_bit(first, second, max_len, |index, set| {
+            self.weights[index] += if set { step } else { -step };
+        });
+    }
+
+    // The predicted score of a pair, in standard deviations from the mean score
+    pub fn predict(&self, first: &[u8], second: &[u8]) -> f64 {
+        let mut prediction = self.bias;
+        for_each_bit(first, second, self.max_len, |index, set| {
+            if set {
+                prediction += self.weights[index];
+            } else {
+                prediction -= self.weights[index];
+            }
+        });
+        prediction
+    }
+}
+
+// Call `f` with the feature index of every bit of both inputs, and whether it is set
+fn for_each_bit<F: FnMut(usize, bool)>(first: &[u8], second: &[u8], max_len: usize, mut f: F) {
+    for (side, input) in [first, second].iter().enumerate() {
+        for (index, byte) in input.iter().take(max_len).enumerate() {
+            for bit in 0..8 {
+                f((side * max_len + index) * 8 + bit, byte & (1 << bit) != 0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surrogate_test() {
+        // Pairs whose first input has its lowest bit set score highly
+        let mut surrogate = Surrogate::new(1);
+        for _ in 0..20 {
+            for byte in 0..=255u8 {
+                let score = if byte & 1 == 1 { 1000.0 } else { 10.0 };
+                surrogate.train(&[byte], &[byte.wrapping_mul(7)], score);
+            }
+        }
+        surrogate.train(&[1], &[1], std::f64::NEG_INFINITY);
+        assert_eq!(surrogate.samples(), 20 * 256);
+        assert!(surrogate.predict(&[0x81], &[0]) > surrogate.predict(&[0x80], &[0]));
+        assert!(surrogate.predict(&[3], &[0]) > 0.0);
+        assert!(surrogate.predict(&[2], &[0]) < 0.0);
+    }
+}
@@ -0,0 +1,1177 @@
+// Dynamic taint tracking, through instrumentation that reports every instruction to the host
+//
+// Statistics tell whether timing depends on the secrets, and traces show where two inputs behave differently.
+// Taint tracking instead says exactly which values computed from the secret inputs reach a branch condition or a
+// memory address, for a single input. Every byte of the secret inputs is labelled with its index, and the host
+// keeps a shadow of the module's state holding the labels of each value: its operand stack, locals, globals and
+// linear memory. Wasm is validated, so the height of the operand stack before each instruction is known statically,
+// and the rewritten module only has to say which instruction is about to run, along with the address of memory
+// accesses and the value of branch conditions. Values returned by imported functions are labelled with everything
+// their arguments were.
+//
+// Only explicit flows are tracked: a value assigned in a branch taken because of a secret isn't labelled with it,
+// but the branch itself is reported.
+
+use crate::binary::*;
+use crate::errors::SideFuzzError;
+use crate::instrument::*;
+use crate::tracing::ExecutionTrace;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use wasmi::*;
+
+// Hooks, in the order the instrumentation refers to them
+const STEP: usize = 0;
+const VALUE: usize = 1;
+const BULK: usize = 2;
+
+const HOOKS: &[Hook] = &[
+    Hook {
+        name: "taint_step",
+        params: &[I32],
+        results: &[],
+    },
+    Hook {
+        name: "taint_value",
+        params: &[I32, I32],
+        results: &[],
+    },
+    Hook {
+        name: "taint_bulk",
+        params: &[I32, I32, I32, I32],
+        results: &[],
+    },
+];
+
+// The address or condition, then one local for each type of value that can be stored, then the other two
+// operands of bulk memory instructions
+const LOCALS: &[u8] = &[I32, I32, I64, F32, F64, I32, I32];
+
+// Which bytes of the input a value was computed from, as a bitset. Most values aren't computed from the input,
+// their sets are empty and don't allocate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Taint(Vec<u64>);
+
+impl Taint {
+    pub(crate) fn byte(index: usize) -> Self {
+        let mut words = vec![0; index / 64 + 1];
+        words[index / 64] |= 1 << (index % 64);
+        Taint(words)
+    }
+
+    pub fn is_tainted(&self) -> bool {
+        self.0.iter().any(|word| *word != 0)
+    }
+
+    pub(crate) fn add(&mut self, other: &Taint) {
+        if other.0.len() > self.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+        for (word, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *word |= *other;
+        }
+    }
+
+    // The indices of the input bytes, in order
+    pub fn bytes(&self) -> Vec<usize> {
+        (0..self.0.len() * 64)
+            .filter(|index| self.0[index / 64] & (1 << (index % 64)) != 0)
+            .collect()
+    }
+}
+
+// What a tainted value was used as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Use {
+    Branch,     // The condition of an if, br_if or br_table
+    Address,    // The address of a load or store, or an operand of a bulk memory instruction
+    CallTarget, // The table index of an indirect call
+}
+
+impl std::fmt::Display for Use {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Use::Branch => write!(f, "branch condition"),
+            Use::Address => write!(f, "memory address"),
+            Use::CallTarget => write!(f, "indirect call target"),
+        }
+    }
+}
+
+// An instruction that used tainted values during an execution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaintedUse {
+    pub used_as: Use,
+    pub count: u64,   // How many times it ran with a tainted value
+    pub taint: Taint, // Every input byte the values were computed from
+    pub outcomes: BTreeSet<u32>, // Which ways a tainted branch went: 1 or 0 for if and br_if, the index for br_table
+}
+
+// Where a branch goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Label {
+    base: u32,      // Stack height its values are moved to
+    arity: u32,     // How many values it takes
+    function: bool, // Branches to the function's label return from it
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BranchKind {
+    Always, // br
+    If,     // br_if
+    Table,  // br_table
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Bulk {
+    Copy, // memory.copy
+    Fill, // memory.fill
+    Init, // memory.init, whose data is never tainted
+}
+
+// What an instruction does to the shadow state. Heights are of the operand stack before the instruction runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Entry {
+        params: u32,
+        locals: u32,
+    },
+    Op {
+        height: u32,
+        pops: u32,
+        pushes: u32,
+    }, // Every result is computed from every operand
+    LocalGet {
+        height: u32,
+        local: u32,
+    },
+    LocalSet {
+        height: u32,
+        local: u32,
+    },
+    LocalTee {
+        height: u32,
+        local: u32,
+    },
+    GlobalGet {
+        height: u32,
+        global: u32,
+    },
+    GlobalSet {
+        height: u32,
+        global: u32,
+    },
+    Load {
+        height: u32,
+        offset: u32,
+        size: u32,
+    },
+    Store {
+        height: u32,
+        offset: u32,
+        size: u32,
+    },
+    If {
+        height: u32,
+    },
+    Branch {
+        height: u32,
+        kind: BranchKind,
+        targets: Vec<Label>,
+    },
+    Call {
+        height: u32,
+        params: u32,
+        indirect: bool,
+        tail: bool,
+    },
+    Returned {
+        height: u32,
+        params: u32,
+        results: u32,
+        indirect: bool,
+    }, // After the call
+    Return {
+        height: u32,
+        results: u32,
+    },
+    Bulk {
+        height: u32,
+        op: Bulk,
+    },
+}
+
+impl Step {
+    // What is reported for instructions using tainted values
+    fn name(&self) -> &'static str {
+        match self {
+            Step::Load { .. } => "load",
+            Step::Store { .. } => "store",
+            Step::If { .. } => "if",
+            Step::Branch {
+                kind: BranchKind::If,
+                ..
+            } => "br_if",
+            Step::Branch { .. } => "br_table",
+            Step::Call { .. } => "call_indirect",
+            Step::Bulk { op: Bulk::Copy, .. } => "memory.copy",
+            Step::Bulk { op: Bulk::Fill, .. } => "memory.fill",
+            Step::Bulk { .. } => "memory.init",
+            _ => "instruction",
+        }
+    }
+}
+
+// A step, and the instruction it is for
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Located {
+    func: u32,        // Function index
+    instruction: u32, // Which instruction in the function it is, 0 for the entry
+    step: Step,
+}
+
+// The steps of every instruction of a module, in the order the instrumentation visits them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    steps: Vec<Located>,
+    sites: Vec<(Option<u32>, Option<u32>)>, // The steps run before and after each site
+}
+
+// The control frames of a function body, while working out stack heights
+struct Control {
+    base: u32,
+    params: u32,
+    results: u32,
+    is_loop: bool,
+}
+
+impl Program {
+    pub fn of(bytes: &[u8]) -> Result<Self, SideFuzzError> {
+        program(bytes).ok_or(SideFuzzError::InstrumentationFailed)
+    }
+
+    // Describe an instruction reported as using tainted values
+    pub fn describe(&self, id: u32) -> Option<(u32, String)> {
+        let located = self.steps.get(id as usize)?;
+        Some((
+            located.func,
+            format!(
+                "instruction {} ({})",
+                located.instruction,
+                located.step.name()
+            ),
+        ))
+    }
+
+    fn push(&mut self, func: u32, instruction: u32, step: Option<Step>) -> Option<u32> {
+        let step = step?;
+        self.steps.push(Located {
+            func,
+            instruction,
+            step,
+        });
+        Some(self.steps.len() as u32 - 1)
+    }
+}
+
+fn program(bytes: &[u8]) -> Option<Program> {
+    let mut types: Vec<(u32, u32)> = Vec::new();
+    let mut func_types: Vec<u32> = Vec::new();
+    let mut imported_funcs = 0;
+    let mut program = Program {
+        steps: Vec::new(),
+        sites: Vec::new(),
+    };
+
+    for section in sections(bytes)?.iter() {
+        let mut pos = section.start;
+        match section.id {
+            SECTION_TYPE => {
+                let count = read_u32(bytes, &mut pos)?;
+                for _ in 0..count {
+                    pos += 1;
+                    let params = read_u32(bytes, &mut pos)?;
+                    pos += params as usize;
+                    let results = read_u32(bytes, &mut pos)?;
+                    pos += results as usize;
+                    types.push((params, results));
+                }
+            }
+            SECTION_IMPORT => {
+                let count = read_u32(bytes, &mut pos)?;
+                for _ in 0..count {
+                    read_name(bytes, &mut pos)?;
+                    read_name(bytes, &mut pos)?;
+                    let kind = *bytes.get(pos)?;
+                    pos += 1;
+                    match kind {
+                        KIND_FUNC => {
+                            func_types.push(read_u32(bytes, &mut pos)?);
+                            imported_funcs += 1;
+                        }
+                        1 => {
+                            pos += 1;
+                            skip_limits(bytes, &mut pos)?;
+                        }
+                        2 => skip_limits(bytes, &mut pos)?,
+                        KIND_GLOBAL => pos += 2,
+                        _ => return None,
+                    }
+                }
+            }
+            SECTION_FUNCTION => {
+                let count = read_u32(bytes, &mut pos)?;
+                for _ in 0..count {
+                    func_types.push(read_u32(bytes, &mut pos)?);
+                }
+            }
+            SECTION_CODE => {
+                let count = read_u32(bytes, &mut pos)?;
+                for i in 0..count {
+                    let size = read_u32(bytes, &mut pos)? as usize;
+                    let end = pos.checked_add(size)?;
+                    let func = imported_funcs + i;
+                    let ty = *types.get(*func_types.get(func as usize)? as usize)?;
+                    walk_body(
+                        bytes.get(pos..end)?,
+                        func,
+                        ty,
+                        &types,
+                        &func_types,
+                        &mut program,
+                    )?;
+                    pos = end;
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(program)
+}
+
+// Add the steps of a function body, which has type `ty`
+fn walk_body(
+    body: &[u8],
+    func: u32,
+    ty: (u32, u32),
+    types: &[(u32, u32)],
+    func_types: &[u32],
+    program: &mut Program,
+) -> Option<()> {
+    let mut pos = 0;
+    let groups = read_u32(body, &mut pos)?;
+    let mut locals = ty.0;
+    for _ in 0..groups {
+        locals = locals.checked_add(read_u32(body, &mut pos)?)?;
+        pos += 1;
+    }
+    let entry = program.push(
+        func,
+        0,
+        Some(Step::Entry {
+            params: ty.0,
+            locals,
+        }),
+    );
+    program.sites.push((entry, None));
+
+    let block_type = |start: usize| -> Option<(u32, u32)> {
+        let byte = *body.get(start + 1)?;
+        if byte == 0x40 {
+            Some((0, 0))
+        } else if (0x6f..=0x7f).contains(&byte) {
+            Some((0, 1))
+        } else {
+            types
+                .get(read_u32(body, &mut (start + 1))? as usize)
+                .cloned()
+        }
+    };
+    let func_type = |func: u32| types.get(*func_types.get(func as usize)? as usize).cloned();
+
+    let mut controls = vec![Control {
+        base: 0,
+        params: 0,
+        results: ty.1,
+        is_loop: false,
+    }];
+    let label = |controls: &[Control], depth: u32| -> Option<Label> {
+        let index = controls.len().checked_sub(depth as usize + 1)?;
+        let control = &controls[index];
+        Some(Label {
+            base: control.base,
+            arity: if control.is_loop {
+                control.params
+            } else {
+                control.results
+            },
+            function: index == 0,
+        })
+    };
+
+    let mut height: u32 = 0;
+    let mut dead = false; // After an unconditional branch, until the end of the block
+    let mut dead_blocks = 0; // Blocks started in dead code
+    let mut instruction = 0;
+    while pos < body.len() {
+        let start = pos;
+        let (op, func_index) = decode(body, &mut pos)?;
+        instruction += 1;
+        let index = || read_u32(body, &mut (start + 1));
+
+        if dead {
+            match op.opcode {
+                _ if op.is_block_start() => dead_blocks += 1,
+                OP_END if dead_blocks > 0 => dead_blocks -= 1,
+                OP_ELSE | OP_END if dead_blocks == 0 => dead = false,
+                _ => {}
+            }
+            if dead {
+                program.sites.push((None, None));
+                continue;
+            }
+        }
+
+        let mut after = None;
+        let step = match (op.opcode, op.sub) {
+            (0x00, _) => {
+                dead = true;
+                None
+            }
+            (OP_BLOCK, _) | (OP_LOOP, _) => {
+                let (params, results) = block_type(start)?;
+                controls.push(Control {
+                    base: height.checked_sub(params)?,
+                    params,
+                    results,
+                    is_loop: op.opcode == OP_LOOP,
+                });
+                None
+            }
+            (OP_IF, _) => {
+                let (params, results) = block_type(start)?;
+                let step = Step::If { height };
+                height = height.checked_sub(1)?;
+                controls.push(Control {
+                    base: height.checked_sub(params)?,
+                    params,
+                    results,
+                    is_loop: false,
+                });
+                Some(step)
+            }
+            (OP_ELSE, _) => {
+                let control = controls.last()?;
+                height = control.base + control.params;
+                None
+            }
+            (OP_END, _) => {
+                let control = controls.pop()?;
+                if controls.is_empty() {
+                    Some(Step::Return {
+                        height,
+                        results: control.results,
+                    })
+                } else {
+                    height = control.base + control.results;
+                    None
+                }
+            }
+            (OP_BR, _) => {
+                dead = true;
+                Some(Step::Branch {
+                    height,
+                    kind: BranchKind::Always,
+                    targets: vec![label(&controls, index()?)?],
+                })
+            }
+            (OP_BR_IF, _) => {
+                let step = Step::Branch {
+                    height,
+                    kind: BranchKind::If,
+                    targets: vec![label(&controls, index()?)?],
+                };
+                height = height.checked_sub(1)?;
+                Some(step)
+            }
+            (OP_BR_TABLE, _) => {
+                let mut targets_pos = start + 1;
+                let count = read_u32(body, &mut targets_pos)?;
+                let mut targets = Vec::with_capacity(count as usize + 1);
+                for _ in 0..=count {
+                    targets.push(label(&controls, read_u32(body, &mut targets_pos)?)?);
+                }
+                dead = true;
+                Some(Step::Branch {
+                    height,
+                    kind: BranchKind::Table,
+                    targets,
+                })
+            }
+            (OP_RETURN, _) => {
+                dead = true;
+                Some(Step::Return {
+                    height,
+                    results: ty.1,
+                })
+            }
+            (OP_CALL, _)
+            | (OP_CALL_INDIRECT, _)
+            | (OP_RETURN_CALL, _)
+            | (OP_RETURN_CALL_INDIRECT, _) => {
+                let indirect =
+                    op.opcode == OP_CALL_INDIRECT || op.opcode == OP_RETURN_CALL_INDIRECT;
+                let tail = op.opcode == OP_RETURN_CALL || op.opcode == OP_RETURN_CALL_INDIRECT;
+                let (params, results) = if indirect {
+                    types.get(index()? as usize).cloned()?
+                } else {
+                    func_type(func_index?)?
+                };
+                let step = Step::Call {
+                    height,
+                    params,
+                    indirect,
+                    tail,
+                };
+                if tail {
+                    dead = true;
+                } else {
+                    after = Some(Step::Returned {
+                        height,
+                        params,
+                        results,
+                        indirect,
+                    });
+                    height = height.checked_sub(params + indirect as u32)? + results;
+                }
+                Some(step)
+            }
+            (0x20, _) => Some(Step::LocalGet {
+                height,
+                local: index()?,
+            }),
+            (0x21, _) => Some(Step::LocalSet {
+                height,
+                local: index()?,
+            }),
+            (0x22, _) => Some(Step::LocalTee {
+                height,
+                local: index()?,
+            }),
+            (0x23, _) => Some(Step::GlobalGet {
+                height,
+                global: index()?,
+            }),
+            (0x24, _) => Some(Step::GlobalSet {
+                height,
+                global: index()?,
+            }),
+            _ if op.is_load() => Some(Step::Load {
+                height,
+                offset: op.memarg_offset,
+                size: access_size(op.opcode),
+            }),
+            _ if op.is_store() => Some(Step::Store {
+                height,
+                offset: op.memarg_offset,
+                size: access_size(op.opcode),
+            }),
+            (0xfc, 8) | (0xfc, 10) | (0xfc, 11) => Some(Step::Bulk {
+                height,
+                op: match op.sub {
+                    8 => Bulk::Init,
+                    10 => Bulk::Copy,
+                    _ => Bulk::Fill,
+                },
+            }),
+            (opcode, sub) => {
+                let (pops, pushes) = stack_effect(opcode, sub)?;
+                if pushes == 0 {
+                    // Nothing is left to track
+                    None
+                } else {
+                    Some(Step::Op {
+                        height,
+                        pops,
+                        pushes,
+                    })
+                }
+            }
+        };
+
+        height = match &step {
+            Some(Step::LocalGet { .. }) | Some(Step::GlobalGet { .. }) => height + 1,
+            Some(Step::LocalSet { .. }) | Some(Step::GlobalSet { .. }) => height.checked_sub(1)?,
+            Some(Step::Store { .. }) => height.checked_sub(2)?,
+            Some(Step::Bulk { .. }) => height.checked_sub(3)?,
+            Some(Step::Op { pops, pushes, .. }) => height.checked_sub(*pops)? + pushes,
+            None if !dead
+                && !op.is_block_start()
+                && op.opcode != OP_ELSE
+                && op.opcode != OP_END =>
+            {
+                let (pops, pushes) = stack_effect(op.opcode, op.sub).unwrap_or((0, 0));
+                height.checked_sub(pops)? + pushes
+            }
+            _ => height,
+        };
+
+        let before = program.push(func, instruction, step);
+        let after = program.push(func, instruction, after);
+        program.sites.push((before, after));
+        if op.opcode == OP_END && controls.is_empty() {
+            return Some(());
+        }
+    }
+    None
+}
+
+// Bytes accessed by a load or store
+fn access_size(opcode: u8) -> u32 {
+    match opcode {
+        0x2c..=0x2d | 0x30..=0x31 | 0x3a | 0x3c => 1,
+        0x2e..=0x2f | 0x32..=0x33 | 0x3b | 0x3d => 2,
+        0x29 | 0x2b | 0x37 | 0x39 => 8,
+        _ => 4,
+    }
+}
+
+// How many operands an instruction without a step of its own pops, and how many results it pushes
+fn stack_effect(opcode: u8, sub: u32) -> Option<(u32, u32)> {
+    Some(match opcode {
+        0x01 => (0, 0),
+        0x1a => (1, 0),
+        0x1b | 0x1c => (3, 1),
+        0x25 => (1, 1),
+        0x26 => (2, 0),
+        0x3f => (0, 1),
+        0x40 => (1, 1),
+        0x41..=0x44 => (0, 1),
+        0x45 | 0x50 | 0x67..=0x69 | 0x79..=0x7b | 0x8b..=0x91 | 0x99..=0x9f | 0xa7..=0xc4 => (1, 1),
+        0x46..=0x4f | 0x51..=0x66 | 0x6a..=0x78 | 0x7c..=0x8a | 0x92..=0x98 | 0xa0..=0xa6 => (2, 1),
+        0xd0 | OP_REF_FUNC => (0, 1),
+        0xd1 => (1, 1),
+        0xfc => match sub {
+            0..=7 => (1, 1),
+            9 | 13 => (0, 0),
+            12 | 14 | 17 => (3, 0),
+            15 => (2, 1),
+            16 => (0, 1),
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+// Instrument the module to report each instruction to the host
+pub(crate) fn instrument_taint(bytes: &[u8]) -> Option<Vec<u8>> {
+    let program = program(bytes)?;
+    let mut sites = program.sites.iter();
+    instrument(bytes, HOOKS, LOCALS, |site| {
+        let mut insert = Insert::default();
+        let (before, after) = match sites.next() {
+            Some(steps) => *steps,
+            None => return insert,
+        };
+        if let Some(id) = before {
+            emit_step(
+                &mut insert.before,
+                site,
+                id,
+                &program.steps[id as usize].step,
+            );
+        }
+        if let Some(id) = after {
+            emit_i32_const(&mut insert.after, id as i32);
+            emit_call(&mut insert.after, site.hooks[STEP]);
+        }
+        insert
+    })
+}
+
+// Report step `id` before the instruction at `site`, passing along the operands the host needs
+fn emit_step(code: &mut Vec<u8>, site: &Site, id: u32, step: &Step) {
+    let first = site.locals;
+    let (second, third) = (site.locals + 5, site.locals + 6);
+    match step {
+        Step::Load { .. } | Step::Store { .. } => {
+            // Stores have the value on top of the address, so stash it while we pass the address
+            let value = site.op.and_then(|op| op.store_type()).map(|ty| match ty {
+                I32 => site.locals + 1,
+                I64 => site.locals + 2,
+                F32 => site.locals + 3,
+                _ => site.locals + 4,
+            });
+            if let Some(value) = value {
+                emit_local_set(code, value);
+            }
+            emit_local_set(code, first);
+            emit_i32_const(code, id as i32);
+            emit_local_get(code, first);
+            emit_call(code, site.hooks[VALUE]);
+            emit_local_get(code, first);
+            if let Some(value) = value {
+                emit_local_get(code, value);
+            }
+        }
+        Step::If { .. }
+        | Step::Branch {
+            kind: BranchKind::If,
+            ..
+        }
+        | Step::Branch {
+            kind: BranchKind::Table,
+            ..
+        } => {
+            emit_local_set(code, first);
+            emit_i32_const(code, id as i32);
+            emit_local_get(code, first);
+            emit_call(code, site.hooks[VALUE]);
+            emit_local_get(code, first);
+        }
+        Step::Bulk { .. } => {
+            emit_local_set(code, third);
+            emit_local_set(code, second);
+            emit_local_set(code, first);
+            emit_i32_const(code, id as i32);
+            for local in [first, second, third].iter() {
+                emit_local_get(code, *local);
+            }
+            emit_call(code, site.hooks[BULK]);
+            for local in [first, second, third].iter() {
+                emit_local_get(code, *local);
+            }
+        }
+        _ => {
+            emit_i32_const(code, id as i32);
+            emit_call(code, site.hooks[STEP]);
+        }
+    }
+}
+
+// A function's locals and operand stack, in the shadow state
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Frame {
+    locals: Vec<Taint>,
+    stack: Vec<Taint>,
+}
+
+impl Frame {
+    fn slot(&mut self, height: u32) -> &mut Taint {
+        let height = height as usize;
+        if self.stack.len() <= height {
+            self.stack.resize(height + 1, Taint::default());
+        }
+        &mut self.stack[height]
+    }
+
+    fn local(&mut self, local: u32) -> &mut Taint {
+        let local = local as usize;
+        if self.locals.len() <= local {
+            self.locals.resize(local + 1, Taint::default());
+        }
+        &mut self.locals[local]
+    }
+
+    // The values from `height - count` up to `height`
+    fn values(&mut self, height: u32, count: u32) -> Vec<Taint> {
+        (height.saturating_sub(count)..height)
+            .map(|height| self.slot(height).clone())
+            .collect()
+    }
+}
+
+// The labels of every value of an execution
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaintState {
+    frames: Vec<Frame>,
+    globals: HashMap<u32, Taint>,
+    memory: HashMap<u64, Taint>, // Labels of each byte of linear memory
+    args: Option<Vec<Taint>>,    // Arguments of the call being made
+    results: Option<Vec<Taint>>, // Results of the call that just returned
+    pub uses: BTreeMap<u32, TaintedUse>, // Instructions that used tainted values, by step
+}
+
+impl TaintState {
+    // Label the `len` bytes at `address` as input bytes `first` onwards
+    pub(crate) fn taint_input(&mut self, address: u64, first: usize, len: usize) {
+        for i in 0..len {
+            self.memory
+                .insert(address + i as u64, Taint::byte(first + i));
+        }
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        if self.frames.is_empty() {
+            self.frames.push(Frame::default());
+        }
+        // Ok to unwrap, there is at least one frame
+        self.frames.last_mut().unwrap()
+    }
+
+    fn set_byte(&mut self, address: u64, taint: &Taint) {
+        if taint.is_tainted() {
+            self.memory.insert(address, taint.clone());
+        } else {
+            self.memory.remove(&address);
+        }
+    }
+
+    fn report(&mut self, id: u32, used_as: Use, taint: &Taint) {
+        if !taint.is_tainted() {
+            return;
+        }
+        let used = self.uses.entry(id).or_insert_with(|| TaintedUse {
+            used_as,
+            count: 0,
+            taint: Taint::default(),
+            outcomes: BTreeSet::new(),
+        });
+        used.count += 1;
+        used.taint.add(taint);
+    }
+
+    // Report a branch, and which way it went if its condition was tainted
+    fn report_branch(&mut self, id: u32, taint: &Taint, outcome: u32) {
+        self.report(id, Use::Branch, taint);
+        if let Some(used) = self.uses.get_mut(&id) {
+            used.outcomes.insert(outcome);
+        }
+    }
+
+    // Move the values a branch takes to where its label expects them
+    fn branch(&mut self, label: &Label, height: u32) {
+        if label.function {
+            self.ret(height, label.arity);
+            return;
+        }
+        let frame = self.frame();
+        let values = frame.values(height, label.arity);
+        for (i, value) in values.into_iter().enumerate() {
+            *frame.slot(label.base + i as u32) = value;
+        }
+    }
+
+    fn ret(&mut self, height: u32, results: u32) {
+        let results = self.frame().values(height, results);
+        self.frames.pop();
+        self.results = Some(results);
+    }
+
+    // Run step `id` of `program`, with the operands passed by the instrumentation
+    fn step(&mut self, program: &Program, id: u32, operands: [u32; 3]) {
+        let step = match program.steps.get(id as usize) {
+            Some(located) => &located.step,
+            None => return,
+        };
+        match step {
+            Step::Entry { params, locals } => {
+                let mut frame = Frame {
+                    locals: vec![Taint::default(); *locals as usize],
+                    stack: Vec::new(),
+                };
+                for (i, arg) in self.args.take().unwrap_or_default().into_iter().enumerate() {
+                    if i < *params as usize {
+                        *frame.local(i as u32) = arg;
+                    }
+                }
+                self.frames.push(frame);
+            }
+            Step::Op {
+                height,
+                pops,
+                pushes,
+            } => {
+                let frame = self.frame();
+                let mut taint = Taint::default();
+                for value in frame.values(*height, *pops) {
+                    taint.add(&value);
+                }
+                let base = height.saturating_sub(*pops);
+                for i in 0..*pushes {
+                    *frame.slot(base + i) = taint.clone();
+                }
+            }
+            Step::LocalGet { height, local } => {
+                let frame = self.frame();
+                let taint = frame.local(*local).clone();
+                *frame.slot(*height) = taint;
+            }
+            Step::LocalSet { height, local } | Step::LocalTee { height, local } => {
+                let frame = self.frame();
+                let taint = frame.slot(height.saturating_sub(1)).clone();
+                *frame.local(*local) = taint;
+            }
+            Step::GlobalGet { height, global } => {
+                let taint = self.globals.get(global).cloned().unwrap_or_default();
+                *self.frame().slot(*height) = taint;
+            }
+            Step::GlobalSet { height, global } => {
+                let taint = self.frame().slot(height.saturating_sub(1)).clone();
+                if taint.is_tainted() {
+                    self.globals.insert(*global, taint);
+                } else {
+                    self.globals.remove(global);
+                }
+            }
+            Step::Load {
+                height,
+                offset,
+                size,
+            } => {
+                let address = operands[0] as u64 + *offset as u64;
+                let address_taint = self.frame().slot(height.saturating_sub(1)).clone();
+                self.report(id, Use::Address, &address_taint);
+                // Values loaded from a tainted address are tainted by it too, so table lookups stay tainted
+                let mut taint = address_taint;
+                for i in 0..*size as u64 {
+                    if let Some(byte) = self.memory.get(&(address + i)) {
+                        taint.add(byte);
+                    }
+                }
+                *self.frame().slot(height.saturating_sub(1)) = taint;
+            }
+            Step::Store {
+                height,
+                offset,
+                size,
+            } => {
+                let address = operands[0] as u64 + *offset as u64;
+                let frame = self.frame();
+                let address_taint = frame.slot(height.saturating_sub(2)).clone();
+                let taint = frame.slot(height.saturating_sub(1)).clone();
+                self.report(id, Use::Address, &address_taint);
+                for i in 0..*size as u64 {
+                    self.set_byte(address + i, &taint);
+                }
+            }
+            Step::If { height } => {
+                let condition = self.frame().slot(height.saturating_sub(1)).clone();
+                self.report_branch(id, &condition, (operands[0] != 0) as u32);
+            }
+            Step::Branch {
+                height,
+                kind,
+                targets,
+            } => {
+                let (height, target) = match kind {
+                    BranchKind::Always => (*height, targets.first()),
+                    BranchKind::If | BranchKind::Table => {
+                        let height = height.saturating_sub(1);
+                        let condition = self.frame().slot(height).clone();
+                        let outcome = match kind {
+                            BranchKind::If => (operands[0] != 0) as u32,
+                            _ => (operands[0] as u32).min(targets.len().saturating_sub(1) as u32),
+                        };
+                        self.report_branch(id, &condition, outcome);
+                        let target = match kind {
+                            BranchKind::If if operands[0] == 0 => None,
+                            BranchKind::If => targets.first(),
+                            _ => targets.get(operands[0] as usize).or_else(|| targets.last()),
+                        };
+                        (height, target)
+                    }
+                };
+                if let Some(target) = target {
+                    self.branch(target, height);
+                }
+            }
+            Step::Call {
+                height,
+                params,
+                indirect,
+                tail,
+            } => {
+                let frame = self.frame();
+                let top = if *indirect {
+                    let index = frame.slot(height.saturating_sub(1)).clone();
+                    self.report(id, Use::CallTarget, &index);
+                    height.saturating_sub(1)
+                } else {
+                    *height
+                };
+                self.args = Some(self.frame().values(top, *params));
+                self.results = None;
+                if *tail {
+                    self.frames.pop();
+                }
+            }
+            Step::Returned {
+                height,
+                params,
+                results,
+                indirect,
+            } => {
+                // Imported functions never took their arguments, their results are computed from all of them
+                let args = self.args.take();
+                let values = match self.results.take() {
+                    Some(values) => values,
+                    None => {
+                        let mut taint = Taint::default();
+                        for arg in args.unwrap_or_default() {
+                            taint.add(&arg);
+                        }
+                        vec![taint; *results as usize]
+                    }
+                };
+                let base = height.saturating_sub(params + *indirect as u32);
+                let frame = self.frame();
+                for (i, value) in values.into_iter().take(*results as usize).enumerate() {
+                    *frame.slot(base + i as u32) = value;
+                }
+            }
+            Step::Return { height, results } => self.ret(*height, *results),
+            Step::Bulk { height, op } => {
+                let operand_taints = self.frame().values(*height, 3);
+                let (destination, source, len) =
+                    (operands[0] as u64, operands[1] as u64, operands[2] as u64);
+                // The address operands, and the length, decide which memory is accessed
+                let mut address_taint = Taint::default();
+                for (i, taint) in operand_taints.iter().enumerate() {
+                    if *op != Bulk::Fill || i != 1 {
+                        address_taint.add(taint);
+                    }
+                }
+                self.report(id, Use::Address, &address_taint);
+                match op {
+                    Bulk::Copy => {
+                        let bytes: Vec<Taint> = (0..len)
+                            .map(|i| self.memory.get(&(source + i)).cloned().unwrap_or_default())
+                            .collect();
+                        for (i, taint) in bytes.iter().enumerate() {
+                            self.set_byte(destination + i as u64, taint);
+                        }
+                    }
+                    Bulk::Fill => {
+                        let taint = operand_taints.get(1).cloned().unwrap_or_default();
+                        for i in 0..len {
+                            self.set_byte(destination + i, &taint);
+                        }
+                    }
+                    Bulk::Init => {
+                        for i in 0..len {
+                            self.memory.remove(&(destination + i));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Define the taint hooks of `bytes`, tracking into `trace`
+pub(crate) fn add_to_linker(
+    linker: &mut Linker<()>,
+    store: &mut Store<()>,
+    trace: &Arc<Mutex<ExecutionTrace>>,
+    bytes: &[u8],
+) -> Result<(), SideFuzzError> {
+    let program = Arc::new(Program::of(bytes)?);
+
+    let (step_program, step_trace) = (program.clone(), trace.clone());
+    let taint_step = Func::wrap(&mut *store, move |id: i32| {
+        // Ok to unwrap, the lock is never held while anything can panic.
+        step_trace
+            .lock()
+            .unwrap()
+            .taint
+            .step(&step_program, id as u32, [0; 3]);
+    });
+    linker.define(HOOK_MODULE, "taint_step", taint_step)?;
+
+    let (value_program, value_trace) = (program.clone(), trace.clone());
+    let taint_value = Func::wrap(&mut *store, move |id: i32, value: i32| {
+        // Ok to unwrap, the lock is never held while anything can panic.
+        value_trace
+            .lock()
+            .unwrap()
+            .taint
+            .step(&value_program, id as u32, [value as u32, 0, 0]);
+    });
+    linker.define(HOOK_MODULE, "taint_value", taint_value)?;
+
+    let bulk_trace = trace.clone();
+    let taint_bulk = Func::wrap(
+        &mut *store,
+        move |id: i32, first: i32, second: i32, third: i32| {
+            // Ok to unwrap, the lock is never held while anything can panic.
+            bulk_trace.lock().unwrap().taint.step(
+                &program,
+                id as u32,
+                [first as u32, second as u32, third as u32],
+            );
+        },
+    );
+    linker.define(HOOK_MODULE, "taint_bulk", taint_bulk)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (module (memory 1) (func i32.const 0 i32.load8_u if end))
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic + version
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // types
+        0x03, 0x02, 0x01, 0x00, // functions
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory
+        0x0a, 0x0c, 0x01, 0x0a, 0x00, 0x41, 0x00, 0x2d, 0x00, 0x00, 0x04, 0x40, 0x0b,
+        0x0b, // code
+    ];
+
+    #[test]
+    fn taint_test() {
+        let program = Program::of(MODULE).unwrap();
+        let steps: Vec<Step> = program
+            .steps
+            .iter()
+            .map(|located| located.step.clone())
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                Step::Entry {
+                    params: 0,
+                    locals: 0
+                },
+                Step::Op {
+                    height: 0,
+                    pops: 0,
+                    pushes: 1
+                },
+                Step::Load {
+                    height: 1,
+                    offset: 0,
+                    size: 1
+                },
+                Step::If { height: 1 },
+                Step::Return {
+                    height: 0,
+                    results: 0
+                },
+            ]
+        );
+        // The entry, four instructions and the end of the function
+        assert_eq!(program.sites.len(), 6);
+        assert!(instrument_taint(MODULE).is_some());
+
+        // The byte at address 0 is the second input byte, and decides the branch
+        let mut state = TaintState::default();
+        state.taint_input(0, 1, 1);
+        for id in 0..steps.len() as u32 {
+            state.step(&program, id, [0; 3]);
+        }
+        let used = &state.uses[&3];
+        assert_eq!(used.used_as, Use::Branch);
+        assert_eq!(used.taint.bytes(), vec![1]);
+        assert_eq!(used.outcomes.iter().collect::<Vec<_>>(), vec![&0]);
+        assert_eq!(state.uses.len(), 1);
+        assert_eq!(
+            program.describe(3),
+            Some((0, "instruction 3 (if)".to_string()))
+        );
+        assert!(state.frames.is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+// Fuzzing targets, and the backends that run and measure them
+
+use crate::errors::SideFuzzError;
+use crate::features::WasmFeatures;
+use crate::stubs::Stubs;
+use crate::wasm::{WasmModule, WasmOptions, DEFAULT_MAX_INPUT_LEN};
+use float_duration::FloatDuration;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Something that can be fed inputs and measured
+pub trait Target: Send {
+    // Count instructions for a given input
+    fn count_instructions(&mut self, input: &[u8]) -> Result<u64, SideFuzzError>;
+
+    // Count instructions for a given input, also returning what the target passed to `sidefuzz::set_output`.
+    // Targets that can't read outputs return None.
+    fn run_with_output(&mut self, input: &[u8]) -> Result<(u64, Option<Vec<u8>>), SideFuzzError> {
+        Ok((self.count_instructions(input)?, None))
+    }
+
+    // Measure how long a single execution takes, used to estimate how long fuzzing will take
+    fn measure_time(&mut self) -> Result<FloatDuration, SideFuzzError>;
+
+    fn fuzz_len(&self) -> usize;
+
+    // Lengths of each of the target's inputs, see `WasmModule::input_segments`
+    fn input_segments(&self) -> Vec<usize>;
+
+    fn input_is_str(&self) -> bool;
+
+    // Count instructions for several inputs, which some targets can do faster than one at a time
+    fn count_batch(&mut self, inputs: &[&[u8]]) -> Vec<Result<u64, SideFuzzError>> {
+        inputs
+            .iter()
+            .map(|input| self.count_instructions(input))
+            .collect()
+    }
+
+    // The most inputs `count_batch` runs at once, there is no point in passing fewer
+    fn batch_capacity(&self) -> usize {
+        1
+    }
+
+    // Which of the target's inputs are public, one flag for each of `input_segments`.
+    // Public inputs are randomized when comparing timings, only the other inputs are secret.
+    fn public_segments(&self) -> Vec<bool> {
+        vec![false; self.input_segments().len()]
+    }
+
+    // Whether inputs can be any length up to `fuzz_len`, instead of exactly `fuzz_len`
+    fn variable_len(&self) -> bool {
+        false
+    }
+
+    // Reseed the random number generator used for generating throwaway inputs
+    fn set_seed(&mut self, seed: u64);
+
+    // Create another instance of the target, starting from a fresh state
+    fn try_clone(&self) -> Result<Box<dyn Target>, SideFuzzError>;
+
+    // Whether clones can run at the same time as the original, on other threads
+    fn parallel(&self) -> bool {
+        true
+    }
+
+    // The module bytes and options of wasm targets, for re-running inputs with instrumentation
+    fn wasm(&self) -> Option<(Vec<u8>, WasmOptions)> {
+        None
+    }
+}
+
+// How targets are run and measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Wasm,   // Instruction counting in the wasmi interpreter
+    Native, // A native shared library, measured with whatever the OS offers, see `perf`
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wasm" => Ok(Backend::Wasm),
+            "native" => Ok(Backend::Native),
+            _ => Err(format!("unknown backend '{}'", s)),
+        }
+    }
+}
+
+// What runs wasm targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WasmEngine {
+    Interpreter, // The wasmi interpreter
+    Jit,         // wasmtime's Cranelift JIT, much faster for big targets but counting instructions differently
+}
+
+impl FromStr for WasmEngine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interpreter" => Ok(WasmEngine::Interpreter),
+            "jit" => Ok(WasmEngine::Jit),
+            _ => Err(format!("unknown engine '{}'", s)),
+        }
+    }
+}
+
+// What is measured when a target executes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Measure {
+    Instructions, // Instructions executed, exact and noise free
+    Time,         // Wall-clock time in nanoseconds, what an attacker actually observes
+}
+
+impl FromStr for Measure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "instructions" => Ok(Measure::Instructions),
+            "time" => Ok(Measure::Time),
+            _ => Err(format!("unknown measurement '{}'", s)),
+        }
+    }
+}
+
+// How to load, run and measure a target
+#[derive(Debug, Clone)]
+pub struct TargetOptions {
+    pub backend: Backend,
+    pub engine: WasmEngine, // Only used by wasm targets
+    pub measure: Measure,
+    pub stubs: Stubs,                   // Only used by wasm targets
+    pub max_instructions: Option<u64>, // Only used by wasm targets
+    pub max_input_len: Option<usize>,
+    pub timeout: Option<Duration>,
+    pub features: WasmFeatures, // Only used by wasm targets
+    pub persistent: bool,       // Only used by wasm targets
+    pub entry: Option<String>,  // Only used by wasm targets
+    pub tinygo: bool,           // Only used by wasm targets
+    pub key: Option<Vec<u8>>,   // Only used by wasm targets
+    pub fuzz_key: bool,         // Only used by wasm targets
+    pub message: Option<Vec<u8>>, // The fixed message when fuzzing the key
+    pub fresh_memory: bool,     // Only used by wasm targets
+    pub record: Option<String>, // Write every execution to this session log
+    pub pin_cpu: Option<usize>, // Only used when measuring time
+}
+
+impl Default for TargetOptions {
+    fn default() -> Self {
+        TargetOptions {
+            backend: Backend::Wasm,
+            engine: WasmEngine::Interpreter,
+            measure: Measure::Instructions,
+            stubs: Stubs::default(),
+            max_instructions: None,
+            max_input_len: None,
+            timeout: None,
+            features: WasmFeatures::default(),
+            persistent: false,
+            entry: None,
+            tinygo: false,
+            key: None,
+            fuzz_key: false,
+            message: None,
+            fresh_memory: false,
+            record: None,
+            pin_cpu: None,
+        }
+    }
+}
+
+impl TargetOptions {
+    // Options for instantiating wasm targets
+    pub(crate) fn wasm_options(&self) -> WasmOptions {
+        WasmOptions {
+            trace: None,
+            stubs: self.stubs.clone(),
+            max_instructions: self.max_instructions,
+            max_input_len: self.max_input_len,
+            timeout: self.timeout,
+            features: self.features.clone(),
+            persistent: self.persistent,
+            entry: self.entry.clone(),
+            tinygo: self.tinygo,
+            key: self.key.clone(),
+            fuzz_key: self.fuzz_key,
+            message: self.message.clone(),
+            fresh_memory: self.fresh_memory,
+        }
+    }
+}
+
+// Load a target from a file
+pub(crate) fn load(filename: &str, options: &TargetOptions) -> Result<Box<dyn Target>, SideFuzzError> {
+    let target: Box<dyn Target> = match options.backend {
+        Backend::Wasm => match options.engine {
+            WasmEngine::Interpreter => Box::new(WasmModule::from_file_with_options(filename, options.wasm_options())?),
+            #[cfg(feature = "jit")]
+            WasmEngine::Jit => Box::new(crate::jit::JitModule::from_file_with_options(filename, options.wasm_options())?),
+            #[cfg(not(feature = "jit"))]
+            WasmEngine::Jit => return Err(SideFuzzError::JitUnsupported),
+        },
+        #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+        Backend::Native => {
+            if options.key.is_some() || options.fuzz_key {
+                return Err(SideFuzzError::KeyUnsupported);
+            }
+            // Native targets allocate their own input, so only the length needs checking
+            let mut module = crate::native::NativeModule::from_file(filename)?;
+            module.set_timeout(options.timeout);
+            log::info!("Measuring the native target in {}", module.unit());
+            let max_input_len = options.max_input_len.unwrap_or(DEFAULT_MAX_INPUT_LEN);
+            if module.fuzz_len() > max_input_len {
+                return Err(SideFuzzError::FuzzLenTooLong(module.fuzz_len() as u32, max_input_len));
+            }
+            Box::new(module)
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+        Backend::Native => return Err(SideFuzzError::NativeUnsupported),
+    };
+
+    let target: Box<dyn Target> = match options.measure {
+        Measure::Instructions => target,
+        Measure::Time => Box::new(crate::timing::TimedTarget::new(target, options.pin_cpu)),
+    };
+
+    // Recorded around the measurement, so the log holds what was actually measured
+    let target: Box<dyn Target> = match &options.record {
+        Some(path) => Box::new(crate::session::RecordingTarget::new(target, path, options.measure)?),
+        None => target,
+    };
+
+    if crate::logging::executions_enabled() {
+        Ok(Box::new(crate::logging::LoggingTarget::new(target)))
+    } else {
+        Ok(target)
+    }
+}
+
+impl Target for WasmModule {
+    fn count_instructions(&mut self, input: &[u8]) -> Result<u64, SideFuzzError> {
+        WasmModule::count_instructions(self, input)
+    }
+
+    fn run_with_output(&mut self, input: &[u8]) -> Result<(u64, Option<Vec<u8>>), SideFuzzError> {
+        WasmModule::run_with_output(self, input)
+    }
+
+    fn measure_time(&mut self) -> Result<FloatDuration, SideFuzzError> {
+        WasmModule::measure_time(self)
+    }
+
+    fn fuzz_len(&self) -> usize {
+        WasmModule::fuzz_len(self)
+    }
+
+    fn input_segments(&self) -> Vec<usize> {
+        WasmModule::input_segments(self)
+    }
+
+    fn input_is_str(&self) -> bool {
+        WasmModule::input_is_str(self)
+    }
+
+    fn public_segments(&self) -> Vec<bool> {
+        WasmModule::public_segments(self)
+    }
+
+    fn count_batch(&mut self, inputs: &[&[u8]]) -> Vec<Result<u64, SideFuzzError>> {
+        WasmModule::count_batch(self, inputs)
+    }
+
+    fn batch_capacity(&self) -> usize {
+        WasmModule::batch_capacity(self)
+    }
+
+    fn variable_len(&self) -> bool {
+        WasmModule::variable_len(self)
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        WasmModule::set_seed(self, seed)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Target>, SideFuzzError> {
+        let module = WasmModule::with_options(self.bytes(), self.options().clone())?;
+        Ok(Box::new(module))
+    }
+
+    fn wasm(&self) -> Option<(Vec<u8>, WasmOptions)> {
+        Some((self.bytes(), self.options().clone()))
+    }
+}
@@ -0,0 +1,313 @@
+// Input templates, describing the structure of a target's input
+//
+// Targets that parse structured data (DER, protobuf, bignum encodings) mostly reject random bytes.
+// A template splits the input into fields, so that mutation keeps the structure valid and only fuzzes fields that can vary.
+// Templates are JSON, for example:
+//
+// {"fields": [
+//   {"type": "fixed", "value": "3020"},
+//   {"type": "choice", "values": ["01", "02"]},
+//   {"type": "int", "len": 2, "min": 0, "max": 1000},
+//   {"type": "bytes", "len": 28, "name": "key"}
+// ]}
+//
+// Simple templates can also be written as hex with "??" for each byte that can be anything, such as "0000????ff".
+
+use crate::errors::SideFuzzError;
+use rand::distributions::Uniform;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Deserialize)]
+struct TemplateSpec {
+    fields: Vec<FieldSpec>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FieldSpec {
+    name: Option<String>,
+    #[serde(flatten)]
+    kind: FieldKind,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FieldKind {
+    Fixed { value: String },        // Constant bytes, in hex, never mutated
+    Bytes { len: usize },           // Freely mutated bytes
+    Choice { values: Vec<String> }, // One of several values, in hex, all of the same length
+    Int { len: usize, min: u64, max: u64 }, // A big-endian unsigned integer within a range
+}
+
+#[derive(Debug, Clone)]
+enum Field {
+    Fixed(Vec<u8>),
+    Bytes,
+    Choice(Vec<Vec<u8>>),
+    Int(Uniform<u64>, u64, u64),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Template {
+    fields: Vec<(Range<usize>, Field)>,
+    len: usize,
+}
+
+impl Template {
+    pub fn load(path: &str) -> Result<Self, SideFuzzError> {
+        let json = std::fs::read_to_string(path)?;
+        Self::parse(&json)
+    }
+
+    pub fn parse(json: &str) -> Result<Self, SideFuzzError> {
+        let spec: TemplateSpec =
+            serde_json::from_str(json).map_err(|e| SideFuzzError::BadTemplate(e.to_string()))?;
+
+        let mut fields = Vec::with_capacity(spec.fields.len());
+        let mut offset = 0;
+        for (index, spec) in spec.fields.into_iter().enumerate() {
+            let name = spec.name.unwrap_or_else(|| format!("field {}", index));
+            let bad = |reason: &str| SideFuzzError::BadTemplate(format!("{}: {}", name, reason));
+
+            let (field_len, field) = match spec.kind {
+                FieldKind::Fixed { value } => {
+                    let value = hex::decode(value).map_err(|_| bad("value is not valid hex"))?;
+                    (value.len(), Field::Fixed(value))
+                }
+                FieldKind::Bytes { len } => (len, Field::Bytes),
+                FieldKind::Choice { values } => {
+                    let values = values
+                        .into_iter()
+                        .map(hex::decode)
+                        .collect::<Result<Vec<Vec<u8>>, _>>()
+                        .map_err(|_| bad("values are not valid hex"))?;
+                    let field_len = values
+                        .first()
+                        .map(|v| v.len())
+                        .ok_or_else(|| bad("no values"))?;
+                    if values.iter().any(|v| v.len() != field_len) {
+                        return Err(bad("values are not all the same length"));
+                    }
+                    (field_len, Field::Choice(values))
+                }
+                FieldKind::Int { len, min, max } => {
+                    if len == 0 || len > 8 {
+                        return Err(bad("integers must be between 1 and 8 bytes"));
+                    }
+                    let limit = if len == 8 {
+                        u64::MAX
+                    } else {
+                        (1u64 << (len * 8)) - 1
+                    };
+                    if min > max || max > limit {
+                        return Err(bad("range doesn't fit in the integer"));
+                    }
+                    (len, Field::Int(Uniform::new_inclusive(min, max), min, max))
+                }
+            };
+
+            fields.push((offset..offset + field_len, field));
+            offset += field_len;
+        }
+
+        Ok(Template {
+            fields,
+            len: offset,
+        })
+    }
+
+    // Parse a hex pattern, where each "??" is a byte that can be anything
+    pub fn from_pattern(pattern: &str) -> Result<Self, SideFuzzError> {
+        if pattern.len() % 2 != 0 {
+            return Err(SideFuzzError::BadTemplate(
+                "patterns must have two characters for each byte".to_string(),
+            ));
+        }
+        let mut fields: Vec<(Range<usize>, Field)> = Vec::new();
+        for (offset, byte) in pattern.as_bytes().chunks(2).enumerate() {
+            let field = if byte == b"??" {
+                Field::Bytes
+            } else {
+                let value = std::str::from_utf8(byte)
+                    .ok()
+                    .and_then(|byte| hex::decode(byte).ok())
+                    .ok_or_else(|| {
+                        SideFuzzError::BadTemplate(format!("byte {} is neither hex nor ??", offset))
+                    })?;
+                Field::Fixed(value)
+            };
+            // Runs of wildcards or of fixed bytes are kept as a single field
+            let merged = match (fields.last_mut(), &field) {
+                (Some((range, Field::Bytes)), Field::Bytes) => {
+                    range.end += 1;
+                    true
+                }
+                (Some((range, Field::Fixed(value))), Field::Fixed(byte)) => {
+                    range.end += 1;
+                    value.extend_from_slice(byte);
+                    true
+                }
+                _ => false,
+            };
+            if !merged {
+                fields.push((offset..offset + 1, field));
+            }
+        }
+        Ok(Template {
+            fields,
+            len: pattern.len() / 2,
+        })
+    }
+
+    // Load a JSON template from a file ending in ".json", or parse anything else as a hex pattern
+    pub fn from_arg(arg: &str) -> Result<Self, SideFuzzError> {
+        if arg.ends_with(".json") {
+            Self::load(arg)
+        } else {
+            Self::from_pattern(arg)
+        }
+    }
+
+    // Whether every input matching the template is the same
+    pub fn is_fixed(&self) -> bool {
+        self.fields
+            .iter()
+            .all(|(range, field)| range.is_empty() || matches!(field, Field::Fixed(_)))
+    }
+
+    // The template as a hex pattern, with "??" for every byte that can vary
+    pub fn pattern(&self) -> String {
+        let mut pattern = String::with_capacity(self.len * 2);
+        for (range, field) in self.fields.iter() {
+            match field {
+                Field::Fixed(value) => pattern += &hex::encode(value),
+                _ => pattern += &"??".repeat(range.len()),
+            }
+        }
+        pattern
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // A random input matching the template
+    pub fn random<R: Rng>(&self, rng: &mut R) -> Vec<u8> {
+        let mut input: Vec<u8> = (0..self.len).map(|_| rng.gen()).collect();
+        for (range, field) in self.fields.iter() {
+            if let Field::Int(range_distribution, _, _) = field {
+                write_int(&mut input[range.clone()], rng.sample(range_distribution));
+            }
+        }
+        self.conform(rng, &mut input);
+        input
+    }
+
+    // Fix up an input (after breeding or mutation) so that it matches the template again
+    pub fn conform<R: Rng>(&self, rng: &mut R, input: &mut [u8]) {
+        for (range, field) in self.fields.iter() {
+            let bytes = &mut input[range.clone()];
+            match field {
+                Field::Fixed(value) => bytes.copy_from_slice(value),
+                Field::Bytes => {}
+                Field::Choice(values) => {
+                    if !values.iter().any(|v| v[..] == bytes[..]) {
+                        // Ok to unwrap, templates always have at least one choice.
+                        bytes.copy_from_slice(values.choose(rng).unwrap());
+                    }
+                }
+                Field::Int(range_distribution, min, max) => {
+                    let value = read_int(bytes);
+                    if value < *min || value > *max {
+                        write_int(bytes, rng.sample(range_distribution));
+                    }
+                }
+            }
+        }
+    }
+
+    // The byte range of a random field that can vary
+    pub fn random_field<R: Rng>(&self, rng: &mut R) -> Range<usize> {
+        let variable: Vec<&Range<usize>> = self
+            .fields
+            .iter()
+            .filter(|(range, field)| !range.is_empty() && !matches!(field, Field::Fixed(_)))
+            .map(|(range, _)| range)
+            .collect();
+        match variable.choose(rng) {
+            Some(range) => (*range).clone(),
+            None => 0..self.len,
+        }
+    }
+}
+
+fn read_int(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+fn write_int(bytes: &mut [u8], mut value: u64) {
+    for byte in bytes.iter_mut().rev() {
+        *byte = value as u8;
+        value >>= 8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::template::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn template_test() {
+        let template = Template::parse(
+            r#"{"fields": [
+                {"type": "fixed", "value": "3020"},
+                {"type": "choice", "values": ["01", "02"]},
+                {"type": "int", "len": 2, "min": 10, "max": 20},
+                {"type": "bytes", "len": 3}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(template.len(), 8);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut input = vec![0xff; 8];
+        template.conform(&mut rng, &mut input);
+        assert_eq!(&input[0..2], &[0x30, 0x20]);
+        assert!(input[2] == 1 || input[2] == 2);
+        assert!((10..=20).contains(&read_int(&input[3..5])));
+        assert_eq!(&input[5..8], &[0xff, 0xff, 0xff]);
+
+        for _ in 0..10 {
+            let range = template.random_field(&mut rng);
+            assert!(range.start >= 2);
+        }
+
+        assert!(Template::parse(
+            r#"{"fields": [{"type": "int", "len": 1, "max": 256, "min": 0}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn pattern_test() {
+        let template = Template::from_pattern("00ff????ab").unwrap();
+        assert_eq!(template.len(), 5);
+        assert_eq!(template.fields.len(), 3);
+        assert_eq!(template.pattern(), "00ff????ab");
+        assert!(!template.is_fixed());
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let input = template.random(&mut rng);
+        assert_eq!(&input[..2], &[0x00, 0xff]);
+        assert_eq!(input[4], 0xab);
+
+        assert!(Template::from_pattern("0102").unwrap().is_fixed());
+        assert!(Template::from_pattern("0?").is_err());
+        assert!(Template::from_pattern("012").is_err());
+    }
+}
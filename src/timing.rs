@@ -0,0 +1,196 @@
+// Wall-clock timing measurement
+//
+// Instruction counts are exact, but they are not what an attacker sees. Timing real executions lets results be
+// validated against actual timings, at the cost of noise. Each measurement is repeated and a percentile taken,
+// which filters out executions slowed down by interrupts, frequency scaling and the like. Measurements are all
+// taken on one cpu, and a machine that scales its cpu frequency gets a warning, as its timings say little about
+// the target.
+
+use crate::errors::SideFuzzError;
+use crate::target::Target;
+use crate::wasm::WasmOptions;
+use float_duration::FloatDuration;
+use std::time::Instant;
+
+// How many times each input is executed per measurement
+const REPETITIONS: usize = 15;
+
+// Which of the sorted repetitions is used, 0.5 is the median
+const PERCENTILE: f64 = 0.5;
+
+// A target measured in nanoseconds of wall-clock time instead of instructions
+pub(crate) struct TimedTarget {
+    inner: Box<dyn Target>,
+    cpu: Option<usize>, // Given with --pin-cpu
+}
+
+impl TimedTarget {
+    // Time `inner`, pinning the current thread to `cpu`, or else to the cpu it is running on, so every measurement
+    // is taken there
+    pub fn new(inner: Box<dyn Target>, cpu: Option<usize>) -> Self {
+        match cpu.or_else(current_cpu) {
+            Some(cpu) => pin(cpu),
+            None => log::warn!("could not find out which cpu is timing the target, it is not pinned"),
+        }
+        TimedTarget { inner, cpu }
+    }
+}
+
+impl Target for TimedTarget {
+    fn count_instructions(&mut self, input: &[u8]) -> Result<u64, SideFuzzError> {
+        let mut timings = Vec::with_capacity(REPETITIONS);
+        for _ in 0..REPETITIONS {
+            let start_time = Instant::now();
+            self.inner.count_instructions(input)?;
+            timings.push(start_time.elapsed().as_nanos() as u64);
+        }
+        Ok(percentile(&mut timings, PERCENTILE))
+    }
+
+    // The output is the same on every repetition, so it is read from an extra untimed one
+    fn run_with_output(&mut self, input: &[u8]) -> Result<(u64, Option<Vec<u8>>), SideFuzzError> {
+        let (_, output) = self.inner.run_with_output(input)?;
+        Ok((self.count_instructions(input)?, output))
+    }
+
+    fn measure_time(&mut self) -> Result<FloatDuration, SideFuzzError> {
+        Ok(self.inner.measure_time()? * REPETITIONS as f64)
+    }
+
+    fn fuzz_len(&self) -> usize {
+        self.inner.fuzz_len()
+    }
+
+    fn input_segments(&self) -> Vec<usize> {
+        self.inner.input_segments()
+    }
+
+    fn input_is_str(&self) -> bool {
+        self.inner.input_is_str()
+    }
+
+    fn public_segments(&self) -> Vec<bool> {
+        self.inner.public_segments()
+    }
+
+    fn variable_len(&self) -> bool {
+        self.inner.variable_len()
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.inner.set_seed(seed)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Target>, SideFuzzError> {
+        Ok(Box::new(TimedTarget::new(self.inner.try_clone()?, self.cpu)))
+    }
+
+    // Timings taken while other threads are busy fuzzing would be mostly noise
+    fn parallel(&self) -> bool {
+        false
+    }
+
+    fn wasm(&self) -> Option<(Vec<u8>, WasmOptions)> {
+        self.inner.wasm()
+    }
+}
+
+// The value at `p` (between 0 and 1) of the sorted values. `values` must not be empty.
+fn percentile(values: &mut [u64], p: f64) -> u64 {
+    values.sort_unstable();
+    let index = ((values.len() - 1) as f64 * p).round() as usize;
+    values[index]
+}
+
+#[cfg(target_os = "linux")]
+fn current_cpu() -> Option<usize> {
+    let cpu = unsafe { libc::sched_getcpu() };
+    if cpu < 0 {
+        None
+    } else {
+        Some(cpu as usize)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_cpu() -> Option<usize> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn pin(cpu: usize) {
+    // Pinning is best effort, timing still works (with more noise) if it fails.
+    let pinned = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0
+    };
+    if !pinned {
+        log::warn!(
+            "could not pin the timing thread to cpu {}: {}",
+            cpu,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin(_cpu: usize) {
+    log::warn!("pinning the timing thread to a cpu is only supported on Linux");
+}
+
+// Things about the machine that make timings on `cpu` (or the current one) noisy, such as frequency scaling
+pub fn machine_warnings(cpu: Option<usize>) -> Vec<String> {
+    let cpu = cpu.or_else(current_cpu).unwrap_or(0);
+    let read = |path: String| std::fs::read_to_string(path).ok();
+    frequency_warnings(
+        cpu,
+        read(format!("/sys/devices/system/cpu/cpu{}/cpufreq/scaling_governor", cpu)).as_deref(),
+        read("/sys/devices/system/cpu/intel_pstate/no_turbo".to_string()).as_deref(),
+        read("/sys/devices/system/cpu/cpufreq/boost".to_string()).as_deref(),
+    )
+}
+
+// Warnings from the cpufreq settings of Linux, files that don't exist are None
+fn frequency_warnings(
+    cpu: usize,
+    governor: Option<&str>,
+    no_turbo: Option<&str>,
+    boost: Option<&str>,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    match governor.map(str::trim) {
+        Some("performance") | None => {}
+        Some(governor) => warnings.push(format!(
+            "cpu {} scales its frequency with the '{}' governor, timings will vary with it. Set the 'performance' governor for stable timings",
+            cpu, governor
+        )),
+    }
+    let turbo = no_turbo.map(str::trim) == Some("0") || boost.map(str::trim) == Some("1");
+    if turbo {
+        warnings.push(
+            "turbo boost is enabled, timings will vary with the temperature of the cpu. Turn it off for stable timings"
+                .to_string(),
+        );
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timing::*;
+
+    #[test]
+    fn percentile_test() {
+        let mut values = vec![50, 10, 1000, 20, 30];
+        assert_eq!(percentile(&mut values, 0.5), 30);
+        assert_eq!(percentile(&mut values, 0.0), 10);
+        assert_eq!(percentile(&mut values, 1.0), 1000);
+
+        assert!(frequency_warnings(2, Some("performance\n"), Some("1\n"), None).is_empty());
+        assert!(frequency_warnings(2, None, None, None).is_empty());
+        let warnings = frequency_warnings(2, Some("powersave\n"), None, Some("1\n"));
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("cpu 2") && warnings[0].contains("powersave"));
+    }
+}
@@ -0,0 +1,150 @@
+// Compatibility with modules built by TinyGo
+//
+// Go code needs its runtime started before any exported function can be called: `_start` sets up the heap, runs
+// every package's init functions and then main, which for a fuzz target should be empty. Under -target=wasi it may
+// end by calling proc_exit(0), which is a successful start rather than a crash. -target=wasm modules also import
+// the runtime's clock and the syscall/js functions from the "gojs" (or, before TinyGo 0.27, "env") module. Those
+// are stubbed like any other import, except for the clock: sleeping advances it, so sleeping goroutines wake up
+// deterministically instead of running into the instruction budget.
+
+use crate::errors::SideFuzzError;
+use crate::fuel::FuelMeter;
+use std::sync::{Arc, Mutex};
+use wasmi::core::{TrapCode, ValueType};
+use wasmi::*;
+
+// The export that starts the runtime
+pub(crate) const START: &str = "_start";
+
+// Instructions the runtime may take to start, which aren't part of any execution's count
+pub(crate) const STARTUP_INSTRUCTIONS: u64 = 1_000_000_000;
+
+// Exports of TinyGo's scheduler, for the JS glue to call back into
+const SCHEDULER_EXPORTS: &[&str] = &["go_scheduler", "resume"];
+
+// Whether a module looks like it was built by TinyGo, from the names of its imported functions, its exports and
+// its named functions
+pub(crate) fn is_tinygo<'a>(
+    imports: impl IntoIterator<Item = &'a str>,
+    exports: impl IntoIterator<Item = &'a str>,
+    bytes: &[u8],
+) -> bool {
+    imports
+        .into_iter()
+        .any(|name| name.starts_with("runtime.") || name.starts_with("syscall/js."))
+        || exports
+            .into_iter()
+            .any(|name| SCHEDULER_EXPORTS.contains(&name))
+        || crate::binary::function_names(bytes)
+            .values()
+            .any(|name| name.starts_with("tinygo_"))
+}
+
+// The runtime's clock in nanoseconds, which only moves when the target sleeps, starting over before every execution
+#[derive(Clone, Default)]
+pub(crate) struct GoClock {
+    ticks: Arc<Mutex<f64>>,
+}
+
+impl GoClock {
+    pub fn reset(&self) {
+        // Ok to unwrap, the lock is never held while anything can panic.
+        *self.ticks.lock().unwrap() = 0.0;
+    }
+
+    fn now(&self) -> f64 {
+        *self.ticks.lock().unwrap()
+    }
+
+    fn sleep(&self, ticks: f64) {
+        *self.ticks.lock().unwrap() += ticks.max(0.0);
+    }
+}
+
+// Define the runtime's clock, for whichever module `module` imports it from
+pub(crate) fn add_to_linker(
+    linker: &mut Linker<()>,
+    store: &mut Store<()>,
+    module: &Module,
+    clock: &GoClock,
+) -> Result<(), SideFuzzError> {
+    for import in module.imports() {
+        let ty = match import.ty() {
+            ExternType::Func(ty) => ty.clone(),
+            _ => continue,
+        };
+        let clock = clock.clone();
+        let func = match import.name() {
+            "runtime.ticks" if ty.params().is_empty() && ty.results() == [ValueType::F64] => {
+                Func::new(
+                    &mut *store,
+                    ty,
+                    move |_caller: Caller<'_, ()>, _params: &[Value], results: &mut [Value]| {
+                        results[0] = Value::F64(clock.now().into());
+                        Ok(())
+                    },
+                )
+            }
+            "runtime.sleepTicks" if ty.params() == [ValueType::F64] && ty.results().is_empty() => {
+                Func::new(
+                    &mut *store,
+                    ty,
+                    move |_caller: Caller<'_, ()>, params: &[Value], _results: &mut [Value]| {
+                        if let Value::F64(ticks) = params[0] {
+                            clock.sleep(ticks.into());
+                        }
+                        Ok(())
+                    },
+                )
+            }
+            _ => continue,
+        };
+        linker.define(import.module(), import.name(), func)?;
+    }
+    Ok(())
+}
+
+// Start the runtime, if the module has a `_start` export. Modules built with -buildmode=c-shared are started by
+// `_initialize` instead, like other WASI reactors.
+pub(crate) fn start(store: &mut Store<()>, instance: &Instance) -> Result<(), SideFuzzError> {
+    let start = match instance
+        .get_export(&*store, START)
+        .and_then(Extern::into_func)
+    {
+        Some(start) => start,
+        None => return Ok(()),
+    };
+    FuelMeter::default()
+        .reset(store, STARTUP_INSTRUCTIONS)
+        .map_err(|_| SideFuzzError::TinyGoStartFailed("the store is out of fuel".to_string()))?;
+    match start.call(&mut *store, &[], &mut []) {
+        Ok(()) => Ok(()),
+        Err(Error::Trap(trap)) if trap.i32_exit_status() == Some(0) => Ok(()),
+        Err(Error::Trap(trap)) if trap.trap_code() == Some(TrapCode::OutOfFuel) => {
+            Err(SideFuzzError::TinyGoStartFailed(format!(
+                "main didn't return within {} instructions, it should be empty",
+                STARTUP_INSTRUCTIONS
+            )))
+        }
+        Err(err) => Err(SideFuzzError::TinyGoStartFailed(err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tinygo_test() {
+        assert!(is_tinygo(vec!["runtime.ticks"], vec!["fuzz"], &[]));
+        assert!(is_tinygo(vec!["fd_write"], vec!["_start", "resume"], &[]));
+        assert!(!is_tinygo(vec!["fd_write"], vec!["_start", "fuzz"], &[]));
+
+        let clock = GoClock::default();
+        clock.sleep(1500.0);
+        clock.sleep(-10.0);
+        assert_eq!(clock.now(), 1500.0);
+        clock.reset();
+        assert_eq!(clock.now(), 0.0);
+    }
+}
@@ -0,0 +1,460 @@
+// This file contains the "trace" subcommand
+
+use crate::cache::{simulate, CacheConfig};
+use crate::errors::SideFuzzError;
+use crate::features::WasmFeatures;
+use crate::stubs::Stubs;
+use crate::taint::{Program, Taint, TaintedUse};
+use crate::tracing::*;
+use crate::wasm::{WasmModule, WasmOptions};
+use std::collections::{BTreeMap, BTreeSet};
+
+// The most call sites listed when their call counts differ
+const MAX_CALL_SITES: usize = 20;
+
+// The most basic blocks listed when their counts differ
+const MAX_BLOCKS: usize = 20;
+
+// The most instructions listed as using tainted values
+const MAX_TAINTED: usize = 20;
+
+pub struct Trace {
+    module: WasmModule,
+    first: Vec<u8>,
+    second: Vec<u8>,
+    kind: TraceKind,
+    cache: CacheConfig,
+}
+
+impl Trace {
+    // Create a new trace command with the given (traced) wasm module and two inputs
+    pub fn new(
+        module: WasmModule,
+        first: Vec<u8>,
+        second: Vec<u8>,
+        kind: TraceKind,
+    ) -> Result<Self, SideFuzzError> {
+        if first.len() != second.len() {
+            return Err(SideFuzzError::InputsDifferentSizes);
+        }
+
+        if first.len() != module.fuzz_len() {
+            return Err(SideFuzzError::InputsWrongSize(module.fuzz_len()));
+        }
+
+        Ok(Trace {
+            module,
+            first,
+            second,
+            kind,
+            cache: CacheConfig::default(),
+        })
+    }
+
+    // Set the cache that is simulated when observing the cache
+    pub fn set_cache_config(&mut self, cache: CacheConfig) {
+        self.cache = cache;
+    }
+
+    pub fn from_file(
+        filename: &str,
+        first: Vec<u8>,
+        second: Vec<u8>,
+        kind: TraceKind,
+    ) -> Result<Self, SideFuzzError> {
+        Self::from_file_with_stubs(filename, &Stubs::default(), first, second, kind)
+    }
+
+    pub fn from_file_with_stubs(
+        filename: &str,
+        stubs: &Stubs,
+        first: Vec<u8>,
+        second: Vec<u8>,
+        kind: TraceKind,
+    ) -> Result<Self, SideFuzzError> {
+        let options = WasmOptions {
+            trace: Some(kind),
+            stubs: stubs.clone(),
+            max_instructions: None,
+            max_input_len: None,
+            timeout: None,
+            features: WasmFeatures::default(),
+            persistent: false,
+            entry: None,
+            tinygo: false,
+            key: None,
+            fuzz_key: false,
+            message: None,
+            fresh_memory: false,
+        };
+        let module = WasmModule::from_file_with_options(filename, options)?;
+        Self::new(module, first, second, kind)
+    }
+
+    pub fn run(&mut self) -> Result<(), SideFuzzError> {
+        let first = self.module.trace(&self.first)?;
+        let second = self.module.trace(&self.second)?;
+
+        match self.kind {
+            TraceKind::Memory => report_memory(&first, &second),
+            TraceKind::Cache => report_cache(&first, &second, &self.cache),
+            TraceKind::Branches => {
+                let sites = block_sites(&self.module.bytes())?;
+                report_branches(&first, &second, &sites);
+            }
+            TraceKind::Calls => {
+                let bytes = self.module.bytes();
+                report_calls(&first, &second, &call_sites(&bytes)?, &bytes);
+            }
+            TraceKind::Counts => {
+                let bytes = self.module.bytes();
+                report_block_counts(&first, &second, &block_sites(&bytes)?, &bytes);
+            }
+            TraceKind::Taint => {
+                let bytes = self.module.bytes();
+                report_taint(&first, &second, &Program::of(&bytes)?, &bytes);
+            }
+            TraceKind::Functions => {
+                let deltas = function_deltas(&first, &second, &self.module.bytes());
+                if deltas.is_empty() {
+                    println!("Every function ran the same number of instructions for both inputs.");
+                } else {
+                    println!("{}", format_function_deltas(&deltas));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn report_memory(first: &ExecutionTrace, second: &ExecutionTrace) {
+    if !first.secrets.is_empty() || !second.secrets.is_empty() {
+        return report_secret_memory(first, second);
+    }
+    let (first, second) = (&first.memory, &second.memory);
+    println!("input 1: {} memory accesses", first.len());
+    println!("input 2: {} memory accesses", second.len());
+
+    match first_divergence(first, second) {
+        None => println!("Memory access patterns are identical for both inputs."),
+        Some(index) => {
+            let differing = first
+                .iter()
+                .zip(second.iter())
+                .filter(|(a, b)| a != b)
+                .count();
+            println!(
+                "Memory access patterns differ, the access pattern depends on the input. {} accesses differ.",
+                differing + (first.len() as isize - second.len() as isize).abs() as usize
+            );
+            println!(
+                "First difference at access {}: input 1 accessed {}, input 2 accessed {}",
+                index,
+                format_address(first.get(index)),
+                format_address(second.get(index))
+            );
+        }
+    }
+}
+
+// Report memory access patterns of a target that declared secrets, only counting those that can depend on them
+fn report_secret_memory(first: &ExecutionTrace, second: &ExecutionTrace) {
+    for (name, trace) in [("input 1", first), ("input 2", second)].iter() {
+        let secret_read = match trace.first_secret_access() {
+            Some(index) => format!("first read a secret at access {}", index),
+            None => "never read a secret".to_string(),
+        };
+        println!(
+            "{}: {} memory accesses, {} secret regions declared, {}",
+            name,
+            trace.memory.len(),
+            trace.secrets.len(),
+            secret_read
+        );
+    }
+
+    match secret_divergence(first, second) {
+        SecretDivergence::Identical => println!("Memory access patterns are identical for both inputs."),
+        SecretDivergence::Public(index) => println!(
+            "Memory access patterns differ at access {}, before either input read a secret. The difference depends on public data only, accesses after it can't be compared.",
+            index
+        ),
+        SecretDivergence::Secret(index) => {
+            println!("Memory access patterns differ after a secret was read, the access pattern depends on the secrets.");
+            println!(
+                "First difference at access {}: input 1 accessed {}, input 2 accessed {}",
+                index,
+                format_address(first.memory.get(index)),
+                format_address(second.memory.get(index))
+            );
+        }
+    }
+}
+
+fn report_cache(first: &ExecutionTrace, second: &ExecutionTrace, config: &CacheConfig) {
+    let first = simulate(&first.memory, config);
+    let second = simulate(&second.memory, config);
+    println!(
+        "Simulating a {} byte cache: {} sets of {} lines of {} bytes",
+        config.line_size * config.sets * config.ways as u64,
+        config.sets,
+        config.ways,
+        config.line_size
+    );
+    for (name, observation) in [("input 1", &first), ("input 2", &second)].iter() {
+        println!(
+            "{}: {} accesses to {} lines in {} sets, {} hits, {} misses",
+            name,
+            observation.accesses.len(),
+            observation.lines.len(),
+            observation.sets.len(),
+            observation.hits,
+            observation.misses
+        );
+    }
+
+    if first.accesses == second.accesses {
+        println!("Cache line usage is identical for both inputs, a cache-timing attacker can't tell them apart.");
+        return;
+    }
+
+    println!("Cache line usage differs, a cache-timing attacker can tell the inputs apart.");
+    if let Some(index) = first_divergence(&first.accesses, &second.accesses) {
+        println!(
+            "First difference at access {}: input 1 used line {}, input 2 used line {}",
+            index,
+            format_line(first.accesses.get(index), config),
+            format_line(second.accesses.get(index), config)
+        );
+    }
+    report_footprint("lines", &first.lines, &second.lines);
+    report_footprint("sets", &first.sets, &second.sets);
+    if first.misses != second.misses {
+        println!(
+            "Miss counts differ by {}, which shows up in the timing of the target itself.",
+            (first.misses as isize - second.misses as isize).abs()
+        );
+    }
+}
+
+// Report the lines or sets only one of the inputs touched
+fn report_footprint(what: &str, first: &BTreeSet<u64>, second: &BTreeSet<u64>) {
+    let only_first = first.difference(second).count();
+    let only_second = second.difference(first).count();
+    if only_first == 0 && only_second == 0 {
+        println!("Both inputs touch the same {}.", what);
+    } else {
+        println!(
+            "{} {} are only touched by input 1, {} only by input 2.",
+            only_first, what, only_second
+        );
+    }
+}
+
+fn format_line(line: Option<&u64>, config: &CacheConfig) -> String {
+    match line {
+        Some(line) => format!(
+            "{} (address 0x{:08x}, set {})",
+            line,
+            line * config.line_size,
+            config.set(*line)
+        ),
+        None => "nothing".to_string(),
+    }
+}
+
+fn report_branches(first: &ExecutionTrace, second: &ExecutionTrace, sites: &[BlockSite]) {
+    let (first, second) = (&first.blocks, &second.blocks);
+    println!("input 1: {} basic blocks executed", first.len());
+    println!("input 2: {} basic blocks executed", second.len());
+
+    match first_divergence(first, second) {
+        None => println!("Control flow is identical for both inputs."),
+        Some(index) => {
+            println!(
+                "Control flow differs, it depends on the input. Execution diverges after {} identical blocks.",
+                index
+            );
+            if index > 0 {
+                println!(
+                    "Last common block: {}",
+                    format_block(Some(&first[index - 1]), sites)
+                );
+            }
+            println!(
+                "input 1 then entered {}",
+                format_block(first.get(index), sites)
+            );
+            println!(
+                "input 2 then entered {}",
+                format_block(second.get(index), sites)
+            );
+        }
+    }
+}
+
+// Call depth and call counts are reported separately, as either can depend on the input without the other
+fn report_calls(first: &ExecutionTrace, second: &ExecutionTrace, sites: &[CallSite], bytes: &[u8]) {
+    println!("input 1: calls nest up to {} deep", first.max_depth);
+    println!("input 2: calls nest up to {} deep", second.max_depth);
+    if first.max_depth == second.max_depth {
+        println!("Call depth is the same for both inputs.");
+    } else {
+        println!("Call depth depends on the input, which shows in how much stack the target uses.");
+    }
+
+    let mut ids: Vec<u32> = first.calls.keys().chain(second.calls.keys()).cloned().collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let differing: Vec<(u32, u64, u64)> = ids
+        .into_iter()
+        .map(|id| {
+            let count = |trace: &ExecutionTrace| trace.calls.get(&id).cloned().unwrap_or(0);
+            (id, count(first), count(second))
+        })
+        .filter(|(_, first, second)| first != second)
+        .collect();
+    if differing.is_empty() {
+        println!("Every call site was taken the same number of times for both inputs.");
+        return;
+    }
+
+    println!(
+        "Call counts depend on the input, {} call sites were taken a different number of times:",
+        differing.len()
+    );
+    let names = crate::binary::function_names(bytes);
+    for (id, first, second) in differing.iter().take(MAX_CALL_SITES) {
+        let site = match sites.get(*id as usize) {
+            Some(site) => match names.get(&site.func) {
+                Some(name) => format!("{} in {}", site, name),
+                None => site.to_string(),
+            },
+            None => format!("call site {}", id),
+        };
+        println!("  {}: {} times for input 1, {} for input 2", site, first, second);
+    }
+    if differing.len() > MAX_CALL_SITES {
+        println!("  and {} more", differing.len() - MAX_CALL_SITES);
+    }
+}
+
+// Blocks whose counts differ, most different first
+fn report_block_counts(first: &ExecutionTrace, second: &ExecutionTrace, sites: &[BlockSite], bytes: &[u8]) {
+    let total = |trace: &ExecutionTrace| trace.block_counts.iter().sum::<u64>();
+    println!("input 1: {} basic blocks executed", total(first));
+    println!("input 2: {} basic blocks executed", total(second));
+
+    let mut differing: Vec<(usize, u64, u64)> = first
+        .block_counts
+        .iter()
+        .zip(second.block_counts.iter())
+        .enumerate()
+        .filter(|(_, (first, second))| first != second)
+        .map(|(id, (first, second))| (id, *first, *second))
+        .collect();
+    if differing.is_empty() {
+        println!("Every basic block ran the same number of times for both inputs.");
+        return;
+    }
+    differing.sort_by_key(|(id, first, second)| (std::cmp::Reverse(first.max(second) - first.min(second)), *id));
+
+    println!(
+        "Control flow depends on the input, {} basic blocks ran a different number of times:",
+        differing.len()
+    );
+    let names = crate::binary::function_names(bytes);
+    for (id, first, second) in differing.iter().take(MAX_BLOCKS) {
+        let site = match sites.get(*id) {
+            Some(site) => match names.get(&site.func) {
+                Some(name) => format!("{} in {}", site, name),
+                None => site.to_string(),
+            },
+            None => format!("block {}", id),
+        };
+        println!("  {}: {} times for input 1, {} for input 2", site, first, second);
+    }
+    if differing.len() > MAX_BLOCKS {
+        println!("  and {} more", differing.len() - MAX_BLOCKS);
+    }
+}
+
+// Instructions that used values computed from the secret inputs, for either input
+fn report_taint(first: &ExecutionTrace, second: &ExecutionTrace, program: &Program, bytes: &[u8]) {
+    let (first, second) = (&first.taint.uses, &second.taint.uses);
+    println!("input 1: {} instructions used values computed from the secret inputs", first.len());
+    println!("input 2: {} instructions used values computed from the secret inputs", second.len());
+
+    let mut ids: Vec<u32> = first.keys().chain(second.keys()).cloned().collect();
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.is_empty() {
+        println!("No branch condition or memory address was computed from the secret inputs.");
+        return;
+    }
+
+    let names = crate::binary::function_names(bytes);
+    for id in ids.iter().take(MAX_TAINTED) {
+        let uses: Vec<&TaintedUse> = first.get(id).into_iter().chain(second.get(id)).collect();
+        let mut taint = Taint::default();
+        for used in uses.iter() {
+            taint.add(&used.taint);
+        }
+        let site = match program.describe(*id) {
+            Some((func, instruction)) => match names.get(&func) {
+                Some(name) => format!("{} in {} (function {})", instruction, name, func),
+                None => format!("{} in function {}", instruction, func),
+            },
+            None => format!("step {}", id),
+        };
+        let count = |uses: &BTreeMap<u32, TaintedUse>| uses.get(id).map_or(0, |used| used.count);
+        println!(
+            "  {}: {} from input bytes {}, {} times for input 1, {} for input 2",
+            site,
+            uses[0].used_as,
+            format_bytes(&taint.bytes()),
+            count(first),
+            count(second)
+        );
+    }
+    if ids.len() > MAX_TAINTED {
+        println!("  and {} more", ids.len() - MAX_TAINTED);
+    }
+}
+
+// Byte indices as ranges, such as "0-3, 7"
+fn format_bytes(bytes: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for byte in bytes {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == *byte => *end = *byte,
+            _ => ranges.push((*byte, *byte)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_block(id: Option<&u32>, sites: &[BlockSite]) -> String {
+    match id.and_then(|id| sites.get(*id as usize)) {
+        Some(site) => site.to_string(),
+        None => "nothing (execution finished)".to_string(),
+    }
+}
+
+fn format_address(address: Option<&u64>) -> String {
+    match address {
+        Some(address) => format!("0x{:08x}", address),
+        None => "nothing".to_string(),
+    }
+}
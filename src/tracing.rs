@@ -0,0 +1,520 @@
+// Execution tracing through module instrumentation
+//
+// Instruction counts only catch timing differences. Tracing records what the target did during an execution,
+// so that secret-dependent behaviour can be found even when instruction counts are identical.
+
+use crate::errors::SideFuzzError;
+use crate::fuel::Fuel;
+use crate::instrument::*;
+use crate::taint::TaintState;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use wasmi::*;
+
+// What to record during execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+    Memory,    // Every linear-memory address loaded from or stored to
+    Branches,  // Every basic block entered
+    Functions, // How many instructions ran in each function
+    Cache,     // Memory accesses, run through a cache simulator
+    Calls,     // How deep calls nest, and how often each call site is taken
+    Counts,    // How often each basic block ran, counted by the module itself
+    Taint,     // Which values computed from the secret inputs reach branches and addresses
+}
+
+impl FromStr for TraceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(TraceKind::Memory),
+            "branches" => Ok(TraceKind::Branches),
+            "functions" => Ok(TraceKind::Functions),
+            "cache" => Ok(TraceKind::Cache),
+            "calls" => Ok(TraceKind::Calls),
+            "counts" => Ok(TraceKind::Counts),
+            "taint" => Ok(TraceKind::Taint),
+            _ => Err(format!("unknown trace kind '{}'", s)),
+        }
+    }
+}
+
+// What was recorded during a single execution
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecutionTrace {
+    pub memory: Vec<u64>,
+    pub secrets: Vec<SecretRegion>,    // Regions the target passed to `sidefuzz::declare_secret`
+    pub taint: TaintState,             // Labels of every value, and the instructions that used secret ones
+    pub blocks: Vec<u32>,              // Indices into the module's `block_sites`
+    pub block_counts: Vec<u64>,        // Times each of the module's `block_sites` ran
+    pub functions: BTreeMap<u32, u64>, // Instructions executed in each function, by function index
+    pub calls: BTreeMap<u32, u64>,     // Times each call site was taken, by index into the module's `call_sites`
+    pub max_depth: u32,                // The most calls that were running at once
+    current_function: Option<(u32, u64)>, // The running function, and fuel consumed when it started running
+    depth: u32,                           // Calls that are running
+}
+
+impl ExecutionTrace {
+    // Control moved to `func` (or execution finished, if None) when `fuel` had been consumed.
+    // Everything since the last switch is attributed to the function that was running.
+    pub(crate) fn switch_function(&mut self, func: Option<u32>, fuel: u64) {
+        if let Some((running, since)) = self.current_function {
+            *self.functions.entry(running).or_insert(0) += fuel.saturating_sub(since);
+        }
+        self.current_function = func.map(|func| (func, fuel));
+    }
+
+    // The call site `id` was taken
+    pub(crate) fn enter_call(&mut self, id: u32) {
+        *self.calls.entry(id).or_insert(0) += 1;
+        self.depth += 1;
+        self.max_depth = self.max_depth.max(self.depth);
+    }
+
+    // A call returned
+    pub(crate) fn leave_call(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    // The function that was running when the trace was taken
+    pub(crate) fn running_function(&self) -> Option<u32> {
+        self.current_function.map(|(func, _)| func)
+    }
+
+    // The target declared `len` bytes at `start` secret
+    pub(crate) fn declare_secret(&mut self, start: u64, len: u64) {
+        self.secrets.push(SecretRegion {
+            start,
+            len,
+            declared_at: self.memory.len(),
+        });
+    }
+
+    // The first memory access into a secret region, counting only accesses made after the region was declared.
+    // Until then nothing the target did can depend on the secrets.
+    pub fn first_secret_access(&self) -> Option<usize> {
+        self.memory.iter().enumerate().position(|(index, address)| {
+            self.secrets
+                .iter()
+                .any(|region| region.declared_at <= index && region.contains(*address))
+        })
+    }
+}
+
+// Memory the target declared secret, while tracing memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretRegion {
+    pub start: u64,
+    pub len: u64,
+    pub declared_at: usize, // How many memory accesses had been recorded when it was declared
+}
+
+impl SecretRegion {
+    fn contains(&self, address: u64) -> bool {
+        address >= self.start && address - self.start < self.len
+    }
+}
+
+// Where two memory traces of a target that declared secrets first differ. Accesses before either execution read a
+// secret depend on public data only, a difference there isn't a leak of the secrets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretDivergence {
+    Identical,
+    Public(usize), // Before any secret was read
+    Secret(usize), // After a secret was read
+}
+
+pub fn secret_divergence(first: &ExecutionTrace, second: &ExecutionTrace) -> SecretDivergence {
+    let index = match first_divergence(&first.memory, &second.memory) {
+        Some(index) => index,
+        None => return SecretDivergence::Identical,
+    };
+    let secret_read = first
+        .first_secret_access()
+        .into_iter()
+        .chain(second.first_secret_access())
+        .min();
+    match secret_read {
+        Some(secret_read) if secret_read < index => SecretDivergence::Secret(index),
+        _ => SecretDivergence::Public(index),
+    }
+}
+
+// How many instructions a function ran for each of two inputs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FunctionDelta {
+    pub func: u32,
+    pub name: Option<String>,
+    pub instructions_1: u64,
+    pub instructions_2: u64,
+}
+
+impl FunctionDelta {
+    pub fn delta(&self) -> i64 {
+        self.instructions_1 as i64 - self.instructions_2 as i64
+    }
+}
+
+// Where a basic block starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSite {
+    pub func: u32,          // Function index
+    pub kind: &'static str, // What starts the block
+    pub ordinal: u32,       // Which block in the function this is
+}
+
+impl std::fmt::Display for BlockSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "function {} block {} ({})",
+            self.func, self.ordinal, self.kind
+        )
+    }
+}
+
+// Where a call is made
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallSite {
+    pub func: u32,      // Function index of the caller
+    pub indirect: bool, // Through a table, so the callee can change
+    pub ordinal: u32,   // Which call in the function this is
+}
+
+impl std::fmt::Display for CallSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "function {} call {}{}",
+            self.func,
+            self.ordinal,
+            if self.indirect { " (indirect)" } else { "" }
+        )
+    }
+}
+
+const MEMORY_HOOKS: &[Hook] = &[Hook {
+    name: "trace_memory",
+    params: &[I32, I32],
+    results: &[],
+}];
+
+// The address, then one local for each type of value that can be stored
+const MEMORY_LOCALS: &[u8] = &[I32, I32, I64, F32, F64];
+
+const BLOCK_HOOKS: &[Hook] = &[Hook {
+    name: "trace_block",
+    params: &[I32],
+    results: &[],
+}];
+
+const FUNCTION_HOOKS: &[Hook] = &[Hook {
+    name: "trace_function",
+    params: &[I32],
+    results: &[],
+}];
+
+const CALL_HOOKS: &[Hook] = &[
+    Hook {
+        name: "trace_call",
+        params: &[I32],
+        results: &[],
+    },
+    Hook {
+        name: "trace_return",
+        params: &[],
+        results: &[],
+    },
+];
+
+// The import targets call to declare memory secret
+pub(crate) const DECLARE_SECRET: &str = "declare_secret";
+
+// Instrument the module to record the given kind of trace
+pub(crate) fn instrument_module(bytes: &[u8], kind: TraceKind) -> Result<Vec<u8>, SideFuzzError> {
+    let instrumented = match kind {
+        TraceKind::Memory | TraceKind::Cache => instrument_memory(bytes),
+        TraceKind::Branches => instrument_blocks(bytes, &mut Vec::new()),
+        TraceKind::Functions => instrument_functions(bytes),
+        TraceKind::Calls => instrument_calls(bytes, &mut Vec::new()),
+        TraceKind::Counts => crate::counters::instrument_block_counts(bytes),
+        TraceKind::Taint => crate::taint::instrument_taint(bytes),
+    };
+    instrumented.ok_or(SideFuzzError::InstrumentationFailed)
+}
+
+// Describe the basic blocks of a module, in the same order as they are numbered in branch traces
+pub fn block_sites(bytes: &[u8]) -> Result<Vec<BlockSite>, SideFuzzError> {
+    let mut sites = Vec::new();
+    instrument_blocks(bytes, &mut sites).ok_or(SideFuzzError::InstrumentationFailed)?;
+    Ok(sites)
+}
+
+// What starts a basic block at `site`, if one starts there. A block starting at an instruction starts after it.
+// Blocks start at the function entry, on entering a block, loop or either arm of an if,
+// after the end of a block, and when a br_if isn't taken.
+pub(crate) fn block_start(site: &Site) -> Option<&'static str> {
+    let op = match site.op {
+        None => return Some("entry"),
+        Some(op) => op,
+    };
+    match op.opcode {
+        OP_BLOCK => Some("block"),
+        OP_LOOP => Some("loop"),
+        OP_IF => Some("if"),
+        OP_ELSE => Some("else"),
+        OP_END if !site.function_end => Some("end"),
+        OP_BR_IF => Some("br_if not taken"),
+        _ => None,
+    }
+}
+
+// Call `trace_block(id)` at the start of every basic block
+fn instrument_blocks(bytes: &[u8], sites: &mut Vec<BlockSite>) -> Option<Vec<u8>> {
+    let mut ordinal = 0;
+    instrument(bytes, BLOCK_HOOKS, &[], |site| {
+        let mut insert = Insert::default();
+        let kind = match block_start(site) {
+            Some(kind) => kind,
+            None => return insert,
+        };
+
+        if site.op.is_none() {
+            ordinal = 0;
+        }
+        let id = sites.len() as i32;
+        sites.push(BlockSite {
+            func: site.func,
+            kind,
+            ordinal,
+        });
+        ordinal += 1;
+
+        let code = if site.op.is_none() {
+            &mut insert.before
+        } else {
+            &mut insert.after
+        };
+        emit_i32_const(code, id);
+        emit_call(code, site.hooks[0]);
+
+        insert
+    })
+}
+
+// Call `trace_function(func)` whenever control moves into a function: on entry, and when a call returns.
+// Calls are the only way control moves between functions, so instructions between two hooks all ran in one function.
+fn instrument_functions(bytes: &[u8]) -> Option<Vec<u8>> {
+    instrument(bytes, FUNCTION_HOOKS, &[], |site| {
+        let mut insert = Insert::default();
+        let code = match site.op {
+            None => &mut insert.before,
+            Some(op) if op.opcode == OP_CALL || op.opcode == OP_CALL_INDIRECT => &mut insert.after,
+            _ => return insert,
+        };
+        emit_i32_const(code, site.func as i32);
+        emit_call(code, site.hooks[0]);
+        insert
+    })
+}
+
+// Describe the call sites of a module, in the same order as they are numbered in call traces
+pub fn call_sites(bytes: &[u8]) -> Result<Vec<CallSite>, SideFuzzError> {
+    let mut sites = Vec::new();
+    instrument_calls(bytes, &mut sites).ok_or(SideFuzzError::InstrumentationFailed)?;
+    Ok(sites)
+}
+
+// Call `trace_call(id)` before every call and `trace_return()` after it. Calls to imports are counted too, so the
+// depth is the number of calls running, including the one into the host.
+fn instrument_calls(bytes: &[u8], sites: &mut Vec<CallSite>) -> Option<Vec<u8>> {
+    let mut ordinal = 0;
+    instrument(bytes, CALL_HOOKS, &[], |site| {
+        let mut insert = Insert::default();
+        let indirect = match site.op {
+            None => {
+                ordinal = 0;
+                return insert;
+            }
+            Some(op) if op.opcode == OP_CALL => false,
+            Some(op) if op.opcode == OP_CALL_INDIRECT => true,
+            _ => return insert,
+        };
+
+        let id = sites.len() as i32;
+        sites.push(CallSite {
+            func: site.func,
+            indirect,
+            ordinal,
+        });
+        ordinal += 1;
+
+        // The arguments stay on the stack underneath the hook's
+        emit_i32_const(&mut insert.before, id);
+        emit_call(&mut insert.before, site.hooks[0]);
+        emit_call(&mut insert.after, site.hooks[1]);
+        insert
+    })
+}
+
+// Call `trace_memory(address, offset)` before every load and store
+fn instrument_memory(bytes: &[u8]) -> Option<Vec<u8>> {
+    instrument(bytes, MEMORY_HOOKS, MEMORY_LOCALS, |site| {
+        let mut insert = Insert::default();
+        let op = match site.op {
+            Some(op) if op.is_load() || op.is_store() => op,
+            _ => return insert,
+        };
+
+        let address = site.locals;
+        let value = op.store_type().map(|ty| match ty {
+            I32 => site.locals + 1,
+            I64 => site.locals + 2,
+            F32 => site.locals + 3,
+            _ => site.locals + 4,
+        });
+
+        // Stores have the value on top of the address, so stash it while we trace the address
+        let code = &mut insert.before;
+        if let Some(value) = value {
+            emit_local_set(code, value);
+        }
+        emit_local_tee(code, address);
+        emit_i32_const(code, op.memarg_offset as i32);
+        emit_call(code, site.hooks[0]);
+        emit_local_get(code, address);
+        if let Some(value) = value {
+            emit_local_get(code, value);
+        }
+
+        insert
+    })
+}
+
+// Define the tracing hooks, recording into `trace`. Every target can declare secrets, they are only recorded when
+// `secrets` is set, as nothing else clears them.
+pub(crate) fn add_to_linker(
+    linker: &mut Linker<()>,
+    store: &mut Store<()>,
+    trace: &Arc<Mutex<ExecutionTrace>>,
+    secrets: bool,
+) -> Result<(), SideFuzzError> {
+    let memory_trace = trace.clone();
+    let trace_memory = Func::wrap(&mut *store, move |address: i32, offset: i32| {
+        let address = address as u32 as u64 + offset as u32 as u64;
+        // Ok to unwrap, the lock is never held while anything can panic.
+        memory_trace.lock().unwrap().memory.push(address);
+    });
+    linker.define(HOOK_MODULE, "trace_memory", trace_memory)?;
+
+    let block_trace = trace.clone();
+    let trace_block = Func::wrap(&mut *store, move |id: i32| {
+        // Ok to unwrap, the lock is never held while anything can panic.
+        block_trace.lock().unwrap().blocks.push(id as u32);
+    });
+    linker.define(HOOK_MODULE, "trace_block", trace_block)?;
+
+    let function_trace = trace.clone();
+    let trace_function = Func::wrap(&mut *store, move |caller: Caller<'_, ()>, func: i32| {
+        let fuel = caller.consumed();
+        // Ok to unwrap, the lock is never held while anything can panic.
+        function_trace
+            .lock()
+            .unwrap()
+            .switch_function(Some(func as u32), fuel);
+    });
+    linker.define(HOOK_MODULE, "trace_function", trace_function)?;
+
+    let call_trace = trace.clone();
+    let trace_call = Func::wrap(&mut *store, move |id: i32| {
+        // Ok to unwrap, the lock is never held while anything can panic.
+        call_trace.lock().unwrap().enter_call(id as u32);
+    });
+    linker.define(HOOK_MODULE, "trace_call", trace_call)?;
+
+    let return_trace = trace.clone();
+    let trace_return = Func::wrap(&mut *store, move || {
+        // Ok to unwrap, the lock is never held while anything can panic.
+        return_trace.lock().unwrap().leave_call();
+    });
+    linker.define(HOOK_MODULE, "trace_return", trace_return)?;
+
+    let secret_trace = trace.clone();
+    let declare_secret = Func::wrap(&mut *store, move |pointer: i32, len: i32| {
+        if secrets {
+            // Ok to unwrap, the lock is never held while anything can panic.
+            secret_trace
+                .lock()
+                .unwrap()
+                .declare_secret(pointer as u32 as u64, len as u32 as u64);
+        }
+    });
+    linker.define(HOOK_MODULE, DECLARE_SECRET, declare_secret)?;
+
+    Ok(())
+}
+
+// Compare per-function instruction counts, returning the functions that differ, largest difference first
+pub fn function_deltas(
+    first: &ExecutionTrace,
+    second: &ExecutionTrace,
+    bytes: &[u8],
+) -> Vec<FunctionDelta> {
+    let names = crate::binary::function_names(bytes);
+    let mut funcs: Vec<u32> = first
+        .functions
+        .keys()
+        .chain(second.functions.keys())
+        .cloned()
+        .collect();
+    funcs.sort_unstable();
+    funcs.dedup();
+
+    let mut deltas: Vec<FunctionDelta> = funcs
+        .into_iter()
+        .map(|func| FunctionDelta {
+            func,
+            name: names.get(&func).cloned(),
+            instructions_1: first.functions.get(&func).cloned().unwrap_or(0),
+            instructions_2: second.functions.get(&func).cloned().unwrap_or(0),
+        })
+        .filter(|delta| delta.delta() != 0)
+        .collect();
+    deltas.sort_by_key(|delta| (std::cmp::Reverse(delta.delta().abs()), delta.func));
+    deltas
+}
+
+// Render function deltas as a table
+pub fn format_function_deltas(deltas: &[FunctionDelta]) -> String {
+    let mut table = format!(
+        "{:>10} {:>14} {:>14}  function",
+        "delta", "input 1", "input 2"
+    );
+    for delta in deltas {
+        let name = match &delta.name {
+            Some(name) => format!("{} ({})", name, delta.func),
+            None => format!("function {}", delta.func),
+        };
+        table += &format!(
+            "\n{:>10} {:>14} {:>14}  {}",
+            delta.delta(),
+            delta.instructions_1,
+            delta.instructions_2,
+            name
+        );
+    }
+    table
+}
+
+// Where two sequences first differ, if they do
+pub(crate) fn first_divergence<T: PartialEq>(first: &[T], second: &[T]) -> Option<usize> {
+    let common = first.len().min(second.len());
+    match (0..common).find(|&i| first[i] != second[i]) {
+        Some(i) => Some(i),
+        None if first.len() != second.len() => Some(common),
+        None => None,
+    }
+}
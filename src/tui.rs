@@ -0,0 +1,195 @@
+// A live statistics dashboard, redrawn in place on stderr
+//
+// This is deliberately plain ANSI escape codes, so that it works in any terminal without extra dependencies.
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+// How many of the most recent progress messages are shown below the statistics
+const LOG_LINES: usize = 8;
+
+// Statistics about a run so far. Fields that don't apply to the current run are None.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub executions: u64, // Executions of the target so far
+    pub generations: Option<u64>,
+    pub best_score: Option<f64>,
+    pub t_value: Option<f64>,   // Of the input pair currently being checked
+    pub diversity: Option<f64>, // Fraction of the population that is unique
+    pub bit_diversity: Option<f64>, // Mean fraction of bits that differ between two individuals
+    pub remaining_executions: Option<u64>, // Estimated executions until a decision is reached
+    pub crashes: Option<u64>,              // Distinct inputs that crashed the target so far
+}
+
+#[derive(Debug)]
+pub(crate) struct Dashboard {
+    started: Instant,
+    stats: Stats,
+    log: VecDeque<String>,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Dashboard {
+            started: Instant::now(),
+            stats: Stats::default(),
+            log: VecDeque::with_capacity(LOG_LINES),
+        }
+    }
+
+    pub fn update(&mut self, stats: &Stats) {
+        self.stats = stats.clone();
+        self.draw();
+    }
+
+    pub fn log(&mut self, message: String) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(message);
+        self.draw();
+    }
+
+    fn draw(&self) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = execution_rate(&self.stats, elapsed);
+        let eta = time_to_decision(&self.stats, rate);
+
+        let mut lines = vec![
+            "sidefuzz".to_string(),
+            String::new(),
+            format!("elapsed             {}", format_duration(elapsed)),
+            format!("executions/sec      {:.0}", rate),
+        ];
+        if let Some(generations) = self.stats.generations {
+            lines.push(format!("generations         {}", generations));
+        }
+        if let Some(best_score) = self.stats.best_score {
+            lines.push(format!("best difference     {}", best_score));
+        }
+        if let Some(diversity) = self.stats.diversity {
+            lines.push(format!("unique individuals  {:.1}%", diversity * 100.0));
+        }
+        if let Some(bit_diversity) = self.stats.bit_diversity {
+            lines.push(format!("bit diversity       {:.1}%", bit_diversity * 100.0));
+        }
+        lines.push(format!(
+            "t-value             {}",
+            self.stats
+                .t_value
+                .map(|t| format!("{:.3}", t))
+                .unwrap_or_else(|| "-".to_string())
+        ));
+        lines.push(format!("time to decision    {}", eta));
+        lines.push(String::new());
+        lines.extend(self.log.iter().cloned());
+
+        // Clear the screen, move to the top left and draw everything
+        let mut screen = String::from("\x1b[2J\x1b[H");
+        for line in lines {
+            screen += &line;
+            screen += "\n";
+        }
+        let stderr = std::io::stderr();
+        let mut stderr = stderr.lock();
+        let _ = stderr.write_all(screen.as_bytes());
+        let _ = stderr.flush();
+    }
+}
+
+// Executions per second, `elapsed` seconds into the run
+pub(crate) fn execution_rate(stats: &Stats, elapsed: f64) -> f64 {
+    if elapsed > 0.0 {
+        stats.executions as f64 / elapsed
+    } else {
+        0.0
+    }
+}
+
+// How long until a decision is reached, at `rate` executions per second
+pub(crate) fn time_to_decision(stats: &Stats, rate: f64) -> String {
+    match stats.remaining_executions {
+        Some(remaining) if rate > 0.0 => format_duration(remaining as f64 / rate),
+        _ => "unknown".to_string(),
+    }
+}
+
+pub(crate) fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    if seconds >= 3600 {
+        format!(
+            "{}h {:02}m {:02}s",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else if seconds >= 60 {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+// Parse a duration such as "30m", "90s" or "1h 30m", in days, hours, minutes and seconds. A number without a unit
+// is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("'{}' isn't a duration such as 90s, 30m or 1h30m", s);
+    let s = s.trim();
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    if s.is_empty() {
+        return Err(invalid());
+    }
+    let mut seconds: u64 = 0;
+    let mut rest = s;
+    while !rest.trim_start().is_empty() {
+        rest = rest.trim_start();
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        let amount: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit = match rest[digits..].chars().next() {
+            Some('d') => 24 * 60 * 60,
+            Some('h') => 60 * 60,
+            Some('m') => 60,
+            Some('s') => 1,
+            _ => return Err(invalid()),
+        };
+        seconds = amount
+            .checked_mul(unit)
+            .and_then(|amount| seconds.checked_add(amount))
+            .ok_or_else(invalid)?;
+        rest = &rest[digits + 1..];
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+// Executions DudeCT still needs before the t-value reaches `threshold`.
+// The t-value grows with the square root of the number of samples, if there is a real difference.
+pub(crate) fn remaining_samples(t: f64, samples: usize, threshold: f64) -> Option<u64> {
+    if t <= 0.0 || !t.is_finite() {
+        return None;
+    }
+    let needed = samples as f64 * (threshold / t).powi(2);
+    Some((needed - samples as f64).max(0.0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaining_samples_test() {
+        // Doubling the t-value takes four times the samples
+        assert_eq!(remaining_samples(2.0, 1000, 4.0), Some(3000));
+        assert_eq!(remaining_samples(5.0, 1000, 4.0), Some(0));
+        assert_eq!(remaining_samples(0.0, 1000, 4.0), None);
+        assert_eq!(format_duration(3725.0), "1h 02m 05s");
+        assert_eq!(parse_duration("1h 02m 05s"), Ok(Duration::from_secs(3725)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+}
@@ -1,7 +1,11 @@
 // Misc utility functions used by various parts of the program
 
-use crate::wasm::WasmModule;
+use crate::scores::Scorer;
+use crate::target::Target;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
 use std::f64::{NAN, NEG_INFINITY};
+use std::ops::Range;
 
 #[derive(Debug, Clone, Default)]
 pub struct InputPair {
@@ -20,35 +24,31 @@ pub struct ScoredInputPair {
 
 impl ScoredInputPair {
     pub fn generate(
-        module: &mut WasmModule,
+        module: &mut dyn Target,
         first: Vec<u8>,
         second: Vec<u8>,
         is_str: bool,
     ) -> Self {
-        // First
-        let first_instructions = module.count_instructions(&first);
-        let first_instructions = match first_instructions {
-            Ok(count) => count,
-            Err(_) => {
-                // WASM trapped, score is negative infinity
-                return ScoredInputPair {
-                    score: NEG_INFINITY,
-                    highest: NAN,
-                    lowest: NAN,
-                    pair: InputPair {
-                        first,
-                        second,
-                        is_str,
-                    },
-                };
-            }
+        // The second input isn't run if the first one crashed
+        let first_instructions = module.count_instructions(&first).ok();
+        let second_instructions = match first_instructions {
+            Some(_) => module.count_instructions(&second).ok(),
+            None => None,
         };
+        Self::from_counts(first, second, is_str, first_instructions, second_instructions)
+    }
 
-        // Second
-        let second_instructions = module.count_instructions(&second);
-        let second_instructions = match second_instructions {
-            Ok(count) => count,
-            Err(_) => {
+    // Score a pair from the instruction counts of its inputs, None if that input crashed
+    pub fn from_counts(
+        first: Vec<u8>,
+        second: Vec<u8>,
+        is_str: bool,
+        first_instructions: Option<u64>,
+        second_instructions: Option<u64>,
+    ) -> Self {
+        let (first_instructions, second_instructions) = match (first_instructions, second_instructions) {
+            (Some(first_instructions), Some(second_instructions)) => (first_instructions, second_instructions),
+            _ => {
                 // WASM trapped, score is negative infinity
                 return ScoredInputPair {
                     score: NEG_INFINITY,
@@ -90,6 +90,77 @@ impl ScoredInputPair {
     }
 }
 
+// Score every pair, counting all of their inputs together so targets that can run batches of inputs do
+pub(crate) fn score_pairs(
+    module: &mut dyn Target,
+    pairs: Vec<InputPair>,
+    scorer: &mut Scorer,
+) -> Vec<ScoredInputPair> {
+    let inputs: Vec<&[u8]> = pairs
+        .iter()
+        .flat_map(|pair| vec![pair.first.as_slice(), pair.second.as_slice()])
+        .collect();
+    let counts: Vec<Option<u64>> = module
+        .count_batch(&inputs)
+        .into_iter()
+        .map(|count| count.ok())
+        .collect();
+    scored_from_counts(pairs, &counts, scorer)
+}
+
+// Score every pair from the counts of its inputs, the first and second input of each pair in turn.
+// None is a crash.
+pub(crate) fn scored_from_counts(
+    pairs: Vec<InputPair>,
+    counts: &[Option<u64>],
+    scorer: &mut Scorer,
+) -> Vec<ScoredInputPair> {
+    pairs
+        .into_iter()
+        .zip(counts.chunks(2))
+        .map(|(pair, counts)| scorer.scored(pair, counts[0], counts[1]))
+        .collect()
+}
+
+// Generate a random input. String inputs are alphanumeric ascii, so they are always valid utf8.
+pub(crate) fn random_input<R: Rng>(rng: &mut R, len: usize, is_str: bool) -> Vec<u8> {
+    if is_str {
+        let input: String = (&mut *rng).sample_iter(&Alphanumeric).take(len).collect();
+        input.into_bytes()
+    } else {
+        (0..len).map(|_| rng.gen::<u8>()).collect()
+    }
+}
+
+// The byte ranges of the public inputs, given the length of each input and whether it is public
+pub(crate) fn public_ranges(segments: &[usize], public: &[bool]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    for (len, public) in segments.iter().zip(public.iter()) {
+        if *public && *len > 0 {
+            ranges.push(offset..offset + len);
+        }
+        offset += len;
+    }
+    ranges
+}
+
+// Copy the public parts of `from` into `to`, so that the two inputs only differ in their secret parts.
+// Variable-length inputs may end partway through a public part, only the bytes both inputs have are copied.
+pub(crate) fn share_public(public: &[Range<usize>], from: &[u8], to: &mut [u8]) {
+    let len = from.len().min(to.len());
+    for range in public.iter() {
+        let range = range.start.min(len)..range.end.min(len);
+        to[range.clone()].copy_from_slice(&from[range]);
+    }
+}
+
+// Sort scored input pairs from most fit to least fit
+pub(crate) fn sort_by_score(scored: &mut [ScoredInputPair]) {
+    // Unwrap OK since score cannot be NAN.
+    scored.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+}
+
 // Given a t-value, the the p-value from it.
 //
 // This currently uses t-tables, in the future it will use an actual formula.
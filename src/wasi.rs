@@ -0,0 +1,145 @@
+// A minimal WASI preview1 shim.
+//
+// Targets compiled for wasm32-wasi import a handful of functions from "wasi_snapshot_preview1" as soon
+// as they pull in std. None of them are relevant to fuzzing, so we provide deterministic stubs:
+// clocks are frozen at zero, randomness comes from a seeded generator that starts over before every execution,
+// output is discarded and the environment is empty.
+// Keeping these deterministic is important, any variance here would show up as noise in instruction counts.
+
+use crate::errors::SideFuzzError;
+use crate::stubs::Entropy;
+use wasmi::core::Trap;
+use wasmi::*;
+
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+// WASI errno values
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_BADF: i32 = 8;
+const ERRNO_FAULT: i32 = 21;
+
+// Add the WASI shim to the linker
+pub(crate) fn add_to_linker(
+    linker: &mut Linker<()>,
+    store: &mut Store<()>,
+    entropy: &Entropy,
+) -> Result<(), SideFuzzError> {
+    // Clocks are frozen at zero
+    let clock_time_get = Func::wrap(
+        &mut *store,
+        |mut caller: Caller<'_, ()>, _clock_id: i32, _precision: i64, time_ptr: i32| -> i32 {
+            write_bytes(&mut caller, time_ptr, &0u64.to_le_bytes())
+        },
+    );
+    linker.define(WASI_MODULE, "clock_time_get", clock_time_get)?;
+
+    let clock_res_get = Func::wrap(
+        &mut *store,
+        |mut caller: Caller<'_, ()>, _clock_id: i32, res_ptr: i32| -> i32 {
+            write_bytes(&mut caller, res_ptr, &1u64.to_le_bytes())
+        },
+    );
+    linker.define(WASI_MODULE, "clock_res_get", clock_res_get)?;
+
+    // Randomness is the same in every execution
+    let entropy = entropy.clone();
+    let random_get = Func::wrap(
+        &mut *store,
+        move |mut caller: Caller<'_, ()>, buf_ptr: i32, buf_len: i32| -> i32 {
+            let mut buf = vec![0; buf_len as u32 as usize];
+            entropy.fill(&mut buf);
+            write_bytes(&mut caller, buf_ptr, &buf)
+        },
+    );
+    linker.define(WASI_MODULE, "random_get", random_get)?;
+
+    // Writes are discarded, but we report them as fully written so the target doesn't retry
+    let fd_write = Func::wrap(
+        &mut *store,
+        |mut caller: Caller<'_, ()>,
+         _fd: i32,
+         iovs_ptr: i32,
+         iovs_len: i32,
+         nwritten_ptr: i32|
+         -> i32 {
+            let mut written: u32 = 0;
+            for i in 0..iovs_len as u32 {
+                // Each iovec is a (ptr: u32, len: u32) pair, we only need the length
+                let mut len = [0u8; 4];
+                let offset = iovs_ptr as u32 as usize + (i as usize * 8) + 4;
+                if read_bytes(&caller, offset, &mut len).is_err() {
+                    return ERRNO_FAULT;
+                }
+                written = written.wrapping_add(u32::from_le_bytes(len));
+            }
+            write_bytes(&mut caller, nwritten_ptr, &written.to_le_bytes())
+        },
+    );
+    linker.define(WASI_MODULE, "fd_write", fd_write)?;
+
+    // The environment and arguments are empty
+    for (getter, sizes_getter) in &[
+        ("environ_get", "environ_sizes_get"),
+        ("args_get", "args_sizes_get"),
+    ] {
+        let get = Func::wrap(&mut *store, |_: i32, _: i32| -> i32 { ERRNO_SUCCESS });
+        linker.define(WASI_MODULE, getter, get)?;
+
+        let sizes_get = Func::wrap(
+            &mut *store,
+            |mut caller: Caller<'_, ()>, count_ptr: i32, size_ptr: i32| -> i32 {
+                let result = write_bytes(&mut caller, count_ptr, &0u32.to_le_bytes());
+                if result != ERRNO_SUCCESS {
+                    return result;
+                }
+                write_bytes(&mut caller, size_ptr, &0u32.to_le_bytes())
+            },
+        );
+        linker.define(WASI_MODULE, sizes_getter, sizes_get)?;
+    }
+
+    // There are no files
+    let fd_close = Func::wrap(&mut *store, |_fd: i32| -> i32 { ERRNO_BADF });
+    linker.define(WASI_MODULE, "fd_close", fd_close)?;
+
+    let fd_fdstat_get = Func::wrap(&mut *store, |_fd: i32, _stat_ptr: i32| -> i32 {
+        ERRNO_BADF
+    });
+    linker.define(WASI_MODULE, "fd_fdstat_get", fd_fdstat_get)?;
+
+    let fd_seek = Func::wrap(
+        &mut *store,
+        |_fd: i32, _offset: i64, _whence: i32, _new_offset_ptr: i32| -> i32 { ERRNO_BADF },
+    );
+    linker.define(WASI_MODULE, "fd_seek", fd_seek)?;
+
+    let sched_yield = Func::wrap(&mut *store, || -> i32 { ERRNO_SUCCESS });
+    linker.define(WASI_MODULE, "sched_yield", sched_yield)?;
+
+    // Exiting traps, which the fuzzer treats the same as a panic
+    let proc_exit = Func::wrap(&mut *store, |code: i32| -> Result<(), Trap> {
+        Err(Trap::i32_exit(code))
+    });
+    linker.define(WASI_MODULE, "proc_exit", proc_exit)?;
+
+    Ok(())
+}
+
+fn write_bytes(caller: &mut Caller<'_, ()>, ptr: i32, bytes: &[u8]) -> i32 {
+    let memory = match caller.get_export("memory").and_then(Extern::into_memory) {
+        Some(memory) => memory,
+        None => return ERRNO_FAULT,
+    };
+    match memory.write(&mut *caller, ptr as u32 as usize, bytes) {
+        Ok(_) => ERRNO_SUCCESS,
+        Err(_) => ERRNO_FAULT,
+    }
+}
+
+fn read_bytes(caller: &Caller<'_, ()>, offset: usize, buf: &mut [u8]) -> Result<(), ()> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or(())?;
+    memory.read(caller, offset, buf).map_err(|_| ())
+}
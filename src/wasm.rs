@@ -1,31 +1,211 @@
+use crate::assemblyscript::{InputKind, INPUT_KIND};
+use crate::bindgen::Exports;
+use crate::counters::BlockCounters;
 use crate::errors::SideFuzzError;
+use crate::features::WasmFeatures;
+use crate::fuel::{Fuel, FuelMeter};
+use crate::persistent::{decode_results, BatchBuffers, BATCH_EXPORTS};
+use crate::stubs::{Entropy, Stubs};
+use crate::tinygo::GoClock;
+use crate::tracing::{ExecutionTrace, TraceKind};
 use float_duration::{FloatDuration, TimePoint};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::convert::TryFrom;
 use std::fs::File;
 use std::io::prelude::*;
-use std::time::Instant;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use wasmi::*;
 
+// Executions stop after this many instructions unless configured otherwise, so infinite loops can't hang fuzzing
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 1_000_000_000;
+
+// Executions shorter than this aren't used to work out how many instructions fit in the timeout
+const MIN_CALIBRATION_TIME: Duration = Duration::from_micros(10);
+
+// Targets may ask for at most this many bytes of input unless configured otherwise
+pub const DEFAULT_MAX_INPUT_LEN: usize = 1024;
+
+// Instructions startup code such as `_initialize` may take, which aren't part of any execution's count
+const STARTUP_BUDGET: u64 = 1_000_000_000;
+
+// Instructions allowed for reading an execution's output, which aren't part of its count
+const OUTPUT_BUDGET: u64 = 10_000;
+
+// Options controlling how a module is instantiated.
+// These are kept with the module so that clones and reboots behave the same.
+#[derive(Debug, Clone, Default)]
+pub struct WasmOptions {
+    // Instrument the module to record a trace of each execution.
+    // This changes instruction counts, so traced modules must not be used for counting.
+    pub trace: Option<TraceKind>,
+    // How imports that aren't otherwise provided are stubbed
+    pub stubs: Stubs,
+    // Instruction budget for each execution, defaults to DEFAULT_MAX_INSTRUCTIONS
+    pub max_instructions: Option<u64>,
+    // Longest input a target may ask for, defaults to DEFAULT_MAX_INPUT_LEN
+    pub max_input_len: Option<usize>,
+    // Wall-clock time each execution may take
+    pub timeout: Option<Duration>,
+    // The proposals modules may use
+    pub features: WasmFeatures,
+    // Run inputs in batches through the module's "fuzz_batch" export
+    pub persistent: bool,
+    // The export to fuzz, for modules with several entry points. Defaults to "fuzz".
+    pub entry: Option<String>,
+    // Start Go's runtime first, for TinyGo modules that aren't recognized as such
+    pub tinygo: bool,
+    // A fixed secret written to the module's "key_pointer" export once, separately from the fuzzed input
+    pub key: Option<Vec<u8>>,
+    // Fuzz the key instead of the input, which is written once with `message` or zeros
+    pub fuzz_key: bool,
+    pub message: Option<Vec<u8>>,
+    // Restore memory and globals to the snapshot taken after instantiation before every execution, so no state
+    // is left behind by earlier executions
+    pub fresh_memory: bool,
+}
+
 pub struct WasmModule {
     module: Vec<u8>,
+    options: WasmOptions,
     store: Store<()>,
     instance: Instance,
     memory: Memory,
-    fuzz_ptr: usize,
+    fuzz_segments: Vec<(usize, usize)>, // (pointer, length) of each input, in order
+    public_segments: Vec<bool>,         // Whether each input is public
     fuzz_len: u32,
     input_is_str: bool,
+    input_kind: InputKind, // How the input is written, for AssemblyScript targets
+    snapshot: Option<Snapshot>,
+    rng: StdRng,
+    trace: Arc<Mutex<ExecutionTrace>>,
+    exports: Exports, // Where the exports are, for modules that rename them
+    actual_len: Option<ActualLen>, // Only set for variable-length targets
+    instructions_per_second: f64,  // The fastest rate seen, used to turn the timeout into an instruction budget
+    meter: FuelMeter,
+    batch: Option<BatchBuffers>, // Only set in persistent mode
+    entropy: Entropy,            // Stands in for imported randomness
+    clock: GoClock,              // Stands in for the Go runtime's clock
+    block_counters: Option<BlockCounters>, // Only set for modules traced with `TraceKind::Counts`
+}
+
+// Where a variable-length target reads the length of the current input from
+#[derive(Clone, Copy)]
+enum ActualLen {
+    Global(Global), // A mutable i32 global
+    Address(usize), // An i32 in linear memory, at the address held by an immutable global. This is how Rust exports statics.
+}
+
+// A copy of linear memory and mutable globals, used to quickly reset the instance to a known good state
+struct Snapshot {
+    memory: Vec<u8>,
+    globals: Vec<(Global, Value)>,
 }
 
 impl WasmModule {
     pub fn new(module: Vec<u8>) -> Result<Self, SideFuzzError> {
-	let engine = Engine::new(&Config::default().consume_fuel(true));
+        Self::with_options(module, WasmOptions::default())
+    }
 
-        let parsed = Module::new(&engine, module.as_slice())?;
+    pub fn with_options(module: Vec<u8>, options: WasmOptions) -> Result<Self, SideFuzzError> {
+        let module = crate::component::core_module(module)?;
+        if let Some(feature) = unsupported_memories(&module) {
+            return Err(SideFuzzError::UnsupportedWasmFeature(feature));
+        }
+        if options.persistent && options.fresh_memory {
+            return Err(SideFuzzError::PersistentModeUnsupported(
+                "fresh memory restores memory before every input, but batches run many inputs in one call".to_string(),
+            ));
+        }
+        crate::meta::check_compatible(&module)?;
+        let mut config = options.features.config();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+
+        let instrumented = match options.trace {
+            Some(kind) => Some(crate::tracing::instrument_module(&module, kind)?),
+            None => None,
+        };
+        let instrumented = instrumented.as_ref().unwrap_or(&module);
+        let block_counters = match options.trace {
+            Some(TraceKind::Counts) => BlockCounters::of(&module),
+            _ => None,
+        };
+
+        // Export all mutable globals so they can be included in snapshots
+        let prepared = crate::binary::export_mutable_globals(instrumented);
+        let prepared = prepared.as_ref().unwrap_or(instrumented);
+
+        let parsed = Module::new(&engine, prepared.as_slice())?;
 	let mut store = Store::new(parsed.engine(), ());
-        let instance = Linker::<()>::new().instantiate(&mut store, &parsed)?.ensure_no_start(&mut store)?;
+
+        // Targets built for wasm32-wasi import WASI functions, satisfy them with stubs
+        let mut linker = Linker::<()>::new();
+        let entropy = Entropy::new(options.stubs.seed());
+        crate::wasi::add_to_linker(&mut linker, &mut store, &entropy)?;
+
+        // Hooks called by instrumented modules
+        let trace = Arc::new(Mutex::new(ExecutionTrace::default()));
+        let secrets = options.trace == Some(TraceKind::Memory);
+        crate::tracing::add_to_linker(&mut linker, &mut store, &trace, secrets)?;
+        if options.trace == Some(TraceKind::Taint) {
+            crate::taint::add_to_linker(&mut linker, &mut store, &trace, &module)?;
+        }
+        crate::persistent::add_to_linker(&mut linker, &mut store)?;
+
+        // TinyGo modules import the Go runtime's clock
+        let clock = GoClock::default();
+        crate::tinygo::add_to_linker(&mut linker, &mut store, &parsed, &clock)?;
+
+        // Anything else the module imports is stubbed out
+        crate::stubs::add_to_linker(&mut linker, &mut store, &parsed, &options.stubs, &entropy)?;
+
+        // Go's runtime may also have a start function, which runs on instantiation and needs fuel
+        let tinygo = options.tinygo
+            || crate::tinygo::is_tinygo(
+                parsed.imports().map(|import| import.name()),
+                parsed.exports().map(|export| export.name()),
+                &module,
+            );
+        let instance = linker.instantiate(&mut store, &parsed)?;
+        let instance = if tinygo {
+            // Ok to expect, a fresh store has consumed no fuel
+            FuelMeter::default()
+                .reset(&mut store, crate::tinygo::STARTUP_INSTRUCTIONS)
+                .expect("Could not meter fresh wasm store.");
+            instance.start(&mut store)?
+        } else {
+            instance.ensure_no_start(&mut store)?
+        };
+
+        // WASI reactors expect `_initialize` to be called before anything else
+        if let Some(initialize) = instance.get_export(&store, "_initialize").and_then(Extern::into_func) {
+            run_startup(&mut store, "_initialize", initialize)?;
+        }
+
+        // wasm-bindgen modules expect their JS glue to call `__wbindgen_start` first
+        if crate::bindgen::is_bindgen(&parsed) {
+            let start = instance.get_export(&store, crate::bindgen::BINDGEN_START);
+            if let Some(start) = start.and_then(Extern::into_func) {
+                run_startup(&mut store, crate::bindgen::BINDGEN_START, start)?;
+            }
+        }
+
+        // Go's runtime is started by `_start`, before any of its exports can be called
+        if tinygo {
+            crate::tinygo::start(&mut store, &instance)?;
+        }
+        let names: Vec<&str> = instance.exports(&store).map(|export| export.name()).collect();
+        let mut exports = Exports::resolve(names.iter().copied());
+        if let Some(entry) = &options.entry {
+            exports
+                .set_entry(&names, entry)
+                .map_err(|entries| SideFuzzError::NoSuchEntry(entry.clone(), entries.join(", ")))?;
+        }
 
         // Get memory instance exported by name 'mem' from the module instance.
-        let memory = instance.get_export(&store, "memory");
+        let memory = instance.get_export(&store, exports.name("memory"));
         let memory = memory.ok_or(SideFuzzError::WasmModuleNoMemory)?;
         let memory = memory
             .into_memory()
@@ -33,38 +213,100 @@ impl WasmModule {
 
         let mut wasm_module = Self {
             module: module,
+            options: options,
 	    store: store,
             instance: instance,
             memory: memory,
-            fuzz_ptr: 0,
+            fuzz_segments: Vec::new(),
+            public_segments: Vec::new(),
             fuzz_len: 0,
             input_is_str: false,
+            input_kind: InputKind::Bytes,
+            snapshot: None,
+            rng: StdRng::seed_from_u64(0),
+            trace: trace,
+            exports,
+            actual_len: None,
+            instructions_per_second: 0.0,
+            meter: FuelMeter::default(),
+            batch: None,
+            entropy,
+            clock,
+            block_counters,
         };
 
         // Set input pointers
         wasm_module.set_input_pointer()?;
 
+        // The key goes in before priming, in case the target derives anything from it lazily
+        wasm_module.set_key()?;
+
         // Prime lazy statics
         wasm_module.prime_lazy_statics()?;
 
+        // The batch buffers are allocated when first asked for, which must happen before the snapshot
+        if wasm_module.options.persistent {
+            wasm_module.set_up_batch()?;
+        }
+
+        // Priming ran code that counted its blocks, they start from zero in the snapshot
+        if let Some(counters) = wasm_module.block_counters {
+            counters.reset(&wasm_module.instance, &mut wasm_module.store)?;
+        }
+
+        // Snapshot the primed state so we can restore it cheaply after a crash
+        wasm_module.take_snapshot();
+
         Ok(wasm_module)
     }
 
     pub fn from_file(filename: &str) -> Result<Self, SideFuzzError> {
+        Self::from_file_with_options(filename, WasmOptions::default())
+    }
+
+    pub fn from_file_with_options(
+        filename: &str,
+        options: WasmOptions,
+    ) -> Result<Self, SideFuzzError> {
         let mut file = File::open(filename)?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
-        Ok(Self::new(buf)?)
+        Ok(Self::with_options(buf, options)?)
+    }
+
+    pub fn options(&self) -> &WasmOptions {
+        &self.options
     }
 
     pub fn fuzz_len(&self) -> usize {
         self.fuzz_len as usize
     }
 
+    // Lengths of each of the target's inputs. Targets with a single input have a single segment.
+    // The fuzzing input is all segments concatenated together.
+    pub fn input_segments(&self) -> Vec<usize> {
+        self.fuzz_segments.iter().map(|(_, len)| *len).collect()
+    }
+
     pub fn input_is_str(&self) -> bool {
         self.input_is_str
     }
 
+    // Whether each of `input_segments` is public, from the optional "input_is_public_at" export
+    pub fn public_segments(&self) -> Vec<bool> {
+        self.public_segments.clone()
+    }
+
+    // Whether inputs can be any length up to `fuzz_len`, instead of exactly `fuzz_len`
+    pub fn variable_len(&self) -> bool {
+        self.actual_len.is_some()
+    }
+
+
+    // Reseed the random number generator used for generating throwaway inputs
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
 
     pub fn bytes(&self) -> Vec<u8> {
         self.module.clone()
@@ -72,38 +314,370 @@ impl WasmModule {
 
     // Count instructions for a given input
     pub fn count_instructions(&mut self, input: &[u8]) -> Result<u64, SideFuzzError> {
-        self.memory
-            .write(&mut self.store, self.fuzz_ptr, input)
-            .map_err(|e| SideFuzzError::MemorySetError(e.into()))?;
-        self.store.add_fuel(u64::MAX - self.store.fuel_consumed().unwrap()).unwrap();
-        let result = self.instance.get_export(&self.store, "fuzz").ok_or(SideFuzzError::WasmModuleNoInputPointer)?.into_func().ok_or(SideFuzzError::WasmModuleNoInputPointer)?.call(&mut self.store, &[], &mut []);
+        // The interpreter can't be interrupted, so the timeout is enforced by also stopping executions once they
+        // have run as many instructions as fit in it
+        let max_instructions = self.options.max_instructions.unwrap_or(DEFAULT_MAX_INSTRUCTIONS);
+        let timeout_budget = self.timeout_budget().filter(|budget| *budget < max_instructions);
+        let budget = timeout_budget.unwrap_or(max_instructions);
+
+        // Executions made while instantiating come before the snapshot, and can't be restored to it
+        if self.options.fresh_memory && self.snapshot.is_some() {
+            self.restore();
+        }
+
+        // Consumed fuel is never reset, so a store that has run for long enough is replaced by a fresh one.
+        // This happens before the input is written, as rebooting would lose it.
+        if self.meter.reset(&mut self.store, budget).is_err() {
+            self.reboot();
+            // Ok to expect, a fresh store has consumed very little fuel
+            self.meter
+                .reset(&mut self.store, budget)
+                .expect("Could not meter fresh wasm store.");
+        }
+
+        if self.input_kind != InputKind::Bytes {
+            // AssemblyScript objects hold a single input, and their header says how long it is
+            let input = &input[..input.len().min(self.fuzz_len as usize)];
+            let ptr = self.fuzz_segments.first().map_or(0, |(ptr, _)| *ptr);
+            for (address, bytes) in self.input_kind.writes(ptr, input) {
+                self.memory
+                    .write(&mut self.store, address, &bytes)
+                    .map_err(|e| SideFuzzError::MemorySetError(e.into()))?;
+            }
+            if let Some(actual_len) = self.actual_len {
+                self.set_actual_len(actual_len, input.len())?;
+            }
+        } else if let Some(actual_len) = self.actual_len {
+            // Variable-length targets have a single input, and only read as much of it as they are told to
+            let input = &input[..input.len().min(self.fuzz_len as usize)];
+            let ptr = self.fuzz_segments.first().map_or(0, |(ptr, _)| *ptr);
+            self.memory
+                .write(&mut self.store, ptr, input)
+                .map_err(|e| SideFuzzError::MemorySetError(e.into()))?;
+            self.set_actual_len(actual_len, input.len())?;
+        } else {
+            let mut offset = 0;
+            for (ptr, len) in self.fuzz_segments.iter() {
+                let segment = match input.get(offset..offset + len) {
+                    Some(segment) => segment,
+                    None => break,
+                };
+                self.memory
+                    .write(&mut self.store, *ptr, segment)
+                    .map_err(|e| SideFuzzError::MemorySetError(e.into()))?;
+                offset += len;
+            }
+        }
+        let start_time = self.options.timeout.map(|_| Instant::now());
+        self.entropy.reset();
+        self.clock.reset();
+
+        let result = self.instance.get_export(&self.store, self.exports.name("fuzz")).ok_or(SideFuzzError::WasmModuleNoFuzz)?.into_func().ok_or(SideFuzzError::WasmModuleNoFuzz)?.call(&mut self.store, &[], &mut []);
         if let Err(err) = result {
-            // If we've got a MemoryAccessOutOfBounds error, then we've corrupted our memory.
-            // In a real application this would be a crash, so reboot the instance and start over.
             if let wasmi::Error::Trap(trap) = &err {
-                if let Some(wasmi::core::TrapCode::MemoryOutOfBounds) = trap.trap_code() {
-                    self.reboot();
+                match trap.trap_code() {
+                    // If we've got a MemoryAccessOutOfBounds error, then we've corrupted our memory.
+                    // In a real application this would be a crash, so reboot the instance and start over.
+                    Some(wasmi::core::TrapCode::MemoryOutOfBounds) => self.restore(),
+                    // Execution was abandoned part way through, so memory may be in any state
+                    Some(wasmi::core::TrapCode::OutOfFuel) => {
+                        self.restore();
+                        if let (Some(timeout), Some(_)) = (self.options.timeout, timeout_budget) {
+                            return Err(SideFuzzError::Timeout(timeout.as_millis() as u64));
+                        }
+                        return Err(SideFuzzError::InstructionBudgetExhausted(budget));
+                    }
+                    _ => {}
                 }
             }
             return Err(SideFuzzError::WasmError(err));
         }
-        let count = u64::MAX - self.store.fuel_consumed().unwrap();
+        let count = self.meter.consumed(&self.store);
+
+        if let (Some(timeout), Some(start_time)) = (self.options.timeout, start_time) {
+            let elapsed = start_time.elapsed();
+            // Too short executions are mostly timer resolution
+            if elapsed >= MIN_CALIBRATION_TIME {
+                let rate = count as f64 / elapsed.as_secs_f64();
+                if rate > self.instructions_per_second {
+                    self.instructions_per_second = rate;
+                }
+            }
+            // Executions that are slow without running many instructions, in host functions for example
+            if elapsed > timeout {
+                return Err(SideFuzzError::Timeout(timeout.as_millis() as u64));
+            }
+        }
 
         Ok(count)
     }
 
+    // Count instructions for a given input, and read what the execution passed to `sidefuzz::set_output`.
+    // The output is None for targets that don't export one, and for executions that didn't set it.
+    pub fn run_with_output(&mut self, input: &[u8]) -> Result<(u64, Option<Vec<u8>>), SideFuzzError> {
+        let count = self.count_instructions(input)?;
+        if self.instance.get_export(&self.store, self.exports.name("output_len")).is_none() {
+            return Ok((count, None));
+        }
+
+        // The execution may have used up all of its fuel, and reading the output needs a little more
+        self.meter
+            .reset(&mut self.store, OUTPUT_BUDGET)
+            .map_err(|_| SideFuzzError::WasmModuleBadOutput)?;
+        let len = self.call_i32("output_len", &[]).map_err(|_| SideFuzzError::WasmModuleBadOutput)?;
+        if len < 0 {
+            return Ok((count, None));
+        }
+        let ptr = self.call_i32("output_pointer", &[]).map_err(|_| SideFuzzError::WasmModuleBadOutput)?;
+        let mut output = vec![0; len as usize];
+        self.memory
+            .read(&self.store, ptr as u32 as usize, &mut output)
+            .map_err(|_| SideFuzzError::WasmModuleBadOutput)?;
+        Ok((count, Some(output)))
+    }
+
+    // Count instructions for several inputs. In persistent mode they are run in batches, and a batch that crashes is
+    // run again one input at a time, so that each input still gets its own result.
+    pub fn count_batch(&mut self, inputs: &[&[u8]]) -> Vec<Result<u64, SideFuzzError>> {
+        let mut counts = Vec::with_capacity(inputs.len());
+        for chunk in inputs.chunks(self.batch_capacity()) {
+            let fuzz_len = self.fuzz_len as usize;
+            let batched = match self.batch {
+                Some(_) if chunk.len() > 1 && chunk.iter().all(|input| input.len() == fuzz_len) => {
+                    self.run_batch(chunk).ok()
+                }
+                _ => None,
+            };
+            match batched {
+                Some(batched) => counts.extend(batched.into_iter().map(Ok)),
+                None => {
+                    for input in chunk.iter() {
+                        counts.push(self.count_instructions(input));
+                    }
+                }
+            }
+        }
+        counts
+    }
+
+    // The most inputs run in a single call, 1 unless in persistent mode
+    pub fn batch_capacity(&self) -> usize {
+        self.batch.map_or(1, |batch| batch.capacity)
+    }
+
+    // Find the batch buffers, and calibrate how much a batch adds to each count
+    fn set_up_batch(&mut self) -> Result<(), SideFuzzError> {
+        let missing: Vec<&str> = BATCH_EXPORTS
+            .iter()
+            .copied()
+            .filter(|name| {
+                self.instance
+                    .get_export(&self.store, self.exports.name(name))
+                    .is_none()
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(SideFuzzError::PersistentModeUnsupported(format!(
+                "the module doesn't export {}. Targets defined with sidefuzz_target! export them",
+                missing.join(", ")
+            )));
+        }
+        if self.actual_len.is_some() {
+            return Err(SideFuzzError::PersistentModeUnsupported(
+                "variable-length inputs can't be batched".to_string(),
+            ));
+        }
+
+        let capacity = self.call_i32("batch_capacity", &[])?;
+        let inputs = self.call_i32("input_batch_pointer", &[])? as u32 as usize;
+        let results = self.call_i32("batch_results_pointer", &[])? as u32 as usize;
+        let memory_size = self.memory.data(&self.store).len();
+        if capacity <= 0 {
+            return Err(SideFuzzError::PersistentModeUnsupported(
+                "the module's batches hold no inputs".to_string(),
+            ));
+        }
+        let capacity = capacity as usize;
+        if inputs + capacity * self.fuzz_len as usize > memory_size || results + capacity * 8 > memory_size {
+            return Err(SideFuzzError::WasmModuleInputOutOfBounds(memory_size));
+        }
+
+        // The same input counted on its own and in a batch
+        let input = vec![0; self.fuzz_len as usize];
+        let single = self.count_instructions(&input)?;
+        self.batch = Some(BatchBuffers {
+            inputs,
+            results,
+            capacity,
+            overhead: 0,
+        });
+        let batched = self.run_batch(&[&input])?;
+        self.batch = Some(BatchBuffers {
+            inputs,
+            results,
+            capacity,
+            overhead: batched[0].saturating_sub(single),
+        });
+        Ok(())
+    }
+
+    // Run inputs of exactly `fuzz_len` bytes in one call, returning the count of each, or an error if any crashed
+    fn run_batch(&mut self, inputs: &[&[u8]]) -> Result<Vec<u64>, SideFuzzError> {
+        let max_instructions = self.options.max_instructions.unwrap_or(DEFAULT_MAX_INSTRUCTIONS);
+        let per_input = self
+            .timeout_budget()
+            .filter(|budget| *budget < max_instructions)
+            .unwrap_or(max_instructions);
+        let budget = per_input.saturating_mul(inputs.len() as u64);
+        if self.meter.reset(&mut self.store, budget).is_err() {
+            self.reboot();
+            // Ok to expect, a fresh store has consumed very little fuel
+            self.meter
+                .reset(&mut self.store, budget)
+                .expect("Could not meter fresh wasm store.");
+        }
+        // Ok to expect, batches are only run once the buffers have been found.
+        // This is read after any reboot, which finds the buffers again.
+        let batch = self.batch.expect("Batch run outside of persistent mode.");
+
+        // The generator only starts over per batch, so batched inputs see different randomness
+        self.entropy.reset();
+        self.clock.reset();
+        let buffer: Vec<u8> = inputs.iter().flat_map(|input| input.iter().copied()).collect();
+        self.memory
+            .write(&mut self.store, batch.inputs, &buffer)
+            .map_err(|e| SideFuzzError::MemorySetError(e.into()))?;
+        let fuzz_batch = self
+            .instance
+            .get_export(&self.store, self.exports.name("fuzz_batch"))
+            .and_then(Extern::into_func)
+            .ok_or(SideFuzzError::WasmModuleNoFuzz)?;
+        if let Err(err) = fuzz_batch.call(&mut self.store, &[Value::I32(inputs.len() as i32)], &mut []) {
+            // The batch may have stopped part way through any of the inputs
+            self.restore();
+            return Err(SideFuzzError::WasmError(err));
+        }
+
+        let mut results = vec![0; inputs.len() * 8];
+        self.memory
+            .read(&self.store, batch.results, &mut results)
+            .map_err(|e| SideFuzzError::MemorySetError(e.into()))?;
+        Ok(decode_results(&results, inputs.len(), batch.overhead))
+    }
+
+    // How many instructions fit in the timeout, once an execution has shown how fast the target runs
+    fn timeout_budget(&self) -> Option<u64> {
+        let timeout = self.options.timeout?;
+        if self.instructions_per_second > 0.0 {
+            Some((self.instructions_per_second * timeout.as_secs_f64()) as u64)
+        } else {
+            None
+        }
+    }
+
+    // Execute the target with the given input, returning what was recorded.
+    // The module must have been created with a `trace` option.
+    pub fn trace(&mut self, input: &[u8]) -> Result<ExecutionTrace, SideFuzzError> {
+        // Ok to unwrap, the lock is never held while anything can panic.
+        *self.trace.lock().unwrap() = ExecutionTrace::default();
+        if let Some(counters) = self.block_counters {
+            counters.reset(&self.instance, &mut self.store)?;
+        }
+        if self.options.trace == Some(TraceKind::Taint) {
+            // Every byte of the secret inputs is labelled with its index in the fuzzing input
+            let mut trace = self.trace.lock().unwrap();
+            let mut first = 0;
+            for (i, (ptr, len)) in self.fuzz_segments.iter().enumerate() {
+                if !self.public_segments.get(i).cloned().unwrap_or(false) {
+                    trace.taint.taint_input(*ptr as u64, first, *len);
+                }
+                first += len;
+            }
+        }
+        self.count_instructions(input)?;
+        let mut trace = std::mem::replace(&mut *self.trace.lock().unwrap(), ExecutionTrace::default());
+        trace.switch_function(None, self.store.consumed());
+        if let Some(counters) = self.block_counters {
+            trace.block_counts = counters.read(&self.instance, &self.store)?;
+        }
+        Ok(trace)
+    }
+
+    // Execute the target with an input that crashes it, returning the error and the function that was running.
+    // The module must have been created with the `Functions` trace option. Returns None if it didn't crash.
+    pub fn trace_crash(&mut self, input: &[u8]) -> Option<(SideFuzzError, Option<u32>)> {
+        // Ok to unwrap, the lock is never held while anything can panic.
+        *self.trace.lock().unwrap() = ExecutionTrace::default();
+        let err = self.count_instructions(input).err()?;
+        let func = self.trace.lock().unwrap().running_function();
+        Some((err, func))
+    }
+
+    // Take a snapshot of linear memory and all mutable globals
+    fn take_snapshot(&mut self) {
+        let mut globals = Vec::new();
+        for export in self.instance.exports(&self.store) {
+            if let Some(global) = export.into_global() {
+                if let Mutability::Var = global.ty(&self.store).mutability() {
+                    globals.push(global);
+                }
+            }
+        }
+        let globals = globals
+            .into_iter()
+            .map(|global| (global, global.get(&self.store)))
+            .collect();
+
+        self.snapshot = Some(Snapshot {
+            memory: self.memory.data(&self.store).to_vec(),
+            globals,
+        });
+    }
+
+    // Restore the instance to the snapshot, falling back to a full reboot if that isn't possible
+    fn restore(&mut self) {
+        let snapshot = match &self.snapshot {
+            Some(snapshot) => snapshot,
+            None => return self.reboot(),
+        };
+
+        // Memory can grow but not shrink, if it has grown we need to start over.
+        let memory = self.memory.data_mut(&mut self.store);
+        if memory.len() != snapshot.memory.len() {
+            return self.reboot();
+        }
+        memory.copy_from_slice(&snapshot.memory);
+
+        for (global, value) in snapshot.globals.iter() {
+            // Ok to expect, we only snapshot mutable globals and the value came from the same global.
+            global
+                .set(&mut self.store, value.clone())
+                .expect("Could not restore wasm global.");
+        }
+    }
+
     // Restart / Reboot the instance
     fn reboot(&mut self) {
+        log::debug!("Starting a fresh instance of the module");
         // This should be ok to expect here since the module has already been instantiated previously.
-        let new = Self::new(self.module.clone()).expect("Could not reboot wasm module instance.");
+        let new = Self::with_options(self.module.clone(), self.options.clone())
+            .expect("Could not reboot wasm module instance.");
 	self.store = new.store;
 	self.instance = new.instance;
 	self.memory = new.memory;
+        self.snapshot = new.snapshot;
+        self.trace = new.trace;
+        self.exports = new.exports;
+        self.actual_len = new.actual_len;
+        self.meter = new.meter;
+        self.batch = new.batch;
+        self.entropy = new.entropy;
+        self.clock = new.clock;
     }
 
     // Measure and report the running time for a single execution
     pub fn measure_time(&mut self) -> Result<FloatDuration, SideFuzzError> {
-        let input: Vec<u8> = (0..self.fuzz_len).map(|_| rand::random::<u8>()).collect();
+        let input: Vec<u8> = (0..self.fuzz_len).map(|_| self.rng.gen::<u8>()).collect();
         let start_time = Instant::now();
         self.count_instructions(&input)?;
         let end_time = Instant::now();
@@ -116,7 +690,7 @@ impl WasmModule {
         // Prime until it completes successfully (limited to 100 attemps).
         let mut i = 0;
         loop {
-            let input: Vec<u8> = (0..self.fuzz_len).map(|_| rand::random::<u8>()).collect();
+            let input: Vec<u8> = (0..self.fuzz_len).map(|_| self.rng.gen::<u8>()).collect();
             let result = self.count_instructions(&input);
             if result.is_ok() {
                 return Ok(());
@@ -137,60 +711,356 @@ impl WasmModule {
         let mut input_pointer = vec![wasmi::Value::I32(0); 1];
         self
             .instance
-            .get_export(&self.store, "input_pointer").ok_or(SideFuzzError::WasmModuleNoInputPointer)?.into_func().ok_or(SideFuzzError::WasmModuleNoInputPointer)?.call(&mut self.store, &[], &mut input_pointer)?;
+            .get_export(&self.store, self.exports.name("input_pointer")).ok_or(SideFuzzError::WasmModuleNoInputPointer)?.into_func().ok_or(SideFuzzError::WasmModuleNoInputPointer)?.call(&mut self.store, &[], &mut input_pointer)?;
 
         // Call the "input_len" exported function to get the input length
         let mut input_len = vec![wasmi::Value::I64(0); 1];
-        dbg!(self
+        self
             .instance
-            .get_export(&self.store, "input_len").ok_or(SideFuzzError::WasmModuleBadInpuLen)?.into_func().ok_or(SideFuzzError::WasmModuleBadInpuLen)?.call(&mut self.store, &[], &mut input_len))?;
-        dbg!(input_len.clone());
+            .get_export(&self.store, self.exports.name("input_len")).ok_or(SideFuzzError::WasmModuleNoInputLen)?.into_func().ok_or(SideFuzzError::WasmModuleNoInputLen)?.call(&mut self.store, &[], &mut input_len)?;
 
-        // Call the "input_is_str" exported function to check if input is a string
-        dbg!(0);
+        // Input is bytes unless the optional "input_is_str" exported function says it is a string
         let mut input_is_str = vec![wasmi::Value::I32(0); 1];
-        dbg!(self.instance.exports(&self.store));
-        dbg!(self
-            .instance
-            .get_export(&self.store, "input_is_str")).ok_or(SideFuzzError::WasmModuleBadInpuLen)?.into_func().ok_or(SideFuzzError::WasmModuleBadInpuLen)?.call(&mut self.store, &[], &mut input_is_str)?;
-        dbg!(1);
+        if let Some(func) = self.instance.get_export(&self.store, self.exports.name("input_is_str")) {
+            func.into_func().ok_or(SideFuzzError::WasmModuleBadInpuLen)?.call(&mut self.store, &[], &mut input_is_str)?;
+        }
 
+        // Targets built from C may return size_t or uint64_t, so both widths are accepted
         let input_pointer = match input_pointer[0] {
             wasmi::Value::I32(inner) => inner,
+            wasmi::Value::I64(inner) if inner >= 0 && inner <= u32::MAX as i64 => inner as u32 as i32,
             _ => {
                 return Err(SideFuzzError::WasmModuleBadInputPointer);
             }
         };
-        dbg!(2);
 
-        let input_len = match dbg!(input_len[0].clone()) {
+        let input_len = match input_len[0] {
             wasmi::Value::I32(inner) => inner,
+            wasmi::Value::I64(inner) if inner >= 0 && inner <= i32::MAX as i64 => inner as i32,
             _ => {
                 return Err(SideFuzzError::WasmModuleBadInpuLen);
             }
         };
-        if input_len > 1024 {
-            return Err(SideFuzzError::FuzzLenTooLong(input_len as u32));
+        let max_input_len = self.options.max_input_len.unwrap_or(DEFAULT_MAX_INPUT_LEN);
+        if input_len < 0 {
+            return Err(SideFuzzError::WasmModuleBadInpuLen);
+        }
+        if input_len as usize > max_input_len {
+            return Err(SideFuzzError::FuzzLenTooLong(input_len as u32, max_input_len));
         }
 
 let input_is_str = match input_is_str[0] {
 wasmi::Value::I32(inner) => inner > 0,
+wasmi::Value::I64(inner) => inner > 0,
 _ => {
 return Err(SideFuzzError::WasmModuleBadInpuLen);
 }
 };
 
-self.fuzz_ptr = input_pointer as usize;
+        // Targets with several inputs describe each of them, otherwise the whole input is a single segment
+        let has_segments = self.instance.get_export(&self.store, self.exports.name("input_count")).is_some();
+        let segments = if has_segments {
+            self.describe_input_segments()?
+        } else {
+            vec![(input_pointer as usize, input_len as usize)]
+        };
+        let marks_public = has_segments
+            && self
+                .instance
+                .get_export(&self.store, self.exports.name("input_is_public_at"))
+                .is_some();
+        let mut public_segments = vec![false; segments.len()];
+        if marks_public {
+            for (index, public) in public_segments.iter_mut().enumerate() {
+                *public = self.call_i32("input_is_public_at", &[Value::I32(index as i32)])? > 0;
+            }
+        }
+        if segments.iter().map(|(_, len)| len).sum::<usize>() != input_len as usize {
+            return Err(SideFuzzError::WasmModuleBadInputSegments);
+        }
+
+        // Inputs must lie entirely inside linear memory, which can grow but never shrinks
+        let memory_size = self.memory.data(&self.store).len();
+        if segments.iter().any(|(ptr, len)| ptr + len > memory_size) {
+            return Err(SideFuzzError::WasmModuleInputOutOfBounds(memory_size));
+        }
+
+        // AssemblyScript targets may ask for their input as a managed object, which must be their only input
+        let input_kind = self.input_kind()?;
+        if input_kind != InputKind::Bytes {
+            if has_segments || self.options.persistent {
+                return Err(SideFuzzError::WasmModuleBadManagedInput(
+                    "it must be the only input, and can't be run in persistent mode".to_string(),
+                ));
+            }
+            input_kind.check(self.memory.data(&self.store), input_pointer as u32 as usize, input_len as usize)?;
+        }
+        let input_is_str = input_is_str || input_kind == InputKind::String;
+
+self.fuzz_segments = segments;
+        self.public_segments = public_segments;
 self.fuzz_len = input_len as u32;
 self.input_is_str = input_is_str;
+        self.input_kind = input_kind;
+        self.actual_len = self.find_actual_len();
+        log::debug!(
+            "{} bytes of input in {} segments, starting at {}{}{}",
+            self.fuzz_len,
+            self.fuzz_segments.len(),
+            input_pointer,
+            if self.input_is_str { ", as a string" } else { "" },
+            if self.actual_len.is_some() { ", of variable length" } else { "" }
+        );
 
         Ok(())
     }
+
+    // Set up the key the target fetches with `sidefuzz::fetch_key`: write the fixed key to it if one was given, or
+    // fuzz it in place of the input with `fuzz_key`.
+    // The key is only allocated once the target has fetched it, which it may do after fetching its input, so the
+    // target is run once more if it hasn't been yet.
+    fn set_key(&mut self) -> Result<(), SideFuzzError> {
+        if self.options.key.is_none() && !self.options.fuzz_key {
+            return Ok(());
+        }
+        let mut key_len = self.call_i32("key_len", &[]).map_err(|_| SideFuzzError::WasmModuleNoKey)?;
+        if key_len == 0 {
+            let input = vec![0; self.fuzz_len as usize];
+            let _ = crate::black_box(self.count_instructions(&input));
+            key_len = self.call_i32("key_len", &[]).map_err(|_| SideFuzzError::WasmModuleNoKey)?;
+        }
+        if key_len <= 0 {
+            return Err(SideFuzzError::WasmModuleNoKey);
+        }
+        let key_pointer = self.call_i32("key_pointer", &[]).map_err(|_| SideFuzzError::WasmModuleNoKey)?;
+        let (key_pointer, key_len) = (key_pointer as u32 as usize, key_len as usize);
+        let memory_size = self.memory.data(&self.store).len();
+        if key_pointer + key_len > memory_size {
+            return Err(SideFuzzError::WasmModuleInputOutOfBounds(memory_size));
+        }
+        if self.options.fuzz_key {
+            return self.fuzz_key(key_pointer, key_len);
+        }
+
+        // Ok to unwrap, there is a key unless it is fuzzed
+        let key = self.options.key.clone().unwrap();
+        if key.len() != key_len {
+            return Err(SideFuzzError::KeyWrongSize(key.len(), key_len as i32));
+        }
+        self.memory
+            .write(&mut self.store, key_pointer, &key)
+            .map_err(|_| SideFuzzError::WasmModuleInputOutOfBounds(memory_size))?;
+        log::debug!("{} byte key written at {}", key_len, key_pointer);
+        Ok(())
+    }
+
+    // Fuzz the key in place of the input, which is fixed to the message: all zero, unless one was given
+    fn fuzz_key(&mut self, key_pointer: usize, key_len: usize) -> Result<(), SideFuzzError> {
+        if self.input_kind != InputKind::Bytes || self.options.persistent {
+            return Err(SideFuzzError::KeyFuzzingUnsupported(
+                "the message must be bytes, and can't be run in persistent mode".to_string(),
+            ));
+        }
+        let fuzz_len = self.fuzz_len as usize;
+        let message = self.options.message.clone().unwrap_or_else(|| vec![0; fuzz_len]);
+        let fits = message.len() == fuzz_len || (self.actual_len.is_some() && message.len() < fuzz_len);
+        if !fits {
+            return Err(SideFuzzError::MessageWrongSize(message.len(), fuzz_len));
+        }
+        // Running the message writes it, and nothing writes over it once the key is fuzzed instead
+        let _ = crate::black_box(self.count_instructions(&message));
+
+        self.fuzz_segments = vec![(key_pointer, key_len)];
+        self.public_segments = vec![false];
+        self.fuzz_len = key_len as u32;
+        self.input_is_str = false;
+        self.actual_len = None;
+        log::debug!(
+            "Fuzzing the {} byte key at {}, with a fixed {} byte message",
+            key_len,
+            key_pointer,
+            message.len()
+        );
+        Ok(())
+    }
+
+    // Find the exported "input_actual_len" global of variable-length targets.
+    // Rust targets export a static, which is only set once the target has asked for a variable-length input.
+    fn find_actual_len(&self) -> Option<ActualLen> {
+        let global = self
+            .instance
+            .get_export(&self.store, self.exports.name("input_actual_len"))?
+            .into_global()?;
+        let value = global.get(&self.store);
+        if let Mutability::Var = global.ty(&self.store).mutability() {
+            return match value {
+                Value::I32(_) => Some(ActualLen::Global(global)),
+                _ => None,
+            };
+        }
+
+        let address = match value {
+            Value::I32(address) => address as u32 as usize,
+            _ => return None,
+        };
+        let mut len = [0; 4];
+        self.memory.read(&self.store, address, &mut len).ok()?;
+        if i32::from_le_bytes(len) < 0 {
+            return None;
+        }
+        Some(ActualLen::Address(address))
+    }
+
+    // Tell a variable-length target how long the current input is
+    fn set_actual_len(&mut self, actual_len: ActualLen, len: usize) -> Result<(), SideFuzzError> {
+        match actual_len {
+            ActualLen::Global(global) => global
+                .set(&mut self.store, Value::I32(len as i32))
+                .map_err(|e| SideFuzzError::WasmError(e.into())),
+            ActualLen::Address(address) => self
+                .memory
+                .write(&mut self.store, address, &(len as i32).to_le_bytes())
+                .map_err(|e| SideFuzzError::MemorySetError(e.into())),
+        }
+    }
+
+    // Read the input descriptor table, from the "input_count", "input_pointer_at" and "input_len_at" exports
+    fn describe_input_segments(&mut self) -> Result<Vec<(usize, usize)>, SideFuzzError> {
+        let count = self.call_i32("input_count", &[])?;
+        if count < 0 || count as usize > crate::MAX_INPUTS {
+            return Err(SideFuzzError::WasmModuleBadInputSegments);
+        }
+        let mut segments = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let ptr = self.call_i32("input_pointer_at", &[Value::I32(index)])?;
+            let len = self.call_i32("input_len_at", &[Value::I32(index)])?;
+            if len < 0 {
+                return Err(SideFuzzError::WasmModuleBadInputSegments);
+            }
+            segments.push((ptr as u32 as usize, len as usize));
+        }
+        Ok(segments)
+    }
+
+    // What the optional "input_kind" export, a constant or a function, asks for the input to be written as
+    fn input_kind(&mut self) -> Result<InputKind, SideFuzzError> {
+        let value = match self.instance.get_export(&self.store, self.exports.name(INPUT_KIND)) {
+            Some(Extern::Global(global)) => match global.get(&self.store) {
+                Value::I32(value) => value,
+                _ => {
+                    return Err(SideFuzzError::WasmModuleBadManagedInput(
+                        "input_kind must be an i32".to_string(),
+                    ))
+                }
+            },
+            Some(_) => self.call_i32(INPUT_KIND, &[])?,
+            None => 0,
+        };
+        InputKind::from_export(value)
+    }
+
+    // Call an exported function returning a single i32, or an i64 that fits in one
+    fn call_i32(&mut self, name: &str, args: &[Value]) -> Result<i32, SideFuzzError> {
+        let mut result = [Value::I32(0)];
+        self.instance
+            .get_export(&self.store, self.exports.name(name))
+            .and_then(Extern::into_func)
+            .ok_or(SideFuzzError::WasmModuleBadInputSegments)?
+            .call(&mut self.store, args, &mut result)?;
+        match result[0] {
+            Value::I32(inner) => Ok(inner),
+            Value::I64(inner) => i32::try_from(inner).map_err(|_| SideFuzzError::WasmModuleBadInputSegments),
+            _ => Err(SideFuzzError::WasmModuleBadInputSegments),
+        }
+    }
 }
 
 impl Clone for WasmModule {
     fn clone(&self) -> Self {
         // This should be ok to expect here since the module has already been instantiated previously.
-        Self::new(self.module.clone()).expect("Unable to clone wasm module")
+        Self::with_options(self.module.clone(), self.options.clone())
+            .expect("Unable to clone wasm module")
+    }
+}
+
+// Memory proposals wasmi can't run, so they can fail clearly instead of with a validation error.
+// Inputs are written through the single 32-bit memory the target exports.
+pub(crate) fn unsupported_memories(bytes: &[u8]) -> Option<String> {
+    let memories = crate::binary::memories(bytes).unwrap_or_default();
+    if memories.iter().any(|memory| memory.memory64) {
+        Some("64-bit memory (the memory64 proposal)".to_string())
+    } else if memories.len() > 1 {
+        Some(format!("{} memories (the multi-memory proposal)", memories.len()))
+    } else {
+        None
+    }
+}
+
+// Run a function the module expects to be called before anything else. The store doesn't have fuel for it yet, and
+// what it runs isn't part of any execution's count.
+fn run_startup(store: &mut Store<()>, name: &str, func: Func) -> Result<(), SideFuzzError> {
+    let failed = |reason: String| SideFuzzError::StartupFailed(name.to_string(), reason);
+    FuelMeter::default()
+        .reset(store, STARTUP_BUDGET)
+        .map_err(|_| failed("the store is out of fuel".to_string()))?;
+    match func.call(&mut *store, &[], &mut []) {
+        Ok(()) => Ok(()),
+        Err(wasmi::Error::Trap(trap)) if trap.trap_code() == Some(wasmi::core::TrapCode::OutOfFuel) => Err(failed(
+            format!("it didn't return within {} instructions", STARTUP_BUDGET),
+        )),
+        Err(err) => Err(failed(err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::*;
+
+    #[test]
+    fn initialize_test() {
+        // Traps unless `_initialize` has run: global.get 0, i32.eqz, if, unreachable, end
+        let fuzz = [0x00, 0x23, 0x00, 0x45, 0x04, 0x40, 0x00, 0x0b, 0x0b];
+        // i32.const 1, global.set 0
+        let initialize = [0x00, 0x41, 0x01, 0x24, 0x00, 0x0b];
+        let mut module = WasmModule::new(test_target(&fuzz, &[("_initialize", &initialize)])).unwrap();
+        assert!(module.count_instructions(&[0]).is_ok());
+
+        // unreachable
+        let trapping = [0x00, 0x00, 0x0b];
+        match WasmModule::new(test_target(&fuzz, &[("_initialize", &trapping)])) {
+            Err(SideFuzzError::StartupFailed(name, _)) => assert_eq!(name, "_initialize"),
+            _ => panic!("a trapping _initialize should fail to start"),
+        }
+    }
+
+    #[test]
+    fn bindgen_start_test() {
+        // Traps unless `__wbindgen_start` has run, like `initialize_test`
+        let fuzz = [0x00, 0x23, 0x00, 0x45, 0x04, 0x40, 0x00, 0x0b, 0x0b];
+        let start = [0x00, 0x41, 0x01, 0x24, 0x00, 0x0b];
+        let module = test_target(&fuzz, &[(crate::bindgen::BINDGEN_START, &start)]);
+        let mut module = WasmModule::new(module).unwrap();
+        assert!(module.count_instructions(&[0]).is_ok());
+    }
+
+    #[test]
+    fn fresh_memory_test() {
+        for fuzz in [GROWING_GLOBAL, GROWING_MEMORY].iter() {
+            let counts = |fresh_memory: bool| {
+                let options = WasmOptions {
+                    fresh_memory,
+                    ..WasmOptions::default()
+                };
+                let mut module = WasmModule::with_options(test_target(fuzz, &[]), options).unwrap();
+                (0..3)
+                    .map(|_| module.count_instructions(&[0]).unwrap())
+                    .collect::<Vec<u64>>()
+            };
+            let fresh = counts(true);
+            assert!(fresh.iter().all(|count| *count == fresh[0]));
+            let kept = counts(false);
+            assert!(kept[0] < kept[1] && kept[1] < kept[2]);
+        }
     }
 }
@@ -0,0 +1,100 @@
+// Watching a target for rebuilds, for fuzz --watch
+//
+// The file is polled from a background thread, which works the same everywhere and is cheap next to a rebuild. It
+// only counts as changed once its modification time and size have stayed the same for two polls in a row, so that
+// a target the compiler is still writing isn't loaded half-written. Fuzzing checks for changes after every
+// generation, the same way it checks for Ctrl-C.
+
+use crate::interrupt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+static CHANGED: AtomicBool = AtomicBool::new(false);
+
+// What tells versions of the file apart, None while it doesn't exist
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &str) -> Stamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+// Decides from successive polls when the file has settled on a new version
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Poll {
+    current: Stamp, // The version last loaded
+    last: Stamp,    // The version seen by the previous poll
+}
+
+impl Poll {
+    fn new(current: Stamp) -> Self {
+        Poll {
+            current,
+            last: current,
+        }
+    }
+
+    // Whether the file settled on a new version with `now`
+    fn poll(&mut self, now: Stamp) -> bool {
+        let settled = now.is_some() && now == self.last && now != self.current;
+        if settled {
+            self.current = now;
+        }
+        self.last = now;
+        settled
+    }
+}
+
+// Watch the file at `path` until the process exits
+pub(crate) fn start(path: &str) {
+    let path = path.to_string();
+    let mut poll = Poll::new(stamp(&path));
+    std::thread::spawn(move || loop {
+        std::thread::sleep(POLL_INTERVAL);
+        if poll.poll(stamp(&path)) {
+            CHANGED.store(true, Ordering::SeqCst);
+        }
+    });
+}
+
+// Whether the file changed since the last `wait`
+pub(crate) fn changed() -> bool {
+    CHANGED.load(Ordering::SeqCst)
+}
+
+// Block until the file changes, returning false if the run was interrupted instead
+pub(crate) fn wait() -> bool {
+    while !CHANGED.swap(false, Ordering::SeqCst) {
+        if interrupt::requested() {
+            return false;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_test() {
+        let built = |secs, len| Some((SystemTime::UNIX_EPOCH + Duration::from_secs(secs), len));
+        let mut poll = Poll::new(built(1, 100));
+        assert!(!poll.poll(built(1, 100)));
+
+        // Still being written, then settled
+        assert!(!poll.poll(built(2, 40)));
+        assert!(!poll.poll(built(3, 120)));
+        assert!(poll.poll(built(3, 120)));
+        assert!(!poll.poll(built(3, 120)));
+
+        // Deleted before being written again
+        assert!(!poll.poll(None));
+        assert!(!poll.poll(None));
+        assert!(!poll.poll(built(4, 120)));
+        assert!(poll.poll(built(4, 120)));
+    }
+}
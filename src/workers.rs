@@ -0,0 +1,155 @@
+// A pool of worker threads, each with its own instance of the wasm module, used to score populations in parallel
+//
+// Workers pull chunks of the population from a shared queue, so workers on other machines can join the pool
+// through `listen` and take chunks the same way the threads do.
+
+use crate::errors::SideFuzzError;
+use crate::scores::Scorer;
+use crate::util::*;
+use crate::target::Target;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+// Chunks of the population are this many pairs at most once remote workers have joined, so slower machines hold
+// fewer pairs up at the end of a generation
+const REMOTE_CHUNK: usize = 50;
+
+pub(crate) type Job = (usize, Vec<InputPair>);
+
+// A scored chunk, or a chunk to schedule again because the remote worker scoring it went away
+pub(crate) type Outcome = (usize, Result<Vec<ScoredInputPair>, Vec<InputPair>>);
+
+pub(crate) type Queue = Arc<Mutex<Receiver<Job>>>;
+
+pub struct WorkerPool {
+    jobs: Option<Sender<Job>>, // Only None while the pool is dropped
+    queue: Queue,
+    results: Sender<Outcome>,
+    receiver: Receiver<Outcome>,
+    handles: Vec<JoinHandle<()>>,
+    remote: Arc<AtomicUsize>, // Remote workers connected
+    scorer: Scorer,           // Scores counts reported by remote workers
+}
+
+impl WorkerPool {
+    // Spawn `num_workers` threads, each with its own clone of the target and of `scorer`
+    pub fn new(target: &dyn Target, num_workers: usize, scorer: &Scorer) -> Result<Self, SideFuzzError> {
+        if !target.parallel() {
+            return Err(SideFuzzError::WorkersUnsupported);
+        }
+
+        let (jobs, job_receiver) = channel();
+        let queue = Arc::new(Mutex::new(job_receiver));
+        let (results, receiver) = channel();
+
+        let mut handles = Vec::with_capacity(num_workers);
+        for _ in 0..num_workers {
+            let queue = queue.clone();
+            let results = results.clone();
+            let mut module = target.try_clone()?;
+            let mut scorer = scorer.try_clone()?;
+
+            handles.push(std::thread::spawn(move || {
+                // Score chunks until the pool is dropped
+                while let Some((index, chunk)) = next_job(&queue) {
+                    let scored = score_pairs(module.as_mut(), chunk, &mut scorer);
+                    if results.send((index, Ok(scored))).is_err() {
+                        return;
+                    }
+                }
+            }));
+        }
+
+        Ok(WorkerPool {
+            jobs: Some(jobs),
+            queue,
+            results,
+            receiver,
+            handles,
+            remote: Arc::new(AtomicUsize::new(0)),
+            scorer: scorer.try_clone()?,
+        })
+    }
+
+    // Accept workers from other machines on `addr`, such as "0.0.0.0:7878", for as long as the pool exists
+    pub fn listen(
+        &mut self,
+        addr: &str,
+        token: Option<String>,
+        target: &dyn Target,
+    ) -> Result<(), SideFuzzError> {
+        crate::distributed::serve(
+            addr,
+            token,
+            target,
+            self.scorer.try_clone()?,
+            self.queue.clone(),
+            self.results.clone(),
+            self.remote.clone(),
+        )
+    }
+
+    pub fn num_workers(&self) -> usize {
+        self.handles.len() + self.remote.load(Ordering::SeqCst)
+    }
+
+    // Score the population, returning it sorted most fit to least fit.
+    // The result doesn't depend on how the work was scheduled across workers.
+    pub fn score(&self, population: &[InputPair]) -> Vec<ScoredInputPair> {
+        // Ok to unwrap, the sender is only taken when the pool is dropped.
+        let jobs = self.jobs.as_ref().unwrap();
+        let num_workers = self.num_workers().max(1);
+        let mut chunk_size = (population.len() + num_workers - 1) / num_workers;
+        if self.remote.load(Ordering::SeqCst) > 0 {
+            chunk_size = chunk_size.min(REMOTE_CHUNK);
+        }
+
+        let mut num_chunks = 0;
+        for (index, chunk) in population.chunks(chunk_size.max(1)).enumerate() {
+            // Ok to expect, workers only exit when the pool is dropped.
+            jobs.send((index, chunk.to_vec()))
+                .expect("Fuzzing worker exited unexpectedly.");
+            num_chunks += 1;
+        }
+
+        // Put chunks back in population order before sorting, so ties are broken the same way every time
+        let mut chunks: Vec<Vec<ScoredInputPair>> = vec![Vec::new(); num_chunks];
+        let mut remaining = num_chunks;
+        while remaining > 0 {
+            let (index, outcome) = self
+                .receiver
+                .recv()
+                .expect("Fuzzing worker exited unexpectedly.");
+            match outcome {
+                Ok(scored) => {
+                    chunks[index] = scored;
+                    remaining -= 1;
+                }
+                Err(chunk) => jobs
+                    .send((index, chunk))
+                    .expect("Fuzzing worker exited unexpectedly."),
+            }
+        }
+
+        let mut scored: Vec<ScoredInputPair> = chunks.into_iter().flatten().collect();
+        sort_by_score(&mut scored);
+        scored
+    }
+}
+
+// The next chunk to score, or None once the pool is dropped
+pub(crate) fn next_job(queue: &Queue) -> Option<Job> {
+    queue.lock().ok()?.recv().ok()
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // Closing the queue stops the workers. Remote workers are told the run is over, but aren't waited for.
+        self.jobs = None;
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}